@@ -1,7 +1,9 @@
 //! Benchmarks for evaluating how we fare against sqlite
 
 use criterion::measurement::WallTime;
-use criterion::{criterion_group, criterion_main, BenchmarkGroup, BenchmarkId, Criterion, SamplingMode, Throughput};
+use criterion::{
+    criterion_group, criterion_main, BatchSize, BenchmarkGroup, BenchmarkId, Criterion, SamplingMode, Throughput,
+};
 use spacetimedb_bench::prelude::*;
 
 fn build_group<'a>(c: &'a mut Criterion, named: &str, run: Runs) -> BenchmarkGroup<'a, WallTime> {
@@ -28,6 +30,27 @@ fn bench_insert_tx_per_row(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_insert_tx_per_row_durability(c: &mut Criterion) {
+    let run = Runs::Tiny;
+    let mut group = build_group(c, "insert_row_durability", run);
+
+    group.bench_function(BenchmarkId::new(SQLITE, "normal"), |b| {
+        let mut pool = Pool::new(false).unwrap();
+        b.iter(|| sqlite::insert_tx_per_row(&mut pool, run).unwrap())
+    });
+    for mode in [DurabilityMode::Sync, DurabilityMode::Group, DurabilityMode::Async] {
+        group.bench_function(BenchmarkId::new(SPACETIME, format!("{mode:?}").to_lowercase()), |b| {
+            b.iter_batched_ref(
+                || spacetime::build_db_with_durability(mode).unwrap(),
+                |db| spacetime::insert_tx_per_row_durable(db, run).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+
+    group.finish();
+}
+
 fn bench_insert_tx(c: &mut Criterion) {
     let run = Runs::Small;
     let mut group = build_group(c, "insert_bulk_rows", run);
@@ -61,5 +84,11 @@ fn bench_select_no_index(c: &mut Criterion) {
 }
 
 // Note: Reflex this same benchmarks in `main.rs`
-criterion_group!(benches, bench_insert_tx_per_row, bench_insert_tx, bench_select_no_index);
+criterion_group!(
+    benches,
+    bench_insert_tx_per_row,
+    bench_insert_tx_per_row_durability,
+    bench_insert_tx,
+    bench_select_no_index
+);
 criterion_main!(benches);
@@ -52,6 +52,17 @@ pub enum DbEngine {
     Spacetime,
 }
 
+/// When SpacetimeDB considers commits durable
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum DurabilityMode {
+    /// Sync every commit to disk
+    Sync,
+    /// Sync commits to disk together within the default group commit window
+    Group,
+    /// Leave syncing to the OS
+    Async,
+}
+
 /// # of Rows to use in the benchmark
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum Runs {
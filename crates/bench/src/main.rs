@@ -26,6 +26,15 @@ enum Commands {
         #[arg(value_enum)]
         rows: Option<Runs>,
     },
+    /// Generate insert, each generate a transaction, and wait for them to be durable
+    InsertDurable {
+        /// When SpacetimeDB considers commits durable
+        #[arg(value_enum)]
+        durability: DurabilityMode,
+        /// How many rows
+        #[arg(value_enum)]
+        rows: Option<Runs>,
+    },
     /// Run queries without a index
     SelectNoIndex {
         /// How many rows
@@ -58,6 +67,20 @@ fn main() -> ResultBench<()> {
         Commands::Insert { rows } => {
             bench_fn!(cli, insert_tx_per_row, rows.unwrap_or(Runs::Tiny), false)
         }
+        Commands::InsertDurable { durability, rows } => {
+            let run = rows.unwrap_or(Runs::Tiny);
+            match cli.db {
+                // Sqlite runs with `synchronous = normal` in WAL mode, as in the other benchmarks
+                DbEngine::Sqlite => {
+                    let mut pool = Pool::new(false)?;
+                    sqlite::insert_tx_per_row(&mut pool, run)
+                }
+                DbEngine::Spacetime => {
+                    let db = spacetime::build_db_with_durability(durability)?;
+                    spacetime::insert_tx_per_row_durable(&db, run)
+                }
+            }
+        }
         Commands::InsertBulk { rows } => {
             bench_fn!(cli, insert_tx, rows.unwrap_or(Runs::Small), true)
         }
@@ -1,7 +1,8 @@
 use crate::prelude::*;
 use spacetimedb::db::datastore::locking_tx_datastore::MutTxId;
 use spacetimedb::db::datastore::traits::TableDef;
-use spacetimedb::db::relational_db::{open_db, RelationalDB};
+use spacetimedb::db::relational_db::{open_db, open_db_with_durability, RelationalDB};
+use spacetimedb::db::{Durability, GroupCommit};
 use spacetimedb_lib::sats::product;
 use spacetimedb_lib::{AlgebraicType, AlgebraicValue, ProductType};
 
@@ -29,6 +30,18 @@ fn build_db(in_memory: bool) -> ResultBench<DbResult> {
     Ok((stdb, tmp_dir, table_id))
 }
 
+/// Opens a fresh persistent database which syncs its commits according to `mode`.
+pub fn build_db_with_durability(mode: DurabilityMode) -> ResultBench<DbResult> {
+    let durability = match mode {
+        DurabilityMode::Sync => Durability::Sync,
+        DurabilityMode::Group => Durability::Group(GroupCommit::default()),
+        DurabilityMode::Async => Durability::Async,
+    };
+    let (tmp_dir, table_id) = init_db(false)?;
+    let stdb = open_db_with_durability(&tmp_dir, false, durability)?;
+    Ok((stdb, tmp_dir, table_id))
+}
+
 fn insert_row(db: &RelationalDB, tx: &mut MutTxId, table_id: u32, run: Runs) -> ResultBench<()> {
     for row in run.data() {
         db.insert(
@@ -119,3 +132,33 @@ pub fn select_no_index(pool: &mut Pool<DbResult>, run: Runs) -> ResultBench<()>
     }
     Ok(())
 }
+
+/// Inserts each row in its own transaction, then waits until all of them are durable.
+pub fn insert_tx_per_row_durable(db: &DbResult, run: Runs) -> ResultBench<()> {
+    let (conn, _tmp_dir, table_id) = db;
+
+    let mut last_commit = None;
+    for row in run.data() {
+        let mut tx = conn.begin_tx();
+
+        conn.insert(
+            &mut tx,
+            *table_id,
+            product![
+                AlgebraicValue::I32(row.a),
+                AlgebraicValue::U64(row.b),
+                AlgebraicValue::String(row.c),
+            ],
+        )?;
+        if let Some((_, Some(commit))) = conn.commit_tx(tx)? {
+            last_commit = Some(commit);
+        }
+    }
+
+    // Commits become durable in order, so waiting for the last one is enough.
+    if let Some(commit) = last_commit {
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        assert!(runtime.block_on(commit.durable()));
+    }
+    Ok(())
+}
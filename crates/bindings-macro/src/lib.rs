@@ -492,6 +492,11 @@ fn spacetimedb_table(item: TokenStream) -> syn::Result<TokenStream> {
 /// * `#[primarykey]`
 ///
 ///    Similar to `#[unique]`, but generates additional CRUD methods.
+///
+/// Indexes on one or more fields are declared on the struct itself,
+/// e.g. `#[spacetimedb(index(btree, name = "guild_rank", guild_id, rank))]`.
+/// An index on several fields orders rows by the first field, then the second, and so on,
+/// so it can also serve lookups on its first field alone.
 #[proc_macro_derive(TableType, attributes(sats, unique, autoinc, primarykey))]
 pub fn spacetimedb_tabletype(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let item = syn::parse_macro_input!(item as syn::DeriveInput);
@@ -613,6 +618,9 @@ fn spacetimedb_tabletype_impl(item: syn::DeriveInput) -> syn::Result<TokenStream
         let MacroInput::Index { ty, name, field_names } = args else {
             continue;
         };
+        if field_names.is_empty() {
+            return Err(syn::Error::new_spanned(attr, "an index must have at least one field"));
+        }
         let col_ids = field_names
             .iter()
             .map(|ident| {
@@ -623,7 +631,12 @@ fn spacetimedb_tabletype_impl(item: syn::DeriveInput) -> syn::Result<TokenStream
                 Ok(col.index)
             })
             .collect::<syn::Result<Vec<_>>>()?;
-        let name = name.as_deref().unwrap_or("default_index");
+        // Indexes are named `{table}_{field}*_idx` unless a name is given,
+        // as index names must be unique within the database.
+        let name = name.unwrap_or_else(|| {
+            let field_names = field_names.iter().map(|ident| ident.to_string()).collect::<Vec<_>>();
+            format!("{}_{}_idx", table_name, field_names.join("_"))
+        });
         indexes.push(quote!(spacetimedb::IndexDef {
             name: #name,
             ty: spacetimedb::spacetimedb_lib::IndexType::#ty,
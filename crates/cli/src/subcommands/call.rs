@@ -197,7 +197,7 @@ fn add_reducer_ctx_to_err(error: &mut String, schema_json: Value, reducer_name:
         .collect::<Vec<_>>();
 
     // Hide these pseudo-reducers; they shouldn't be callable.
-    reducers.retain(|&c| !matches!(c, "__update__" | "__init__"));
+    reducers.retain(|&c| !matches!(c, "__update__" | "__init__" | "__migrate__"));
 
     if let Some(best) = find_best_match_for_name(&reducers, reducer_name, None) {
         write!(error, "\n\nA reducer with a similar name exists: `{}`", best).unwrap();
@@ -45,6 +45,27 @@ pub fn cli() -> clap::Command {
                 .help("Turn on diagnostic/performance tracing for this project")
                 .action(SetTrue),
         )
+        .arg(
+            Arg::new("durability")
+                .long("durability")
+                .value_parser(["sync", "group", "async"])
+                .help("When commits to a new database are considered durable")
+                .long_help("When commits to a new database are considered durable. `sync` syncs every commit to disk before it completes, `group` syncs commits to disk together once the group commit window is full, and `async` syncs commits to disk in the background every second. Defaults to `sync`."),
+        )
+        .arg(
+            Arg::new("group_commit_ms")
+                .long("group-commit-ms")
+                .value_parser(clap::value_parser!(u64))
+                .requires("durability")
+                .help("With `--durability group`, the longest time in milliseconds a commit waits to be synced"),
+        )
+        .arg(
+            Arg::new("group_commit_bytes")
+                .long("group-commit-bytes")
+                .value_parser(clap::value_parser!(u64))
+                .requires("durability")
+                .help("With `--durability group`, the most bytes written before commits are synced"),
+        )
         // TODO(tyler): We should be able to pass in either an identity or an alias here
         .arg(
             Arg::new("identity")
@@ -98,6 +119,9 @@ pub async fn exec(mut config: Config, args: &ArgMatches) -> Result<(), anyhow::E
     let host_type = args.get_one::<String>("host_type").unwrap();
     let clear_database = args.get_flag("clear_database");
     let trace_log = args.get_flag("trace_log");
+    let durability = args.get_one::<String>("durability");
+    let group_commit_ms = args.get_one::<u64>("group_commit_ms").map(|ms| ms.to_string());
+    let group_commit_bytes = args.get_one::<u64>("group_commit_bytes").map(|bytes| bytes.to_string());
     let anon_identity = args.get_flag("anon_identity");
     let skip_clippy = args.get_flag("skip_clippy");
     let build_debug = args.get_flag("debug");
@@ -130,6 +154,16 @@ pub async fn exec(mut config: Config, args: &ArgMatches) -> Result<(), anyhow::E
         query_params.push(("trace_log", "true"));
     }
 
+    if let Some(durability) = durability {
+        query_params.push(("durability", durability.as_str()));
+    }
+    if let Some(group_commit_ms) = &group_commit_ms {
+        query_params.push(("group_commit_ms", group_commit_ms.as_str()));
+    }
+    if let Some(group_commit_bytes) = &group_commit_bytes {
+        query_params.push(("group_commit_bytes", group_commit_bytes.as_str()));
+    }
+
     let path_to_wasm = crate::tasks::build(path_to_project, skip_clippy, build_debug)?;
     let program_bytes = fs::read(path_to_wasm)?;
 
@@ -166,7 +200,12 @@ pub async fn exec(mut config: Config, args: &ArgMatches) -> Result<(), anyhow::E
 
     let response: PublishResult = serde_json::from_slice(&bytes[..]).unwrap();
     match response {
-        PublishResult::Success { domain, address, op } => {
+        PublishResult::Success {
+            domain,
+            address,
+            op,
+            schema_changes,
+        } => {
             let op = match op {
                 PublishOp::Created => "Created new",
                 PublishOp::Updated => "Updated",
@@ -176,6 +215,9 @@ pub async fn exec(mut config: Config, args: &ArgMatches) -> Result<(), anyhow::E
             } else {
                 println!("{} database with address: {}", op, address);
             }
+            for change in schema_changes {
+                println!("  {}", change);
+            }
         }
         PublishResult::TldNotRegistered { domain } => {
             return Err(anyhow::anyhow!(
@@ -1,6 +1,7 @@
 use crate::api::{ClientApi, Connection};
 use crate::sql::run_sql;
 use colored::*;
+use reqwest::RequestBuilder;
 
 use rustyline::completion::Completer;
 use rustyline::error::ReadlineError;
@@ -32,6 +33,13 @@ create,
 where
 join
 sort by
+explain
+analyze
+begin
+commit
+rollback
+savepoint
+release
 .exit
 .clear
 ";
@@ -56,9 +64,17 @@ pub async fn exec(con: Connection) -> Result<(), anyhow::Error> {
     );
 
     let api = ClientApi::new(con);
+    // The session of the transaction opened by `BEGIN`, which the following lines run in.
+    let mut session = None;
 
     loop {
-        let readline = rl.readline(&format!("🪐{}>", &database).green());
+        // Like `psql`, mark the prompt while in a transaction.
+        let prompt = if session.is_some() {
+            format!("🪐{}*>", &database)
+        } else {
+            format!("🪐{}>", &database)
+        };
+        let readline = rl.readline(&prompt.green());
         match readline {
             Ok(line) => match line.as_str() {
                 ".exit" => break,
@@ -68,8 +84,15 @@ pub async fn exec(con: Connection) -> Result<(), anyhow::Error> {
                 sql => {
                     rl.add_history_entry(sql).ok();
 
-                    if let Err(err) = run_sql(api.sql(), sql).await {
-                        eprintln!("{}", err.to_string().red())
+                    match run_sql(sql_in_session(&api, session), sql).await {
+                        Ok(open) => session = open,
+                        Err(err) => {
+                            // The session is gone, e.g. its transaction was rolled back for being idle.
+                            if is_not_found(&err) {
+                                session = None;
+                            }
+                            eprintln!("{}", err.to_string().red())
+                        }
                     }
                 }
             },
@@ -84,11 +107,29 @@ pub async fn exec(con: Connection) -> Result<(), anyhow::Error> {
         }
     }
 
+    if session.is_some() {
+        println!("{}", "Rolling back the open transaction.".yellow());
+        run_sql(sql_in_session(&api, session), "ROLLBACK").await.ok();
+    }
+
     rl.save_history(".history.txt").ok();
 
     Ok(())
 }
 
+fn sql_in_session(api: &ClientApi, session: Option<u64>) -> RequestBuilder {
+    match session {
+        Some(session) => api.sql().query(&[("session", session)]),
+        None => api.sql(),
+    }
+}
+
+fn is_not_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .and_then(|err| err.status())
+        .map_or(false, |status| status == reqwest::StatusCode::NOT_FOUND)
+}
+
 pub(crate) struct ReplHelper {
     syntaxes: SyntaxSet,
     theme: Theme,
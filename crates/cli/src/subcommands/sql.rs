@@ -66,21 +66,23 @@ pub(crate) async fn parse_req(mut config: Config, args: &ArgMatches) -> Result<C
     })
 }
 
-pub(crate) async fn run_sql(builder: RequestBuilder, sql: &str) -> Result<(), anyhow::Error> {
-    let json = builder
-        .body(sql.to_owned())
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
+/// Runs the `sql` and prints its results.
+///
+/// Returns the session left open by a `BEGIN`, to send the following statements to.
+pub(crate) async fn run_sql(builder: RequestBuilder, sql: &str) -> Result<Option<u64>, anyhow::Error> {
+    let response = builder.body(sql.to_owned()).send().await?.error_for_status()?;
+    let session = response
+        .headers()
+        .get("spacetime-sql-session")
+        .and_then(|session| session.to_str().ok()?.parse().ok());
+    let json = response.text().await?;
 
     let stmt_result_json: Vec<StmtResultJson> = serde_json::from_str(&json)?;
 
     // Print only `OK for empty tables as it's likely a command like `INSERT`.
     if stmt_result_json.is_empty() {
         println!("OK");
-        return Ok(());
+        return Ok(session);
     };
 
     for (i, stmt_result) in stmt_result_json.iter().enumerate() {
@@ -116,7 +118,7 @@ pub(crate) async fn run_sql(builder: RequestBuilder, sql: &str) -> Result<(), an
         }
     }
 
-    Ok(())
+    Ok(session)
 }
 
 pub async fn exec(config: Config, args: &ArgMatches) -> Result<(), anyhow::Error> {
@@ -131,7 +133,11 @@ pub async fn exec(config: Config, args: &ArgMatches) -> Result<(), anyhow::Error
         let con = parse_req(config, args).await?;
         let api = ClientApi::new(con);
 
-        run_sql(api.sql(), query).await?;
+        if run_sql(api.sql(), query).await?.is_some() {
+            eprintln!(
+                "The transaction opened by `BEGIN` was left open until it is rolled back for being idle. Use `--interactive` to run a transaction across statements."
+            );
+        }
     }
     Ok(())
 }
@@ -251,6 +251,22 @@ impl headers::Header for SpacetimeEnergyUsed {
     }
 }
 
+pub struct SpacetimeSqlSession(pub u64);
+impl headers::Header for SpacetimeSqlSession {
+    fn name() -> &'static http::HeaderName {
+        static NAME: http::HeaderName = http::HeaderName::from_static("spacetime-sql-session");
+        &NAME
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i HeaderValue>>(_values: &mut I) -> Result<Self, headers::Error> {
+        unimplemented!()
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        values.extend([self.0.into()])
+    }
+}
+
 pub struct SpacetimeExecutionDurationMicros(pub Duration);
 impl headers::Header for SpacetimeExecutionDurationMicros {
     fn name() -> &'static http::HeaderName {
@@ -6,6 +6,7 @@ use spacetimedb::auth::identity::{DecodingKey, EncodingKey};
 use spacetimedb::client::ClientActorIndex;
 use spacetimedb::control_db::ControlDb;
 use spacetimedb::database_instance_context_controller::DatabaseInstanceContextController;
+use spacetimedb::db::Durability;
 use spacetimedb::hash::Hash;
 use spacetimedb::host::UpdateDatabaseResult;
 use spacetimedb::host::{EnergyQuanta, HostController};
@@ -68,6 +69,7 @@ pub trait ControlCtx: ControlNodeDelegate + Send + Sync {
         num_replicas: u32,
         force: bool,
         trace_log: bool,
+        durability: Durability,
     ) -> Result<(), anyhow::Error>;
 
     async fn update_database(
@@ -13,6 +13,7 @@ use spacetimedb::host::EntityDef;
 use spacetimedb::host::ReducerArgs;
 use spacetimedb::host::ReducerCallError;
 use spacetimedb::host::ReducerOutcome;
+use spacetimedb::host::UpdateDatabaseError;
 use spacetimedb::host::UpdateDatabaseSuccess;
 use spacetimedb_lib::name;
 use spacetimedb_lib::name::DomainName;
@@ -22,10 +23,11 @@ use spacetimedb_lib::sats::WithTypespace;
 
 use crate::auth::{
     SpacetimeAuth, SpacetimeAuthHeader, SpacetimeEnergyUsed, SpacetimeExecutionDurationMicros, SpacetimeIdentity,
-    SpacetimeIdentityToken,
+    SpacetimeIdentityToken, SpacetimeSqlSession,
 };
 use spacetimedb::address::Address;
 use spacetimedb::database_logger::DatabaseLogger;
+use spacetimedb::db::{Durability, GroupCommit};
 use spacetimedb::host::DescribedEntityType;
 use spacetimedb::identity::Identity;
 use spacetimedb::json::client_api::StmtResultJson;
@@ -144,6 +146,10 @@ fn reducer_outcome_response(identity: &Identity, reducer: &str, outcome: Reducer
                 "Module energy budget exhausted.".to_owned(),
             )
         }
+        ReducerOutcome::WriteConflict => (
+            StatusCode::CONFLICT,
+            "Reducer's transaction conflicted with concurrent transactions and was rolled back.".to_owned(),
+        ),
     }
 }
 
@@ -157,7 +163,9 @@ pub enum DBCallErr {
 use chrono::Utc;
 use rand::Rng;
 use spacetimedb::auth::identity::encode_token;
+use spacetimedb::error::{DBError, SessionError};
 use spacetimedb::sql::execute::execute;
+use spacetimedb::sql::prepared::SqlParam;
 use spacetimedb_lib::identity::AuthCtx;
 use spacetimedb_lib::name::{DnsLookupResponse, InsertDomainResult, PublishResult};
 use spacetimedb_lib::recovery::{RecoveryCode, RecoveryCodeResponse};
@@ -490,15 +498,75 @@ pub struct SqlParams {
 }
 
 #[derive(Deserialize)]
-pub struct SqlQueryParams {}
+pub struct SqlQueryParams {
+    /// The session opened by an earlier `BEGIN`, whose transaction the statements run in.
+    session: Option<u64>,
+}
+
+/// The `application/json` form of a `SQL` request, with the parameters bound to its placeholders `$1`, `$2`, ...
+///
+/// The parameters are given either as `JSON` values in `params`,
+/// or as hex-encoded `BSATN` values in `bsatn_params`.
+#[derive(Deserialize)]
+pub struct SqlRequest {
+    sql: String,
+    #[serde(default)]
+    params: Vec<Box<serde_json::value::RawValue>>,
+    #[serde(default)]
+    bsatn_params: Vec<String>,
+}
+
+impl SqlRequest {
+    fn into_parts(self) -> Result<(String, Vec<SqlParam>), (StatusCode, String)> {
+        let bad_request = |err: String| (StatusCode::BAD_REQUEST, err);
+        let params = match (self.params.is_empty(), self.bsatn_params.is_empty()) {
+            (_, true) => self
+                .params
+                .into_iter()
+                .map(|param| SqlParam::Json(param.get().into()))
+                .collect(),
+            (true, false) => self
+                .bsatn_params
+                .into_iter()
+                .enumerate()
+                .map(|(i, param)| {
+                    hex::decode(param)
+                        .map(|bytes| SqlParam::Bsatn(bytes.into()))
+                        .map_err(|err| bad_request(format!("Invalid parameter `${}`: {err}", i + 1)))
+                })
+                .collect::<Result<_, _>>()?,
+            (false, false) => {
+                return Err(bad_request(
+                    "Only one of `params` and `bsatn_params` can be given".to_string(),
+                ))
+            }
+        };
+        Ok((self.sql, params))
+    }
+}
 
 pub async fn sql(
     State(worker_ctx): State<Arc<dyn WorkerCtx>>,
     Path(SqlParams { name_or_address }): Path<SqlParams>,
-    Query(SqlQueryParams {}): Query<SqlQueryParams>,
+    Query(SqlQueryParams { session }): Query<SqlQueryParams>,
     auth: SpacetimeAuthHeader,
-    body: String,
+    content_type: Option<TypedHeader<headers::ContentType>>,
+    body: Bytes,
 ) -> axum::response::Result<impl IntoResponse> {
+    // A `JSON` body carries the parameters of the query, anything else is the bare `SQL` text.
+    let is_json = content_type.map_or(false, |TypedHeader(content_type)| {
+        mime::Mime::from(content_type).subtype() == mime::JSON
+    });
+    let (sql_text, params) = if is_json {
+        let request: SqlRequest = serde_json::from_slice(&body)
+            .map_err(|err| (StatusCode::BAD_REQUEST, format!("Invalid SQL request: {err}")))?;
+        request.into_parts()?
+    } else {
+        let sql_text = String::from_utf8(body.to_vec())
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Request body didn't contain valid UTF-8"))?;
+        (sql_text, Vec::new())
+    };
+
     // Anyone is authorized to execute SQL queries. The SQL engine will determine
     // which queries this identity is allowed to execute against the database.
     let auth = auth.get_or_create(&*worker_ctx).await?;
@@ -520,29 +588,41 @@ pub async fn sql(
     let instance_id = database_instance.id;
 
     let host = worker_ctx.host_controller();
-    match host.get_module_host(instance_id) {
-        Ok(_) => {}
+    let module = match host.get_module_host(instance_id) {
+        Ok(module) => module,
         Err(_) => {
             let dbic = worker_ctx
                 .load_module_host_context(database, instance_id)
                 .await
                 .map_err(log_and_500)?;
-            host.spawn_module_host(dbic).await.map_err(log_and_500)?;
+            host.spawn_module_host(dbic).await.map_err(log_and_500)?
         }
     };
 
-    let results = match execute(
-        worker_ctx.database_instance_context_controller(),
-        instance_id,
-        body,
-        auth,
-    ) {
-        Ok(results) => results,
+    // The statements may wait for a transaction held open by another session.
+    let outcome = tokio::task::spawn_blocking(move || {
+        execute(
+            worker_ctx.database_instance_context_controller(),
+            instance_id,
+            sql_text,
+            params,
+            session,
+            auth,
+            Some(module.subscription()),
+        )
+    })
+    .await
+    .map_err(log_and_500)?;
+    let outcome = match outcome {
+        Ok(outcome) => outcome,
         Err(err) => {
             log::warn!("{}", err);
             return if let Some(auth_err) = err.get_auth_error() {
                 let err = format!("{auth_err}");
                 Err((StatusCode::UNAUTHORIZED, err).into())
+            } else if let DBError::Session(SessionError::NotFound(_) | SessionError::Expired { .. }) = err {
+                let err = format!("{err}");
+                Err((StatusCode::NOT_FOUND, err).into())
             } else {
                 let err = format!("{err}");
                 Err((StatusCode::BAD_REQUEST, err).into())
@@ -550,7 +630,8 @@ pub async fn sql(
         }
     };
 
-    let json = results
+    let json = outcome
+        .results
         .into_iter()
         .map(|result| StmtResultJson {
             schema: result.head.ty(),
@@ -558,7 +639,11 @@ pub async fn sql(
         })
         .collect::<Vec<_>>();
 
-    Ok((StatusCode::OK, axum::Json(json)))
+    Ok((
+        StatusCode::OK,
+        outcome.session.map(|session| TypedHeader(SpacetimeSqlSession(session))),
+        axum::Json(json),
+    ))
 }
 
 #[derive(Deserialize)]
@@ -764,6 +849,28 @@ pub struct PublishDatabaseQueryParams {
     trace_log: Option<bool>,
     #[serde(default)]
     register_tld: bool,
+    durability: Option<String>,
+    group_commit_ms: Option<u64>,
+    group_commit_bytes: Option<u64>,
+}
+
+fn parse_durability(
+    durability: Option<&str>,
+    group_commit_ms: Option<u64>,
+    group_commit_bytes: Option<u64>,
+) -> Result<Durability, String> {
+    match durability {
+        None | Some("sync") => Ok(Durability::Sync),
+        Some("async") => Ok(Durability::Async),
+        Some("group") => {
+            let default = GroupCommit::default();
+            Ok(Durability::Group(GroupCommit {
+                max_delay_ms: group_commit_ms.unwrap_or(default.max_delay_ms),
+                max_bytes: group_commit_bytes.unwrap_or(default.max_bytes),
+            }))
+        }
+        Some(other) => Err(format!("unknown durability {other}")),
+    }
 }
 
 #[cfg(not(feature = "tracelogging"))]
@@ -789,6 +896,9 @@ pub async fn publish(
         clear,
         trace_log,
         register_tld,
+        durability,
+        group_commit_ms,
+        group_commit_bytes,
     } = query_params;
 
     // You should not be able to publish to a database that you do not own
@@ -843,6 +953,10 @@ pub async fn publish(
 
     let trace_log = should_trace(trace_log);
 
+    let durability = parse_durability(durability.as_deref(), group_commit_ms, group_commit_bytes)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let mut schema_changes = vec![];
     let op = match control_ctx_find_database(&*ctx, &db_address).await? {
         Some(db) => {
             if db.identity != auth.identity {
@@ -858,6 +972,7 @@ pub async fn publish(
                     num_replicas,
                     clear,
                     trace_log,
+                    durability,
                 )
                 .await
                 .map_err(log_and_500)?;
@@ -870,20 +985,31 @@ pub async fn publish(
                 if let Some(res) = res {
                     let success = match res {
                         Ok(success) => success,
+                        Err(UpdateDatabaseError::MigrateFailed(migrate_result)) => {
+                            let (status, body) =
+                                reducer_outcome_response(&auth.identity, "migrate", migrate_result.outcome);
+                            return Err((
+                                status,
+                                format!("Database update rejected: migrate reducer failed: {body}"),
+                            )
+                                .into());
+                        }
                         Err(e) => {
                             return Err((StatusCode::BAD_REQUEST, format!("Database update rejected: {e}")).into());
                         }
                     };
-                    if let UpdateDatabaseSuccess {
-                        update_result: Some(update_result),
+                    let UpdateDatabaseSuccess {
+                        schema_changes: applied_changes,
+                        update_result,
                         migrate_results: _,
-                    } = success
-                    {
+                    } = success;
+                    if let Some(update_result) = update_result {
                         match reducer_outcome_response(&auth.identity, "update", update_result.outcome) {
                             (StatusCode::OK, _) => {}
                             (status, body) => return Err((status, body).into()),
                         }
                     }
+                    schema_changes = applied_changes;
                 }
 
                 log::debug!("Updated database {}", db_address.to_hex());
@@ -906,6 +1032,7 @@ pub async fn publish(
                 num_replicas,
                 false,
                 trace_log,
+                durability,
             )
             .await
             .map_err(log_and_500)?;
@@ -920,6 +1047,7 @@ pub async fn publish(
         }),
         address: db_address.to_hex(),
         op,
+        schema_changes,
     };
 
     //TODO(tyler): Eventually we want it to be possible to publish a database
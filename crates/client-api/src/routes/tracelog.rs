@@ -10,7 +10,7 @@ use tempdir::TempDir;
 
 use spacetimedb::address::Address;
 use spacetimedb::database_instance_context::DatabaseInstanceContext;
-use spacetimedb::db::Storage;
+use spacetimedb::db::{Durability, Storage};
 use spacetimedb::hash::hash_bytes;
 use spacetimedb::host::instance_env::InstanceEnv;
 use spacetimedb::host::scheduler::Scheduler;
@@ -87,6 +87,7 @@ pub async fn perform_tracelog_replay(body: Bytes) -> axum::response::Result<impl
         address,
         db_path.to_path_buf(),
         logger_path,
+        Durability::default(),
     );
     let iv = InstanceEnv::new(dbic, Scheduler::dummy(&tmp_dir.path().join("scheduler")), None);
 
@@ -6,9 +6,11 @@ mod client_connection_index;
 mod message_handlers;
 pub mod messages;
 
-pub use client_connection::{ClientClosed, ClientConnection, ClientConnectionSender, DataMessage, Protocol};
+pub use client_connection::{
+    ClientCall, ClientClosed, ClientConnection, ClientConnectionSender, DataMessage, Protocol,
+};
 pub use client_connection_index::ClientActorIndex;
-pub use message_handlers::MessageHandleError;
+pub use message_handlers::{MessageExecutionError, MessageHandleError};
 
 #[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
 pub struct ClientActorId {
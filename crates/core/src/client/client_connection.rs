@@ -22,6 +22,14 @@ pub struct ClientConnectionSender {
     sendtx: mpsc::Sender<DataMessage>,
 }
 
+/// A reducer call made by a client over its connection,
+/// with the `request_id` the client chose for it.
+#[derive(Clone, Debug)]
+pub struct ClientCall {
+    pub sender: ClientConnectionSender,
+    pub request_id: u32,
+}
+
 #[derive(Debug, thiserror::Error)]
 #[error("client disconnected")]
 pub struct ClientClosed;
@@ -32,6 +40,12 @@ impl ClientConnectionSender {
         Self { id, protocol, sendtx }
     }
 
+    /// Like [Self::dummy], but keeps the messages sent to the client, in the returned receiver.
+    pub fn dummy_with_channel(id: ClientActorId, protocol: Protocol) -> (Self, mpsc::Receiver<DataMessage>) {
+        let (sendtx, rx) = mpsc::channel(16);
+        (Self { id, protocol, sendtx }, rx)
+    }
+
     pub fn send_message(&self, message: impl ServerMessage) -> impl Future<Output = Result<(), ClientClosed>> + '_ {
         self.send(message.serialize(self.protocol))
     }
@@ -159,13 +173,30 @@ impl ClientConnection {
         message_handlers::handle(self, message.into())
     }
 
-    pub async fn call_reducer(&self, reducer: &str, args: ReducerArgs) -> Result<ReducerCallResult, ReducerCallError> {
+    pub async fn call_reducer(
+        &self,
+        reducer: &str,
+        args: ReducerArgs,
+        request_id: u32,
+    ) -> Result<ReducerCallResult, ReducerCallError> {
+        let client = ClientCall {
+            sender: self.sender(),
+            request_id,
+        };
         self.module
-            .call_reducer(self.id.identity, Some(self.sender()), reducer, args)
+            .call_reducer(self.id.identity, Some(client), reducer, args)
             .await
     }
 
     pub fn subscribe(&self, subscription: Subscribe) -> Result<(), NoSuchModule> {
         self.module.subscription().add_subscriber(self.sender(), subscription)
     }
+
+    pub fn subscribe_query(&self, query_id: u32, query: String) -> Result<(), NoSuchModule> {
+        self.module.subscription().add_query(self.sender(), query_id, query)
+    }
+
+    pub fn unsubscribe_query(&self, query_id: u32) -> Result<(), NoSuchModule> {
+        self.module.subscription().remove_query(self.sender(), query_id)
+    }
 }
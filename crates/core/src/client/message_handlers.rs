@@ -3,7 +3,7 @@ use std::time::Duration;
 use crate::host::module_host::{EventStatus, ModuleEvent, ModuleFunctionCall};
 use crate::host::{EnergyDiff, ReducerArgs, Timestamp};
 use crate::identity::Identity;
-use crate::protobuf::client_api::{message, FunctionCall, Message, Subscribe};
+use crate::protobuf::client_api::{message, FunctionCall, Message, Subscribe, SubscribeQuery, UnsubscribeQuery};
 use crate::worker_metrics::{WEBSOCKET_REQUESTS, WEBSOCKET_REQUEST_MSG_SIZE};
 use bytes::Bytes;
 use bytestring::ByteString;
@@ -48,11 +48,25 @@ pub async fn handle(client: &ClientConnection, message: DataMessage) -> Result<(
 async fn handle_binary(client: &ClientConnection, message_buf: Vec<u8>) -> Result<(), MessageHandleError> {
     let message = Message::decode(Bytes::from(message_buf))?;
     let message = match message.r#type {
-        Some(message::Type::FunctionCall(FunctionCall { ref reducer, arg_bytes })) => {
+        Some(message::Type::FunctionCall(FunctionCall {
+            ref reducer,
+            arg_bytes,
+            request_id,
+        })) => {
             let args = ReducerArgs::Bsatn(arg_bytes.into());
-            DecodedMessage::Call { reducer, args }
+            DecodedMessage::Call {
+                reducer,
+                args,
+                request_id,
+            }
         }
         Some(message::Type::Subscribe(subscription)) => DecodedMessage::Subscribe(subscription),
+        Some(message::Type::SubscribeQuery(SubscribeQuery { query_id, query })) => {
+            DecodedMessage::SubscribeQuery { query_id, query }
+        }
+        Some(message::Type::UnsubscribeQuery(UnsubscribeQuery { query_id })) => {
+            DecodedMessage::UnsubscribeQuery { query_id }
+        }
         _ => return Err(MessageHandleError::InvalidMessage),
     };
 
@@ -69,19 +83,35 @@ async fn handle_text(client: &ClientConnection, message: String) -> Result<(), M
             #[serde(borrow, rename = "fn")]
             func: std::borrow::Cow<'a, str>,
             args: &'a serde_json::value::RawValue,
+            #[serde(default)]
+            request_id: u32,
         },
         #[serde(rename = "subscribe")]
         Subscribe { query_strings: Vec<String> },
+        #[serde(rename = "subscribe_query")]
+        SubscribeQuery { query_id: u32, query: String },
+        #[serde(rename = "unsubscribe_query")]
+        UnsubscribeQuery { query_id: u32 },
     }
 
     let message = ByteString::from(message);
     let msg = serde_json::from_str::<Message>(&message)?;
     let msg = match msg {
-        Message::Call { ref func, args } => {
+        Message::Call {
+            ref func,
+            args,
+            request_id,
+        } => {
             let args = ReducerArgs::Json(message.slice_ref(args.get()));
-            DecodedMessage::Call { reducer: func, args }
+            DecodedMessage::Call {
+                reducer: func,
+                args,
+                request_id,
+            }
         }
         Message::Subscribe { query_strings } => DecodedMessage::Subscribe(Subscribe { query_strings }),
+        Message::SubscribeQuery { query_id, query } => DecodedMessage::SubscribeQuery { query_id, query },
+        Message::UnsubscribeQuery { query_id } => DecodedMessage::UnsubscribeQuery { query_id },
     };
 
     msg.handle(client).await?;
@@ -90,22 +120,46 @@ async fn handle_text(client: &ClientConnection, message: String) -> Result<(), M
 }
 
 enum DecodedMessage<'a> {
-    Call { reducer: &'a str, args: ReducerArgs },
+    Call {
+        reducer: &'a str,
+        args: ReducerArgs,
+        request_id: u32,
+    },
     Subscribe(Subscribe),
+    SubscribeQuery {
+        query_id: u32,
+        query: String,
+    },
+    UnsubscribeQuery {
+        query_id: u32,
+    },
 }
 
 impl DecodedMessage<'_> {
     async fn handle(self, client: &ClientConnection) -> Result<(), MessageExecutionError> {
+        let mut request_id = 0;
         let res = match self {
-            DecodedMessage::Call { reducer, args } => {
-                let res = client.call_reducer(reducer, args).await;
+            DecodedMessage::Call {
+                reducer,
+                args,
+                request_id: id,
+            } => {
+                request_id = id;
+                let res = client.call_reducer(reducer, args, request_id).await;
                 res.map(drop).map_err(|e| (Some(reducer), e.into()))
             }
             DecodedMessage::Subscribe(subscription) => client.subscribe(subscription).map_err(|e| (None, e.into())),
+            DecodedMessage::SubscribeQuery { query_id, query } => {
+                client.subscribe_query(query_id, query).map_err(|e| (None, e.into()))
+            }
+            DecodedMessage::UnsubscribeQuery { query_id } => {
+                client.unsubscribe_query(query_id).map_err(|e| (None, e.into()))
+            }
         };
         res.map_err(|(reducer, err)| MessageExecutionError {
             reducer: reducer.map(str::to_owned),
             caller_identity: client.id.identity,
+            request_id,
             err,
         })
     }
@@ -117,6 +171,8 @@ impl DecodedMessage<'_> {
 pub struct MessageExecutionError {
     pub reducer: Option<String>,
     pub caller_identity: Identity,
+    /// The id the client chose for the reducer call, or 0 if the message was not a call.
+    pub request_id: u32,
     #[source]
     pub err: anyhow::Error,
 }
@@ -133,23 +189,29 @@ impl MessageExecutionError {
             status: EventStatus::Failed(format!("{:#}", self.err)),
             energy_quanta_used: EnergyDiff::ZERO,
             host_execution_duration: Duration::ZERO,
+            commit: None,
+            read_tx: None,
         }
     }
 }
 
 impl ServerMessage for MessageExecutionError {
     fn serialize_text(self) -> crate::json::client_api::MessageJson {
+        let request_id = self.request_id;
         TransactionUpdateMessage {
             event: &mut self.into_event(),
             database_update: Default::default(),
+            request_id,
         }
         .serialize_text()
     }
 
     fn serialize_binary(self) -> Message {
+        let request_id = self.request_id;
         TransactionUpdateMessage {
             event: &mut self.into_event(),
             database_update: Default::default(),
+            request_id,
         }
         .serialize_binary()
     }
@@ -2,8 +2,12 @@ use prost::Message as _;
 
 use crate::host::module_host::{DatabaseUpdate, EventStatus, ModuleEvent};
 use crate::identity::Identity;
-use crate::json::client_api::{EventJson, FunctionCallJson, IdentityTokenJson, MessageJson, TransactionUpdateJson};
-use crate::protobuf::client_api::{event, message, Event, FunctionCall, IdentityToken, Message, TransactionUpdate};
+use crate::json::client_api::{
+    EventJson, FunctionCallJson, IdentityTokenJson, MessageJson, QueryUpdateJson, TransactionUpdateJson,
+};
+use crate::protobuf::client_api::{
+    event, message, Event, FunctionCall, IdentityToken, Message, QueryUpdate, TransactionUpdate,
+};
 
 use super::{DataMessage, Protocol};
 
@@ -45,15 +49,22 @@ impl ServerMessage for IdentityTokenMessage {
 pub struct TransactionUpdateMessage<'a> {
     pub event: &'a mut ModuleEvent,
     pub database_update: DatabaseUpdate,
+    /// The id chosen for the call by the client receiving the message, if it made the call, otherwise 0.
+    pub request_id: u32,
 }
 
 impl ServerMessage for TransactionUpdateMessage<'_> {
     fn serialize_text(self) -> MessageJson {
-        let Self { event, database_update } = self;
+        let Self {
+            event,
+            database_update,
+            request_id,
+        } = self;
         let (status_str, errmsg) = match &event.status {
             EventStatus::Committed(_) => ("committed", String::new()),
             EventStatus::Failed(errmsg) => ("failed", errmsg.clone()),
             EventStatus::OutOfEnergy => ("out_of_energy", String::new()),
+            EventStatus::WriteConflict => ("write_conflict", String::new()),
         };
 
         let event = EventJson {
@@ -63,8 +74,10 @@ impl ServerMessage for TransactionUpdateMessage<'_> {
             function_call: FunctionCallJson {
                 reducer: event.function_call.reducer.to_owned(),
                 args: event.function_call.args.get_json().clone(),
+                request_id,
             },
             energy_quanta_used: event.energy_quanta_used.0,
+            host_execution_duration_micros: event.host_execution_duration.as_micros() as u64,
             message: errmsg,
         };
 
@@ -76,11 +89,16 @@ impl ServerMessage for TransactionUpdateMessage<'_> {
     }
 
     fn serialize_binary(self) -> Message {
-        let Self { event, database_update } = self;
+        let Self {
+            event,
+            database_update,
+            request_id,
+        } = self;
         let (status, errmsg) = match &event.status {
             EventStatus::Committed(_) => (event::Status::Committed, String::new()),
             EventStatus::Failed(errmsg) => (event::Status::Failed, errmsg.clone()),
             EventStatus::OutOfEnergy => (event::Status::OutOfEnergy, String::new()),
+            EventStatus::WriteConflict => (event::Status::WriteConflict, String::new()),
         };
 
         let event = Event {
@@ -90,6 +108,7 @@ impl ServerMessage for TransactionUpdateMessage<'_> {
             function_call: Some(FunctionCall {
                 reducer: event.function_call.reducer.to_owned(),
                 arg_bytes: event.function_call.args.get_bsatn().clone().into(),
+                request_id,
             }),
             message: errmsg,
             energy_quanta_used: event.energy_quanta_used.0 as i64,
@@ -114,6 +133,7 @@ impl ServerMessage for &mut TransactionUpdateMessage<'_> {
         TransactionUpdateMessage {
             event: &mut *self.event,
             database_update: self.database_update.clone(),
+            request_id: self.request_id,
         }
         .serialize_text()
     }
@@ -121,6 +141,7 @@ impl ServerMessage for &mut TransactionUpdateMessage<'_> {
         TransactionUpdateMessage {
             event: &mut *self.event,
             database_update: self.database_update.clone(),
+            request_id: self.request_id,
         }
         .serialize_binary()
     }
@@ -142,6 +163,30 @@ impl ServerMessage for SubscriptionUpdateMessage {
     }
 }
 
+/// The rows gained or lost by a client upon subscribing to or unsubscribing from the query `query_id`.
+pub struct QueryUpdateMessage {
+    pub query_id: u32,
+    pub database_update: DatabaseUpdate,
+}
+
+impl ServerMessage for QueryUpdateMessage {
+    fn serialize_text(self) -> MessageJson {
+        MessageJson::QueryUpdate(QueryUpdateJson {
+            query_id: self.query_id,
+            subscription_update: self.database_update.into_json(),
+        })
+    }
+
+    fn serialize_binary(self) -> Message {
+        Message {
+            r#type: Some(message::Type::QueryUpdate(QueryUpdate {
+                query_id: self.query_id,
+                subscription_update: Some(self.database_update.into_protobuf()),
+            })),
+        }
+    }
+}
+
 pub struct CachedMessage<M> {
     msg: M,
     text: Option<String>,
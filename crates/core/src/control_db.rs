@@ -3,7 +3,7 @@ use crate::address::Address;
 use crate::hash::hash_bytes;
 use crate::host::EnergyQuanta;
 use crate::identity::Identity;
-use crate::messages::control_db::{Database, DatabaseInstance, EnergyBalance, IdentityEmail, Node};
+use crate::messages::control_db::{Database, DatabaseInstance, DatabaseV0, EnergyBalance, IdentityEmail, Node};
 use crate::stdb_path;
 
 use spacetimedb_lib::name::{DomainName, DomainParsingError, InsertDomainResult, RegisterTldResult, Tld, TldRef};
@@ -41,6 +41,17 @@ pub enum Error {
     JSONDeserializationError(#[from] serde_json::Error),
 }
 
+/// Decodes a [`Database`] record.
+///
+/// Records written before `durability` was added are decoded as [`DatabaseV0`].
+fn decode_database(bytes: &[u8]) -> core::result::Result<Database, bsatn::DecodeError> {
+    bsatn::from_slice(bytes).or_else(|e| {
+        bsatn::from_slice::<DatabaseV0>(bytes)
+            .map(Database::from)
+            .map_err(|_| e)
+    })
+}
+
 impl From<sled::Error> for Error {
     fn from(err: sled::Error) -> Self {
         match err {
@@ -287,7 +298,7 @@ impl ControlDb {
         let scan_key: &[u8] = b"";
         for result in tree.range(scan_key..) {
             let (_key, value) = result?;
-            let database = bsatn::from_slice(&value).unwrap();
+            let database = decode_database(&value).unwrap();
             databases.push(database);
         }
         Ok(databases)
@@ -307,7 +318,7 @@ impl ControlDb {
         let key = address.to_hex();
         let value = tree.get(key.as_bytes())?;
         if let Some(value) = value {
-            let database = bsatn::from_slice(&value[..]).unwrap();
+            let database = decode_database(&value[..]).unwrap();
             return Ok(Some(database));
         }
         Ok(None)
@@ -341,7 +352,7 @@ impl ControlDb {
 
         let old_value = tree.get(database.id.to_be_bytes())?;
         if let Some(old_value) = old_value {
-            let old_database: Database = bsatn::from_slice(&old_value[..])?;
+            let old_database = decode_database(&old_value[..])?;
 
             if database.address != old_database.address && tree_by_address.contains_key(key.as_bytes())? {
                 return Err(Error::DatabaseAlreadyExists(key));
@@ -363,7 +374,7 @@ impl ControlDb {
         let tree_by_address = self.db.open_tree("database_by_address")?;
 
         if let Some(old_value) = tree.get(id.to_be_bytes())? {
-            let database: Database = bsatn::from_slice(&old_value[..])?;
+            let database = decode_database(&old_value[..])?;
             let key = database.address.to_hex();
 
             tree_by_address.remove(key.as_bytes())?;
@@ -92,3 +92,45 @@ async fn test_domain() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_decode_database_without_durability() -> anyhow::Result<()> {
+    let tmp = TempDir::new("database-v0")?;
+    let cdb = tokio::task::spawn_blocking({
+        let path = tmp.path().to_path_buf();
+        move || ControlDb::at(path)
+    })
+    .await??;
+
+    let address = Address::from_arr(&[1; 16]);
+    // A record as written before `Database` had a `durability`.
+    let old = DatabaseV0 {
+        id: 7,
+        address,
+        identity: *ALICE,
+        host_type: crate::messages::control_db::HostType::Wasmer,
+        num_replicas: 1,
+        program_bytes_address: hash_bytes("program"),
+        trace_log: true,
+    };
+    let buf = bsatn::to_vec(&old)?;
+    cdb.db.open_tree("database")?.insert(7u64.to_be_bytes(), buf.clone())?;
+    cdb.db.open_tree("database_by_address")?.insert(address.to_hex(), buf)?;
+
+    let database = cdb.get_database_by_id(7).await?.expect("database should decode");
+    assert_eq!(database.identity, *ALICE);
+    assert!(database.trace_log);
+    assert_eq!(database.durability, crate::db::Durability::Sync);
+
+    let database = cdb
+        .get_database_by_address(&address)
+        .await?
+        .expect("database should decode");
+    assert_eq!(database.id, 7);
+    assert_eq!(database.durability, crate::db::Durability::Sync);
+
+    assert_eq!(cdb.delete_database(7).await?, Some(7));
+    let _ = tmp.close().ok(); // force tmp to not be dropped until here
+
+    Ok(())
+}
@@ -5,9 +5,11 @@ use crate::db::ostorage::memory_object_db::MemoryObjectDB;
 use crate::db::ostorage::sled_object_db::SledObjectDB;
 use crate::db::ostorage::ObjectDB;
 use crate::db::relational_db::RelationalDB;
-use crate::db::Storage;
+use crate::db::{Durability, Storage};
 use crate::identity::Identity;
 use crate::messages::control_db::Database;
+use crate::sql::prepared::PreparedStatements;
+use crate::sql::session::SqlSessions;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
@@ -20,6 +22,8 @@ pub struct DatabaseInstanceContext {
     pub address: Address,
     pub logger: Arc<Mutex<DatabaseLogger>>,
     pub relational_db: Arc<RelationalDB>,
+    pub prepared_statements: Arc<PreparedStatements>,
+    pub sql_sessions: Arc<SqlSessions>,
 }
 
 impl DatabaseInstanceContext {
@@ -39,6 +43,7 @@ impl DatabaseInstanceContext {
             database.address,
             db_path,
             &log_path,
+            database.durability,
         )
     }
 
@@ -59,6 +64,7 @@ impl DatabaseInstanceContext {
         address: Address,
         db_path: PathBuf,
         log_path: &Path,
+        durability: Durability,
     ) -> Arc<Self> {
         let message_log = match storage {
             Storage::Memory => None,
@@ -84,7 +90,9 @@ impl DatabaseInstanceContext {
             identity,
             address,
             logger: Arc::new(Mutex::new(DatabaseLogger::open(log_path))),
-            relational_db: Arc::new(RelationalDB::open(db_path, message_log, odb).unwrap()),
+            relational_db: Arc::new(RelationalDB::open(db_path, message_log, odb, durability).unwrap()),
+            prepared_statements: Arc::default(),
+            sql_sessions: Arc::default(),
         })
     }
 
@@ -3,6 +3,7 @@ use super::{
     message_log::MessageLog,
     messages::commit::Commit,
     ostorage::ObjectDB,
+    Durability, GroupCommit,
 };
 use crate::{
     db::{
@@ -14,13 +15,66 @@ use crate::{
     },
     error::DBError,
 };
-use spacetimedb_lib::hash::hash_bytes;
-use std::sync::Arc;
+use spacetimedb_lib::hash::{hash_bytes, Hash};
+use spacetimedb_lib::DataKey;
+use std::collections::HashSet;
 use std::sync::Mutex;
+use std::sync::{Arc, Condvar, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// What was removed by [`CommitLog::compact`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub segments_removed: usize,
+    pub objects_removed: usize,
+}
+
+/// A commit which has been appended to the [`MessageLog`],
+/// but which may not be durable yet, depending on the [`Durability`] of the database.
+#[derive(Debug, Clone)]
+pub struct AppendedCommit {
+    /// The number of bytes the commit took up in the message log.
+    pub bytes_written: usize,
+    /// The offset of the commit in the message log.
+    pub commit_offset: u64,
+    /// The offset of the first commit which is not durable yet,
+    /// or `None` if there is no message log.
+    durable_offset: Option<watch::Receiver<u64>>,
+}
+
+impl AppendedCommit {
+    /// Returns whether the commit is durable yet.
+    pub fn is_durable(&self) -> bool {
+        match &self.durable_offset {
+            Some(durable_offset) => *durable_offset.borrow() > self.commit_offset,
+            None => true,
+        }
+    }
+
+    /// Waits until the commit is durable.
+    ///
+    /// Returns `false` if the message log was closed before the commit became durable.
+    pub async fn durable(mut self) -> bool {
+        let Some(durable_offset) = &mut self.durable_offset else {
+            return true;
+        };
+        loop {
+            if *durable_offset.borrow() > self.commit_offset {
+                return true;
+            }
+            if durable_offset.changed().await.is_err() {
+                return *durable_offset.borrow() > self.commit_offset;
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct CommitLog {
     mlog: Option<Arc<Mutex<MessageLog>>>,
+    syncer: Option<Arc<LogSyncer>>,
     odb: Arc<Mutex<Box<dyn ObjectDB + Send>>>,
     unwritten_commit: Arc<Mutex<Commit>>,
 }
@@ -30,36 +84,122 @@ impl CommitLog {
         mlog: Option<Arc<Mutex<MessageLog>>>,
         odb: Arc<Mutex<Box<dyn ObjectDB + Send>>>,
         unwritten_commit: Commit,
+        durability: Durability,
     ) -> Self {
         Self {
+            syncer: mlog.clone().map(|mlog| LogSyncer::start(mlog, durability)),
             mlog,
             odb,
             unwritten_commit: Arc::new(Mutex::new(unwritten_commit)),
         }
     }
 
+    /// Returns the hash of the last commit written,
+    /// together with the offsets the next commit and transaction will be written at.
+    pub fn position(&self) -> (Option<Hash>, u64, u64) {
+        let unwritten_commit = self.unwritten_commit.lock().unwrap();
+        (
+            unwritten_commit.parent_commit_hash,
+            unwritten_commit.commit_offset,
+            unwritten_commit.min_tx_offset,
+        )
+    }
+
     /// Persist to disk the [Tx] result into the [MessageLog].
     ///
-    /// Returns `Some(n_bytes_written)` if `commit_result` was persisted, `None` if it doesn't have bytes to write.
+    /// Returns `Some(appended_commit)` if `commit_result` was written, `None` if it doesn't have bytes to write.
+    /// Whether the commit is durable by the time this returns depends on the [`Durability`] of the log.
     #[tracing::instrument(skip_all)]
-    pub fn append_tx<D>(&self, tx_data: &TxData, datastore: &D) -> Result<Option<usize>, DBError>
+    pub fn append_tx<D>(&self, tx_data: &TxData, datastore: &D) -> Result<Option<AppendedCommit>, DBError>
     where
         D: MutTxDatastore<RowId = RowId>,
     {
-        if let Some(bytes) = self.generate_commit(tx_data, datastore) {
-            if let Some(mlog) = &self.mlog {
-                let mut mlog = mlog.lock().unwrap();
+        // NOTE: The unwritten commit stays locked until the commit is in the message log,
+        // so that commits are appended in the order of their offsets,
+        // and so that `compact` never observes a commit in flight.
+        let mut unwritten_commit = self.unwritten_commit.lock().unwrap();
+        let commit_offset = unwritten_commit.commit_offset;
+        if let Some(bytes) = self.generate_commit(&mut unwritten_commit, tx_data, datastore) {
+            if let Some(syncer) = &self.syncer {
+                let mut mlog = syncer.mlog.lock().unwrap();
                 mlog.append(&bytes)?;
-                mlog.sync_all()?;
-                log::trace!("DATABASE: FSYNC");
+                syncer.appended(&mut mlog, bytes.len())?;
             }
-            Ok(Some(bytes.len()))
+            Ok(Some(AppendedCommit {
+                bytes_written: bytes.len(),
+                commit_offset,
+                durable_offset: self.syncer.as_ref().map(|syncer| syncer.durable_offset.subscribe()),
+            }))
         } else {
             Ok(None)
         }
     }
 
-    fn generate_commit<D: MutTxDatastore<RowId = RowId>>(&self, tx_data: &TxData, _datastore: &D) -> Option<Vec<u8>> {
+    /// Syncs every commit appended so far to disk, regardless of the [`Durability`] of the log.
+    pub fn sync_all(&self) -> Result<(), DBError> {
+        match &self.syncer {
+            Some(syncer) => syncer.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the message log segments which only contain commits before `min_commit_offset`,
+    /// then removes every object from the object store which is neither in `live_objects`
+    /// nor inserted by one of the remaining commits.
+    ///
+    /// `live_objects` must be collected before calling this, after the commit at `min_commit_offset`.
+    /// The objects of the commits made since are then kept as inserted by a remaining commit.
+    ///
+    /// NOTE: `live_objects` must not be collected while the log is locked here,
+    /// as the datastore is locked by the commits appending to the log.
+    pub fn compact(&self, min_commit_offset: u64, live_objects: HashSet<Hash>) -> Result<CompactionStats, DBError> {
+        // No commit is in flight while the log is scanned and the objects removed.
+        let _unwritten_commit = self.unwritten_commit.lock().unwrap();
+
+        let mut segments_removed = 0;
+        let mut referenced = live_objects;
+        if let Some(mlog) = &self.mlog {
+            let mut mlog = mlog.lock().unwrap();
+            segments_removed = mlog.truncate_before(min_commit_offset)?;
+
+            // Replaying the remaining commits needs the objects they insert,
+            // even if the rows have since been deleted.
+            mlog.flush()?;
+            for message in mlog.iter_from(min_commit_offset) {
+                let (commit, _) = Commit::decode(message);
+                for transaction in &commit.transactions {
+                    for write in &transaction.writes {
+                        if let (Operation::Insert, DataKey::Hash(hash)) = (write.operation, write.data_key) {
+                            referenced.insert(hash);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut odb = self.odb.lock().unwrap();
+        let objects_removed = odb.retain(&mut |hash| referenced.contains(hash))?;
+        odb.sync_all()?;
+
+        log::debug!(
+            "Compacted {} message log segments and {} objects before commit offset {}",
+            segments_removed,
+            objects_removed,
+            min_commit_offset
+        );
+
+        Ok(CompactionStats {
+            segments_removed,
+            objects_removed,
+        })
+    }
+
+    fn generate_commit<D: MutTxDatastore<RowId = RowId>>(
+        &self,
+        unwritten_commit: &mut Commit,
+        tx_data: &TxData,
+        _datastore: &D,
+    ) -> Option<Vec<u8>> {
         // We are not creating a commit for empty transactions.
         // The reason for this is that empty transactions get encoded as 0 bytes,
         // so a commit containing an empty transaction contains no useful information.
@@ -67,7 +207,6 @@ impl CommitLog {
             return None;
         }
 
-        let mut unwritten_commit = self.unwritten_commit.lock().unwrap();
         let writes = tx_data
             .records
             .iter()
@@ -112,3 +251,131 @@ impl CommitLog {
         }
     }
 }
+
+/// Syncs the [`MessageLog`] to disk according to a [`Durability`],
+/// and publishes the offset of the first commit which is not durable yet.
+struct LogSyncer {
+    mlog: Arc<Mutex<MessageLog>>,
+    durability: Durability,
+    unsynced: Mutex<Unsynced>,
+    wakeup: Condvar,
+    durable_offset: watch::Sender<u64>,
+}
+
+/// The commits which have been written to the OS, but not synced to disk yet.
+#[derive(Default)]
+struct Unsynced {
+    bytes: u64,
+    since: Option<Instant>,
+}
+
+impl LogSyncer {
+    fn start(mlog: Arc<Mutex<MessageLog>>, durability: Durability) -> Arc<Self> {
+        let (durable_offset, _) = watch::channel(mlog.lock().unwrap().max_offset());
+        let syncer = Arc::new(Self {
+            mlog,
+            durability,
+            unsynced: Mutex::default(),
+            wakeup: Condvar::new(),
+            durable_offset,
+        });
+        if let Some(window) = durability.window() {
+            let syncer = Arc::downgrade(&syncer);
+            thread::Builder::new()
+                .name("log-syncer".into())
+                .spawn(move || Self::run_group_commit(syncer, window))
+                .expect("failed to spawn the message log syncer thread");
+        }
+        syncer
+    }
+
+    /// Called with `mlog` still locked after appending a commit of `bytes_written` bytes to it.
+    fn appended(&self, mlog: &mut MessageLog, bytes_written: usize) -> Result<(), DBError> {
+        match self.durability.window() {
+            None => {
+                mlog.sync_all()?;
+                log::trace!("DATABASE: FSYNC");
+                self.publish(mlog.max_offset());
+            }
+            // The commit only becomes durable once the syncer thread syncs the window it is in.
+            Some(window) => {
+                mlog.flush()?;
+                let mut unsynced = self.unsynced.lock().unwrap();
+                unsynced.bytes += bytes_written as u64;
+                let window_opened = unsynced.since.is_none();
+                unsynced.since.get_or_insert_with(Instant::now);
+                if window_opened || unsynced.bytes >= window.max_bytes {
+                    self.wakeup.notify_one();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Syncs every commit appended so far to disk.
+    fn sync(&self) -> Result<(), DBError> {
+        let mut mlog = self.mlog.lock().unwrap();
+        let mut unsynced = self.unsynced.lock().unwrap();
+        *unsynced = Unsynced::default();
+        if let Err(err) = mlog.sync_all() {
+            // Try again once the window is full again, rather than right away.
+            unsynced.since = Some(Instant::now());
+            return Err(err);
+        }
+        log::trace!("DATABASE: FSYNC");
+        self.publish(mlog.max_offset());
+        Ok(())
+    }
+
+    fn publish(&self, durable_offset: u64) {
+        self.durable_offset.send_if_modified(|offset| {
+            let advanced = durable_offset > *offset;
+            *offset = (*offset).max(durable_offset);
+            advanced
+        });
+    }
+
+    fn run_group_commit(syncer: Weak<Self>, window: GroupCommit) {
+        // The thread checks back at least this often,
+        // so that it exits soon after the log has been dropped.
+        const MAX_WAIT: Duration = Duration::from_millis(100);
+
+        while let Some(syncer) = syncer.upgrade() {
+            if syncer.wait_for_window(window, MAX_WAIT) {
+                if let Err(err) = syncer.sync() {
+                    log::error!("Failed to sync the message log: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Waits at most `max_wait` for the group commit `window` to fill up,
+    /// and returns whether it did.
+    fn wait_for_window(&self, window: GroupCommit, max_wait: Duration) -> bool {
+        let is_full = |unsynced: &Unsynced| match unsynced.since {
+            Some(since) => unsynced.bytes >= window.max_bytes || since.elapsed() >= window.max_delay(),
+            None => false,
+        };
+
+        let unsynced = self.unsynced.lock().unwrap();
+        if is_full(&unsynced) {
+            return true;
+        }
+        let timeout = match unsynced.since {
+            Some(since) => window.max_delay().saturating_sub(since.elapsed()).min(max_wait),
+            None => max_wait,
+        };
+        let (unsynced, _) = self.wakeup.wait_timeout(unsynced, timeout).unwrap();
+        is_full(&unsynced)
+    }
+}
+
+impl Drop for LogSyncer {
+    fn drop(&mut self) {
+        if self.durability != Durability::Sync {
+            if let Err(err) = self.sync() {
+                log::error!("Failed to sync the message log on close: {}", err);
+            }
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use crate::error::DBError;
 use spacetimedb_lib::relation::{DbTable, RowCount};
-use spacetimedb_sats::ProductValue;
+use spacetimedb_sats::{AlgebraicValue, ProductValue};
 
-use super::datastore::locking_tx_datastore::Iter;
+use std::ops::RangeBounds;
+
+use super::datastore::locking_tx_datastore::{Iter, IterByColRange};
 
 #[derive(Debug, Clone, Copy)]
 pub enum CatalogKind {
@@ -24,6 +26,19 @@ impl<'a> TableCursor<'a> {
     }
 }
 
+/// A relational iterator that yields the rows of a table whose column falls within a range,
+/// seeking an index on the column when there is one.
+pub struct IndexCursor<'a, R: RangeBounds<AlgebraicValue>> {
+    pub table: DbTable,
+    pub iter: IterByColRange<'a, R>,
+}
+
+impl<'a, R: RangeBounds<AlgebraicValue>> IndexCursor<'a, R> {
+    pub fn new(table: DbTable, iter: IterByColRange<'a, R>) -> Result<Self, DBError> {
+        Ok(Self { table, iter })
+    }
+}
+
 /// Common wrapper for relational iterators of [Catalog].
 pub struct CatalogCursor<I> {
     pub(crate) table: DbTable,
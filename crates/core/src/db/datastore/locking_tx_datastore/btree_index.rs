@@ -1,14 +1,12 @@
-use super::RowId;
+use super::{index::project_key, RowId};
 use crate::{
     db::datastore::traits::{IndexId, IndexSchema},
     error::DBError,
 };
-use spacetimedb_lib::{data_key::ToDataKey, DataKey};
-use spacetimedb_sats::{AlgebraicValue, ProductValue};
-use std::{
-    collections::{btree_set, BTreeSet},
-    ops::{Bound, RangeBounds},
-};
+use im::{ordset, OrdSet};
+use spacetimedb_lib::{data_key::ToDataKey, DataKey, IndexType};
+use spacetimedb_sats::{product_value::InvalidFieldError, AlgebraicValue, ProductValue};
+use std::ops::{Bound, RangeBounds};
 
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
 struct IndexKey {
@@ -27,7 +25,7 @@ impl IndexKey {
 }
 
 pub struct BTreeIndexIter<'a> {
-    iter: btree_set::Iter<'a, IndexKey>,
+    iter: ordset::Iter<'a, IndexKey>,
 }
 
 impl Iterator for BTreeIndexIter<'_> {
@@ -39,10 +37,39 @@ impl Iterator for BTreeIndexIter<'_> {
     }
 }
 
+/// Bounds on the leading columns of the keys of a multi-column [BTreeIndex].
+///
+/// The keys of a multi-column index are products of the column values,
+/// so a range over its leading columns can't be expressed as a range of keys.
+/// Instead, the keys are filtered against these bounds.
+struct PrefixBounds {
+    /// The number of leading columns the bounds apply to.
+    len: usize,
+    start: Bound<Vec<AlgebraicValue>>,
+    end: Bound<Vec<AlgebraicValue>>,
+}
+
+impl PrefixBounds {
+    /// The key to start a range over the index from.
+    /// A product of a prefix sorts before any longer product starting with that prefix.
+    fn start_key(&self) -> Bound<IndexKey> {
+        match &self.start {
+            Bound::Included(x) | Bound::Excluded(x) => Bound::Included(IndexKey {
+                value: AlgebraicValue::product(x.clone()),
+                row_id: RowId(DataKey::min_datakey()),
+            }),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+}
+
 /// An iterator for the rows that match a value [AlgebraicValue] on the
 /// [BTreeIndex]
 pub struct BTreeIndexRangeIter<'a> {
-    range_iter: btree_set::Range<'a, IndexKey>,
+    range_iter: ordset::RangedIter<'a, IndexKey>,
+    /// For a prefix seek on a multi-column index,
+    /// the bounds that the leading columns of each key must fall within.
+    prefix: Option<PrefixBounds>,
 }
 
 impl Iterator for BTreeIndexRangeIter<'_> {
@@ -50,36 +77,65 @@ impl Iterator for BTreeIndexRangeIter<'_> {
 
     #[tracing::instrument(skip_all)]
     fn next(&mut self) -> Option<Self::Item> {
-        self.range_iter.next().map(|key| key.row_id)
+        let Some(bounds) = &self.prefix else {
+            return self.range_iter.next().map(|key| key.row_id);
+        };
+        let len = bounds.len;
+        for key in &mut self.range_iter {
+            let elements = &key.value.as_product()?.elements;
+            let prefix = &elements[..len.min(elements.len())];
+            if let Bound::Excluded(start) = &bounds.start {
+                if prefix == start.as_slice() {
+                    continue;
+                }
+            }
+            // The keys are sorted, so once past the end bound, no key can match anymore.
+            let before_end = match &bounds.end {
+                Bound::Included(end) => prefix <= end.as_slice(),
+                Bound::Excluded(end) => prefix < end.as_slice(),
+                Bound::Unbounded => true,
+            };
+            return before_end.then_some(key.row_id);
+        }
+        None
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct BTreeIndex {
     pub(crate) index_id: IndexId,
     pub(crate) table_id: u32,
-    pub(crate) col_id: u32,
+    /// The indexed columns, in the order their values are compared.
+    pub(crate) cols: Vec<u32>,
     pub(crate) name: String,
     pub(crate) is_unique: bool,
-    idx: BTreeSet<IndexKey>,
+    idx: OrdSet<IndexKey>,
 }
 
 impl BTreeIndex {
-    pub(crate) fn new(index_id: IndexId, table_id: u32, col_id: u32, name: String, is_unique: bool) -> Self {
+    pub(crate) fn new(index_id: IndexId, table_id: u32, cols: Vec<u32>, name: String, is_unique: bool) -> Self {
         Self {
             index_id,
             table_id,
-            col_id,
+            cols,
             name,
             is_unique,
-            idx: BTreeSet::new(),
+            idx: OrdSet::new(),
         }
     }
 
+    /// Returns the key of `row` in this index.
+    pub(crate) fn get_key(&self, row: &ProductValue) -> Result<AlgebraicValue, InvalidFieldError> {
+        project_key(&self.cols, row)
+    }
+
     #[tracing::instrument(skip_all)]
     pub(crate) fn insert(&mut self, row: &ProductValue) -> Result<(), DBError> {
-        let col_value = row.get_field(self.col_id as usize, None)?;
-        let key = IndexKey::from_row(col_value, row.to_data_key());
-        self.idx.insert(key);
+        let key = self.get_key(row)?;
+        self.idx.insert(IndexKey {
+            value: key,
+            row_id: RowId(row.to_data_key()),
+        });
         Ok(())
     }
 
@@ -92,19 +148,18 @@ impl BTreeIndex {
     #[tracing::instrument(skip_all)]
     pub(crate) fn violates_unique_constraint(&self, row: &ProductValue) -> bool {
         if self.is_unique {
-            let col_value = row.get_field(self.col_id as usize, None).unwrap();
-            return self.contains_any(col_value);
+            let key = self.get_key(row).unwrap();
+            return self.contains_any(&key);
         }
         false
     }
 
     #[tracing::instrument(skip_all)]
-    pub(crate) fn get_rows_that_violate_unique_constraint<'a>(
-        &'a self,
-        row: &'a ProductValue,
-    ) -> Option<BTreeIndexRangeIter<'a>> {
-        self.is_unique
-            .then(|| self.seek(row.get_field(self.col_id as usize, None).unwrap()))
+    pub(crate) fn get_rows_that_violate_unique_constraint(
+        &self,
+        row: &ProductValue,
+    ) -> Option<BTreeIndexRangeIter<'_>> {
+        self.is_unique.then(|| self.seek(&self.get_key(row).unwrap()))
     }
 
     /// Returns `true` if the [BTreeIndex] contains a value for the specified `value`.
@@ -123,15 +178,18 @@ impl BTreeIndex {
     /// that fall within the specified `range`.
     #[tracing::instrument(skip_all)]
     pub(crate) fn scan_range(&self, range: impl RangeBounds<AlgebraicValue>) -> BTreeIndexRangeIter {
-        let map = |bound, datakey| match bound {
-            Bound::Included(x) => Bound::Included(IndexKey::from_row(x, datakey)),
-            Bound::Excluded(x) => Bound::Excluded(IndexKey::from_row(x, datakey)),
+        // The keys of a value are ordered by `RowId` between the min and max `DataKey`,
+        // so a bound includes all the keys of its value by reaching past them, and excludes them by stopping short.
+        let map = |bound, include, exclude| match bound {
+            Bound::Included(x) => Bound::Included(IndexKey::from_row(x, include)),
+            Bound::Excluded(x) => Bound::Excluded(IndexKey::from_row(x, exclude)),
             Bound::Unbounded => Bound::Unbounded,
         };
-        let start = map(range.start_bound(), DataKey::min_datakey());
-        let end = map(range.end_bound(), DataKey::max_datakey());
+        let start = map(range.start_bound(), DataKey::min_datakey(), DataKey::max_datakey());
+        let end = map(range.end_bound(), DataKey::max_datakey(), DataKey::min_datakey());
         BTreeIndexRangeIter {
             range_iter: self.idx.range((start, end)),
+            prefix: None,
         }
     }
 
@@ -142,11 +200,54 @@ impl BTreeIndex {
     ///
     /// For a unique index this will always yield at most one `RowId`.
     #[tracing::instrument(skip_all)]
-    pub(crate) fn seek<'a>(&'a self, value: &'a AlgebraicValue) -> BTreeIndexRangeIter<'a> {
+    pub(crate) fn seek(&self, value: &AlgebraicValue) -> BTreeIndexRangeIter<'_> {
         let k_start = IndexKey::from_row(value, DataKey::min_datakey());
         let k_end = IndexKey::from_row(value, DataKey::max_datakey());
         BTreeIndexRangeIter {
             range_iter: self.idx.range(k_start..k_end),
+            prefix: None,
+        }
+    }
+
+    /// Returns an iterator over the [BTreeIndex] that yields all the `RowId`s
+    /// whose leading indexed columns match the values in `prefix`.
+    ///
+    /// A `prefix` covering all the indexed columns behaves like [`BTreeIndex::seek`].
+    #[tracing::instrument(skip_all)]
+    pub(crate) fn seek_prefix(&self, prefix: &[AlgebraicValue]) -> BTreeIndexRangeIter<'_> {
+        if let ([_], [value]) = (&*self.cols, prefix) {
+            return self.seek(value);
+        }
+        self.prefix_range(PrefixBounds {
+            len: prefix.len(),
+            start: Bound::Included(prefix.to_vec()),
+            end: Bound::Included(prefix.to_vec()),
+        })
+    }
+
+    /// Returns an iterator over the [BTreeIndex] that yields all the `RowId`s
+    /// whose leading indexed column falls within the specified `range`.
+    #[tracing::instrument(skip_all)]
+    pub(crate) fn scan_prefix_range(&self, range: impl RangeBounds<AlgebraicValue>) -> BTreeIndexRangeIter<'_> {
+        if self.cols.len() == 1 {
+            return self.scan_range(range);
+        }
+        let map = |bound| match bound {
+            Bound::Included(x) => Bound::Included(vec![AlgebraicValue::clone(x)]),
+            Bound::Excluded(x) => Bound::Excluded(vec![AlgebraicValue::clone(x)]),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.prefix_range(PrefixBounds {
+            len: 1,
+            start: map(range.start_bound()),
+            end: map(range.end_bound()),
+        })
+    }
+
+    fn prefix_range(&self, prefix: PrefixBounds) -> BTreeIndexRangeIter<'_> {
+        BTreeIndexRangeIter {
+            range_iter: self.idx.range((prefix.start_key(), Bound::Unbounded)),
+            prefix: Some(prefix),
         }
     }
 
@@ -165,7 +266,8 @@ impl From<&BTreeIndex> for IndexSchema {
         IndexSchema {
             index_id: x.index_id.0,
             table_id: x.table_id,
-            col_id: x.col_id,
+            cols: x.cols.clone(),
+            index_type: IndexType::BTree,
             is_unique: x.is_unique,
             index_name: x.name.clone(),
         }
@@ -0,0 +1,125 @@
+use super::{index::project_key, RowId};
+use crate::{
+    db::datastore::traits::{IndexId, IndexSchema},
+    error::DBError,
+};
+use im::{ordset, HashMap, OrdSet};
+use spacetimedb_lib::{data_key::ToDataKey, IndexType};
+use spacetimedb_sats::{product_value::InvalidFieldError, AlgebraicValue, ProductValue};
+
+/// An iterator for the rows that match a value [AlgebraicValue] on the
+/// [HashIndex]
+pub struct HashIndexIter<'a> {
+    iter: Option<ordset::Iter<'a, RowId>>,
+}
+
+impl Iterator for HashIndexIter<'_> {
+    type Item = RowId;
+
+    #[tracing::instrument(skip_all)]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.as_mut()?.next().copied()
+    }
+}
+
+/// An index mapping the values of the indexed columns to the rows holding them.
+///
+/// Unlike a [BTreeIndex](super::btree_index::BTreeIndex), the keys are not ordered,
+/// so it can only serve equality lookups on all of its columns.
+#[derive(Clone)]
+pub(crate) struct HashIndex {
+    pub(crate) index_id: IndexId,
+    pub(crate) table_id: u32,
+    pub(crate) cols: Vec<u32>,
+    pub(crate) name: String,
+    pub(crate) is_unique: bool,
+    idx: HashMap<AlgebraicValue, OrdSet<RowId>>,
+}
+
+impl HashIndex {
+    pub(crate) fn new(index_id: IndexId, table_id: u32, cols: Vec<u32>, name: String, is_unique: bool) -> Self {
+        Self {
+            index_id,
+            table_id,
+            cols,
+            name,
+            is_unique,
+            idx: HashMap::new(),
+        }
+    }
+
+    /// Returns the key of `row` in this index.
+    pub(crate) fn get_key(&self, row: &ProductValue) -> Result<AlgebraicValue, InvalidFieldError> {
+        project_key(&self.cols, row)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub(crate) fn insert(&mut self, row: &ProductValue) -> Result<(), DBError> {
+        let key = self.get_key(row)?;
+        self.idx.entry(key).or_default().insert(RowId(row.to_data_key()));
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub(crate) fn delete(&mut self, col_value: &AlgebraicValue, row_id: &RowId) {
+        if let Some(rows) = self.idx.get_mut(col_value) {
+            rows.remove(row_id);
+            if rows.is_empty() {
+                self.idx.remove(col_value);
+            }
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub(crate) fn violates_unique_constraint(&self, row: &ProductValue) -> bool {
+        if self.is_unique {
+            let key = self.get_key(row).unwrap();
+            return self.contains_any(&key);
+        }
+        false
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub(crate) fn get_rows_that_violate_unique_constraint(&self, row: &ProductValue) -> Option<HashIndexIter<'_>> {
+        self.is_unique.then(|| self.seek(&self.get_key(row).unwrap()))
+    }
+
+    /// Returns `true` if the [HashIndex] contains a value for the specified `value`.
+    #[tracing::instrument(skip_all)]
+    pub(crate) fn contains_any(&self, value: &AlgebraicValue) -> bool {
+        self.idx.contains_key(value)
+    }
+
+    /// Returns an iterator over the [HashIndex] that yields all the `RowId`s
+    /// that match the specified `value` in the indexed columns.
+    ///
+    /// For a unique index this will always yield at most one `RowId`.
+    #[tracing::instrument(skip_all)]
+    pub(crate) fn seek(&self, value: &AlgebraicValue) -> HashIndexIter<'_> {
+        HashIndexIter {
+            iter: self.idx.get(value).map(|rows| rows.iter()),
+        }
+    }
+
+    /// Construct the [HashIndex] from the rows.
+    #[tracing::instrument(skip_all)]
+    pub(crate) fn build_from_rows<'a>(&mut self, rows: impl Iterator<Item = &'a ProductValue>) -> Result<(), DBError> {
+        for row in rows {
+            self.insert(row)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&HashIndex> for IndexSchema {
+    fn from(x: &HashIndex) -> Self {
+        IndexSchema {
+            index_id: x.index_id.0,
+            table_id: x.table_id,
+            cols: x.cols.clone(),
+            index_type: IndexType::Hash,
+            is_unique: x.is_unique,
+            index_name: x.name.clone(),
+        }
+    }
+}
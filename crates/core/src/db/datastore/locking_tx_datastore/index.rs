@@ -0,0 +1,209 @@
+use super::{
+    btree_index::{BTreeIndex, BTreeIndexRangeIter},
+    hash_index::{HashIndex, HashIndexIter},
+    RowId,
+};
+use crate::{
+    db::datastore::traits::{ColId, IndexId, IndexSchema},
+    error::DBError,
+};
+use spacetimedb_lib::IndexType;
+use spacetimedb_sats::{product_value::InvalidFieldError, AlgebraicValue, ProductValue};
+use std::ops::RangeBounds;
+
+/// Returns the key of `row` in an index on `cols`.
+///
+/// For a single-column index this is the value of the column,
+/// while for a multi-column index this is a product of the values of the columns.
+pub(crate) fn project_key(cols: &[u32], row: &ProductValue) -> Result<AlgebraicValue, InvalidFieldError> {
+    if let [col_id] = *cols {
+        return row.get_field(col_id as usize, None).cloned();
+    }
+    let elements = cols
+        .iter()
+        .map(|col_id| row.get_field(*col_id as usize, None).cloned())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(AlgebraicValue::product(elements))
+}
+
+/// An iterator for the rows that match a seek on an [Index].
+pub enum IndexSeekIter<'a> {
+    BTree(BTreeIndexRangeIter<'a>),
+    Hash(HashIndexIter<'a>),
+}
+
+impl Iterator for IndexSeekIter<'_> {
+    type Item = RowId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            IndexSeekIter::BTree(iter) => iter.next(),
+            IndexSeekIter::Hash(iter) => iter.next(),
+        }
+    }
+}
+
+/// An index on the columns of a table, of any [IndexType].
+#[derive(Clone)]
+pub(crate) enum Index {
+    BTree(BTreeIndex),
+    Hash(HashIndex),
+}
+
+impl Index {
+    pub(crate) fn new(
+        index_type: IndexType,
+        index_id: IndexId,
+        table_id: u32,
+        cols: Vec<u32>,
+        name: String,
+        is_unique: bool,
+    ) -> Self {
+        match index_type {
+            IndexType::BTree => Self::BTree(BTreeIndex::new(index_id, table_id, cols, name, is_unique)),
+            IndexType::Hash => Self::Hash(HashIndex::new(index_id, table_id, cols, name, is_unique)),
+        }
+    }
+
+    /// Returns a new index of the same type and on the same columns, without any rows.
+    pub(crate) fn new_empty(&self) -> Self {
+        Self::new(
+            self.index_type(),
+            self.index_id(),
+            self.table_id(),
+            self.cols().to_vec(),
+            self.name().to_owned(),
+            self.is_unique(),
+        )
+    }
+
+    pub(crate) fn index_type(&self) -> IndexType {
+        match self {
+            Self::BTree(_) => IndexType::BTree,
+            Self::Hash(_) => IndexType::Hash,
+        }
+    }
+
+    pub(crate) fn index_id(&self) -> IndexId {
+        match self {
+            Self::BTree(index) => index.index_id,
+            Self::Hash(index) => index.index_id,
+        }
+    }
+
+    pub(crate) fn table_id(&self) -> u32 {
+        match self {
+            Self::BTree(index) => index.table_id,
+            Self::Hash(index) => index.table_id,
+        }
+    }
+
+    pub(crate) fn cols(&self) -> &[u32] {
+        match self {
+            Self::BTree(index) => &index.cols,
+            Self::Hash(index) => &index.cols,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            Self::BTree(index) => &index.name,
+            Self::Hash(index) => &index.name,
+        }
+    }
+
+    pub(crate) fn is_unique(&self) -> bool {
+        match self {
+            Self::BTree(index) => index.is_unique,
+            Self::Hash(index) => index.is_unique,
+        }
+    }
+
+    /// Returns the indexed columns as [ColId]s.
+    pub(crate) fn col_ids(&self) -> Vec<ColId> {
+        self.cols().iter().copied().map(ColId).collect()
+    }
+
+    /// Returns the key of `row` in this index.
+    pub(crate) fn get_key(&self, row: &ProductValue) -> Result<AlgebraicValue, InvalidFieldError> {
+        project_key(self.cols(), row)
+    }
+
+    pub(crate) fn insert(&mut self, row: &ProductValue) -> Result<(), DBError> {
+        match self {
+            Self::BTree(index) => index.insert(row),
+            Self::Hash(index) => index.insert(row),
+        }
+    }
+
+    pub(crate) fn delete(&mut self, col_value: &AlgebraicValue, row_id: &RowId) {
+        match self {
+            Self::BTree(index) => index.delete(col_value, row_id),
+            Self::Hash(index) => index.delete(col_value, row_id),
+        }
+    }
+
+    pub(crate) fn violates_unique_constraint(&self, row: &ProductValue) -> bool {
+        match self {
+            Self::BTree(index) => index.violates_unique_constraint(row),
+            Self::Hash(index) => index.violates_unique_constraint(row),
+        }
+    }
+
+    pub(crate) fn get_rows_that_violate_unique_constraint(&self, row: &ProductValue) -> Option<IndexSeekIter<'_>> {
+        match self {
+            Self::BTree(index) => index
+                .get_rows_that_violate_unique_constraint(row)
+                .map(IndexSeekIter::BTree),
+            Self::Hash(index) => index
+                .get_rows_that_violate_unique_constraint(row)
+                .map(IndexSeekIter::Hash),
+        }
+    }
+
+    /// Returns an iterator over the index that yields all the `RowId`s
+    /// whose leading indexed columns match the values in `prefix`.
+    ///
+    /// A hash index can only be seeked by all of its columns,
+    /// so this returns `None` for a hash index when `prefix` is shorter than that.
+    pub(crate) fn seek_prefix(&self, prefix: &[AlgebraicValue]) -> Option<IndexSeekIter<'_>> {
+        match self {
+            Self::BTree(index) => Some(IndexSeekIter::BTree(index.seek_prefix(prefix))),
+            Self::Hash(index) if prefix.len() == index.cols.len() => {
+                let key = match prefix {
+                    [value] => value.clone(),
+                    _ => AlgebraicValue::product(prefix.to_vec()),
+                };
+                Some(IndexSeekIter::Hash(index.seek(&key)))
+            }
+            Self::Hash(_) => None,
+        }
+    }
+
+    /// Returns an iterator over the index that yields all the `RowId`s
+    /// whose leading indexed column falls within the specified `range`.
+    ///
+    /// A hash index doesn't order its keys, so this returns `None` for a hash index.
+    pub(crate) fn scan_prefix_range(&self, range: impl RangeBounds<AlgebraicValue>) -> Option<IndexSeekIter<'_>> {
+        match self {
+            Self::BTree(index) => Some(IndexSeekIter::BTree(index.scan_prefix_range(range))),
+            Self::Hash(_) => None,
+        }
+    }
+
+    pub(crate) fn build_from_rows<'a>(&mut self, rows: impl Iterator<Item = &'a ProductValue>) -> Result<(), DBError> {
+        match self {
+            Self::BTree(index) => index.build_from_rows(rows),
+            Self::Hash(index) => index.build_from_rows(rows),
+        }
+    }
+}
+
+impl From<&Index> for IndexSchema {
+    fn from(x: &Index) -> Self {
+        match x {
+            Index::BTree(index) => index.into(),
+            Index::Hash(index) => index.into(),
+        }
+    }
+}
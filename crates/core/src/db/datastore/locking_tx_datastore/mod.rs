@@ -1,27 +1,33 @@
 mod btree_index;
+mod hash_index;
+mod index;
 mod sequence;
 mod table;
 use self::{
-    btree_index::{BTreeIndex, BTreeIndexRangeIter},
+    index::{Index, IndexSeekIter},
     sequence::Sequence,
     table::Table,
 };
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
     ops::RangeBounds,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     vec,
 };
 
 use super::{
     system_tables::{
-        StColumnRow, StIndexRow, StSequenceRow, StTableRow, INDEX_ID_SEQUENCE_ID, SEQUENCE_ID_SEQUENCE_ID,
-        ST_COLUMNS_ID, ST_COLUMNS_ROW_TYPE, ST_INDEXES_ID, ST_INDEX_ROW_TYPE, ST_SEQUENCES_ID, ST_SEQUENCE_ROW_TYPE,
-        ST_TABLES_ID, ST_TABLE_ROW_TYPE, TABLE_ID_SEQUENCE_ID,
+        decode_st_index_row, StColumnRow, StIndexRow, StSequenceRow, StTableRow, INDEX_ID_SEQUENCE_ID,
+        SEQUENCE_ID_SEQUENCE_ID, ST_COLUMNS_ID, ST_COLUMNS_ROW_TYPE, ST_INDEXES_ID, ST_INDEX_ROW_TYPE, ST_SEQUENCES_ID,
+        ST_SEQUENCE_ROW_TYPE, ST_TABLES_ID, ST_TABLE_ROW_TYPE, TABLE_ID_SEQUENCE_ID,
     },
     traits::{
-        self, ColId, DataRow, IndexDef, IndexId, IndexSchema, MutTx, MutTxDatastore, SequenceDef, SequenceId, TableDef,
-        TableId, TableSchema, TxData, TxDatastore,
+        self, ColId, ColumnDef, DataRow, IndexDef, IndexId, IndexSchema, MutTxDatastore, SequenceDef, SequenceId,
+        TableDef, TableId, TableSchema, TxData, TxDatastore,
     },
 };
 
@@ -33,16 +39,21 @@ use crate::{
             system_tables::{st_columns_schema, st_indexes_schema, st_sequences_schema, st_table_schema},
             traits::ColumnSchema,
         },
-        messages::{transaction::Transaction, write::Operation},
+        messages::{
+            transaction::Transaction,
+            write::{Operation, Write},
+        },
         ostorage::ObjectDB,
+        snapshot::{SequenceSnapshot, TableSnapshot},
     },
     error::{DBError, IndexError, TableError},
 };
-use parking_lot::{lock_api::ArcMutexGuard, Mutex, RawMutex};
+use im::OrdMap;
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
 use spacetimedb_lib::{
     auth::{StAccess, StTableType},
     data_key::ToDataKey,
-    DataKey,
+    DataKey, Hash,
 };
 use spacetimedb_sats::{
     AlgebraicType, AlgebraicValue, BuiltinType, BuiltinValue, ProductType, ProductTypeElement, ProductValue,
@@ -104,30 +115,134 @@ impl DataRef {
     }
 }
 
+/// A mutable transaction.
+///
+/// Works on its own copy of the committed state and the sequences, so any number of these may be open at once.
+/// On commit, its writes are applied to the committed state only if no transaction
+/// committed since it began wrote to any of the tables it read or wrote, or advanced any of the same sequences.
+/// The transactions which commit are therefore serializable, in the order they commit.
 pub struct MutTxId {
-    lock: ArcMutexGuard<RawMutex, Inner>,
+    inner: Inner,
+    /// The committed state as of the moment the transaction began.
+    base: CommittedState,
+    /// The value of every sequence as of the moment the transaction began.
+    base_sequences: HashMap<SequenceId, i128>,
+    /// The tables the transaction read from, including the system tables it looked names up in.
+    reads: Mutex<HashSet<TableId>>,
+}
+
+/// The state of a [`MutTxId`] at some point, which it can later be rolled back to,
+/// undoing everything the transaction did since, including its changes to the schema.
+///
+/// Sequences are not rolled back, so the values they generated since are skipped.
+pub struct Savepoint {
+    tx_state: TxState,
+    committed_state: CommittedState,
+}
+
+impl MutTxId {
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint {
+            tx_state: self.inner.tx_state.clone().expect("the transaction is not open"),
+            committed_state: self.inner.committed_state.clone(),
+        }
+    }
+
+    pub fn rollback_to_savepoint(&mut self, savepoint: &Savepoint) {
+        self.inner.tx_state = Some(savepoint.tx_state.clone());
+        self.inner.committed_state = savepoint.committed_state.clone();
+    }
+
+    /// The version of the schema as seen by the transaction, see [`TxId::schema_version`].
+    pub fn schema_version(&self) -> u64 {
+        self.inner.committed_state.schema_version
+    }
+
+    /// Records that the transaction read from `table_id`, see [`Inner::commit`].
+    fn read(&self, table_id: TableId) -> &Inner {
+        self.reads.lock().insert(table_id);
+        &self.inner
+    }
+}
+
+/// A read-only transaction.
+///
+/// Holds a consistent snapshot of the committed state as of the moment the
+/// transaction began. Any number of these may be open at once, and they
+/// neither block nor are blocked by a concurrent [`MutTxId`].
+pub struct TxId {
+    committed_state: CommittedState,
+}
+
+impl fmt::Debug for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TxId")
+            .field("schema_version", &self.committed_state.schema_version)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TxId {
+    fn view(&self) -> StateView<'_> {
+        StateView {
+            committed_state: &self.committed_state,
+            tx_state: None,
+        }
+    }
+
+    /// The version of the schema as seen by the transaction.
+    ///
+    /// Two transactions see the same tables, columns and indexes when they see the same version,
+    /// so what is derived from the schema, like the plan of a query, can be reused between them.
+    pub fn schema_version(&self) -> u64 {
+        self.committed_state.schema_version
+    }
+}
+
+/// The last version of the schema given out by [`next_schema_version`].
+static SCHEMA_VERSION: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a version of the schema that was never given out before.
+fn next_schema_version() -> u64 {
+    SCHEMA_VERSION.fetch_add(1, Ordering::Relaxed) + 1
 }
 
+/// The committed tables of the database.
+///
+/// Tables are shared copy-on-write between the writer and the snapshots held
+/// by read-only transactions, so cloning a `CommittedState` is cheap and a table
+/// is only copied when it is modified while a reader still holds the old version.
+/// Copying a [`Table`] shares its rows and indexes with the original,
+/// so a commit costs time logarithmic in the size of the tables it modifies.
+#[derive(Clone)]
 struct CommittedState {
-    tables: HashMap<TableId, Table>,
+    tables: HashMap<TableId, Arc<Table>>,
+    /// Changed to a new version whenever the tables, columns or indexes are changed.
+    schema_version: u64,
 }
 
 impl CommittedState {
     fn new() -> Self {
-        Self { tables: HashMap::new() }
+        Self {
+            tables: HashMap::new(),
+            schema_version: next_schema_version(),
+        }
     }
 
     fn get_or_create_table(&mut self, table_id: TableId, row_type: &ProductType, schema: &TableSchema) -> &mut Table {
-        self.tables.entry(table_id).or_insert_with(|| Table {
-            row_type: row_type.clone(),
-            schema: schema.clone(),
-            rows: BTreeMap::new(),
-            indexes: HashMap::new(),
-        })
+        let table = self.tables.entry(table_id).or_insert_with(|| {
+            Arc::new(Table {
+                row_type: row_type.clone(),
+                schema: schema.clone(),
+                rows: OrdMap::new(),
+                indexes: HashMap::new(),
+            })
+        });
+        Arc::make_mut(table)
     }
 
     fn get_table(&mut self, table_id: &TableId) -> Option<&mut Table> {
-        self.tables.get_mut(table_id)
+        self.tables.get_mut(table_id).map(Arc::make_mut)
     }
 
     fn merge(&mut self, tx_state: TxState, memory: BTreeMap<DataKey, Arc<Vec<u8>>>) -> TxData {
@@ -149,9 +264,14 @@ impl CommittedState {
                 }
             }));
 
-            // Add all newly created indexes to the committed state
-            for (_, index) in table.indexes {
-                if !commit_table.indexes.contains_key(&ColId(index.col_id)) {
+            // Add all newly created indexes to the committed state,
+            // and to its schema, unless the table was created along with them.
+            for (cols, index) in table.indexes {
+                if !commit_table.indexes.contains_key(&cols) {
+                    let index_id = index.index_id().0;
+                    if !commit_table.schema.indexes.iter().any(|x| x.index_id == index_id) {
+                        commit_table.schema.indexes.push((&index).into());
+                    }
                     commit_table.insert_index(index);
                 }
             }
@@ -187,13 +307,22 @@ impl CommittedState {
         table_id: &TableId,
         col_id: &ColId,
         value: &'a AlgebraicValue,
-    ) -> Option<BTreeIndexRangeIter<'a>> {
+    ) -> Option<IndexSeekIter<'a>> {
         if let Some(table) = self.tables.get(table_id) {
             table.index_seek(*col_id, value)
         } else {
             None
         }
     }
+
+    pub fn index_range_scan(
+        &self,
+        table_id: &TableId,
+        col_id: &ColId,
+        range: impl RangeBounds<AlgebraicValue>,
+    ) -> Option<IndexSeekIter<'_>> {
+        self.tables.get(table_id)?.index_range_scan(*col_id, range)
+    }
 }
 
 /// `TxState` tracks all of the modifications made during a particular transaction.
@@ -222,6 +351,7 @@ impl CommittedState {
 ///   - any row in `insert_tables` must not be in the associated `CommittedState`
 ///   - any row in `delete_tables` must be in the associated `CommittedState`
 ///   - any row cannot be in both `insert_tables` and `delete_tables`
+#[derive(Clone)]
 struct TxState {
     /// For each table,  additions have
     insert_tables: HashMap<TableId, Table>,
@@ -288,7 +418,7 @@ impl TxState {
     }
 
     /// When there's an index on `col_id`,
-    /// returns an iterator over the [BTreeIndex] that yields all the `RowId`s
+    /// returns an iterator over the [Index] that yields all the `RowId`s
     /// that match the specified `value` in the indexed column.
     ///
     /// Matching is defined by `Ord for AlgebraicValue`.
@@ -300,11 +430,340 @@ impl TxState {
         table_id: &TableId,
         col_id: &ColId,
         value: &'a AlgebraicValue,
-    ) -> Option<BTreeIndexRangeIter<'a>> {
+    ) -> Option<IndexSeekIter<'a>> {
         self.insert_tables.get(table_id)?.index_seek(*col_id, value)
     }
+
+    /// When there's an index on `col_id`,
+    /// returns an iterator over the [Index] that yields all the `RowId`s
+    /// where the value in the indexed column falls within `range`.
+    ///
+    /// When there is no index, or `col_id` only has a hash index, this returns `None`.
+    pub fn index_range_scan(
+        &self,
+        table_id: &TableId,
+        col_id: &ColId,
+        range: impl RangeBounds<AlgebraicValue>,
+    ) -> Option<IndexSeekIter<'_>> {
+        self.insert_tables.get(table_id)?.index_range_scan(*col_id, range)
+    }
+}
+
+/// A read-only view of the database as seen by a transaction:
+/// the committed state, overlaid with the changes of the transaction so far, if it is mutable.
+#[derive(Clone, Copy)]
+struct StateView<'a> {
+    committed_state: &'a CommittedState,
+    tx_state: Option<&'a TxState>,
+}
+
+impl<'a> StateView<'a> {
+    fn row_type_for_table(self, table_id: TableId) -> super::Result<ProductType> {
+        // Fetch the `ProductType` from the in memory table if it exists.
+        // The `ProductType` is invalidated if the schema of the table changes.
+        if let Some(row_type) = self.get_row_type(&table_id) {
+            return Ok(row_type.clone());
+        }
+
+        // Look up the columns for the table in question.
+        // NOTE: This is quite an expensive operation, although we only need
+        // to do this in situations where there is not currently an in memory
+        // representation of a table. This would happen in situations where
+        // we have created the table in the database, but have not yet
+        // represented in memory or inserted any rows into it.
+        let table_schema = self.schema_for_table(table_id)?;
+        let elements = table_schema
+            .columns
+            .into_iter()
+            .map(|col| ProductTypeElement {
+                name: None,
+                algebraic_type: col.col_type,
+            })
+            .collect();
+        Ok(ProductType { elements })
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn schema_for_table(self, table_id: TableId) -> super::Result<TableSchema> {
+        if let Some(schema) = self.get_schema(&table_id) {
+            return Ok(schema.clone());
+        }
+
+        // Look up the table_name for the table in question.
+        let table_id_col: ColId = ColId(0);
+
+        // TODO(george): As part of the bootstrapping process, we add a bunch of rows
+        // and only at very end do we patch things up and create table metadata, indexes,
+        // and so on. Early parts of that process insert rows, and need the schema to do
+        // so. We can't just call iter_by_col_eq here as that would attempt to use the
+        // index which we haven't created yet. So instead we just manually Scan here.
+        let rows = IterByColEq::Scan(ScanIterByColEq {
+            value: &AlgebraicValue::U32(table_id.0),
+            col_id: table_id_col,
+            scan_iter: self.iter(&ST_TABLES_ID)?,
+        })
+        .collect::<Vec<_>>();
+        assert!(rows.len() <= 1, "Expected at most one row in st_tables for table_id");
+
+        let row = rows.first().ok_or_else(|| TableError::IdNotFound(table_id.0))?;
+        let el = StTableRow::try_from(row.view())?;
+        let table_name = el.table_name.to_owned();
+        let table_id = el.table_id;
+
+        // Look up the columns for the table in question.
+        let mut columns = Vec::new();
+        const TABLE_ID_COL: ColId = ColId(0);
+        for data_ref in self.iter_by_col_eq(&ST_COLUMNS_ID, &TABLE_ID_COL, &AlgebraicValue::U32(table_id))? {
+            let row = data_ref.view();
+
+            let el = StColumnRow::try_from(row)?;
+            let col_schema = ColumnSchema {
+                table_id: el.table_id,
+                col_id: el.col_id,
+                col_name: el.col_name.into(),
+                col_type: el.col_type,
+                is_autoinc: el.is_autoinc,
+            };
+            columns.push(col_schema);
+        }
+
+        columns.sort_by_key(|col| col.col_id);
+
+        // Look up the indexes for the table in question.
+        let mut indexes = Vec::new();
+        let table_id_col: ColId = ColId(1);
+        for data_ref in self.iter_by_col_eq(&ST_INDEXES_ID, &table_id_col, &AlgebraicValue::U32(table_id))? {
+            let row = data_ref.view();
+
+            let el = StIndexRow::try_from(row)?;
+            let index_schema = IndexSchema {
+                table_id: el.table_id,
+                cols: el.cols,
+                index_type: el.index_type,
+                index_name: el.index_name.into(),
+                is_unique: el.is_unique,
+                index_id: el.index_id,
+            };
+            indexes.push(index_schema);
+        }
+
+        Ok(TableSchema {
+            columns,
+            table_id,
+            table_name,
+            indexes,
+            table_type: el.table_type,
+            table_access: el.table_access,
+        })
+    }
+
+    fn table_id_from_name(self, table_name: &str) -> super::Result<Option<TableId>> {
+        let table_name_col: ColId = ColId(1);
+        self.iter_by_col_eq(
+            &ST_TABLES_ID,
+            &table_name_col,
+            &AlgebraicValue::String(table_name.to_owned()),
+        )
+        .map(|mut iter| {
+            iter.next()
+                .map(|row| TableId(*row.view().elements[0].as_u32().unwrap()))
+        })
+    }
+
+    fn table_name_from_id(self, table_id: TableId) -> super::Result<Option<String>> {
+        let table_id_col: ColId = ColId(0);
+        self.iter_by_col_eq(&ST_TABLES_ID, &table_id_col, &AlgebraicValue::U32(table_id.0))
+            .map(|mut iter| {
+                iter.next()
+                    .map(|row| row.view().elements[1].as_string().unwrap().to_owned())
+            })
+    }
+
+    fn index_id_from_name(self, index_name: &str) -> super::Result<Option<IndexId>> {
+        let index_name_col: ColId = ColId(3);
+        self.iter_by_col_eq(
+            &ST_INDEXES_ID,
+            &index_name_col,
+            &AlgebraicValue::String(index_name.to_owned()),
+        )
+        .map(|mut iter| {
+            iter.next()
+                .map(|row| IndexId(*row.view().elements[0].as_u32().unwrap()))
+        })
+    }
+
+    fn table_exists(self, table_id: &TableId) -> bool {
+        self.tx_state
+            .map(|tx_state| tx_state.insert_tables.contains_key(table_id))
+            .unwrap_or(false)
+            || self.committed_state.tables.contains_key(table_id)
+    }
+
+    fn get(self, table_id: &TableId, row_id: &RowId) -> super::Result<Option<DataRef>> {
+        if !self.table_exists(table_id) {
+            return Err(TableError::IdNotFound(table_id.0).into());
+        }
+        if let Some(tx_state) = self.tx_state {
+            match tx_state.get_row_op(table_id, row_id) {
+                RowState::Committed(_) => unreachable!("a row cannot be committed in a tx state"),
+                RowState::Insert(row) => {
+                    return Ok(Some(DataRef::new(row)));
+                }
+                RowState::Delete => {
+                    return Ok(None);
+                }
+                RowState::Absent => {}
+            }
+        }
+        Ok(self
+            .committed_state
+            .tables
+            .get(table_id)
+            .and_then(|table| table.get_row(row_id))
+            .map(|row| DataRef::new(row.clone())))
+    }
+
+    fn get_row_type(self, table_id: &TableId) -> Option<&'a ProductType> {
+        if let Some(row_type) = self
+            .tx_state
+            .and_then(|tx_state| tx_state.insert_tables.get(table_id))
+            .map(|table| table.get_row_type())
+        {
+            return Some(row_type);
+        }
+        self.committed_state
+            .tables
+            .get(table_id)
+            .map(|table| table.get_row_type())
+    }
+
+    fn get_schema(self, table_id: &TableId) -> Option<&'a TableSchema> {
+        if let Some(schema) = self
+            .tx_state
+            .and_then(|tx_state| tx_state.insert_tables.get(table_id))
+            .map(|table| table.get_schema())
+        {
+            return Some(schema);
+        }
+        self.committed_state
+            .tables
+            .get(table_id)
+            .map(|table| table.get_schema())
+    }
+
+    fn iter(self, table_id: &TableId) -> super::Result<Iter<'a>> {
+        if self.table_exists(table_id) {
+            return Ok(Iter::new(*table_id, self));
+        }
+        Err(TableError::IdNotFound(table_id.0).into())
+    }
+
+    fn iter_by_col_range<R: std::ops::RangeBounds<spacetimedb_sats::AlgebraicValue>>(
+        self,
+        table_id: &TableId,
+        col_id: &ColId,
+        range: R,
+    ) -> super::Result<IterByColRange<'a, R>> {
+        // Like `iter_by_col_eq`, we have to scan the index in both the current tx state
+        // and the committed state, and fall back to scanning the entire table
+        // when the column is not indexed.
+        let bounds = (range.start_bound(), range.end_bound());
+        // A column with only a hash index is scanned like one without an index.
+        let inserted_rows = self.tx_state.and_then(|tx_state| {
+            tx_state
+                .index_range_scan(table_id, col_id, bounds)
+                .map(|inserted_rows| (tx_state, inserted_rows))
+        });
+        if let Some((tx_state, inserted_rows)) = inserted_rows {
+            // The current transaction has modified this table, and the table is indexed.
+            let committed_rows = self.committed_state.index_range_scan(table_id, col_id, bounds);
+            Ok(IterByColRange::Index(IndexIterByColRange {
+                range,
+                col_id: *col_id,
+                iter: IndexSeekIterInner {
+                    table_id: *table_id,
+                    tx_state,
+                    inserted_rows,
+                    committed_rows,
+                    committed_state: self.committed_state,
+                },
+            }))
+        } else {
+            // Either the current transaction has not modified this table, or the table is not
+            // indexed.
+            match self.committed_state.index_range_scan(table_id, col_id, bounds) {
+                Some(committed_rows) => Ok(IterByColRange::CommittedIndex(CommittedIndexIter {
+                    table_id: *table_id,
+                    tx_state: self.tx_state,
+                    committed_state: self.committed_state,
+                    committed_rows,
+                })),
+                None => Ok(IterByColRange::Scan(ScanIterByColRange {
+                    range,
+                    scan_iter: self.iter(table_id)?,
+                    col_id: *col_id,
+                })),
+            }
+        }
+    }
+
+    /// Returns an iterator,
+    /// yielding every row in the table identified by `table_id`,
+    /// where the column data identified by `col_id` equates to `value`.
+    fn iter_by_col_eq(
+        self,
+        table_id: &TableId,
+        col_id: &ColId,
+        value: &'a AlgebraicValue,
+    ) -> super::Result<IterByColEq<'a>> {
+        // We have to index_seek in both the committed state and the current tx state.
+        // First, we will check modifications in the current tx. It may be that the table
+        // has not been modified yet in the current tx, in which case we will only search
+        // the committed state. Finally, the table may not be indexed at all, in which case
+        // we fall back to iterating the entire table.
+
+        // We need to check the tx_state first. In particular, it may be that the index
+        // was only added in the current transaction.
+        // TODO(george): It's unclear that we truly support dynamically creating an index
+        // yet. In particular, I don't know if creating an index in a transaction and
+        // rolling it back will leave the index in place.
+        if let Some((tx_state, inserted_rows)) = self
+            .tx_state
+            .and_then(|tx_state| Some((tx_state, tx_state.index_seek(table_id, col_id, value)?)))
+        {
+            // The current transaction has modified this table, and the table is indexed.
+            Ok(IterByColEq::Index(IndexIterByColEq {
+                value,
+                col_id: *col_id,
+                iter: IndexSeekIterInner {
+                    table_id: *table_id,
+                    tx_state,
+                    inserted_rows,
+                    committed_rows: self.committed_state.index_seek(table_id, col_id, value),
+                    committed_state: self.committed_state,
+                },
+            }))
+        } else {
+            // Either the current transaction has not modified this table, or the table is not
+            // indexed.
+            match self.committed_state.index_seek(table_id, col_id, value) {
+                Some(committed_rows) => Ok(IterByColEq::CommittedIndex(CommittedIndexIter {
+                    table_id: *table_id,
+                    tx_state: self.tx_state,
+                    committed_state: self.committed_state,
+                    committed_rows,
+                })),
+                None => Ok(IterByColEq::Scan(ScanIterByColEq {
+                    value,
+                    col_id: *col_id,
+                    scan_iter: self.iter(table_id)?,
+                })),
+            }
+        }
+    }
 }
 
+#[derive(Clone)]
 struct SequencesState {
     sequences: HashMap<SequenceId, Sequence>,
 }
@@ -342,6 +801,13 @@ impl Inner {
         }
     }
 
+    fn view(&self) -> StateView<'_> {
+        StateView {
+            committed_state: &self.committed_state,
+            tx_state: self.tx_state.as_ref(),
+        }
+    }
+
     fn bootstrap_system_table(&mut self, schema: TableSchema) -> Result<(), DBError> {
         let table_id = schema.table_id;
         let table_name = &schema.table_name;
@@ -417,7 +883,8 @@ impl Inner {
             let row = StIndexRow {
                 index_id: index.index_id,
                 table_id,
-                col_id: index.col_id,
+                cols: index.cols.clone(),
+                index_type: index.index_type,
                 index_name: &index.index_name,
                 is_unique: index.is_unique,
             };
@@ -448,15 +915,20 @@ impl Inner {
         for row in rows {
             let index_row = StIndexRow::try_from(&row)?;
             let table = self.committed_state.get_table(&TableId(index_row.table_id)).unwrap();
-            let mut index = BTreeIndex::new(
+            let mut index = Index::new(
+                index_row.index_type,
                 IndexId(index_row.index_id),
                 index_row.table_id,
-                index_row.col_id,
+                index_row.cols,
                 index_row.index_name.into(),
                 index_row.is_unique,
             );
             index.build_from_rows(table.scan_rows())?;
-            table.indexes.insert(ColId(index_row.col_id), index);
+            // The schema of a table replayed before the index was created doesn't have it yet.
+            if !table.schema.indexes.iter().any(|x| x.index_id == index_row.index_id) {
+                table.schema.indexes.push((&index).into());
+            }
+            table.indexes.insert(index.col_ids(), index);
         }
         Ok(())
     }
@@ -472,16 +944,13 @@ impl Inner {
             let table_id = TableId(table_row.table_id);
             let schema = self.schema_for_table(table_id)?;
             let row_type = self.row_type_for_table(table_id)?;
-            if self.committed_state.get_table(&table_id).is_none() {
-                self.committed_state.tables.insert(
-                    table_id,
-                    Table {
-                        row_type,
-                        schema,
-                        indexes: HashMap::new(),
-                        rows: BTreeMap::new(),
-                    },
-                );
+            if let Entry::Vacant(entry) = self.committed_state.tables.entry(table_id) {
+                entry.insert(Arc::new(Table {
+                    row_type,
+                    schema,
+                    indexes: HashMap::new(),
+                    rows: OrdMap::new(),
+                }));
             }
         }
         Ok(())
@@ -701,108 +1170,18 @@ impl Inner {
                 row_type,
                 schema,
                 indexes: HashMap::new(),
-                rows: BTreeMap::new(),
+                rows: OrdMap::new(),
             },
         );
         Ok(())
     }
 
     fn row_type_for_table(&self, table_id: TableId) -> super::Result<ProductType> {
-        // Fetch the `ProductType` from the in memory table if it exists.
-        // The `ProductType` is invalidated if the schema of the table changes.
-        if let Some(row_type) = self.get_row_type(&table_id) {
-            return Ok(row_type.clone());
-        }
-
-        // Look up the columns for the table in question.
-        // NOTE: This is quite an expensive operation, although we only need
-        // to do this in situations where there is not currently an in memory
-        // representation of a table. This would happen in situations where
-        // we have created the table in the database, but have not yet
-        // represented in memory or inserted any rows into it.
-        let table_schema = self.schema_for_table(table_id)?;
-        let elements = table_schema
-            .columns
-            .into_iter()
-            .map(|col| ProductTypeElement {
-                name: None,
-                algebraic_type: col.col_type,
-            })
-            .collect();
-        Ok(ProductType { elements })
+        self.view().row_type_for_table(table_id)
     }
 
-    #[tracing::instrument(skip_all)]
     fn schema_for_table(&self, table_id: TableId) -> super::Result<TableSchema> {
-        if let Some(schema) = self.get_schema(&table_id) {
-            return Ok(schema.clone());
-        }
-
-        // Look up the table_name for the table in question.
-        let table_id_col: ColId = ColId(0);
-
-        // TODO(george): As part of the bootstrapping process, we add a bunch of rows
-        // and only at very end do we patch things up and create table metadata, indexes,
-        // and so on. Early parts of that process insert rows, and need the schema to do
-        // so. We can't just call iter_by_col_eq here as that would attempt to use the
-        // index which we haven't created yet. So instead we just manually Scan here.
-        let rows = IterByColEq::Scan(ScanIterByColEq {
-            value: &AlgebraicValue::U32(table_id.0),
-            col_id: table_id_col,
-            scan_iter: self.iter(&ST_TABLES_ID)?,
-        })
-        .collect::<Vec<_>>();
-        assert!(rows.len() <= 1, "Expected at most one row in st_tables for table_id");
-
-        let row = rows.first().ok_or_else(|| TableError::IdNotFound(table_id.0))?;
-        let el = StTableRow::try_from(row.view())?;
-        let table_name = el.table_name.to_owned();
-        let table_id = el.table_id;
-
-        // Look up the columns for the table in question.
-        let mut columns = Vec::new();
-        const TABLE_ID_COL: ColId = ColId(0);
-        for data_ref in self.iter_by_col_eq(&ST_COLUMNS_ID, &TABLE_ID_COL, &AlgebraicValue::U32(table_id))? {
-            let row = data_ref.view();
-
-            let el = StColumnRow::try_from(row)?;
-            let col_schema = ColumnSchema {
-                table_id: el.table_id,
-                col_id: el.col_id,
-                col_name: el.col_name.into(),
-                col_type: el.col_type,
-                is_autoinc: el.is_autoinc,
-            };
-            columns.push(col_schema);
-        }
-
-        columns.sort_by_key(|col| col.col_id);
-
-        // Look up the indexes for the table in question.
-        let mut indexes = Vec::new();
-        let table_id_col: ColId = ColId(1);
-        for data_ref in self.iter_by_col_eq(&ST_INDEXES_ID, &table_id_col, &AlgebraicValue::U32(table_id))? {
-            let row = data_ref.view();
-
-            let el = StIndexRow::try_from(row)?;
-            let index_schema = IndexSchema {
-                table_id: el.table_id,
-                col_id: el.col_id,
-                index_name: el.index_name.into(),
-                is_unique: el.is_unique,
-                index_id: el.index_id,
-            };
-            indexes.push(index_schema);
-        }
-
-        Ok(TableSchema {
-            columns,
-            table_id,
-            table_name,
-            indexes,
-            table_type: el.table_type,
-            table_access: el.table_access,
-        })
+        self.view().schema_for_table(table_id)
     }
 
     fn drop_table(&mut self, table_id: TableId) -> super::Result<()> {
@@ -843,8 +1222,8 @@ impl Inner {
         self.drop_table_from_st_tables(table_id)?;
 
         // Delete the table and its rows and indexes from memory.
-        // TODO: This needs to not remove it from the committed state, because it can still be rolled back.
-        // We will have to store the deletion in the TxState and then apply it to the CommittedState in commit.
+        // NOTE: If the transaction is rolled back, the committed state is restored
+        // from the copy shared with read-only transactions, which still has the table.
         self.committed_state.tables.remove(&table_id);
         Ok(())
     }
@@ -859,40 +1238,96 @@ impl Inner {
         let row = rows.first().ok_or_else(|| TableError::IdNotFound(table_id.0))?;
         let row_id = RowId(row.view().to_data_key());
         let mut el = StTableRow::try_from(row.view())?;
+        for name in [el.table_name, new_name] {
+            if table_name_is_system(name) {
+                return Err(TableError::System(name.into()).into());
+            }
+        }
         el.table_name = new_name;
         self.delete(&ST_TABLES_ID, &row_id)?;
         self.insert(ST_TABLES_ID, (&el).into())?;
         Ok(())
     }
 
+    fn add_column(&mut self, table_id: TableId, column: ColumnDef, default: AlgebraicValue) -> super::Result<()> {
+        let schema = self.schema_for_table(table_id)?;
+        if schema.get_column_by_name(&column.col_name).is_some() {
+            return Err(TableError::DuplicateColumnName(column.col_name).into());
+        }
+        log::trace!("COLUMN ADDING: {} to table: {}", column.col_name, schema.table_name);
+
+        // Take the rows out of the table, so they can be reinserted with the new column.
+        let rows = self.iter(&table_id)?.map(|row| row.view().clone()).collect::<Vec<_>>();
+        self.delete_by_rel(&table_id, rows.iter().cloned())?;
+
+        // Insert the column into st_columns.
+        let col_id = schema.columns.len() as u32;
+        let row = StColumnRow {
+            table_id: table_id.0,
+            col_id,
+            col_name: &column.col_name,
+            col_type: column.col_type.clone(),
+            is_autoinc: column.is_autoinc,
+        };
+        self.insert(ST_COLUMNS_ID, (&row).into())?;
+        if column.is_autoinc {
+            let sequence_def = SequenceDef {
+                sequence_name: format!("{}_{}_seq", schema.table_name, column.col_name),
+                table_id: table_id.0,
+                col_id,
+                increment: 1,
+                start: Some(1),
+                min_value: Some(1),
+                max_value: None,
+            };
+            self.create_sequence(sequence_def)?;
+        }
+
+        // Update the in memory representation of the table.
+        // NOTE: This is the transaction's own copy of the committed state,
+        // which is discarded if the transaction is rolled back.
+        let column = ColumnSchema {
+            table_id: table_id.0,
+            col_id,
+            col_name: column.col_name,
+            col_type: column.col_type,
+            is_autoinc: column.is_autoinc,
+        };
+        let committed_table = self.committed_state.get_table(&table_id);
+        let insert_table = self.tx_state.as_mut().unwrap().get_insert_table_mut(&table_id);
+        for table in committed_table.into_iter().chain(insert_table) {
+            table.row_type.elements.push(ProductTypeElement {
+                name: None,
+                algebraic_type: column.col_type.clone(),
+            });
+            table.schema.columns.push(column.clone());
+        }
+
+        // Reinsert the rows, which fills in the values of an autoinc column.
+        for mut row in rows {
+            row.elements.push(default.clone());
+            self.insert(table_id, row)?;
+        }
+
+        log::trace!("COLUMN ADDED: {} to table: {}", column.col_name, schema.table_name);
+        Ok(())
+    }
+
     fn table_id_from_name(&self, table_name: &str) -> super::Result<Option<TableId>> {
-        let table_name_col: ColId = ColId(1);
-        self.iter_by_col_eq(
-            &ST_TABLES_ID,
-            &table_name_col,
-            &AlgebraicValue::String(table_name.to_owned()),
-        )
-        .map(|mut iter| {
-            iter.next()
-                .map(|row| TableId(*row.view().elements[0].as_u32().unwrap()))
-        })
+        self.view().table_id_from_name(table_name)
     }
 
     fn table_name_from_id(&self, table_id: TableId) -> super::Result<Option<String>> {
-        let table_id_col: ColId = ColId(0);
-        self.iter_by_col_eq(&ST_TABLES_ID, &table_id_col, &AlgebraicValue::U32(table_id.0))
-            .map(|mut iter| {
-                iter.next()
-                    .map(|row| row.view().elements[1].as_string().unwrap().to_owned())
-            })
+        self.view().table_name_from_id(table_id)
     }
 
     fn create_index(&mut self, index: IndexDef) -> super::Result<IndexId> {
         log::trace!(
-            "INDEX CREATING: {} for table: {} and col: {}",
+            "INDEX CREATING: {} ({}) for table: {} and cols: {:?}",
             index.name,
+            index.index_type.as_str(),
             index.table_id,
-            index.col_id
+            index.cols
         );
 
         // Insert the index row into st_indexes
@@ -901,7 +1336,8 @@ impl Inner {
         let row = StIndexRow {
             index_id: 0, // Autogen'd
             table_id: index.table_id,
-            col_id: index.col_id,
+            cols: index.cols.clone(),
+            index_type: index.index_type,
             index_name: &index.name,
             is_unique: index.is_unique,
         };
@@ -914,10 +1350,11 @@ impl Inner {
         self.create_index_internal(IndexId(index_id), &index)?;
 
         log::trace!(
-            "INDEX CREATED: {} for table: {} and col: {}",
+            "INDEX CREATED: {} ({}) for table: {} and cols: {:?}",
             index.name,
+            index.index_type.as_str(),
             index.table_id,
-            index.col_id
+            index.cols
         );
         Ok(IndexId(index_id))
     }
@@ -939,7 +1376,7 @@ impl Inner {
                     row_type,
                     schema,
                     indexes: HashMap::new(),
-                    rows: BTreeMap::new(),
+                    rows: OrdMap::new(),
                 },
             );
             self.tx_state
@@ -949,29 +1386,31 @@ impl Inner {
                 .unwrap()
         };
 
-        let mut insert_index = BTreeIndex::new(
+        let mut insert_index = Index::new(
+            index.index_type,
             index_id,
             index.table_id,
-            index.col_id,
+            index.cols.clone(),
             index.name.to_string(),
             index.is_unique,
         );
         insert_index.build_from_rows(insert_table.scan_rows())?;
 
         // NOTE: Also add all the rows in the already committed table to the index.
-        if let Some(committed_table) = self.committed_state.get_table(&TableId(index.table_id)) {
+        if let Some(committed_table) = self.committed_state.tables.get(&TableId(index.table_id)) {
             insert_index.build_from_rows(committed_table.scan_rows())?;
         }
 
         insert_table.schema.indexes.push(IndexSchema {
             table_id: index.table_id,
-            col_id: index.col_id,
+            cols: index.cols.clone(),
+            index_type: index.index_type,
             index_name: index.name.to_string(),
             is_unique: index.is_unique,
             index_id: index_id.0,
         });
 
-        insert_table.indexes.insert(ColId(index.col_id), insert_index);
+        insert_table.indexes.insert(insert_index.col_ids(), insert_index);
         Ok(())
     }
 
@@ -999,16 +1438,20 @@ impl Inner {
     }
 
     fn drop_index_internal(&mut self, index_id: &IndexId) {
-        for (_, table) in self.committed_state.tables.iter_mut() {
+        for table in self.committed_state.tables.values_mut() {
             let mut cols = vec![];
-            for index in table.indexes.values_mut() {
-                if index.index_id == *index_id {
-                    cols.push(index.col_id);
+            for index in table.indexes.values() {
+                if index.index_id() == *index_id {
+                    cols.push(index.col_ids());
                 }
             }
+            if cols.is_empty() {
+                continue;
+            }
+            let table = Arc::make_mut(table);
             for col in cols {
-                table.indexes.remove(&ColId(col));
-                table.schema.indexes.retain(|x| x.col_id != col);
+                table.indexes.remove(&col);
+                table.schema.indexes.retain(|x| x.index_id != index_id.0);
             }
         }
         if let Some(insert_table) = self
@@ -1019,28 +1462,19 @@ impl Inner {
         {
             let mut cols = vec![];
             for index in insert_table.indexes.values_mut() {
-                if index.index_id == *index_id {
-                    cols.push(index.col_id);
+                if index.index_id() == *index_id {
+                    cols.push(index.col_ids());
                 }
             }
             for col in cols {
-                insert_table.indexes.remove(&ColId(col));
-                insert_table.schema.indexes.retain(|x| x.col_id != col);
+                insert_table.indexes.remove(&col);
+                insert_table.schema.indexes.retain(|x| x.index_id != index_id.0);
             }
         }
     }
 
     fn index_id_from_name(&self, index_name: &str) -> super::Result<Option<IndexId>> {
-        let index_name_col: ColId = ColId(3);
-        self.iter_by_col_eq(
-            &ST_INDEXES_ID,
-            &index_name_col,
-            &AlgebraicValue::String(index_name.to_owned()),
-        )
-        .map(|mut iter| {
-            iter.next()
-                .map(|row| IndexId(*row.view().elements[0].as_u32().unwrap()))
-        })
+        self.view().index_id_from_name(index_name)
     }
 
     fn contains_row(&self, table_id: &TableId, row_id: &RowId) -> RowState {
@@ -1062,11 +1496,7 @@ impl Inner {
     }
 
     fn table_exists(&self, table_id: &TableId) -> bool {
-        self.tx_state
-            .as_ref()
-            .map(|tx_state| tx_state.insert_tables.contains_key(table_id))
-            .unwrap_or(false)
-            || self.committed_state.tables.contains_key(table_id)
+        self.view().table_exists(table_id)
     }
 
     fn sequence_value_to_algebraic_value(
@@ -1161,6 +1591,7 @@ impl Inner {
 
     #[tracing::instrument(skip_all)]
     fn insert_row_internal(&mut self, table_id: TableId, row: ProductValue) -> super::Result<()> {
+        self.touch_schema(table_id);
         let mut bytes = Vec::new();
         row.encode(&mut bytes);
         let data_key = DataKey::from_data(&bytes);
@@ -1181,20 +1612,9 @@ impl Inner {
                 indexes: committed_table
                     .indexes
                     .iter()
-                    .map(|(col_id, index)| {
-                        (
-                            *col_id,
-                            BTreeIndex::new(
-                                index.index_id,
-                                index.table_id,
-                                index.col_id,
-                                index.name.clone(),
-                                index.is_unique,
-                            ),
-                        )
-                    })
+                    .map(|(cols, index)| (cols.clone(), index.new_empty()))
                     .collect::<HashMap<_, _>>(),
-                rows: BTreeMap::new(),
+                rows: OrdMap::new(),
             };
             self.tx_state.as_mut().unwrap().insert_tables.insert(table_id, table);
             self.tx_state.as_ref().unwrap().get_insert_table(&table_id).unwrap()
@@ -1203,17 +1623,10 @@ impl Inner {
         // Check unique constraints
         for index in insert_table.indexes.values() {
             if index.violates_unique_constraint(&row) {
-                let value = row.get_field(index.col_id as usize, None).unwrap();
-                return Err(IndexError::UniqueConstraintViolation {
-                    constraint_name: index.name.clone(),
-                    table_name: insert_table.schema.table_name.clone(),
-                    col_name: insert_table.schema.columns[index.col_id as usize].col_name.clone(),
-                    value: value.clone(),
-                }
-                .into());
+                return Err(unique_constraint_violation(index, &insert_table.schema, &row));
             }
         }
-        if let Some(table) = self.committed_state.tables.get_mut(&table_id) {
+        if let Some(table) = self.committed_state.tables.get(&table_id) {
             for index in table.indexes.values() {
                 let Some(violators) = index.get_rows_that_violate_unique_constraint(&row) else {
                     continue;
@@ -1221,24 +1634,10 @@ impl Inner {
                 for row_id in violators {
                     if let Some(delete_table) = self.tx_state.as_ref().unwrap().delete_tables.get(&table_id) {
                         if !delete_table.contains(&row_id) {
-                            let value = row.get_field(index.col_id as usize, None).unwrap();
-                            return Err(IndexError::UniqueConstraintViolation {
-                                constraint_name: index.name.clone(),
-                                table_name: table.schema.table_name.clone(),
-                                col_name: table.schema.columns[index.col_id as usize].col_name.clone(),
-                                value: value.clone(),
-                            }
-                            .into());
+                            return Err(unique_constraint_violation(index, &table.schema, &row));
                         }
                     } else {
-                        let value = row.get_field(index.col_id as usize, None).unwrap();
-                        return Err(IndexError::UniqueConstraintViolation {
-                            constraint_name: index.name.clone(),
-                            table_name: table.schema.table_name.clone(),
-                            col_name: table.schema.columns[index.col_id as usize].col_name.clone(),
-                            value: value.clone(),
-                        }
-                        .into());
+                        return Err(unique_constraint_violation(index, &table.schema, &row));
                     }
                 }
             }
@@ -1292,55 +1691,7 @@ impl Inner {
     }
 
     fn get(&self, table_id: &TableId, row_id: &RowId) -> super::Result<Option<DataRef>> {
-        if !self.table_exists(table_id) {
-            return Err(TableError::IdNotFound(table_id.0).into());
-        }
-        match self.tx_state.as_ref().unwrap().get_row_op(table_id, row_id) {
-            RowState::Committed(_) => unreachable!("a row cannot be committed in a tx state"),
-            RowState::Insert(row) => {
-                return Ok(Some(DataRef::new(row)));
-            }
-            RowState::Delete => {
-                return Ok(None);
-            }
-            RowState::Absent => {}
-        }
-        Ok(self
-            .committed_state
-            .tables
-            .get(table_id)
-            .and_then(|table| table.get_row(row_id))
-            .map(|row| DataRef::new(row.clone())))
-    }
-
-    fn get_row_type(&self, table_id: &TableId) -> Option<&ProductType> {
-        if let Some(row_type) = self
-            .tx_state
-            .as_ref()
-            .and_then(|tx_state| tx_state.insert_tables.get(table_id))
-            .map(|table| table.get_row_type())
-        {
-            return Some(row_type);
-        }
-        self.committed_state
-            .tables
-            .get(table_id)
-            .map(|table| table.get_row_type())
-    }
-
-    fn get_schema(&self, table_id: &TableId) -> Option<&TableSchema> {
-        if let Some(schema) = self
-            .tx_state
-            .as_ref()
-            .and_then(|tx_state| tx_state.insert_tables.get(table_id))
-            .map(|table| table.get_schema())
-        {
-            return Some(schema);
-        }
-        self.committed_state
-            .tables
-            .get(table_id)
-            .map(|table| table.get_schema())
+        self.view().get(table_id, row_id)
     }
 
     fn delete(&mut self, table_id: &TableId, row_id: &RowId) -> super::Result<bool> {
@@ -1348,6 +1699,7 @@ impl Inner {
     }
 
     fn delete_row_internal(&mut self, table_id: &TableId, row_id: &RowId) -> bool {
+        self.touch_schema(*table_id);
         match self.contains_row(table_id, row_id) {
             RowState::Committed(_) => {
                 // If the row is present because of a previously committed transaction,
@@ -1375,6 +1727,15 @@ impl Inner {
         }
     }
 
+    /// Gives the schema a new version if `table_id` is one of the system tables that describe it.
+    ///
+    /// `st_sequences` is left out, as its rows change whenever a sequence allocates more values.
+    fn touch_schema(&mut self, table_id: TableId) {
+        if [ST_TABLES_ID, ST_COLUMNS_ID, ST_INDEXES_ID].contains(&table_id) {
+            self.committed_state.schema_version = next_schema_version();
+        }
+    }
+
     fn delete_by_rel(
         &mut self,
         table_id: &TableId,
@@ -1391,10 +1752,7 @@ impl Inner {
     }
 
     fn iter(&self, table_id: &TableId) -> super::Result<Iter> {
-        if self.table_exists(table_id) {
-            return Ok(Iter::new(*table_id, self));
-        }
-        Err(TableError::IdNotFound(table_id.0).into())
+        self.view().iter(table_id)
     }
 
     fn iter_by_col_range<'a, R: std::ops::RangeBounds<spacetimedb_sats::AlgebraicValue>>(
@@ -1403,86 +1761,115 @@ impl Inner {
         col_id: &ColId,
         range: R,
     ) -> super::Result<IterByColRange<'a, R>> {
-        Ok(IterByColRange::Scan(ScanIterByColRange {
-            range,
-            scan_iter: self.iter(table_id)?,
-            col_id: *col_id,
-        }))
+        self.view().iter_by_col_range(table_id, col_id, range)
     }
 
-    /// Returns an iterator,
-    /// yielding every row in the table identified by `table_id`,
-    /// where the column data identified by `col_id` equates to `value`.
     fn iter_by_col_eq<'a>(
         &'a self,
         table_id: &TableId,
         col_id: &ColId,
         value: &'a AlgebraicValue,
     ) -> super::Result<IterByColEq> {
-        // We have to index_seek in both the committed state and the current tx state.
-        // First, we will check modifications in the current tx. It may be that the table
-        // has not been modified yet in the current tx, in which case we will only search
-        // the committed state. Finally, the table may not be indexed at all, in which case
-        // we fall back to iterating the entire table.
+        self.view().iter_by_col_eq(table_id, col_id, value)
+    }
 
-        // We need to check the tx_state first. In particular, it may be that the index
-        // was only added in the current transaction.
-        // TODO(george): It's unclear that we truly support dynamically creating an index
-        // yet. In particular, I don't know if creating an index in a transaction and
-        // rolling it back will leave the index in place.
-        if let Some(inserted_rows) = self
-            .tx_state
-            .as_ref()
-            .and_then(|tx_state| tx_state.index_seek(table_id, col_id, value))
+    /// Applies the writes of `tx` to this committed state.
+    ///
+    /// Returns `None`, applying nothing, if a transaction committed since `tx` began
+    /// wrote to a table that `tx` read from or wrote to, or advanced a sequence that `tx` advanced.
+    /// Checking the tables `tx` read as well as those it wrote keeps two transactions
+    /// which each read what the other writes from both committing, i.e. write skew.
+    fn commit(&mut self, tx: MutTxId) -> super::Result<Option<TxData>> {
+        let MutTxId {
+            inner: mut tx_inner,
+            base,
+            base_sequences,
+            reads,
+        } = tx;
+        let tx_state = tx_inner.tx_state.take().unwrap();
+
+        let same_table = |a: Option<&Arc<Table>>, b: Option<&Arc<Table>>| match (a, b) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        // The tables the transaction changed directly, e.g. by creating or dropping an index.
+        let altered_tables = tx_inner
+            .committed_state
+            .tables
+            .keys()
+            .chain(base.tables.keys())
+            .filter(|table_id| !same_table(tx_inner.committed_state.tables.get(table_id), base.tables.get(table_id)))
+            .copied()
+            .collect::<HashSet<_>>();
+        let written_tables = altered_tables
+            .iter()
+            .chain(tx_state.insert_tables.keys())
+            .chain(tx_state.delete_tables.keys());
+        let reads = reads.into_inner();
+        let mut accessed_tables = written_tables.chain(&reads);
+        if accessed_tables
+            .any(|table_id| !same_table(self.committed_state.tables.get(table_id), base.tables.get(table_id)))
         {
-            // The current transaction has modified this table, and the table is indexed.
-            let tx_state = self.tx_state.as_ref().unwrap();
-            Ok(IterByColEq::Index(IndexIterByColEq {
-                value,
-                col_id: *col_id,
-                iter: IndexSeekIterInner {
-                    table_id: *table_id,
-                    tx_state,
-                    inserted_rows,
-                    committed_rows: self.committed_state.index_seek(table_id, col_id, value),
-                    committed_state: &self.committed_state,
-                },
-            }))
-        } else {
-            // Either the current transaction has not modified this table, or the table is not
-            // indexed.
-            match self.committed_state.index_seek(table_id, col_id, value) {
-                Some(committed_rows) => Ok(IterByColEq::CommittedIndex(CommittedIndexIterByColEq {
-                    table_id: *table_id,
-                    tx_state: self.tx_state.as_ref().unwrap(),
-                    committed_state: &self.committed_state,
-                    committed_rows,
-                })),
-                None => Ok(IterByColEq::Scan(ScanIterByColEq {
-                    value,
-                    col_id: *col_id,
-                    scan_iter: self.iter(table_id)?,
-                })),
-            }
+            self.keep_sequences(tx_inner.sequence_state, &base_sequences);
+            return Ok(None);
         }
-    }
 
-    fn commit(&mut self) -> super::Result<Option<TxData>> {
-        let tx_state = self.tx_state.take().unwrap();
-        let memory = std::mem::take(&mut self.memory);
-        let tx_data = self.committed_state.merge(tx_state, memory);
-        Ok(Some(tx_data))
+        let sequence_value = |sequences: &SequencesState, seq_id| sequences.sequences.get(seq_id).map(Sequence::value);
+        let advanced_sequences = tx_inner
+            .sequence_state
+            .sequences
+            .keys()
+            .chain(base_sequences.keys())
+            .filter(|seq_id| sequence_value(&tx_inner.sequence_state, seq_id) != base_sequences.get(seq_id).copied())
+            .copied()
+            .collect::<HashSet<_>>();
+        if advanced_sequences
+            .iter()
+            .any(|seq_id| sequence_value(&self.sequence_state, seq_id) != base_sequences.get(seq_id).copied())
+        {
+            self.keep_sequences(tx_inner.sequence_state, &base_sequences);
+            return Ok(None);
+        }
+
+        for table_id in altered_tables {
+            match tx_inner.committed_state.tables.remove(&table_id) {
+                Some(table) => self.committed_state.tables.insert(table_id, table),
+                None => self.committed_state.tables.remove(&table_id),
+            };
+        }
+        for seq_id in advanced_sequences {
+            match tx_inner.sequence_state.sequences.remove(&seq_id) {
+                Some(sequence) => self.sequence_state.sequences.insert(seq_id, sequence),
+                None => self.sequence_state.sequences.remove(&seq_id),
+            };
+        }
+        // The schema merged from the one the transaction changed and the one committed since it began
+        // is seen by neither, so it gets a version of its own.
+        if tx_inner.committed_state.schema_version != base.schema_version {
+            self.committed_state.schema_version = next_schema_version();
+        }
+        Ok(Some(self.committed_state.merge(tx_state, tx_inner.memory)))
     }
 
-    fn rollback(&mut self) {
-        self.tx_state = None;
-        // TODO: Check that no sequences exceed their allocation after the rollback.
+    /// Keeps the values a transaction that is not committed generated from the sequences
+    /// that no other transaction advanced since it began, as sequences are not rolled back.
+    fn keep_sequences(&mut self, sequences: SequencesState, base_sequences: &HashMap<SequenceId, i128>) {
+        for (seq_id, sequence) in sequences.sequences {
+            let base_value = base_sequences.get(&seq_id).copied();
+            let value = self.sequence_state.sequences.get(&seq_id).map(Sequence::value);
+            if base_value.is_some() && base_value == value && base_value != Some(sequence.value()) {
+                self.sequence_state.sequences.insert(seq_id, sequence);
+            }
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct Locking {
     inner: Arc<Mutex<Inner>>,
+    /// The state as of the last commit, from which read-only transactions take their snapshots.
+    committed_state: Arc<RwLock<CommittedState>>,
 }
 
 impl Locking {
@@ -1517,26 +1904,59 @@ impl Locking {
         log::trace!("DATABASE:BOOTSTRAPPING SYSTEM TABLES DONE");
 
         Ok(Locking {
+            committed_state: Arc::new(RwLock::new(datastore.committed_state.clone())),
             inner: Arc::new(Mutex::new(datastore)),
         })
     }
 
+    /// Runs `f` against the committed state of `inner`,
+    /// then makes the result visible to read-only transactions.
+    ///
+    /// Readers are blocked from starting a transaction while `f` runs,
+    /// so they never observe a half-applied change.
+    fn update_committed_state<T>(&self, inner: &mut Inner, f: impl FnOnce(&mut Inner) -> T) -> T {
+        /// Publishes the committed state of `inner` when dropped,
+        /// so that readers never observe the emptied state, even if `f` panics.
+        struct Publish<'a> {
+            committed_state: RwLockWriteGuard<'a, CommittedState>,
+            inner: &'a mut Inner,
+        }
+        impl Drop for Publish<'_> {
+            fn drop(&mut self) {
+                *self.committed_state = self.inner.committed_state.clone();
+            }
+        }
+
+        let mut publish = Publish {
+            committed_state: self.committed_state.write(),
+            inner,
+        };
+        // Release the previous copy first, so that tables which are not held
+        // by an open read-only transaction are modified in place, not cloned.
+        *publish.committed_state = CommittedState::new();
+        f(publish.inner)
+    }
+
     /// The purpose of this is to rebuild the state of the datastore
     /// after having inserted all of rows from the message log.
     /// This is necessary because, for example, inserting a row into `st_table`
     /// is not equivalent to calling `create_table`.
     /// There may eventually be better way to do this, but this will have to do for now.
+    ///
+    /// Read-only transactions only observe replayed or restored rows once this has been called.
     pub fn rebuild_state_after_replay(&self) -> Result<(), DBError> {
         let mut inner = self.inner.lock();
-
-        // `build_missing_tables` must be called before indexes.
-        // Honestly this should maybe just be one big procedure.
-        // See John Carmack's philosophy on this.
-        inner.build_missing_tables()?;
-        inner.build_indexes()?;
-        inner.build_sequence_state()?;
-
-        Ok(())
+        self.update_committed_state(&mut inner, |inner| {
+            // `build_missing_tables` must be called before indexes.
+            // Honestly this should maybe just be one big procedure.
+            // See John Carmack's philosophy on this.
+            inner.build_missing_tables()?;
+            inner.build_indexes()?;
+            inner.build_sequence_state()?;
+            inner.committed_state.schema_version = next_schema_version();
+
+            Ok(())
+        })
     }
 
     pub fn replay_transaction(
@@ -1545,38 +1965,237 @@ impl Locking {
         odb: Arc<std::sync::Mutex<Box<dyn ObjectDB + Send>>>,
     ) -> Result<(), DBError> {
         let mut inner = self.inner.lock();
-        for write in &transaction.writes {
+        // Replay the writes to the system tables first,
+        // so that the schema of every table is known before its rows are decoded.
+        let is_system_table =
+            |table_id| [ST_TABLES_ID, ST_COLUMNS_ID, ST_SEQUENCES_ID, ST_INDEXES_ID].contains(&table_id);
+        let (system_writes, writes): (Vec<&Write>, Vec<&Write>) = transaction
+            .writes
+            .iter()
+            .partition(|write| is_system_table(TableId(write.set_id)));
+        for write in system_writes.into_iter().chain(writes) {
             let table_id = TableId(write.set_id);
             let schema = inner.schema_for_table(table_id)?;
             let row_type = inner.row_type_for_table(table_id)?;
-            let table = inner.committed_state.tables.entry(table_id).or_insert(Table {
-                row_type: row_type.clone(),
-                schema,
-                indexes: HashMap::new(),
-                rows: BTreeMap::new(),
+            let table = inner.committed_state.tables.entry(table_id).or_insert_with(|| {
+                Arc::new(Table {
+                    row_type: row_type.clone(),
+                    schema,
+                    indexes: HashMap::new(),
+                    rows: OrdMap::new(),
+                })
             });
+            let table = Arc::make_mut(table);
+            let read_bytes = || match write.data_key {
+                DataKey::Data(data) => Some(data.to_vec()),
+                DataKey::Hash(hash) => odb.lock().unwrap().get(hash).map(|data| data.to_vec()),
+            };
             match write.operation {
+                // A row of `st_indexes` inserted in an older format is stored under the key of its upgraded encoding.
+                // A row deleted since the upgrade was never inserted in that encoding,
+                // so its key is the one it's stored under and its bytes aren't in the object store.
+                Operation::Delete if table_id == ST_INDEXES_ID => {
+                    let row_id = match read_bytes() {
+                        Some(bytes) => Self::decode_stored_row(table_id, &row_type, &bytes)?.0,
+                        None => RowId(write.data_key),
+                    };
+                    table.rows.remove(&row_id);
+                }
                 Operation::Delete => {
                     table.rows.remove(&RowId(write.data_key));
                 }
                 Operation::Insert => {
-                    let product_value = match write.data_key {
-                        DataKey::Data(data) => ProductValue::decode(&row_type, &mut &data[..]).unwrap_or_else(|_| {
-                            panic!("Couldn't decode product value to {:?} from message log", row_type)
-                        }),
-                        DataKey::Hash(hash) => {
-                            let data = odb.lock().unwrap().get(hash).unwrap();
-                            ProductValue::decode(&row_type, &mut &data[..]).unwrap_or_else(|_| {
-                                panic!("Couldn't decode product value to {:?} from message log", row_type)
-                            })
-                        }
-                    };
-                    table.rows.insert(RowId(write.data_key), product_value);
+                    let bytes = read_bytes().unwrap();
+                    let (row_id, product_value) = Self::decode_stored_row(table_id, &row_type, &bytes)
+                        .unwrap_or_else(|_| panic!("Couldn't decode product value to {:?} from message log", row_type));
+                    let st_columns_row = (table_id == ST_COLUMNS_ID).then(|| product_value.clone());
+                    table.rows.insert(row_id, product_value);
+                    if let Some(row) = st_columns_row {
+                        Self::replay_added_column(&mut inner, &row)?;
+                    }
                 }
             }
         }
         Ok(())
     }
+
+    /// Decodes a row of `table_id` read from the message log or a snapshot,
+    /// along with the [`RowId`] it is stored under.
+    ///
+    /// Rows of `st_indexes` written in an older format are upgraded,
+    /// and stored under the key of their upgraded encoding.
+    fn decode_stored_row(
+        table_id: TableId,
+        row_type: &ProductType,
+        bytes: &[u8],
+    ) -> Result<(RowId, ProductValue), DBError> {
+        if table_id == ST_INDEXES_ID {
+            let row = decode_st_index_row(bytes)?;
+            return Ok((RowId(row.to_data_key()), row));
+        }
+        let row = ProductValue::decode(row_type, &mut &bytes[..])?;
+        Ok((RowId(DataKey::from_data(bytes)), row))
+    }
+
+    /// Appends the column described by `st_columns_row` to its table in memory,
+    /// if the table has already been built with the columns that precede it.
+    ///
+    /// The rows of a table to which a column was added are replayed with the new column,
+    /// so they can only be decoded once the in memory row type of the table includes it.
+    fn replay_added_column(inner: &mut Inner, st_columns_row: &ProductValue) -> Result<(), DBError> {
+        let col = StColumnRow::try_from(st_columns_row)?;
+        let Some(table) = inner.committed_state.get_table(&TableId(col.table_id)) else {
+            return Ok(());
+        };
+        if col.col_id as usize != table.row_type.elements.len() {
+            return Ok(());
+        }
+        table.row_type.elements.push(ProductTypeElement {
+            name: None,
+            algebraic_type: col.col_type.clone(),
+        });
+        table.schema.columns.push(ColumnSchema {
+            table_id: col.table_id,
+            col_id: col.col_id,
+            col_name: col.col_name.into(),
+            col_type: col.col_type,
+            is_autoinc: col.is_autoinc,
+        });
+        Ok(())
+    }
+
+    /// Commits `tx` like [`MutTxDatastore::commit_mut_tx`],
+    /// then calls `f` with its data before any other transaction can commit.
+    ///
+    /// This is how commits are appended to the message log in the order they are applied,
+    /// so that the committed state and the log position agree whenever no commit is in progress.
+    ///
+    /// Also returns a read-only transaction of the state exactly as `tx` left it,
+    /// which the transactions committed after it don't change.
+    pub fn commit_mut_tx_and_then<T>(
+        &self,
+        tx: MutTxId,
+        f: impl FnOnce(&TxData) -> super::Result<T>,
+    ) -> super::Result<Option<(TxData, T, TxId)>> {
+        let mut inner = self.inner.lock();
+        let Some(tx_data) = self.update_committed_state(&mut inner, |inner| inner.commit(tx))? else {
+            return Ok(None);
+        };
+        let res = f(&tx_data)?;
+        let read_tx = TxId {
+            committed_state: inner.committed_state.clone(),
+        };
+        drop(inner);
+        Ok(Some((tx_data, res, read_tx)))
+    }
+
+    /// Copies the committed rows of every table, including the system tables,
+    /// together with the current value of every sequence.
+    ///
+    /// `at` is called while no commit is in progress, e.g. to read the position of the message log,
+    /// which then matches the copied state exactly.
+    ///
+    /// NOTE: This only blocks until any commit in progress is done, long enough to share the committed tables.
+    /// The rows are encoded after releasing the lock.
+    pub fn snapshot_state<T>(&self, at: impl FnOnce() -> T) -> (T, Vec<TableSnapshot>, Vec<SequenceSnapshot>) {
+        let (at, committed_state, sequences) = {
+            let inner = self.inner.lock();
+            let sequences = inner
+                .sequence_state
+                .sequences
+                .iter()
+                .map(|(sequence_id, sequence)| SequenceSnapshot {
+                    sequence_id: sequence_id.0,
+                    value: sequence.value(),
+                })
+                .collect();
+            (at(), inner.committed_state.clone(), sequences)
+        };
+        let tables = committed_state
+            .tables
+            .iter()
+            .map(|(table_id, table)| TableSnapshot {
+                table_id: table_id.0,
+                rows: table
+                    .scan_rows()
+                    .map(|row| {
+                        let mut bytes = Vec::new();
+                        row.encode(&mut bytes);
+                        bytes
+                    })
+                    .collect(),
+            })
+            .collect();
+        (at, tables, sequences)
+    }
+
+    /// Returns the hashes of all committed rows whose `RowId` refers to an object
+    /// in the object store, rather than containing the row inline.
+    pub fn committed_objects(&self) -> HashSet<Hash> {
+        let inner = self.inner.lock();
+        inner
+            .committed_state
+            .tables
+            .values()
+            .flat_map(|table| table.rows.keys())
+            .filter_map(|row_id| match row_id.0 {
+                DataKey::Hash(hash) => Some(hash),
+                DataKey::Data(_) => None,
+            })
+            .collect()
+    }
+
+    /// Replaces the committed rows of every table in `tables`
+    /// with the rows of the snapshot.
+    ///
+    /// Like [`Self::replay_transaction`], this must be followed
+    /// by a call to [`Self::rebuild_state_after_replay`].
+    pub fn restore_snapshot(&self, mut tables: Vec<TableSnapshot>) -> Result<(), DBError> {
+        let mut inner = self.inner.lock();
+        // The system tables have the lowest ids, so restoring tables in order
+        // makes `st_table` and `st_columns` available before any user table
+        // needs its schema.
+        tables.sort_unstable_by_key(|table| table.table_id);
+        for TableSnapshot { table_id, rows } in tables {
+            let table_id = TableId(table_id);
+            let schema = inner.schema_for_table(table_id)?;
+            let row_type = inner.row_type_for_table(table_id)?;
+            let rows = rows
+                .into_iter()
+                .map(|bytes| Self::decode_stored_row(table_id, &row_type, &bytes))
+                .collect::<Result<_, DBError>>()?;
+            inner.committed_state.tables.insert(
+                table_id,
+                Arc::new(Table {
+                    row_type,
+                    schema,
+                    indexes: HashMap::new(),
+                    rows,
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    /// Restores the in-memory sequence values captured by a snapshot.
+    ///
+    /// If commits were replayed on top of the snapshot, values past the ones
+    /// in the snapshot may already have been handed out. In that case every
+    /// sequence resumes from its persisted allocation instead,
+    /// just as it would after losing its in-memory state.
+    pub fn restore_sequences(&self, sequences: &[SequenceSnapshot], replayed_commits: bool) {
+        let mut inner = self.inner.lock();
+        for (sequence_id, sequence) in inner.sequence_state.sequences.iter_mut() {
+            let snapshot_value = sequences
+                .iter()
+                .find(|s| s.sequence_id == sequence_id.0)
+                .map(|s| s.value);
+            match snapshot_value {
+                Some(value) if !replayed_commits => sequence.set_value(value),
+                _ => sequence.resume_from_allocation(),
+            }
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
@@ -1593,28 +2212,28 @@ impl DataRow for Locking {
 }
 
 impl traits::Tx for Locking {
-    type TxId = MutTxId;
+    type TxId = TxId;
 
     fn begin_tx(&self) -> Self::TxId {
-        self.begin_mut_tx()
+        TxId {
+            committed_state: self.committed_state.read().clone(),
+        }
     }
 
-    fn release_tx(&self, tx: Self::TxId) {
-        self.rollback_mut_tx(tx)
-    }
+    fn release_tx(&self, _tx: Self::TxId) {}
 }
 
 pub struct Iter<'a> {
     table_id: TableId,
-    inner: &'a Inner,
+    view: StateView<'a>,
     stage: ScanStage<'a>,
 }
 
 impl<'a> Iter<'a> {
-    fn new(table_id: TableId, inner: &'a Inner) -> Self {
+    fn new(table_id: TableId, view: StateView<'a>) -> Self {
         Self {
             table_id,
-            inner,
+            view,
             stage: ScanStage::Start,
         }
     }
@@ -1623,10 +2242,10 @@ impl<'a> Iter<'a> {
 enum ScanStage<'a> {
     Start,
     CurrentTx {
-        iter: std::collections::btree_map::Iter<'a, RowId, ProductValue>,
+        iter: im::ordmap::Iter<'a, RowId, ProductValue>,
     },
     Committed {
-        iter: std::collections::btree_map::Iter<'a, RowId, ProductValue>,
+        iter: im::ordmap::Iter<'a, RowId, ProductValue>,
     },
 }
 
@@ -1636,23 +2255,27 @@ impl Iterator for Iter<'_> {
         loop {
             match &mut self.stage {
                 ScanStage::Start => {
-                    if let Some(table) = self.inner.committed_state.tables.get(&self.table_id) {
+                    if let Some(table) = self.view.committed_state.tables.get(&self.table_id) {
                         self.stage = ScanStage::Committed {
                             iter: table.rows.iter(),
                         };
-                    } else if let Some(table) = self.inner.tx_state.as_ref().unwrap().insert_tables.get(&self.table_id)
+                    } else if let Some(table) = self
+                        .view
+                        .tx_state
+                        .and_then(|tx_state| tx_state.insert_tables.get(&self.table_id))
                     {
                         self.stage = ScanStage::CurrentTx {
                             iter: table.rows.iter(),
                         };
-                    };
+                    } else {
+                        break;
+                    }
                 }
                 ScanStage::Committed { iter } => {
                     for (row_id, row) in iter {
                         match self
-                            .inner
+                            .view
                             .tx_state
-                            .as_ref()
                             .map(|tx_state| tx_state.get_row_op(&self.table_id, row_id))
                         {
                             Some(RowState::Committed(_)) => unreachable!("a row cannot be committed in a tx state"),
@@ -1667,9 +2290,8 @@ impl Iterator for Iter<'_> {
                         }
                     }
                     if let Some(table) = self
-                        .inner
+                        .view
                         .tx_state
-                        .as_ref()
                         .and_then(|tx_state| tx_state.insert_tables.get(&self.table_id))
                     {
                         self.stage = ScanStage::CurrentTx {
@@ -1689,6 +2311,32 @@ impl Iterator for Iter<'_> {
         }
         None
     }
+
+    /// Before the first row is yielded, the bounds count the committed rows the transaction hasn't deleted,
+    /// and the rows it has inserted, some of which may be committed rows it has inserted again.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let ScanStage::Start = self.stage else {
+            return (0, None);
+        };
+        let committed = self
+            .view
+            .committed_state
+            .tables
+            .get(&self.table_id)
+            .map_or(0, |table| table.rows.len());
+        let (inserted, deleted) = match self.view.tx_state {
+            Some(tx_state) => (
+                tx_state
+                    .insert_tables
+                    .get(&self.table_id)
+                    .map_or(0, |table| table.rows.len()),
+                tx_state.delete_tables.get(&self.table_id).map_or(0, |rows| rows.len()),
+            ),
+            None => (0, 0),
+        };
+        let committed = committed.saturating_sub(deleted);
+        (committed.max(inserted), Some(committed + inserted))
+    }
 }
 
 /// An iterator returned from `iter_by_col_eq`. This yields up all
@@ -1703,7 +2351,7 @@ pub enum IterByColEq<'a> {
 
     /// When the column has an index, and the table
     /// has not been modified in this transaction.
-    CommittedIndex(CommittedIndexIterByColEq<'a>),
+    CommittedIndex(CommittedIndexIter<'a>),
 }
 
 impl Iterator for IterByColEq<'_> {
@@ -1763,8 +2411,8 @@ struct IndexSeekIterInner<'a> {
     table_id: TableId,
     tx_state: &'a TxState,
     committed_state: &'a CommittedState,
-    inserted_rows: BTreeIndexRangeIter<'a>,
-    committed_rows: Option<BTreeIndexRangeIter<'a>>,
+    inserted_rows: IndexSeekIter<'a>,
+    committed_rows: Option<IndexSeekIter<'a>>,
 }
 
 impl Iterator for IndexSeekIterInner<'_> {
@@ -1792,22 +2440,21 @@ impl Iterator for IndexSeekIterInner<'_> {
     }
 }
 
-pub struct CommittedIndexIterByColEq<'a> {
+pub struct CommittedIndexIter<'a> {
     table_id: TableId,
-    tx_state: &'a TxState,
+    tx_state: Option<&'a TxState>,
     committed_state: &'a CommittedState,
-    committed_rows: BTreeIndexRangeIter<'a>,
+    committed_rows: IndexSeekIter<'a>,
 }
 
-impl Iterator for CommittedIndexIterByColEq<'_> {
+impl Iterator for CommittedIndexIter<'_> {
     type Item = DataRef;
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(row_id) = self.committed_rows.find(|row_id| {
             !self
                 .tx_state
-                .delete_tables
-                .get(&self.table_id)
+                .and_then(|tx_state| tx_state.delete_tables.get(&self.table_id))
                 .map_or(false, |table| table.contains(row_id))
         }) {
             return Some(get_committed_row(self.committed_state, &self.table_id, &row_id));
@@ -1823,9 +2470,40 @@ fn get_committed_row(state: &CommittedState, table_id: &TableId, row_id: &RowId)
     DataRef::new(state.tables.get(table_id).unwrap().get_row(row_id).unwrap().clone())
 }
 
+/// Returns the error for inserting `row` into the table described by `schema`
+/// when that violates the unique constraint of `index`.
+///
+/// For a multi-column index, the column names are joined by commas
+/// and the value is the product of the column values.
+fn unique_constraint_violation(index: &Index, schema: &TableSchema, row: &ProductValue) -> DBError {
+    let col_name = index
+        .cols()
+        .iter()
+        .map(|col_id| schema.columns[*col_id as usize].col_name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    IndexError::UniqueConstraintViolation {
+        constraint_name: index.name().to_owned(),
+        table_name: schema.table_name.clone(),
+        col_name,
+        value: index.get_key(row).unwrap(),
+    }
+    .into()
+}
+
+/// An iterator returned from `iter_by_col_range`. This yields up all
+/// rows in a table which have a column with a value in a particular range.
 pub enum IterByColRange<'a, R: RangeBounds<AlgebraicValue>> {
+    /// When the column in question does not have an index.
     Scan(ScanIterByColRange<'a, R>),
-    // TODO: Index(IndexRangeScanIter<'a>),
+
+    /// When the column has an index, and the table
+    /// has been modified this transaction.
+    Index(IndexIterByColRange<'a, R>),
+
+    /// When the column has an index, and the table
+    /// has not been modified in this transaction.
+    CommittedIndex(CommittedIndexIter<'a>),
 }
 
 impl<R: RangeBounds<AlgebraicValue>> Iterator for IterByColRange<'_, R> {
@@ -1834,11 +2512,31 @@ impl<R: RangeBounds<AlgebraicValue>> Iterator for IterByColRange<'_, R> {
     fn next(&mut self) -> Option<Self::Item> {
         match self {
             IterByColRange::Scan(range) => range.next(),
-            // TODO: RangeScanIter::Index(range) => range.next(),
+            IterByColRange::Index(range) => range.next(),
+            IterByColRange::CommittedIndex(range) => range.next(),
         }
     }
 }
 
+pub struct IndexIterByColRange<'a, R: RangeBounds<AlgebraicValue>> {
+    iter: IndexSeekIterInner<'a>,
+    col_id: ColId,
+    range: R,
+}
+
+impl<R: RangeBounds<AlgebraicValue>> Iterator for IndexIterByColRange<'_, R> {
+    type Item = DataRef;
+
+    #[tracing::instrument(skip_all)]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.find(|data_ref| {
+            let row = data_ref.view();
+            let value = &row.elements[self.col_id.0 as usize];
+            self.range.contains(value)
+        })
+    }
+}
+
 pub struct ScanIterByColRange<'a, R: RangeBounds<AlgebraicValue>> {
     scan_iter: Iter<'a>,
     col_id: ColId,
@@ -1865,8 +2563,28 @@ impl TxDatastore for Locking {
     type IterByColRange<'a, R: std::ops::RangeBounds<spacetimedb_sats::AlgebraicValue>> = IterByColRange<'a, R> where Self: 'a;
     type IterByColEq<'a> = IterByColEq<'a> where Self: 'a;
 
+    fn row_type_for_table_tx(&self, tx: &Self::TxId, table_id: TableId) -> super::Result<ProductType> {
+        tx.view().row_type_for_table(table_id)
+    }
+
+    fn schema_for_table_tx(&self, tx: &Self::TxId, table_id: TableId) -> super::Result<TableSchema> {
+        tx.view().schema_for_table(table_id)
+    }
+
+    fn table_id_exists_tx(&self, tx: &Self::TxId, table_id: &TableId) -> bool {
+        tx.view().table_exists(table_id)
+    }
+
+    fn table_id_from_name_tx(&self, tx: &Self::TxId, table_name: &str) -> super::Result<Option<TableId>> {
+        tx.view().table_id_from_name(table_name)
+    }
+
+    fn table_name_from_id_tx(&self, tx: &Self::TxId, table_id: TableId) -> super::Result<Option<String>> {
+        tx.view().table_name_from_id(table_id)
+    }
+
     fn iter_tx<'a>(&'a self, tx: &'a Self::TxId, table_id: TableId) -> super::Result<Self::Iter<'a>> {
-        self.iter_mut_tx(tx, table_id)
+        tx.view().iter(&table_id)
     }
 
     fn iter_by_col_range_tx<'a, R: std::ops::RangeBounds<spacetimedb_sats::AlgebraicValue>>(
@@ -1876,7 +2594,7 @@ impl TxDatastore for Locking {
         col_id: ColId,
         range: R,
     ) -> super::Result<Self::IterByColRange<'a, R>> {
-        self.iter_by_col_range_mut_tx(tx, table_id, col_id, range)
+        tx.view().iter_by_col_range(&table_id, &col_id, range)
     }
 
     fn iter_by_col_eq_tx<'a>(
@@ -1886,7 +2604,7 @@ impl TxDatastore for Locking {
         col_id: ColId,
         value: &'a spacetimedb_sats::AlgebraicValue,
     ) -> super::Result<Self::IterByColEq<'a>> {
-        self.iter_by_col_eq_mut_tx(tx, table_id, col_id, value)
+        tx.view().iter_by_col_eq(&table_id, &col_id, value)
     }
 
     fn get_tx<'a>(
@@ -1895,7 +2613,7 @@ impl TxDatastore for Locking {
         table_id: TableId,
         row_id: Self::RowId,
     ) -> super::Result<Option<Self::DataRef>> {
-        self.get_mut_tx(tx, table_id, row_id)
+        tx.view().get(&table_id, &row_id)
     }
 }
 
@@ -1903,26 +2621,42 @@ impl traits::MutTx for Locking {
     type MutTxId = MutTxId;
 
     fn begin_mut_tx(&self) -> Self::MutTxId {
-        let mut inner = self.inner.lock_arc();
-        if inner.tx_state.is_some() {
-            panic!("The previous transaction was not properly rolled back or committed.");
+        let inner = self.inner.lock();
+        let base_sequences = inner
+            .sequence_state
+            .sequences
+            .iter()
+            .map(|(seq_id, sequence)| (*seq_id, sequence.value()))
+            .collect();
+        MutTxId {
+            inner: Inner {
+                memory: BTreeMap::new(),
+                committed_state: inner.committed_state.clone(),
+                tx_state: Some(TxState::new()),
+                sequence_state: inner.sequence_state.clone(),
+            },
+            base: inner.committed_state.clone(),
+            base_sequences,
+            reads: Mutex::default(),
         }
-        inner.tx_state = Some(TxState::new());
-        MutTxId { lock: inner }
     }
 
-    fn rollback_mut_tx(&self, mut tx: Self::MutTxId) {
-        tx.lock.rollback();
+    fn rollback_mut_tx(&self, tx: Self::MutTxId) {
+        // The transaction only ever changed its own copy of the committed state.
+        // TODO: Check that no sequences exceed their allocation after the rollback.
+        let mut inner = self.inner.lock();
+        inner.keep_sequences(tx.inner.sequence_state, &tx.base_sequences);
     }
 
-    fn commit_mut_tx(&self, mut tx: Self::MutTxId) -> super::Result<Option<TxData>> {
-        tx.lock.commit()
+    fn commit_mut_tx(&self, tx: Self::MutTxId) -> super::Result<Option<TxData>> {
+        let mut inner = self.inner.lock();
+        self.update_committed_state(&mut inner, |inner| inner.commit(tx))
     }
 }
 
 impl MutTxDatastore for Locking {
     fn create_table_mut_tx(&self, tx: &mut Self::MutTxId, schema: TableDef) -> super::Result<TableId> {
-        tx.lock.create_table(schema)
+        tx.inner.create_table(schema)
     }
 
     /// This function is used to get the `ProductType` of the rows in a
@@ -1939,7 +2673,7 @@ impl MutTxDatastore for Locking {
     ///
     /// This function is known to be called quite frequently.
     fn row_type_for_table_mut_tx(&self, tx: &Self::MutTxId, table_id: TableId) -> super::Result<ProductType> {
-        tx.lock.row_type_for_table(table_id)
+        tx.read(table_id).row_type_for_table(table_id)
     }
 
     /// IMPORTANT! This function is relatively expensive, and much more
@@ -1947,53 +2681,65 @@ impl MutTxDatastore for Locking {
     /// `row_type_for_table_mut_tx` if you only need to access the `ProductType`
     /// of the table.
     fn schema_for_table_mut_tx(&self, tx: &Self::MutTxId, table_id: TableId) -> super::Result<TableSchema> {
-        tx.lock.schema_for_table(table_id)
+        // The name of the table is kept in `st_tables`.
+        tx.read(ST_TABLES_ID);
+        tx.read(table_id).schema_for_table(table_id)
     }
 
     /// This function is relatively expensive because it needs to be
     /// transactional, however we don't expect to be dropping tables very often.
     fn drop_table_mut_tx(&self, tx: &mut Self::MutTxId, table_id: TableId) -> super::Result<()> {
-        tx.lock.drop_table(table_id)
+        tx.inner.drop_table(table_id)
     }
 
     fn rename_table_mut_tx(&self, tx: &mut Self::MutTxId, table_id: TableId, new_name: &str) -> super::Result<()> {
-        tx.lock.rename_table(table_id, new_name)
+        tx.inner.rename_table(table_id, new_name)
+    }
+
+    fn add_column_mut_tx(
+        &self,
+        tx: &mut Self::MutTxId,
+        table_id: TableId,
+        column: ColumnDef,
+        default: AlgebraicValue,
+    ) -> super::Result<()> {
+        tx.inner.add_column(table_id, column, default)
     }
 
     fn table_id_exists(&self, tx: &Self::MutTxId, table_id: &TableId) -> bool {
-        tx.lock.table_exists(table_id)
+        tx.read(*table_id).table_exists(table_id)
     }
 
     fn table_id_from_name_mut_tx(&self, tx: &Self::MutTxId, table_name: &str) -> super::Result<Option<TableId>> {
-        tx.lock.table_id_from_name(table_name)
+        tx.read(ST_TABLES_ID).table_id_from_name(table_name)
     }
 
     fn table_name_from_id_mut_tx(&self, tx: &Self::MutTxId, table_id: TableId) -> super::Result<Option<String>> {
-        tx.lock.table_name_from_id(table_id)
+        tx.read(ST_TABLES_ID).table_name_from_id(table_id)
     }
 
     fn create_index_mut_tx(&self, tx: &mut Self::MutTxId, index: IndexDef) -> super::Result<IndexId> {
-        tx.lock.create_index(index)
+        tx.inner.create_index(index)
     }
 
     fn drop_index_mut_tx(&self, tx: &mut Self::MutTxId, index_id: IndexId) -> super::Result<()> {
-        tx.lock.drop_index(&index_id)
+        tx.inner.drop_index(&index_id)
     }
 
     fn index_id_from_name_mut_tx(&self, tx: &Self::MutTxId, index_name: &str) -> super::Result<Option<IndexId>> {
-        tx.lock.index_id_from_name(index_name)
+        tx.read(ST_INDEXES_ID).index_id_from_name(index_name)
     }
 
     fn get_next_sequence_value_mut_tx(&self, tx: &mut Self::MutTxId, seq_id: SequenceId) -> super::Result<i128> {
-        tx.lock.get_next_sequence_value(seq_id)
+        tx.inner.get_next_sequence_value(seq_id)
     }
 
     fn create_sequence_mut_tx(&self, tx: &mut Self::MutTxId, seq: SequenceDef) -> super::Result<SequenceId> {
-        tx.lock.create_sequence(seq)
+        tx.inner.create_sequence(seq)
     }
 
     fn drop_sequence_mut_tx(&self, tx: &mut Self::MutTxId, seq_id: SequenceId) -> super::Result<()> {
-        tx.lock.drop_sequence(seq_id)
+        tx.inner.drop_sequence(seq_id)
     }
 
     fn sequence_id_from_name_mut_tx(
@@ -2001,11 +2747,11 @@ impl MutTxDatastore for Locking {
         tx: &Self::MutTxId,
         sequence_name: &str,
     ) -> super::Result<Option<SequenceId>> {
-        tx.lock.sequence_id_from_name(sequence_name)
+        tx.read(ST_SEQUENCES_ID).sequence_id_from_name(sequence_name)
     }
 
     fn iter_mut_tx<'a>(&'a self, tx: &'a Self::MutTxId, table_id: TableId) -> super::Result<Self::Iter<'a>> {
-        tx.lock.iter(&table_id)
+        tx.read(table_id).iter(&table_id)
     }
 
     fn iter_by_col_range_mut_tx<'a, R: std::ops::RangeBounds<spacetimedb_sats::AlgebraicValue>>(
@@ -2015,7 +2761,7 @@ impl MutTxDatastore for Locking {
         col_id: ColId,
         range: R,
     ) -> super::Result<Self::IterByColRange<'a, R>> {
-        tx.lock.iter_by_col_range(&table_id, &col_id, range)
+        tx.read(table_id).iter_by_col_range(&table_id, &col_id, range)
     }
 
     fn iter_by_col_eq_mut_tx<'a>(
@@ -2025,7 +2771,7 @@ impl MutTxDatastore for Locking {
         col_id: ColId,
         value: &'a spacetimedb_sats::AlgebraicValue,
     ) -> super::Result<Self::IterByColEq<'a>> {
-        tx.lock.iter_by_col_eq(&table_id, &col_id, value)
+        tx.read(table_id).iter_by_col_eq(&table_id, &col_id, value)
     }
 
     fn get_mut_tx<'a>(
@@ -2034,7 +2780,7 @@ impl MutTxDatastore for Locking {
         table_id: TableId,
         row_id: Self::RowId,
     ) -> super::Result<Option<Self::DataRef>> {
-        tx.lock.get(&table_id, &row_id)
+        tx.read(table_id).get(&table_id, &row_id)
     }
 
     fn delete_mut_tx<'a>(
@@ -2043,7 +2789,8 @@ impl MutTxDatastore for Locking {
         table_id: TableId,
         row_id: Self::RowId,
     ) -> super::Result<bool> {
-        tx.lock.delete(&table_id, &row_id)
+        tx.reads.get_mut().insert(table_id);
+        tx.inner.delete(&table_id, &row_id)
     }
 
     fn delete_by_rel_mut_tx<R: IntoIterator<Item = spacetimedb_sats::ProductValue>>(
@@ -2052,7 +2799,8 @@ impl MutTxDatastore for Locking {
         table_id: TableId,
         relation: R,
     ) -> super::Result<Option<u32>> {
-        tx.lock.delete_by_rel(&table_id, relation)
+        tx.reads.get_mut().insert(table_id);
+        tx.inner.delete_by_rel(&table_id, relation)
     }
 
     fn insert_mut_tx<'a>(
@@ -2061,13 +2809,13 @@ impl MutTxDatastore for Locking {
         table_id: TableId,
         row: spacetimedb_sats::ProductValue,
     ) -> super::Result<ProductValue> {
-        tx.lock.insert(table_id, row)
+        tx.inner.insert(table_id, row)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{ColId, Locking, StTableRow};
+    use super::{ColId, DataRef, IterByColEq, IterByColRange, Locking, RowId, StTableRow};
     use crate::{
         db::datastore::{
             locking_tx_datastore::{
@@ -2075,6 +2823,7 @@ mod tests {
             },
             traits::{
                 ColumnDef, ColumnSchema, DataRow, IndexDef, IndexSchema, MutTx, MutTxDatastore, TableDef, TableSchema,
+                Tx, TxDatastore,
             },
         },
         error::{DBError, IndexError},
@@ -2082,9 +2831,12 @@ mod tests {
     use itertools::Itertools;
     use spacetimedb_lib::{
         auth::{StAccess, StTableType},
+        data_key::ToDataKey,
         error::ResultTest,
+        IndexType,
     };
     use spacetimedb_sats::{AlgebraicType, AlgebraicValue, ProductValue};
+    use std::ops::Bound;
 
     fn get_datastore() -> super::super::Result<Locking> {
         Locking::bootstrap()
@@ -2113,13 +2865,15 @@ mod tests {
             indexes: vec![
                 IndexDef {
                     table_id: 0, // Ignored
-                    col_id: 0,
+                    cols: vec![0],
+                    index_type: IndexType::BTree,
                     name: "id_idx".into(),
                     is_unique: true,
                 },
                 IndexDef {
                     table_id: 0, // Ignored
-                    col_id: 1,
+                    cols: vec![1],
+                    index_type: IndexType::BTree,
                     name: "name_idx".into(),
                     is_unique: true,
                 },
@@ -2180,9 +2934,10 @@ mod tests {
 
                 StColumnRow { table_id: 3, col_id: 0, col_name: "index_id".to_string(), col_type: AlgebraicType::U32, is_autoinc: true },
                 StColumnRow { table_id: 3, col_id: 1, col_name: "table_id".to_string(), col_type: AlgebraicType::U32, is_autoinc: false },
-                StColumnRow { table_id: 3, col_id: 2, col_name: "col_id".to_string(), col_type: AlgebraicType::U32, is_autoinc: false },
+                StColumnRow { table_id: 3, col_id: 2, col_name: "cols".to_string(), col_type: AlgebraicType::array(AlgebraicType::U32), is_autoinc: false },
                 StColumnRow { table_id: 3, col_id: 3, col_name: "index_name".to_string(), col_type: AlgebraicType::String, is_autoinc: false },
                 StColumnRow { table_id: 3, col_id: 4, col_name: "is_unique".to_string(), col_type: AlgebraicType::Bool, is_autoinc: false },
+                StColumnRow { table_id: 3, col_id: 5, col_name: "index_type".to_string(), col_type: AlgebraicType::String, is_autoinc: false },
             ]
         );
         let index_rows = datastore
@@ -2194,10 +2949,10 @@ mod tests {
         assert_eq!(
             index_rows,
             vec![
-                StIndexRow { index_id: 0, table_id: 0, col_id: 0, index_name: "table_id_idx".to_string(), is_unique: true },
-                StIndexRow { index_id: 1, table_id: 3, col_id: 0, index_name: "index_id_idx".to_string(), is_unique: true },
-                StIndexRow { index_id: 2, table_id: 2, col_id: 0, index_name: "sequences_id_idx".to_string(), is_unique: true },
-                StIndexRow { index_id: 3, table_id: 0, col_id: 1, index_name: "table_name_idx".to_string(), is_unique: true },
+                StIndexRow { index_id: 0, table_id: 0, cols: vec![0], index_type: IndexType::BTree, index_name: "table_id_idx".to_string(), is_unique: true },
+                StIndexRow { index_id: 1, table_id: 3, cols: vec![0], index_type: IndexType::BTree, index_name: "index_id_idx".to_string(), is_unique: true },
+                StIndexRow { index_id: 2, table_id: 2, cols: vec![0], index_type: IndexType::BTree, index_name: "sequences_id_idx".to_string(), is_unique: true },
+                StIndexRow { index_id: 3, table_id: 0, cols: vec![1], index_type: IndexType::BTree, index_name: "table_name_idx".to_string(), is_unique: true },
             ]
         );
         let sequence_rows = datastore
@@ -2330,8 +3085,8 @@ mod tests {
                 ColumnSchema { table_id: 4, col_id: 2, col_name: "age".to_string(), col_type: AlgebraicType::U32, is_autoinc: false },
             ],
             indexes: vec![
-                IndexSchema { index_id: 4, table_id: 4, col_id: 0, index_name: "id_idx".to_string(), is_unique: true },
-                IndexSchema { index_id: 5, table_id: 4, col_id: 1, index_name: "name_idx".to_string(), is_unique: true },
+                IndexSchema { index_id: 4, table_id: 4, cols: vec![0], index_type: IndexType::BTree, index_name: "id_idx".to_string(), is_unique: true },
+                IndexSchema { index_id: 5, table_id: 4, cols: vec![1], index_type: IndexType::BTree, index_name: "name_idx".to_string(), is_unique: true },
             ],
             table_type: StTableType::User,
             table_access: StAccess::Public,
@@ -2358,8 +3113,8 @@ mod tests {
                 ColumnSchema { table_id: 4, col_id: 2, col_name: "age".to_string(), col_type: AlgebraicType::U32, is_autoinc: false },
             ],
             indexes: vec![
-                IndexSchema { index_id: 4, table_id: 4, col_id: 0, index_name: "id_idx".to_string(), is_unique: true },
-                IndexSchema { index_id: 5, table_id: 4, col_id: 1, index_name: "name_idx".to_string(), is_unique: true },
+                IndexSchema { index_id: 4, table_id: 4, cols: vec![0], index_type: IndexType::BTree, index_name: "id_idx".to_string(), is_unique: true },
+                IndexSchema { index_id: 5, table_id: 4, cols: vec![1], index_type: IndexType::BTree, index_name: "name_idx".to_string(), is_unique: true },
             ],
             table_type: StTableType::User,
             table_access: StAccess::Public,
@@ -2695,7 +3450,8 @@ mod tests {
         datastore.commit_mut_tx(tx)?;
         let mut tx = datastore.begin_mut_tx();
         let index_def = IndexDef {
-            col_id: 2,
+            cols: vec![2],
+            index_type: IndexType::BTree,
             name: "age_idx".to_string(),
             is_unique: true,
             table_id: table_id.0,
@@ -2708,13 +3464,13 @@ mod tests {
             .collect::<Vec<_>>();
         #[rustfmt::skip]
         assert_eq!(index_rows, vec![
-            StIndexRow { index_id: 0, table_id: 0, col_id: 0, index_name: "table_id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 1, table_id: 3, col_id: 0, index_name: "index_id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 2, table_id: 2, col_id: 0, index_name: "sequences_id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 3, table_id: 0, col_id: 1, index_name: "table_name_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 4, table_id: 4, col_id: 0, index_name: "id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 5, table_id: 4, col_id: 1, index_name: "name_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 6, table_id: 4, col_id: 2, index_name: "age_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 0, table_id: 0, cols: vec![0], index_type: IndexType::BTree, index_name: "table_id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 1, table_id: 3, cols: vec![0], index_type: IndexType::BTree, index_name: "index_id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 2, table_id: 2, cols: vec![0], index_type: IndexType::BTree, index_name: "sequences_id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 3, table_id: 0, cols: vec![1], index_type: IndexType::BTree, index_name: "table_name_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 4, table_id: 4, cols: vec![0], index_type: IndexType::BTree, index_name: "id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 5, table_id: 4, cols: vec![1], index_type: IndexType::BTree, index_name: "name_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 6, table_id: 4, cols: vec![2], index_type: IndexType::BTree, index_name: "age_idx".to_string(), is_unique: true },
         ]);
         let row = ProductValue::from_iter(vec![
             AlgebraicValue::U32(0), // 0 will be ignored.
@@ -2762,7 +3518,8 @@ mod tests {
         let mut tx = datastore.begin_mut_tx();
         let index_def = IndexDef {
             table_id: table_id.0,
-            col_id: 2,
+            cols: vec![2],
+            index_type: IndexType::BTree,
             name: "age_idx".to_string(),
             is_unique: true,
         };
@@ -2776,13 +3533,13 @@ mod tests {
             .collect::<Vec<_>>();
         #[rustfmt::skip]
         assert_eq!(index_rows, vec![
-            StIndexRow { index_id: 0, table_id: 0, col_id: 0, index_name: "table_id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 1, table_id: 3, col_id: 0, index_name: "index_id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 2, table_id: 2, col_id: 0, index_name: "sequences_id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 3, table_id: 0, col_id: 1, index_name: "table_name_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 4, table_id: 4, col_id: 0, index_name: "id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 5, table_id: 4, col_id: 1, index_name: "name_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 6, table_id: 4, col_id: 2, index_name: "age_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 0, table_id: 0, cols: vec![0], index_type: IndexType::BTree, index_name: "table_id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 1, table_id: 3, cols: vec![0], index_type: IndexType::BTree, index_name: "index_id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 2, table_id: 2, cols: vec![0], index_type: IndexType::BTree, index_name: "sequences_id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 3, table_id: 0, cols: vec![1], index_type: IndexType::BTree, index_name: "table_name_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 4, table_id: 4, cols: vec![0], index_type: IndexType::BTree, index_name: "id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 5, table_id: 4, cols: vec![1], index_type: IndexType::BTree, index_name: "name_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 6, table_id: 4, cols: vec![2], index_type: IndexType::BTree, index_name: "age_idx".to_string(), is_unique: true },
         ]);
         let row = ProductValue::from_iter(vec![
             AlgebraicValue::U32(0), // 0 will be ignored.
@@ -2814,6 +3571,30 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_create_index_post_commit_schema() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let schema = basic_table_schema();
+        let table_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        datastore.commit_mut_tx(tx)?;
+        let mut tx = datastore.begin_mut_tx();
+        let index_def = IndexDef {
+            table_id: table_id.0,
+            cols: vec![2],
+            index_type: IndexType::BTree,
+            name: "age_idx".to_string(),
+            is_unique: true,
+        };
+        datastore.create_index_mut_tx(&mut tx, index_def)?;
+        datastore.commit_mut_tx(tx)?;
+        let tx = datastore.begin_mut_tx();
+        let schema = datastore.schema_for_table_mut_tx(&tx, table_id)?;
+        let age_indexes = schema.indexes.iter().filter(|x| x.index_name == "age_idx").count();
+        assert_eq!(age_indexes, 1, "{:?}", schema.indexes);
+        Ok(())
+    }
+
     #[test]
     fn test_create_index_post_rollback() -> ResultTest<()> {
         let datastore = get_datastore()?;
@@ -2829,7 +3610,8 @@ mod tests {
         datastore.commit_mut_tx(tx)?;
         let mut tx = datastore.begin_mut_tx();
         let index_def = IndexDef {
-            col_id: 2,
+            cols: vec![2],
+            index_type: IndexType::BTree,
             name: "age_idx".to_string(),
             is_unique: true,
             table_id: table_id.0,
@@ -2844,12 +3626,12 @@ mod tests {
             .collect::<Vec<_>>();
         #[rustfmt::skip]
         assert_eq!(index_rows, vec![
-            StIndexRow { index_id: 0, table_id: 0, col_id: 0, index_name: "table_id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 1, table_id: 3, col_id: 0, index_name: "index_id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 2, table_id: 2, col_id: 0, index_name: "sequences_id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 3, table_id: 0, col_id: 1, index_name: "table_name_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 4, table_id: 4, col_id: 0, index_name: "id_idx".to_string(), is_unique: true },
-            StIndexRow { index_id: 5, table_id: 4, col_id: 1, index_name: "name_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 0, table_id: 0, cols: vec![0], index_type: IndexType::BTree, index_name: "table_id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 1, table_id: 3, cols: vec![0], index_type: IndexType::BTree, index_name: "index_id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 2, table_id: 2, cols: vec![0], index_type: IndexType::BTree, index_name: "sequences_id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 3, table_id: 0, cols: vec![1], index_type: IndexType::BTree, index_name: "table_name_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 4, table_id: 4, cols: vec![0], index_type: IndexType::BTree, index_name: "id_idx".to_string(), is_unique: true },
+            StIndexRow { index_id: 5, table_id: 4, cols: vec![1], index_type: IndexType::BTree, index_name: "name_idx".to_string(), is_unique: true },
         ]);
         let row = ProductValue::from_iter(vec![
             AlgebraicValue::U32(0), // 0 will be ignored.
@@ -2933,6 +3715,455 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_read_tx_sees_snapshot() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let schema = basic_table_schema();
+        let table_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        let row = ProductValue::from_iter(vec![
+            AlgebraicValue::U32(0), // 0 will be ignored.
+            AlgebraicValue::String("Foo".to_string()),
+            AlgebraicValue::U32(18),
+        ]);
+        datastore.insert_mut_tx(&mut tx, table_id, row)?;
+        datastore.commit_mut_tx(tx)?;
+
+        let read_tx = datastore.begin_tx();
+        // A read-only transaction doesn't hold up a writer, nor the other way around.
+        let mut tx = datastore.begin_mut_tx();
+        let row = ProductValue::from_iter(vec![
+            AlgebraicValue::U32(0), // 0 will be ignored.
+            AlgebraicValue::String("Bar".to_string()),
+            AlgebraicValue::U32(19),
+        ]);
+        datastore.insert_mut_tx(&mut tx, table_id, row)?;
+        let other_read_tx = datastore.begin_tx();
+        assert_eq!(datastore.iter_tx(&other_read_tx, table_id)?.count(), 1);
+        datastore.release_tx(other_read_tx);
+        datastore.commit_mut_tx(tx)?;
+
+        // The snapshot taken before the commit doesn't change...
+        let names = datastore
+            .iter_tx(&read_tx, table_id)?
+            .map(|r| r.view().elements[1].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![AlgebraicValue::String("Foo".to_string())]);
+        let rows = datastore
+            .iter_by_col_eq_tx(&read_tx, table_id, ColId(1), &AlgebraicValue::String("Bar".to_string()))?
+            .count();
+        assert_eq!(rows, 0);
+        datastore.release_tx(read_tx);
+
+        // ...while a new one sees it.
+        let read_tx = datastore.begin_tx();
+        assert_eq!(datastore.iter_tx(&read_tx, table_id)?.count(), 2);
+        let rows = datastore
+            .iter_by_col_eq_tx(&read_tx, table_id, ColId(1), &AlgebraicValue::String("Bar".to_string()))?
+            .count();
+        assert_eq!(rows, 1);
+        assert_eq!(
+            datastore.schema_for_table_tx(&read_tx, table_id)?.table_name,
+            "Foo".to_string()
+        );
+        datastore.release_tx(read_tx);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_tx_after_panicking_update() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let schema = basic_table_schema();
+        let table_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        let row = ProductValue::from_iter(vec![
+            AlgebraicValue::U32(0), // 0 will be ignored.
+            AlgebraicValue::String("Foo".to_string()),
+            AlgebraicValue::U32(18),
+        ]);
+        datastore.insert_mut_tx(&mut tx, table_id, row)?;
+        datastore.commit_mut_tx(tx)?;
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut inner = datastore.inner.lock();
+            datastore.update_committed_state(&mut inner, |_| panic!("update failed"))
+        }));
+        assert!(panicked.is_err());
+
+        // Readers still see the committed state.
+        let read_tx = datastore.begin_tx();
+        assert_eq!(datastore.iter_tx(&read_tx, table_id)?.count(), 1);
+        datastore.release_tx(read_tx);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_conflict() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let table_id = datastore.create_table_mut_tx(&mut tx, basic_table_schema())?;
+        datastore.commit_mut_tx(tx)?;
+
+        let row = |name: &str| {
+            ProductValue::from_iter(vec![
+                AlgebraicValue::U32(0), // 0 will be ignored.
+                AlgebraicValue::String(name.to_string()),
+                AlgebraicValue::U32(18),
+            ])
+        };
+        let mut tx1 = datastore.begin_mut_tx();
+        let mut tx2 = datastore.begin_mut_tx();
+        datastore.insert_mut_tx(&mut tx1, table_id, row("Foo"))?;
+        datastore.insert_mut_tx(&mut tx2, table_id, row("Bar"))?;
+        assert!(datastore.commit_mut_tx(tx1)?.is_some());
+        assert!(datastore.commit_mut_tx(tx2)?.is_none());
+
+        let tx = datastore.begin_mut_tx();
+        let rows = datastore
+            .iter_mut_tx(&tx, table_id)?
+            .map(|r| r.view().elements[1].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![AlgebraicValue::String("Foo".to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_disjoint_writes_commit() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let foo_id = datastore.create_table_mut_tx(&mut tx, basic_table_schema())?;
+        let mut schema = basic_table_schema();
+        schema.table_name = "Bar".into();
+        for index in &mut schema.indexes {
+            index.name = format!("bar_{}", index.name);
+        }
+        let bar_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        datastore.commit_mut_tx(tx)?;
+
+        let row = ProductValue::from_iter(vec![
+            AlgebraicValue::U32(0), // 0 will be ignored.
+            AlgebraicValue::String("Foo".to_string()),
+            AlgebraicValue::U32(18),
+        ]);
+        let mut tx1 = datastore.begin_mut_tx();
+        let mut tx2 = datastore.begin_mut_tx();
+        datastore.insert_mut_tx(&mut tx1, foo_id, row.clone())?;
+        datastore.insert_mut_tx(&mut tx2, bar_id, row)?;
+        assert!(datastore.commit_mut_tx(tx1)?.is_some());
+        assert!(datastore.commit_mut_tx(tx2)?.is_some());
+
+        let read_tx = datastore.begin_tx();
+        assert_eq!(datastore.iter_tx(&read_tx, foo_id)?.count(), 1);
+        assert_eq!(datastore.iter_tx(&read_tx, bar_id)?.count(), 1);
+        datastore.release_tx(read_tx);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_skew_conflict() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let foo_id = datastore.create_table_mut_tx(&mut tx, basic_table_schema())?;
+        let mut schema = basic_table_schema();
+        schema.table_name = "Bar".into();
+        for index in &mut schema.indexes {
+            index.name = format!("bar_{}", index.name);
+        }
+        let bar_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        datastore.commit_mut_tx(tx)?;
+
+        let row = ProductValue::from_iter(vec![
+            AlgebraicValue::U32(0), // 0 will be ignored.
+            AlgebraicValue::String("Foo".to_string()),
+            AlgebraicValue::U32(18),
+        ]);
+        // Each transaction only inserts if the table the other one inserts into is empty,
+        // so at most one of them may commit.
+        let mut tx1 = datastore.begin_mut_tx();
+        let mut tx2 = datastore.begin_mut_tx();
+        assert_eq!(datastore.iter_mut_tx(&tx1, bar_id)?.count(), 0);
+        datastore.insert_mut_tx(&mut tx1, foo_id, row.clone())?;
+        assert_eq!(datastore.iter_mut_tx(&tx2, foo_id)?.count(), 0);
+        datastore.insert_mut_tx(&mut tx2, bar_id, row)?;
+        assert!(datastore.commit_mut_tx(tx1)?.is_some());
+        assert!(datastore.commit_mut_tx(tx2)?.is_none());
+
+        let read_tx = datastore.begin_tx();
+        assert_eq!(datastore.iter_tx(&read_tx, foo_id)?.count(), 1);
+        assert_eq!(datastore.iter_tx(&read_tx, bar_id)?.count(), 0);
+        datastore.release_tx(read_tx);
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_version() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let table_id = datastore.create_table_mut_tx(&mut tx, basic_table_schema())?;
+        datastore.commit_mut_tx(tx)?;
+        let committed_version = || {
+            let read_tx = datastore.begin_tx();
+            let version = read_tx.schema_version();
+            datastore.release_tx(read_tx);
+            version
+        };
+        let version = committed_version();
+
+        let mut tx = datastore.begin_mut_tx();
+        let row = ProductValue::from_iter(vec![
+            AlgebraicValue::U32(0), // 0 will be ignored.
+            AlgebraicValue::String("Foo".to_string()),
+            AlgebraicValue::U32(18),
+        ]);
+        datastore.insert_mut_tx(&mut tx, table_id, row)?;
+        assert_eq!(tx.schema_version(), version, "Changing the rows keeps the schema");
+        datastore.commit_mut_tx(tx)?;
+        assert_eq!(committed_version(), version);
+
+        let mut schema = basic_table_schema();
+        schema.table_name = "Bar".into();
+        for index in &mut schema.indexes {
+            index.name = format!("bar_{}", index.name);
+        }
+        let mut tx = datastore.begin_mut_tx();
+        datastore.create_table_mut_tx(&mut tx, schema.clone())?;
+        assert_ne!(tx.schema_version(), version);
+        datastore.rollback_mut_tx(tx);
+        assert_eq!(committed_version(), version, "Rolling back keeps the schema");
+
+        let mut tx = datastore.begin_mut_tx();
+        datastore.create_table_mut_tx(&mut tx, schema)?;
+        let tx_version = tx.schema_version();
+        datastore.commit_mut_tx(tx)?;
+        assert_ne!(committed_version(), version);
+        assert_ne!(committed_version(), tx_version, "A commit gets a version of its own");
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_tx_from_other_thread() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let schema = basic_table_schema();
+        let table_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        datastore.commit_mut_tx(tx)?;
+
+        let tx = datastore.begin_mut_tx();
+        let reader = datastore.clone();
+        let exists = std::thread::spawn(move || {
+            let read_tx = reader.begin_tx();
+            let exists = reader.table_id_exists_tx(&read_tx, &table_id);
+            reader.release_tx(read_tx);
+            exists
+        })
+        .join()
+        .unwrap();
+        assert!(exists);
+        datastore.rollback_mut_tx(tx);
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_table_post_rollback() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let schema = basic_table_schema();
+        let table_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        let row = ProductValue::from_iter(vec![
+            AlgebraicValue::U32(0), // 0 will be ignored.
+            AlgebraicValue::String("Foo".to_string()),
+            AlgebraicValue::U32(18),
+        ]);
+        datastore.insert_mut_tx(&mut tx, table_id, row)?;
+        datastore.commit_mut_tx(tx)?;
+
+        let mut tx = datastore.begin_mut_tx();
+        datastore.drop_table_mut_tx(&mut tx, table_id)?;
+        datastore.rollback_mut_tx(tx);
+
+        let tx = datastore.begin_mut_tx();
+        let rows = datastore.iter_mut_tx(&tx, table_id)?.count();
+        assert_eq!(rows, 1);
+        let rows = datastore
+            .iter_by_col_eq_mut_tx(&tx, table_id, ColId(0), &AlgebraicValue::U32(1))?
+            .count();
+        assert_eq!(rows, 1);
+        datastore.rollback_mut_tx(tx);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_multi_column_index() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let schema = basic_table_schema();
+        let table_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        for (name, age) in [("Foo", 18), ("Bar", 18), ("Baz", 20), ("Qux", 25)] {
+            let row = ProductValue::from_iter(vec![
+                AlgebraicValue::U32(0), // 0 will be ignored.
+                AlgebraicValue::String(name.to_string()),
+                AlgebraicValue::U32(age),
+            ]);
+            datastore.insert_mut_tx(&mut tx, table_id, row)?;
+        }
+        let index_def = IndexDef {
+            cols: vec![2, 1],
+            index_type: IndexType::BTree,
+            name: "age_name_idx".to_string(),
+            is_unique: false,
+            table_id: table_id.0,
+        };
+        datastore.create_index_mut_tx(&mut tx, index_def)?;
+        datastore.commit_mut_tx(tx)?;
+
+        let tx = datastore.begin_mut_tx();
+        let index_rows = datastore
+            .iter_by_col_eq_mut_tx(
+                &tx,
+                ST_INDEXES_ID,
+                ColId(3),
+                &AlgebraicValue::String("age_name_idx".into()),
+            )?
+            .map(|x| StIndexRow::try_from(x.view()).unwrap().to_owned())
+            .collect::<Vec<_>>();
+        #[rustfmt::skip]
+        assert_eq!(index_rows, vec![
+            StIndexRow { index_id: 6, table_id: 4, cols: vec![2, 1], index_type: IndexType::BTree, index_name: "age_name_idx".to_string(), is_unique: false },
+        ]);
+
+        let age_18 = AlgebraicValue::U32(18);
+        // `age` is the leading column of the index, so lookups on it use the index.
+        let names = |rows: Vec<DataRef>| {
+            rows.iter()
+                .map(|row| row.view().field_as_str(1, None).unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let iter = datastore.iter_by_col_eq_mut_tx(&tx, table_id, ColId(2), &age_18)?;
+        assert!(matches!(iter, IterByColEq::CommittedIndex(_)));
+        assert_eq!(names(iter.collect()), ["Bar", "Foo"]);
+        let iter = datastore.iter_by_col_range_mut_tx(&tx, table_id, ColId(2), AlgebraicValue::U32(19)..)?;
+        assert!(matches!(iter, IterByColRange::CommittedIndex(_)));
+        assert_eq!(names(iter.collect()), ["Baz", "Qux"]);
+        let iter = datastore.iter_by_col_range_mut_tx(
+            &tx,
+            table_id,
+            ColId(2),
+            (
+                Bound::Excluded(AlgebraicValue::U32(18)),
+                Bound::Included(AlgebraicValue::U32(20)),
+            ),
+        )?;
+        assert_eq!(names(iter.collect()), ["Baz"]);
+        datastore.rollback_mut_tx(tx);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_column_index_pre_commit() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let mut schema = basic_table_schema();
+        schema.indexes.push(IndexDef {
+            cols: vec![2, 1],
+            index_type: IndexType::BTree,
+            name: "age_name_idx".to_string(),
+            is_unique: false,
+            table_id: 0, // Ignored
+        });
+        let table_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        datastore.commit_mut_tx(tx)?;
+
+        let mut tx = datastore.begin_mut_tx();
+        for (name, age) in [("Foo", 18), ("Bar", 30), ("Baz", 18)] {
+            let row = ProductValue::from_iter(vec![
+                AlgebraicValue::U32(0), // 0 will be ignored.
+                AlgebraicValue::String(name.to_string()),
+                AlgebraicValue::U32(age),
+            ]);
+            datastore.insert_mut_tx(&mut tx, table_id, row)?;
+        }
+        let age_18 = AlgebraicValue::U32(18);
+        let iter = datastore.iter_by_col_eq_mut_tx(&tx, table_id, ColId(2), &age_18)?;
+        assert!(matches!(iter, IterByColEq::Index(_)));
+        assert_eq!(iter.count(), 2);
+        let iter = datastore.iter_by_col_range_mut_tx(&tx, table_id, ColId(2), ..AlgebraicValue::U32(30))?;
+        assert!(matches!(iter, IterByColRange::Index(_)));
+        assert_eq!(iter.count(), 2);
+
+        // Deleted rows are removed from the index.
+        let deleted = datastore
+            .iter_by_col_eq_mut_tx(&tx, table_id, ColId(1), &AlgebraicValue::String("Foo".into()))?
+            .map(|row| RowId(row.view().to_data_key()))
+            .collect::<Vec<_>>();
+        for row_id in deleted {
+            datastore.delete_mut_tx(&mut tx, table_id, row_id)?;
+        }
+        let iter = datastore.iter_by_col_eq_mut_tx(&tx, table_id, ColId(2), &age_18)?;
+        assert_eq!(iter.count(), 1);
+        datastore.rollback_mut_tx(tx);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_hash_index() -> ResultTest<()> {
+        let datastore = get_datastore()?;
+        let mut tx = datastore.begin_mut_tx();
+        let schema = basic_table_schema();
+        let table_id = datastore.create_table_mut_tx(&mut tx, schema)?;
+        for (name, age) in [("Foo", 18), ("Bar", 30), ("Baz", 18)] {
+            let row = ProductValue::from_iter(vec![
+                AlgebraicValue::U32(0), // 0 will be ignored.
+                AlgebraicValue::String(name.to_string()),
+                AlgebraicValue::U32(age),
+            ]);
+            datastore.insert_mut_tx(&mut tx, table_id, row)?;
+        }
+        for (cols, name, is_unique) in [(vec![2], "age_idx", false), (vec![1], "name_idx", true)] {
+            let index_def = IndexDef {
+                cols,
+                index_type: IndexType::Hash,
+                name: name.to_string(),
+                is_unique,
+                table_id: table_id.0,
+            };
+            datastore.create_index_mut_tx(&mut tx, index_def)?;
+        }
+        datastore.commit_mut_tx(tx)?;
+
+        let mut tx = datastore.begin_mut_tx();
+        let index_rows = datastore
+            .iter_by_col_eq_mut_tx(&tx, ST_INDEXES_ID, ColId(3), &AlgebraicValue::String("age_idx".into()))?
+            .map(|x| StIndexRow::try_from(x.view()).unwrap().to_owned())
+            .collect::<Vec<_>>();
+        #[rustfmt::skip]
+        assert_eq!(index_rows, vec![
+            StIndexRow { index_id: 6, table_id: 4, cols: vec![2], index_type: IndexType::Hash, index_name: "age_idx".to_string(), is_unique: false },
+        ]);
+
+        let age_18 = AlgebraicValue::U32(18);
+        let iter = datastore.iter_by_col_eq_mut_tx(&tx, table_id, ColId(2), &age_18)?;
+        assert!(matches!(iter, IterByColEq::CommittedIndex(_)));
+        assert_eq!(iter.count(), 2);
+        // A hash index can't serve a range scan, so the rows are scanned instead.
+        let iter = datastore.iter_by_col_range_mut_tx(&tx, table_id, ColId(2), AlgebraicValue::U32(19)..)?;
+        assert!(matches!(iter, IterByColRange::Scan(_)));
+        assert_eq!(iter.count(), 1);
+
+        // The unique hash index still enforces its constraint.
+        let row = ProductValue::from_iter(vec![
+            AlgebraicValue::U32(0), // 0 will be ignored.
+            AlgebraicValue::String("Foo".to_string()),
+            AlgebraicValue::U32(40),
+        ]);
+        let result = datastore.insert_mut_tx(&mut tx, table_id, row);
+        assert!(matches!(
+            result,
+            Err(DBError::Index(IndexError::UniqueConstraintViolation { .. }))
+        ));
+        datastore.rollback_mut_tx(tx);
+        Ok(())
+    }
+
     // TODO: Add the following tests
     // - Create index with unique constraint and immediately insert a row that violates the constraint before committing.
     // - Create a tx that inserts 2000 rows with an autoinc column
@@ -1,5 +1,6 @@
 use crate::db::datastore::traits::SequenceSchema;
 
+#[derive(Clone)]
 pub struct Sequence {
     schema: SequenceSchema,
     value: i128,
@@ -82,4 +83,21 @@ impl Sequence {
     pub fn set_allocation(&mut self, allocated: i128) {
         self.schema.allocated = allocated;
     }
+
+    pub fn value(&self) -> i128 {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: i128) {
+        self.value = value;
+    }
+
+    /// Continues the sequence from its persisted allocation,
+    /// which no value handed out so far can have reached.
+    /// See [`Self::needs_allocation`].
+    pub fn resume_from_allocation(&mut self) {
+        if (self.schema.min_value..=self.schema.max_value).contains(&self.schema.allocated) {
+            self.value = self.schema.allocated;
+        }
+    }
 }
@@ -1,25 +1,31 @@
 use super::{
-    btree_index::{BTreeIndex, BTreeIndexIter, BTreeIndexRangeIter},
+    btree_index::BTreeIndexIter,
+    index::{Index, IndexSeekIter},
     RowId,
 };
 use crate::db::datastore::traits::{ColId, TableSchema};
+use im::OrdMap;
+use spacetimedb_lib::IndexType;
 use spacetimedb_sats::{AlgebraicValue, ProductType, ProductValue};
-use std::{
-    collections::{BTreeMap, HashMap},
-    ops::RangeBounds,
-};
+use std::{collections::HashMap, ops::RangeBounds};
 
+/// The rows of a table and its indexes.
+///
+/// The rows and index keys are kept in persistent collections that share structure between clones,
+/// so cloning a table is cheap and modifying a clone only copies the nodes along the modified paths.
+#[derive(Clone)]
 pub(crate) struct Table {
     pub(crate) row_type: ProductType,
     pub(crate) schema: TableSchema,
-    pub(crate) indexes: HashMap<ColId, BTreeIndex>,
-    pub(crate) rows: BTreeMap<RowId, ProductValue>,
+    /// The indexes of the table, keyed by their columns.
+    pub(crate) indexes: HashMap<Vec<ColId>, Index>,
+    pub(crate) rows: OrdMap<RowId, ProductValue>,
 }
 
 impl Table {
-    pub(crate) fn insert_index(&mut self, mut index: BTreeIndex) {
+    pub(crate) fn insert_index(&mut self, mut index: Index) {
         index.build_from_rows(self.scan_rows()).unwrap();
-        self.indexes.insert(ColId(index.col_id), index);
+        self.indexes.insert(index.col_ids(), index);
     }
 
     pub(crate) fn insert(&mut self, row_id: RowId, row: ProductValue) {
@@ -31,9 +37,9 @@ impl Table {
 
     pub(crate) fn delete(&mut self, row_id: &RowId) -> Option<ProductValue> {
         let row = self.rows.remove(row_id)?;
-        for (col_id, index) in self.indexes.iter_mut() {
-            let col_value = row.get_field(col_id.0 as usize, None).unwrap();
-            index.delete(col_value, row_id)
+        for index in self.indexes.values_mut() {
+            let key = index.get_key(&row).unwrap();
+            index.delete(&key, row_id)
         }
         Some(row)
     }
@@ -54,30 +60,53 @@ impl Table {
         self.rows.values()
     }
 
+    /// Returns the btree index to use for lookups on `col_id`:
+    /// the single-column index on `col_id` if there is one,
+    /// or else a multi-column index whose leading column is `col_id`.
+    fn btree_index_for_col(&self, col_id: ColId) -> Option<&Index> {
+        self.indexes
+            .iter()
+            .filter(|(cols, index)| cols.first() == Some(&col_id) && index.index_type() == IndexType::BTree)
+            .min_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)))
+            .map(|(_, index)| index)
+    }
+
     /// When there's an index for `col_id`,
-    /// returns an iterator over the [`BTreeIndex`] that yields all the `RowId`s
+    /// returns an iterator over the [`Index`] that yields all the `RowId`s
     /// that match the specified `value` in the indexed column.
     ///
     /// Matching is defined by `Ord for AlgebraicValue`.
+    /// A multi-column btree index is used when `col_id` is its leading column.
     ///
     /// For a unique index this will always yield at most one `RowId`.
-    pub(crate) fn index_seek<'a>(
-        &'a self,
-        col_id: ColId,
-        value: &'a AlgebraicValue,
-    ) -> Option<BTreeIndexRangeIter<'a>> {
-        self.indexes.get(&col_id).map(|index| index.seek(value))
-    }
-
-    pub(crate) fn _index_scan(&self, col_id: ColId) -> BTreeIndexIter<'_> {
-        self.indexes.get(&col_id).unwrap().scan()
+    pub(crate) fn index_seek<'a>(&'a self, col_id: ColId, value: &'a AlgebraicValue) -> Option<IndexSeekIter<'a>> {
+        let prefix = std::slice::from_ref(value);
+        match self.indexes.get(&[col_id][..]) {
+            Some(index) => index.seek_prefix(prefix),
+            None => self.btree_index_for_col(col_id)?.seek_prefix(prefix),
+        }
     }
 
-    pub(crate) fn _index_range_scan(
+    /// When there's a btree index for `col_id`,
+    /// returns an iterator over the [`Index`] that yields all the `RowId`s
+    /// where the value in the indexed column falls within `range`.
+    ///
+    /// A multi-column btree index is used when `col_id` is its leading column.
+    /// A hash index can only serve equality lookups, so it's never used for a range.
+    pub(crate) fn index_range_scan(
         &self,
         col_id: ColId,
         range: impl RangeBounds<AlgebraicValue>,
-    ) -> BTreeIndexRangeIter<'_> {
-        self.indexes.get(&col_id).unwrap().scan_range(range)
+    ) -> Option<IndexSeekIter<'_>> {
+        self.btree_index_for_col(col_id)?.scan_prefix_range(range)
+    }
+
+    /// Returns an iterator over the `RowId`s of the btree index on `col_id`, in index order,
+    /// or `None` when `col_id` has no btree index.
+    pub(crate) fn _index_scan(&self, col_id: ColId) -> Option<BTreeIndexIter<'_>> {
+        match self.indexes.get(&[col_id][..])? {
+            Index::BTree(index) => Some(index.scan()),
+            Index::Hash(_) => None,
+        }
     }
 }
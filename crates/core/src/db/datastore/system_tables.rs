@@ -1,8 +1,12 @@
 use super::traits::{ColumnSchema, IndexSchema, SequenceId, SequenceSchema, TableId, TableSchema};
 use crate::error::{DBError, TableError};
 use once_cell::sync::Lazy;
-use spacetimedb_lib::auth::{StAccess, StTableType};
-use spacetimedb_sats::{product, AlgebraicType, AlgebraicValue, ProductType, ProductValue};
+use spacetimedb_lib::{
+    auth::{StAccess, StTableType},
+    buffer::DecodeError,
+    IndexType,
+};
+use spacetimedb_sats::{product, AlgebraicType, AlgebraicValue, ArrayValue, ProductType, ProductValue};
 
 /// The static ID of the table that defines tables
 pub(crate) const ST_TABLES_ID: TableId = TableId(0);
@@ -76,9 +80,10 @@ impl StColumnFields {
 pub enum StIndexFields {
     IndexId = 0,
     TableId = 1,
-    ColId = 2,
+    Cols = 2,
     IndexName = 3,
     IsUnique = 4,
+    IndexType = 5,
 }
 
 impl StIndexFields {
@@ -87,9 +92,10 @@ impl StIndexFields {
         match self {
             StIndexFields::IndexId => "index_id",
             StIndexFields::TableId => "table_id",
-            StIndexFields::ColId => "col_id",
+            StIndexFields::Cols => "cols",
             StIndexFields::IndexName => "index_name",
             StIndexFields::IsUnique => "is_unique",
+            StIndexFields::IndexType => "index_type",
         }
     }
 }
@@ -138,14 +144,16 @@ pub fn st_table_schema() -> TableSchema {
             IndexSchema {
                 index_id: ST_TABLE_ID_INDEX_ID,
                 table_id: ST_TABLES_ID.0,
-                col_id: StTableFields::TableId as u32,
+                cols: vec![StTableFields::TableId as u32],
+                index_type: IndexType::BTree,
                 index_name: "table_id_idx".into(),
                 is_unique: true,
             },
             IndexSchema {
                 index_id: ST_TABLE_NAME_INDEX_ID,
                 table_id: ST_TABLES_ID.0,
-                col_id: StTableFields::TableName as u32,
+                cols: vec![StTableFields::TableName as u32],
+                index_type: IndexType::BTree,
                 index_name: "table_name_idx".into(),
                 is_unique: true,
             },
@@ -246,9 +254,9 @@ pub static ST_COLUMNS_ROW_TYPE: Lazy<ProductType> =
 
 /// System Table [ST_INDEXES]
 ///
-/// | index_id: u32 | table_id: u32 | col_id: u32 | index_name: String | is_unique: bool      |
-/// |---------------|---------------|-------------|--------------------|----------------------|
-/// | 1             | 1             | 1           | "ix_sample"        | 0                    |
+/// | index_id: u32 | table_id: u32 | cols: Array<u32> | index_name: String | is_unique: bool | index_type: String |
+/// |---------------|---------------|------------------|--------------------|-----------------|--------------------|
+/// | 1             | 1             | [1, 2]           | "ix_sample"        | 0               | "btree"            |
 pub fn st_indexes_schema() -> TableSchema {
     TableSchema {
         table_id: ST_INDEXES_ID.0,
@@ -257,7 +265,8 @@ pub fn st_indexes_schema() -> TableSchema {
         indexes: vec![IndexSchema {
             index_id: ST_INDEX_ID_INDEX_ID,
             table_id: ST_INDEXES_ID.0,
-            col_id: 0,
+            cols: vec![0],
+            index_type: IndexType::BTree,
             index_name: "index_id_idx".into(),
             is_unique: true,
         }],
@@ -279,8 +288,8 @@ pub fn st_indexes_schema() -> TableSchema {
             ColumnSchema {
                 table_id: ST_INDEXES_ID.0,
                 col_id: 2,
-                col_name: "col_id".into(),
-                col_type: AlgebraicType::U32,
+                col_name: "cols".into(),
+                col_type: AlgebraicType::array(AlgebraicType::U32),
                 is_autoinc: false,
             },
             ColumnSchema {
@@ -297,6 +306,13 @@ pub fn st_indexes_schema() -> TableSchema {
                 col_type: AlgebraicType::Bool,
                 is_autoinc: false,
             },
+            ColumnSchema {
+                table_id: ST_INDEXES_ID.0,
+                col_id: 5,
+                col_name: "index_type".into(),
+                col_type: AlgebraicType::String,
+                is_autoinc: false,
+            },
         ],
         table_type: StTableType::System,
         table_access: StAccess::Public,
@@ -306,6 +322,70 @@ pub fn st_indexes_schema() -> TableSchema {
 pub static ST_INDEX_ROW_TYPE: Lazy<ProductType> =
     Lazy::new(|| ProductType::from_iter(st_indexes_schema().columns.iter().map(|c| c.col_type.clone())));
 
+/// The row type of [ST_INDEXES] before an index could span several columns:
+///
+/// | index_id: u32 | table_id: u32 | col_id: u32 | index_name: String | is_unique: bool |
+static ST_INDEX_ROW_TYPE_V0: Lazy<ProductType> = Lazy::new(|| {
+    ProductType::from_iter([
+        AlgebraicType::U32,
+        AlgebraicType::U32,
+        AlgebraicType::U32,
+        AlgebraicType::String,
+        AlgebraicType::Bool,
+    ])
+});
+
+/// Decodes `bytes` as a value of `row_type`, failing unless all of them are consumed.
+fn decode_exact(row_type: &ProductType, mut bytes: &[u8]) -> Result<ProductValue, DecodeError> {
+    let row = ProductValue::decode(row_type, &mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(DecodeError::Other(format!("{} trailing bytes", bytes.len())));
+    }
+    Ok(row)
+}
+
+/// The row type of [ST_INDEXES] before an index could be a hash index:
+///
+/// | index_id: u32 | table_id: u32 | cols: Array<u32> | index_name: String | is_unique: bool |
+static ST_INDEX_ROW_TYPE_V1: Lazy<ProductType> = Lazy::new(|| {
+    ProductType::from_iter([
+        AlgebraicType::U32,
+        AlgebraicType::U32,
+        AlgebraicType::array(AlgebraicType::U32),
+        AlgebraicType::String,
+        AlgebraicType::Bool,
+    ])
+});
+
+/// Decodes a row of [ST_INDEXES] read from the message log or a snapshot.
+///
+/// Rows written in an older format are upgraded to [ST_INDEX_ROW_TYPE]:
+/// a single indexed column becomes an array of one column,
+/// and an index without an `index_type` is a btree index.
+pub(crate) fn decode_st_index_row(bytes: &[u8]) -> Result<ProductValue, DecodeError> {
+    decode_exact(&ST_INDEX_ROW_TYPE, bytes).or_else(|e| {
+        // A single column of `0` reads as an empty array of columns, which no index has.
+        let v1 = decode_exact(&ST_INDEX_ROW_TYPE_V1, bytes).ok().filter(|row| {
+            let cols = row.elements[StIndexFields::Cols as usize].as_array();
+            !matches!(cols, Some(ArrayValue::U32(cols)) if cols.is_empty())
+        });
+        let mut row = match v1 {
+            Some(row) => row,
+            None => {
+                let Ok(mut row) = decode_exact(&ST_INDEX_ROW_TYPE_V0, bytes) else {
+                    return Err(e);
+                };
+                let col_id = *row.elements[StIndexFields::Cols as usize].as_u32().unwrap();
+                row.elements[StIndexFields::Cols as usize] = AlgebraicValue::ArrayOf(vec![col_id]);
+                row
+            }
+        };
+        row.elements
+            .push(AlgebraicValue::String(IndexType::BTree.as_str().to_string()));
+        Ok(row)
+    })
+}
+
 /// System Table [ST_SEQUENCES]
 ///
 /// | sequence_id | sequence_name     | increment | start | min_value | max_value | table_id | col_id | allocated |
@@ -319,7 +399,8 @@ pub(crate) fn st_sequences_schema() -> TableSchema {
         indexes: vec![IndexSchema {
             index_id: ST_SEQUENCE_ID_INDEX_ID,
             table_id: ST_SEQUENCES_ID.0,
-            col_id: 0,
+            cols: vec![0],
+            index_type: IndexType::BTree,
             index_name: "sequences_id_idx".into(),
             is_unique: true,
         }],
@@ -527,9 +608,10 @@ impl<Name: AsRef<str>> From<&StColumnRow<Name>> for ProductValue {
 pub struct StIndexRow<Name: AsRef<str>> {
     pub(crate) index_id: u32,
     pub(crate) table_id: u32,
-    pub(crate) col_id: u32,
+    pub(crate) cols: Vec<u32>,
     pub(crate) index_name: Name,
     pub(crate) is_unique: bool,
+    pub(crate) index_type: IndexType,
 }
 
 impl StIndexRow<&str> {
@@ -537,9 +619,10 @@ impl StIndexRow<&str> {
         StIndexRow {
             index_id: self.index_id,
             table_id: self.table_id,
-            col_id: self.col_id,
+            cols: self.cols.clone(),
             index_name: self.index_name.to_owned(),
             is_unique: self.is_unique,
+            index_type: self.index_type,
         }
     }
 }
@@ -549,15 +632,28 @@ impl<'a> TryFrom<&'a ProductValue> for StIndexRow<&'a str> {
     fn try_from(row: &'a ProductValue) -> Result<StIndexRow<&'a str>, DBError> {
         let index_id = row.field_as_u32(StIndexFields::IndexId as usize, None)?;
         let table_id = row.field_as_u32(StIndexFields::TableId as usize, None)?;
-        let col_id = row.field_as_u32(StIndexFields::ColId as usize, None)?;
+        let cols = row.extract_field(StIndexFields::Cols as usize, None, |f| match f.as_array()? {
+            ArrayValue::U32(cols) => Some(cols.clone()),
+            _ => None,
+        })?;
         let index_name = row.field_as_str(StIndexFields::IndexName as usize, None)?;
         let is_unique = row.field_as_bool(StIndexFields::IsUnique as usize, None)?;
+        let index_type = row
+            .field_as_str(StIndexFields::IndexType as usize, None)?
+            .try_into()
+            .map_err(|x: &str| TableError::DecodeField {
+                table: ST_INDEXES_NAME.into(),
+                field: StIndexFields::IndexType.name().into(),
+                expect: format!("`{}` or `{}`", IndexType::BTree.as_str(), IndexType::Hash.as_str()),
+                found: x.to_string(),
+            })?;
         Ok(StIndexRow {
             index_id,
             table_id,
-            col_id,
+            cols,
             index_name,
             is_unique,
+            index_type,
         })
     }
 }
@@ -567,9 +663,10 @@ impl<Name: AsRef<str>> From<&StIndexRow<Name>> for ProductValue {
         product![
             AlgebraicValue::U32(x.index_id),
             AlgebraicValue::U32(x.table_id),
-            AlgebraicValue::U32(x.col_id),
+            AlgebraicValue::ArrayOf(x.cols.clone()),
             AlgebraicValue::String(x.index_name.as_ref().to_string()),
-            AlgebraicValue::Bool(x.is_unique)
+            AlgebraicValue::Bool(x.is_unique),
+            AlgebraicValue::String(x.index_type.as_str().to_string())
         ]
     }
 }
@@ -2,7 +2,7 @@ use crate::db::relational_db::ST_TABLES_ID;
 use core::fmt;
 use spacetimedb_lib::auth::{StAccess, StTableType};
 use spacetimedb_lib::relation::{DbTable, FieldName, FieldOnly, Header, TableField};
-use spacetimedb_lib::DataKey;
+use spacetimedb_lib::{DataKey, IndexType};
 use spacetimedb_sats::{AlgebraicType, AlgebraicValue, ProductType, ProductTypeElement, ProductValue};
 use spacetimedb_vm::expr::SourceExpr;
 use std::{ops::RangeBounds, sync::Arc};
@@ -61,7 +61,9 @@ pub struct SequenceDef {
 pub struct IndexSchema {
     pub(crate) index_id: u32,
     pub(crate) table_id: u32,
-    pub(crate) col_id: u32,
+    /// The indexed columns, in the order their values are compared.
+    pub(crate) cols: Vec<u32>,
+    pub(crate) index_type: IndexType,
     pub(crate) index_name: String,
     pub(crate) is_unique: bool,
 }
@@ -70,15 +72,17 @@ pub struct IndexSchema {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct IndexDef {
     pub(crate) table_id: u32,
-    pub(crate) col_id: u32,
+    pub(crate) cols: Vec<u32>,
+    pub(crate) index_type: IndexType,
     pub(crate) name: String,
     pub(crate) is_unique: bool,
 }
 
 impl IndexDef {
-    pub fn new(name: String, table_id: u32, col_id: u32, is_unique: bool) -> Self {
+    pub fn new(name: String, table_id: u32, cols: Vec<u32>, index_type: IndexType, is_unique: bool) -> Self {
         Self {
-            col_id,
+            cols,
+            index_type,
             name,
             is_unique,
             table_id,
@@ -90,7 +94,8 @@ impl From<IndexSchema> for IndexDef {
     fn from(value: IndexSchema) -> Self {
         Self {
             table_id: value.table_id,
-            col_id: value.col_id,
+            cols: value.cols,
+            index_type: value.index_type,
             name: value.index_name,
             is_unique: value.is_unique,
         }
@@ -357,6 +362,14 @@ pub trait TxDatastore: DataRow + Tx {
     where
         Self: 'a;
 
+    // Tables
+    fn row_type_for_table_tx(&self, tx: &Self::TxId, table_id: TableId) -> Result<ProductType>;
+    fn schema_for_table_tx(&self, tx: &Self::TxId, table_id: TableId) -> Result<TableSchema>;
+    fn table_id_exists_tx(&self, tx: &Self::TxId, table_id: &TableId) -> bool;
+    fn table_id_from_name_tx(&self, tx: &Self::TxId, table_name: &str) -> Result<Option<TableId>>;
+    fn table_name_from_id_tx(&self, tx: &Self::TxId, table_id: TableId) -> Result<Option<String>>;
+
+    // Data
     fn iter_tx<'a>(&'a self, tx: &'a Self::TxId, table_id: TableId) -> Result<Self::Iter<'a>>;
 
     fn iter_by_col_range_tx<'a, R: RangeBounds<AlgebraicValue>>(
@@ -390,6 +403,16 @@ pub trait MutTxDatastore: TxDatastore + MutTx {
     fn schema_for_table_mut_tx(&self, tx: &Self::MutTxId, table_id: TableId) -> Result<TableSchema>;
     fn drop_table_mut_tx(&self, tx: &mut Self::MutTxId, table_id: TableId) -> Result<()>;
     fn rename_table_mut_tx(&self, tx: &mut Self::MutTxId, table_id: TableId, new_name: &str) -> Result<()>;
+    /// Appends `column` to the table with `table_id`, setting it to `default` in every existing row.
+    ///
+    /// An autoinc column is instead filled in from its sequence when `default` is zero.
+    fn add_column_mut_tx(
+        &self,
+        tx: &mut Self::MutTxId,
+        table_id: TableId,
+        column: ColumnDef,
+        default: AlgebraicValue,
+    ) -> Result<()>;
     fn table_id_exists(&self, tx: &Self::MutTxId, table_id: &TableId) -> bool;
     fn table_id_from_name_mut_tx(&self, tx: &Self::MutTxId, table_name: &str) -> Result<Option<TableId>>;
     fn table_name_from_id_mut_tx(&self, tx: &Self::MutTxId, table_id: TableId) -> Result<Option<String>>;
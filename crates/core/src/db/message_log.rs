@@ -132,9 +132,11 @@ impl MessageLog {
 
         let end_size = self.open_segment_size + size as u64;
         if end_size > MAX_SEGMENT_SIZE {
-            self.flush()?;
+            // The segment is synced before moving on, as `sync_all` only syncs the open segment.
+            self.sync_all()?;
+            self.segments.last_mut().unwrap().size = self.open_segment_size;
             self.segments.push(Segment {
-                min_offset: self.open_segment_max_offset + 1,
+                min_offset: self.open_segment_max_offset,
                 size: 0,
             });
 
@@ -191,79 +193,135 @@ impl MessageLog {
         self.root.clone()
     }
 
+    /// Returns the offset of the oldest message still in the log.
+    pub fn min_offset(&self) -> u64 {
+        self.segments[0].min_offset
+    }
+
+    /// Removes all segments which only contain messages before `offset`.
+    /// The segment currently being written to is never removed.
+    ///
+    /// Returns the number of segments removed.
+    #[tracing::instrument]
+    pub fn truncate_before(&mut self, offset: u64) -> Result<usize, DBError> {
+        // A segment can be removed if the segment after it starts at or before `offset`.
+        let count = self
+            .segments
+            .windows(2)
+            .take_while(|segments| segments[1].min_offset <= offset)
+            .count();
+        for segment in self.segments.drain(..count) {
+            fs::remove_file(self.root.join(segment.name() + ".log"))?;
+            self.total_size -= segment.size;
+            log::debug!("Removed message log segment {}", segment.name());
+        }
+        Ok(count)
+    }
+
+    /// Returns the offset the next appended message will have.
+    pub fn max_offset(&self) -> u64 {
+        self.open_segment_max_offset
+    }
+
     pub fn iter(&self) -> MessageLogIter {
         self.iter_from(0)
     }
 
+    /// Returns an iterator over all messages starting at `start_offset`.
     pub fn iter_from(&self, start_offset: u64) -> MessageLogIter {
         MessageLogIter {
             offset: start_offset,
             message_log: self,
+            segment_index: self.segment_for_offset(start_offset),
             open_segment_file: None,
         }
     }
 
-    fn segment_for_offset(&self, offset: u64) -> Option<Segment> {
-        let prev = self.segments[0];
-        for segment in &self.segments {
-            if segment.min_offset > offset {
-                return Some(prev);
-            }
+    /// Returns the index of the segment containing `offset`,
+    /// or `None` if the offset lies past the end of the log.
+    fn segment_for_offset(&self, offset: u64) -> Option<usize> {
+        if offset >= self.open_segment_max_offset {
+            return None;
         }
-        if offset <= self.open_segment_max_offset {
-            return Some(*self.segments.last().unwrap());
-        }
-        None
+        // `segments` is sorted by `min_offset` and the first segment may not start at 0
+        // if older segments have been removed.
+        let index = self.segments.partition_point(|segment| segment.min_offset <= offset);
+        Some(index.saturating_sub(1))
     }
 }
 
 pub struct MessageLogIter<'a> {
     offset: u64,
     message_log: &'a MessageLog,
+    segment_index: Option<usize>,
     open_segment_file: Option<BufReader<File>>,
 }
 
+impl<'a> MessageLogIter<'a> {
+    /// Opens the segment at `segment_index`,
+    /// skipping any messages before `self.offset`.
+    fn open_segment(&mut self, segment_index: usize) -> std::io::Result<BufReader<File>> {
+        let segment = self.message_log.segments[segment_index];
+        let file = OpenOptions::new()
+            .read(true)
+            .open(self.message_log.root.join(segment.name() + ".log"))?;
+        let mut file = BufReader::new(file);
+        for _ in segment.min_offset..self.offset {
+            let mut buf = [0; HEADER_SIZE];
+            file.read_exact(&mut buf)?;
+            let message_len = u32::from_le_bytes(buf);
+            file.seek_relative(message_len as i64)?;
+        }
+        Ok(file)
+    }
+}
+
 impl<'a> Iterator for MessageLogIter<'a> {
     type Item = Vec<u8>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let open_segment_file: &mut BufReader<File>;
-        if let Some(f) = &mut self.open_segment_file {
-            open_segment_file = f;
-        } else {
-            let segment = self.message_log.segment_for_offset(self.offset).unwrap();
-            let file = OpenOptions::new()
-                .read(true)
-                .open(self.message_log.root.join(segment.name() + ".log"))
-                .unwrap();
-            let file = BufReader::new(file);
-            self.open_segment_file = Some(file);
-            open_segment_file = self.open_segment_file.as_mut().unwrap();
-        }
+        loop {
+            let segment_index = self.segment_index?;
+            if segment_index >= self.message_log.segments.len() {
+                return None;
+            }
 
-        // TODO: use offset to jump to the right spot in the file
-        // open_segment_file.seek_relative(byte_offset(self.offset));
+            let open_segment_file = match &mut self.open_segment_file {
+                Some(file) => file,
+                None => {
+                    let file = self
+                        .open_segment(segment_index)
+                        .unwrap_or_else(|err| panic!("MessageLogIter: {:?}", err));
+                    self.open_segment_file.insert(file)
+                }
+            };
 
-        let mut buf = [0; HEADER_SIZE];
-        if let Err(err) = open_segment_file.read_exact(&mut buf) {
-            match err.kind() {
-                std::io::ErrorKind::UnexpectedEof => return None,
-                _ => panic!("MessageLogIter: {:?}", err),
-            }
-        };
-        let message_len = u32::from_le_bytes(buf);
-
-        let mut buf = vec![0; message_len as usize];
-        if let Err(err) = open_segment_file.read_exact(&mut buf) {
-            match err.kind() {
-                std::io::ErrorKind::UnexpectedEof => return None,
-                _ => panic!("MessageLogIter: {:?}", err),
+            let mut buf = [0; HEADER_SIZE];
+            if let Err(err) = open_segment_file.read_exact(&mut buf) {
+                match err.kind() {
+                    // Move on to the next segment, if any.
+                    std::io::ErrorKind::UnexpectedEof => {
+                        self.open_segment_file = None;
+                        self.segment_index = Some(segment_index + 1);
+                        continue;
+                    }
+                    _ => panic!("MessageLogIter: {:?}", err),
+                }
+            };
+            let message_len = u32::from_le_bytes(buf);
+
+            let mut buf = vec![0; message_len as usize];
+            if let Err(err) = open_segment_file.read_exact(&mut buf) {
+                match err.kind() {
+                    std::io::ErrorKind::UnexpectedEof => return None,
+                    _ => panic!("MessageLogIter: {:?}", err),
+                }
             }
-        }
 
-        self.offset += 1;
+            self.offset += 1;
 
-        Some(buf)
+            return Some(buf);
+        }
     }
 }
 
@@ -327,4 +385,58 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_message_log_iter_from() -> ResultTest<()> {
+        let tmp_dir = TempDir::new("message_log_test")?;
+        let path = tmp_dir.path();
+        let mut message_log = MessageLog::open(path)?;
+
+        for i in 0..100u32 {
+            message_log.append(i.to_le_bytes())?;
+        }
+        message_log.sync_all()?;
+
+        let messages = message_log.iter_from(42).collect::<Vec<_>>();
+        assert_eq!(messages.len(), 58);
+        assert_eq!(messages[0], 42u32.to_le_bytes());
+        assert_eq!(message_log.iter_from(100).count(), 0);
+        assert_eq!(message_log.iter().count(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_message_log_truncate_before() -> ResultTest<()> {
+        let tmp_dir = TempDir::new("message_log_test")?;
+        let path = tmp_dir.path();
+
+        // Write two segments by hand, as rolling over to a new segment takes 1 GiB.
+        for (min_offset, count) in [(0u32, 10u32), (10, 5)] {
+            let mut segment = Vec::new();
+            for i in min_offset..min_offset + count {
+                segment.extend(4u32.to_le_bytes());
+                segment.extend(i.to_le_bytes());
+            }
+            std::fs::write(path.join(format!("{:0>20}.log", min_offset)), segment)?;
+        }
+
+        let mut message_log = MessageLog::open(path)?;
+        assert_eq!(message_log.max_offset(), 15);
+        assert_eq!(message_log.iter_from(8).count(), 7);
+
+        assert_eq!(message_log.truncate_before(9)?, 0);
+        assert_eq!(message_log.truncate_before(12)?, 1);
+        assert_eq!(message_log.min_offset(), 10);
+        assert_eq!(message_log.size(), 5 * 8);
+
+        let messages = message_log.iter_from(12).collect::<Vec<_>>();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0], 12u32.to_le_bytes());
+
+        // The open segment is never removed.
+        assert_eq!(message_log.truncate_before(15)?, 0);
+
+        Ok(())
+    }
 }
@@ -7,9 +7,14 @@ pub mod messages;
 pub mod ostorage;
 pub mod relational_db;
 mod relational_operators;
+pub mod snapshot;
 
 pub use spacetimedb_lib::{AlgebraicType, AlgebraicValue, ProductType, ProductValue};
 
+use spacetimedb_sats::de::Deserialize;
+use spacetimedb_sats::ser::Serialize;
+use std::time::Duration;
+
 /// Whether SpacetimeDB is run in memory, or persists objects and
 /// a message log to disk.
 #[derive(Clone, Copy)]
@@ -20,3 +25,67 @@ pub enum Storage {
     /// The object store is persisted to disk, and a message log is kept.
     Disk,
 }
+
+/// When a commit written to the message log is considered durable.
+///
+/// This is a tradeoff between write throughput and how many commits
+/// may be lost if the machine crashes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Durability {
+    /// Every commit is synced to disk before `commit_tx` returns.
+    #[default]
+    Sync,
+
+    /// Commits are written to the OS as they happen,
+    /// and synced to disk together once the [`GroupCommit`] window is full.
+    Group(GroupCommit),
+
+    /// Commits are written to the OS as they happen,
+    /// and synced to disk in the background every [`ASYNC_SYNC_INTERVAL`].
+    ///
+    /// `commit_tx` never waits for the disk, but a commit is only considered durable once it has been synced.
+    Async,
+}
+
+/// How often the message log is synced to disk under [`Durability::Async`].
+pub const ASYNC_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Durability {
+    /// The window in which commits are synced together, or `None` if each commit is synced on its own.
+    fn window(self) -> Option<GroupCommit> {
+        match self {
+            Durability::Sync => None,
+            Durability::Group(window) => Some(window),
+            Durability::Async => Some(GroupCommit {
+                max_delay_ms: ASYNC_SYNC_INTERVAL.as_millis() as u64,
+                max_bytes: u64::MAX,
+            }),
+        }
+    }
+}
+
+/// The window in which commits are grouped into a single sync to disk.
+///
+/// The message log is synced as soon as either limit is reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupCommit {
+    /// The longest time a commit waits to be synced, in milliseconds.
+    pub max_delay_ms: u64,
+    /// The most bytes written to the message log before it is synced.
+    pub max_bytes: u64,
+}
+
+impl GroupCommit {
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_millis(self.max_delay_ms)
+    }
+}
+
+impl Default for GroupCommit {
+    fn default() -> Self {
+        Self {
+            max_delay_ms: 10,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
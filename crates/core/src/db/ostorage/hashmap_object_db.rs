@@ -165,6 +165,33 @@ impl ObjectDB for HashMapObjectDB {
         self.map.get(&hash).map(|v| bytes::Bytes::from(v.clone()))
     }
 
+    fn delete(&mut self, hash: Hash) -> Result<bool, DBError> {
+        let Some(bytes) = self.map.remove(&hash) else {
+            return Ok(false);
+        };
+
+        let folder = hex::encode(&hash.data[0..1]);
+        let filename = hex::encode(&hash.data[1..]);
+        let path = self.root.join(folder).join(filename);
+        match fs::remove_file(path) {
+            Ok(()) => (),
+            // The object may never have made it to disk.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+
+        self.obj_size -= bytes.len() as u64;
+        Ok(true)
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Hash) -> bool) -> Result<usize, DBError> {
+        let to_delete = self.map.keys().filter(|hash| !keep(hash)).copied().collect::<Vec<_>>();
+        for hash in &to_delete {
+            self.delete(*hash)?;
+        }
+        Ok(to_delete.len())
+    }
+
     // NOTE: Flushing a `File` does nothing (just returns Ok(())), but flushing a BufWriter will
     // write the current buffer to the `File` by calling write. All `File` writes are atomic
     // so if you want to do an atomic action, make sure it all fits within the BufWriter buffer.
@@ -271,6 +298,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_delete_and_retain() -> ResultTest<()> {
+        let (mut db, tmp_dir) = setup()?;
+
+        let hash1 = db.add(TEST_DATA1.to_vec());
+        let hash2 = db.add(TEST_DATA2.to_vec());
+        db.sync_all()?;
+
+        assert!(db.delete(hash1)?);
+        assert!(!db.delete(hash1)?);
+        assert!(db.get(hash1).is_none());
+        assert_eq!(db.total_obj_size_bytes(), TEST_DATA2.len() as u64);
+
+        assert_eq!(db.retain(&mut |hash| *hash != hash2)?, 1);
+        assert!(db.get(hash2).is_none());
+        drop(db);
+
+        // The objects are also gone from disk.
+        let db = HashMapObjectDB::open(tmp_dir.path())?;
+        assert_eq!(db.total_obj_size_bytes(), 0);
+        Ok(())
+    }
+
     #[test]
     fn test_size() -> ResultTest<()> {
         let (mut db, _tmp_dir) = setup()?;
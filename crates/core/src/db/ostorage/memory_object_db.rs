@@ -22,6 +22,16 @@ impl ObjectDB for MemoryObjectDB {
         self.objects.get(&hash).cloned()
     }
 
+    fn delete(&mut self, hash: Hash) -> Result<bool, crate::error::DBError> {
+        Ok(self.objects.remove(&hash).is_some())
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Hash) -> bool) -> Result<usize, crate::error::DBError> {
+        let len = self.objects.len();
+        self.objects.retain(|hash, _| keep(hash));
+        Ok(len - self.objects.len())
+    }
+
     /// Flushing an in-memory object store is a no-op.
     fn flush(&mut self) -> Result<(), crate::error::DBError> {
         Ok(())
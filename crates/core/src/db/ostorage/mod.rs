@@ -18,6 +18,11 @@ pub mod sled_object_db;
 pub trait ObjectDB {
     fn add(&mut self, bytes: Vec<u8>) -> Hash;
     fn get(&self, hash: Hash) -> Option<bytes::Bytes>;
+    /// Removes the object stored under `hash`, returning whether it was present.
+    fn delete(&mut self, hash: Hash) -> Result<bool, DBError>;
+    /// Removes every object for which `keep` returns `false`,
+    /// returning the number of objects removed.
+    fn retain(&mut self, keep: &mut dyn FnMut(&Hash) -> bool) -> Result<usize, DBError>;
     fn flush(&mut self) -> Result<(), DBError>;
     fn sync_all(&mut self) -> Result<(), DBError>;
 }
@@ -2,7 +2,7 @@ use crate::db::ostorage::ObjectDB;
 use crate::error::DBError;
 use crate::hash::{hash_bytes, Hash};
 use bytes::Bytes;
-use rocksdb::{ColumnFamilyDescriptor, Options, DB};
+use rocksdb::{ColumnFamilyDescriptor, IteratorMode, Options, DB};
 use std::fs;
 use std::path::Path;
 
@@ -56,6 +56,30 @@ impl ObjectDB for RocksDBObjectDB {
         }
     }
 
+    fn delete(&mut self, hash: Hash) -> Result<bool, DBError> {
+        let cf = self.db.cf_handle(RocksDBObjectDB::OBJECTS_CF).unwrap();
+
+        if self.db.get_pinned_cf(cf, hash.as_slice())?.is_none() {
+            return Ok(false);
+        }
+        self.db.delete_cf(cf, hash.as_slice())?;
+        Ok(true)
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Hash) -> bool) -> Result<usize, DBError> {
+        let cf = self.db.cf_handle(RocksDBObjectDB::OBJECTS_CF).unwrap();
+
+        let mut count = 0;
+        for item in self.db.iterator_cf(cf, IteratorMode::Start) {
+            let (key, _) = item?;
+            if !keep(&Hash::from_slice(&key)) {
+                self.db.delete_cf(cf, &key)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
     fn flush(&mut self) -> Result<(), DBError> {
         match self.db.flush() {
             Ok(_) => Ok(()),
@@ -123,6 +147,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_delete_and_retain() -> ResultTest<()> {
+        let mut db = setup()?;
+
+        let hash1 = db.add(TEST_DATA1.to_vec());
+        let hash2 = db.add(TEST_DATA2.to_vec());
+
+        assert!(db.delete(hash1)?);
+        assert!(!db.delete(hash1)?);
+        assert!(db.get(hash1).is_none());
+
+        assert_eq!(db.retain(&mut |hash| *hash != hash2)?, 1);
+        assert!(db.get(hash2).is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_miss() -> ResultTest<()> {
         let mut db = setup()?;
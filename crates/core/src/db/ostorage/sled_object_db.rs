@@ -38,6 +38,22 @@ impl ObjectDB for SledObjectDB {
         }
     }
 
+    fn delete(&mut self, hash: Hash) -> Result<bool, DBError> {
+        Ok(self.db.remove(hash.as_slice())?.is_some())
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Hash) -> bool) -> Result<usize, DBError> {
+        let mut count = 0;
+        for key in self.db.iter().keys() {
+            let key = key?;
+            if !keep(&Hash::from_slice(&key)) {
+                self.db.remove(key)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
     fn flush(&mut self) -> Result<(), DBError> {
         match self.db.flush() {
             Ok(_) => Ok(()),
@@ -102,6 +118,21 @@ mod tests {
         assert!(db.sync_all().is_ok());
     }
 
+    #[test]
+    fn test_delete_and_retain() {
+        let mut db = setup().unwrap();
+
+        let hash1 = db.add(TEST_DATA1.to_vec());
+        let hash2 = db.add(TEST_DATA2.to_vec());
+
+        assert!(db.delete(hash1).unwrap());
+        assert!(!db.delete(hash1).unwrap());
+        assert!(db.get(hash1).is_none());
+
+        assert_eq!(db.retain(&mut |hash| *hash != hash2).unwrap(), 1);
+        assert!(db.get(hash2).is_none());
+    }
+
     #[test]
     fn test_miss() {
         let mut db = setup().unwrap();
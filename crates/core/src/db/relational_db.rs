@@ -1,18 +1,22 @@
-use super::commit_log::CommitLog;
-use super::datastore::locking_tx_datastore::{Data, DataRef, Iter, IterByColEq, IterByColRange, MutTxId, RowId};
+use super::commit_log::{AppendedCommit, CommitLog, CompactionStats};
+use super::datastore::locking_tx_datastore::{
+    Data, DataRef, Iter, IterByColEq, IterByColRange, MutTxId, RowId, Savepoint, TxId,
+};
 use super::datastore::traits::{
-    ColId, DataRow, IndexDef, IndexId, MutTx, MutTxDatastore, SequenceDef, SequenceId, TableDef, TableId, TableSchema,
-    TxData,
+    ColId, ColumnDef, DataRow, IndexDef, IndexId, MutTx, MutTxDatastore, SequenceDef, SequenceId, TableDef, TableId,
+    TableSchema, Tx, TxData, TxDatastore,
 };
 use super::message_log::MessageLog;
 use super::ostorage::memory_object_db::MemoryObjectDB;
 use super::relational_operators::Relation;
+use super::Durability;
 use crate::db::db_metrics::{RDB_DELETE_BY_REL_TIME, RDB_DROP_TABLE_TIME, RDB_INSERT_TIME, RDB_ITER_TIME};
 use crate::db::messages::commit::Commit;
 use crate::db::ostorage::hashmap_object_db::HashMapObjectDB;
 use crate::db::ostorage::ObjectDB;
+use crate::db::snapshot::{Snapshot, SnapshotStore};
 use crate::error::{DBError, DatabaseError, TableError};
-use crate::hash::Hash;
+use crate::hash::{hash_bytes, Hash};
 use crate::util::prometheus_handle::HistogramVecHandle;
 use fs2::FileExt;
 use prometheus::HistogramVec;
@@ -22,6 +26,7 @@ use spacetimedb_sats::{AlgebraicType, AlgebraicValue, ProductType, ProductValue}
 use std::fs::{create_dir_all, File};
 use std::ops::RangeBounds;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
 use super::datastore::locking_tx_datastore::Locking;
@@ -43,11 +48,16 @@ pub const ST_COLUMNS_ID: u32 = 1;
 /// The ID that we can start use to generate user tables that will not conflict with the bootstrapped ones.
 pub const ST_TABLE_ID_START: u32 = 2;
 
+/// The number of commits after which a new snapshot of the committed state is taken.
+const SNAPSHOT_INTERVAL: u64 = 100_000;
+
 #[derive(Clone)]
 pub struct RelationalDB {
     // TODO(cloutiertyler): This should not be public
     pub(crate) inner: Locking,
     commit_log: CommitLog,
+    snapshots: Option<Arc<SnapshotStore>>,
+    last_snapshot_offset: Arc<AtomicU64>,
     _lock: Arc<File>,
 }
 
@@ -72,6 +82,7 @@ impl RelationalDB {
         root: impl AsRef<Path>,
         message_log: Option<Arc<Mutex<MessageLog>>>,
         odb: Arc<Mutex<Box<dyn ObjectDB + Send>>>,
+        durability: Durability,
     ) -> Result<Self, DBError> {
         log::trace!("DATABASE: OPENING");
 
@@ -86,18 +97,44 @@ impl RelationalDB {
         lock.try_lock_exclusive()
             .map_err(|err| DatabaseError::DatabasedOpened(root.to_path_buf(), err.into()))?;
 
-        let datastore = Locking::bootstrap()?;
+        let snapshots = match &message_log {
+            Some(_) => Some(Arc::new(SnapshotStore::open(root.join("snapshots"))?)),
+            None => None,
+        };
+
+        let mut datastore = Locking::bootstrap()?;
+        let mut snapshot_commit_offset = 0;
         let unwritten_commit = {
             let mut transaction_offset = 0;
-            let mut last_commit_offset = None;
+            let mut commit_offset = 0;
             let mut last_hash: Option<Hash> = None;
 
             if let Some(message_log) = &message_log {
                 let message_log = message_log.lock().unwrap();
-                for message in message_log.iter() {
-                    let (commit, _) = Commit::decode(message);
-                    last_hash = commit.parent_commit_hash;
-                    last_commit_offset = Some(commit.commit_offset);
+
+                // Start from the newest usable snapshot, if any,
+                // so that we only need to replay the commits after it.
+                let mut sequences = None;
+                if let Some(snapshots) = &snapshots {
+                    if let Some((restored, snapshot)) = restore_newest_snapshot(snapshots, message_log.max_offset())? {
+                        datastore = restored;
+                        last_hash = snapshot.parent_commit_hash;
+                        commit_offset = snapshot.commit_offset;
+                        transaction_offset = snapshot.min_tx_offset;
+                        snapshot_commit_offset = snapshot.commit_offset;
+                        sequences = Some(snapshot.sequences);
+                    }
+                }
+
+                // Without a snapshot to restore, we need the whole log.
+                if commit_offset < message_log.min_offset() {
+                    return Err(DatabaseError::MissingSnapshot(message_log.min_offset()).into());
+                }
+
+                for message in message_log.iter_from(commit_offset) {
+                    let (commit, _) = Commit::decode(&message);
+                    last_hash = Some(hash_bytes(&message));
+                    commit_offset = commit.commit_offset + 1;
                     for transaction in commit.transactions {
                         transaction_offset += 1;
                         // NOTE: Although I am creating a datastore transaction in a
@@ -115,13 +152,11 @@ impl RelationalDB {
                 // is not equivalent to calling `create_table`.
                 // There may eventually be better way to do this, but this will have to do for now.
                 datastore.rebuild_state_after_replay()?;
-            }
 
-            let commit_offset = if let Some(last_commit_offset) = last_commit_offset {
-                last_commit_offset + 1
-            } else {
-                0
-            };
+                if let Some(sequences) = sequences {
+                    datastore.restore_sequences(&sequences, commit_offset > snapshot_commit_offset);
+                }
+            }
 
             log::debug!(
                 "Initialized with {} commits and tx offset {}",
@@ -136,13 +171,15 @@ impl RelationalDB {
                 transactions: Vec::new(),
             }
         };
-        let commit_log = CommitLog::new(message_log, odb.clone(), unwritten_commit);
+        let commit_log = CommitLog::new(message_log, odb.clone(), unwritten_commit, durability);
 
         // i.e. essentially bootstrap the creation of the schema
         // tables by hard coding the schema of the schema tables
         let db = Self {
             inner: datastore,
             commit_log,
+            snapshots,
+            last_snapshot_offset: Arc::new(AtomicU64::new(snapshot_commit_offset)),
             _lock: Arc::new(lock),
         };
 
@@ -175,6 +212,11 @@ impl RelationalDB {
         self.inner.schema_for_table_mut_tx(tx, TableId(table_id))
     }
 
+    #[tracing::instrument(skip_all)]
+    pub fn schema_for_table_tx(&self, tx: &TxId, table_id: u32) -> Result<TableSchema, DBError> {
+        self.inner.schema_for_table_tx(tx, TableId(table_id))
+    }
+
     #[tracing::instrument(skip_all)]
     pub fn row_schema_for_table(&self, tx: &MutTxId, table_id: u32) -> Result<ProductType, DBError> {
         self.inner.row_type_for_table_mut_tx(tx, TableId(table_id))
@@ -219,13 +261,151 @@ impl RelationalDB {
         log::trace!("ROLLBACK TX");
         self.inner.rollback_mut_tx(tx)
     }
-    pub fn commit_tx(&self, tx: MutTxId) -> Result<Option<(TxData, Option<usize>)>, DBError> {
+
+    /// Marks the current state of `tx`, so that what it does after can be undone
+    /// with [`Self::rollback_to_savepoint`] while keeping the transaction open.
+    pub fn savepoint(&self, tx: &MutTxId) -> Savepoint {
+        log::trace!("SAVEPOINT");
+        tx.savepoint()
+    }
+
+    pub fn rollback_to_savepoint(&self, tx: &mut MutTxId, savepoint: &Savepoint) {
+        log::trace!("ROLLBACK TO SAVEPOINT");
+        tx.rollback_to_savepoint(savepoint)
+    }
+
+    /// Begin a read-only transaction.
+    ///
+    /// It sees the database as of the last commit, and it neither blocks
+    /// nor is blocked by concurrent transactions. See also [`Self::with_read_only`].
+    pub fn begin_read_tx(&self) -> TxId {
+        log::trace!("BEGIN READ TX");
+        self.inner.begin_tx()
+    }
+
+    pub fn release_tx(&self, tx: TxId) {
+        log::trace!("RELEASE TX");
+        self.inner.release_tx(tx)
+    }
+
+    /// Commit a transaction.
+    ///
+    /// Returns the [`AppendedCommit`] if the transaction wrote anything to the message log,
+    /// which callers can wait on if they need the commit to be durable.
+    pub fn commit_tx(&self, tx: MutTxId) -> Result<Option<(TxData, Option<AppendedCommit>)>, DBError> {
+        let committed = self.commit_tx_with_read_tx(tx)?;
+        Ok(committed.map(|(tx_data, commit, _)| (tx_data, commit)))
+    }
+
+    /// Commit a transaction like [`Self::commit_tx`],
+    /// also returning a read-only transaction of the state right after the commit,
+    /// which the transactions committed after it don't change.
+    pub fn commit_tx_with_read_tx(
+        &self,
+        tx: MutTxId,
+    ) -> Result<Option<(TxData, Option<AppendedCommit>, TxId)>, DBError> {
         log::trace!("COMMIT TX");
-        if let Some(tx_data) = self.inner.commit_mut_tx(tx)? {
-            let bytes_written = self.commit_log.append_tx(&tx_data, &self.inner)?;
-            return Ok(Some((tx_data, bytes_written)));
+        let committed = self
+            .inner
+            .commit_mut_tx_and_then(tx, |tx_data| self.commit_log.append_tx(tx_data, &self.inner))?;
+        if let Some((_, Some(_), _)) = &committed {
+            self.maybe_take_snapshot();
+        }
+        Ok(committed)
+    }
+
+    /// Starts taking a snapshot in the background
+    /// if [`SNAPSHOT_INTERVAL`] commits have been written since the last one.
+    ///
+    /// The commit has already been persisted at this point,
+    /// so a failure to write the snapshot is logged rather than returned.
+    fn maybe_take_snapshot(&self) {
+        if self.snapshots.is_none() {
+            return;
+        }
+        let (_, commit_offset, _) = self.commit_log.position();
+        let last_snapshot_offset = self.last_snapshot_offset.load(Ordering::Acquire);
+        if commit_offset.saturating_sub(last_snapshot_offset) < SNAPSHOT_INTERVAL {
+            return;
+        }
+        // Only one of the threads committing concurrently takes the snapshot.
+        if self
+            .last_snapshot_offset
+            .compare_exchange(last_snapshot_offset, commit_offset, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return;
         }
-        Ok(None)
+        // The committing thread must not wait for the rows to be encoded and written.
+        let db = self.clone();
+        let spawned = std::thread::Builder::new().name("snapshot".into()).spawn(move || {
+            if let Err(err) = db.take_snapshot().and_then(|_| db.compact()) {
+                log::error!(
+                    "Failed to snapshot and compact at commit offset {}: {}",
+                    commit_offset,
+                    err
+                );
+            }
+        });
+        if let Err(err) = spawned {
+            log::error!("Failed to spawn the snapshot thread: {}", err);
+        }
+    }
+
+    /// Writes a snapshot of the committed state to disk,
+    /// so that opening the database only replays the commits after it.
+    ///
+    /// Returns the commit offset the snapshot was taken at,
+    /// or `None` if the database is not persisted to disk.
+    ///
+    /// **Note**: this blocks until any open transaction is done,
+    /// so it must not be called while holding a [`MutTxId`].
+    pub fn take_snapshot(&self) -> Result<Option<u64>, DBError> {
+        let Some(snapshots) = &self.snapshots else {
+            return Ok(None);
+        };
+        // Commits are appended to the log before their transaction releases the datastore,
+        // so the committed state contains exactly the commits before `commit_offset`.
+        let ((parent_commit_hash, commit_offset, min_tx_offset), tables, sequences) =
+            self.inner.snapshot_state(|| self.commit_log.position());
+        // The snapshot must never be ahead of the commits that survive a crash,
+        // so the commits it contains are made durable before it is written.
+        self.commit_log.sync_all()?;
+        snapshots.write(&Snapshot {
+            parent_commit_hash,
+            commit_offset,
+            min_tx_offset,
+            tables,
+            sequences,
+        })?;
+        self.last_snapshot_offset.fetch_max(commit_offset, Ordering::AcqRel);
+        Ok(Some(commit_offset))
+    }
+
+    /// Reclaims disk space no longer needed to open the database.
+    ///
+    /// Removes the message log segments before the oldest snapshot on disk,
+    /// as well as the objects in the object store that are neither referenced
+    /// by a committed row nor by a commit after that snapshot.
+    ///
+    /// Returns `None` if there is no snapshot yet,
+    /// in which case the whole message log is still needed.
+    ///
+    /// **Note**: this blocks until any open transaction is done,
+    /// so it must not be called while holding a [`MutTxId`].
+    pub fn compact(&self) -> Result<Option<CompactionStats>, DBError> {
+        let min_commit_offset = match &self.snapshots {
+            Some(snapshots) => match snapshots.offsets()?.last() {
+                Some(offset) => *offset,
+                None => return Ok(None),
+            },
+            // Nothing is ever replayed from memory, so only live rows need their objects.
+            None => 0,
+        };
+        // Collected before the commit log is locked, as commits lock the datastore and then the log.
+        let live_objects = self.inner.committed_objects();
+        let stats = self.commit_log.compact(min_commit_offset, live_objects)?;
+        Ok(Some(stats))
     }
 
     /// Run a fallible function in a transaction.
@@ -266,18 +446,31 @@ impl RelationalDB {
         self.finish_tx(tx, res)
     }
 
+    /// Run a function in a read-only transaction.
+    ///
+    /// Unlike [`Self::with_auto_commit`], this doesn't wait for, nor hold up,
+    /// any transaction that modifies the database.
+    pub fn with_read_only<F, A>(&self, f: F) -> A
+    where
+        F: FnOnce(&TxId) -> A,
+    {
+        let tx = self.begin_read_tx();
+        let res = f(&tx);
+        self.release_tx(tx);
+        res
+    }
+
     /// Perform the transactional logic for the `tx` according to the `res`
+    ///
+    /// Fails with [`DatabaseError::WriteConflict`] if `tx` conflicted with a concurrent transaction.
     pub fn finish_tx<A, E>(&self, tx: MutTxId, res: Result<A, E>) -> Result<A, E>
     where
         E: From<DBError>,
     {
         if res.is_err() {
             self.rollback_tx(tx);
-        } else {
-            match self.commit_tx(tx).map_err(E::from)? {
-                Some(_) => (),
-                None => panic!("TODO: retry?"),
-            }
+        } else if self.commit_tx(tx).map_err(E::from)?.is_none() {
+            return Err(DBError::from(DatabaseError::WriteConflict).into());
         }
         res
     }
@@ -303,6 +496,22 @@ impl RelationalDB {
         self.inner.rename_table_mut_tx(tx, TableId(table_id), new_name)
     }
 
+    /// Add a column to the end of a table.
+    ///
+    /// Every row in the table is rewritten with `default` as the value of the new column,
+    /// so this is as expensive as updating every row.
+    ///
+    /// If the table is not found or already has a column with the same name, an error is returned.
+    pub fn add_column(
+        &self,
+        tx: &mut MutTxId,
+        table_id: u32,
+        column: ColumnDef,
+        default: AlgebraicValue,
+    ) -> Result<(), DBError> {
+        self.inner.add_column_mut_tx(tx, TableId(table_id), column, default)
+    }
+
     #[tracing::instrument(skip_all)]
     pub fn table_id_from_name(&self, tx: &MutTxId, table_name: &str) -> Result<Option<u32>, DBError> {
         self.inner
@@ -310,6 +519,11 @@ impl RelationalDB {
             .map(|x| x.map(|x| x.0))
     }
 
+    #[tracing::instrument(skip_all)]
+    pub fn table_id_from_name_tx(&self, tx: &TxId, table_name: &str) -> Result<Option<u32>, DBError> {
+        self.inner.table_id_from_name_tx(tx, table_name).map(|x| x.map(|x| x.0))
+    }
+
     #[tracing::instrument(skip_all)]
     pub fn table_exist(&self, tx: &MutTxId, table_name: &str) -> Result<Option<u32>, DBError> {
         self.inner
@@ -322,6 +536,11 @@ impl RelationalDB {
         self.inner.table_name_from_id_mut_tx(tx, TableId(table_id))
     }
 
+    #[tracing::instrument(skip_all)]
+    pub fn table_name_from_id_tx(&self, tx: &TxId, table_id: u32) -> Result<Option<String>, DBError> {
+        self.inner.table_name_from_id_tx(tx, TableId(table_id))
+    }
+
     #[tracing::instrument(skip_all)]
     pub fn column_attrs(
         &self,
@@ -333,7 +552,7 @@ impl RelationalDB {
         let Some(column) = table.columns.get(col_id as usize) else {
             return Ok(None);
         };
-        let unique_index = table.indexes.iter().find(|x| x.col_id == col_id).map(|x| x.is_unique);
+        let unique_index = table.indexes.iter().find(|x| x.cols == [col_id]).map(|x| x.is_unique);
         Ok(Some(match (column.is_autoinc, unique_index) {
             (true, Some(true)) => ColumnIndexAttribute::Identity,
             (true, Some(false) | None) => ColumnIndexAttribute::AutoInc,
@@ -381,6 +600,15 @@ impl RelationalDB {
         self.inner.iter_mut_tx(tx, TableId(table_id))
     }
 
+    /// Returns an iterator,
+    /// yielding every row in the table identified by `table_id`,
+    /// as of the start of the read-only transaction `tx`.
+    #[tracing::instrument(skip(self, tx))]
+    pub fn iter_tx<'a>(&'a self, tx: &'a TxId, table_id: u32) -> Result<Iter<'a>, DBError> {
+        measure(&RDB_ITER_TIME, table_id);
+        self.inner.iter_tx(tx, TableId(table_id))
+    }
+
     /// Returns an iterator,
     /// yielding every row in the table identified by `table_id`,
     /// where the column data identified by `col_id` matches `value`.
@@ -389,7 +617,7 @@ impl RelationalDB {
     #[tracing::instrument(skip(self, tx))]
     pub fn iter_by_col_eq<'a>(
         &'a self,
-        tx: &'a mut MutTxId,
+        tx: &'a MutTxId,
         table_id: u32,
         col_id: u32,
         value: &'a AlgebraicValue,
@@ -398,6 +626,23 @@ impl RelationalDB {
             .iter_by_col_eq_mut_tx(tx, TableId(table_id), ColId(col_id), value)
     }
 
+    /// Returns an iterator,
+    /// yielding every row in the table identified by `table_id`,
+    /// where the column data identified by `col_id` matches `value`,
+    /// as of the start of the read-only transaction `tx`.
+    ///
+    /// Matching is defined by `Ord for AlgebraicValue`.
+    pub fn iter_by_col_eq_tx<'a>(
+        &'a self,
+        tx: &'a TxId,
+        table_id: u32,
+        col_id: u32,
+        value: &'a AlgebraicValue,
+    ) -> Result<IterByColEq<'a>, DBError> {
+        self.inner
+            .iter_by_col_eq_tx(tx, TableId(table_id), ColId(col_id), value)
+    }
+
     /// Returns an iterator,
     /// yielding every row in the table identified by `table_id`,
     /// where the column data identified by `col_id` matches what is within `range`.
@@ -414,6 +659,23 @@ impl RelationalDB {
             .iter_by_col_range_mut_tx(tx, TableId(table_id), ColId(col_id), range)
     }
 
+    /// Returns an iterator,
+    /// yielding every row in the table identified by `table_id`,
+    /// where the column data identified by `col_id` matches what is within `range`,
+    /// as of the start of the read-only transaction `tx`.
+    ///
+    /// Matching is defined by `Ord for AlgebraicValue`.
+    pub fn iter_by_col_range_tx<'a, R: RangeBounds<AlgebraicValue> + 'a>(
+        &'a self,
+        tx: &'a TxId,
+        table_id: u32,
+        col_id: u32,
+        range: R,
+    ) -> Result<IterByColRange<'a, R>, DBError> {
+        self.inner
+            .iter_by_col_range_tx(tx, TableId(table_id), ColId(col_id), range)
+    }
+
     #[tracing::instrument(skip(self, tx))]
     pub fn insert(&self, tx: &mut MutTxId, table_id: u32, row: ProductValue) -> Result<ProductValue, DBError> {
         measure(&RDB_INSERT_TIME, table_id);
@@ -470,6 +732,40 @@ impl RelationalDB {
     }
 }
 
+/// Bootstraps a datastore from the newest snapshot that can be read and restored,
+/// falling back to older snapshots if newer ones turn out to be corrupt.
+///
+/// Snapshots taken past `max_commit_offset`, the end of the message log, are skipped,
+/// as new commits would otherwise be written to the log under offsets they already cover.
+fn restore_newest_snapshot(
+    snapshots: &SnapshotStore,
+    max_commit_offset: u64,
+) -> Result<Option<(Locking, Snapshot)>, DBError> {
+    for offset in snapshots.offsets()? {
+        if offset > max_commit_offset {
+            log::warn!(
+                "Skipping snapshot at commit offset {} past the end of the message log at {}",
+                offset,
+                max_commit_offset
+            );
+            continue;
+        }
+        let restored = snapshots.read(offset).and_then(|mut snapshot| {
+            let datastore = Locking::bootstrap()?;
+            datastore.restore_snapshot(std::mem::take(&mut snapshot.tables))?;
+            Ok((datastore, snapshot))
+        });
+        match restored {
+            Ok(restored) => {
+                log::debug!("Restored snapshot at commit offset {}", offset);
+                return Ok(Some(restored));
+            }
+            Err(err) => log::warn!("Failed to restore snapshot at commit offset {}: {}", offset, err),
+        }
+    }
+    Ok(None)
+}
+
 fn make_default_ostorage(in_memory: bool, path: impl AsRef<Path>) -> Result<Box<dyn ObjectDB + Send>, DBError> {
     Ok(if in_memory {
         Box::<MemoryObjectDB>::default()
@@ -479,6 +775,14 @@ fn make_default_ostorage(in_memory: bool, path: impl AsRef<Path>) -> Result<Box<
 }
 
 pub fn open_db(path: impl AsRef<Path>, in_memory: bool) -> Result<RelationalDB, DBError> {
+    open_db_with_durability(path, in_memory, Durability::default())
+}
+
+pub fn open_db_with_durability(
+    path: impl AsRef<Path>,
+    in_memory: bool,
+    durability: Durability,
+) -> Result<RelationalDB, DBError> {
     let path = path.as_ref();
     let mlog = if in_memory {
         None
@@ -486,7 +790,7 @@ pub fn open_db(path: impl AsRef<Path>, in_memory: bool) -> Result<RelationalDB,
         Some(Arc::new(Mutex::new(MessageLog::open(path.join("mlog"))?)))
     };
     let odb = Arc::new(Mutex::new(make_default_ostorage(in_memory, path.join("odb"))?));
-    let stdb = RelationalDB::open(path, mlog, odb)?;
+    let stdb = RelationalDB::open(path, mlog, odb, durability)?;
 
     Ok(stdb)
 }
@@ -522,19 +826,26 @@ mod tests {
     use crate::db::datastore::system_tables::ST_SEQUENCES_ID;
     use crate::db::datastore::traits::ColumnDef;
     use crate::db::datastore::traits::IndexDef;
+    use crate::db::datastore::traits::IndexId;
     use crate::db::datastore::traits::TableDef;
+    use crate::db::datastore::traits::{TxData, TxOp, TxRecord};
     use crate::db::message_log::MessageLog;
     use crate::db::relational_db::ST_TABLES_ID;
+    use crate::db::{Durability, GroupCommit};
 
-    use super::RelationalDB;
+    use super::{open_db, open_db_with_durability, RelationalDB};
+    use crate::db::commit_log::AppendedCommit;
+    use crate::db::datastore::traits::TableId;
     use crate::db::relational_db::make_default_ostorage;
     use crate::db::relational_db::tests_utils::make_test_db;
     use crate::error::{DBError, DatabaseError, IndexError};
     use spacetimedb_lib::auth::StAccess;
     use spacetimedb_lib::auth::StTableType;
+    use spacetimedb_lib::data_key::ToDataKey;
     use spacetimedb_lib::error::ResultTest;
-    use spacetimedb_lib::{AlgebraicType, AlgebraicValue, ProductType};
+    use spacetimedb_lib::{AlgebraicType, AlgebraicValue, DataKey, IndexType, ProductType, ProductValue};
     use spacetimedb_sats::product;
+    use tempdir::TempDir;
 
     #[test]
     fn test() -> ResultTest<()> {
@@ -568,7 +879,7 @@ mod tests {
             tmp_dir.path().join("odb"),
         )?));
 
-        match RelationalDB::open(tmp_dir.path(), mlog, odb) {
+        match RelationalDB::open(tmp_dir.path(), mlog, odb, Durability::default()) {
             Ok(_) => {
                 panic!("Allowed to open database twice")
             }
@@ -854,7 +1165,8 @@ mod tests {
             }],
             indexes: vec![IndexDef {
                 table_id: 0,
-                col_id: 0,
+                cols: vec![0],
+                index_type: IndexType::BTree,
                 name: "MyTable_my_col_idx".to_string(),
                 is_unique: false,
             }],
@@ -896,7 +1208,8 @@ mod tests {
             }],
             indexes: vec![IndexDef {
                 table_id: 0,
-                col_id: 0,
+                cols: vec![0],
+                index_type: IndexType::BTree,
                 name: "MyTable_my_col_idx".to_string(),
                 is_unique: true,
             }],
@@ -943,7 +1256,8 @@ mod tests {
             }],
             indexes: vec![IndexDef {
                 table_id: 0,
-                col_id: 0,
+                cols: vec![0],
+                index_type: IndexType::BTree,
                 name: "MyTable_my_col_idx".to_string(),
                 is_unique: true,
             }],
@@ -1006,19 +1320,22 @@ mod tests {
             indexes: vec![
                 IndexDef {
                     table_id: 0,
-                    col_id: 0,
+                    cols: vec![0],
+                    index_type: IndexType::BTree,
                     name: "MyTable_col1_idx".to_string(),
                     is_unique: true,
                 },
                 IndexDef {
                     table_id: 0,
-                    col_id: 2,
+                    cols: vec![2],
+                    index_type: IndexType::BTree,
                     name: "MyTable_col3_idx".to_string(),
                     is_unique: false,
                 },
                 IndexDef {
                     table_id: 0,
-                    col_id: 3,
+                    cols: vec![3],
+                    index_type: IndexType::BTree,
                     name: "MyTable_col4_idx".to_string(),
                     is_unique: true,
                 },
@@ -1076,7 +1393,8 @@ mod tests {
             }],
             indexes: vec![IndexDef {
                 table_id: 0,
-                col_id: 0,
+                cols: vec![0],
+                index_type: IndexType::BTree,
                 name: "MyTable_my_col_idx".to_string(),
                 is_unique: true,
             }],
@@ -1101,6 +1419,522 @@ mod tests {
         Ok(())
     }
 
+    fn my_table_rows(stdb: &RelationalDB, table_id: u32) -> ResultTest<Vec<i64>> {
+        let tx = stdb.begin_tx();
+        let mut rows = stdb
+            .iter(&tx, table_id)?
+            .map(|r| *r.view().elements[0].as_i64().unwrap())
+            .collect::<Vec<i64>>();
+        stdb.rollback_tx(tx);
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Appends a commit inserting `row` into `table_id` to the log, bypassing the datastore,
+    /// as if it had been written by an older version.
+    fn append_raw_insert(stdb: &RelationalDB, table_id: TableId, row: ProductValue) -> Result<(), DBError> {
+        let mut bytes = Vec::new();
+        row.encode(&mut bytes);
+        let tx_data = TxData {
+            records: vec![TxRecord {
+                op: TxOp::Insert(Arc::new(bytes)),
+                key: row.to_data_key(),
+                product_value: row,
+                table_id,
+            }],
+        };
+        stdb.commit_log.append_tx(&tx_data, &stdb.inner)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_index_with_single_column() -> ResultTest<()> {
+        let (stdb, tmp_dir) = make_test_db()?;
+
+        let mut tx = stdb.begin_tx();
+        let mut schema = TableDef::from(ProductType::from_iter([("my_col", AlgebraicType::I64)]));
+        schema.table_name = "MyTable".to_string();
+        let table_id = stdb.create_table(&mut tx, schema)?;
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(1)])?;
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(2)])?;
+        stdb.commit_tx(tx)?;
+
+        // A row of `st_indexes` as written before an index could span several columns.
+        let index_id = 1000;
+        let row = product![
+            AlgebraicValue::U32(index_id),
+            AlgebraicValue::U32(table_id),
+            AlgebraicValue::U32(0),
+            AlgebraicValue::String("MyTable_my_col_idx".to_string()),
+            AlgebraicValue::Bool(false)
+        ];
+        append_raw_insert(&stdb, ST_INDEXES_ID, row)?;
+        drop(stdb);
+
+        let stdb = open_db(&tmp_dir, false)?;
+        let mut tx = stdb.begin_tx();
+        let indexes = stdb.schema_for_table(&tx, table_id)?.indexes;
+        assert_eq!(indexes.len(), 1);
+        assert_eq!(indexes[0].index_id, index_id);
+        assert_eq!(indexes[0].cols, vec![0]);
+        assert_eq!(indexes[0].index_type, IndexType::BTree);
+        let rows = stdb
+            .iter_by_col_eq(&tx, table_id, 0, &AlgebraicValue::I64(2))?
+            .map(|r| *r.view().elements[0].as_i64().unwrap())
+            .collect::<Vec<i64>>();
+        assert_eq!(rows, vec![2]);
+
+        // The upgraded row can be dropped, and stays dropped after replaying again.
+        stdb.drop_index(&mut tx, IndexId(index_id))?;
+        stdb.commit_tx(tx)?;
+        drop(stdb);
+
+        let stdb = open_db(&tmp_dir, false)?;
+        let tx = stdb.begin_tx();
+        assert!(stdb.schema_for_table(&tx, table_id)?.indexes.is_empty());
+        stdb.rollback_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_index_without_type() -> ResultTest<()> {
+        let (stdb, tmp_dir) = make_test_db()?;
+
+        let mut tx = stdb.begin_tx();
+        let mut schema = TableDef::from(ProductType::from_iter([("my_col", AlgebraicType::I64)]));
+        schema.table_name = "MyTable".to_string();
+        let table_id = stdb.create_table(&mut tx, schema)?;
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(1)])?;
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(2)])?;
+        stdb.commit_tx(tx)?;
+
+        // A row of `st_indexes` as written before an index could be a hash index.
+        let index_id = 1000;
+        let row = product![
+            AlgebraicValue::U32(index_id),
+            AlgebraicValue::U32(table_id),
+            AlgebraicValue::ArrayOf(vec![0u32]),
+            AlgebraicValue::String("MyTable_my_col_idx".to_string()),
+            AlgebraicValue::Bool(false)
+        ];
+        append_raw_insert(&stdb, ST_INDEXES_ID, row)?;
+        drop(stdb);
+
+        let stdb = open_db(&tmp_dir, false)?;
+        let tx = stdb.begin_tx();
+        let indexes = stdb.schema_for_table(&tx, table_id)?.indexes;
+        assert_eq!(indexes.len(), 1);
+        assert_eq!(indexes[0].index_id, index_id);
+        assert_eq!(indexes[0].cols, vec![0]);
+        assert_eq!(indexes[0].index_type, IndexType::BTree);
+        let rows = stdb
+            .iter_by_col_range(&tx, table_id, 0, AlgebraicValue::I64(2)..)?
+            .map(|r| *r.view().elements[0].as_i64().unwrap())
+            .collect::<Vec<i64>>();
+        assert_eq!(rows, vec![2]);
+        stdb.rollback_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_restore() -> ResultTest<()> {
+        let (stdb, tmp_dir) = make_test_db()?;
+
+        let mut tx = stdb.begin_tx();
+        let schema = TableDef {
+            table_name: "MyTable".to_string(),
+            columns: vec![ColumnDef {
+                col_name: "my_col".to_string(),
+                col_type: AlgebraicType::I64,
+                is_autoinc: true,
+            }],
+            indexes: vec![],
+            table_type: StTableType::User,
+            table_access: StAccess::Public,
+        };
+        let table_id = stdb.create_table(&mut tx, schema)?;
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(0)])?;
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(0)])?;
+        stdb.commit_tx(tx)?;
+
+        assert_eq!(stdb.take_snapshot()?, Some(1));
+        drop(stdb);
+
+        let stdb = open_db(&tmp_dir, false)?;
+        assert_eq!(my_table_rows(&stdb, table_id)?, vec![1, 2]);
+
+        // The sequence continues where it left off.
+        let mut tx = stdb.begin_tx();
+        let row = stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(0)])?;
+        stdb.commit_tx(tx)?;
+        assert_eq!(row, product![AlgebraicValue::I64(3)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_replays_later_commits() -> ResultTest<()> {
+        let (stdb, tmp_dir) = make_test_db()?;
+
+        let mut tx = stdb.begin_tx();
+        let mut schema = TableDef::from(ProductType::from_iter([("my_col", AlgebraicType::I64)]));
+        schema.table_name = "MyTable".to_string();
+        let table_id = stdb.create_table(&mut tx, schema)?;
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(1)])?;
+        stdb.commit_tx(tx)?;
+        stdb.take_snapshot()?;
+
+        let mut tx = stdb.begin_tx();
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(2)])?;
+        stdb.delete_by_rel(&mut tx, table_id, vec![product![AlgebraicValue::I64(1)]])?;
+        stdb.commit_tx(tx)?;
+        let snapshot_offset = stdb.take_snapshot()?.unwrap();
+
+        let mut tx = stdb.begin_tx();
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(3)])?;
+        stdb.commit_tx(tx)?;
+        drop(stdb);
+
+        let stdb = open_db(&tmp_dir, false)?;
+        assert_eq!(my_table_rows(&stdb, table_id)?, vec![2, 3]);
+        drop(stdb);
+
+        // A corrupt snapshot falls back to the previous one.
+        let path = tmp_dir
+            .path()
+            .join("snapshots")
+            .join(format!("{:0>20}.snapshot", snapshot_offset));
+        let mut bytes = std::fs::read(&path)?;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, bytes)?;
+
+        let stdb = open_db(&tmp_dir, false)?;
+        assert_eq!(my_table_rows(&stdb, table_id)?, vec![2, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_matches_log_position() -> ResultTest<()> {
+        let (stdb, _tmp_dir) = make_test_db()?;
+
+        let mut tx = stdb.begin_tx();
+        let mut schema = TableDef::from(ProductType::from_iter([("my_col", AlgebraicType::I64)]));
+        schema.table_name = "MyTable".to_string();
+        let table_id = stdb.create_table(&mut tx, schema)?;
+        stdb.commit_tx(tx)?;
+        let (_, first_insert_offset, _) = stdb.commit_log.position();
+
+        // Every commit inserts one row, so a snapshot taken while committing
+        // must hold as many rows as there are commits before its offset.
+        let writer = {
+            let stdb = stdb.clone();
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    let mut tx = stdb.begin_tx();
+                    stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(i)])
+                        .unwrap();
+                    stdb.commit_tx(tx).unwrap();
+                }
+            })
+        };
+        let snapshots = stdb.snapshots.clone().unwrap();
+        for _ in 0..20 {
+            let offset = stdb.take_snapshot()?.unwrap();
+            let snapshot = snapshots.read(offset)?;
+            let table = snapshot.tables.iter().find(|t| t.table_id == table_id).unwrap();
+            assert_eq!(table.rows.len() as u64, offset - first_insert_offset);
+        }
+        writer.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_column() -> ResultTest<()> {
+        let (stdb, tmp_dir) = make_test_db()?;
+
+        let mut tx = stdb.begin_tx();
+        let schema = TableDef {
+            table_name: "MyTable".to_string(),
+            columns: vec![ColumnDef {
+                col_name: "my_col".to_string(),
+                col_type: AlgebraicType::I64,
+                is_autoinc: false,
+            }],
+            indexes: vec![],
+            table_type: StTableType::User,
+            table_access: StAccess::Public,
+        };
+        let table_id = stdb.create_table(&mut tx, schema)?;
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(1)])?;
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(2)])?;
+        stdb.commit_tx(tx)?;
+
+        let mut tx = stdb.begin_tx();
+        let column = ColumnDef {
+            col_name: "my_str".to_string(),
+            col_type: AlgebraicType::String,
+            is_autoinc: false,
+        };
+        stdb.add_column(&mut tx, table_id, column, AlgebraicValue::String("".into()))?;
+        stdb.insert(
+            &mut tx,
+            table_id,
+            product![AlgebraicValue::I64(3), AlgebraicValue::String("x".into())],
+        )?;
+        stdb.commit_tx(tx)?;
+        drop(stdb);
+
+        // The new column and the backfilled rows survive a replay of the log.
+        let stdb = open_db(&tmp_dir, false)?;
+        let tx = stdb.begin_tx();
+        let schema = stdb.schema_for_table(&tx, table_id)?;
+        assert_eq!(schema.columns.len(), 2);
+        assert_eq!(schema.columns[1].col_name, "my_str");
+        let mut rows = stdb.iter(&tx, table_id)?.map(|r| r.view().clone()).collect::<Vec<_>>();
+        rows.sort();
+        stdb.rollback_tx(tx);
+        assert_eq!(
+            rows,
+            vec![
+                product![AlgebraicValue::I64(1), AlgebraicValue::String("".into())],
+                product![AlgebraicValue::I64(2), AlgebraicValue::String("".into())],
+                product![AlgebraicValue::I64(3), AlgebraicValue::String("x".into())],
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> ResultTest<()> {
+        let tmp_dir = TempDir::new("stdb_test")?;
+        let mlog = Some(Arc::new(Mutex::new(MessageLog::open(tmp_dir.path().join("mlog"))?)));
+        let odb = Arc::new(Mutex::new(make_default_ostorage(false, tmp_dir.path().join("odb"))?));
+        let stdb = RelationalDB::open(tmp_dir.path(), mlog, odb.clone(), Durability::default())?;
+
+        // Rows this large are stored in the object store rather than inline in their `RowId`.
+        let row = |c: char| product![AlgebraicValue::String(c.to_string().repeat(64))];
+        let object = |c: char| match row(c).to_data_key() {
+            DataKey::Hash(hash) => hash,
+            DataKey::Data(_) => unreachable!(),
+        };
+
+        let mut tx = stdb.begin_tx();
+        let mut schema = TableDef::from(ProductType::from_iter([("my_col", AlgebraicType::String)]));
+        schema.table_name = "MyTable".to_string();
+        let table_id = stdb.create_table(&mut tx, schema)?;
+        stdb.insert(&mut tx, table_id, row('a'))?;
+        stdb.insert(&mut tx, table_id, row('b'))?;
+        stdb.commit_tx(tx)?;
+
+        let mut tx = stdb.begin_tx();
+        stdb.delete_by_rel(&mut tx, table_id, vec![row('a')])?;
+        stdb.commit_tx(tx)?;
+        assert_eq!(stdb.compact()?, None);
+        stdb.take_snapshot()?;
+
+        let mut tx = stdb.begin_tx();
+        stdb.insert(&mut tx, table_id, row('c'))?;
+        stdb.commit_tx(tx)?;
+        let mut tx = stdb.begin_tx();
+        stdb.delete_by_rel(&mut tx, table_id, vec![row('c')])?;
+        stdb.commit_tx(tx)?;
+
+        let stats = stdb.compact()?.unwrap();
+        assert_eq!(stats.segments_removed, 0);
+        {
+            let odb = odb.lock().unwrap();
+            // `a` was deleted before the snapshot, while replaying from the snapshot still inserts `c`.
+            assert!(odb.get(object('a')).is_none());
+            assert!(odb.get(object('b')).is_some());
+            assert!(odb.get(object('c')).is_some());
+        }
+        drop(stdb);
+        drop(odb);
+
+        let stdb = open_db(&tmp_dir, false)?;
+        let tx = stdb.begin_tx();
+        let rows = stdb.iter(&tx, table_id)?.map(|r| r.view().clone()).collect::<Vec<_>>();
+        stdb.rollback_tx(tx);
+        assert_eq!(rows, vec![row('b')]);
+
+        Ok(())
+    }
+
+    // Check that compacting while other threads commit neither deadlocks nor loses objects.
+    #[test]
+    fn test_compact_while_committing() -> ResultTest<()> {
+        let tmp_dir = TempDir::new("stdb_test")?;
+        let stdb = open_db(&tmp_dir, false)?;
+        let row = |i: usize| product![AlgebraicValue::String(i.to_string().repeat(64))];
+
+        let mut tx = stdb.begin_tx();
+        let mut schema = TableDef::from(ProductType::from_iter([("my_col", AlgebraicType::String)]));
+        schema.table_name = "MyTable".to_string();
+        let table_id = stdb.create_table(&mut tx, schema)?;
+        stdb.commit_tx(tx)?;
+
+        const COMMITS: usize = 200;
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let committer = {
+            let stdb = stdb.clone();
+            let done_tx = done_tx.clone();
+            std::thread::spawn(move || {
+                for i in 0..COMMITS {
+                    let mut tx = stdb.begin_tx();
+                    stdb.insert(&mut tx, table_id, row(i)).unwrap();
+                    stdb.commit_tx(tx).unwrap();
+                }
+                done_tx.send(()).unwrap();
+            })
+        };
+        let compactor = {
+            let stdb = stdb.clone();
+            std::thread::spawn(move || {
+                for _ in 0..20 {
+                    stdb.take_snapshot().unwrap();
+                    stdb.compact().unwrap();
+                }
+                done_tx.send(()).unwrap();
+            })
+        };
+        for _ in 0..2 {
+            done_rx
+                .recv_timeout(std::time::Duration::from_secs(60))
+                .expect("compacting and committing deadlocked");
+        }
+        committer.join().unwrap();
+        compactor.join().unwrap();
+        drop(stdb);
+
+        let stdb = open_db(&tmp_dir, false)?;
+        let tx = stdb.begin_tx();
+        let rows = stdb.iter(&tx, table_id)?.count();
+        stdb.rollback_tx(tx);
+        assert_eq!(rows, COMMITS);
+
+        Ok(())
+    }
+
+    fn my_table(stdb: &RelationalDB) -> ResultTest<u32> {
+        let mut tx = stdb.begin_tx();
+        let schema = TableDef {
+            table_name: "MyTable".to_string(),
+            columns: vec![ColumnDef {
+                col_name: "my_col".to_string(),
+                col_type: AlgebraicType::I64,
+                is_autoinc: false,
+            }],
+            indexes: vec![],
+            table_type: StTableType::User,
+            table_access: StAccess::Public,
+        };
+        let table_id = stdb.create_table(&mut tx, schema)?;
+        stdb.commit_tx(tx)?;
+        Ok(table_id)
+    }
+
+    fn insert_my_table(stdb: &RelationalDB, table_id: u32, value: i64) -> ResultTest<AppendedCommit> {
+        let mut tx = stdb.begin_tx();
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(value)])?;
+        let (_, commit) = stdb.commit_tx(tx)?.expect("transaction should commit");
+        Ok(commit.expect("insert should be written to the log"))
+    }
+
+    #[test]
+    fn test_finish_tx_write_conflict() -> ResultTest<()> {
+        let (stdb, _tmp_dir) = make_test_db()?;
+        let table_id = my_table(&stdb)?;
+
+        let mut tx = stdb.begin_tx();
+        stdb.insert(&mut tx, table_id, product![AlgebraicValue::I64(1)])?;
+        insert_my_table(&stdb, table_id, 2)?;
+        let res = stdb.finish_tx(tx, Ok::<_, DBError>(()));
+        assert!(matches!(res, Err(DBError::Database(DatabaseError::WriteConflict))));
+        assert_eq!(my_table_rows(&stdb, table_id)?, vec![2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_durability_sync_and_async() -> ResultTest<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        for durability in [Durability::Sync, Durability::Async] {
+            let tmp_dir = TempDir::new("stdb_test")?;
+            let stdb = open_db_with_durability(&tmp_dir, false, durability)?;
+            let table_id = my_table(&stdb)?;
+
+            let commit = insert_my_table(&stdb, table_id, 1)?;
+            assert_eq!(commit.commit_offset, 1);
+            // An async commit is only durable once the background sync reaches it.
+            assert_eq!(commit.is_durable(), durability == Durability::Sync, "{durability:?}");
+            assert!(runtime.block_on(commit.durable()), "{durability:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_durability_group_commit() -> ResultTest<()> {
+        let tmp_dir = TempDir::new("stdb_test")?;
+        // A window that never fills up on its own.
+        let durability = Durability::Group(GroupCommit {
+            max_delay_ms: 60_000,
+            max_bytes: u64::MAX,
+        });
+        let stdb = open_db_with_durability(&tmp_dir, false, durability)?;
+        let table_id = my_table(&stdb)?;
+
+        let first = insert_my_table(&stdb, table_id, 1)?;
+        let second = insert_my_table(&stdb, table_id, 2)?;
+        assert!(!first.is_durable());
+        assert!(!second.is_durable());
+
+        stdb.commit_log.sync_all()?;
+        assert!(first.is_durable());
+        assert!(second.is_durable());
+
+        // Commits which are not synced yet are synced when the database is closed.
+        insert_my_table(&stdb, table_id, 3)?;
+        drop(stdb);
+        let stdb = open_db(&tmp_dir, false)?;
+        assert_eq!(my_table_rows(&stdb, table_id)?, vec![1, 2, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_durability_group_commit_window() -> ResultTest<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+
+        // The time window closes on its own.
+        let tmp_dir = TempDir::new("stdb_test")?;
+        let durability = Durability::Group(GroupCommit {
+            max_delay_ms: 5,
+            max_bytes: u64::MAX,
+        });
+        let stdb = open_db_with_durability(&tmp_dir, false, durability)?;
+        let table_id = my_table(&stdb)?;
+        let commit = insert_my_table(&stdb, table_id, 1)?;
+        assert!(runtime.block_on(commit.durable()));
+
+        // A full byte window is synced right away.
+        let tmp_dir = TempDir::new("stdb_test")?;
+        let durability = Durability::Group(GroupCommit {
+            max_delay_ms: 60_000,
+            max_bytes: 1,
+        });
+        let stdb = open_db_with_durability(&tmp_dir, false, durability)?;
+        let table_id = my_table(&stdb)?;
+        let commit = insert_my_table(&stdb, table_id, 1)?;
+        assert!(runtime.block_on(commit.durable()));
+
+        Ok(())
+    }
+
     // #[test]
     // fn test_rename_column() -> ResultTest<()> {
     //     let (mut stdb, _tmp_dir) = make_test_db()?;
@@ -0,0 +1,274 @@
+use crate::error::DBError;
+use crate::hash::{hash_bytes, Hash, HASH_SIZE};
+use spacetimedb_sats::buffer::{BufReader, BufWriter, DecodeError};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The number of snapshots kept on disk.
+/// Older snapshots are only kept around so that we can fall back to them
+/// in case the newest one turns out to be corrupt.
+const SNAPSHOTS_TO_KEEP: usize = 2;
+
+const SNAPSHOT_EXT: &str = "snapshot";
+
+/// A point-in-time copy of the committed state of a database,
+/// including all system tables and the in-memory sequence state.
+///
+/// A snapshot taken at `commit_offset` contains the effects of every commit
+/// before `commit_offset`, so restoring it only requires replaying the
+/// commits from `commit_offset` onwards.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The hash of the last commit contained in this snapshot.
+    pub parent_commit_hash: Option<Hash>,
+    /// The offset of the first commit *not* contained in this snapshot.
+    pub commit_offset: u64,
+    /// The offset of the first transaction *not* contained in this snapshot.
+    pub min_tx_offset: u64,
+    pub tables: Vec<TableSnapshot>,
+    pub sequences: Vec<SequenceSnapshot>,
+}
+
+/// The committed rows of a table, each encoded as BSATN.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TableSnapshot {
+    pub table_id: u32,
+    pub rows: Vec<Vec<u8>>,
+}
+
+/// The in-memory value of a sequence.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SequenceSnapshot {
+    pub sequence_id: u32,
+    pub value: i128,
+}
+
+// snapshot: <parent_commit_hash(1|33)><commit_offset(8)><min_tx_offset(8)>
+//           <table_count(4)>[<table_id(4)><row_count(4)>[<row_len(4)><row>]*]*
+//           <sequence_count(4)>[<sequence_id(4)><value(16)>]*
+//           <checksum(32)>
+impl Snapshot {
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        let start = bytes.len();
+
+        match self.parent_commit_hash {
+            None => bytes.put_u8(0),
+            Some(hash) => {
+                bytes.put_u8(1);
+                bytes.put_slice(&hash.data);
+            }
+        }
+        bytes.put_u64(self.commit_offset);
+        bytes.put_u64(self.min_tx_offset);
+
+        bytes.put_u32(self.tables.len() as u32);
+        for table in &self.tables {
+            bytes.put_u32(table.table_id);
+            bytes.put_u32(table.rows.len() as u32);
+            for row in &table.rows {
+                bytes.put_u32(row.len() as u32);
+                bytes.put_slice(row);
+            }
+        }
+
+        bytes.put_u32(self.sequences.len() as u32);
+        for sequence in &self.sequences {
+            bytes.put_u32(sequence.sequence_id);
+            bytes.put_i128(sequence.value);
+        }
+
+        let checksum = hash_bytes(&bytes[start..]);
+        bytes.put_slice(&checksum.data);
+    }
+
+    /// Decodes a snapshot, verifying its checksum first.
+    pub fn decode(bytes: impl AsRef<[u8]>) -> Result<Self, DecodeError> {
+        let bytes = bytes.as_ref();
+        if bytes.len() < HASH_SIZE {
+            return Err(DecodeError::BufferLength);
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - HASH_SIZE);
+        if hash_bytes(body).as_slice() != checksum {
+            return Err(DecodeError::Other("snapshot checksum mismatch".into()));
+        }
+
+        let bytes = &mut &body[..];
+        let parent_commit_hash = match bytes.get_u8()? {
+            0 => None,
+            1 => Some(Hash::from_arr(&bytes.get_array()?)),
+            _ => return Err(DecodeError::InvalidTag),
+        };
+        let commit_offset = bytes.get_u64()?;
+        let min_tx_offset = bytes.get_u64()?;
+
+        let table_count = bytes.get_u32()?;
+        let mut tables = Vec::with_capacity(table_count as usize);
+        for _ in 0..table_count {
+            let table_id = bytes.get_u32()?;
+            let row_count = bytes.get_u32()?;
+            let mut rows = Vec::with_capacity(row_count as usize);
+            for _ in 0..row_count {
+                let row_len = bytes.get_u32()?;
+                rows.push(bytes.get_slice(row_len as usize)?.to_vec());
+            }
+            tables.push(TableSnapshot { table_id, rows });
+        }
+
+        let sequence_count = bytes.get_u32()?;
+        let mut sequences = Vec::with_capacity(sequence_count as usize);
+        for _ in 0..sequence_count {
+            let sequence_id = bytes.get_u32()?;
+            let value = bytes.get_i128()?;
+            sequences.push(SequenceSnapshot { sequence_id, value });
+        }
+
+        if bytes.remaining() != 0 {
+            return Err(DecodeError::Other("trailing bytes after snapshot".into()));
+        }
+
+        Ok(Self {
+            parent_commit_hash,
+            commit_offset,
+            min_tx_offset,
+            tables,
+            sequences,
+        })
+    }
+}
+
+/// A directory of snapshots, each named after the commit offset it was taken at.
+#[derive(Debug)]
+pub struct SnapshotStore {
+    root: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, DBError> {
+        let root = root.as_ref();
+        fs::create_dir_all(root)?;
+        Ok(Self { root: root.to_owned() })
+    }
+
+    fn path_for_offset(&self, commit_offset: u64) -> PathBuf {
+        self.root.join(format!("{:0>20}.{}", commit_offset, SNAPSHOT_EXT))
+    }
+
+    /// Returns the commit offsets of all snapshots on disk, newest first.
+    pub fn offsets(&self) -> Result<Vec<u64>, DBError> {
+        let mut offsets: Vec<u64> = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |ext| ext != SNAPSHOT_EXT) {
+                continue;
+            }
+            if let Some(offset) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()) {
+                offsets.push(offset);
+            }
+        }
+        offsets.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(offsets)
+    }
+
+    /// Reads and validates the snapshot taken at `commit_offset`.
+    pub fn read(&self, commit_offset: u64) -> Result<Snapshot, DBError> {
+        let bytes = fs::read(self.path_for_offset(commit_offset))?;
+        Ok(Snapshot::decode(bytes)?)
+    }
+
+    /// Durably writes `snapshot` to disk and removes snapshots
+    /// that are no longer needed.
+    ///
+    /// The snapshot is written to a temporary file which is renamed into place
+    /// once it has been synced, so a crash never leaves a partially written
+    /// snapshot behind under a valid name.
+    pub fn write(&self, snapshot: &Snapshot) -> Result<(), DBError> {
+        let mut bytes = Vec::new();
+        snapshot.encode(&mut bytes);
+
+        let path = self.path_for_offset(snapshot.commit_offset);
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp_path)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        #[cfg(target_family = "unix")]
+        File::open(&self.root)?.sync_all()?;
+
+        log::debug!(
+            "Wrote snapshot at commit offset {} ({} bytes)",
+            snapshot.commit_offset,
+            bytes.len()
+        );
+
+        for offset in self.offsets()?.into_iter().skip(SNAPSHOTS_TO_KEEP) {
+            fs::remove_file(self.path_for_offset(offset))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spacetimedb_lib::error::ResultTest;
+    use tempdir::TempDir;
+
+    fn snapshot(commit_offset: u64) -> Snapshot {
+        Snapshot {
+            parent_commit_hash: Some(hash_bytes(b"parent")),
+            commit_offset,
+            min_tx_offset: commit_offset * 2,
+            tables: vec![
+                TableSnapshot {
+                    table_id: 0,
+                    rows: vec![vec![1, 2, 3], vec![]],
+                },
+                TableSnapshot {
+                    table_id: 4,
+                    rows: vec![],
+                },
+            ],
+            sequences: vec![SequenceSnapshot {
+                sequence_id: 1,
+                value: -42,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() -> ResultTest<()> {
+        let snapshot = snapshot(7);
+        let mut bytes = Vec::new();
+        snapshot.encode(&mut bytes);
+        assert_eq!(Snapshot::decode(&bytes)?, snapshot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_checksum() {
+        let mut bytes = Vec::new();
+        snapshot(7).encode(&mut bytes);
+        bytes[10] ^= 0xff;
+        assert!(Snapshot::decode(&bytes).is_err());
+        assert!(Snapshot::decode(&bytes[..HASH_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_store_keeps_newest() -> ResultTest<()> {
+        let tmp_dir = TempDir::new("snapshot_test")?;
+        let store = SnapshotStore::open(tmp_dir.path())?;
+        for offset in [3, 10, 42] {
+            store.write(&snapshot(offset))?;
+        }
+        assert_eq!(store.offsets()?, vec![42, 10]);
+        assert_eq!(store.read(42)?, snapshot(42));
+        Ok(())
+    }
+}
@@ -8,11 +8,12 @@ use spacetimedb_lib::{PrimaryKey, ProductValue};
 use spacetimedb_sats::product_value::InvalidFieldError;
 use spacetimedb_sats::satn::Satn;
 use spacetimedb_sats::AlgebraicValue;
-use spacetimedb_vm::errors::{ErrorKind, ErrorLang, ErrorVm};
+use spacetimedb_vm::errors::{ErrorKind, ErrorLang, ErrorType, ErrorVm};
 use spacetimedb_vm::expr::Crud;
 use std::num::ParseIntError;
 use std::path::PathBuf;
 use std::sync::{MutexGuard, PoisonError};
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -56,6 +57,8 @@ pub enum TableError {
 pub enum IndexError {
     #[error("Index not found: {0:?}")]
     NotFound(IndexId),
+    #[error("Index with name `{0}` not found.")]
+    NameNotFound(String),
     #[error("Index already exist: {0:?}: {1}")]
     IndexAlreadyExists(IndexDef, String),
     #[error("Column not found: {0:?}")]
@@ -83,6 +86,12 @@ pub enum SubscriptionError {
     Empty,
     #[error("Queries with side effects not allowed: {0:?}")]
     SideEffect(Crud),
+    #[error("Unsupported in subscriptions: {0}")]
+    Unsupported(String),
+    #[error("Query id {0} is already in use")]
+    QueryIdInUse(u32),
+    #[error("Query id {0} not found")]
+    QueryIdNotFound(u32),
 }
 
 #[derive(Error, Debug)]
@@ -104,6 +113,8 @@ pub enum PlanError {
     AmbiguousField { field: String, found: Vec<FieldName> },
     #[error("Plan error: `{0}`")]
     Unstructured(String),
+    #[error("Invalid parameter `{param}`: {error}")]
+    InvalidParam { param: String, error: String },
     #[error("Internal DBError: `{0}`")]
     DatabaseInternal(Box<DBError>),
     #[error("Relation Error: `{0}`")]
@@ -112,12 +123,34 @@ pub enum PlanError {
     VmError(#[from] ErrorVm),
 }
 
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SessionError {
+    #[error("SQL session `{0}` not found")]
+    NotFound(u64),
+    #[error("The transaction of SQL session `{id}` was rolled back after being idle for {idle:?}")]
+    Expired { id: u64, idle: Duration },
+    #[error("Only the owner of the database can keep a transaction open across requests")]
+    OwnerRequired,
+    #[error("There is already a transaction in progress")]
+    AlreadyInTransaction,
+    #[error("There is no transaction in progress")]
+    NoTransaction,
+    #[error("Savepoint `{0}` does not exist")]
+    SavepointNotFound(String),
+    #[error("The transaction conflicted with a concurrent one and was rolled back")]
+    WriteConflict,
+}
+
 #[derive(Error, Debug)]
 pub enum DatabaseError {
     #[error("Database instance not found: {0}")]
     NotFound(u64),
     #[error("Database is already opened. Path:`{0}`. Error:{1}")]
     DatabasedOpened(PathBuf, anyhow::Error),
+    #[error("Message log was truncated before commit offset {0}, but no snapshot to restore from was found")]
+    MissingSnapshot(u64),
+    #[error("The transaction conflicted with a concurrent one and was rolled back")]
+    WriteConflict,
 }
 
 #[derive(Error, Debug)]
@@ -157,6 +190,8 @@ pub enum DBError {
     Subscription(#[from] SubscriptionError),
     #[error("ClientError: {0}")]
     Client(#[from] ClientError),
+    #[error("SessionError: {0}")]
+    Session(#[from] SessionError),
     #[error("SqlParserError: {error}, executing: `{sql}`")]
     SqlParser {
         sql: String,
@@ -191,6 +226,12 @@ impl From<DBError> for PlanError {
     }
 }
 
+impl From<ErrorType> for PlanError {
+    fn from(err: ErrorType) -> Self {
+        PlanError::VmError(err.into())
+    }
+}
+
 impl<'a, T: ?Sized + 'a> From<PoisonError<std::sync::MutexGuard<'a, T>>> for DBError {
     fn from(err: PoisonError<MutexGuard<'_, T>>) -> Self {
         DBError::MessageLogPoisoned(err.to_string())
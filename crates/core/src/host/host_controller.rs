@@ -6,7 +6,7 @@ use anyhow::Context;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::fmt;
-use std::ops::Sub;
+use std::ops::{Add, Sub};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -110,6 +110,14 @@ impl Sub for EnergyQuanta {
     }
 }
 
+impl Add for EnergyDiff {
+    type Output = EnergyDiff;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        EnergyDiff(self.0 + rhs.0)
+    }
+}
+
 impl fmt::Debug for EnergyDiff {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)?;
@@ -129,6 +137,7 @@ pub enum ReducerOutcome {
     Committed,
     Failed(String),
     BudgetExceeded,
+    WriteConflict,
 }
 
 impl ReducerOutcome {
@@ -137,6 +146,9 @@ impl ReducerOutcome {
             Self::Committed => Ok(()),
             Self::Failed(e) => Err(anyhow::anyhow!(e)),
             Self::BudgetExceeded => Err(anyhow::anyhow!("reducer ran out of energy")),
+            Self::WriteConflict => Err(anyhow::anyhow!(
+                "reducer's transaction conflicted with concurrent transactions"
+            )),
         }
     }
 }
@@ -147,11 +159,13 @@ impl From<&EventStatus> for ReducerOutcome {
             EventStatus::Committed(_) => ReducerOutcome::Committed,
             EventStatus::Failed(e) => ReducerOutcome::Failed(e.clone()),
             EventStatus::OutOfEnergy => ReducerOutcome::BudgetExceeded,
+            EventStatus::WriteConflict => ReducerOutcome::WriteConflict,
         }
     }
 }
 
 pub struct UpdateOutcome {
+    /// The module host of the new module, which has already exited if the update failed.
     pub module_host: ModuleHost,
     pub update_result: UpdateDatabaseResult,
 }
@@ -183,14 +197,33 @@ impl HostController {
         Ok(())
     }
 
+    /// Starts the module of `module_host_context` and updates the database to it.
+    ///
+    /// The module host of the previous module keeps running, and is only replaced
+    /// by the new one if the update succeeds.
     pub async fn update_module_host(
         &self,
         module_host_context: ModuleHostContext,
     ) -> Result<UpdateOutcome, anyhow::Error> {
-        let module_host = self.spawn_module_host(module_host_context).await?;
+        let key = module_host_context.dbic.database_instance_id;
+        let (module_host, start_module, start_scheduler) =
+            tokio::task::block_in_place(|| Self::make_module_host(module_host_context, self.energy_monitor.clone()))?;
+        start_module.start();
         // TODO: see init_module_host
-        let update_result = module_host.update_database().await?;
+        let update_result = match module_host.update_database().await {
+            Ok(update_result) => update_result,
+            Err(err) => {
+                module_host.exit().await;
+                return Err(err);
+            }
+        };
 
+        if update_result.is_ok() {
+            self.install_module_host(key, &module_host).await;
+            start_scheduler.start(&module_host)?;
+        } else {
+            module_host.exit().await;
+        }
         Ok(UpdateOutcome {
             module_host,
             update_result,
@@ -219,10 +252,7 @@ impl HostController {
         let (module_host, start_module, start_scheduler) =
             tokio::task::block_in_place(|| Self::make_module_host(module_host_context, self.energy_monitor.clone()))?;
 
-        let old_module = self.modules.lock().unwrap().insert(key, module_host.clone());
-        if let Some(old_module) = old_module {
-            old_module.exit().await
-        }
+        self.install_module_host(key, &module_host).await;
         start_module.start();
         start_scheduler.start(&module_host)?;
 
@@ -246,6 +276,15 @@ impl HostController {
         Ok((module_host, module_starter, mhc.scheduler_starter))
     }
 
+    /// Makes `module_host` the module host of database instance `key`,
+    /// stopping the one it replaces.
+    async fn install_module_host(&self, key: u64, module_host: &ModuleHost) {
+        let old_module = self.modules.lock().unwrap().insert(key, module_host.clone());
+        if let Some(old_module) = old_module {
+            old_module.exit().await
+        }
+    }
+
     /// Request a list of all describable entities in a module.
     pub fn catalog(&self, instance_id: u64) -> Result<Catalog, anyhow::Error> {
         let module_host = self.get_module_host(instance_id)?;
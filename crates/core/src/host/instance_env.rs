@@ -1,6 +1,6 @@
 use parking_lot::{Mutex, MutexGuard};
 use prometheus::HistogramVec;
-use spacetimedb_lib::{bsatn, ProductValue};
+use spacetimedb_lib::{bsatn, IndexType, ProductValue};
 use std::ops::DerefMut;
 use std::sync::Arc;
 use std::time::SystemTime;
@@ -304,12 +304,11 @@ impl InstanceEnv {
     /// on a product of the given columns in `col_ids`,
     /// in the table identified by `table_id`.
     ///
-    /// Currently only single-column-indices are supported
-    /// and they may only be of the btree index type.
+    /// The `index_type` is `0` for a btree index and `1` for a hash index.
+    /// An index on a single column is unique when the column has a unique constraint,
+    /// while an index on several columns is never unique.
     ///
-    /// The `table_name` is used together with the column ids to construct the name of the index.
-    /// As only single-column-indices are supported right now,
-    /// the name will be in the format `{table_name}_{col_id}`.
+    /// Returns an error when `col_ids` is empty or refers to a column that doesn't exist.
     #[tracing::instrument(skip_all)]
     pub fn create_index(
         &self,
@@ -323,30 +322,28 @@ impl InstanceEnv {
         let stdb = &*self.dbic.relational_db;
         let tx = &mut *self.get_tx()?;
 
-        // TODO(george) This check should probably move towards src/db/index, but right
-        // now the API is pretty hardwired towards btrees.
-        //
         // TODO(george) Dedup the constant here.
-        match index_type {
-            0 => (),
-            1 => todo!("Hash indexes not yet supported"),
+        let ty = match index_type {
+            0 => IndexType::BTree,
+            1 => IndexType::Hash,
             _ => return Err(NodesError::BadIndexType(index_type)),
         };
 
-        // TODO(george) The index API right now only allows single column indexes.
-        let col_id = match &*col_ids {
-            [id] => *id as u32,
-            _ => todo!("Multi-column indexes are not yet supported"),
+        let cols: Vec<u32> = col_ids.iter().map(|col_id| *col_id as u32).collect();
+        let mut attrs = Vec::with_capacity(cols.len());
+        for col_id in &cols {
+            attrs.push(stdb.column_attrs(tx, table_id, *col_id)?.ok_or(NodesError::BadColumn)?);
+        }
+        let is_unique = match *attrs {
+            [] => return Err(NodesError::BadColumn),
+            [attr] => attr.is_unique(),
+            _ => false,
         };
 
-        let is_unique = stdb
-            .column_attrs(tx, table_id, col_id)?
-            .expect("invalid col_id")
-            .is_unique();
-
         let index = IndexDef {
             table_id,
-            col_id,
+            cols,
+            index_type: ty,
             name: index_name.clone(),
             is_unique,
         };
@@ -482,7 +479,7 @@ impl InstanceEnv {
         .map_err(NodesError::DecodeFilter)?;
         let q = spacetimedb_vm::dsl::query(&schema).with_select(filter_to_column_op(&schema.table_name, filter));
         //TODO: How pass the `caller` here?
-        let p = &mut DbProgram::new(stdb, tx, AuthCtx::for_current(self.dbic.identity));
+        let p = &mut DbProgram::new(stdb, tx.into(), AuthCtx::for_current(self.dbic.identity));
         let results = match spacetimedb_vm::eval::run_ast(p, q.into()) {
             Code::Table(table) => table,
             _ => unreachable!("query should always return a table"),
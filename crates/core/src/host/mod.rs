@@ -13,6 +13,7 @@ mod host_controller;
 pub(crate) mod module_host;
 pub use module_host::{UpdateDatabaseError, UpdateDatabaseResult, UpdateDatabaseSuccess};
 pub mod scheduler;
+mod schema_migration;
 mod wasmer;
 
 // Visible for integration testing.
@@ -103,7 +104,10 @@ pub struct InvalidReducerArguments {
 
 pub use module_host::{EntityDef, ReducerCallError};
 
-fn from_json_seed<'de, T: serde::de::DeserializeSeed<'de>>(s: &'de str, seed: T) -> anyhow::Result<T::Value> {
+pub(crate) fn from_json_seed<'de, T: serde::de::DeserializeSeed<'de>>(
+    s: &'de str,
+    seed: T,
+) -> anyhow::Result<T::Value> {
     let mut de = serde_json::Deserializer::from_str(s);
     let mut track = serde_path_to_error::Track::new();
     let out = seed
@@ -1,6 +1,8 @@
 use super::{ArgsTuple, EnergyDiff, InvalidReducerArguments, ReducerArgs, ReducerCallResult, Timestamp};
-use crate::client::ClientConnectionSender;
+use crate::client::ClientCall;
 use crate::database_logger::LogLevel;
+use crate::db::commit_log::AppendedCommit;
+use crate::db::datastore::locking_tx_datastore::TxId;
 use crate::db::datastore::traits::{TableId, TxData, TxOp};
 use crate::db::relational_db::RelationalDB;
 use crate::error::DBError;
@@ -11,6 +13,7 @@ use crate::protobuf::client_api::{table_row_operation, SubscriptionUpdate, Table
 use crate::subscription::module_subscription_actor::ModuleSubscriptionManager;
 use base64::{engine::general_purpose::STANDARD as BASE_64_STD, Engine as _};
 use indexmap::IndexMap;
+use spacetimedb_lib::name::SchemaChange;
 use spacetimedb_lib::{ReducerDef, TableDef};
 use spacetimedb_sats::{ProductValue, Typespace, WithTypespace};
 use std::collections::HashMap;
@@ -34,8 +37,9 @@ impl DatabaseUpdate {
 
     pub fn from_writes(stdb: &RelationalDB, tx_data: &TxData) -> Self {
         let mut map: HashMap<TableId, Vec<TableOp>> = HashMap::new();
-        //TODO: This should be wrapped with .auto_commit
-        let tx = stdb.begin_tx();
+        // The names are read from a snapshot, so as not to wait for a transaction
+        // that is holding the database, e.g. one kept open by a `SQL` session.
+        let tx = stdb.begin_read_tx();
         for record in tx_data.records.iter() {
             let op = match record.op {
                 TxOp::Delete => 0,
@@ -64,7 +68,7 @@ impl DatabaseUpdate {
             let table_name = if let Some(name) = table_name_map.get(&table_id) {
                 name.clone()
             } else {
-                let table_name = stdb.table_name_from_id(&tx, table_id.0).unwrap().unwrap();
+                let table_name = stdb.table_name_from_id_tx(&tx, table_id.0).unwrap().unwrap();
                 table_name_map.insert(table_id, table_name.clone());
                 table_name
             };
@@ -74,7 +78,7 @@ impl DatabaseUpdate {
                 ops: table_row_operations,
             });
         }
-        stdb.rollback_tx(tx);
+        stdb.release_tx(tx);
 
         DatabaseUpdate { tables: table_updates }
     }
@@ -160,6 +164,9 @@ pub enum EventStatus {
     Committed(DatabaseUpdate),
     Failed(String),
     OutOfEnergy,
+    /// The reducer's transaction kept conflicting with concurrently committed transactions,
+    /// so it was rolled back after running the reducer the maximum number of times.
+    WriteConflict,
 }
 
 impl EventStatus {
@@ -185,6 +192,13 @@ pub struct ModuleEvent {
     pub status: EventStatus,
     pub energy_quanta_used: EnergyDiff,
     pub host_execution_duration: Duration,
+    /// The commit written to the message log by this event, if any,
+    /// which may not be durable yet.
+    pub commit: Option<AppendedCommit>,
+    /// A read-only transaction of the state right after this event's transaction committed, if it did.
+    /// Subscriptions are evaluated against it, rather than against the state by the time the commit is broadcast,
+    /// which may include later commits.
+    pub read_tx: Option<Arc<TxId>>,
 }
 
 #[derive(Debug)]
@@ -196,7 +210,7 @@ enum ModuleHostCommand {
     },
     CallReducer {
         caller_identity: Identity,
-        client: Option<ClientConnectionSender>,
+        client: Option<ClientCall>,
         reducer_id: usize,
         args: ArgsTuple,
         respond_to: oneshot::Sender<ReducerCallResult>,
@@ -280,7 +294,7 @@ pub trait ModuleHostActor: Send + 'static {
     fn call_reducer(
         &mut self,
         caller_identity: Identity,
-        client: Option<ClientConnectionSender>,
+        client: Option<ClientCall>,
         reducer_id: usize,
         args: ArgsTuple,
         respond_to: oneshot::Sender<ReducerCallResult>,
@@ -310,13 +324,14 @@ pub type UpdateDatabaseResult = Result<UpdateDatabaseSuccess, UpdateDatabaseErro
 
 #[derive(Debug)]
 pub struct UpdateDatabaseSuccess {
+    /// The changes made to the schema of the database, in the order they were
+    /// applied.
+    pub schema_changes: Vec<SchemaChange>,
     /// Outcome of calling the module's __update__ reducer, `None` if none is
     /// defined.
     pub update_result: Option<ReducerCallResult>,
-    /// Outcome of calling the module's pending __migrate__ reducers, empty if
-    /// none are defined or pending.
-    ///
-    /// Currently always empty, as __migrate__ is not yet supported.
+    /// Outcome of calling the module's __migrate__ reducer, empty if none is
+    /// defined.
     pub migrate_results: Vec<ReducerCallResult>,
 }
 
@@ -324,6 +339,8 @@ pub struct UpdateDatabaseSuccess {
 pub enum UpdateDatabaseError {
     #[error("incompatible schema changes for: {tables:?}")]
     IncompatibleSchema { tables: Vec<String> },
+    #[error("migrate reducer failed: {:?}", .0.outcome)]
+    MigrateFailed(ReducerCallResult),
     #[error(transparent)]
     Database(#[from] DBError),
 }
@@ -417,7 +434,7 @@ impl ModuleHost {
     pub async fn call_reducer(
         &self,
         caller_identity: Identity,
-        client: Option<ClientConnectionSender>,
+        client: Option<ClientCall>,
         reducer_name: &str,
         args: ReducerArgs,
     ) -> Result<ReducerCallResult, ReducerCallError> {
@@ -0,0 +1,415 @@
+//! Migrating the schema of a database to the tables defined by an updated module.
+//!
+//! Only changes that don't lose data are applied automatically:
+//! new tables, new and dropped indexes, and columns appended to an existing table.
+//! An appended column is filled in with the zero value of its type,
+//! which a `#[spacetimedb(migrate)]` reducer can then backfill.
+
+use crate::db::datastore::locking_tx_datastore::MutTxId;
+use crate::db::datastore::traits::{ColumnDef, IndexDef, IndexId, TableDef, TableSchema};
+use crate::db::relational_db::RelationalDB;
+use crate::error::DBError;
+use spacetimedb_lib::name::SchemaChange;
+use spacetimedb_sats::{AlgebraicType, AlgebraicValue, ArrayValue, BuiltinType};
+use std::collections::BTreeMap;
+
+/// A single change to the schema of a database.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum MigrationStep {
+    CreateTable(TableDef),
+    AddColumn {
+        table_id: u32,
+        table_name: String,
+        column: ColumnDef,
+        default: AlgebraicValue,
+    },
+    CreateIndex {
+        table_name: String,
+        index: IndexDef,
+    },
+    DropIndex {
+        table_name: String,
+        index_id: IndexId,
+        index_name: String,
+    },
+}
+
+impl MigrationStep {
+    /// Applies this change to the database within `tx`,
+    /// returning a description of the change for the publisher of the module.
+    pub(crate) fn apply(self, stdb: &RelationalDB, tx: &mut MutTxId) -> Result<SchemaChange, DBError> {
+        Ok(match self {
+            Self::CreateTable(schema) => {
+                let table = schema.table_name.clone();
+                stdb.create_table(tx, schema)?;
+                SchemaChange::CreateTable { table }
+            }
+            Self::AddColumn {
+                table_id,
+                table_name,
+                column,
+                default,
+            } => {
+                let column_name = column.col_name.clone();
+                stdb.add_column(tx, table_id, column, default)?;
+                SchemaChange::AddColumn {
+                    table: table_name,
+                    column: column_name,
+                }
+            }
+            Self::CreateIndex { table_name, index } => {
+                let index_name = index.name.clone();
+                stdb.create_index(tx, index)?;
+                SchemaChange::CreateIndex {
+                    table: table_name,
+                    index: index_name,
+                }
+            }
+            Self::DropIndex {
+                table_name,
+                index_id,
+                index_name,
+            } => {
+                stdb.drop_index(tx, index_id)?;
+                SchemaChange::DropIndex {
+                    table: table_name,
+                    index: index_name,
+                }
+            }
+        })
+    }
+}
+
+/// A table whose stored schema can't be migrated automatically to the one defined by the module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IncompatibleTable {
+    pub(crate) table_name: String,
+    pub(crate) reason: String,
+}
+
+/// Returns the changes needed to migrate the `known_tables` of a database
+/// to the `proposed_tables` defined by a module,
+/// or every table for which that can't be done automatically.
+///
+/// Indexes are dropped before any column is added,
+/// and columns are added before any index is created,
+/// so that a changed index can keep its name and cover a new column.
+pub(crate) fn plan_migration(
+    known_tables: Vec<TableSchema>,
+    proposed_tables: Vec<TableDef>,
+) -> Result<Vec<MigrationStep>, Vec<IncompatibleTable>> {
+    let mut known_tables: BTreeMap<String, TableSchema> = known_tables
+        .into_iter()
+        .map(|schema| (schema.table_name.clone(), schema))
+        .collect();
+
+    let mut steps = Vec::new();
+    let mut new_tables = Vec::new();
+    let mut incompatible = Vec::new();
+    for proposed in proposed_tables {
+        let Some(known) = known_tables.remove(&proposed.table_name) else {
+            new_tables.push(MigrationStep::CreateTable(proposed));
+            continue;
+        };
+        let table_name = proposed.table_name.clone();
+        match plan_table_migration(known, proposed) {
+            Ok(table_steps) => steps.extend(table_steps),
+            Err(reason) => incompatible.push(IncompatibleTable { table_name, reason }),
+        }
+    }
+    // We may at some point decide to drop orphaned tables automatically,
+    // but for now it's an incompatible schema change
+    for orphan in known_tables.into_keys() {
+        if !orphan.starts_with("st_") {
+            incompatible.push(IncompatibleTable {
+                table_name: orphan,
+                reason: "the table is no longer defined by the module".into(),
+            });
+        }
+    }
+    if !incompatible.is_empty() {
+        return Err(incompatible);
+    }
+
+    steps.sort_by_key(|step| match step {
+        MigrationStep::DropIndex { .. } => 0,
+        MigrationStep::AddColumn { .. } => 1,
+        MigrationStep::CreateIndex { .. } => 2,
+        MigrationStep::CreateTable(_) => 3,
+    });
+    steps.extend(new_tables);
+    Ok(steps)
+}
+
+fn plan_table_migration(known: TableSchema, mut proposed: TableDef) -> Result<Vec<MigrationStep>, String> {
+    if known.table_type != proposed.table_type || known.table_access != proposed.table_access {
+        return Err("the type or access of the table changed".into());
+    }
+    if proposed.columns.len() < known.columns.len() {
+        return Err("columns were removed from the table".into());
+    }
+
+    let mut steps = Vec::new();
+    let (kept_columns, added_columns) = proposed.columns.split_at(known.columns.len());
+    for (known_col, proposed_col) in known.columns.iter().zip(kept_columns) {
+        if ColumnDef::from(known_col.clone()) != *proposed_col {
+            return Err(format!("column `{}` changed", known_col.col_name));
+        }
+    }
+    for column in added_columns {
+        let default = default_value(&column.col_type)
+            .ok_or_else(|| format!("column `{}` was added without a default value", column.col_name))?;
+        steps.push(MigrationStep::AddColumn {
+            table_id: known.table_id,
+            table_name: known.table_name.clone(),
+            column: column.clone(),
+            default,
+        });
+    }
+
+    // The table is known, so we also know its id. Update the
+    // index definitions so the indexes of both schemas are comparable.
+    for index in proposed.indexes.iter_mut() {
+        index.table_id = known.table_id;
+    }
+    for index in &known.indexes {
+        if !proposed.indexes.contains(&IndexDef::from(index.clone())) {
+            steps.push(MigrationStep::DropIndex {
+                table_name: known.table_name.clone(),
+                index_id: IndexId(index.index_id),
+                index_name: index.index_name.clone(),
+            });
+        }
+    }
+    for index in proposed.indexes {
+        if !known.indexes.iter().any(|known| IndexDef::from(known.clone()) == index) {
+            steps.push(MigrationStep::CreateIndex {
+                table_name: known.table_name.clone(),
+                index,
+            });
+        }
+    }
+    Ok(steps)
+}
+
+/// Returns the value that a column of type `ty` has in the existing rows of a table it is added to,
+/// or `None` if the type has no obvious zero value.
+///
+/// That is `false`, `0`, the empty string, array or map,
+/// `none` for an option, and the zero value of every field for a product.
+fn default_value(ty: &AlgebraicType) -> Option<AlgebraicValue> {
+    Some(match ty {
+        AlgebraicType::Sum(sum) => {
+            let tag = sum.variants.iter().position(|variant| {
+                variant.name.as_deref() == Some("none") && variant.algebraic_type == AlgebraicType::UNIT_TYPE
+            })?;
+            if sum.variants.len() != 2 {
+                return None;
+            }
+            AlgebraicValue::sum(tag as u8, AlgebraicValue::UNIT)
+        }
+        AlgebraicType::Product(product) => AlgebraicValue::product(
+            product
+                .elements
+                .iter()
+                .map(|element| default_value(&element.algebraic_type))
+                .collect::<Option<_>>()?,
+        ),
+        AlgebraicType::Builtin(builtin) => match builtin {
+            BuiltinType::Bool => AlgebraicValue::Bool(false),
+            BuiltinType::I8 => AlgebraicValue::I8(0),
+            BuiltinType::U8 => AlgebraicValue::U8(0),
+            BuiltinType::I16 => AlgebraicValue::I16(0),
+            BuiltinType::U16 => AlgebraicValue::U16(0),
+            BuiltinType::I32 => AlgebraicValue::I32(0),
+            BuiltinType::U32 => AlgebraicValue::U32(0),
+            BuiltinType::I64 => AlgebraicValue::I64(0),
+            BuiltinType::U64 => AlgebraicValue::U64(0),
+            BuiltinType::I128 => AlgebraicValue::I128(0),
+            BuiltinType::U128 => AlgebraicValue::U128(0),
+            BuiltinType::F32 => AlgebraicValue::F32(0.0.into()),
+            BuiltinType::F64 => AlgebraicValue::F64(0.0.into()),
+            BuiltinType::String => AlgebraicValue::String(String::new()),
+            BuiltinType::Array(_) => AlgebraicValue::ArrayOf(ArrayValue::default()),
+            BuiltinType::Map(_) => AlgebraicValue::map(BTreeMap::new()),
+        },
+        AlgebraicType::Ref(_) => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::datastore::traits::{ColumnSchema, IndexSchema};
+    use spacetimedb_lib::auth::{StAccess, StTableType};
+    use spacetimedb_lib::IndexType;
+    use spacetimedb_sats::SumTypeVariant;
+
+    fn column(col_name: &str, col_type: AlgebraicType) -> ColumnDef {
+        ColumnDef {
+            col_name: col_name.into(),
+            col_type,
+            is_autoinc: false,
+        }
+    }
+
+    fn index(name: &str, cols: Vec<u32>) -> IndexDef {
+        IndexDef::new(name.into(), 0, cols, IndexType::BTree, false)
+    }
+
+    fn table_def(table_name: &str, columns: Vec<ColumnDef>, indexes: Vec<IndexDef>) -> TableDef {
+        TableDef {
+            table_name: table_name.into(),
+            columns,
+            indexes,
+            table_type: StTableType::User,
+            table_access: StAccess::Public,
+        }
+    }
+
+    /// Returns the schema that `def` would have once created as the table with `table_id`.
+    fn known_schema(table_id: u32, def: &TableDef) -> TableSchema {
+        TableSchema {
+            table_id,
+            table_name: def.table_name.clone(),
+            columns: def
+                .columns
+                .iter()
+                .enumerate()
+                .map(|(col_id, col)| ColumnSchema {
+                    table_id,
+                    col_id: col_id as u32,
+                    col_name: col.col_name.clone(),
+                    col_type: col.col_type.clone(),
+                    is_autoinc: col.is_autoinc,
+                })
+                .collect(),
+            indexes: def
+                .indexes
+                .iter()
+                .enumerate()
+                .map(|(index_id, index)| IndexSchema {
+                    index_id: 100 + index_id as u32,
+                    table_id,
+                    cols: index.cols.clone(),
+                    index_type: index.index_type,
+                    index_name: index.name.clone(),
+                    is_unique: index.is_unique,
+                })
+                .collect(),
+            table_type: def.table_type,
+            table_access: def.table_access,
+        }
+    }
+
+    fn person() -> TableDef {
+        table_def(
+            "Person",
+            vec![column("id", AlgebraicType::U64), column("name", AlgebraicType::String)],
+            vec![index("Person_id_idx", vec![0])],
+        )
+    }
+
+    #[test]
+    fn test_plan_unchanged() {
+        let steps = plan_migration(vec![known_schema(4, &person())], vec![person()]);
+        assert_eq!(steps, Ok(vec![]));
+    }
+
+    #[test]
+    fn test_plan_safe_changes() {
+        let mut proposed = person();
+        proposed
+            .columns
+            .push(column("nickname", AlgebraicType::option(AlgebraicType::String)));
+        proposed.columns.push(column("age", AlgebraicType::U8));
+        proposed.indexes = vec![index("Person_name_idx", vec![1]), index("Person_id_idx", vec![0, 3])];
+        let pet = table_def("Pet", vec![column("owner", AlgebraicType::U64)], vec![]);
+
+        let steps = plan_migration(vec![known_schema(4, &person())], vec![proposed, pet.clone()]).unwrap();
+        let mut name_idx = index("Person_name_idx", vec![1]);
+        name_idx.table_id = 4;
+        let mut id_age_idx = index("Person_id_idx", vec![0, 3]);
+        id_age_idx.table_id = 4;
+        assert_eq!(
+            steps,
+            vec![
+                MigrationStep::DropIndex {
+                    table_name: "Person".into(),
+                    index_id: IndexId(100),
+                    index_name: "Person_id_idx".into(),
+                },
+                MigrationStep::AddColumn {
+                    table_id: 4,
+                    table_name: "Person".into(),
+                    column: column("nickname", AlgebraicType::option(AlgebraicType::String)),
+                    default: AlgebraicValue::OptionNone(),
+                },
+                MigrationStep::AddColumn {
+                    table_id: 4,
+                    table_name: "Person".into(),
+                    column: column("age", AlgebraicType::U8),
+                    default: AlgebraicValue::U8(0),
+                },
+                MigrationStep::CreateIndex {
+                    table_name: "Person".into(),
+                    index: name_idx,
+                },
+                MigrationStep::CreateIndex {
+                    table_name: "Person".into(),
+                    index: id_age_idx,
+                },
+                MigrationStep::CreateTable(pet),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_incompatible_changes() {
+        let mut changed_column = person();
+        changed_column.columns[1].col_type = AlgebraicType::U32;
+        let mut removed_column = person();
+        removed_column.table_name = "Other".into();
+        removed_column.columns.pop();
+        let mut no_default = person();
+        no_default.table_name = "Enum".into();
+        no_default.columns.push(column(
+            "kind",
+            AlgebraicType::sum(vec![SumTypeVariant::unit("a"), SumTypeVariant::unit("b")]),
+        ));
+        let mut known_other = known_schema(5, &person());
+        known_other.table_name = "Other".into();
+        let mut known_enum = known_schema(6, &person());
+        known_enum.table_name = "Enum".into();
+        let mut orphan = known_schema(7, &person());
+        orphan.table_name = "Orphan".into();
+
+        let incompatible = plan_migration(
+            vec![known_schema(4, &person()), known_other, known_enum, orphan],
+            vec![changed_column, removed_column, no_default],
+        )
+        .unwrap_err();
+        let tables = incompatible.iter().map(|t| t.table_name.as_str()).collect::<Vec<_>>();
+        assert_eq!(tables, ["Person", "Other", "Enum", "Orphan"]);
+    }
+
+    #[test]
+    fn test_default_value() {
+        let ty = AlgebraicType::product(vec![
+            AlgebraicType::Bool.into(),
+            AlgebraicType::array(AlgebraicType::U32).into(),
+        ]);
+        assert_eq!(
+            default_value(&ty),
+            Some(AlgebraicValue::product(vec![
+                AlgebraicValue::Bool(false),
+                AlgebraicValue::ArrayOf(ArrayValue::default()),
+            ]))
+        );
+        assert_eq!(
+            default_value(&AlgebraicType::option(AlgebraicType::U8)),
+            Some(AlgebraicValue::OptionNone())
+        );
+        assert_eq!(default_value(&AlgebraicType::simple_enum(["a", "b"].into_iter())), None);
+    }
+}
@@ -15,6 +15,9 @@ pub const SETUP_DUNDER: &str = "__setup__";
 pub const INIT_DUNDER: &str = "__init__";
 /// the reducer with this name is invoked when updating the database
 pub const UPDATE_DUNDER: &str = "__update__";
+/// the reducer with this name is invoked when updating the database,
+/// within the transaction that migrates its schema
+pub const MIGRATE_DUNDER: &str = "__migrate__";
 pub const IDENTITY_CONNECTED_DUNDER: &str = "__identity_connected__";
 pub const IDENTITY_DISCONNECTED_DUNDER: &str = "__identity_disconnected__";
 
@@ -1,9 +1,11 @@
-use std::collections::BTreeMap;
 use std::ops::ControlFlow;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::db::datastore::traits::{ColumnDef, IndexDef, TableDef, TableSchema};
+use crate::db::commit_log::AppendedCommit;
+use crate::db::datastore::locking_tx_datastore::{MutTxId, TxId};
+use crate::db::datastore::traits::{ColumnDef, IndexDef, TableDef};
+use crate::db::relational_db::RelationalDB;
 use crate::host::scheduler::Scheduler;
 use anyhow::Context;
 use bytes::Bytes;
@@ -12,7 +14,7 @@ use spacetimedb_lib::buffer::DecodeError;
 use spacetimedb_lib::{bsatn, IndexType, ModuleDef};
 use tokio::sync::oneshot;
 
-use crate::client::ClientConnectionSender;
+use crate::client::ClientCall;
 use crate::database_instance_context::DatabaseInstanceContext;
 use crate::database_logger::{DatabaseLogger, LogLevel, Record};
 use crate::hash::Hash;
@@ -21,6 +23,7 @@ use crate::host::module_host::{
     DatabaseUpdate, EventStatus, ModuleEvent, ModuleFunctionCall, ModuleHostActor, ModuleInfo, UpdateDatabaseError,
     UpdateDatabaseResult, UpdateDatabaseSuccess,
 };
+use crate::host::schema_migration::plan_migration;
 use crate::host::tracelog::instance_trace::TraceLog;
 use crate::host::{
     ArgsTuple, EnergyDiff, EnergyMonitor, EnergyMonitorFingerprint, EnergyQuanta, EntityDef, ReducerCallResult,
@@ -34,6 +37,12 @@ use super::*;
 
 const MSG_CHANNEL_CAP: usize = 8;
 const MSG_CHANNEL_TIMEOUT: Duration = Duration::from_millis(500);
+/// The number of times a reducer is run before its call fails with [`EventStatus::WriteConflict`],
+/// when its transaction keeps conflicting with concurrently committed transactions.
+const MAX_REDUCER_ATTEMPTS: u32 = 3;
+
+/// The status of a reducer call, with its commit and a read-only transaction of the state right after it, if it committed.
+type TxOutcome = (EventStatus, Option<AppendedCommit>, Option<Arc<TxId>>);
 
 pub trait WasmModule: Send + 'static {
     type Instance: WasmInstance;
@@ -392,7 +401,7 @@ impl<T: WasmModule> ModuleHostActor for WasmModuleHostActor<T> {
     fn call_reducer(
         &mut self,
         caller_identity: Identity,
-        client: Option<ClientConnectionSender>,
+        client: Option<ClientCall>,
         reducer_id: usize,
 
         args: ArgsTuple,
@@ -433,6 +442,11 @@ impl SystemLogger<'_> {
             .write(crate::database_logger::LogLevel::Warn, &Self::record(msg), &())
     }
 
+    fn info(&mut self, msg: &str) {
+        self.inner
+            .write(crate::database_logger::LogLevel::Info, &Self::record(msg), &())
+    }
+
     fn error(&mut self, msg: &str) {
         self.inner
             .write(crate::database_logger::LogLevel::Error, &Self::record(msg), &())
@@ -552,60 +566,71 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
 
     #[tracing::instrument(skip_all)]
     fn update_database(&mut self) -> Result<UpdateDatabaseResult, anyhow::Error> {
-        let stdb = &*self.database_instance_context().relational_db;
-
-        let mut tainted = vec![];
-        stdb.with_auto_commit::<_, _, anyhow::Error>(|tx| {
-            let mut known_tables: BTreeMap<String, TableSchema> = stdb
-                .get_all_tables(tx)?
-                .into_iter()
-                .map(|schema| (schema.table_name.clone(), schema))
-                .collect();
+        let stdb = self.database_instance_context().relational_db.clone();
 
-            let mut new_tables = Vec::new();
-            for table in self.info.catalog.values().filter_map(EntityDef::as_table) {
-                let mut proposed_schema = self.schema_for(table)?;
-                if let Some(known_schema) = known_tables.remove(&table.name) {
-                    // If the table is known, we also know its id. Update the
-                    // index definitions so the `TableDef` of both schemas is
-                    // equivalent.
-                    for index in proposed_schema.indexes.iter_mut() {
-                        index.table_id = known_schema.table_id;
-                    }
-                    let known_schema = TableDef::from(known_schema);
-                    if known_schema != proposed_schema {
-                        self.system_logger()
-                            .warn(&format!("stored and proposed schema of `{}` differ", table.name));
-                        tainted.push(table.name.to_owned());
-                    } else {
-                        // Table unchanged
-                    }
-                } else {
-                    new_tables.push((table, proposed_schema));
-                }
+        let proposed_tables = self
+            .info
+            .catalog
+            .values()
+            .filter_map(EntityDef::as_table)
+            .map(|table| self.schema_for(table))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut tx = stdb.begin_tx();
+        let steps = match stdb.get_all_tables(&tx) {
+            Ok(known_tables) => plan_migration(known_tables, proposed_tables),
+            Err(err) => {
+                stdb.rollback_tx(tx);
+                return Err(err.into());
             }
-            // We may at some point decide to drop orphaned tables automatically,
-            // but for now it's an incompatible schema change
-            for orphan in known_tables.into_keys() {
-                if !orphan.starts_with("st_") {
-                    self.system_logger()
-                        .warn(format!("Orphaned table: {}", orphan).as_str());
-                    tainted.push(orphan);
+        };
+        let steps = match steps {
+            Ok(steps) => steps,
+            Err(incompatible) => {
+                stdb.rollback_tx(tx);
+                let mut logger = self.system_logger();
+                for table in &incompatible {
+                    logger.warn(&format!(
+                        "stored and proposed schema of `{}` differ: {}",
+                        table.table_name, table.reason
+                    ));
                 }
+                logger.error("module update rejected due to schema mismatch");
+                let tables = incompatible.into_iter().map(|table| table.table_name).collect();
+                return Ok(Err(UpdateDatabaseError::IncompatibleSchema { tables }));
             }
-            if tainted.is_empty() {
-                for (table, schema) in new_tables {
-                    stdb.create_table(tx, schema)
-                        .with_context(|| format!("failed to create table {}", table.name))?;
+        };
+
+        let mut schema_changes = Vec::with_capacity(steps.len());
+        for step in steps {
+            match step.apply(&stdb, &mut tx) {
+                Ok(change) => {
+                    self.system_logger().info(&format!("Schema migration: {change}"));
+                    schema_changes.push(change);
+                }
+                Err(err) => {
+                    stdb.rollback_tx(tx);
+                    self.system_logger()
+                        .error(&format!("module update rejected: schema migration failed: {err}"));
+                    return Ok(Err(err.into()));
                 }
             }
+        }
 
-            Ok(())
-        })?;
-        if !tainted.is_empty() {
-            self.system_logger()
-                .error("module update rejected due to schema mismatch");
-            return Ok(Err(UpdateDatabaseError::IncompatibleSchema { tables: tainted }));
+        // The migrate reducer runs in the same transaction as the schema changes,
+        // so that it can backfill new columns before anyone observes them.
+        let mut migrate_results = vec![];
+        if let Some(id) = self.info.reducers.get_index_of(MIGRATE_DUNDER) {
+            let migrate_result = self.call_migrate_reducer(&stdb, tx, id);
+            if !matches!(migrate_result.outcome, ReducerOutcome::Committed) {
+                // The schema changes were rolled back along with the migrate reducer.
+                self.system_logger()
+                    .error("module update rejected: migrate reducer failed");
+                return Ok(Err(UpdateDatabaseError::MigrateFailed(migrate_result)));
+            }
+            migrate_results.push(migrate_result);
+        } else if stdb.commit_tx(tx)?.is_none() {
+            anyhow::bail!("schema migration conflicted with a concurrent transaction");
         }
 
         let update_result = self.info.reducers.get_index_of(UPDATE_DUNDER).map(|id| {
@@ -618,16 +643,69 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
         });
 
         Ok(Ok(UpdateDatabaseSuccess {
+            schema_changes,
             update_result,
-            migrate_results: vec![],
+            migrate_results,
         }))
     }
 
+    /// Calls the migrate reducer within `tx`, which has the schema changes of an update applied,
+    /// then commits or rolls back `tx` depending on the outcome of the reducer.
+    ///
+    /// Unlike [`Self::call_reducer`], the reducer is not retried on a write conflict,
+    /// as that would require reapplying the schema changes.
+    fn call_migrate_reducer(&mut self, stdb: &RelationalDB, tx: MutTxId, reducer_id: usize) -> ReducerCallResult {
+        let start_instant = Instant::now();
+        let timestamp = Timestamp::now();
+        let caller_identity = self.database_instance_context().identity;
+        let mut args = ArgsTuple::default();
+
+        let energy_fingerprint = EnergyMonitorFingerprint {
+            module_hash: self.info.module_hash,
+            module_identity: self.info.identity,
+            caller_identity,
+            reducer_name: MIGRATE_DUNDER,
+        };
+        let budget = self.energy_monitor.reducer_budget(&energy_fingerprint);
+
+        let op = InstanceOp::Reducer {
+            id: reducer_id,
+            sender: &caller_identity,
+            timestamp,
+            arg_bytes: args.get_bsatn().clone(),
+        };
+        let (result, energy) = self.run_in_tx(stdb, tx, op, budget, &energy_fingerprint);
+        let (status, commit, read_tx) = result.unwrap_or((EventStatus::WriteConflict, None, None));
+
+        let execution_duration = start_instant.elapsed();
+        let outcome = ReducerOutcome::from(&status);
+        let event = ModuleEvent {
+            timestamp,
+            caller_identity,
+            function_call: ModuleFunctionCall {
+                reducer: MIGRATE_DUNDER.to_owned(),
+                args,
+            },
+            status,
+            energy_quanta_used: energy.used,
+            host_execution_duration: execution_duration,
+            commit,
+            read_tx,
+        };
+        self.event_tx.broadcast_event_blocking(None, event);
+
+        ReducerCallResult {
+            outcome,
+            energy_used: energy.used,
+            execution_duration,
+        }
+    }
+
     #[tracing::instrument(skip_all)]
     fn call_reducer(
         &mut self,
         caller_identity: Identity,
-        client: Option<ClientConnectionSender>,
+        client: Option<ClientCall>,
         reducer_id: usize,
         mut args: ArgsTuple,
     ) -> ReducerCallResult {
@@ -639,7 +717,7 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
 
         log::trace!("Calling reducer {}", reducerdef.name);
 
-        let (status, energy) = self.execute(InstanceOp::Reducer {
+        let (status, energy, commit, read_tx) = self.execute(InstanceOp::Reducer {
             id: reducer_id,
             sender: &caller_identity,
             timestamp,
@@ -661,8 +739,10 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
             status,
             energy_quanta_used: energy.used,
             host_execution_duration: execution_duration,
+            commit,
+            read_tx,
         };
-        self.event_tx.broadcast_event_blocking(client.as_ref(), event);
+        self.event_tx.broadcast_event_blocking(client, event);
 
         ReducerCallResult {
             outcome,
@@ -686,7 +766,7 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
 
         let timestamp = Timestamp::now();
 
-        let (status, energy) = self.execute(InstanceOp::ConnDisconn {
+        let (status, energy, commit, read_tx) = self.execute(InstanceOp::ConnDisconn {
             conn: connected,
             sender: &identity,
             timestamp,
@@ -712,15 +792,18 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
             caller_identity: identity,
             energy_quanta_used: energy.used,
             host_execution_duration: start_instant.elapsed(),
+            commit,
+            read_tx,
         };
         self.event_tx.broadcast_event_blocking(None, event);
     }
 
     #[tracing::instrument(skip_all)]
-    fn execute(&mut self, op: InstanceOp<'_>) -> (EventStatus, EnergyStats) {
+    fn execute(&mut self, op: InstanceOp<'_>) -> (EventStatus, EnergyStats, Option<AppendedCommit>, Option<Arc<TxId>>) {
         let address = &self.database_instance_context().address.to_abbreviated_hex();
+        let info = self.info.clone();
         let func_ident = match op {
-            InstanceOp::Reducer { id, .. } => &*self.info.reducers[id].name,
+            InstanceOp::Reducer { id, .. } => &*info.reducers[id].name,
             InstanceOp::ConnDisconn { conn, .. } => {
                 if conn {
                     IDENTITY_CONNECTED_DUNDER
@@ -742,8 +825,41 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
 
         let budget = self.energy_monitor.reducer_budget(&energy_fingerprint);
 
-        let tx = self.database_instance_context().relational_db.begin_tx();
+        let stdb = self.database_instance_context().relational_db.clone();
+        let (result, energy) = retry_on_write_conflict(budget, MAX_REDUCER_ATTEMPTS, |attempt, budget| {
+            if attempt > 1 {
+                log::debug!("Retrying reducer {func_ident:?} after a write conflict (attempt {attempt})");
+            }
+
+            let tx = stdb.begin_tx();
+            let (result, energy) = self.run_in_tx(&stdb, tx, op.clone(), budget, &energy_fingerprint);
+            if result.is_none() {
+                log::warn!("Reducer {func_ident:?} hit a write conflict on attempt {attempt}");
+            }
+            (result, energy)
+        });
 
+        let (status, commit, read_tx) = result.unwrap_or_else(|| {
+            log::warn!("Reducer {func_ident:?} gave up after {MAX_REDUCER_ATTEMPTS} write conflicts");
+            (EventStatus::WriteConflict, None, None)
+        });
+        (status, energy, commit, read_tx)
+    }
+
+    /// Runs `op` within `tx`, then commits `tx` if `op` succeeded, or rolls it back otherwise.
+    ///
+    /// Returns `None` if `tx` conflicted with a concurrently committed transaction,
+    /// in which case its writes were discarded.
+    fn run_in_tx(
+        &mut self,
+        stdb: &RelationalDB,
+        tx: MutTxId,
+        op: InstanceOp<'_>,
+        budget: EnergyQuanta,
+        energy_fingerprint: &EnergyMonitorFingerprint<'_>,
+    ) -> (Option<TxOutcome>, EnergyStats) {
+        let address = &self.database_instance_context().address.to_abbreviated_hex();
+        let func_ident = energy_fingerprint.reducer_name;
         let tx_slot = self.instance.instance_env().tx.clone();
         let (tx, result) = tx_slot.set(tx, || match op {
             InstanceOp::Reducer {
@@ -770,7 +886,7 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
         } = result;
 
         self.energy_monitor
-            .record(&energy_fingerprint, energy.used, execution_duration);
+            .record(energy_fingerprint, energy.used, execution_duration);
 
         const FRAME_LEN_60FPS: Duration = match Duration::from_secs(1).checked_div(60) {
             Some(d) => d,
@@ -798,7 +914,6 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
         //     };
         // }
 
-        let stdb = &*self.database_instance_context().relational_db;
         let status = match call_result {
             Err(err) => {
                 stdb.rollback_tx(tx);
@@ -822,22 +937,28 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
                 EventStatus::Failed(errmsg.into())
             }
             Ok(Ok(())) => {
-                if let Some((tx_data, bytes_written)) = stdb.commit_tx(tx).unwrap() {
-                    // TODO(cloutiertyler): This tracking doesn't really belong here if we want to write transactions to disk
-                    // in batches. This is because it's possible for a tiny reducer call to trigger a whole commit to be written to disk.
-                    // We should track the commit sizes instead internally to the CommitLog probably.
-                    if let Some(bytes_written) = bytes_written {
-                        REDUCER_WRITE_SIZE
-                            .with_label_values(&[address, func_ident])
-                            .observe(bytes_written as f64);
+                let (tx_data, appended_commit, read_tx) = match stdb.commit_tx_with_read_tx(tx) {
+                    Ok(Some(committed)) => committed,
+                    Ok(None) => return (None, energy),
+                    Err(err) => {
+                        log::error!("Failed to commit the transaction of reducer {func_ident:?}: {err}");
+                        let status = EventStatus::Failed("Failed to commit the transaction.".into());
+                        return (Some((status, None, None)), energy);
                     }
-                    EventStatus::Committed(DatabaseUpdate::from_writes(stdb, &tx_data))
-                } else {
-                    todo!("Write skew, you need to implement retries my man, T-dawg.");
+                };
+                // TODO(cloutiertyler): This tracking doesn't really belong here if we want to write transactions to disk
+                // in batches. This is because it's possible for a tiny reducer call to trigger a whole commit to be written to disk.
+                // We should track the commit sizes instead internally to the CommitLog probably.
+                if let Some(appended_commit) = &appended_commit {
+                    REDUCER_WRITE_SIZE
+                        .with_label_values(&[address, func_ident])
+                        .observe(appended_commit.bytes_written as f64);
                 }
+                let status = EventStatus::Committed(DatabaseUpdate::from_writes(stdb, &tx_data));
+                return (Some((status, appended_commit, Some(Arc::new(read_tx)))), energy);
             }
         };
-        (status, energy)
+        (Some((status, None, None)), energy)
     }
 
     // Helpers - NOT API
@@ -864,32 +985,29 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
             })
             .collect::<anyhow::Result<_>>()?;
 
+        for index in table.indexes.iter() {
+            anyhow::ensure!(!index.col_ids.is_empty(), "index `{}` has no columns", index.name);
+            for col_id in index.col_ids.iter() {
+                anyhow::ensure!(
+                    (*col_id as usize) < columns.len(),
+                    "invalid column id in `{}`",
+                    index.name
+                );
+            }
+        }
+
         let mut indexes = Vec::new();
         for (col_id, col) in columns.iter().enumerate() {
-            let mut index_for_column = None;
-            for index in table.indexes.iter() {
-                let [index_col_id] = *index.col_ids else {
-                    anyhow::bail!("multi-column indexes not yet supported")
-                };
-                if index_col_id as usize != col_id {
-                    continue;
-                }
-                index_for_column = Some(index);
-                break;
-            }
+            let index_for_column = table.indexes.iter().find(|index| *index.col_ids == [col_id as u8]);
 
             let col_attr = table.column_attrs.get(col_id).context("invalid column id")?;
             // If there's an index defined for this column already, use it
             // making sure that it is unique if the column has a unique constraint
             if let Some(index) = index_for_column {
-                match index.ty {
-                    IndexType::BTree => {}
-                    // TODO
-                    IndexType::Hash => anyhow::bail!("hash indexes not yet supported"),
-                }
                 let index = IndexDef {
                     table_id: 0, // Will be ignored
-                    col_id: col_id as u32,
+                    cols: vec![col_id as u32],
+                    index_type: index.ty,
                     name: index.name.clone(),
                     is_unique: col_attr.is_unique(),
                 };
@@ -899,7 +1017,8 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
                 // anyway.
                 let index = IndexDef {
                     table_id: 0, // Will be ignored
-                    col_id: col_id as u32,
+                    cols: vec![col_id as u32],
+                    index_type: IndexType::BTree,
                     name: format!("{}_{}_unique", table.name, col.col_name),
                     is_unique: true,
                 };
@@ -907,6 +1026,18 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
             }
         }
 
+        // Multi-column indexes come after the single-column ones.
+        // Unique constraints are declared per column, so these are never unique.
+        for index in table.indexes.iter().filter(|index| index.col_ids.len() > 1) {
+            indexes.push(IndexDef {
+                table_id: 0, // Will be ignored
+                cols: index.col_ids.iter().map(|col_id| *col_id as u32).collect(),
+                index_type: index.ty,
+                name: index.name.clone(),
+                is_unique: false,
+            });
+        }
+
         Ok(TableDef {
             table_name: table.name.clone(),
             columns,
@@ -922,7 +1053,35 @@ impl<T: WasmInstance> WasmInstanceActor<T> {
     }
 }
 
-#[derive(Debug)]
+/// Runs `attempt` until its transaction commits without a write conflict,
+/// at most `max_attempts` times, and returns its result along with the energy used by all the attempts.
+///
+/// `attempt` is passed the number of the attempt, starting from `1`, and the budget left for it,
+/// and returns `None` if its transaction conflicted with a concurrent one and was rolled back.
+/// Returns `None` if every attempt conflicted.
+fn retry_on_write_conflict<R>(
+    budget: EnergyQuanta,
+    max_attempts: u32,
+    mut attempt: impl FnMut(u32, EnergyQuanta) -> (Option<R>, EnergyStats),
+) -> (Option<R>, EnergyStats) {
+    let mut energy = EnergyStats {
+        used: EnergyDiff::ZERO,
+        remaining: budget,
+    };
+    for n in 1..=max_attempts {
+        let (result, attempt_energy) = attempt(n, energy.remaining);
+        energy = EnergyStats {
+            used: energy.used + attempt_energy.used,
+            remaining: attempt_energy.remaining,
+        };
+        if result.is_some() {
+            return (result, energy);
+        }
+    }
+    (None, energy)
+}
+
+#[derive(Debug, Clone)]
 enum InstanceOp<'a> {
     Reducer {
         id: usize,
@@ -949,7 +1108,7 @@ enum InstanceMessage {
     },
     CallReducer {
         caller_identity: Identity,
-        client: Option<ClientConnectionSender>,
+        client: Option<ClientCall>,
         reducer_id: usize,
         args: ArgsTuple,
         respond_to: oneshot::Sender<ReducerCallResult>,
@@ -963,3 +1122,65 @@ enum InstanceMessage {
         message: String,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an attempt that uses 10 eV of its budget
+    /// and hits a write conflict until its `commits_on`th run.
+    fn conflicting_attempt(
+        commits_on: u32,
+        budgets: &mut Vec<EnergyQuanta>,
+    ) -> impl FnMut(u32, EnergyQuanta) -> (Option<u32>, EnergyStats) + '_ {
+        move |n, budget| {
+            budgets.push(budget);
+            let energy = EnergyStats {
+                used: EnergyDiff(10),
+                remaining: EnergyQuanta(budget.0 - 10),
+            };
+            ((n >= commits_on).then_some(n), energy)
+        }
+    }
+
+    #[test]
+    fn test_retry_until_no_write_conflict() {
+        let mut budgets = Vec::new();
+        let (result, energy) = retry_on_write_conflict(
+            EnergyQuanta(100),
+            MAX_REDUCER_ATTEMPTS,
+            conflicting_attempt(3, &mut budgets),
+        );
+        assert_eq!(result, Some(3));
+        // Each retry only gets the budget left over from the previous attempts.
+        assert_eq!(budgets, [EnergyQuanta(100), EnergyQuanta(90), EnergyQuanta(80)]);
+        assert_eq!(energy.used.0, 30);
+        assert_eq!(energy.remaining, EnergyQuanta(70));
+    }
+
+    #[test]
+    fn test_no_retry_without_write_conflict() {
+        let mut budgets = Vec::new();
+        let (result, energy) = retry_on_write_conflict(
+            EnergyQuanta(100),
+            MAX_REDUCER_ATTEMPTS,
+            conflicting_attempt(1, &mut budgets),
+        );
+        assert_eq!(result, Some(1));
+        assert_eq!(budgets, [EnergyQuanta(100)]);
+        assert_eq!(energy.used.0, 10);
+    }
+
+    #[test]
+    fn test_give_up_after_max_write_conflicts() {
+        let mut budgets = Vec::new();
+        let (result, energy) = retry_on_write_conflict(
+            EnergyQuanta(100),
+            MAX_REDUCER_ATTEMPTS,
+            conflicting_attempt(u32::MAX, &mut budgets),
+        );
+        assert_eq!(result, None);
+        assert_eq!(budgets.len(), MAX_REDUCER_ATTEMPTS as usize);
+        assert_eq!(energy.used.0, 10 * MAX_REDUCER_ATTEMPTS as i128);
+    }
+}
@@ -384,7 +384,6 @@ impl WasmInstanceEnv {
 
             // Read the column ids on which to create an index from WASM memory.
             // This may be one column or an index on several columns.
-            // TODO(george) The index API right now only allows single column indexes.
             let cols = mem.read_bytes(&caller, col_ids, col_len)?;
 
             caller
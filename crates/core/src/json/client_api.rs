@@ -34,6 +34,7 @@ pub enum MessageJson {
     Event(EventJson),
     TransactionUpdate(TransactionUpdateJson),
     IdentityToken(IdentityTokenJson),
+    QueryUpdate(QueryUpdateJson),
 }
 
 impl MessageJson {
@@ -52,6 +53,7 @@ pub struct IdentityTokenJson {
 pub struct FunctionCallJson {
     pub reducer: String,
     pub args: ByteString,
+    pub request_id: u32,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -82,6 +84,7 @@ pub struct EventJson {
     pub caller_identity: String, // hex identity
     pub function_call: FunctionCallJson,
     pub energy_quanta_used: i128,
+    pub host_execution_duration_micros: u64,
     pub message: String,
 }
 
@@ -91,6 +94,12 @@ pub struct TransactionUpdateJson {
     pub subscription_update: SubscriptionUpdateJson,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryUpdateJson {
+    pub query_id: u32,
+    pub subscription_update: SubscriptionUpdateJson,
+}
+
 #[serde_as]
 #[derive(Debug, Clone, Serialize)]
 pub struct StmtResultJson {
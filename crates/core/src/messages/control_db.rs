@@ -3,6 +3,7 @@ use spacetimedb_sats::de::Deserialize;
 use spacetimedb_sats::ser::Serialize;
 
 use crate::address::Address;
+use crate::db::Durability;
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct IdentityEmail {
@@ -30,6 +31,34 @@ pub struct Database {
     pub program_bytes_address: Hash,
     /// Whether to create a full event log of all database events, for diagnostic / replay purposes.
     pub trace_log: bool,
+    /// When commits to the database are considered durable.
+    pub durability: Durability,
+}
+/// A [`Database`] record as written before it had a `durability`.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseV0 {
+    pub id: u64,
+    pub address: Address,
+    pub identity: Identity,
+    pub host_type: HostType,
+    pub num_replicas: u32,
+    pub program_bytes_address: Hash,
+    pub trace_log: bool,
+}
+impl From<DatabaseV0> for Database {
+    /// Those databases synced every commit, i.e. [`Durability::Sync`].
+    fn from(v0: DatabaseV0) -> Self {
+        Self {
+            id: v0.id,
+            address: v0.address,
+            identity: v0.identity,
+            host_type: v0.host_type,
+            num_replicas: v0.num_replicas,
+            program_bytes_address: v0.program_bytes_address,
+            trace_log: v0.trace_log,
+            durability: Durability::Sync,
+        }
+    }
 }
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct DatabaseStatus {
@@ -1,26 +1,38 @@
 use spacetimedb_lib::auth::{StAccess, StTableType};
 use spacetimedb_lib::error::RelationError;
 use spacetimedb_lib::table::{ColumnDef, ProductTypeMeta};
-use spacetimedb_lib::ColumnIndexAttribute;
+use spacetimedb_lib::{ColumnIndexAttribute, IndexType};
+use spacetimedb_sats::algebraic_type::fmt::fmt_algebraic_type;
 use spacetimedb_sats::{AlgebraicType, AlgebraicValue, ProductTypeElement};
 use sqlparser::ast::{
-    Assignment, BinaryOperator, ColumnDef as SqlColumnDef, ColumnOption, DataType, ExactNumberInfo, Expr as SqlExpr,
-    GeneratedAs, HiveDistributionStyle, Ident, JoinConstraint, JoinOperator, ObjectName, ObjectType, Query, Select,
-    SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, Value, Values,
+    AlterTableOperation, Assignment, BinaryOperator, ColumnDef as SqlColumnDef, ColumnOption, DataType, DoUpdate,
+    ExactNumberInfo, Expr as SqlExpr, Function, FunctionArg, FunctionArgExpr, GeneratedAs, HiveDistributionStyle,
+    Ident, JoinConstraint, JoinOperator, ObjectName, ObjectType, Offset, OnConflictAction, OnInsert, OrderByExpr,
+    Query, Select, SelectItem, SetExpr, SetOperator, SetQuantifier, Statement, TableFactor, TableWithJoins,
+    UnaryOperator, Value, Values,
 };
 use sqlparser::dialect::PostgreSqlDialect;
-use sqlparser::parser::Parser;
+use sqlparser::keywords::Keyword;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::Token;
 use std::collections::HashMap;
 
-use crate::db::datastore::locking_tx_datastore::MutTxId;
-use crate::db::datastore::traits::{MutTxDatastore, TableId, TableSchema};
+use crate::db::datastore::traits::{MutTxDatastore, TableId, TableSchema, TxDatastore};
 use crate::db::relational_db::RelationalDB;
 use crate::error::{DBError, PlanError};
+use crate::sql::prepared::Placeholders;
+use crate::vm::TxMode;
 use spacetimedb_lib::relation::{extract_table_field, FieldExpr, FieldName};
 use spacetimedb_vm::errors::ErrorVm;
-use spacetimedb_vm::expr::{ColumnOp, DbType, Expr};
-use spacetimedb_vm::operator::{OpCmp, OpLogic, OpQuery};
+use spacetimedb_vm::expr::{
+    option_type, AggregateExpr, AggregateFn, AggregateQuery, AlterTable, ColumnOp, ComputedColumn, ConflictAction,
+    DbType, Expr, LimitExpr, OnConflict, OuterJoin, SetOp, SortKey,
+};
+use spacetimedb_vm::functions::ScalarFn;
+use spacetimedb_vm::operator::{OpCmp, OpLogic, OpMath, OpQuery};
 use spacetimedb_vm::ops::parse::parse;
+use spacetimedb_vm::typecheck::check_column_op;
+use spacetimedb_vm::types::Ty;
 
 /// Simplify to detect features of the syntax we don't support yet
 /// Because we use [PostgreSqlDialect] in the compiler step it already protect against features
@@ -88,10 +100,14 @@ impl Table {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Column {
     /// Any expression, not followed by `[ AS ] alias`
     UnnamedExpr(Expr),
+    /// An aggregate function like `COUNT(*)`, optionally followed by `[ AS ] alias`
+    Aggregate(AggregateExpr),
+    /// An expression computed for each row, like `score * 2` or `lower(name)`, optionally followed by `[ AS ] alias`
+    Computed(ComputedColumn),
     /// An qualified `table.*`
     QualifiedWildcard { table: String },
     /// An unqualified `SELECT *`
@@ -99,9 +115,19 @@ pub enum Column {
 }
 
 /// The list of expressions for `SELECT expr1, expr2...` determining what data to extract.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Selection {
     pub(crate) clauses: Vec<ColumnOp>,
+    pub(crate) subqueries: Vec<Subquery>,
+}
+
+/// A subquery of the `WHERE` clause, as in `field [NOT] IN (SELECT ...)` or `[NOT] EXISTS (SELECT ...)`,
+/// that keeps the rows by the rows the `query` yields.
+#[derive(Clone)]
+pub struct Subquery {
+    pub field: Option<FieldName>,
+    pub query: SqlAst,
+    pub negated: bool,
 }
 
 impl Default for Selection {
@@ -112,7 +138,10 @@ impl Default for Selection {
 
 impl Selection {
     pub fn new() -> Self {
-        Self { clauses: Vec::new() }
+        Self {
+            clauses: Vec::new(),
+            subqueries: Vec::new(),
+        }
     }
 
     pub fn with_cmp(self, op: OpQuery, lhs: ColumnOp, rhs: ColumnOp) -> Self {
@@ -123,15 +152,53 @@ impl Selection {
     }
 }
 
+#[derive(Clone)]
 pub struct OnExpr {
     pub op: OpCmp,
     pub lhs: FieldName,
     pub rhs: FieldName,
 }
 
-/// The `JOIN [INNER] ON join_expr OpCmp join_expr` clause
+impl OnExpr {
+    /// Returns the expression with the field of the joined table `rhs` on the right,
+    /// as in `FROM t1 JOIN t2 ON t1.id = t2.id`, when it's written inverted, as in `ON t2.id = t1.id`.
+    fn orient(self, rhs: &TableSchema) -> Self {
+        if self.lhs.table() == rhs.table_name && self.rhs.table() != rhs.table_name {
+            OnExpr {
+                op: self.op.reverse(),
+                lhs: self.rhs,
+                rhs: self.lhs,
+            }
+        } else {
+            self
+        }
+    }
+}
+
+/// The `[INNER] JOIN`, `LEFT|RIGHT|FULL [OUTER] JOIN` clauses with an `ON join_expr OpCmp join_expr`,
+/// and the `CROSS JOIN` clauses or further tables in `FROM table1, table2`
+#[derive(Clone)]
 pub enum Join {
-    Inner { rhs: TableSchema, on: OnExpr },
+    Inner {
+        rhs: TableSchema,
+        on: OnExpr,
+    },
+    Outer {
+        kind: OuterJoin,
+        rhs: TableSchema,
+        on: OnExpr,
+    },
+    Cross {
+        rhs: TableSchema,
+    },
+}
+
+impl Join {
+    pub fn rhs(&self) -> &TableSchema {
+        match self {
+            Join::Inner { rhs, .. } | Join::Outer { rhs, .. } | Join::Cross { rhs } => rhs,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -140,47 +207,69 @@ pub struct FromField {
     pub column: ColumnDef,
 }
 
-/// The list of tables in `... FROM table1 [JOIN table2] ...`
+/// The list of tables in `... FROM table1 [JOIN table2] ...`,
+/// and the aggregates computed over their rows in `SELECT`, `HAVING` and `ORDER BY`.
+#[derive(Clone)]
 pub struct From {
     pub root: TableSchema,
     pub join: Option<Vec<Join>>,
+    pub aggregates: Vec<AggregateExpr>,
 }
 
 impl From {
     pub fn new(root: TableSchema) -> Self {
-        Self { root, join: None }
+        Self {
+            root,
+            join: None,
+            aggregates: Vec::new(),
+        }
     }
 
-    pub fn with_inner_join(self, rhs: TableSchema, on: OnExpr) -> Self {
+    fn with_join(self, join: Join) -> Self {
         let mut x = self;
+        x.join.get_or_insert_with(Vec::new).push(join);
+        x
+    }
 
-        // Check if the field are inverted:
-        // FROM t1 JOIN t2 ON t2.id = t1.id
-        let on = if on.rhs.table() == x.root.table_name && x.root.get_column_by_field(&on.rhs).is_some() {
-            OnExpr {
-                op: on.op.reverse(),
-                lhs: on.rhs,
-                rhs: on.lhs,
-            }
-        } else {
-            on
-        };
-        if let Some(joins) = &mut x.join {
-            joins.push(Join::Inner { rhs, on })
-        } else {
-            x.join = Some(vec![Join::Inner { rhs, on }])
-        }
+    pub fn with_inner_join(self, rhs: TableSchema, on: OnExpr) -> Self {
+        let on = on.orient(&rhs);
+        self.with_join(Join::Inner { rhs, on })
+    }
 
-        x
+    pub fn with_outer_join(self, kind: OuterJoin, rhs: TableSchema, on: OnExpr) -> Self {
+        let on = on.orient(&rhs);
+        self.with_join(Join::Outer { kind, rhs, on })
+    }
+
+    pub fn with_cross_join(self, rhs: TableSchema) -> Self {
+        self.with_join(Join::Cross { rhs })
     }
 
     /// Returns all the tables, including the ones inside the joins
     pub fn iter_tables(&self) -> impl Iterator<Item = &TableSchema> {
-        [&self.root].into_iter().chain(self.join.iter().flat_map(|x| {
-            x.iter().map(|t| match t {
-                Join::Inner { rhs, .. } => rhs,
-            })
-        }))
+        [&self.root]
+            .into_iter()
+            .chain(self.join.iter().flat_map(|x| x.iter().map(Join::rhs)))
+    }
+
+    /// Whether the rows of the table `name` may be missing from the result,
+    /// because it's on the side of an outer join whose rows are only kept when they match,
+    /// so its columns are options.
+    pub fn is_nullable(&self, name: &str) -> bool {
+        let mut tables = vec![self.root.table_name.as_str()];
+        let mut nullable = Vec::new();
+        for join in self.join.iter().flatten() {
+            if let Join::Outer { kind, rhs, .. } = join {
+                if kind.keeps_rhs() {
+                    nullable.extend(tables.iter().copied());
+                }
+                if kind.keeps_lhs() {
+                    nullable.push(rhs.table_name.as_str());
+                }
+            }
+            tables.push(&join.rhs().table_name);
+        }
+        nullable.contains(&name)
     }
 
     /// Returns all the table names as a `Vec<String>`, including the ones inside the joins.
@@ -190,19 +279,60 @@ impl From {
 
     /// Returns all the fields matching `f` as a `Vec<FromField>`,
     /// including the ones inside the joins.
+    ///
+    /// A field qualified by a table name only matches the fields of that table.
     pub fn find_field(&self, f: &str) -> Result<Vec<FromField>, RelationError> {
         let field = extract_table_field(f)?;
-        let fields = self.iter_tables().filter_map(|t| {
-            let f = t.normalize_field(&field);
-            t.get_column_by_field(&f).map(|column| FromField {
-                field: f,
-                column: column.into(),
-            })
-        });
+        let fields = self
+            .iter_tables()
+            .filter(|t| field.table.map_or(true, |name| name == t.table_name))
+            .filter_map(|t| {
+                let f = t.normalize_field(&field);
+                t.get_column_by_field(&f).map(|column| {
+                    let mut column: ColumnDef = column.into();
+                    let ty = &column.column.algebraic_type;
+                    if option_type(ty).is_none() && self.is_nullable(&t.table_name) {
+                        column.column.algebraic_type = AlgebraicType::option(ty.clone());
+                    }
+                    FromField { field: f, column }
+                })
+            });
 
         Ok(fields.collect())
     }
 
+    /// Returns the aggregate computing `fun` over the column `arg`, or over the rows when `arg` is `None`.
+    pub fn find_aggregate(&self, fun: AggregateFn, arg: Option<&FieldName>) -> Option<&AggregateExpr> {
+        self.aggregates.iter().find(|x| x.fun == fun && x.arg.as_ref() == arg)
+    }
+
+    /// Adds the aggregate `x`, unless it is already computed.
+    pub fn with_aggregate(self, x: AggregateExpr) -> Self {
+        let mut s = self;
+        if !s.aggregates.contains(&x) {
+            s.aggregates.push(x);
+        }
+        s
+    }
+
+    /// Returns the type of the values of `field`, either a column of the tables or an aggregate.
+    pub fn field_type(&self, field: &FieldName) -> Result<AlgebraicType, PlanError> {
+        if let Some(x) = self.aggregates.iter().find(|x| &x.field == field) {
+            let arg = x
+                .arg
+                .as_ref()
+                .map(|arg| self.resolve_field(&arg.to_string()))
+                .transpose()?;
+            return x
+                .fun
+                .result_type(arg.as_ref().map(|arg| &arg.column.column.algebraic_type))
+                .ok_or_else(|| PlanError::Unsupported {
+                    feature: format!("Aggregate `{x}` over values of the type of its column."),
+                });
+        }
+        Ok(self.resolve_field(&field.to_string())?.column.column.algebraic_type)
+    }
+
     /// Checks if the field `named` matches exactly once in all the tables
     /// including the ones inside the joins
     pub fn resolve_field(&self, named: &str) -> Result<FromField, PlanError> {
@@ -227,25 +357,44 @@ impl From {
 }
 
 /// Defines the portions of the `SQL` standard that we support.
+#[derive(Clone)]
 pub enum SqlAst {
     Select {
         from: From,
         project: Vec<Column>,
+        distinct: bool,
         selection: Option<Selection>,
+        aggregate: Option<AggregateQuery>,
+        having: Option<Selection>,
+        order_by: Vec<SortKey>,
+        limit: Option<LimitExpr>,
+    },
+    /// `query UNION | INTERSECT | EXCEPT [ALL] query`, sorted and limited after the rows are combined.
+    SetOperation {
+        op: SetOp,
+        all: bool,
+        lhs: Box<SqlAst>,
+        rhs: Box<SqlAst>,
+        order_by: Vec<SortKey>,
+        limit: Option<LimitExpr>,
     },
     Insert {
         table: TableSchema,
         columns: Vec<FieldName>,
         values: Vec<Vec<FieldExpr>>,
+        on_conflict: Option<OnConflict>,
+        returning: Option<Vec<ComputedColumn>>,
     },
     Update {
         table: TableSchema,
         assignments: HashMap<FieldName, FieldExpr>,
         selection: Option<Selection>,
+        returning: Option<Vec<ComputedColumn>>,
     },
     Delete {
         table: TableSchema,
         selection: Option<Selection>,
+        returning: Option<Vec<ComputedColumn>>,
     },
     CreateTable {
         table: String,
@@ -253,32 +402,34 @@ pub enum SqlAst {
         table_type: StTableType,
         table_access: StAccess,
     },
+    CreateIndex {
+        name: String,
+        table: TableSchema,
+        cols: Vec<u32>,
+        index_type: IndexType,
+        is_unique: bool,
+    },
+    AlterTable {
+        table: TableSchema,
+        alter: AlterTable,
+    },
     Drop {
         name: String,
         kind: DbType,
         table_access: StAccess,
+        if_exists: bool,
+    },
+    Explain {
+        query: Box<SqlAst>,
+        analyze: bool,
     },
-}
-
-fn extract_field(table: &From, of: &SqlExpr) -> Result<Option<ProductTypeElement>, PlanError> {
-    match of {
-        SqlExpr::Identifier(x) => {
-            let f = table.resolve_field(&x.value)?;
-            Ok(Some(f.column.column))
-        }
-        SqlExpr::CompoundIdentifier(ident) => {
-            let col_name = compound_ident(ident);
-            let f = table.resolve_field(&col_name)?;
-            Ok(Some(f.column.column))
-        }
-        _ => Ok(None),
-    }
 }
 
 /// Parses `value` according to the type of the field, as provided by `field`.
 ///
 /// When `field` is `None`, the type is inferred to an integer or float depending on if a `.` separator is present.
 /// The `is_long` parameter decides whether to parse as a 64-bit type or a 32-bit one.
+/// When `field` is of an option type, `value` is parsed as its `some` variant.
 fn infer_number(field: Option<&ProductTypeElement>, value: &str, is_long: bool) -> Result<AlgebraicValue, ErrorVm> {
     match field {
         None => {
@@ -295,12 +446,238 @@ fn infer_number(field: Option<&ProductTypeElement>, value: &str, is_long: bool)
             };
             parse(value, &ty)
         }
-        Some(f) => parse(value, &f.algebraic_type),
+        Some(f) => match option_type(&f.algebraic_type) {
+            Some(ty) => parse(value, ty).map(AlgebraicValue::OptionSome),
+            None => parse(value, &f.algebraic_type),
+        },
+    }
+}
+
+/// Wraps `value` in `some` when it is compared to a `field` of an option type,
+/// like the columns of the side of an outer join that may be missing.
+fn some_if_option(field: Option<&ProductTypeElement>, value: AlgebraicValue) -> AlgebraicValue {
+    match field {
+        Some(f) if option_type(&f.algebraic_type).is_some() => AlgebraicValue::OptionSome(value),
+        _ => value,
+    }
+}
+
+/// Returns the value standing for the placeholder `name`, like `$1`, until its parameter is bound to it,
+/// as a value of the type of `field`, the column it is compared to or assigned to.
+fn compile_placeholder(
+    params: &Placeholders,
+    name: &str,
+    field: Option<&ProductTypeElement>,
+) -> Result<AlgebraicValue, PlanError> {
+    let invalid = |error: String| PlanError::InvalidParam {
+        param: name.to_string(),
+        error,
+    };
+    let pos = name
+        .strip_prefix('$')
+        .and_then(|x| x.parse::<usize>().ok())
+        .filter(|x| *x > 0)
+        .ok_or_else(|| invalid("Expected a placeholder like `$1`.".into()))?;
+    let field =
+        field.ok_or_else(|| invalid("Its type is unknown, as it isn't compared to or assigned to a column.".into()))?;
+    Ok(params.add(pos, field.algebraic_type.clone()))
+}
+
+/// Returns the type of the values `op` computes, checking its operands are of types it can operate on.
+///
+/// The placeholders in `op` are of the type of the parameter they stand for.
+fn type_of(table: &From, params: &Placeholders, op: &ColumnOp) -> Result<Ty, PlanError> {
+    check_column_op(
+        &params.as_fields(op),
+        &|field: &FieldName| match params.field_type(field) {
+            Some(ty) => Ok(ty),
+            None => table.field_type(field),
+        },
+    )
+}
+
+/// Returns `ty` as the type a literal or a parameter is inferred to, if it's known.
+fn type_hint(ty: Ty) -> Option<ProductTypeElement> {
+    match ty {
+        Ty::Val(ty) => Some(ProductTypeElement::new(ty, None)),
+        _ => None,
+    }
+}
+
+/// Returns the type of the `some` variant of `field` when it's of an option type, or its type otherwise.
+fn some_type(field: Option<&ProductTypeElement>) -> Option<ProductTypeElement> {
+    field.map(|f| {
+        let ty = option_type(&f.algebraic_type).unwrap_or(&f.algebraic_type);
+        ProductTypeElement::new(ty.clone(), None)
+    })
+}
+
+/// Returns the arithmetic operator of `op`, if it's one.
+fn math_op(op: &BinaryOperator) -> Option<OpMath> {
+    match op {
+        BinaryOperator::Plus => Some(OpMath::Add),
+        BinaryOperator::Minus => Some(OpMath::Minus),
+        BinaryOperator::Multiply => Some(OpMath::Mul),
+        BinaryOperator::Divide => Some(OpMath::Div),
+        _ => None,
+    }
+}
+
+/// Whether `expr` is a literal or a parameter, whose type is inferred from the expression it's compared to
+/// or operated with.
+fn is_untyped(expr: &SqlExpr) -> bool {
+    match expr {
+        SqlExpr::Value(_) => true,
+        SqlExpr::Nested(x) | SqlExpr::UnaryOp { expr: x, .. } => is_untyped(x),
+        _ => false,
+    }
+}
+
+/// Compiles the operands of a binary operation, inferring the type of a literal or a parameter from the other one,
+/// like in `inventory.id = 1` or `score * 2`, so `1` and `2` get the type of the column.
+///
+/// When both are literals, they get the type of `field`, if any.
+fn compile_operands(
+    table: &From,
+    params: &Placeholders,
+    field: Option<&ProductTypeElement>,
+    lhs: SqlExpr,
+    rhs: SqlExpr,
+) -> Result<(ColumnOp, ColumnOp), PlanError> {
+    let swap = is_untyped(&lhs);
+    let (first, second) = if swap { (rhs, lhs) } else { (lhs, rhs) };
+    let first = compile_expr_value(table, params, field, first)?;
+    let hint = type_hint(type_of(table, params, &first)?);
+    let second = compile_expr_value(table, params, hint.as_ref().or(field), second)?;
+    Ok(if swap { (second, first) } else { (first, second) })
+}
+
+/// Wraps `op` in `NOT` if `negated`, as in `NOT LIKE` or `NOT BETWEEN`.
+fn not_if(negated: bool, op: ColumnOp) -> ColumnOp {
+    if negated {
+        ColumnOp::Not(Box::new(op))
+    } else {
+        op
+    }
+}
+
+/// Compiles a call of the built-in scalar function `fun`, like `lower(name)` or `coalesce(nickname, name)`.
+fn compile_scalar_fn(
+    table: &From,
+    params: &Placeholders,
+    field: Option<&ProductTypeElement>,
+    fun: ScalarFn,
+    f: Function,
+) -> Result<ColumnOp, PlanError> {
+    let Function {
+        name,
+        args,
+        over,
+        distinct,
+        ..
+    } = f;
+    unsupported!("Function", over, distinct);
+
+    let args = args
+        .into_iter()
+        .map(|x| match x {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(x)) => Ok(x),
+            x => Err(PlanError::Unsupported {
+                feature: format!("Unsupported argument {x} for {name}, only expressions are supported."),
+            }),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if fun != ScalarFn::Coalesce {
+        let args = args
+            .into_iter()
+            .map(|x| compile_expr_value(table, params, None, x))
+            .collect::<Result<_, _>>()?;
+        return Ok(ColumnOp::Call { fun, args });
+    }
+
+    // The arguments of `coalesce` are of the same type, so the literals get the type of the others,
+    // but not as options, so `coalesce(nickname, 'anonymous')` is never `none`.
+    let mut hint = None;
+    let mut compiled = Vec::with_capacity(args.len());
+    for x in &args {
+        compiled.push(match is_untyped(x) {
+            true => None,
+            false => {
+                let x = compile_expr_value(table, params, field, x.clone())?;
+                if hint.is_none() {
+                    hint = some_type(type_hint(type_of(table, params, &x)?).as_ref());
+                }
+                Some(x)
+            }
+        });
     }
+    let args = args
+        .into_iter()
+        .zip(compiled)
+        .map(|(x, compiled)| match compiled {
+            Some(x) => Ok(x),
+            None => compile_expr_value(table, params, hint.as_ref().or(field), x),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(ColumnOp::Call { fun, args })
+}
+
+/// Compiles `expr [NOT] LIKE pattern`, or `ILIKE` when `ignore_case`, which compares both lowercased.
+fn compile_like(
+    table: &From,
+    params: &Placeholders,
+    expr: SqlExpr,
+    pattern: SqlExpr,
+    negated: bool,
+    escape_char: Option<char>,
+    ignore_case: bool,
+) -> Result<ColumnOp, PlanError> {
+    unsupported!("LIKE", escape_char);
+
+    let string = ProductTypeElement::new(AlgebraicType::String, None);
+    let (expr, pattern) = compile_operands(table, params, Some(&string), expr, pattern)?;
+    let args = match ignore_case {
+        true => [expr, pattern]
+            .map(|x| ColumnOp::Call {
+                fun: ScalarFn::Lower,
+                args: vec![x],
+            })
+            .into(),
+        false => vec![expr, pattern],
+    };
+    Ok(not_if(
+        negated,
+        ColumnOp::Call {
+            fun: ScalarFn::Like,
+            args,
+        },
+    ))
+}
+
+/// Compiles `expr IS [NOT] NULL`.
+///
+/// Unlike `expr = NULL`, which is unknown, this tells whether `expr` is `none`.
+fn compile_is_null(table: &From, params: &Placeholders, expr: SqlExpr, negated: bool) -> Result<ColumnOp, PlanError> {
+    let args = vec![compile_expr_value(table, params, None, expr)?];
+    Ok(not_if(
+        negated,
+        ColumnOp::Call {
+            fun: ScalarFn::IsNull,
+            args,
+        },
+    ))
 }
 
 /// Compiles a [SqlExpr] expression into a [ColumnOp]
-fn compile_expr_value(table: &From, field: Option<&ProductTypeElement>, of: SqlExpr) -> Result<ColumnOp, PlanError> {
+///
+/// The literals and parameters are of the type of `field`, if any,
+/// which is the type of the expression they are compared to or operated with.
+fn compile_expr_value(
+    table: &From,
+    params: &Placeholders,
+    field: Option<&ProductTypeElement>,
+    of: SqlExpr,
+) -> Result<ColumnOp, PlanError> {
     Ok(ColumnOp::Field(match of {
         SqlExpr::Identifier(name) => FieldExpr::Name(table.resolve_field(&name.value)?.field),
         SqlExpr::CompoundIdentifier(ident) => {
@@ -309,23 +686,106 @@ fn compile_expr_value(table: &From, field: Option<&ProductTypeElement>, of: SqlE
         }
         SqlExpr::Value(x) => FieldExpr::Value(match x {
             Value::Number(value, is_long) => infer_number(field, &value, is_long)?,
-            Value::SingleQuotedString(s) => AlgebraicValue::String(s),
-            Value::DoubleQuotedString(s) => AlgebraicValue::String(s),
-            Value::Boolean(x) => AlgebraicValue::Bool(x),
+            Value::SingleQuotedString(s) => some_if_option(field, AlgebraicValue::String(s)),
+            Value::DoubleQuotedString(s) => some_if_option(field, AlgebraicValue::String(s)),
+            Value::Boolean(x) => some_if_option(field, AlgebraicValue::Bool(x)),
             Value::Null => AlgebraicValue::OptionNone(),
+            Value::Placeholder(name) => compile_placeholder(params, &name, field)?,
             x => {
                 return Err(PlanError::Unsupported {
                     feature: format!("Unsupported value: {x}."),
                 })
             }
         }),
+        SqlExpr::IsNull(x) => return compile_is_null(table, params, *x, false),
+        SqlExpr::IsNotNull(x) => return compile_is_null(table, params, *x, true),
         SqlExpr::BinaryOp { left, op, right } => {
-            let (op, lhs, rhs) = compile_bin_op(table, op, left, right)?;
-
-            return Ok(ColumnOp::cmp(op, lhs, rhs));
+            return match math_op(&op) {
+                Some(op) => {
+                    // The operands are numbers, so the literals are of the type of the number in the option, if any.
+                    let field = some_type(field);
+                    let (lhs, rhs) = compile_operands(table, params, field.as_ref(), *left, *right)?;
+                    Ok(ColumnOp::math(op, lhs, rhs))
+                }
+                None => {
+                    let (op, lhs, rhs) = compile_bin_op(table, params, op, *left, *right)?;
+                    Ok(ColumnOp::cmp(op, lhs, rhs))
+                }
+            };
+        }
+        SqlExpr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => {
+            return Ok(ColumnOp::Not(Box::new(compile_expr_value(table, params, None, *expr)?)));
+        }
+        SqlExpr::UnaryOp {
+            op: UnaryOperator::Plus,
+            expr,
+        } => {
+            return compile_expr_value(table, params, field, *expr);
+        }
+        SqlExpr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match *expr {
+            SqlExpr::Value(Value::Number(value, is_long)) => {
+                FieldExpr::Value(infer_number(field, &format!("-{value}"), is_long)?)
+            }
+            // `-x` is `0 - x`, with `0` of the type of `x`.
+            x => {
+                let x = compile_expr_value(table, params, field, x)?;
+                let zero = SqlExpr::Value(Value::Number("0".into(), false));
+                let zero = compile_expr_value(table, params, type_hint(type_of(table, params, &x)?).as_ref(), zero)?;
+                return Ok(ColumnOp::math(OpMath::Minus, zero, x));
+            }
+        },
+        SqlExpr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let expr = compile_expr_value(table, params, None, *expr)?;
+            let field = type_hint(type_of(table, params, &expr)?);
+            let low = compile_expr_value(table, params, field.as_ref(), *low)?;
+            let high = compile_expr_value(table, params, field.as_ref(), *high)?;
+            let between = ColumnOp::cmp(
+                OpLogic::And.into(),
+                ColumnOp::cmp(OpCmp::GtEq.into(), expr.clone(), low),
+                ColumnOp::cmp(OpCmp::LtEq.into(), expr, high),
+            );
+            return Ok(not_if(negated, between));
+        }
+        SqlExpr::Like {
+            negated,
+            expr,
+            pattern,
+            escape_char,
+        } => {
+            return compile_like(table, params, *expr, *pattern, negated, escape_char, false);
+        }
+        SqlExpr::ILike {
+            negated,
+            expr,
+            pattern,
+            escape_char,
+        } => {
+            return compile_like(table, params, *expr, *pattern, negated, escape_char, true);
         }
         SqlExpr::Nested(x) => {
-            return compile_expr_value(table, field, *x);
+            return compile_expr_value(table, params, field, *x);
+        }
+        SqlExpr::Function(f) => match ScalarFn::from_name(&f.name.to_string()) {
+            Some(fun) => return compile_scalar_fn(table, params, field, fun, f),
+            None => FieldExpr::Name(compile_aggregate_field(table, f)?),
+        },
+        x @ (SqlExpr::InSubquery { .. } | SqlExpr::Exists { .. } | SqlExpr::Subquery(_)) => {
+            return Err(PlanError::Unsupported {
+                feature: format!(
+                    "Subquery {x}, only `IN` and `EXISTS` joined to the `WHERE` conditions by `AND` are supported."
+                ),
+            })
         }
         x => {
             return Err(PlanError::Unsupported {
@@ -335,8 +795,13 @@ fn compile_expr_value(table: &From, field: Option<&ProductTypeElement>, of: SqlE
     }))
 }
 
-fn compile_expr_field(table: &From, field: Option<&ProductTypeElement>, of: SqlExpr) -> Result<FieldExpr, PlanError> {
-    match compile_expr_value(table, field, of)? {
+fn compile_expr_field(
+    table: &From,
+    params: &Placeholders,
+    field: Option<&ProductTypeElement>,
+    of: SqlExpr,
+) -> Result<FieldExpr, PlanError> {
+    match compile_expr_value(table, params, field, of)? {
         ColumnOp::Field(field) => Ok(field),
         x => Err(PlanError::Unsupported {
             feature: format!("Complex expression {x} on insert..."),
@@ -366,9 +831,10 @@ fn compile_table_factor(table: TableFactor) -> Result<Table, PlanError> {
 /// Compiles a binary operation like `field > 1`
 fn compile_bin_op(
     table: &From,
+    params: &Placeholders,
     op: BinaryOperator,
-    lhs: Box<sqlparser::ast::Expr>,
-    rhs: Box<sqlparser::ast::Expr>,
+    lhs: SqlExpr,
+    rhs: SqlExpr,
 ) -> Result<(OpQuery, ColumnOp, ColumnOp), PlanError> {
     let op: OpQuery = match op {
         BinaryOperator::Gt => OpCmp::Gt.into(),
@@ -386,35 +852,136 @@ fn compile_bin_op(
         }
     };
 
-    let field_lhs = extract_field(table, &lhs)?;
-    let field_rhs = extract_field(table, &rhs)?;
-    // This inversion is for inferring the type of the right side, like in `inventory.id = 1`,
-    // so `1` get the type of `inventory.id`
-    let lhs = compile_expr_value(table, field_rhs.as_ref(), *lhs)?;
-    let rhs = compile_expr_value(table, field_lhs.as_ref(), *rhs)?;
+    let (lhs, rhs) = compile_operands(table, params, None, lhs, rhs)?;
 
     Ok((op, lhs, rhs))
 }
 
-fn _compile_where(table: &From, filter: SqlExpr, selection: Selection) -> Result<Option<Selection>, PlanError> {
-    match filter {
-        SqlExpr::BinaryOp { left, op, right } => {
-            let (op, lhs, rhs) = compile_bin_op(table, op, left, right)?;
+/// Whether `expr` has a subquery, as in `field IN (SELECT ...)`.
+fn has_subquery(expr: &SqlExpr) -> bool {
+    match expr {
+        SqlExpr::InSubquery { .. } | SqlExpr::Exists { .. } | SqlExpr::Subquery(_) => true,
+        SqlExpr::BinaryOp { left, right, .. } => has_subquery(left) || has_subquery(right),
+        SqlExpr::Nested(x) | SqlExpr::UnaryOp { expr: x, .. } => has_subquery(x),
+        _ => false,
+    }
+}
 
-            Ok(Some(selection.with_cmp(op, lhs, rhs)))
+/// Compiles the subquery of `field IN (SELECT ...)`, which must yield a single column of the type of `field`.
+fn compile_in_subquery(
+    db: &RelationalDB,
+    tx: &TxMode,
+    params: &Placeholders,
+    table: &From,
+    expr: SqlExpr,
+    subquery: Query,
+    negated: bool,
+) -> Result<Subquery, PlanError> {
+    let field = match expr {
+        SqlExpr::Identifier(name) => table.resolve_field(&name.value)?,
+        SqlExpr::CompoundIdentifier(ident) => table.resolve_field(&compound_ident(&ident))?,
+        x => {
+            return Err(PlanError::Unsupported {
+                feature: format!("Unsupported in IN: {x}, only columns are supported."),
+            })
         }
-        SqlExpr::Nested(x) => _compile_where(table, *x, selection),
-        x => Err(PlanError::Unsupported {
-            feature: format!("Unsupported in WHERE: {x}."),
-        }),
+    };
+    let query = compile_query(db, tx, params, subquery)?;
+    let columns = query_columns(&query)?;
+    let [(_, ty)] = columns.as_slice() else {
+        return Err(PlanError::Unstructured(format!(
+            "The subquery of `{} IN` must yield a single column, found {}.",
+            field.field,
+            columns.len()
+        )));
+    };
+    let field_ty = &field.column.column.algebraic_type;
+    if option_type(field_ty).unwrap_or(field_ty) != option_type(ty).unwrap_or(ty) {
+        return Err(PlanError::Unstructured(format!(
+            "The subquery of `{} IN` must yield values of the type of the column.",
+            field.field
+        )));
+    }
+    Ok(Subquery {
+        field: Some(field.field),
+        query,
+        negated,
+    })
+}
+
+fn _compile_where(
+    db: &RelationalDB,
+    tx: &TxMode,
+    params: &Placeholders,
+    table: &From,
+    filter: SqlExpr,
+    selection: Selection,
+) -> Result<Option<Selection>, PlanError> {
+    match filter {
+        // The subqueries are evaluated apart from the other conditions, so they can only be joined to them by `AND`.
+        SqlExpr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } if has_subquery(&left) || has_subquery(&right) => {
+            let selection = _compile_where(db, tx, params, table, *left, selection)?.unwrap_or_default();
+            _compile_where(db, tx, params, table, *right, selection)
+        }
+        SqlExpr::Nested(x) => _compile_where(db, tx, params, table, *x, selection),
+        SqlExpr::InSubquery {
+            expr,
+            subquery,
+            negated,
+        } => {
+            let mut selection = selection;
+            let subquery = compile_in_subquery(db, tx, params, table, *expr, *subquery, negated)?;
+            selection.subqueries.push(subquery);
+            Ok(Some(selection))
+        }
+        SqlExpr::Exists { subquery, negated } => {
+            let mut selection = selection;
+            selection.subqueries.push(Subquery {
+                field: None,
+                query: compile_query(db, tx, params, *subquery)?,
+                negated,
+            });
+            Ok(Some(selection))
+        }
+        x => {
+            let mut selection = selection;
+            let clause = compile_expr_value(table, params, None, x)?;
+            check_condition(table, params, &clause)?;
+            selection.clauses.push(clause);
+            Ok(Some(selection))
+        }
+    }
+}
+
+/// Checks the condition `op` of a `WHERE` or `HAVING` clause yields a `bool`.
+fn check_condition(table: &From, params: &Placeholders, op: &ColumnOp) -> Result<(), PlanError> {
+    match type_of(table, params, op)? {
+        Ty::Val(ty) if option_type(&ty).unwrap_or(&ty) == &AlgebraicType::Bool => Ok(()),
+        // As in `WHERE NULL`, which keeps no rows.
+        Ty::Unknown => Ok(()),
+        ty => Err(PlanError::Unstructured(format!(
+            "The condition `{op}` must be a `bool`, found: {ty}."
+        ))),
     }
 }
 
 /// Compiles the `WHERE` clause
-fn compile_where(table: &From, filter: Option<SqlExpr>) -> Result<Option<Selection>, PlanError> {
+///
+/// Its subqueries are compiled on their own, so they can't refer to the columns of the outer query.
+fn compile_where(
+    db: &RelationalDB,
+    tx: &TxMode,
+    params: &Placeholders,
+    table: &From,
+    filter: Option<SqlExpr>,
+) -> Result<Option<Selection>, PlanError> {
     if let Some(filter) = filter {
         let selection = Selection::new();
-        _compile_where(table, filter, selection)
+        _compile_where(db, tx, params, table, filter, selection)
     } else {
         Ok(None)
     }
@@ -423,25 +990,36 @@ fn compile_where(table: &From, filter: Option<SqlExpr>) -> Result<Option<Selecti
 /// Retrieves the [TableSchema] for the [Table]
 ///
 /// Fails if the table `name` and/or `table_id` is not found
-fn find_table(db: &RelationalDB, tx: &MutTxId, t: Table) -> Result<TableSchema, PlanError> {
-    let table_id = db
-        .table_id_from_name(tx, &t.name)?
-        .ok_or(PlanError::UnknownTable { table: t.name.clone() })?;
-    if !db.inner.table_id_exists(tx, &TableId(table_id)) {
+fn find_table(db: &RelationalDB, tx: &TxMode, t: Table) -> Result<TableSchema, PlanError> {
+    let table_id = match tx {
+        TxMode::MutTx(tx) => db.table_id_from_name(tx, &t.name)?,
+        TxMode::Tx(tx) => db.table_id_from_name_tx(tx, &t.name)?,
+    }
+    .ok_or(PlanError::UnknownTable { table: t.name.clone() })?;
+    let exists = match tx {
+        TxMode::MutTx(tx) => db.inner.table_id_exists(tx, &TableId(table_id)),
+        TxMode::Tx(tx) => db.inner.table_id_exists_tx(tx, &TableId(table_id)),
+    };
+    if !exists {
         return Err(PlanError::UnknownTable { table: t.name });
     }
-    db.schema_for_table(tx, table_id)
-        .map_err(|e| PlanError::DatabaseInternal(Box::new(e)))
+    match tx {
+        TxMode::MutTx(tx) => db.schema_for_table(tx, table_id),
+        TxMode::Tx(tx) => db.schema_for_table_tx(tx, table_id),
+    }
+    .map_err(|e| PlanError::DatabaseInternal(Box::new(e)))
 }
 
 /// Compiles the `FROM` clause
-fn compile_from(db: &RelationalDB, tx: &MutTxId, from: &[TableWithJoins]) -> Result<From, PlanError> {
-    if from.len() > 1 {
-        return Err(PlanError::Unsupported {
-            feature: "Multiple tables in `FROM`.".into(),
-        });
-    }
-
+///
+/// The tables after the first in `FROM table1, table2` are cross joined,
+/// and the planner turns them into inner joins when the `WHERE` clause compares their columns.
+fn compile_from(
+    db: &RelationalDB,
+    tx: &TxMode,
+    params: &Placeholders,
+    from: &[TableWithJoins],
+) -> Result<From, PlanError> {
     let root_table = match from.first() {
         Some(root_table) => root_table,
         None => {
@@ -453,66 +1031,79 @@ fn compile_from(db: &RelationalDB, tx: &MutTxId, from: &[TableWithJoins]) -> Res
     let base = find_table(db, tx, t)?;
     let mut base = From::new(base);
 
-    for join in &root_table.joins {
-        match &join.join_operator {
-            JoinOperator::Inner(constraint) => {
-                let t = compile_table_factor(join.relation.clone())?;
-                let join = find_table(db, tx, t)?;
-
-                match constraint {
-                    JoinConstraint::On(x) => {
-                        let expr = compile_expr_value(&base, None, x.clone())?;
-                        match expr {
-                            ColumnOp::Field(_) => {}
-                            ColumnOp::Cmp { op, lhs, rhs } => {
-                                let op = match op {
-                                    OpQuery::Cmp(op) => op,
-                                    OpQuery::Logic(op) => {
-                                        return Err(PlanError::Unsupported {
-                                            feature: format!("Can't use operator {op} on JOIN clause"),
-                                        });
-                                    }
-                                };
-                                let (lhs, rhs) = match (*lhs, *rhs) {
-                                    (ColumnOp::Field(FieldExpr::Name(lhs)), ColumnOp::Field(FieldExpr::Name(rhs))) => {
-                                        (lhs, rhs)
-                                    }
-                                    (lhs, rhs) => {
-                                        return Err(PlanError::Unsupported {
-                                            feature: format!(
-                                                "Can't compare non-field expressions {lhs} and {rhs} in JOIN clause"
-                                            ),
-                                        });
-                                    }
-                                };
-
-                                base = base.with_inner_join(join, OnExpr { op, lhs, rhs })
-                            }
-                        }
-                    }
-                    x => {
-                        return Err(PlanError::Unsupported {
-                            feature: format!("JOIN constrain {x:?} is not valid, can be only on the form Table.Field [Cmp] Table.Field"),
-                        });
-                    }
+    for (pos, table) in from.iter().enumerate() {
+        if pos > 0 {
+            let t = compile_table_factor(table.relation.clone())?;
+            base = base.with_cross_join(find_table(db, tx, t)?);
+        }
+        for join in &table.joins {
+            let t = compile_table_factor(join.relation.clone())?;
+            let rhs = find_table(db, tx, t)?;
+            let (kind, constraint) = match &join.join_operator {
+                JoinOperator::Inner(constraint) => (None, constraint),
+                JoinOperator::LeftOuter(constraint) => (Some(OuterJoin::Left), constraint),
+                JoinOperator::RightOuter(constraint) => (Some(OuterJoin::Right), constraint),
+                JoinOperator::FullOuter(constraint) => (Some(OuterJoin::Full), constraint),
+                JoinOperator::CrossJoin => {
+                    base = base.with_cross_join(rhs);
+                    continue;
                 }
-            }
-            x => {
-                return Err(PlanError::Unsupported {
-                    feature: format!("Unsupported JOIN operator: `{x:?}`"),
-                });
-            }
+                x => {
+                    return Err(PlanError::Unsupported {
+                        feature: format!("Unsupported JOIN operator: `{x:?}`"),
+                    });
+                }
+            };
+            let on = compile_join_on(&base.clone().with_cross_join(rhs.clone()), params, constraint)?;
+            base = match kind {
+                None => base.with_inner_join(rhs, on),
+                Some(kind) => base.with_outer_join(kind, rhs, on),
+            };
         }
     }
 
     Ok(base)
 }
 
+/// Compiles the `ON Table.Field = Table.Field` constraint of a `JOIN`, with the fields of the tables in `table`.
+fn compile_join_on(table: &From, params: &Placeholders, constraint: &JoinConstraint) -> Result<OnExpr, PlanError> {
+    let JoinConstraint::On(x) = constraint else {
+        return Err(PlanError::Unsupported {
+            feature: format!(
+                "JOIN constrain {constraint:?} is not valid, can be only on the form Table.Field [Cmp] Table.Field"
+            ),
+        });
+    };
+    match compile_expr_value(table, params, None, x.clone())? {
+        ColumnOp::Cmp { op, lhs, rhs } => {
+            let op = match op {
+                OpQuery::Cmp(op) => op,
+                OpQuery::Logic(op) => {
+                    return Err(PlanError::Unsupported {
+                        feature: format!("Can't use operator {op} on JOIN clause"),
+                    });
+                }
+            };
+            match (*lhs, *rhs) {
+                (ColumnOp::Field(FieldExpr::Name(lhs)), ColumnOp::Field(FieldExpr::Name(rhs))) => {
+                    Ok(OnExpr { op, lhs, rhs })
+                }
+                (lhs, rhs) => Err(PlanError::Unsupported {
+                    feature: format!("Can't compare non-field expressions {lhs} and {rhs} in JOIN clause"),
+                }),
+            }
+        }
+        x => Err(PlanError::Unsupported {
+            feature: format!("JOIN constrain {x} is not valid, can be only on the form Table.Field [Cmp] Table.Field"),
+        }),
+    }
+}
+
 fn compound_ident(ident: &[Ident]) -> String {
     ident.iter().map(ToString::to_string).collect::<Vec<_>>().join(".")
 }
 
-fn compile_select_item(from: &From, select_item: SelectItem) -> Result<Column, PlanError> {
+fn compile_select_item(from: &From, params: &Placeholders, select_item: SelectItem) -> Result<Column, PlanError> {
     match select_item {
         SelectItem::UnnamedExpr(expr) => match expr {
             sqlparser::ast::Expr::Identifier(ident) => {
@@ -526,7 +1117,7 @@ fn compile_select_item(from: &From, select_item: SelectItem) -> Result<Column, P
                 Ok(Column::UnnamedExpr(Expr::Ident(col_name)))
             }
             sqlparser::ast::Expr::Value(_) => {
-                let value = compile_expr_value(from, None, expr)?;
+                let value = compile_expr_value(from, params, None, expr)?;
                 match value {
                     ColumnOp::Field(value) => match value {
                         FieldExpr::Name(_) => Err(PlanError::Unsupported {
@@ -539,14 +1130,14 @@ fn compile_select_item(from: &From, select_item: SelectItem) -> Result<Column, P
                     }),
                 }
             }
-            sqlparser::ast::Expr::Nested(x) => compile_select_item(from, SelectItem::UnnamedExpr(*x)),
-            _ => Err(PlanError::Unsupported {
-                feature: "Only columns names & scalars are supported.".into(),
-            }),
+            sqlparser::ast::Expr::Nested(x) => compile_select_item(from, params, SelectItem::UnnamedExpr(*x)),
+            sqlparser::ast::Expr::Function(f) if is_aggregate_call(&f) => compile_aggregate(from, f, None),
+            x => compile_computed(from, params, x, None).map(Column::Computed),
+        },
+        SelectItem::ExprWithAlias { expr, alias } => match expr {
+            sqlparser::ast::Expr::Function(f) if is_aggregate_call(&f) => compile_aggregate(from, f, Some(alias)),
+            x => compile_computed(from, params, x, Some(alias)).map(Column::Computed),
         },
-        SelectItem::ExprWithAlias { expr: _, alias: _ } => Err(PlanError::Unsupported {
-            feature: "ExprWithAlias".into(),
-        }),
         SelectItem::QualifiedWildcard(ident, _) => Ok(Column::QualifiedWildcard {
             table: ident.to_string(),
         }),
@@ -554,63 +1145,423 @@ fn compile_select_item(from: &From, select_item: SelectItem) -> Result<Column, P
     }
 }
 
+/// Compiles an expression of `SELECT` computed for each row, like `score * 2`,
+/// named by its `alias` if any, or by the text of the expression otherwise.
+fn compile_computed(
+    from: &From,
+    params: &Placeholders,
+    expr: SqlExpr,
+    alias: Option<Ident>,
+) -> Result<ComputedColumn, PlanError> {
+    let name = alias.map(|x| x.value).unwrap_or_else(|| expr.to_string());
+    let expr = compile_expr_value(from, params, None, expr)?;
+    let Ty::Val(ty) = type_of(from, params, &expr)? else {
+        return Err(PlanError::Unstructured(format!(
+            "The type of `{name}` can't be inferred."
+        )));
+    };
+    let field = FieldName::named(&from.root.table_name, &name);
+    Ok(ComputedColumn { expr, field, ty })
+}
+
+/// Whether `f` calls an aggregate function, as opposed to a built-in scalar function like `lower`.
+fn is_aggregate_call(f: &Function) -> bool {
+    ScalarFn::from_name(&f.name.to_string()).is_none()
+}
+
+/// Compiles a call of an aggregate function, like `COUNT(*)` or `SUM(field)`,
+/// into the function and the column it aggregates, if any.
+fn compile_aggregate_call(table: &From, f: Function) -> Result<(AggregateFn, Option<FromField>), PlanError> {
+    let Function {
+        name,
+        args,
+        over,
+        distinct,
+        ..
+    } = f;
+    unsupported!("Aggregate", over, distinct);
+
+    let fun = match name.to_string().to_lowercase().as_str() {
+        "count" => AggregateFn::Count,
+        "sum" => AggregateFn::Sum,
+        "min" => AggregateFn::Min,
+        "max" => AggregateFn::Max,
+        "avg" => AggregateFn::Avg,
+        _ => {
+            return Err(PlanError::Unsupported {
+                feature: format!("Unsupported function: {name}, only COUNT, SUM, MIN, MAX & AVG are supported."),
+            })
+        }
+    };
+    let arg = match args.as_slice() {
+        [FunctionArg::Unnamed(FunctionArgExpr::Wildcard)] if fun == AggregateFn::Count => None,
+        [FunctionArg::Unnamed(FunctionArgExpr::Expr(SqlExpr::Identifier(ident)))] => {
+            Some(table.resolve_field(&ident.value)?)
+        }
+        [FunctionArg::Unnamed(FunctionArgExpr::Expr(SqlExpr::CompoundIdentifier(ident)))] => {
+            Some(table.resolve_field(&compound_ident(ident))?)
+        }
+        _ => {
+            return Err(PlanError::Unsupported {
+                feature: format!("Unsupported arguments for {name}, only a column, or `*` for COUNT, is supported."),
+            })
+        }
+    };
+    Ok((fun, arg))
+}
+
+/// Compiles a call of an aggregate function in `SELECT`, named by its `alias` if any, as in `COUNT(*) AS total`.
+fn compile_aggregate(table: &From, f: Function, alias: Option<Ident>) -> Result<Column, PlanError> {
+    let (fun, arg) = compile_aggregate_call(table, f)?;
+    let arg = arg.map(|x| x.field);
+    let name = match (alias, &arg) {
+        (Some(alias), _) => alias.value,
+        (None, Some(arg)) => format!("{fun}({})", arg.field()),
+        (None, None) => format!("{fun}(*)"),
+    };
+    let field = FieldName::named(&table.root.table_name, &name);
+    Ok(Column::Aggregate(AggregateExpr { fun, arg, field }))
+}
+
+/// Compiles a call of an aggregate function outside `SELECT` into the field of its result,
+/// which must have been collected by [collect_aggregates].
+fn compile_aggregate_field(table: &From, f: Function) -> Result<FieldName, PlanError> {
+    let call = f.to_string();
+    let (fun, arg) = compile_aggregate_call(table, f)?;
+    match table.find_aggregate(fun, arg.as_ref().map(|x| &x.field)) {
+        Some(x) => Ok(x.field.clone()),
+        None => Err(PlanError::Unsupported {
+            feature: format!("Aggregate {call} outside of SELECT, HAVING or ORDER BY."),
+        }),
+    }
+}
+
+/// Adds to `table` the aggregates called in the expression `of`, that are not yet computed for `SELECT`.
+fn collect_aggregates(table: From, of: &SqlExpr) -> Result<From, PlanError> {
+    match of {
+        SqlExpr::Function(f) if !is_aggregate_call(f) => f.args.iter().try_fold(table, |table, x| match x {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(x)) => collect_aggregates(table, x),
+            _ => Ok(table),
+        }),
+        SqlExpr::Function(f) => {
+            let (fun, arg) = compile_aggregate_call(&table, f.clone())?;
+            let arg = arg.map(|x| x.field);
+            if table.find_aggregate(fun, arg.as_ref()).is_some() {
+                return Ok(table);
+            }
+            match compile_aggregate(&table, f.clone(), None)? {
+                Column::Aggregate(x) => Ok(table.with_aggregate(x)),
+                _ => unreachable!("Expected an aggregate"),
+            }
+        }
+        SqlExpr::BinaryOp { left, right, .. }
+        | SqlExpr::Like {
+            expr: left,
+            pattern: right,
+            ..
+        }
+        | SqlExpr::ILike {
+            expr: left,
+            pattern: right,
+            ..
+        } => collect_aggregates(collect_aggregates(table, left)?, right),
+        SqlExpr::Between { expr, low, high, .. } => {
+            collect_aggregates(collect_aggregates(collect_aggregates(table, expr)?, low)?, high)
+        }
+        SqlExpr::Nested(x) | SqlExpr::UnaryOp { expr: x, .. } | SqlExpr::IsNull(x) | SqlExpr::IsNotNull(x) => {
+            collect_aggregates(table, x)
+        }
+        _ => Ok(table),
+    }
+}
+
+/// Compiles the `GROUP BY ...` clause
+fn compile_group_by(table: &From, group_by: Vec<SqlExpr>) -> Result<Vec<FieldName>, PlanError> {
+    let mut fields = Vec::with_capacity(group_by.len());
+    for expr in group_by {
+        let field = match expr {
+            SqlExpr::Identifier(name) => table.resolve_field(&name.value)?.field,
+            SqlExpr::CompoundIdentifier(ident) => table.resolve_field(&compound_ident(&ident))?.field,
+            x => {
+                return Err(PlanError::Unsupported {
+                    feature: format!("Unsupported in GROUP BY: {x}, only columns are supported."),
+                })
+            }
+        };
+        if !fields.contains(&field) {
+            fields.push(field);
+        }
+    }
+    Ok(fields)
+}
+
+/// Compiles the `ORDER BY ...` clause
+///
+/// Besides columns, the rows can be sorted by aggregates, either by calling them or by their alias.
+fn compile_order_by(table: &From, order_by: Vec<OrderByExpr>) -> Result<Vec<SortKey>, PlanError> {
+    let mut keys = Vec::with_capacity(order_by.len());
+    for OrderByExpr { expr, asc, nulls_first } in order_by {
+        unsupported!("ORDER BY", nulls_first);
+
+        let field = match expr {
+            SqlExpr::Identifier(name) => match table.resolve_field(&name.value) {
+                Ok(f) => f.field,
+                Err(err) => table
+                    .aggregates
+                    .iter()
+                    .find(|x| x.field.field_name() == Some(&name.value))
+                    .ok_or(err)?
+                    .field
+                    .clone(),
+            },
+            SqlExpr::CompoundIdentifier(ident) => table.resolve_field(&compound_ident(&ident))?.field,
+            SqlExpr::Function(f) => compile_aggregate_field(table, f)?,
+            x => {
+                return Err(PlanError::Unsupported {
+                    feature: format!("Unsupported in ORDER BY: {x}, only columns & aggregates are supported."),
+                })
+            }
+        };
+        keys.push(SortKey::new(FieldExpr::Name(field), asc.unwrap_or(true)));
+    }
+    Ok(keys)
+}
+
+/// Compiles the row count of a `LIMIT` or `OFFSET` clause, which must be a non-negative integer.
+fn compile_row_count(clause: &str, of: SqlExpr) -> Result<usize, PlanError> {
+    match of {
+        SqlExpr::Value(Value::Number(value, _)) => value.parse().map_err(|_| {
+            PlanError::Unstructured(format!("`{clause}` must be a non-negative integer, found: {value}."))
+        }),
+        x => Err(PlanError::Unsupported {
+            feature: format!("Unsupported in {clause}: {x}, only integers are supported."),
+        }),
+    }
+}
+
+/// Compiles the `LIMIT ... OFFSET ...` clauses
+fn compile_limit(limit: Option<SqlExpr>, offset: Option<Offset>) -> Result<Option<LimitExpr>, PlanError> {
+    let limit = limit.map(|limit| compile_row_count("LIMIT", limit)).transpose()?;
+    let offset = match offset {
+        Some(offset) => compile_row_count("OFFSET", offset.value)?,
+        None => 0,
+    };
+    Ok((limit.is_some() || offset > 0).then_some(LimitExpr { offset, limit }))
+}
+
 /// Compiles the `SELECT ...` clause
-fn compile_select(db: &RelationalDB, tx: &MutTxId, select: Select) -> Result<SqlAst, PlanError> {
-    let from = compile_from(db, tx, &select.from)?;
+fn compile_select(
+    db: &RelationalDB,
+    tx: &TxMode,
+    params: &Placeholders,
+    select: Select,
+    distinct: bool,
+    order_by: Vec<OrderByExpr>,
+    limit: Option<LimitExpr>,
+) -> Result<SqlAst, PlanError> {
+    let mut from = compile_from(db, tx, params, &select.from)?;
+    // The `WHERE` is compiled before collecting the aggregates, as it filters the rows they are computed over.
+    let selection = compile_where(db, tx, params, &from, select.selection)?;
+
     // SELECT ...
     let mut project = Vec::new();
     for select_item in select.projection {
-        let col = compile_select_item(&from, select_item)?;
+        // The aggregates inside a computed expression, as in `SUM(score) * 2`, are computed before it.
+        if let SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } = &select_item {
+            if !matches!(expr, SqlExpr::Function(f) if is_aggregate_call(f)) {
+                from = collect_aggregates(from, expr)?;
+            }
+        }
+        let col = compile_select_item(&from, params, select_item)?;
+        if let Column::Aggregate(x) = &col {
+            from = from.with_aggregate(x.clone());
+        }
         project.push(col);
     }
+    for expr in select.having.iter().chain(order_by.iter().map(|x| &x.expr)) {
+        from = collect_aggregates(from, expr)?;
+    }
 
-    let selection = compile_where(&from, select.selection)?;
+    let group_by = compile_group_by(&from, select.group_by)?;
+    let aggregate = (!group_by.is_empty() || !from.aggregates.is_empty()).then(|| AggregateQuery {
+        group_by,
+        aggregates: from.aggregates.clone(),
+    });
+    if aggregate.is_none() && select.having.is_some() {
+        return Err(PlanError::Unstructured(
+            "`HAVING` requires a `GROUP BY` or an aggregate function.".into(),
+        ));
+    }
+    let having = compile_where(db, tx, params, &from, select.having)?;
+    if having.iter().any(|x| !x.subqueries.is_empty()) {
+        return Err(PlanError::Unsupported {
+            feature: "Subqueries in `HAVING`.".into(),
+        });
+    }
+    let order_by = compile_order_by(&from, order_by)?;
 
     Ok(SqlAst::Select {
         from,
         project,
+        distinct,
         selection,
+        aggregate,
+        having,
+        order_by,
+        limit,
     })
 }
 
-/// Compiles any `query` clause (currently only `SELECT...`)
-fn compile_query(db: &RelationalDB, tx: &MutTxId, query: Query) -> Result<SqlAst, PlanError> {
-    unsupported!(
-        "SELECT",
-        query.order_by,
-        query.fetch,
-        query.limit,
-        query.offset,
-        query.locks,
-        query.with
-    );
+/// Returns the columns of the rows yielded by the query `ast`, with the field each one is named by, if any,
+/// and its type.
+fn query_columns(ast: &SqlAst) -> Result<Vec<(Option<FieldName>, AlgebraicType)>, PlanError> {
+    let (from, project) = match ast {
+        SqlAst::Select { from, project, .. } => (from, project),
+        SqlAst::SetOperation { lhs, .. } => return query_columns(lhs),
+        _ => unreachable!("Only queries yield rows"),
+    };
+    let mut columns = Vec::new();
+    for column in project {
+        match column {
+            Column::UnnamedExpr(Expr::Ident(name)) => {
+                let f = from.resolve_field(name)?;
+                columns.push((Some(f.field), f.column.column.algebraic_type));
+            }
+            Column::UnnamedExpr(Expr::Value(x)) => columns.push((None, x.type_of())),
+            Column::UnnamedExpr(x) => unreachable!("Wrong expression in SQL query {:?}", x),
+            Column::Aggregate(x) => columns.push((Some(x.field.clone()), from.field_type(&x.field)?)),
+            Column::Computed(x) => columns.push((Some(x.field.clone()), x.ty.clone())),
+            Column::QualifiedWildcard { .. } | Column::Wildcard => {
+                let qualifier = match column {
+                    Column::QualifiedWildcard { table } => Some(table),
+                    _ => None,
+                };
+                for t in from.iter_tables() {
+                    if qualifier.map_or(false, |name| name != &t.table_name) {
+                        continue;
+                    }
+                    for c in &t.columns {
+                        let ty = if option_type(&c.col_type).is_none() && from.is_nullable(&t.table_name) {
+                            AlgebraicType::option(c.col_type.clone())
+                        } else {
+                            c.col_type.clone()
+                        };
+                        columns.push((Some(FieldName::named(&t.table_name, &c.col_name)), ty));
+                    }
+                }
+            }
+        }
+    }
+    Ok(columns)
+}
+
+/// Returns the tables of the leftmost `SELECT` of `ast`, which names the columns of a set operation.
+fn leftmost_from(ast: &SqlAst) -> &From {
+    match ast {
+        SqlAst::Select { from, .. } => from,
+        SqlAst::SetOperation { lhs, .. } => leftmost_from(lhs),
+        _ => unreachable!("Only queries yield rows"),
+    }
+}
+
+/// Compiles the `query1 UNION | INTERSECT | EXCEPT [ALL] query2` clause
+///
+/// Both queries must yield the same number of columns, of the same types,
+/// and the combined rows can only be sorted by the columns of the leftmost query.
+#[allow(clippy::too_many_arguments)]
+fn compile_set_operation(
+    db: &RelationalDB,
+    tx: &TxMode,
+    params: &Placeholders,
+    op: SetOperator,
+    set_quantifier: SetQuantifier,
+    left: SetExpr,
+    right: SetExpr,
+    order_by: Vec<OrderByExpr>,
+    limit: Option<LimitExpr>,
+) -> Result<SqlAst, PlanError> {
+    let op = match op {
+        SetOperator::Union => SetOp::Union,
+        SetOperator::Intersect => SetOp::Intersect,
+        SetOperator::Except => SetOp::Except,
+    };
+    let all = match set_quantifier {
+        SetQuantifier::All => true,
+        SetQuantifier::Distinct | SetQuantifier::None => false,
+    };
+    let lhs = compile_query_body(db, tx, params, left, Vec::new(), None)?;
+    let rhs = compile_query_body(db, tx, params, right, Vec::new(), None)?;
+
+    let (lhs_columns, rhs_columns) = (query_columns(&lhs)?, query_columns(&rhs)?);
+    if lhs_columns.len() != rhs_columns.len() {
+        return Err(PlanError::Unstructured(format!(
+            "Each query of `{op}` must yield the same number of columns, found {} and {}.",
+            lhs_columns.len(),
+            rhs_columns.len()
+        )));
+    }
+    if let Some(((field, _), _)) = lhs_columns
+        .iter()
+        .zip(&rhs_columns)
+        .find(|((_, lhs), (_, rhs))| lhs != rhs)
+    {
+        let column = field.as_ref().map(|x| x.to_string()).unwrap_or_default();
+        return Err(PlanError::Unstructured(format!(
+            "Each query of `{op}` must yield columns of the same types, found a different one at `{column}`."
+        )));
+    }
+
+    let order_by = compile_order_by(leftmost_from(&lhs), order_by)?;
+    for key in &order_by {
+        if let FieldExpr::Name(field) = &key.field {
+            if !lhs_columns.iter().any(|(x, _)| x.as_ref() == Some(field)) {
+                return Err(PlanError::Unstructured(format!(
+                    "`ORDER BY` of `{op}` must be by one of its columns, found `{field}`."
+                )));
+            }
+        }
+    }
+
+    Ok(SqlAst::SetOperation {
+        op,
+        all,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+        order_by,
+        limit,
+    })
+}
 
-    match *query.body {
+/// Compiles the `body` of a query, sorted by `order_by` and limited by `limit`.
+fn compile_query_body(
+    db: &RelationalDB,
+    tx: &TxMode,
+    params: &Placeholders,
+    body: SetExpr,
+    order_by: Vec<OrderByExpr>,
+    limit: Option<LimitExpr>,
+) -> Result<SqlAst, PlanError> {
+    match body {
         SetExpr::Select(select) => {
-            unsupported!(
-                "SELECT",
-                select.distinct,
-                select.top,
-                select.into,
-                select.lateral_views,
-                select.group_by,
-                select.having,
-                select.sort_by
-            );
+            unsupported!("SELECT", select.top, select.into, select.lateral_views, select.sort_by);
+            let distinct = select.distinct;
 
-            compile_select(db, tx, *select)
+            compile_select(db, tx, params, *select, distinct, order_by, limit)
+        }
+        SetExpr::Query(query) => {
+            if !order_by.is_empty() || limit.is_some() {
+                return Err(PlanError::Unsupported {
+                    feature: "`ORDER BY`, `LIMIT` or `OFFSET` of a parenthesized query".into(),
+                });
+            }
+            compile_query(db, tx, params, *query)
         }
-        SetExpr::Query(_) => Err(PlanError::Unsupported {
-            feature: "Query".into(),
-        }),
         SetExpr::SetOperation {
-            op: _,
-            set_quantifier: _,
-            left: _,
-            right: _,
-        } => Err(PlanError::Unsupported {
-            feature: "SetOperation".into(),
-        }),
+            op,
+            set_quantifier,
+            left,
+            right,
+        } => compile_set_operation(db, tx, params, op, set_quantifier, *left, *right, order_by, limit),
         SetExpr::Values(_) => Err(PlanError::Unsupported {
             feature: "Values".into(),
         }),
@@ -626,13 +1577,189 @@ fn compile_query(db: &RelationalDB, tx: &MutTxId, query: Query) -> Result<SqlAst
     }
 }
 
+/// Compiles any `query` clause: a `SELECT ...`, or a set operation between queries, as in `SELECT ... UNION SELECT ...`
+fn compile_query(db: &RelationalDB, tx: &TxMode, params: &Placeholders, query: Query) -> Result<SqlAst, PlanError> {
+    unsupported!("SELECT", query.fetch, query.locks, query.with);
+    let limit = compile_limit(query.limit, query.offset)?;
+
+    compile_query_body(db, tx, params, *query.body, query.order_by, limit)
+}
+
+/// Compiles the `RETURNING ...` clause of an `INSERT`, `UPDATE` or `DELETE`,
+/// into the columns computed from each row the statement inserts, updates or deletes.
+fn compile_returning(
+    table: &From,
+    params: &Placeholders,
+    returning: Option<Vec<SelectItem>>,
+) -> Result<Option<Vec<ComputedColumn>>, PlanError> {
+    let Some(items) = returning else {
+        return Ok(None);
+    };
+    let mut columns = Vec::with_capacity(items.len());
+    for item in items {
+        let (expr, alias) = match item {
+            SelectItem::QualifiedWildcard(name, _) if name.to_string() != table.root.table_name => {
+                return Err(PlanError::TableNotFoundQualified {
+                    expect: name.to_string(),
+                });
+            }
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => {
+                for c in &table.root.columns {
+                    let field = FieldName::named(&table.root.table_name, &c.col_name);
+                    columns.push(ComputedColumn {
+                        expr: ColumnOp::Field(FieldExpr::Name(field.clone())),
+                        field,
+                        ty: c.col_type.clone(),
+                    });
+                }
+                continue;
+            }
+            // The columns are named as in the table, not by the text of the expression, as in `SELECT`.
+            SelectItem::UnnamedExpr(expr) => {
+                let alias = match &expr {
+                    SqlExpr::Identifier(x) => Some(x.clone()),
+                    SqlExpr::CompoundIdentifier(x) => x.last().cloned(),
+                    _ => None,
+                };
+                (expr, alias)
+            }
+            SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias)),
+        };
+        columns.push(compile_computed(table, params, expr, alias)?);
+    }
+    Ok(Some(columns))
+}
+
+/// Checks the `value` assigned to the column `field` is of its type `ty`.
+fn check_assignment(
+    table: &From,
+    params: &Placeholders,
+    field: &FieldName,
+    ty: &AlgebraicType,
+    value: &ColumnOp,
+) -> Result<(), PlanError> {
+    match type_of(table, params, value)? {
+        Ty::Val(x) if &x == ty => Ok(()),
+        // As in `SET nickname = NULL`.
+        Ty::Unknown if option_type(ty).is_some() => Ok(()),
+        x => Err(PlanError::Unstructured(format!(
+            "Can't assign a value of type {x} to `{field}` of type {}.",
+            fmt_algebraic_type(ty)
+        ))),
+    }
+}
+
+/// Compiles the `ON CONFLICT [(cols)] DO NOTHING | DO UPDATE SET ... [WHERE ...]` clause of an `INSERT`,
+/// whose columns must be the ones of a unique index.
+///
+/// The expressions of `DO UPDATE` refer to the row to insert by the table name `excluded`,
+/// so the columns of the existing row are qualified by the name of the table, as in
+/// `SET score = player.score + excluded.score`.
+fn compile_on_conflict(
+    table: &From,
+    params: &Placeholders,
+    on: Option<OnInsert>,
+) -> Result<Option<OnConflict>, PlanError> {
+    let on = match on {
+        None => return Ok(None),
+        Some(OnInsert::OnConflict(on)) => on,
+        Some(x) => {
+            return Err(PlanError::Unsupported {
+                feature: format!("Unsupported: {x}, only `ON CONFLICT` is supported."),
+            })
+        }
+    };
+    let target = on.conflict_target;
+    let mut cols = Vec::with_capacity(target.len());
+    for name in &target {
+        let col = table
+            .root
+            .get_column_by_name(&name.value)
+            .ok_or_else(|| PlanError::UnknownField {
+                field: FieldName::named(&table.root.table_name, &name.value),
+                tables: table.table_names(),
+            })?;
+        cols.push(col.col_id);
+    }
+    let mut sorted = cols.clone();
+    sorted.sort_unstable();
+    let is_unique = table.root.indexes.iter().any(|x| {
+        let mut index = x.cols.clone();
+        index.sort_unstable();
+        x.is_unique && index == sorted
+    });
+    if !cols.is_empty() && !is_unique {
+        return Err(PlanError::Unstructured(format!(
+            "There is no unique constraint on the columns of `ON CONFLICT ({})`.",
+            target.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+        )));
+    }
+
+    let action = match on.action {
+        OnConflictAction::DoNothing => ConflictAction::DoNothing,
+        OnConflictAction::DoUpdate(_) if cols.is_empty() => {
+            return Err(PlanError::Unstructured(
+                "`ON CONFLICT DO UPDATE` requires the columns of a unique constraint, as in `ON CONFLICT (id)`.".into(),
+            ))
+        }
+        OnConflictAction::DoUpdate(DoUpdate { assignments, selection }) => {
+            let mut excluded = table.root.clone();
+            excluded.table_name = OnConflict::EXCLUDED.into();
+            let rows = From::new(table.root.clone()).with_cross_join(excluded);
+
+            // The columns that aren't assigned keep the value of the existing row.
+            let mut set: Vec<_> = table
+                .root
+                .columns
+                .iter()
+                .map(|c| {
+                    let field = FieldName::named(&table.root.table_name, &c.col_name);
+                    ComputedColumn {
+                        expr: ColumnOp::Field(FieldExpr::Name(field.clone())),
+                        field,
+                        ty: c.col_type.clone(),
+                    }
+                })
+                .collect();
+            for Assignment { id, value } in assignments {
+                let name: String = id.iter().map(|x| x.to_string()).collect();
+                let column = set
+                    .iter_mut()
+                    .find(|x| x.field.field_name() == Some(&name))
+                    .ok_or_else(|| PlanError::UnknownField {
+                        field: FieldName::named(&table.root.table_name, &name),
+                        tables: table.table_names(),
+                    })?;
+                let field = ProductTypeElement::new(column.ty.clone(), None);
+                let value = compile_expr_value(&rows, params, Some(&field), value)?;
+                check_assignment(&rows, params, &column.field, &column.ty, &value)?;
+                column.expr = value;
+            }
+            let selection = match selection {
+                Some(x) => {
+                    let x = compile_expr_value(&rows, params, None, x)?;
+                    check_condition(&rows, params, &x)?;
+                    Some(x)
+                }
+                None => None,
+            };
+            ConflictAction::DoUpdate { set, selection }
+        }
+    };
+    Ok(Some(OnConflict { cols, action }))
+}
+
 /// Compiles the `INSERT ...` clause
+#[allow(clippy::too_many_arguments)]
 fn compile_insert(
     db: &RelationalDB,
-    tx: &MutTxId,
+    tx: &TxMode,
+    params: &Placeholders,
     table_name: ObjectName,
     columns: Vec<Ident>,
     data: &Values,
+    on: Option<OnInsert>,
+    returning: Option<Vec<SelectItem>>,
 ) -> Result<SqlAst, PlanError> {
     let table = find_table(db, tx, Table::new(table_name))?;
 
@@ -649,28 +1776,34 @@ fn compile_insert(
         let mut row = Vec::with_capacity(x.len());
         for (pos, v) in x.iter().enumerate() {
             let field = table.root.get_column(pos).map(ProductTypeElement::from);
-            row.push(compile_expr_field(&table, field.as_ref(), v.clone())?);
+            row.push(compile_expr_field(&table, params, field.as_ref(), v.clone())?);
         }
 
         values.push(row);
     }
+    let on_conflict = compile_on_conflict(&table, params, on)?;
+    let returning = compile_returning(&table, params, returning)?;
     Ok(SqlAst::Insert {
         table: table.root,
         columns,
         values,
+        on_conflict,
+        returning,
     })
 }
 
 /// Compiles the `UPDATE ...` clause
 fn compile_update(
     db: &RelationalDB,
-    tx: &MutTxId,
+    tx: &TxMode,
+    params: &Placeholders,
     table: Table,
     assignments: Vec<Assignment>,
     selection: Option<SqlExpr>,
+    returning: Option<Vec<SelectItem>>,
 ) -> Result<SqlAst, PlanError> {
     let table = From::new(find_table(db, tx, table)?);
-    let selection = compile_where(&table, selection)?;
+    let selection = compile_where(db, tx, params, &table, selection)?;
 
     let mut x = HashMap::with_capacity(assignments.len());
 
@@ -678,30 +1811,36 @@ fn compile_update(
         let name: String = col.id.iter().map(|x| x.to_string()).collect();
 
         let field = table.root.get_column_by_name(&name).map(ProductTypeElement::from);
-        let value = compile_expr_field(&table, field.as_ref(), col.value)?;
+        let value = compile_expr_field(&table, params, field.as_ref(), col.value)?;
         x.insert(FieldName::named(&table.root.table_name, &name), value);
     }
 
+    let returning = compile_returning(&table, params, returning)?;
     Ok(SqlAst::Update {
         table: table.root,
         assignments: x,
         selection,
+        returning,
     })
 }
 
 /// Compiles the `DELETE ...` clause
 fn compile_delete(
     db: &RelationalDB,
-    tx: &MutTxId,
+    tx: &TxMode,
+    params: &Placeholders,
     table: Table,
     selection: Option<SqlExpr>,
+    returning: Option<Vec<SelectItem>>,
 ) -> Result<SqlAst, PlanError> {
     let table = From::new(find_table(db, tx, table)?);
-    let selection = compile_where(&table, selection)?;
+    let selection = compile_where(db, tx, params, &table, selection)?;
+    let returning = compile_returning(&table, params, returning)?;
 
     Ok(SqlAst::Delete {
         table: table.root,
         selection,
+        returning,
     })
 }
 
@@ -844,8 +1983,130 @@ fn compile_create_table(table: Table, cols: Vec<SqlColumnDef>) -> Result<SqlAst,
     })
 }
 
+/// Compiles the `CREATE [UNIQUE] INDEX name ON table [USING btree | hash] (columns)` clause
+fn compile_create_index(
+    db: &RelationalDB,
+    tx: &TxMode,
+    name: ObjectName,
+    table: ObjectName,
+    using: Option<Ident>,
+    columns: Vec<OrderByExpr>,
+    is_unique: bool,
+) -> Result<SqlAst, PlanError> {
+    let table = find_table(db, tx, Table::new(table))?;
+
+    let index_type = match using {
+        None => IndexType::BTree,
+        Some(using) => {
+            IndexType::try_from(using.value.to_lowercase().as_str()).map_err(|x| PlanError::Unsupported {
+                feature: format!("Index of type `{x}`"),
+            })?
+        }
+    };
+
+    let mut cols = Vec::with_capacity(columns.len());
+    for col in columns {
+        unsupported!("CREATE INDEX (column order)", col.asc, col.nulls_first);
+        let name = match col.expr {
+            SqlExpr::Identifier(name) => name.value,
+            x => {
+                return Err(PlanError::Unsupported {
+                    feature: format!("Index on the expression {x}"),
+                })
+            }
+        };
+        let column = table.get_column_by_name(&name).ok_or_else(|| PlanError::UnknownField {
+            field: FieldName::named(&table.table_name, &name),
+            tables: vec![table.table_name.clone()],
+        })?;
+        cols.push(column.col_id);
+    }
+
+    Ok(SqlAst::CreateIndex {
+        name: name.to_string(),
+        table,
+        cols,
+        index_type,
+        is_unique,
+    })
+}
+
+/// Compiles the `ALTER TABLE table RENAME TO name` and `ALTER TABLE table ADD [COLUMN] column` clauses
+///
+/// The added column is set in the existing rows to its `DEFAULT`,
+/// or to `NULL` when it is nullable, or to the next value of its sequence when it is an identity.
+fn compile_alter_table(
+    db: &RelationalDB,
+    tx: &TxMode,
+    params: &Placeholders,
+    name: ObjectName,
+    operation: AlterTableOperation,
+) -> Result<SqlAst, PlanError> {
+    let table = find_table(db, tx, Table::new(name))?;
+
+    let alter = match operation {
+        AlterTableOperation::RenameTable { table_name } => AlterTable::Rename {
+            new_name: table_name.to_string(),
+        },
+        AlterTableOperation::AddColumn {
+            column_keyword: _,
+            if_not_exists,
+            mut column_def,
+        } => {
+            unsupported!("ALTER TABLE ADD COLUMN", if_not_exists);
+            if column_size(&column_def).is_some() {
+                return Err(PlanError::Unsupported {
+                    feature: format!("Column with a defined size {}", column_def.name),
+                });
+            }
+
+            // The `DEFAULT` only applies to the existing rows, so it isn't kept with the column.
+            let mut default = None;
+            column_def.options.retain(|x| match &x.option {
+                ColumnOption::Default(expr) => {
+                    default = Some(expr.clone());
+                    false
+                }
+                _ => true,
+            });
+
+            let name = column_def.name.to_string();
+            let (is_null, attr) = compile_column_option(&column_def)?;
+            let column = ProductTypeElement::new_named(column_def_type(&name, is_null, &column_def.data_type)?, name);
+
+            let default = match default {
+                Some(expr) => match compile_expr_field(&From::new(table.clone()), params, Some(&column), expr)? {
+                    FieldExpr::Value(x) => x,
+                    FieldExpr::Name(x) => {
+                        return Err(PlanError::Unsupported {
+                            feature: format!("DEFAULT of the column {x}"),
+                        })
+                    }
+                },
+                None if is_null => AlgebraicValue::OptionNone(),
+                None if attr.is_autoinc() => parse("0", &column.algebraic_type)?,
+                None => {
+                    return Err(PlanError::Unstructured(format!(
+                        "The column `{}` is `NOT NULL`, so it needs a `DEFAULT` for the existing rows of `{}`.",
+                        column_def.name, table.table_name
+                    )))
+                }
+            };
+
+            AlterTable::AddColumn { column, attr, default }
+        }
+        x => {
+            return Err(PlanError::Unsupported {
+                feature: format!("ALTER TABLE {x}"),
+            })
+        }
+    };
+
+    Ok(SqlAst::AlterTable { table, alter })
+}
+
 /// Compiles the `DROP ...` clause
-fn compile_drop(name: &ObjectName, kind: ObjectType) -> Result<SqlAst, PlanError> {
+fn compile_drop(name: &ObjectName, kind: ObjectType, if_exists: bool) -> Result<SqlAst, PlanError> {
     let kind = match kind {
         ObjectType::Table => DbType::Table,
         ObjectType::Index => DbType::Index,
@@ -861,13 +2122,19 @@ fn compile_drop(name: &ObjectName, kind: ObjectType) -> Result<SqlAst, PlanError
         table_access: StAccess::for_name(&name),
         name,
         kind,
+        if_exists,
     })
 }
 
 /// Compiles a `SQL` clause
-fn compile_statement(db: &RelationalDB, tx: &MutTxId, statement: Statement) -> Result<SqlAst, PlanError> {
+fn compile_statement(
+    db: &RelationalDB,
+    tx: &TxMode,
+    params: &Placeholders,
+    statement: Statement,
+) -> Result<SqlAst, PlanError> {
     match statement {
-        Statement::Query(query) => Ok(compile_query(db, tx, *query)?),
+        Statement::Query(query) => Ok(compile_query(db, tx, params, *query)?),
         Statement::Insert {
             or,
             into,
@@ -881,16 +2148,7 @@ fn compile_statement(db: &RelationalDB, tx: &MutTxId, statement: Statement) -> R
             on,
             returning,
         } => {
-            unsupported!(
-                "INSERT",
-                or,
-                overwrite,
-                partitioned,
-                after_columns,
-                table,
-                on,
-                returning
-            );
+            unsupported!("INSERT", or, overwrite, partitioned, after_columns, table);
             if into {
                 let values = match &*source.body {
                     SetExpr::Values(values) => values,
@@ -901,7 +2159,7 @@ fn compile_statement(db: &RelationalDB, tx: &MutTxId, statement: Statement) -> R
                     }
                 };
 
-                return compile_insert(db, tx, table_name, columns, values);
+                return compile_insert(db, tx, params, table_name, columns, values, on, returning);
             };
 
             Err(PlanError::Unsupported {
@@ -915,10 +2173,10 @@ fn compile_statement(db: &RelationalDB, tx: &MutTxId, statement: Statement) -> R
             selection,
             returning,
         } => {
-            unsupported!("UPDATE", from, returning);
+            unsupported!("UPDATE", from);
 
             let table_name = compile_table_factor(table.relation)?;
-            compile_update(db, tx, table_name, assignments, selection)
+            compile_update(db, tx, params, table_name, assignments, selection, returning)
         }
         Statement::Delete {
             tables,
@@ -927,14 +2185,14 @@ fn compile_statement(db: &RelationalDB, tx: &MutTxId, statement: Statement) -> R
             selection,
             returning,
         } => {
-            unsupported!("DELETE", using, returning, tables);
+            unsupported!("DELETE", using, tables);
             if from.len() != 1 {
                 unsupported!("DELETE (multiple tables)", tables);
             }
 
             let table = from.first().unwrap().clone();
             let table_name = compile_table_factor(table.relation)?;
-            compile_delete(db, tx, table_name, selection)
+            compile_delete(db, tx, params, table_name, selection, returning)
         }
         Statement::CreateTable {
             transient,
@@ -1010,7 +2268,7 @@ fn compile_statement(db: &RelationalDB, tx: &MutTxId, statement: Statement) -> R
             restrict,
             purge,
         } => {
-            unsupported!("DROP", if_exists, cascade, purge, restrict);
+            unsupported!("DROP", cascade, purge, restrict);
 
             if names.len() > 1 {
                 return Err(PlanError::Unsupported {
@@ -1024,7 +2282,33 @@ fn compile_statement(db: &RelationalDB, tx: &MutTxId, statement: Statement) -> R
                     feature: "DROP without names".into(),
                 });
             };
-            compile_drop(name, object_type)
+            compile_drop(name, object_type, if_exists)
+        }
+        Statement::CreateIndex {
+            name,
+            table_name,
+            using,
+            columns,
+            unique,
+            if_not_exists,
+        } => {
+            unsupported!("CREATE INDEX", if_not_exists);
+            compile_create_index(db, tx, name, table_name, using, columns, unique)
+        }
+        Statement::AlterTable { name, operation } => compile_alter_table(db, tx, params, name, operation),
+        Statement::Explain {
+            describe_alias: _,
+            analyze,
+            verbose,
+            statement,
+            format,
+        } => {
+            unsupported!("EXPLAIN", verbose, format);
+            let query = compile_statement(db, tx, params, *statement)?;
+            Ok(SqlAst::Explain {
+                query: Box::new(query),
+                analyze,
+            })
         }
         x => Err(PlanError::Unsupported {
             feature: format!("Syntax {x}"),
@@ -1032,17 +2316,121 @@ fn compile_statement(db: &RelationalDB, tx: &MutTxId, statement: Statement) -> R
     }
 }
 
-/// Compiles a `sql` string into a `Vec<SqlAst>` using a SQL parser with [PostgreSqlDialect]
-pub(crate) fn compile_to_ast(db: &RelationalDB, tx: &MutTxId, sql_text: &str) -> Result<Vec<SqlAst>, DBError> {
+/// Parses a `sql` string into its statements using a SQL parser with [PostgreSqlDialect]
+pub(crate) fn parse_sql(sql_text: &str) -> Result<Vec<Statement>, DBError> {
     let dialect = PostgreSqlDialect {};
-    let ast = Parser::parse_sql(&dialect, sql_text).map_err(|error| DBError::SqlParser {
+    Parser::parse_sql(&dialect, sql_text).map_err(|error| DBError::SqlParser {
+        sql: sql_text.to_string(),
+        error,
+    })
+}
+
+/// A statement controlling the transaction of a session, see [SqlSessions](crate::sql::session::SqlSessions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxControl {
+    /// `BEGIN` or `START TRANSACTION`
+    Begin,
+    /// `COMMIT`
+    Commit,
+    /// `ROLLBACK`
+    Rollback,
+    /// `SAVEPOINT name`
+    Savepoint(String),
+    /// `ROLLBACK TO [SAVEPOINT] name`
+    RollbackTo(String),
+    /// `RELEASE [SAVEPOINT] name`
+    Release(String),
+}
+
+/// A statement of a `SQL` script: either one compiled into a [SqlAst], or one controlling its transaction.
+#[derive(Debug, Clone)]
+pub enum SqlStatement {
+    Plan(Box<Statement>),
+    Tx(TxControl),
+}
+
+/// Parses the next statement of a script, see [parse_script].
+fn parse_script_statement(parser: &mut Parser<'_>) -> Result<SqlStatement, ParserError> {
+    if parser.parse_keyword(Keyword::ROLLBACK) {
+        let _ = parser.parse_one_of_keywords(&[Keyword::TRANSACTION, Keyword::WORK]);
+        if parser.parse_keyword(Keyword::TO) {
+            let _ = parser.parse_keyword(Keyword::SAVEPOINT);
+            let name = parser.parse_identifier()?;
+            return Ok(SqlStatement::Tx(TxControl::RollbackTo(name.value)));
+        }
+        let chain = parser.parse_commit_rollback_chain()?;
+        return Ok(if chain {
+            SqlStatement::Plan(Box::new(Statement::Rollback { chain }))
+        } else {
+            SqlStatement::Tx(TxControl::Rollback)
+        });
+    }
+    if parser.parse_keyword(Keyword::RELEASE) {
+        let _ = parser.parse_keyword(Keyword::SAVEPOINT);
+        let name = parser.parse_identifier()?;
+        return Ok(SqlStatement::Tx(TxControl::Release(name.value)));
+    }
+
+    // Transactions with options are left to the planner, which rejects them.
+    Ok(match parser.parse_statement()? {
+        Statement::StartTransaction { modes } if modes.is_empty() => SqlStatement::Tx(TxControl::Begin),
+        Statement::Commit { chain: false } => SqlStatement::Tx(TxControl::Commit),
+        Statement::Savepoint { name } => SqlStatement::Tx(TxControl::Savepoint(name.value)),
+        x => SqlStatement::Plan(Box::new(x)),
+    })
+}
+
+/// Parses a `sql` string like [parse_sql], also accepting the statements that control its transaction.
+///
+/// `ROLLBACK TO` and `RELEASE` are not known to the parser, so they are parsed here.
+pub(crate) fn parse_script(sql_text: &str) -> Result<Vec<SqlStatement>, DBError> {
+    let err = |error| DBError::SqlParser {
         sql: sql_text.to_string(),
         error,
-    })?;
+    };
+    let dialect = PostgreSqlDialect {};
+    let mut parser = Parser::new(&dialect).try_with_sql(sql_text).map_err(err)?;
 
+    let mut statements = Vec::new();
+    let mut expecting_delimiter = false;
+    loop {
+        while parser.consume_token(&Token::SemiColon) {
+            expecting_delimiter = false;
+        }
+        if parser.peek_token().token == Token::EOF {
+            break;
+        }
+        if expecting_delimiter {
+            return parser.expected("end of statement", parser.peek_token()).map_err(err);
+        }
+        statements.push(parse_script_statement(&mut parser).map_err(err)?);
+        expecting_delimiter = true;
+    }
+    Ok(statements)
+}
+
+/// Compiles a `sql` string into a `Vec<SqlAst>` using a SQL parser with [PostgreSqlDialect]
+pub(crate) fn compile_to_ast(
+    db: &RelationalDB,
+    tx: &TxMode,
+    sql_text: &str,
+    params: &Placeholders,
+) -> Result<Vec<SqlAst>, DBError> {
+    compile_statements_to_ast(db, tx, sql_text, parse_sql(sql_text)?, params)
+}
+
+/// Compiles the `statements` parsed from `sql_text` into a `Vec<SqlAst>`,
+/// recording their placeholders `$1`, `$2`, ... into `params`.
+pub(crate) fn compile_statements_to_ast(
+    db: &RelationalDB,
+    tx: &TxMode,
+    sql_text: &str,
+    statements: Vec<Statement>,
+    params: &Placeholders,
+) -> Result<Vec<SqlAst>, DBError> {
     let mut results = Vec::new();
-    for statement in ast {
-        let plan_result = compile_statement(db, tx, statement);
+    for statement in statements {
+        let plan_result = compile_statement(db, tx, params, statement);
         let query = match plan_result {
             Ok(plan) => plan,
             Err(error) => {
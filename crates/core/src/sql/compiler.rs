@@ -1,22 +1,59 @@
 use std::collections::HashMap;
 
-use crate::db::datastore::locking_tx_datastore::MutTxId;
 use crate::db::datastore::traits::TableSchema;
 use crate::db::relational_db::RelationalDB;
 use crate::error::{DBError, PlanError};
-use crate::sql::ast::{compile_to_ast, Column, From, Join, Selection, SqlAst};
+use crate::sql::ast::{compile_statements_to_ast, compile_to_ast, Column, From, Join, Selection, SqlAst};
+use crate::sql::planner::optimize_crud;
+use crate::sql::prepared::{Placeholders, Plan};
+use crate::vm::TxMode;
 use spacetimedb_lib::auth::{StAccess, StTableType};
 use spacetimedb_lib::relation::{self, DbTable, FieldExpr, FieldName, Header};
 use spacetimedb_lib::table::ProductTypeMeta;
+use spacetimedb_lib::IndexType;
 use spacetimedb_sats::ProductType;
 use spacetimedb_vm::dsl::{db_table, db_table_raw, query};
-use spacetimedb_vm::expr::{ColumnOp, CrudExpr, DbType, Expr, QueryExpr, SourceExpr};
+use spacetimedb_vm::expr::{
+    AggregateQuery, AlterTable, ColumnOp, ComputedColumn, CrudExpr, DbType, Expr, LimitExpr, OnConflict, QueryExpr,
+    SetOp, SortKey, SourceExpr,
+};
 use spacetimedb_vm::operator::OpCmp;
+use sqlparser::ast::Statement;
 
 /// Compile the `SQL` expression into a `ast`
-pub fn compile_sql(db: &RelationalDB, tx: &MutTxId, sql_text: &str) -> Result<Vec<CrudExpr>, DBError> {
-    let ast = compile_to_ast(db, tx, sql_text)?;
+pub fn compile_sql(db: &RelationalDB, tx: &TxMode, sql_text: &str) -> Result<Vec<CrudExpr>, DBError> {
+    let placeholders = Placeholders::default();
+    let ast = compile_to_ast(db, tx, sql_text, &placeholders)?;
+    let ast = compile_sql_ast(sql_text, ast)?;
+    // Without parameters, a placeholder can't be bound.
+    placeholders.bind(ast, &[]).map_err(|error| DBError::Plan {
+        sql: sql_text.to_string(),
+        error,
+    })
+}
+
+/// Compile the `statements` parsed from the `SQL` expression into a [Plan] optimized for the schema seen by `tx`,
+/// to which the parameters of each execution are bound.
+pub fn compile_prepared(
+    db: &RelationalDB,
+    tx: &TxMode,
+    sql_text: &str,
+    statements: Vec<Statement>,
+) -> Result<Plan, DBError> {
+    let placeholders = Placeholders::default();
+    let ast = compile_statements_to_ast(db, tx, sql_text, statements, &placeholders)?;
+    let statements = compile_sql_ast(sql_text, ast)?
+        .into_iter()
+        .map(|x| optimize_crud(db, tx, x))
+        .collect::<Result<_, _>>()?;
+    Ok(Plan {
+        schema_version: tx.schema_version(),
+        statements,
+        placeholders: placeholders.into_inner(),
+    })
+}
 
+fn compile_sql_ast(sql_text: &str, ast: Vec<SqlAst>) -> Result<Vec<CrudExpr>, DBError> {
     let mut results = Vec::with_capacity(ast.len());
 
     for sql in ast {
@@ -48,27 +85,69 @@ fn check_field(table: &From, field: &FieldExpr) -> Result<(), PlanError> {
     Ok(())
 }
 
-fn check_field_column(table: &From, field: &ColumnOp) -> Result<(), PlanError> {
-    if let ColumnOp::Field(field) = field {
+/// Verify the `fields` inside the `expr` are valid
+fn check_cmp_expr(table: &From, expr: &ColumnOp) -> Result<(), PlanError> {
+    if let ColumnOp::Field(field) = expr {
         check_field(table, field)?;
     }
+    for x in expr.args() {
+        check_cmp_expr(table, x)?;
+    }
+
     Ok(())
 }
 
-/// Verify the `fields` inside the `expr` are valid
-fn check_cmp_expr(table: &From, expr: &ColumnOp) -> Result<(), PlanError> {
-    match expr {
-        ColumnOp::Field(field) => check_field(table, field)?,
-        ColumnOp::Cmp { op: _, lhs, rhs } => {
-            check_field_column(table, lhs)?;
-            check_field_column(table, rhs)?;
+/// Verify the `field` is available after the `aggregate`,
+/// so it is either one of the columns the rows are grouped by, or an aggregate.
+fn check_aggregated_field(aggregate: &AggregateQuery, field: &FieldExpr) -> Result<(), PlanError> {
+    if let FieldExpr::Name(field) = field {
+        if !aggregate.group_by.contains(field) && !aggregate.aggregates.iter().any(|x| &x.field == field) {
+            return Err(PlanError::Unstructured(format!(
+                "Field `{field}` must appear in the `GROUP BY` clause or be used in an aggregate function."
+            )));
         }
     }
-
     Ok(())
 }
 
+/// Verify the `fields` inside the `expr` are available after the `aggregate`
+fn check_aggregated_expr(aggregate: &AggregateQuery, expr: &ColumnOp) -> Result<(), PlanError> {
+    if let ColumnOp::Field(field) = expr {
+        check_aggregated_field(aggregate, field)?;
+    }
+    expr.args()
+        .into_iter()
+        .try_for_each(|x| check_aggregated_expr(aggregate, x))
+}
+
+/// Turns the projected `field` at `pos` into a [ComputedColumn] that copies it,
+/// so it can be projected along the expressions computed for each row.
+fn computed_field(table: &From, pos: usize, field: FieldExpr) -> Result<ComputedColumn, PlanError> {
+    match field {
+        FieldExpr::Name(name) => Ok(ComputedColumn {
+            ty: table.field_type(&name)?,
+            expr: ColumnOp::Field(FieldExpr::Name(name.clone())),
+            field: name,
+        }),
+        FieldExpr::Value(x) => Ok(ComputedColumn {
+            ty: x.type_of(),
+            expr: ColumnOp::Field(FieldExpr::Value(x)),
+            field: FieldName::positional(&table.root.table_name, pos),
+        }),
+    }
+}
+
+/// Projects the rows of `q` onto the `col_ids`, or onto the `computed` columns when any is computed.
+fn with_projection(q: QueryExpr, col_ids: &[FieldExpr], computed: &Option<Vec<ComputedColumn>>) -> QueryExpr {
+    match computed {
+        Some(columns) => q.with_compute(columns),
+        None => q.with_project(col_ids),
+    }
+}
+
 /// Compiles a `WHERE ...` clause
+///
+/// The subqueries come after the other conditions, so they only see the rows that pass them.
 fn compile_where(q: QueryExpr, table: &From, filter: Selection) -> Result<QueryExpr, PlanError> {
     let mut q = q;
 
@@ -76,14 +155,31 @@ fn compile_where(q: QueryExpr, table: &From, filter: Selection) -> Result<QueryE
         check_cmp_expr(table, &x)?;
         q = q.with_select(x)
     }
+    for x in filter.subqueries {
+        let rhs = compile_query(x.query)?;
+        q = q.with_semi_join(x.field, rhs, x.negated);
+    }
 
     Ok(q)
 }
 
 /// Compiles a `SELECT ...` clause
-fn compile_select(table: From, project: Vec<Column>, selection: Option<Selection>) -> Result<QueryExpr, PlanError> {
+#[allow(clippy::too_many_arguments)]
+fn compile_select(
+    table: From,
+    project: Vec<Column>,
+    distinct: bool,
+    selection: Option<Selection>,
+    aggregate: Option<AggregateQuery>,
+    having: Option<Selection>,
+    order_by: Vec<SortKey>,
+    limit: Option<LimitExpr>,
+) -> Result<QueryExpr, PlanError> {
     let mut not_found = Vec::with_capacity(project.len());
     let mut col_ids = Vec::new();
+    // The expressions computed for each row, by their position in `col_ids`.
+    let mut computed = HashMap::new();
+    let has_computed = project.iter().any(|x| matches!(x, Column::Computed(_)));
     //Match columns to their tables...
     for select_item in project {
         match select_item {
@@ -92,6 +188,16 @@ fn compile_select(table: From, project: Vec<Column>, selection: Option<Selection
                 Err(PlanError::UnknownField { field, tables: _ }) => not_found.push(field),
                 Err(err) => return Err(err),
             },
+            Column::Aggregate(x) => col_ids.push(x.field.into()),
+            Column::Computed(x) => {
+                computed.insert(col_ids.len(), x.clone());
+                col_ids.push(x.field.into());
+            }
+            Column::QualifiedWildcard { .. } | Column::Wildcard if aggregate.is_some() => {
+                return Err(PlanError::Unsupported {
+                    feature: "Wildcards with `GROUP BY` or aggregate functions.".into(),
+                });
+            }
             Column::QualifiedWildcard { table: name } => {
                 if let Some(t) = table.iter_tables().find(|x| x.table_name == name) {
                     for c in t.columns.iter() {
@@ -101,6 +207,14 @@ fn compile_select(table: From, project: Vec<Column>, selection: Option<Selection
                     return Err(PlanError::TableNotFoundQualified { expect: name });
                 }
             }
+            // The computed columns are projected apart, so all the others must be listed along them.
+            Column::Wildcard if has_computed => {
+                for t in table.iter_tables() {
+                    for c in t.columns.iter() {
+                        col_ids.push(FieldName::named(&t.table_name, &c.col_name).into());
+                    }
+                }
+            }
             Column::Wildcard => {}
         }
     }
@@ -112,6 +226,19 @@ fn compile_select(table: From, project: Vec<Column>, selection: Option<Selection
         });
     }
 
+    let computed = has_computed
+        .then(|| {
+            col_ids
+                .iter()
+                .enumerate()
+                .map(|(pos, field)| match computed.remove(&pos) {
+                    Some(x) => Ok(x),
+                    None => computed_field(&table, pos, field.clone()),
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+
     let mut q = query(db_table_raw(
         ProductType::from(&table.root),
         &table.root.table_name,
@@ -122,15 +249,17 @@ fn compile_select(table: From, project: Vec<Column>, selection: Option<Selection
 
     if let Some(ref joins) = table.join {
         for join in joins {
+            let rhs = join.rhs();
+            let t = db_table(rhs.into(), &rhs.table_name, rhs.table_id);
             match join {
-                Join::Inner { rhs, on } => {
-                    let t = db_table(rhs.into(), &rhs.table_name, rhs.table_id);
-                    match on.op {
-                        OpCmp::Eq => {}
-                        x => unreachable!("Unsupported operator `{x}` for joins"),
-                    }
-                    q = q.with_join_inner(t, on.lhs.clone(), on.rhs.clone());
+                Join::Inner { on, .. } | Join::Outer { on, .. } if on.op != OpCmp::Eq => {
+                    return Err(PlanError::Unsupported {
+                        feature: format!("Unsupported operator `{}` for joins", on.op),
+                    });
                 }
+                Join::Inner { on, .. } => q = q.with_join_inner(t, on.lhs.clone(), on.rhs.clone()),
+                Join::Outer { kind, on, .. } => q = q.with_join_outer(*kind, t, on.lhs.clone(), on.rhs.clone()),
+                Join::Cross { .. } => q = q.with_join_cross(t),
             }
         }
     };
@@ -138,12 +267,84 @@ fn compile_select(table: From, project: Vec<Column>, selection: Option<Selection
     if let Some(filter) = selection {
         q = compile_where(q, &table, filter)?;
     }
-    //Is important to project at the end, so joins, filters see fields that are not projected
-    q = q.with_project(&col_ids);
+    if let Some(aggregate) = aggregate {
+        match &computed {
+            Some(columns) => columns
+                .iter()
+                .try_for_each(|x| check_aggregated_expr(&aggregate, &x.expr))?,
+            None => col_ids
+                .iter()
+                .try_for_each(|field| check_aggregated_field(&aggregate, field))?,
+        }
+        for key in &order_by {
+            check_aggregated_field(&aggregate, &key.field)?;
+        }
+        q = q.with_aggregate(&aggregate.group_by, &aggregate.aggregates);
+        for x in having.into_iter().flat_map(|x| x.clauses) {
+            check_aggregated_expr(&aggregate, &x)?;
+            q = q.with_select(x);
+        }
+    }
+    q = q.with_sort(&order_by);
+    if distinct {
+        // The duplicates are only known once projected, and must be removed before counting the rows for the limit.
+        q = with_projection(q, &col_ids, &computed).with_distinct();
+        if let Some(LimitExpr { offset, limit }) = limit {
+            q = q.with_limit(offset, limit);
+        }
+        return Ok(q);
+    }
+    if let Some(LimitExpr { offset, limit }) = limit {
+        q = q.with_limit(offset, limit);
+    }
+    //Is important to project at the end, so joins, filters, sorts see fields that are not projected
+    q = with_projection(q, &col_ids, &computed);
+
+    Ok(q)
+}
 
+/// Compiles a `query1 UNION | INTERSECT | EXCEPT [ALL] query2` clause
+fn compile_set_operation(
+    op: SetOp,
+    all: bool,
+    lhs: SqlAst,
+    rhs: SqlAst,
+    order_by: Vec<SortKey>,
+    limit: Option<LimitExpr>,
+) -> Result<QueryExpr, PlanError> {
+    let mut q = compile_query(lhs)?.with_set_op(op, all, compile_query(rhs)?);
+    q = q.with_sort(&order_by);
+    if let Some(LimitExpr { offset, limit }) = limit {
+        q = q.with_limit(offset, limit);
+    }
     Ok(q)
 }
 
+/// Compiles a query, which is either a `SELECT ...` or a set operation between queries
+fn compile_query(query: SqlAst) -> Result<QueryExpr, PlanError> {
+    match query {
+        SqlAst::Select {
+            from,
+            project,
+            distinct,
+            selection,
+            aggregate,
+            having,
+            order_by,
+            limit,
+        } => compile_select(from, project, distinct, selection, aggregate, having, order_by, limit),
+        SqlAst::SetOperation {
+            op,
+            all,
+            lhs,
+            rhs,
+            order_by,
+            limit,
+        } => compile_set_operation(op, all, *lhs, *rhs, order_by, limit),
+        _ => unreachable!("Only queries yield rows"),
+    }
+}
+
 /// Builds the schema description [DbTable] from the [TableSchema] and their list of columns
 fn compile_columns(table: &TableSchema, columns: Vec<FieldName>) -> DbTable {
     let mut new = Vec::with_capacity(columns.len());
@@ -168,24 +369,32 @@ fn compile_insert(
     table: TableSchema,
     columns: Vec<FieldName>,
     values: Vec<Vec<FieldExpr>>,
+    on_conflict: Option<OnConflict>,
+    returning: Option<Vec<ComputedColumn>>,
 ) -> Result<CrudExpr, PlanError> {
     let db_table = compile_columns(&table, columns);
 
     Ok(CrudExpr::Insert {
         source: SourceExpr::DbTable(db_table),
         rows: values,
+        on_conflict,
+        returning,
     })
 }
 
 /// Compiles a `DELETE ...` clause
-fn compile_delete(table: TableSchema, selection: Option<Selection>) -> Result<CrudExpr, PlanError> {
+fn compile_delete(
+    table: TableSchema,
+    selection: Option<Selection>,
+    returning: Option<Vec<ComputedColumn>>,
+) -> Result<CrudExpr, PlanError> {
     let query = if let Some(filter) = selection {
         let query = QueryExpr::new(&table);
         compile_where(query, &From::new(table), filter)?
     } else {
         QueryExpr::new(&table)
     };
-    Ok(CrudExpr::Delete { query })
+    Ok(CrudExpr::Delete { query, returning })
 }
 
 /// Compiles a `UPDATE ...` clause
@@ -193,6 +402,7 @@ fn compile_update(
     table: TableSchema,
     assignments: HashMap<FieldName, FieldExpr>,
     selection: Option<Selection>,
+    returning: Option<Vec<ComputedColumn>>,
 ) -> Result<CrudExpr, PlanError> {
     let table = From::new(table);
     let delete = if let Some(filter) = selection.clone() {
@@ -218,7 +428,11 @@ fn compile_update(
     } else {
         insert
     };
-    Ok(CrudExpr::Update { insert, delete })
+    Ok(CrudExpr::Update {
+        insert,
+        delete,
+        returning,
+    })
 }
 
 /// Compiles a `CREATE TABLE ...` clause
@@ -236,41 +450,93 @@ fn compile_create_table(
     })
 }
 
+/// Compiles a `CREATE INDEX ...` clause
+fn compile_create_index(
+    name: String,
+    table: TableSchema,
+    cols: Vec<u32>,
+    index_type: IndexType,
+    is_unique: bool,
+) -> Result<CrudExpr, PlanError> {
+    Ok(CrudExpr::CreateIndex {
+        name,
+        table_name: table.table_name,
+        table_id: table.table_id,
+        cols,
+        index_type,
+        is_unique,
+    })
+}
+
+/// Compiles an `ALTER TABLE ...` clause
+fn compile_alter_table(table: TableSchema, alter: AlterTable) -> Result<CrudExpr, PlanError> {
+    Ok(CrudExpr::AlterTable {
+        table_name: table.table_name,
+        table_id: table.table_id,
+        alter,
+    })
+}
+
 /// Compiles a `DROP ...` clause
-fn compile_drop(name: String, kind: DbType, table_access: StAccess) -> Result<CrudExpr, PlanError> {
+fn compile_drop(name: String, kind: DbType, table_access: StAccess, if_exists: bool) -> Result<CrudExpr, PlanError> {
     Ok(CrudExpr::Drop {
         name,
         kind,
         table_access,
+        if_exists,
     })
 }
 
 /// Compiles a `SQL` clause
 fn compile_statement(statement: SqlAst) -> Result<CrudExpr, PlanError> {
     let q = match statement {
-        SqlAst::Select {
-            from,
-            project,
-            selection,
-        } => CrudExpr::Query(compile_select(from, project, selection)?),
-        SqlAst::Insert { table, columns, values } => compile_insert(table, columns, values)?,
+        query @ (SqlAst::Select { .. } | SqlAst::SetOperation { .. }) => CrudExpr::Query(compile_query(query)?),
+        SqlAst::Insert {
+            table,
+            columns,
+            values,
+            on_conflict,
+            returning,
+        } => compile_insert(table, columns, values, on_conflict, returning)?,
         SqlAst::Update {
             table,
             assignments,
             selection,
-        } => compile_update(table, assignments, selection)?,
-        SqlAst::Delete { table, selection } => compile_delete(table, selection)?,
+            returning,
+        } => compile_update(table, assignments, selection, returning)?,
+        SqlAst::Delete {
+            table,
+            selection,
+            returning,
+        } => compile_delete(table, selection, returning)?,
         SqlAst::CreateTable {
             table,
             columns,
             table_type,
             table_access: schema,
         } => compile_create_table(table, columns, table_type, schema)?,
+        SqlAst::CreateIndex {
+            name,
+            table,
+            cols,
+            index_type,
+            is_unique,
+        } => compile_create_index(name, table, cols, index_type, is_unique)?,
+        SqlAst::AlterTable { table, alter } => compile_alter_table(table, alter)?,
         SqlAst::Drop {
             name,
             kind,
             table_access,
-        } => compile_drop(name, kind, table_access)?,
+            if_exists,
+        } => compile_drop(name, kind, table_access, if_exists)?,
+        SqlAst::Explain { query, analyze } => match compile_statement(*query)? {
+            CrudExpr::Query(query) => CrudExpr::Explain { query, analyze },
+            x => {
+                return Err(PlanError::Unsupported {
+                    feature: format!("EXPLAIN of statements other than `SELECT`: {x:?}"),
+                })
+            }
+        },
     };
 
     Ok(q)
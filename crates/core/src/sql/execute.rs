@@ -1,3 +1,7 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
 use spacetimedb_lib::identity::AuthCtx;
 use spacetimedb_lib::relation::MemTable;
 use spacetimedb_lib::{ProductType, ProductValue};
@@ -7,34 +11,321 @@ use spacetimedb_vm::expr::{CodeResult, CrudExpr, Expr};
 use crate::database_instance_context_controller::DatabaseInstanceContextController;
 use crate::db::datastore::locking_tx_datastore::MutTxId;
 use crate::db::relational_db::RelationalDB;
-use crate::error::{DBError, DatabaseError};
-use crate::sql::compiler::compile_sql;
-use crate::vm::DbProgram;
+use crate::error::{DBError, DatabaseError, SessionError};
+use crate::host::module_host::{DatabaseUpdate, EventStatus, ModuleEvent, ModuleFunctionCall};
+use crate::host::{ArgsTuple, EnergyDiff, Timestamp};
+use crate::sql::ast::{SqlStatement, TxControl};
+use crate::sql::compiler::compile_prepared;
+use crate::sql::planner::optimize_crud;
+use crate::sql::prepared::{Prepared, SqlParam};
+use crate::sql::session::{SessionId, SqlSessions, Transaction};
+use crate::subscription::module_subscription_actor::ModuleSubscriptionManager;
+use crate::vm::{DbProgram, TxMode};
+
+/// The name of the function in the [ModuleEvent] of a transaction committed by `SQL`.
+pub const SQL_DUNDER: &str = "__sql__";
 
 pub struct StmtResult {
     pub schema: ProductType,
     pub rows: Vec<ProductValue>,
 }
 
+/// The results of the statements run by [execute], and the session they left open, if any.
+pub struct SqlOutcome {
+    pub results: Vec<MemTable>,
+    pub session: Option<SessionId>,
+}
+
 // TODO(cloutiertyler): we could do this the swift parsing way in which
 // we always generate a plan, but it may contain errors
 
-/// Run a `SQL` query/statement in the specified `database_instance_id`.
+/// Run a `SQL` query/statement in the specified `database_instance_id`,
+/// binding `params` to its placeholders `$1`, `$2`, ...
+///
+/// The statements are parsed once, and their plans reused from the prepared statements of the database
+/// for as long as the schema doesn't change.
+///
+/// They run in the transaction of the `session` opened by an earlier `BEGIN`, if any,
+/// or else in an implicit transaction committed at their end.
+/// If a statement fails, everything the request did is undone, and the session is left as it was,
+/// unless the request ended its transaction.
+///
+/// The committed transactions are broadcast to the clients subscribed through `subscription`.
 pub fn execute(
     db_inst_ctx_controller: &DatabaseInstanceContextController,
     database_instance_id: u64,
     sql_text: String,
+    params: Vec<SqlParam>,
+    session: Option<SessionId>,
     auth: AuthCtx,
-) -> Result<Vec<MemTable>, DBError> {
+    subscription: Option<&ModuleSubscriptionManager>,
+) -> Result<SqlOutcome, DBError> {
     if let Some((database_instance_context, _)) = db_inst_ctx_controller.get(database_instance_id) {
-        database_instance_context
-            .relational_db
-            .with_auto_commit(|tx| run(&database_instance_context.relational_db, tx, &sql_text, auth))
+        let prepared = database_instance_context.prepared_statements.prepare(&sql_text)?;
+        let request = Request {
+            db: &database_instance_context.relational_db,
+            sql_text: &sql_text,
+            prepared: &prepared,
+            params: &params,
+            auth,
+            subscription,
+        };
+        let has_tx_control = prepared.statements.iter().any(|x| matches!(x, SqlStatement::Tx(_)));
+        if session.is_none() && !has_tx_control {
+            let results = request.run_implicit(0..prepared.statements.len())?;
+            return Ok(SqlOutcome { results, session: None });
+        }
+        request.run_script(&database_instance_context.sql_sessions, session)
     } else {
         Err(DatabaseError::NotFound(database_instance_id).into())
     }
 }
 
+/// The statements of one request, with what they need to run.
+struct Request<'a> {
+    db: &'a Arc<RelationalDB>,
+    sql_text: &'a str,
+    prepared: &'a Prepared,
+    params: &'a [SqlParam],
+    auth: AuthCtx,
+    subscription: Option<&'a ModuleSubscriptionManager>,
+}
+
+impl Request<'_> {
+    /// Returns the plan of the prepared `statements` for the schema seen by `tx`, with the parameters bound to it.
+    fn plan(&self, tx: &TxMode, statements: &Range<usize>) -> Result<Vec<CrudExpr>, DBError> {
+        let plan = self.prepared.plan(statements, tx.schema_version(), || {
+            let statements = self.prepared.statements[statements.clone()]
+                .iter()
+                .filter_map(|x| match x {
+                    SqlStatement::Plan(x) => Some((**x).clone()),
+                    SqlStatement::Tx(_) => None,
+                })
+                .collect();
+            compile_prepared(self.db, tx, self.sql_text, statements)
+        })?;
+        plan.bind(self.params).map_err(|error| DBError::Plan {
+            sql: self.sql_text.to_string(),
+            error,
+        })
+    }
+
+    /// Runs the prepared `statements` in `tx`.
+    fn run(&self, tx: &mut MutTxId, statements: &Range<usize>) -> Result<Vec<MemTable>, DBError> {
+        let ast = self.plan(&tx.into(), statements)?;
+        execute_plan(self.db, tx.into(), ast, self.auth)
+    }
+
+    /// Runs the prepared `statements` in their own transaction.
+    fn run_implicit(&self, statements: Range<usize>) -> Result<Vec<MemTable>, DBError> {
+        let db = self.db;
+        // Queries only need a snapshot of the database, so they run in a read-only
+        // transaction and neither wait for, nor hold up, reducers.
+        let read_result = db.with_read_only(|tx| {
+            let ast = self.plan(&tx.into(), &statements)?;
+            if is_read_only(&ast) {
+                execute_plan(db, tx.into(), ast, self.auth).map(Some)
+            } else {
+                Ok(None)
+            }
+        })?;
+        if let Some(result) = read_result {
+            return Ok(result);
+        }
+
+        let mut tx = db.begin_tx();
+        match self.run(&mut tx, &statements) {
+            Ok(result) => {
+                self.commit(tx)?;
+                Ok(result)
+            }
+            Err(err) => {
+                db.rollback_tx(tx);
+                Err(err)
+            }
+        }
+    }
+
+    /// Commits `tx`, and broadcasts its changes to the subscribers.
+    fn commit(&self, tx: MutTxId) -> Result<(), DBError> {
+        let timestamp = Timestamp::now();
+        let Some((tx_data, commit, read_tx)) = self.db.commit_tx_with_read_tx(tx)? else {
+            return Err(SessionError::WriteConflict.into());
+        };
+        if tx_data.records.is_empty() {
+            return Ok(());
+        }
+        if let Some(subscription) = self.subscription {
+            let event = ModuleEvent {
+                timestamp,
+                caller_identity: self.auth.caller,
+                function_call: ModuleFunctionCall {
+                    reducer: SQL_DUNDER.to_owned(),
+                    args: ArgsTuple::default(),
+                },
+                status: EventStatus::Committed(DatabaseUpdate::from_writes(self.db, &tx_data)),
+                energy_quanta_used: EnergyDiff::ZERO,
+                host_execution_duration: Duration::ZERO,
+                commit,
+                read_tx: Some(Arc::new(read_tx)),
+            };
+            // Without a module, there is nobody to tell.
+            let _ = subscription.broadcast_commit_event(event);
+        }
+        Ok(())
+    }
+
+    /// Runs the prepared statements, which may control the transaction, in the transaction of the session `session_id`, if any.
+    ///
+    /// A transaction left open at the end opens a new session, which only the owner of the database can do.
+    fn run_script(&self, sessions: &SqlSessions, session_id: Option<SessionId>) -> Result<SqlOutcome, DBError> {
+        let mut session = session_id
+            .map(|id| {
+                sessions
+                    .get(id, self.auth.caller)
+                    .map(|session| (id, session.lock_arc()))
+            })
+            .transpose()?;
+        let mut transaction = match &mut session {
+            Some((id, session)) => Some(sessions.take_transaction(*id, session)?),
+            None => None,
+        };
+        // What to go back to if a statement fails, while `transaction` is still the one of the session.
+        let restore = transaction
+            .as_ref()
+            .map(|transaction| (self.db.savepoint(&transaction.tx), transaction.savepoints.clone()));
+        let mut same = transaction.is_some();
+
+        let results = match self.run_statements(&mut transaction, &mut same) {
+            Ok(results) => results,
+            Err(err) => {
+                match (session, transaction, restore) {
+                    (Some((_, mut session)), Some(mut transaction), Some((savepoint, savepoints))) if same => {
+                        self.db.rollback_to_savepoint(&mut transaction.tx, &savepoint);
+                        transaction.savepoints = savepoints;
+                        session.keep(transaction);
+                    }
+                    (session, transaction, _) => {
+                        if let Some(transaction) = transaction {
+                            self.db.rollback_tx(transaction.tx);
+                        }
+                        if let Some((id, _)) = session {
+                            sessions.close(id);
+                        }
+                    }
+                }
+                return Err(err);
+            }
+        };
+
+        if let Some((id, mut session)) = session {
+            if same {
+                session.keep(transaction.expect("the transaction of the session is still open"));
+                return Ok(SqlOutcome {
+                    results,
+                    session: Some(id),
+                });
+            }
+            sessions.close(id);
+        }
+        let session = match transaction {
+            Some(transaction) if self.auth.owner != self.auth.caller => {
+                self.db.rollback_tx(transaction.tx);
+                return Err(SessionError::OwnerRequired.into());
+            }
+            Some(transaction) => Some(sessions.open(self.db.clone(), self.auth.caller, transaction)),
+            None => None,
+        };
+        Ok(SqlOutcome { results, session })
+    }
+
+    fn run_statements(&self, transaction: &mut Option<Transaction>, same: &mut bool) -> Result<Vec<MemTable>, DBError> {
+        let mut results = Vec::new();
+        // The statements since the last one controlling the transaction.
+        let mut run = 0..0;
+        for (pos, statement) in self.prepared.statements.iter().enumerate() {
+            let control = match statement {
+                SqlStatement::Plan(_) => {
+                    run.end = pos + 1;
+                    continue;
+                }
+                SqlStatement::Tx(control) => control,
+            };
+            results.extend(self.run_in(transaction, run)?);
+            run = pos + 1..pos + 1;
+
+            match control {
+                TxControl::Begin => {
+                    if transaction.is_some() {
+                        return Err(SessionError::AlreadyInTransaction.into());
+                    }
+                    *transaction = Some(Transaction::new(self.db.begin_tx()));
+                }
+                TxControl::Commit => {
+                    let ended = transaction.take().ok_or(SessionError::NoTransaction)?;
+                    *same = false;
+                    self.commit(ended.tx)?;
+                }
+                TxControl::Rollback => {
+                    let ended = transaction.take().ok_or(SessionError::NoTransaction)?;
+                    *same = false;
+                    self.db.rollback_tx(ended.tx);
+                }
+                TxControl::Savepoint(name) => {
+                    let transaction = transaction.as_mut().ok_or(SessionError::NoTransaction)?;
+                    let savepoint = self.db.savepoint(&transaction.tx);
+                    transaction.savepoints.push((name.clone(), Arc::new(savepoint)));
+                }
+                TxControl::RollbackTo(name) => {
+                    let transaction = transaction.as_mut().ok_or(SessionError::NoTransaction)?;
+                    let pos = find_savepoint(transaction, name)?;
+                    // The savepoint is kept, so it can be rolled back to again.
+                    transaction.savepoints.truncate(pos + 1);
+                    let savepoint = transaction.savepoints[pos].1.clone();
+                    self.db.rollback_to_savepoint(&mut transaction.tx, &savepoint);
+                }
+                TxControl::Release(name) => {
+                    let transaction = transaction.as_mut().ok_or(SessionError::NoTransaction)?;
+                    let pos = find_savepoint(transaction, name)?;
+                    transaction.savepoints.truncate(pos);
+                }
+            }
+        }
+        results.extend(self.run_in(transaction, run)?);
+        Ok(results)
+    }
+
+    /// Runs the prepared `statements` in `transaction`, or in their own one outside of a transaction.
+    fn run_in(
+        &self,
+        transaction: &mut Option<Transaction>,
+        statements: Range<usize>,
+    ) -> Result<Vec<MemTable>, DBError> {
+        if statements.is_empty() {
+            return Ok(Vec::new());
+        }
+        match transaction {
+            Some(transaction) => self.run(&mut transaction.tx, &statements),
+            None => self.run_implicit(statements),
+        }
+    }
+}
+
+/// The position of the latest savepoint `name` of the `transaction`.
+fn find_savepoint(transaction: &Transaction, name: &str) -> Result<usize, SessionError> {
+    transaction
+        .savepoints
+        .iter()
+        .rposition(|(x, _)| x == name)
+        .ok_or_else(|| SessionError::SavepointNotFound(name.to_string()))
+}
+
+/// Whether all of the `ast` can be executed in a read-only transaction.
+fn is_read_only(ast: &[CrudExpr]) -> bool {
+    ast.iter()
+        .all(|x| matches!(x, CrudExpr::Query(_) | CrudExpr::Explain { .. }))
+}
+
 fn collect_result(result: &mut Vec<MemTable>, r: CodeResult) -> Result<(), DBError> {
     match r {
         CodeResult::Value(_) => {}
@@ -53,10 +344,11 @@ fn collect_result(result: &mut Vec<MemTable>, r: CodeResult) -> Result<(), DBErr
 
 pub fn execute_single_sql(
     db: &RelationalDB,
-    tx: &mut MutTxId,
+    tx: TxMode,
     ast: CrudExpr,
     auth: AuthCtx,
 ) -> Result<Vec<MemTable>, DBError> {
+    let ast = optimize_crud(db, &tx, ast)?;
     let p = &mut DbProgram::new(db, tx, auth);
     let q = Expr::Crud(Box::new(ast));
 
@@ -66,14 +358,17 @@ pub fn execute_single_sql(
 }
 
 /// Run the compiled `SQL` expression inside the `vm` created by [DbProgram]
-pub fn execute_sql(
-    db: &RelationalDB,
-    tx: &mut MutTxId,
-    ast: Vec<CrudExpr>,
-    auth: AuthCtx,
-) -> Result<Vec<MemTable>, DBError> {
-    let total = ast.len();
+pub fn execute_sql(db: &RelationalDB, tx: TxMode, ast: Vec<CrudExpr>, auth: AuthCtx) -> Result<Vec<MemTable>, DBError> {
+    let ast = ast
+        .into_iter()
+        .map(|x| optimize_crud(db, &tx, x))
+        .collect::<Result<Vec<_>, _>>()?;
+    execute_plan(db, tx, ast, auth)
+}
 
+/// Runs the `ast` already optimized by [optimize_crud], as the plans of [compile_prepared] are.
+fn execute_plan(db: &RelationalDB, tx: TxMode, ast: Vec<CrudExpr>, auth: AuthCtx) -> Result<Vec<MemTable>, DBError> {
+    let total = ast.len();
     let p = &mut DbProgram::new(db, tx, auth);
     let q = Expr::Block(ast.into_iter().map(|x| Expr::Crud(Box::new(x))).collect());
 
@@ -83,33 +378,35 @@ pub fn execute_sql(
 }
 
 /// Run the `SQL` string using the `auth` credentials
-pub(crate) fn run(
-    db: &RelationalDB,
-    tx: &mut MutTxId,
-    sql_text: &str,
-    auth: AuthCtx,
-) -> Result<Vec<MemTable>, DBError> {
-    let ast = compile_sql(db, tx, sql_text)?;
+#[cfg(test)]
+pub(crate) fn run(db: &RelationalDB, tx: TxMode, sql_text: &str, auth: AuthCtx) -> Result<Vec<MemTable>, DBError> {
+    let ast = crate::sql::compiler::compile_sql(db, &tx, sql_text)?;
     execute_sql(db, tx, ast, auth)
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::db::datastore::locking_tx_datastore::MutTxId;
+    use crate::db::datastore::traits::IndexDef;
     use crate::db::relational_db::tests_utils::make_test_db;
     use crate::db::relational_db::{ST_TABLES_ID, ST_TABLES_NAME};
+    use crate::error::PlanError;
+    use crate::sql::ast::parse_script;
+    use crate::sql::prepared::PreparedStatements;
     use crate::vm::tests::create_table_with_rows;
     use spacetimedb_lib::auth::{StAccess, StTableType};
     use spacetimedb_lib::error::ResultTest;
     use spacetimedb_lib::relation::Header;
-    use spacetimedb_sats::{product, AlgebraicType, BuiltinType, ProductType};
+    use spacetimedb_lib::{Identity, IndexType};
+    use spacetimedb_sats::{bsatn, product, AlgebraicType, AlgebraicValue, BuiltinType, ProductType};
     use spacetimedb_vm::dsl::{mem_table, scalar};
     use spacetimedb_vm::eval::create_game_data;
     use tempdir::TempDir;
 
     /// Short-cut for simplify test execution
     fn run_for_testing(db: &RelationalDB, tx: &mut MutTxId, sql_text: &str) -> Result<Vec<MemTable>, DBError> {
-        run(db, tx, sql_text, AuthCtx::for_testing())
+        run(db, tx.into(), sql_text, AuthCtx::for_testing())
     }
 
     fn create_data(total_rows: u64) -> ResultTest<(RelationalDB, MemTable, TempDir)> {
@@ -141,6 +438,33 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_select_read_only() -> ResultTest<()> {
+        let (db, input, _tmp_dir) = create_data(1)?;
+        let tx = db.begin_read_tx();
+        let result = run(&db, (&tx).into(), "SELECT * FROM inventory", AuthCtx::for_testing())?;
+
+        assert_eq!(result.len(), 1, "Not return results");
+        let result = result.first().unwrap().clone();
+
+        assert_eq!(
+            result.as_without_table_name(),
+            input.as_without_table_name(),
+            "Inventory"
+        );
+
+        let result = run(
+            &db,
+            (&tx).into(),
+            "INSERT INTO inventory (inventory_id, name) VALUES (2, 'test')",
+            AuthCtx::for_testing(),
+        );
+        assert!(result.is_err(), "Modified the database in a read-only transaction");
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
     #[test]
     fn test_select_star_table() -> ResultTest<()> {
         let (db, input, _tmp_dir) = create_data(1)?;
@@ -405,6 +729,628 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_order_by_limit() -> ResultTest<()> {
+        let (db, _input, _tmp_dir) = create_data(5)?;
+        let tx = db.begin_read_tx();
+        let ids = |sql: &str| -> ResultTest<Vec<u64>> {
+            let result = run(&db, (&tx).into(), sql, AuthCtx::for_testing())?;
+            Ok(result[0]
+                .data
+                .iter()
+                .map(|row| *row.elements[0].as_u64().unwrap())
+                .collect())
+        };
+
+        assert_eq!(
+            ids("SELECT inventory_id FROM inventory ORDER BY name DESC")?,
+            vec![5, 4, 3, 2, 1]
+        );
+        assert_eq!(
+            ids("SELECT inventory_id FROM inventory ORDER BY name DESC LIMIT 2 OFFSET 1")?,
+            vec![4, 3]
+        );
+        assert_eq!(
+            ids("SELECT inventory_id FROM inventory WHERE inventory_id > 1 ORDER BY inventory_id LIMIT 2")?,
+            vec![2, 3]
+        );
+        assert_eq!(ids("SELECT * FROM inventory ORDER BY inventory_id OFFSET 4")?, vec![5]);
+        assert_eq!(ids("SELECT * FROM inventory LIMIT 0")?, Vec::<u64>::new());
+
+        let result = run(
+            &db,
+            (&tx).into(),
+            "SELECT * FROM inventory LIMIT -1",
+            AuthCtx::for_testing(),
+        );
+        assert!(result.is_err(), "Accepted a negative LIMIT");
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> ResultTest<()> {
+        let (db, _input, _tmp_dir) = create_data(5)?;
+        let mut tx = db.begin_tx();
+        let table_id = db.table_id_from_name(&tx, "inventory")?.unwrap();
+        db.create_index(
+            &mut tx,
+            IndexDef::new("inventory_id_idx".into(), table_id, vec![0], IndexType::BTree, false),
+        )?;
+        let head = ProductType::from_iter([("player_id", BuiltinType::U64), ("inventory_id", BuiltinType::U64)]);
+        let rows: Vec<_> = (1..=3u64).map(|i| product!(i + 10, i)).collect();
+        create_table_with_rows(&db, &mut tx, "player", head, &rows)?;
+        db.commit_tx(tx)?;
+
+        let tx = db.begin_read_tx();
+        let run = |sql: &str| run(&db, (&tx).into(), sql, AuthCtx::for_testing());
+        let plan = |result: &MemTable| -> Vec<(String, String)> {
+            result
+                .data
+                .iter()
+                .map(|row| {
+                    let text = |pos: usize| row.elements[pos].as_string().unwrap().to_string();
+                    (text(0), text(1))
+                })
+                .collect()
+        };
+
+        let result = run("EXPLAIN SELECT name FROM inventory WHERE inventory_id = 2")?;
+        assert_eq!(result[0].head.fields.len(), 2);
+        let plan = plan(&result[0]);
+        let operations: Vec<_> = plan.iter().map(|(operation, _)| operation.as_str()).collect();
+        assert_eq!(operations, ["Project", "-> Index Scan"]);
+        assert_eq!(plan[0].1, "inventory.name");
+        assert!(plan[1].1.contains("btree index `inventory_id_idx`"), "{}", plan[1].1);
+
+        // The query runs, and each operation reports the rows it yielded.
+        let result = run("EXPLAIN ANALYZE SELECT inventory.* FROM inventory \
+            JOIN player ON inventory.inventory_id = player.inventory_id WHERE player.player_id > 11")?;
+        let operations: Vec<_> = result[0]
+            .data
+            .iter()
+            .map(|row| {
+                (
+                    row.elements[0].as_string().unwrap().as_str(),
+                    *row.elements[2].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            operations,
+            [
+                ("Project", 2),
+                ("-> Hash Join", 2),
+                ("  -> Filter", 2),
+                ("    -> Table Scan", 3),
+                ("  -> Table Scan", 5),
+            ]
+        );
+
+        let result = run("EXPLAIN DELETE FROM inventory");
+        assert!(result.is_err(), "Explained a DELETE");
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_strategy() -> ResultTest<()> {
+        let (db, _input, _tmp_dir) = create_data(5)?;
+        let mut tx = db.begin_tx();
+        let table_id = db.table_id_from_name(&tx, "inventory")?.unwrap();
+        db.create_index(
+            &mut tx,
+            IndexDef::new("inventory_id_idx".into(), table_id, vec![0], IndexType::BTree, false),
+        )?;
+        let head = ProductType::from_iter([("player_id", BuiltinType::U64), ("inventory_id", BuiltinType::U64)]);
+        let rows: Vec<_> = (1..=3u64).map(|i| product!(i + 10, i)).collect();
+        let table_id = create_table_with_rows(&db, &mut tx, "player", head, &rows)?;
+        db.create_index(
+            &mut tx,
+            IndexDef::new(
+                "player_inventory_id_idx".into(),
+                table_id,
+                vec![1],
+                IndexType::BTree,
+                false,
+            ),
+        )?;
+        db.commit_tx(tx)?;
+
+        let tx = db.begin_read_tx();
+        let run = |sql: &str| run(&db, (&tx).into(), sql, AuthCtx::for_testing());
+        let operations = |sql: &str| -> ResultTest<Vec<(String, String)>> {
+            let result = run(&format!("EXPLAIN {sql}"))?;
+            Ok(result[0]
+                .data
+                .iter()
+                .map(|row| {
+                    let text = |pos: usize| row.elements[pos].as_string().unwrap().trim_start().to_string();
+                    (text(0), text(1))
+                })
+                .collect())
+        };
+
+        // The few players left by the filter seek the index of the larger inventory,
+        // which also filters the rows it finds.
+        let sql = "SELECT inventory.name FROM player JOIN inventory ON player.inventory_id = inventory.inventory_id \
+            WHERE player.player_id > 11 AND inventory.name != 'health3'";
+        let plan = operations(sql)?;
+        assert_eq!(plan[1].0, "-> Index Join");
+        assert!(plan[1].1.contains("btree index `inventory_id_idx`"), "{}", plan[1].1);
+        assert!(plan[1].1.contains("health3"), "{}", plan[1].1);
+        let result = run(sql)?;
+        assert_eq!(result[0].data, vec![product!("health2")]);
+
+        // Each of the inventories would be a seek into the smaller players, so the players are hashed instead.
+        let sql = "SELECT player.player_id FROM inventory JOIN player ON inventory.inventory_id = player.inventory_id";
+        let plan = operations(sql)?;
+        assert_eq!(plan[1].0, "-> Hash Join");
+        let result = run(sql)?;
+        let mut ids: Vec<_> = result[0]
+            .data
+            .iter()
+            .map(|row| *row.elements[0].as_u64().unwrap())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, [11, 12, 13]);
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    fn create_guilds(db: &RelationalDB) -> ResultTest<()> {
+        let mut tx = db.begin_tx();
+        let head = ProductType::from_iter([
+            ("id", BuiltinType::U64),
+            ("name", BuiltinType::String),
+            ("guild_id", BuiltinType::U64),
+        ]);
+        let rows = vec![
+            product!(1u64, "a", 10u64),
+            product!(2u64, "b", 20u64),
+            product!(3u64, "c", 99u64),
+        ];
+        create_table_with_rows(db, &mut tx, "player", head, &rows)?;
+        let head = ProductType::from_iter([("id", BuiltinType::U64), ("name", BuiltinType::String)]);
+        let rows = vec![
+            product!(10u64, "red"),
+            product!(20u64, "blue"),
+            product!(30u64, "green"),
+        ];
+        create_table_with_rows(db, &mut tx, "guild", head, &rows)?;
+        db.commit_tx(tx)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_outer_join() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        create_guilds(&db)?;
+
+        let tx = db.begin_read_tx();
+        let run = |sql: &str| run(&db, (&tx).into(), sql, AuthCtx::for_testing());
+        let some = |x: &str| AlgebraicValue::OptionSome(x.into());
+        let none = AlgebraicValue::OptionNone;
+
+        // The players without a guild get `none` for the columns of the guild.
+        let result = run("SELECT player.name, guild.name FROM player LEFT JOIN guild ON player.guild_id = guild.id")?;
+        assert_eq!(
+            result[0].head.ty(),
+            ProductType::from_iter([
+                ("name", AlgebraicType::String),
+                ("name", AlgebraicType::option(AlgebraicType::String)),
+            ])
+        );
+        assert_eq!(
+            result[0].data,
+            vec![
+                product!("a", some("red")),
+                product!("b", some("blue")),
+                product!("c", none())
+            ]
+        );
+
+        let result = run(
+            "SELECT player.name FROM player LEFT JOIN guild ON guild.id = player.guild_id \
+            WHERE guild.id IS NULL OR guild.name = 'red'",
+        )?;
+        assert_eq!(result[0].data, vec![product!("a"), product!("c")]);
+
+        // A comparison with `none` is unknown, so it filters out the players without a guild, even negated.
+        for filter in ["guild.id > 10", "guild.id != 10", "NOT guild.id < 15"] {
+            let result = run(&format!(
+                "SELECT player.name FROM player LEFT JOIN guild ON player.guild_id = guild.id WHERE {filter}"
+            ))?;
+            assert_eq!(result[0].data, vec![product!("b")], "{filter}");
+        }
+
+        // The guilds without players come after the others.
+        let result = run("SELECT player.name, guild.name FROM player RIGHT JOIN guild ON player.guild_id = guild.id")?;
+        assert_eq!(
+            result[0].data,
+            vec![
+                product!(some("a"), "red"),
+                product!(some("b"), "blue"),
+                product!(none(), "green")
+            ]
+        );
+
+        let result = run("SELECT player.name, guild.name FROM player FULL JOIN guild ON player.guild_id = guild.id")?;
+        assert_eq!(
+            result[0].data,
+            vec![
+                product!(some("a"), some("red")),
+                product!(some("b"), some("blue")),
+                product!(some("c"), none()),
+                product!(none(), some("green"))
+            ]
+        );
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_tables() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        create_guilds(&db)?;
+
+        let tx = db.begin_read_tx();
+        let run = |sql: &str| run(&db, (&tx).into(), sql, AuthCtx::for_testing());
+        let operations = |sql: &str| -> ResultTest<Vec<String>> {
+            let result = run(&format!("EXPLAIN {sql}"))?;
+            Ok(result[0]
+                .data
+                .iter()
+                .map(|row| row.elements[0].as_string().unwrap().trim_start().to_string())
+                .collect())
+        };
+
+        // The comparison of the columns of both tables joins them.
+        let sql =
+            "SELECT player.name, guild.name FROM player, guild WHERE guild.id = player.guild_id AND guild.id > 10";
+        assert_eq!(operations(sql)?[1], "-> Hash Join");
+        let result = run(sql)?;
+        assert_eq!(result[0].data, vec![product!("b", "blue")]);
+
+        // Otherwise, every player is combined with every guild.
+        let sql = "SELECT player.name, guild.name FROM player CROSS JOIN guild WHERE guild.name != 'red'";
+        assert_eq!(operations(sql)?[1], "-> Cross Join");
+        let result = run(sql)?;
+        assert_eq!(result[0].data.len(), 6);
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_operations() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        create_guilds(&db)?;
+
+        let tx = db.begin_read_tx();
+        let run = |sql: &str| run(&db, (&tx).into(), sql, AuthCtx::for_testing());
+        let ids = |sql: &str| -> ResultTest<Vec<u64>> {
+            Ok(run(sql)?[0]
+                .data
+                .iter()
+                .map(|row| *row.elements[0].as_u64().unwrap())
+                .collect())
+        };
+
+        let result = ids("SELECT guild_id FROM player UNION SELECT id FROM guild ORDER BY guild_id")?;
+        assert_eq!(result, vec![10, 20, 30, 99]);
+
+        let result = ids("SELECT guild_id FROM player UNION ALL SELECT id FROM guild ORDER BY guild_id DESC LIMIT 3")?;
+        assert_eq!(result, vec![99, 30, 20]);
+
+        let result = ids("SELECT guild_id FROM player INTERSECT SELECT id FROM guild")?;
+        assert_eq!(result, vec![10, 20]);
+
+        let result = ids("SELECT id FROM guild EXCEPT SELECT guild_id FROM player")?;
+        assert_eq!(result, vec![30]);
+
+        // Each row of the right side cancels a single row of the left side.
+        let mut result = ids("SELECT player.guild_id FROM player CROSS JOIN guild \
+            EXCEPT ALL SELECT guild_id FROM player")?;
+        result.sort();
+        assert_eq!(result, vec![10, 10, 20, 20, 99, 99]);
+
+        let result = ids("SELECT DISTINCT player.guild_id FROM player CROSS JOIN guild LIMIT 2")?;
+        assert_eq!(result, vec![10, 20]);
+
+        assert!(
+            run("SELECT id FROM player UNION SELECT name FROM guild").is_err(),
+            "The columns are of different types"
+        );
+        assert!(
+            run("SELECT id, name FROM player UNION SELECT id FROM guild").is_err(),
+            "The queries have a different number of columns"
+        );
+        assert!(
+            run("SELECT guild_id FROM player UNION SELECT id FROM guild ORDER BY name").is_err(),
+            "`name` is not a column of the result"
+        );
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_subqueries() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        create_guilds(&db)?;
+
+        let mut tx = db.begin_tx();
+        let names = |tx: &mut MutTxId, sql: &str| -> ResultTest<Vec<String>> {
+            Ok(run_for_testing(&db, tx, sql)?[0]
+                .data
+                .iter()
+                .map(|row| row.elements[0].as_string().unwrap().to_string())
+                .collect())
+        };
+
+        let sql = "SELECT name FROM player WHERE guild_id IN (SELECT id FROM guild WHERE name != 'red')";
+        assert_eq!(names(&mut tx, sql)?, vec!["b"]);
+
+        let sql = "SELECT name FROM player WHERE guild_id NOT IN (SELECT id FROM guild WHERE name != 'red')";
+        assert_eq!(names(&mut tx, sql)?, vec!["a", "c"]);
+
+        let sql = "SELECT name FROM player WHERE id > 1 AND guild_id IN (SELECT id FROM guild)";
+        assert_eq!(names(&mut tx, sql)?, vec!["b"]);
+
+        let sql = "SELECT name FROM guild WHERE EXISTS (SELECT * FROM player WHERE guild_id = 99)";
+        assert_eq!(names(&mut tx, sql)?.len(), 3);
+
+        let sql = "SELECT name FROM guild WHERE NOT EXISTS (SELECT * FROM player WHERE guild_id = 99)";
+        assert!(names(&mut tx, sql)?.is_empty());
+
+        let sql = "EXPLAIN SELECT name FROM player WHERE guild_id IN (SELECT id FROM guild)";
+        let plan = names(&mut tx, sql)?;
+        assert!(plan.iter().any(|x| x.trim_start() == "-> Semi Join"), "{plan:?}");
+
+        assert!(
+            run_for_testing(
+                &db,
+                &mut tx,
+                "SELECT name FROM player WHERE id = 1 OR guild_id IN (SELECT id FROM guild)"
+            )
+            .is_err(),
+            "Subqueries are only supported joined by `AND`"
+        );
+        assert!(
+            run_for_testing(
+                &db,
+                &mut tx,
+                "SELECT name FROM player WHERE guild_id IN (SELECT id, name FROM guild)"
+            )
+            .is_err(),
+            "The subquery must yield a single column"
+        );
+
+        // The players without a guild are deleted.
+        run_for_testing(
+            &db,
+            &mut tx,
+            "DELETE FROM player WHERE guild_id NOT IN (SELECT id FROM guild)",
+        )?;
+        assert_eq!(names(&mut tx, "SELECT name FROM player")?, vec!["a", "b"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let mut tx = db.begin_tx();
+        let head = ProductType::from_iter([("name", BuiltinType::String), ("score", BuiltinType::I32)]);
+        let rows = vec![
+            product!(scalar("b"), scalar(3i32)),
+            product!(scalar("a"), scalar(-1i32)),
+            product!(scalar("b"), scalar(5i32)),
+            product!(scalar("c"), scalar(4i32)),
+        ];
+        create_table_with_rows(&db, &mut tx, "player", head.clone(), &rows)?;
+        create_table_with_rows(&db, &mut tx, "empty", head, &[])?;
+        db.commit_tx(tx)?;
+
+        let tx = db.begin_read_tx();
+        let run = |sql: &str| run(&db, (&tx).into(), sql, AuthCtx::for_testing());
+
+        let result = run("SELECT COUNT(*), SUM(score), MIN(name), MAX(score), AVG(score) FROM player")?;
+        assert_eq!(
+            result[0].head.ty(),
+            ProductType::from_iter([
+                ("count(*)", AlgebraicType::U64),
+                ("sum(score)", AlgebraicType::I64),
+                ("min(name)", AlgebraicType::option(AlgebraicType::String)),
+                ("max(score)", AlgebraicType::option(AlgebraicType::I32)),
+                ("avg(score)", AlgebraicType::option(AlgebraicType::F64)),
+            ]),
+            "Aggregate schema"
+        );
+        assert_eq!(
+            result[0].data,
+            vec![product!(
+                scalar(4u64),
+                scalar(11i64),
+                AlgebraicValue::OptionSome(scalar("a")),
+                AlgebraicValue::OptionSome(scalar(5i32)),
+                AlgebraicValue::OptionSome(scalar(2.75f64))
+            )],
+            "Aggregate"
+        );
+
+        let result = run("SELECT COUNT(*) AS total, MAX(score) FROM empty")?;
+        assert_eq!(
+            result[0].head.ty(),
+            ProductType::from_iter([
+                ("total", AlgebraicType::U64),
+                ("max(score)", AlgebraicType::option(AlgebraicType::I32)),
+            ]),
+            "Aggregate with alias"
+        );
+        assert_eq!(
+            result[0].data,
+            vec![product!(scalar(0u64), AlgebraicValue::OptionNone())],
+            "Aggregate of no rows"
+        );
+
+        let result = run(
+            "SELECT name, COUNT(*) AS total FROM player WHERE score > 0 GROUP BY name HAVING SUM(score) > 4 ORDER BY total DESC, name",
+        )?;
+        assert_eq!(
+            result[0].data,
+            vec![product!(scalar("b"), scalar(2u64))],
+            "Group by with having"
+        );
+
+        let result = run("SELECT name, MAX(score) FROM player GROUP BY name ORDER BY MAX(score) DESC LIMIT 2")?;
+        assert_eq!(
+            result[0].data,
+            vec![
+                product!(scalar("b"), AlgebraicValue::OptionSome(scalar(5i32))),
+                product!(scalar("c"), AlgebraicValue::OptionSome(scalar(4i32))),
+            ],
+            "Group by with order by aggregate"
+        );
+
+        assert!(
+            run("SELECT score, COUNT(*) FROM player GROUP BY name").is_err(),
+            "Ungrouped field"
+        );
+        assert!(run("SELECT * FROM player GROUP BY name").is_err(), "Wildcard");
+        assert!(
+            run("SELECT name FROM player WHERE COUNT(*) > 1").is_err(),
+            "Aggregate in WHERE"
+        );
+        assert!(
+            run("SELECT name FROM player HAVING score > 1").is_err(),
+            "HAVING without GROUP BY"
+        );
+        assert!(run("SELECT SUM(name) FROM player").is_err(), "Sum of strings");
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scalar_expressions() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        create_guilds(&db)?;
+
+        let tx = db.begin_read_tx();
+        let run = |sql: &str| run(&db, (&tx).into(), sql, AuthCtx::for_testing());
+
+        // The literals get the type of the column they are operated with.
+        let result = run("SELECT name, id * 2 + 1 AS odd FROM player WHERE id - 1 > 0")?;
+        assert_eq!(
+            result[0].head.ty(),
+            ProductType::from_iter([("name", AlgebraicType::String), ("odd", AlgebraicType::U64)])
+        );
+        assert_eq!(result[0].data, vec![product!("b", 5u64), product!("c", 7u64)]);
+
+        let result = run("SELECT name FROM player WHERE guild_id BETWEEN 10 AND 20 AND NOT id = 1")?;
+        assert_eq!(result[0].data, vec![product!("b")]);
+        let result = run("SELECT name FROM player WHERE id NOT BETWEEN 2 AND 3")?;
+        assert_eq!(result[0].data, vec![product!("a")]);
+
+        let result = run("SELECT name FROM guild WHERE name LIKE '%r%' AND name NOT LIKE 'g_een'")?;
+        assert_eq!(result[0].data, vec![product!("red")]);
+        let result = run("SELECT name FROM guild WHERE name ILIKE 'B%'")?;
+        assert_eq!(result[0].data, vec![product!("blue")]);
+
+        // The operations on options yield options, and `none` is neither true nor false.
+        let result = run(
+            "SELECT player.name, guild.id / 10 FROM player LEFT JOIN guild ON player.guild_id = guild.id \
+            WHERE guild.id + 0 IS NULL OR guild.name LIKE 'r%'",
+        )?;
+        assert_eq!(
+            result[0].head.ty(),
+            ProductType::from_iter([
+                ("name", AlgebraicType::String),
+                ("guild.id / 10", AlgebraicType::option(AlgebraicType::U64)),
+            ])
+        );
+        assert_eq!(
+            result[0].data,
+            vec![
+                product!("a", AlgebraicValue::OptionSome(1u64.into())),
+                product!("c", AlgebraicValue::OptionNone())
+            ]
+        );
+
+        // `some(x)` compares as `x`, and comparing with `none` drops the unmatched row.
+        let result = run(
+            "SELECT player.name FROM player LEFT JOIN guild ON player.guild_id = guild.id \
+            WHERE guild.id = player.guild_id",
+        )?;
+        assert_eq!(result[0].data, vec![product!("a"), product!("b")]);
+        let result = run(
+            "SELECT player.name FROM player LEFT JOIN guild ON player.guild_id = guild.id WHERE guild.id / 10 > 1",
+        )?;
+        assert_eq!(result[0].data, vec![product!("b")]);
+
+        assert!(run("SELECT -id FROM player").is_err(), "Overflow of an unsigned column");
+
+        assert!(run("SELECT name + 1 FROM player").is_err(), "String plus number");
+        assert!(
+            run("SELECT name FROM player WHERE id").is_err(),
+            "Condition that isn't a bool"
+        );
+        assert!(run("SELECT id / (id - id) FROM player").is_err(), "Division by zero");
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scalar_functions() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        create_guilds(&db)?;
+
+        let tx = db.begin_read_tx();
+        let run = |sql: &str| run(&db, (&tx).into(), sql, AuthCtx::for_testing());
+
+        let result = run("SELECT upper(name), length(name) AS len FROM guild WHERE lower(name) = 'blue'")?;
+        assert_eq!(
+            result[0].head.ty(),
+            ProductType::from_iter([("upper(name)", AlgebraicType::String), ("len", AlgebraicType::U64)])
+        );
+        assert_eq!(result[0].data, vec![product!("BLUE", 4u64)]);
+
+        let result = run("SELECT abs(1 - 3) FROM player WHERE id = 1")?;
+        assert_eq!(result[0].data, vec![product!(2i32)]);
+
+        // `coalesce` yields the first argument that isn't `none`, of the type of the others.
+        let result = run(
+            "SELECT coalesce(guild.name, 'none') AS guild FROM player LEFT JOIN guild ON player.guild_id = guild.id",
+        )?;
+        assert_eq!(
+            result[0].head.ty(),
+            ProductType::from_iter([("guild", AlgebraicType::String)])
+        );
+        assert_eq!(
+            result[0].data,
+            vec![product!("red"), product!("blue"), product!("none")]
+        );
+
+        let result = run("SELECT name, COUNT(*) * 10 AS total FROM player GROUP BY name HAVING length(name) = 1 ORDER BY name LIMIT 1")?;
+        assert_eq!(result[0].data, vec![product!("a", 10u64)]);
+
+        assert!(run("SELECT lower(id) FROM player").is_err(), "Lowercase of a number");
+        assert!(
+            run("SELECT coalesce(id, name) FROM player").is_err(),
+            "Arguments of different types"
+        );
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
     #[test]
     fn test_insert() -> ResultTest<()> {
         let (db, mut input, _tmp_dir) = create_data(1)?;
@@ -436,6 +1382,126 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_returning() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let mut tx = db.begin_tx();
+        run_for_testing(
+            &db,
+            &mut tx,
+            "CREATE TABLE player (id BIGINT PRIMARY KEY GENERATED BY DEFAULT AS IDENTITY, name TEXT, score INT)",
+        )?;
+
+        // The ids assigned by the sequence are observable.
+        let result = run_for_testing(
+            &db,
+            &mut tx,
+            "INSERT INTO player VALUES (0, 'a', 1), (0, 'b', 2) RETURNING id, name",
+        )?;
+        assert_eq!(
+            result[0].head.ty(),
+            ProductType::from_iter([("id", AlgebraicType::I64), ("name", AlgebraicType::String)])
+        );
+        assert_eq!(result[0].data, vec![product!(1i64, "a"), product!(2i64, "b")]);
+
+        let result = run_for_testing(
+            &db,
+            &mut tx,
+            "UPDATE player SET score = 10 WHERE name = 'a' RETURNING id, score * 2 AS double",
+        )?;
+        assert_eq!(result[0].data, vec![product!(1i64, 20i32)]);
+
+        let result = run_for_testing(&db, &mut tx, "DELETE FROM player WHERE id = 2 RETURNING *")?;
+        assert_eq!(result[0].data, vec![product!(2i64, "b", 2i32)]);
+
+        // Without `RETURNING`, nothing is yielded.
+        let result = run_for_testing(&db, &mut tx, "INSERT INTO player VALUES (0, 'c', 3)")?;
+        assert!(result.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_conflict() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let mut tx = db.begin_tx();
+        run_for_testing(
+            &db,
+            &mut tx,
+            "CREATE TABLE player (id BIGINT PRIMARY KEY GENERATED BY DEFAULT AS IDENTITY, name TEXT UNIQUE, score INT)",
+        )?;
+        run_for_testing(&db, &mut tx, "INSERT INTO player VALUES (0, 'a', 1), (0, 'b', 2)")?;
+
+        let result = run_for_testing(
+            &db,
+            &mut tx,
+            "INSERT INTO player VALUES (0, 'a', 5), (0, 'c', 3) \
+            ON CONFLICT (name) DO UPDATE SET score = player.score + excluded.score RETURNING id, name, score",
+        )?;
+        // The conflicting row still takes the next value of the sequence, as in PostgreSQL.
+        assert_eq!(
+            result[0].data,
+            vec![product!(1i64, "a", 6i32), product!(4i64, "c", 3i32)],
+            "Upsert"
+        );
+
+        let result = run_for_testing(
+            &db,
+            &mut tx,
+            "INSERT INTO player VALUES (0, 'b', 1) \
+            ON CONFLICT (name) DO UPDATE SET score = excluded.score WHERE excluded.score > player.score RETURNING name",
+        )?;
+        assert!(result[0].data.is_empty(), "Upsert skipped by its WHERE");
+
+        let result = run_for_testing(
+            &db,
+            &mut tx,
+            "INSERT INTO player VALUES (0, 'b', 9) ON CONFLICT (name) DO NOTHING RETURNING name",
+        )?;
+        assert!(result[0].data.is_empty(), "Conflict ignored");
+        run_for_testing(
+            &db,
+            &mut tx,
+            "INSERT INTO player VALUES (1, 'd', 9) ON CONFLICT DO NOTHING",
+        )?;
+
+        let result = run_for_testing(&db, &mut tx, "SELECT name, score FROM player")?;
+        assert_eq!(
+            result[0].data,
+            vec![product!("a", 6i32), product!("b", 2i32), product!("c", 3i32)]
+        );
+
+        assert!(
+            run_for_testing(
+                &db,
+                &mut tx,
+                "INSERT INTO player VALUES (1, 'e', 0) ON CONFLICT (name) DO NOTHING"
+            )
+            .is_err(),
+            "Conflict on another unique constraint"
+        );
+        assert!(
+            run_for_testing(
+                &db,
+                &mut tx,
+                "INSERT INTO player VALUES (0, 'e', 0) ON CONFLICT (score) DO NOTHING"
+            )
+            .is_err(),
+            "Columns without a unique constraint"
+        );
+        assert!(
+            run_for_testing(
+                &db,
+                &mut tx,
+                "INSERT INTO player VALUES (0, 'a', 0) ON CONFLICT (name) DO UPDATE SET score = score + 1"
+            )
+            .is_err(),
+            "Ambiguous column of the existing row and the row to insert"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_delete() -> ResultTest<()> {
         let (db, _input, _tmp_dir) = create_data(1)?;
@@ -588,6 +1654,254 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_create_drop_index() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(2)?;
+        let mut tx = db.begin_tx();
+
+        run_for_testing(&db, &mut tx, "CREATE UNIQUE INDEX inventory_name ON inventory (name)")?;
+        run_for_testing(
+            &db,
+            &mut tx,
+            "CREATE INDEX inventory_id_name ON inventory USING hash (inventory_id, name)",
+        )?;
+        let table_id = db.table_id_from_name(&tx, "inventory")?.unwrap();
+        let indexes = db.schema_for_table(&tx, table_id)?.indexes;
+        assert_eq!(
+            indexes
+                .iter()
+                .map(|x| (x.index_name.as_str(), x.cols.clone(), x.index_type, x.is_unique))
+                .collect::<Vec<_>>(),
+            vec![
+                ("inventory_name", vec![1], IndexType::BTree, true),
+                ("inventory_id_name", vec![0, 1], IndexType::Hash, false),
+            ]
+        );
+
+        // The index is filled with the existing rows, and enforces its constraint on the new ones.
+        let result = run_for_testing(&db, &mut tx, "EXPLAIN SELECT * FROM inventory WHERE name = 'health1'")?;
+        assert_eq!(result[0].data[0].elements[0].as_string().unwrap(), "Index Scan");
+        assert!(
+            run_for_testing(
+                &db,
+                &mut tx,
+                "INSERT INTO inventory (inventory_id, name) VALUES (3, 'health1')"
+            )
+            .is_err(),
+            "The index on `name` is unique"
+        );
+        assert!(
+            run_for_testing(&db, &mut tx, "CREATE INDEX other ON inventory (missing)").is_err(),
+            "The column doesn't exist"
+        );
+
+        run_for_testing(&db, &mut tx, "DROP INDEX inventory_name")?;
+        assert_eq!(db.index_id_from_name(&tx, "inventory_name")?, None);
+        assert!(
+            run_for_testing(&db, &mut tx, "DROP INDEX inventory_name").is_err(),
+            "The index was already dropped"
+        );
+        run_for_testing(&db, &mut tx, "DROP INDEX IF EXISTS inventory_name")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_alter_table() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(2)?;
+        let mut tx = db.begin_tx();
+
+        run_for_testing(&db, &mut tx, "ALTER TABLE inventory RENAME TO item")?;
+        assert!(run_for_testing(&db, &mut tx, "SELECT * FROM inventory").is_err());
+
+        run_for_testing(&db, &mut tx, "ALTER TABLE item ADD COLUMN price INT DEFAULT 10")?;
+        run_for_testing(&db, &mut tx, "ALTER TABLE item ADD COLUMN note TEXT NULL")?;
+        run_for_testing(&db, &mut tx, "INSERT INTO item VALUES (3, 'health3', 5, 'new')")?;
+        let result = run_for_testing(&db, &mut tx, "SELECT * FROM item")?;
+        assert_eq!(
+            result[0].head.ty(),
+            ProductType::from_iter([
+                ("inventory_id", AlgebraicType::U64),
+                ("name", AlgebraicType::String),
+                ("price", AlgebraicType::I32),
+                ("note", AlgebraicType::option(AlgebraicType::String)),
+            ])
+        );
+        assert_eq!(
+            result[0].data,
+            vec![
+                product!(1u64, "health1", 10i32, AlgebraicValue::OptionNone()),
+                product!(2u64, "health2", 10i32, AlgebraicValue::OptionNone()),
+                product!(3u64, "health3", 5i32, AlgebraicValue::OptionSome("new".into())),
+            ]
+        );
+
+        assert!(
+            run_for_testing(&db, &mut tx, "ALTER TABLE item ADD COLUMN stock INT").is_err(),
+            "The existing rows need a value for a `NOT NULL` column"
+        );
+        assert!(
+            run_for_testing(&db, &mut tx, "ALTER TABLE item RENAME TO st_item").is_err(),
+            "The `st_` prefix is reserved for the system tables"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_changes_need_owner() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(1)?;
+        let mut tx = db.begin_tx();
+        run_for_testing(&db, &mut tx, "CREATE INDEX inventory_name ON inventory (name)")?;
+
+        let auth = AuthCtx::new(Identity::__dummy(), Identity::from_byte_array([1u8; 32]));
+        for sql in [
+            "CREATE INDEX inventory_id ON inventory (inventory_id)",
+            "DROP INDEX inventory_name",
+            "ALTER TABLE inventory RENAME TO item",
+            "ALTER TABLE inventory ADD COLUMN note TEXT NULL",
+        ] {
+            let result = run(&db, (&mut tx).into(), sql, auth);
+            assert!(result.is_err(), "`{sql}` by a caller other than the owner");
+        }
+        assert!(db.index_id_from_name(&tx, "inventory_name")?.is_some());
+        assert!(db.table_id_from_name(&tx, "inventory")?.is_some());
+
+        Ok(())
+    }
+
+    fn run_prepared(
+        db: &Arc<RelationalDB>,
+        tx: &mut MutTxId,
+        prepared: &PreparedStatements,
+        sql_text: &str,
+        params: &[SqlParam],
+    ) -> Result<Vec<MemTable>, DBError> {
+        let prepared = prepared.prepare(sql_text)?;
+        let request = Request {
+            db,
+            sql_text,
+            prepared: &prepared,
+            params,
+            auth: AuthCtx::for_testing(),
+            subscription: None,
+        };
+        request.run(tx, &(0..prepared.statements.len()))
+    }
+
+    fn json(value: &str) -> SqlParam {
+        SqlParam::Json(value.into())
+    }
+
+    #[test]
+    fn test_params() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(2)?;
+        let db = Arc::new(db);
+        let mut tx = db.begin_tx();
+        let prepared = PreparedStatements::default();
+
+        let select = "SELECT * FROM inventory WHERE inventory_id = $1";
+        let result = run_prepared(&db, &mut tx, &prepared, select, &[json("2")])?;
+        assert_eq!(result[0].data, vec![product!(2u64, "health2")]);
+
+        let insert = "INSERT INTO inventory (inventory_id, name) VALUES ($1, $2)";
+        run_prepared(
+            &db,
+            &mut tx,
+            &prepared,
+            insert,
+            &[json("3"), json(r#""it's \"quoted\"""#)],
+        )?;
+        let result = run_prepared(&db, &mut tx, &prepared, select, &[json("3")])?;
+        assert_eq!(result[0].data, vec![product!(3u64, r#"it's "quoted""#)]);
+
+        let update = "UPDATE inventory SET name = $2 WHERE inventory_id = $1";
+        let id = SqlParam::Bsatn(bsatn::to_vec(&1u64)?.into());
+        run_prepared(&db, &mut tx, &prepared, update, &[id, json(r#""renamed""#)])?;
+        let result = run_prepared(&db, &mut tx, &prepared, select, &[json("1")])?;
+        assert_eq!(result[0].data, vec![product!(1u64, "renamed")]);
+        assert_eq!(prepared.len(), 3, "Each statement is parsed once");
+
+        assert!(
+            run_prepared(&db, &mut tx, &prepared, select, &[]).is_err(),
+            "`$1` has no parameter"
+        );
+        assert!(
+            run_prepared(&db, &mut tx, &prepared, select, &[json(r#""one""#)]).is_err(),
+            "`$1` is not a `u64`"
+        );
+        let untyped = "SELECT * FROM inventory WHERE $1 = $2";
+        assert!(
+            run_prepared(&db, &mut tx, &prepared, untyped, &[json("1"), json("1")]).is_err(),
+            "The type of the parameters can't be inferred"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_params_option() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(0)?;
+        let db = Arc::new(db);
+        let mut tx = db.begin_tx();
+        let prepared = PreparedStatements::default();
+
+        run_for_testing(&db, &mut tx, "CREATE TABLE note (id BIGINT UNSIGNED, text TEXT NULL)")?;
+        let insert = "INSERT INTO note VALUES ($1, $2)";
+        run_prepared(&db, &mut tx, &prepared, insert, &[json("1"), json("null")])?;
+        run_prepared(&db, &mut tx, &prepared, insert, &[json("2"), json(r#""hi""#)])?;
+
+        let result = run_for_testing(&db, &mut tx, "SELECT * FROM note")?;
+        assert_eq!(
+            result[0].data,
+            vec![
+                product!(1u64, AlgebraicValue::OptionNone()),
+                product!(2u64, AlgebraicValue::OptionSome("hi".into())),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepared_plans() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(2)?;
+        let db = Arc::new(db);
+        let mut tx = db.begin_tx();
+        let prepared = PreparedStatements::default();
+
+        let select = "SELECT * FROM inventory WHERE inventory_id = $1";
+        run_prepared(&db, &mut tx, &prepared, select, &[json("1")])?;
+        let statements = prepared.prepare(select)?;
+        let is_cached = |tx: &MutTxId| {
+            let not_cached = || {
+                Err(DBError::Plan {
+                    sql: select.into(),
+                    error: PlanError::Unstructured("not cached".into()),
+                })
+            };
+            statements.plan(&(0..1), tx.schema_version(), not_cached).is_ok()
+        };
+        assert!(is_cached(&tx), "The plan is reused while the schema is the same");
+
+        let result = run_prepared(&db, &mut tx, &prepared, select, &[json("2")])?;
+        assert_eq!(result[0].data, vec![product!(2u64, "health2")]);
+        run_for_testing(
+            &db,
+            &mut tx,
+            "INSERT INTO inventory (inventory_id, name) VALUES (3, 'health3')",
+        )?;
+        assert!(is_cached(&tx), "Changing the rows keeps the plan");
+
+        run_for_testing(&db, &mut tx, "CREATE INDEX inventory_id ON inventory (inventory_id)")?;
+        assert!(!is_cached(&tx), "Changing the schema drops the plan");
+        let result = run_prepared(&db, &mut tx, &prepared, select, &[json("3")])?;
+        assert_eq!(result[0].data, vec![product!(3u64, "health3")]);
+        assert!(is_cached(&tx));
+
+        Ok(())
+    }
+
     #[test]
     fn test_column_constraints() -> ResultTest<()> {
         let (db, _, _tmp_dir) = create_data(0)?;
@@ -651,6 +1965,238 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    fn run_script(
+        db: &Arc<RelationalDB>,
+        sessions: &SqlSessions,
+        session: Option<SessionId>,
+        sql_text: &str,
+        auth: AuthCtx,
+    ) -> Result<SqlOutcome, DBError> {
+        let prepared = Prepared::new(parse_script(sql_text)?);
+        let request = Request {
+            db,
+            sql_text,
+            prepared: &prepared,
+            params: &[],
+            auth,
+            subscription: None,
+        };
+        request.run_script(sessions, session)
+    }
+
+    /// The names in `inventory` as of the last commit.
+    fn committed_names(db: &RelationalDB) -> ResultTest<Vec<ProductValue>> {
+        let result = db.with_read_only(|tx| {
+            run(
+                db,
+                tx.into(),
+                "SELECT name FROM inventory ORDER BY inventory_id",
+                AuthCtx::for_testing(),
+            )
+        })?;
+        Ok(result[0].data.clone())
+    }
+
+    fn session_error<T>(result: Result<T, DBError>) -> Option<SessionError> {
+        match result {
+            Err(DBError::Session(err)) => Some(err),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_transaction_session() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(1)?;
+        let db = Arc::new(db);
+        let sessions = SqlSessions::default();
+        let auth = AuthCtx::for_testing();
+
+        let outcome = run_script(
+            &db,
+            &sessions,
+            None,
+            "BEGIN; INSERT INTO inventory VALUES (2, 'b')",
+            auth,
+        )?;
+        let session = outcome.session.expect("`BEGIN` opens a session");
+        run_script(
+            &db,
+            &sessions,
+            Some(session),
+            "INSERT INTO inventory VALUES (3, 'c')",
+            auth,
+        )?;
+        let outcome = run_script(&db, &sessions, Some(session), "SELECT name FROM inventory", auth)?;
+        assert_eq!(outcome.results[0].data.len(), 3, "The session sees its changes");
+        assert_eq!(outcome.session, Some(session));
+        assert_eq!(committed_names(&db)?, vec![product!("health1")], "Nobody else does");
+
+        let outcome = run_script(&db, &sessions, Some(session), "COMMIT", auth)?;
+        assert_eq!(outcome.session, None);
+        assert!(sessions.is_empty());
+        assert_eq!(committed_names(&db)?.len(), 3);
+        assert_eq!(
+            session_error(run_script(&db, &sessions, Some(session), "COMMIT", auth)),
+            Some(SessionError::NotFound(session))
+        );
+
+        let session = run_script(&db, &sessions, None, "BEGIN; DELETE FROM inventory", auth)?.session;
+        run_script(&db, &sessions, session, "ROLLBACK", auth)?;
+        assert_eq!(committed_names(&db)?.len(), 3);
+
+        // A failing request undoes what it did, but the transaction stays open.
+        let session = run_script(
+            &db,
+            &sessions,
+            None,
+            "BEGIN; INSERT INTO inventory VALUES (4, 'd')",
+            auth,
+        )?
+        .session;
+        let result = run_script(
+            &db,
+            &sessions,
+            session,
+            "INSERT INTO inventory VALUES (5, 'e'); SAVEPOINT a; SELECT * FROM nope",
+            auth,
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            session_error(run_script(&db, &sessions, session, "ROLLBACK TO a", auth)),
+            Some(SessionError::SavepointNotFound("a".into()))
+        );
+        let sql = "SELECT name FROM inventory WHERE inventory_id > 3; COMMIT";
+        let outcome = run_script(&db, &sessions, session, sql, auth)?;
+        assert_eq!(outcome.results[0].data, vec![product!("d")]);
+
+        let sql = "BEGIN; DELETE FROM inventory WHERE inventory_id = 4; COMMIT";
+        assert_eq!(run_script(&db, &sessions, None, sql, auth)?.session, None);
+        assert_eq!(committed_names(&db)?.len(), 3);
+
+        assert_eq!(
+            session_error(run_script(&db, &sessions, None, "COMMIT", auth)),
+            Some(SessionError::NoTransaction)
+        );
+        assert_eq!(
+            session_error(run_script(&db, &sessions, None, "BEGIN; BEGIN", auth)),
+            Some(SessionError::AlreadyInTransaction)
+        );
+        assert!(sessions.is_empty());
+
+        // Only the owner can keep a transaction open, and use its sessions.
+        let other = AuthCtx::new(Identity::__dummy(), Identity::from_byte_array([1u8; 32]));
+        assert_eq!(
+            session_error(run_script(&db, &sessions, None, "BEGIN", other)),
+            Some(SessionError::OwnerRequired)
+        );
+        run_script(&db, &sessions, None, "BEGIN; SELECT name FROM inventory; COMMIT", other)?;
+        let session = run_script(&db, &sessions, None, "START TRANSACTION", auth)?.session;
+        assert!(session_error(run_script(&db, &sessions, session, "COMMIT", other)).is_some());
+        run_script(&db, &sessions, session, "ROLLBACK", auth)?;
+        assert!(sessions.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_savepoints() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(1)?;
+        let db = Arc::new(db);
+        let sessions = SqlSessions::default();
+        let auth = AuthCtx::for_testing();
+        let names = |session| -> ResultTest<Vec<ProductValue>> {
+            let sql = "SELECT name FROM inventory WHERE inventory_id > 1 ORDER BY inventory_id";
+            Ok(run_script(&db, &sessions, session, sql, auth)?.results[0].data.clone())
+        };
+
+        let sql = "BEGIN; INSERT INTO inventory VALUES (2, 'b'); SAVEPOINT a; \
+            INSERT INTO inventory VALUES (3, 'c'); SAVEPOINT b; INSERT INTO inventory VALUES (4, 'd')";
+        let session = run_script(&db, &sessions, None, sql, auth)?.session;
+        run_script(&db, &sessions, session, "ROLLBACK TO b", auth)?;
+        assert_eq!(names(session)?, vec![product!("b"), product!("c")]);
+        run_script(&db, &sessions, session, "ROLLBACK TO SAVEPOINT a", auth)?;
+        assert_eq!(names(session)?, vec![product!("b")]);
+        assert_eq!(
+            session_error(run_script(&db, &sessions, session, "ROLLBACK TO b", auth)),
+            Some(SessionError::SavepointNotFound("b".into())),
+            "Rolling back to a savepoint drops the later ones"
+        );
+
+        // The schema changes are undone as well.
+        let sql = "SAVEPOINT c; CREATE TABLE item (id BIGINT); DROP TABLE inventory; ROLLBACK TO c";
+        run_script(&db, &sessions, session, sql, auth)?;
+        assert_eq!(names(session)?, vec![product!("b")]);
+        assert!(run_script(&db, &sessions, session, "SELECT * FROM item", auth).is_err());
+
+        run_script(&db, &sessions, session, "RELEASE SAVEPOINT a", auth)?;
+        assert_eq!(
+            session_error(run_script(&db, &sessions, session, "ROLLBACK TO a", auth)),
+            Some(SessionError::SavepointNotFound("a".into()))
+        );
+        assert_eq!(
+            session_error(run_script(&db, &sessions, None, "SAVEPOINT a", auth)),
+            Some(SessionError::NoTransaction)
+        );
+
+        run_script(&db, &sessions, session, "COMMIT", auth)?;
+        assert_eq!(committed_names(&db)?, vec![product!("health1"), product!("b")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_idle_timeout() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(1)?;
+        let db = Arc::new(db);
+        let idle = Duration::from_millis(50);
+        let sessions = SqlSessions::new(idle);
+        let auth = AuthCtx::for_testing();
+
+        let sql = "BEGIN; INSERT INTO inventory VALUES (2, 'b')";
+        let session = run_script(&db, &sessions, None, sql, auth)?.session.unwrap();
+        std::thread::sleep(idle * 5);
+
+        assert_eq!(
+            session_error(run_script(&db, &sessions, Some(session), "COMMIT", auth)),
+            Some(SessionError::Expired { id: session, idle })
+        );
+        assert_eq!(
+            session_error(run_script(&db, &sessions, Some(session), "COMMIT", auth)),
+            Some(SessionError::NotFound(session))
+        );
+        // The database is no longer held by the session.
+        run_script(&db, &sessions, None, "INSERT INTO inventory VALUES (3, 'c')", auth)?;
+        assert_eq!(committed_names(&db)?, vec![product!("health1"), product!("c")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_reaper_restarts() -> ResultTest<()> {
+        let (db, _, _tmp_dir) = create_data(1)?;
+        let db = Arc::new(db);
+        let idle = Duration::from_millis(50);
+        let sessions = SqlSessions::new(idle);
+        let auth = AuthCtx::for_testing();
+
+        let expired = |session| {
+            session_error(run_script(&db, &sessions, Some(session), "COMMIT", auth))
+                == Some(SessionError::Expired { id: session, idle })
+        };
+        let a = run_script(&db, &sessions, None, "BEGIN", auth)?.session.unwrap();
+        let b = run_script(&db, &sessions, None, "BEGIN", auth)?.session.unwrap();
+        std::thread::sleep(idle * 5);
+        assert!(expired(a));
+        assert!(expired(b));
+
+        // The reaper stopped once no transaction was left open, and runs again for the next one.
+        let c = run_script(&db, &sessions, None, "BEGIN", auth)?.session.unwrap();
+        std::thread::sleep(idle * 5);
+        assert!(expired(c));
+
+        Ok(())
+    }
+
     #[test]
     fn test_big_sql() -> ResultTest<()> {
         let (db, _input, _tmp_dir) = create_data(1)?;
@@ -0,0 +1,140 @@
+//! The plan of a query, as reported by `EXPLAIN` and `EXPLAIN ANALYZE`.
+//!
+//! [crate::vm::build_query_with_plan] records a [PlanStep] for each operation it sets up,
+//! so the plan describes what the query actually does, including the indexes it seeks.
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use spacetimedb_lib::auth::StAccess;
+use spacetimedb_lib::relation::{Header, MemTable, RelValue, RowCount};
+use spacetimedb_sats::{product, AlgebraicType, ProductType, ProductValue};
+use spacetimedb_vm::errors::ErrorVm;
+use spacetimedb_vm::eval::IterRows;
+use spacetimedb_vm::rel_ops::RelOps;
+
+/// The rows an operation yielded, and the time spent yielding them,
+/// including the time spent in the operations it reads from.
+#[derive(Debug, Default)]
+struct StepStats {
+    rows: Cell<u64>,
+    elapsed: Cell<Duration>,
+}
+
+/// An operation of a query, like a scan, a filter or a join.
+#[derive(Debug)]
+pub struct PlanStep {
+    operation: &'static str,
+    detail: String,
+    /// The plan of the rows joined to the input of the operation, if any.
+    children: Vec<PlanStep>,
+    stats: Option<Rc<StepStats>>,
+}
+
+/// The operations of a query, in the order the rows pass through them.
+#[derive(Debug)]
+pub struct QueryPlan {
+    analyze: bool,
+    steps: Vec<PlanStep>,
+}
+
+impl QueryPlan {
+    /// Creates an empty plan, that measures the operations when `analyze` is set.
+    pub fn new(analyze: bool) -> Self {
+        Self {
+            analyze,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Returns an empty plan for a part of this query, like the right side of a join.
+    pub fn sub_plan(&self) -> Self {
+        Self::new(self.analyze)
+    }
+
+    /// Records the `operation` that yields the rows of `iter`,
+    /// reading from the previous steps, and from `children` if any.
+    ///
+    /// When analyzing, returns `iter` wrapped to count its rows and time them.
+    pub fn step<'a>(
+        &mut self,
+        operation: &'static str,
+        detail: String,
+        children: Option<QueryPlan>,
+        iter: Box<IterRows<'a>>,
+    ) -> Box<IterRows<'a>> {
+        let stats = self.analyze.then(Rc::<StepStats>::default);
+        self.steps.push(PlanStep {
+            operation,
+            detail,
+            children: children.map(|x| x.steps).unwrap_or_default(),
+            stats: stats.clone(),
+        });
+        match stats {
+            Some(stats) => Box::new(Analyzed { iter, stats }),
+            None => iter,
+        }
+    }
+
+    /// Returns the plan as a table, with a row per operation, starting from the one that yields the result.
+    ///
+    /// The operations an operation reads from follow it, indented one more level.
+    pub fn into_mem_table(self) -> MemTable {
+        let mut columns = vec![("operation", AlgebraicType::String), ("detail", AlgebraicType::String)];
+        if self.analyze {
+            columns.push(("rows", AlgebraicType::U64));
+            columns.push(("time_ms", AlgebraicType::F64));
+        }
+        let head = Header::from_product_type("explain", ProductType::from_iter(columns));
+
+        let mut rows = Vec::new();
+        Self::collect_rows(&self.steps, 0, &mut rows);
+        MemTable::new(&head, StAccess::Public, &rows)
+    }
+
+    fn collect_rows(steps: &[PlanStep], depth: usize, rows: &mut Vec<ProductValue>) {
+        let Some((step, input)) = steps.split_last() else {
+            return;
+        };
+        let operation = if depth == 0 {
+            step.operation.to_string()
+        } else {
+            format!("{}-> {}", "  ".repeat(depth - 1), step.operation)
+        };
+        let mut row = product!(operation, step.detail.clone());
+        if let Some(stats) = &step.stats {
+            row.elements.push(stats.rows.get().into());
+            row.elements.push((stats.elapsed.get().as_secs_f64() * 1000.0).into());
+        }
+        rows.push(row);
+
+        Self::collect_rows(&step.children, depth + 1, rows);
+        Self::collect_rows(input, depth + 1, rows);
+    }
+}
+
+/// Counts and times the rows yielded by `iter`.
+struct Analyzed<'a> {
+    iter: Box<IterRows<'a>>,
+    stats: Rc<StepStats>,
+}
+
+impl RelOps for Analyzed<'_> {
+    fn head(&self) -> &Header {
+        self.iter.head()
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.iter.row_count()
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        let start = Instant::now();
+        let row = self.iter.next();
+        self.stats.elapsed.set(self.stats.elapsed.get() + start.elapsed());
+        if let Ok(Some(_)) = &row {
+            self.stats.rows.set(self.stats.rows.get() + 1);
+        }
+        row
+    }
+}
@@ -1,3 +1,7 @@
 pub mod ast;
 pub mod compiler;
 pub mod execute;
+pub mod explain;
+pub mod planner;
+pub mod prepared;
+pub mod session;
@@ -0,0 +1,482 @@
+//! Rewrites a compiled [QueryExpr] so [crate::vm::build_query] reads as few rows as it can:
+//!
+//! - The selections are split on `AND`, and each one that only refers to a single table
+//!   is moved below the joins, so the rows of the table are filtered before they are joined.
+//! - An equality or range selection on an indexed column of a [DbTable] becomes a [Query::IndexScan],
+//!   so the rows are seeked in the index instead of scanning the whole table.
+//! - A cross join, as in `FROM a, b`, becomes an inner join on a selection comparing columns of both sides,
+//!   so it is joined on them instead of combining every pair of rows.
+use std::ops::Bound;
+
+use crate::db::datastore::traits::TableSchema;
+use crate::db::relational_db::RelationalDB;
+use crate::error::DBError;
+use crate::vm::TxMode;
+use spacetimedb_lib::operator::{OpCmp, OpLogic, OpQuery};
+use spacetimedb_lib::relation::{DbTable, FieldExpr, FieldName};
+use spacetimedb_lib::IndexType;
+use spacetimedb_sats::AlgebraicValue;
+use spacetimedb_vm::expr::{
+    ColumnOp, CrudExpr, IndexScan, JoinExpr, Query, QueryExpr, SemiJoinExpr, SetOpExpr, SourceExpr,
+};
+
+/// Plans the queries of `expr`, see [optimize].
+pub fn optimize_crud(db: &RelationalDB, tx: &TxMode, expr: CrudExpr) -> Result<CrudExpr, DBError> {
+    Ok(match expr {
+        CrudExpr::Query(query) => CrudExpr::Query(optimize(db, tx, query)?),
+        CrudExpr::Update {
+            insert,
+            delete,
+            returning,
+        } => CrudExpr::Update {
+            insert,
+            delete: optimize(db, tx, delete)?,
+            returning,
+        },
+        CrudExpr::Delete { query, returning } => CrudExpr::Delete {
+            query: optimize(db, tx, query)?,
+            returning,
+        },
+        CrudExpr::Explain { query, analyze } => CrudExpr::Explain {
+            query: optimize(db, tx, query)?,
+            analyze,
+        },
+        x => x,
+    })
+}
+
+/// Plans `query` against the tables and indexes visible to `tx`.
+///
+/// Only the selections and joins at the start of the query are reordered,
+/// as the operations after them, like a sort or an aggregate, depend on the rows they get.
+/// The queries combined with it, as by a `UNION` or a subquery, are planned on their own.
+pub fn optimize(db: &RelationalDB, tx: &TxMode, query: QueryExpr) -> Result<QueryExpr, DBError> {
+    let QueryExpr { source, query } = query;
+
+    let mut query = query;
+    let prefix = query
+        .iter()
+        .position(|q| !matches!(q, Query::Select(_) | Query::JoinInner(_) | Query::JoinCross(_)))
+        .unwrap_or(query.len());
+    let tail = query.split_off(prefix);
+
+    let mut selects = Vec::new();
+    let mut joins = Vec::new();
+    for q in query {
+        match q {
+            Query::Select(op) => split_and(op, &mut selects),
+            join => joins.push(join),
+        }
+    }
+
+    // A selection can't be attributed to a table that is joined more than once.
+    let table_names: Vec<_> = std::iter::once(source.table_name())
+        .chain(joins.iter().map(|x| join_rhs(x).source.table_name()))
+        .map(str::to_string)
+        .collect();
+    let is_unique = |name: &str| table_names.iter().filter(|x| *x == name).count() == 1;
+
+    // A cross join becomes an inner join on a selection comparing a column of its table
+    // with a column of a table before it, as in `FROM a, b WHERE a.id = b.id`.
+    for (pos, join) in joins.iter_mut().enumerate() {
+        let Query::JoinCross(rhs) = join else {
+            continue;
+        };
+        let rhs_name = rhs.source.table_name();
+        if !is_unique(rhs_name) {
+            continue;
+        }
+        let on = selects.iter().enumerate().find_map(|(idx, op)| {
+            let (lhs, rhs) = field_eq(op)?;
+            let (lhs, rhs) = if lhs.table() == rhs_name {
+                (rhs, lhs)
+            } else {
+                (lhs, rhs)
+            };
+            let before = table_names[..=pos].iter().any(|x| x == lhs.table() && is_unique(x));
+            (rhs.table() == rhs_name && before).then(|| (idx, lhs.clone(), rhs.clone()))
+        });
+        if let Some((idx, col_lhs, col_rhs)) = on {
+            selects.remove(idx);
+            *join = Query::JoinInner(JoinExpr::new(rhs.clone(), col_lhs, col_rhs));
+        }
+    }
+
+    let mut source_selects = Vec::new();
+    let mut join_selects = Vec::new();
+    for op in selects {
+        if is_unique(source.table_name()) && refers_only_to(&op, source.table_name()) {
+            source_selects.push(op);
+        } else if let Some(rhs) = joins.iter_mut().map(join_rhs_mut).find(|rhs| {
+            is_unique(rhs.source.table_name())
+                && refers_only_to(&op, rhs.source.table_name())
+                && rhs.query.iter().all(|q| matches!(q, Query::Select(_)))
+        }) {
+            rhs.query.push(Query::Select(op));
+        } else {
+            join_selects.push(op);
+        }
+    }
+
+    let joins = joins
+        .into_iter()
+        .map(|x| {
+            Ok(match x {
+                Query::JoinInner(x) => Query::JoinInner(JoinExpr {
+                    rhs: optimize(db, tx, x.rhs)?,
+                    ..x
+                }),
+                Query::JoinCross(rhs) => Query::JoinCross(optimize(db, tx, rhs)?),
+                x => x,
+            })
+        })
+        .collect::<Result<Vec<_>, DBError>>()?;
+
+    let mut query = Vec::new();
+    if let SourceExpr::DbTable(table) = &source {
+        let schema = match tx {
+            TxMode::MutTx(tx) => db.schema_for_table(tx, table.table_id)?,
+            TxMode::Tx(tx) => db.schema_for_table_tx(tx, table.table_id)?,
+        };
+        if let Some(scan) = index_scan(table, &schema, &mut source_selects) {
+            query.push(Query::IndexScan(scan));
+        }
+    }
+    query.extend(source_selects.into_iter().map(Query::Select));
+    query.extend(joins);
+    query.extend(join_selects.into_iter().map(Query::Select));
+    for q in tail {
+        query.push(match q {
+            Query::SetOp(x) => Query::SetOp(SetOpExpr {
+                rhs: optimize(db, tx, x.rhs)?,
+                ..x
+            }),
+            Query::SemiJoin(x) => Query::SemiJoin(SemiJoinExpr {
+                rhs: optimize(db, tx, x.rhs)?,
+                ..x
+            }),
+            x => x,
+        });
+    }
+
+    Ok(QueryExpr { source, query })
+}
+
+/// Returns the query on the right of the join `q`.
+fn join_rhs(q: &Query) -> &QueryExpr {
+    match q {
+        Query::JoinInner(join) => &join.rhs,
+        Query::JoinCross(rhs) => rhs,
+        _ => unreachable!("Only inner and cross joins are reordered"),
+    }
+}
+
+fn join_rhs_mut(q: &mut Query) -> &mut QueryExpr {
+    match q {
+        Query::JoinInner(join) => &mut join.rhs,
+        Query::JoinCross(rhs) => rhs,
+        _ => unreachable!("Only inner and cross joins are reordered"),
+    }
+}
+
+/// Returns the fields of `op` when it compares two fields for equality, as in `a.id = b.id`.
+fn field_eq(op: &ColumnOp) -> Option<(&FieldName, &FieldName)> {
+    match op {
+        ColumnOp::Cmp {
+            op: OpQuery::Cmp(OpCmp::Eq),
+            lhs,
+            rhs,
+        } => match (&**lhs, &**rhs) {
+            (ColumnOp::Field(FieldExpr::Name(lhs)), ColumnOp::Field(FieldExpr::Name(rhs))) => Some((lhs, rhs)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Splits the conjunctions of `op` into separate selections.
+fn split_and(op: ColumnOp, into: &mut Vec<ColumnOp>) {
+    match op {
+        ColumnOp::Cmp {
+            op: OpQuery::Logic(OpLogic::And),
+            lhs,
+            rhs,
+        } => {
+            split_and(*lhs, into);
+            split_and(*rhs, into);
+        }
+        op => into.push(op),
+    }
+}
+
+/// Whether all the fields in `op` belong to `table`.
+fn refers_only_to(op: &ColumnOp, table: &str) -> bool {
+    match op {
+        ColumnOp::Field(FieldExpr::Name(field)) => field.table() == table,
+        ColumnOp::Field(FieldExpr::Value(_)) => true,
+        op => op.args().into_iter().all(|x| refers_only_to(x, table)),
+    }
+}
+
+/// Returns the parts of `op` when it compares a field to a value, as in `field op value`.
+fn field_cmp(op: &ColumnOp) -> Option<(&FieldName, OpCmp, &AlgebraicValue)> {
+    let ColumnOp::Cmp {
+        op: OpQuery::Cmp(op),
+        lhs,
+        rhs,
+    } = op
+    else {
+        return None;
+    };
+    match (&**lhs, &**rhs) {
+        (ColumnOp::Field(FieldExpr::Name(field)), ColumnOp::Field(FieldExpr::Value(value))) => {
+            Some((field, *op, value))
+        }
+        (ColumnOp::Field(FieldExpr::Value(value)), ColumnOp::Field(FieldExpr::Name(field))) => {
+            Some((field, op.reverse(), value))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the type of the index that can seek the rows of `table` by `field`.
+///
+/// A btree index can seek by its leading column, and serves both equalities and ranges,
+/// while a hash index can only seek by all its columns, and only serves equalities.
+fn index_type_for(table: &DbTable, schema: &TableSchema, field: &FieldName) -> Option<IndexType> {
+    if field.table() != table.head.table_name {
+        return None;
+    }
+    let col_id = table.head.column_pos(field)? as u32;
+    let mut indexes = schema.indexes.iter();
+    if indexes
+        .clone()
+        .any(|x| x.index_type == IndexType::BTree && x.cols.first() == Some(&col_id))
+    {
+        Some(IndexType::BTree)
+    } else if indexes.any(|x| x.index_type == IndexType::Hash && x.cols == [col_id]) {
+        Some(IndexType::Hash)
+    } else {
+        None
+    }
+}
+
+/// Turns the `selects` on the rows of `table` that an index can serve into an [IndexScan],
+/// and removes them from `selects`.
+///
+/// An equality is preferred, as it seeks the fewest rows,
+/// otherwise the lower and upper bounds on the first column of a btree index with a range are combined.
+fn index_scan(table: &DbTable, schema: &TableSchema, selects: &mut Vec<ColumnOp>) -> Option<IndexScan> {
+    let cmps: Vec<_> = selects
+        .iter()
+        .map(|op| field_cmp(op).and_then(|x| Some((x, index_type_for(table, schema, x.0)?))))
+        .collect();
+
+    if let Some(pos) = cmps.iter().position(|x| matches!(x, Some(((_, OpCmp::Eq, _), _)))) {
+        let ((field, _, value), _) = cmps[pos]?;
+        let scan = IndexScan {
+            field: field.clone(),
+            lower_bound: Bound::Included(value.clone()),
+            upper_bound: Bound::Included(value.clone()),
+        };
+        selects.remove(pos);
+        return Some(scan);
+    }
+
+    let ((field, _, _), _) = *cmps.iter().flatten().find(|((_, op, _), index_type)| {
+        *index_type == IndexType::BTree && matches!(op, OpCmp::Gt | OpCmp::GtEq | OpCmp::Lt | OpCmp::LtEq)
+    })?;
+    let mut scan = IndexScan {
+        field: field.clone(),
+        lower_bound: Bound::Unbounded,
+        upper_bound: Bound::Unbounded,
+    };
+    let mut used = Vec::new();
+    for (pos, x) in cmps.iter().enumerate() {
+        let Some(((x, op, value), _)) = *x else {
+            continue;
+        };
+        if x != field {
+            continue;
+        }
+        let value = value.clone();
+        match op {
+            OpCmp::Gt | OpCmp::GtEq if scan.lower_bound == Bound::Unbounded => {
+                scan.lower_bound = if op == OpCmp::Gt {
+                    Bound::Excluded(value)
+                } else {
+                    Bound::Included(value)
+                };
+            }
+            OpCmp::Lt | OpCmp::LtEq if scan.upper_bound == Bound::Unbounded => {
+                scan.upper_bound = if op == OpCmp::Lt {
+                    Bound::Excluded(value)
+                } else {
+                    Bound::Included(value)
+                };
+            }
+            _ => continue,
+        }
+        used.push(pos);
+    }
+    for pos in used.into_iter().rev() {
+        selects.remove(pos);
+    }
+    Some(scan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::datastore::traits::IndexDef;
+    use crate::db::relational_db::tests_utils::make_test_db;
+    use crate::sql::compiler::compile_sql;
+    use crate::sql::execute::run;
+    use crate::vm::tests::create_table_with_rows;
+    use spacetimedb_lib::error::ResultTest;
+    use spacetimedb_lib::identity::AuthCtx;
+    use spacetimedb_sats::{product, BuiltinType, ProductType};
+
+    fn plan(db: &RelationalDB, tx: &TxMode, sql: &str) -> ResultTest<QueryExpr> {
+        match compile_sql(db, tx, sql)?.remove(0) {
+            CrudExpr::Query(query) => Ok(optimize(db, tx, query)?),
+            x => panic!("Expected a query, got {x:?}"),
+        }
+    }
+
+    fn ids(db: &RelationalDB, tx: TxMode, sql: &str) -> ResultTest<Vec<u64>> {
+        let mut ids: Vec<_> = run(db, tx, sql, AuthCtx::for_testing())?[0]
+            .data
+            .iter()
+            .map(|row| *row.elements[0].as_u64().unwrap())
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn scan(field: &str, lower_bound: Bound<u64>, upper_bound: Bound<u64>) -> Query {
+        let value = |x: Bound<u64>| match x {
+            Bound::Included(x) => Bound::Included(AlgebraicValue::U64(x)),
+            Bound::Excluded(x) => Bound::Excluded(AlgebraicValue::U64(x)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        Query::IndexScan(IndexScan {
+            field: FieldName::named("inventory", field),
+            lower_bound: value(lower_bound),
+            upper_bound: value(upper_bound),
+        })
+    }
+
+    /// Creates the table `inventory` with a btree index on `inventory_id` and a hash index on `name`,
+    /// and the table `player` without indexes.
+    fn create_data(db: &RelationalDB) -> ResultTest<()> {
+        let mut tx = db.begin_tx();
+        let head = ProductType::from_iter([("inventory_id", BuiltinType::U64), ("name", BuiltinType::String)]);
+        let rows: Vec<_> = (1..=4u64).map(|i| product!(i, format!("health{i}"))).collect();
+        let table_id = create_table_with_rows(db, &mut tx, "inventory", head, &rows)?;
+        db.create_index(
+            &mut tx,
+            IndexDef::new("idx_id".into(), table_id, vec![0], IndexType::BTree, false),
+        )?;
+        db.create_index(
+            &mut tx,
+            IndexDef::new("idx_name".into(), table_id, vec![1], IndexType::Hash, false),
+        )?;
+
+        let head = ProductType::from_iter([
+            ("player_id", BuiltinType::U64),
+            ("inventory_id", BuiltinType::U64),
+            ("nickname", BuiltinType::String),
+        ]);
+        let rows: Vec<_> = (1..=4u64).map(|i| product!(i + 10, i, format!("player{i}"))).collect();
+        create_table_with_rows(db, &mut tx, "player", head, &rows)?;
+        db.commit_tx(tx)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_scan() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        create_data(&db)?;
+        let tx = db.begin_read_tx();
+
+        let sql = "SELECT * FROM inventory WHERE inventory_id = 2";
+        let q = plan(&db, &(&tx).into(), sql)?;
+        assert_eq!(
+            q.query,
+            vec![scan("inventory_id", Bound::Included(2), Bound::Included(2))]
+        );
+        assert_eq!(ids(&db, (&tx).into(), sql)?, vec![2]);
+
+        // The value can be on either side of the comparison.
+        let sql = "SELECT * FROM inventory WHERE 1 < inventory_id AND inventory_id <= 3";
+        let q = plan(&db, &(&tx).into(), sql)?;
+        assert_eq!(
+            q.query,
+            vec![scan("inventory_id", Bound::Excluded(1), Bound::Included(3))]
+        );
+        assert_eq!(ids(&db, (&tx).into(), sql)?, vec![2, 3]);
+
+        // A hash index serves an equality, but not a range.
+        let sql = "SELECT * FROM inventory WHERE name = 'health3'";
+        let q = plan(&db, &(&tx).into(), sql)?;
+        assert!(matches!(&q.query[..], [Query::IndexScan(x)] if x.field == FieldName::named("inventory", "name")));
+        assert_eq!(ids(&db, (&tx).into(), sql)?, vec![3]);
+
+        let sql = "SELECT * FROM inventory WHERE name > 'health3'";
+        let q = plan(&db, &(&tx).into(), sql)?;
+        assert!(matches!(&q.query[..], [Query::Select(_)]));
+        assert_eq!(ids(&db, (&tx).into(), sql)?, vec![4]);
+
+        // An equality is preferred to a range, and the rest stays a selection.
+        let sql = "SELECT * FROM inventory WHERE inventory_id > 1 AND name = 'health3'";
+        let q = plan(&db, &(&tx).into(), sql)?;
+        assert!(matches!(&q.query[..], [Query::IndexScan(x), Query::Select(_)] if x.as_eq().is_some()));
+        assert_eq!(ids(&db, (&tx).into(), sql)?, vec![3]);
+
+        // A disjunction can't be served by an index.
+        let sql = "SELECT * FROM inventory WHERE inventory_id = 1 OR inventory_id = 3";
+        let q = plan(&db, &(&tx).into(), sql)?;
+        assert!(matches!(&q.query[..], [Query::Select(_)]));
+        assert_eq!(ids(&db, (&tx).into(), sql)?, vec![1, 3]);
+        db.release_tx(tx);
+
+        // The rows inserted by a transaction are seeked along with the committed ones.
+        let mut tx = db.begin_tx();
+        let table_id = db.table_id_from_name(&tx, "inventory")?.unwrap();
+        db.insert(&mut tx, table_id, product!(5u64, "health3"))?;
+        assert_eq!(
+            ids(&db, (&mut tx).into(), "SELECT * FROM inventory WHERE name = 'health3'")?,
+            vec![3, 5]
+        );
+        assert_eq!(
+            ids(&db, (&mut tx).into(), "SELECT * FROM inventory WHERE inventory_id >= 4")?,
+            vec![4, 5]
+        );
+        db.rollback_tx(tx);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_below_join() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        create_data(&db)?;
+        let tx = db.begin_read_tx();
+
+        // Each selection on a single table is moved below the join, and the one on both tables stays after it.
+        let sql = "SELECT inventory.* FROM inventory JOIN player ON inventory.inventory_id = player.inventory_id \
+        WHERE player.player_id > 11 AND name <> 'health4' AND player.nickname <> 'player3' AND nickname > name";
+        let q = plan(&db, &(&tx).into(), sql)?;
+        match &q.query[..] {
+            [Query::Select(_), Query::JoinInner(join), Query::Select(_), Query::Project(_)] => {
+                assert!(matches!(&join.rhs.query[..], [Query::Select(_), Query::Select(_)]));
+            }
+            x => panic!("Unexpected plan {x:?}"),
+        }
+        assert_eq!(ids(&db, (&tx).into(), sql)?, vec![2]);
+        db.release_tx(tx);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+//! Prepared statements: the `SQL` text parsed once per database,
+//! and compiled into a plan whose placeholders `$1`, `$2`, ... are bound to the parameters of each execution.
+//!
+//! A plan is reused as long as the schema it was compiled against is the one of the transaction it runs in,
+//! as told by the version of the schema, which changes whenever a table, a column or an index is changed.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::{Bound, Range};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use bytestring::ByteString;
+use hashlink::LruCache;
+use parking_lot::Mutex;
+use spacetimedb_lib::de::serde::SeedWrapper;
+use spacetimedb_lib::relation::{FieldExpr, FieldName};
+use spacetimedb_sats::algebraic_type::fmt::fmt_algebraic_type;
+use spacetimedb_sats::{AlgebraicType, AlgebraicValue, SumValue, Typespace};
+use spacetimedb_vm::expr::{
+    option_type, AlterTable, ColumnOp, ComputedColumn, ConflictAction, CrudExpr, Query, QueryExpr, SourceExpr,
+};
+
+use crate::error::{DBError, PlanError};
+use crate::host::from_json_seed;
+use crate::sql::ast::{parse_script, SqlStatement};
+
+/// How many statements are kept by [PreparedStatements], dropping the least recently used first.
+const PREPARED_STATEMENTS_CAPACITY: usize = 256;
+
+/// A parameter of a statement, kept encoded until it is bound to the type of the column
+/// it is compared to or assigned to.
+#[derive(Debug, Clone)]
+pub enum SqlParam {
+    Json(ByteString),
+    Bsatn(Bytes),
+}
+
+impl SqlParam {
+    /// Decodes the parameter as a value of type `ty`.
+    ///
+    /// For an option type, a `JSON` parameter is decoded as its `some` variant, or as `none` when it is `null`,
+    /// so clients can pass plain values, while a `BSATN` parameter is decoded as the whole option.
+    pub fn decode(&self, ty: &AlgebraicType) -> anyhow::Result<AlgebraicValue> {
+        let typespace = Typespace::new(Vec::new());
+        match self {
+            SqlParam::Json(json) => match option_type(ty) {
+                Some(_) if json.trim() == "null" => Ok(AlgebraicValue::OptionNone()),
+                Some(ty) => from_json_seed(json, SeedWrapper(typespace.with_type(ty))).map(AlgebraicValue::OptionSome),
+                None => from_json_seed(json, SeedWrapper(typespace.with_type(ty))),
+            },
+            SqlParam::Bsatn(bytes) => Ok(AlgebraicValue::decode(ty, &mut &bytes[..])?),
+        }
+    }
+}
+
+/// The placeholders `$1`, `$2`, ... found while compiling a statement, in the order they were found.
+///
+/// Each one stands in the plan as a value no `SQL` expression yields, until it's bound to its parameter,
+/// so the plan can be type-checked and optimized without the values of the parameters.
+#[derive(Default)]
+pub struct Placeholders {
+    /// The position of the parameter of each placeholder, and the type it is decoded as.
+    found: RefCell<Vec<(usize, AlgebraicType)>>,
+}
+
+/// The tag of the sum value standing for a placeholder, which isn't the tag of a variant of an option.
+const PLACEHOLDER_TAG: u8 = u8::MAX;
+
+/// The table of the fields standing for the placeholders while the type of an expression is checked.
+const PLACEHOLDER_TABLE: &str = "$";
+
+impl Placeholders {
+    /// Returns the value standing for a placeholder of the parameter at `pos`, from 1, of type `ty`.
+    pub fn add(&self, pos: usize, ty: AlgebraicType) -> AlgebraicValue {
+        let mut found = self.found.borrow_mut();
+        found.push((pos, ty));
+        AlgebraicValue::sum(PLACEHOLDER_TAG, AlgebraicValue::U64(found.len() as u64 - 1))
+    }
+
+    /// Returns `op` with its placeholders replaced by fields whose [Self::field_type] is the type of their parameter.
+    pub fn as_fields(&self, op: &ColumnOp) -> ColumnOp {
+        let mut op = op.clone();
+        visit_op(&mut op, &mut |field| {
+            let FieldExpr::Value(value) = field else {
+                return;
+            };
+            if let Some(n) = as_placeholder(value) {
+                *field = FieldExpr::Name(FieldName::positional(PLACEHOLDER_TABLE, n));
+            }
+        });
+        op
+    }
+
+    /// Returns the type of the parameter of `field`, if it stands for a placeholder, see [Self::as_fields].
+    pub fn field_type(&self, field: &FieldName) -> Option<AlgebraicType> {
+        match field {
+            FieldName::Pos { table, field } if table == PLACEHOLDER_TABLE => {
+                self.found.borrow().get(*field).map(|(_, ty)| ty.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Replaces the placeholders in `statements` by the `params` they stand for.
+    pub fn bind(&self, statements: Vec<CrudExpr>, params: &[SqlParam]) -> Result<Vec<CrudExpr>, PlanError> {
+        bind_params(&self.found.borrow(), statements, params)
+    }
+
+    /// The position and type of the parameter of each placeholder found.
+    pub fn into_inner(self) -> Vec<(usize, AlgebraicType)> {
+        self.found.into_inner()
+    }
+}
+
+/// Replaces the placeholders in `statements` by the `params` they stand for,
+/// decoded as the type of each of the `placeholders`.
+fn bind_params(
+    placeholders: &[(usize, AlgebraicType)],
+    mut statements: Vec<CrudExpr>,
+    params: &[SqlParam],
+) -> Result<Vec<CrudExpr>, PlanError> {
+    if placeholders.is_empty() {
+        return Ok(statements);
+    }
+    let values = placeholders
+        .iter()
+        .map(|(pos, ty)| {
+            let invalid = |error: String| PlanError::InvalidParam {
+                param: format!("${pos}"),
+                error,
+            };
+            let param = params
+                .get(pos - 1)
+                .ok_or_else(|| invalid(format!("Expected a placeholder from `$1` to `${}`.", params.len())))?;
+            param.decode(ty).map_err(|error| {
+                invalid(format!(
+                    "Expected a value of type {}: {error:#}",
+                    fmt_algebraic_type(ty)
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for statement in &mut statements {
+        visit_values(statement, &mut |value| {
+            if let Some(n) = as_placeholder(value) {
+                *value = values[n].clone();
+            }
+        });
+    }
+    Ok(statements)
+}
+
+/// Returns the position of the placeholder `value` stands for in [Placeholders], if it stands for one.
+fn as_placeholder(value: &AlgebraicValue) -> Option<usize> {
+    match value {
+        AlgebraicValue::Sum(SumValue { tag, value }) if *tag == PLACEHOLDER_TAG => value.as_u64().map(|x| *x as usize),
+        _ => None,
+    }
+}
+
+/// Calls `f` on the fields of `op`.
+fn visit_op(op: &mut ColumnOp, f: &mut impl FnMut(&mut FieldExpr)) {
+    match op {
+        ColumnOp::Field(field) => f(field),
+        ColumnOp::Cmp { lhs, rhs, .. } | ColumnOp::Math { lhs, rhs, .. } => {
+            visit_op(lhs, f);
+            visit_op(rhs, f);
+        }
+        ColumnOp::Call { args, .. } => args.iter_mut().for_each(|x| visit_op(x, f)),
+        ColumnOp::Not(x) => visit_op(x, f),
+    }
+}
+
+/// Calls `f` on the values of `fields`.
+fn visit_fields<'a>(fields: impl IntoIterator<Item = &'a mut FieldExpr>, f: &mut impl FnMut(&mut AlgebraicValue)) {
+    for field in fields {
+        if let FieldExpr::Value(x) = field {
+            f(x);
+        }
+    }
+}
+
+/// Calls `f` on the values of the `columns`.
+fn visit_computed<'a>(
+    columns: impl IntoIterator<Item = &'a mut ComputedColumn>,
+    f: &mut impl FnMut(&mut AlgebraicValue),
+) {
+    for column in columns {
+        visit_op(&mut column.expr, &mut |field| visit_fields([field], f));
+    }
+}
+
+/// Calls `f` on the values of `query`, and of the queries it's combined with.
+fn visit_query(query: &mut QueryExpr, f: &mut impl FnMut(&mut AlgebraicValue)) {
+    if let SourceExpr::MemTable(table) = &mut query.source {
+        table
+            .data
+            .iter_mut()
+            .flat_map(|row| &mut row.elements)
+            .for_each(&mut *f);
+    }
+    for q in &mut query.query {
+        match q {
+            Query::IndexScan(scan) => {
+                for bound in [&mut scan.lower_bound, &mut scan.upper_bound] {
+                    if let Bound::Included(x) | Bound::Excluded(x) = bound {
+                        f(x);
+                    }
+                }
+            }
+            Query::Select(op) => visit_op(op, &mut |field| visit_fields([field], f)),
+            Query::Project(fields) => visit_fields(fields, f),
+            Query::Compute(columns) => visit_computed(columns, f),
+            Query::JoinInner(join) => visit_query(&mut join.rhs, f),
+            Query::JoinOuter(join) => visit_query(&mut join.join.rhs, f),
+            Query::JoinCross(rhs) => visit_query(rhs, f),
+            Query::Sort(keys) => visit_fields(keys.iter_mut().map(|x| &mut x.field), f),
+            Query::SetOp(x) => visit_query(&mut x.rhs, f),
+            Query::SemiJoin(x) => visit_query(&mut x.rhs, f),
+            Query::Limit(_) | Query::Aggregate(_) | Query::Distinct => {}
+        }
+    }
+}
+
+/// Calls `f` on the values of `statement`.
+fn visit_values(statement: &mut CrudExpr, f: &mut impl FnMut(&mut AlgebraicValue)) {
+    match statement {
+        CrudExpr::Query(query) | CrudExpr::Explain { query, .. } => visit_query(query, f),
+        CrudExpr::Insert {
+            rows,
+            on_conflict,
+            returning,
+            ..
+        } => {
+            visit_fields(rows.iter_mut().flatten(), f);
+            if let Some(ConflictAction::DoUpdate { set, selection }) = on_conflict.as_mut().map(|x| &mut x.action) {
+                visit_computed(set, f);
+                if let Some(op) = selection {
+                    visit_op(op, &mut |field| visit_fields([field], f));
+                }
+            }
+            visit_computed(returning.iter_mut().flatten(), f);
+        }
+        CrudExpr::Update {
+            insert,
+            delete,
+            returning,
+        } => {
+            visit_query(insert, f);
+            visit_query(delete, f);
+            visit_computed(returning.iter_mut().flatten(), f);
+        }
+        CrudExpr::Delete { query, returning } => {
+            visit_query(query, f);
+            visit_computed(returning.iter_mut().flatten(), f);
+        }
+        CrudExpr::AlterTable {
+            alter: AlterTable::AddColumn { default, .. },
+            ..
+        } => f(default),
+        CrudExpr::AlterTable { .. }
+        | CrudExpr::CreateTable { .. }
+        | CrudExpr::CreateIndex { .. }
+        | CrudExpr::Drop { .. } => {}
+    }
+}
+
+/// The statements compiled from a run of [SqlStatement::Plan]s, planned against the schema of `schema_version`,
+/// with the position and type of the parameter of each of their placeholders.
+pub struct Plan {
+    pub schema_version: u64,
+    pub statements: Vec<CrudExpr>,
+    pub placeholders: Vec<(usize, AlgebraicType)>,
+}
+
+impl Plan {
+    /// Returns the statements to execute, with the `params` bound to their placeholders.
+    pub fn bind(&self, params: &[SqlParam]) -> Result<Vec<CrudExpr>, PlanError> {
+        bind_params(&self.placeholders, self.statements.clone(), params)
+    }
+}
+
+/// The statements of a `SQL` text, and the plans compiled from them.
+pub struct Prepared {
+    pub statements: Vec<SqlStatement>,
+    /// The plan of each run of [SqlStatement::Plan]s between the [SqlStatement::Tx], by the position of its first,
+    /// as compiled against the schema of the last transaction it ran in.
+    plans: Mutex<HashMap<usize, Arc<Plan>>>,
+}
+
+impl Prepared {
+    pub fn new(statements: Vec<SqlStatement>) -> Self {
+        Self {
+            statements,
+            plans: Mutex::default(),
+        }
+    }
+
+    /// Returns the plan of the run of `statements`, compiling it with `compile`
+    /// unless it was already compiled against the schema of `schema_version`.
+    pub fn plan(
+        &self,
+        statements: &Range<usize>,
+        schema_version: u64,
+        compile: impl FnOnce() -> Result<Plan, DBError>,
+    ) -> Result<Arc<Plan>, DBError> {
+        if let Some(plan) = self.plans.lock().get(&statements.start) {
+            if plan.schema_version == schema_version {
+                return Ok(plan.clone());
+            }
+        }
+        let plan = Arc::new(compile()?);
+        self.plans.lock().insert(statements.start, plan.clone());
+        Ok(plan)
+    }
+}
+
+/// The statements most recently executed against a database, by their `SQL` text.
+pub struct PreparedStatements {
+    cache: Mutex<LruCache<String, Arc<Prepared>>>,
+}
+
+impl Default for PreparedStatements {
+    fn default() -> Self {
+        Self {
+            cache: Mutex::new(LruCache::new(PREPARED_STATEMENTS_CAPACITY)),
+        }
+    }
+}
+
+impl PreparedStatements {
+    /// Returns the statements of `sql_text`, parsing them unless they are cached.
+    pub fn prepare(&self, sql_text: &str) -> Result<Arc<Prepared>, DBError> {
+        if let Some(prepared) = self.cache.lock().get(sql_text) {
+            return Ok(prepared.clone());
+        }
+
+        let prepared = Arc::new(Prepared::new(parse_script(sql_text)?));
+        self.cache.lock().insert(sql_text.to_string(), prepared.clone());
+        Ok(prepared)
+    }
+
+    /// The number of statements cached.
+    pub fn len(&self) -> usize {
+        self.cache.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,203 @@
+//! Sessions keeping an explicit transaction open across `SQL` requests,
+//! from its `BEGIN` until its `COMMIT` or `ROLLBACK`.
+//!
+//! The transaction does not block reducers and other writers. Instead, its `COMMIT` fails with a write conflict
+//! if one of them committed to a table it read from or wrote to since its `BEGIN`.
+//! As that grows likelier the longer it stays open, it is rolled back once it has been idle
+//! for the `idle_timeout` of [SqlSessions].
+//!
+//! The idle sessions of a database are rolled back by a single reaper thread,
+//! which only runs while some session has a transaction open.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use spacetimedb_lib::Identity;
+
+use crate::db::datastore::locking_tx_datastore::{MutTxId, Savepoint};
+use crate::db::relational_db::RelationalDB;
+use crate::error::SessionError;
+
+/// How long the transaction of a session can stay idle before it is rolled back.
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub type SessionId = u64;
+
+/// A transaction opened by `BEGIN`, with its savepoints in the order they were taken.
+pub(crate) struct Transaction {
+    pub(crate) tx: MutTxId,
+    pub(crate) savepoints: Vec<(String, Arc<Savepoint>)>,
+}
+
+impl Transaction {
+    pub(crate) fn new(tx: MutTxId) -> Self {
+        Self {
+            tx,
+            savepoints: Vec::new(),
+        }
+    }
+}
+
+pub(crate) struct Session {
+    /// Taken out while a request runs in the session, and for good once the transaction ends.
+    pub(crate) transaction: Option<Transaction>,
+    last_used: Instant,
+    expired: bool,
+}
+
+impl Session {
+    /// Puts back the `transaction` at the end of a request.
+    pub(crate) fn keep(&mut self, transaction: Transaction) {
+        self.transaction = Some(transaction);
+        self.last_used = Instant::now();
+    }
+}
+
+struct OpenSession {
+    owner: Identity,
+    session: Arc<Mutex<Session>>,
+    db: Arc<RelationalDB>,
+}
+
+#[derive(Default)]
+struct OpenSessions {
+    by_id: HashMap<SessionId, OpenSession>,
+    /// Whether the reaper thread is running, see [SqlSessions::reap_idle].
+    reaping: bool,
+}
+
+/// The open sessions of a database.
+pub struct SqlSessions {
+    sessions: Arc<Mutex<OpenSessions>>,
+    next_id: AtomicU64,
+    idle_timeout: Duration,
+}
+
+impl Default for SqlSessions {
+    fn default() -> Self {
+        Self::new(SESSION_IDLE_TIMEOUT)
+    }
+}
+
+impl SqlSessions {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            sessions: Arc::default(),
+            next_id: AtomicU64::new(1),
+            idle_timeout,
+        }
+    }
+
+    /// Opens a session of `owner` for the `transaction`.
+    pub(crate) fn open(&self, db: Arc<RelationalDB>, owner: Identity, transaction: Transaction) -> SessionId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let session = Arc::new(Mutex::new(Session {
+            transaction: Some(transaction),
+            last_used: Instant::now(),
+            expired: false,
+        }));
+
+        let mut sessions = self.sessions.lock();
+        // Sweep the sessions that expired without being looked up again.
+        // Those in use by a request are not expired, and must not be waited on here.
+        sessions
+            .by_id
+            .retain(|_, open| open.session.try_lock().map_or(true, |session| !session.expired));
+        sessions.by_id.insert(id, OpenSession { owner, session, db });
+
+        if !sessions.reaping {
+            sessions.reaping = true;
+            let watched = Arc::downgrade(&self.sessions);
+            let idle_timeout = self.idle_timeout;
+            thread::Builder::new()
+                .name("sql-session-reaper".into())
+                .spawn(move || Self::reap_idle(watched, idle_timeout))
+                .expect("failed to spawn the SQL session reaper thread");
+        }
+        id
+    }
+
+    /// Returns the session `id` of the `caller`.
+    pub(crate) fn get(&self, id: SessionId, caller: Identity) -> Result<Arc<Mutex<Session>>, SessionError> {
+        match self.sessions.lock().by_id.get(&id) {
+            Some(open) if open.owner == caller => Ok(open.session.clone()),
+            _ => Err(SessionError::NotFound(id)),
+        }
+    }
+
+    /// Checks that the `session` fetched by [Self::get] is still open, and takes its transaction.
+    pub(crate) fn take_transaction(&self, id: SessionId, session: &mut Session) -> Result<Transaction, SessionError> {
+        if let Some(transaction) = session.transaction.take() {
+            return Ok(transaction);
+        }
+        if session.expired {
+            self.close(id);
+            return Err(SessionError::Expired {
+                id,
+                idle: self.idle_timeout,
+            });
+        }
+        // The transaction ended while waiting for a concurrent request in the same session.
+        Err(SessionError::NotFound(id))
+    }
+
+    /// Forgets the session `id`, once its transaction has ended.
+    pub(crate) fn close(&self, id: SessionId) {
+        self.sessions.lock().by_id.remove(&id);
+    }
+
+    /// The number of open sessions.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Rolls back the transaction of every session once it has been idle for `idle_timeout`,
+    /// until no session has a transaction open, or the `sessions` are dropped.
+    fn reap_idle(sessions: Weak<Mutex<OpenSessions>>, idle_timeout: Duration) {
+        let mut wait = idle_timeout;
+        loop {
+            thread::sleep(wait);
+            let Some(sessions) = sessions.upgrade() else {
+                return;
+            };
+            let mut sessions = sessions.lock();
+
+            let mut open_transactions = false;
+            wait = idle_timeout;
+            for (id, open) in &sessions.by_id {
+                // A session in use by a request is not idle, and must not be waited on here.
+                let Some(mut session) = open.session.try_lock() else {
+                    open_transactions = true;
+                    continue;
+                };
+                if session.transaction.is_none() {
+                    continue;
+                }
+                let idle = session.last_used.elapsed();
+                if idle < idle_timeout {
+                    open_transactions = true;
+                    wait = wait.min(idle_timeout - idle);
+                    continue;
+                }
+
+                log::warn!("Rolling back the transaction of SQL session {id}, idle for {idle:?}");
+                if let Some(transaction) = session.transaction.take() {
+                    open.db.rollback_tx(transaction.tx);
+                }
+                session.expired = true;
+            }
+
+            if !open_transactions {
+                sessions.reaping = false;
+                return;
+            }
+        }
+    }
+}
@@ -1,16 +1,21 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use super::{
-    query::compile_query,
-    subscription::{QuerySet, Subscription},
+    query::{compile_query, Query, Visibility},
+    subscription::{merge_updates, QueryId, QuerySet, RowCounts, Subscription},
 };
-use crate::db::datastore::locking_tx_datastore::MutTxId;
-use crate::host::module_host::{EventStatus, ModuleEvent};
+use crate::db::datastore::locking_tx_datastore::TxId;
+use crate::error::SubscriptionError;
+use crate::host::module_host::{DatabaseUpdate, EventStatus, ModuleEvent};
 use crate::protobuf::client_api::Subscribe;
 use crate::{
     client::{
-        messages::{CachedMessage, SubscriptionUpdateMessage, TransactionUpdateMessage},
-        ClientActorId, ClientConnectionSender,
+        messages::{
+            CachedMessage, QueryUpdateMessage, ServerMessage, SubscriptionUpdateMessage, TransactionUpdateMessage,
+        },
+        ClientActorId, ClientCall, ClientConnectionSender, MessageExecutionError,
     },
     host::NoSuchModule,
 };
@@ -29,12 +34,27 @@ enum ModuleSubscriptionCommand {
     RemoveSubscriber {
         client_id: ClientActorId,
     },
+    AddQuery {
+        sender: ClientConnectionSender,
+        query_id: QueryId,
+        query: String,
+    },
+    RemoveQuery {
+        sender: ClientConnectionSender,
+        query_id: QueryId,
+    },
+    BroadcastCommitEvent {
+        event: ModuleEvent,
+    },
 }
 
 #[derive(Debug)]
 enum Command {
     Subscription(ModuleSubscriptionCommand),
-    BroadcastCommitEvent { event: ModuleEvent },
+    BroadcastCommitEvent {
+        event: ModuleEvent,
+        client: Option<ClientCall>,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -44,7 +64,7 @@ pub struct ModuleSubscriptionManager {
 
 #[derive(Clone)]
 pub struct SubscriptionEventSender {
-    commit_event_tx: mpsc::UnboundedSender<ModuleEvent>,
+    commit_event_tx: mpsc::UnboundedSender<(ModuleEvent, Option<ClientCall>)>,
 }
 
 impl ModuleSubscriptionManager {
@@ -56,7 +76,7 @@ impl ModuleSubscriptionManager {
             loop {
                 let command = tokio::select! {
                     event = commit_event_rx.recv() => match event {
-                        Some(event) => Command::BroadcastCommitEvent { event },
+                        Some((event, client)) => Command::BroadcastCommitEvent { event, client },
                         // the module has exited
                         None => break,
                     },
@@ -81,37 +101,99 @@ impl ModuleSubscriptionManager {
             .send(ModuleSubscriptionCommand::RemoveSubscriber { client_id })
             .map_err(|_| NoSuchModule)
     }
+
+    pub fn add_query(
+        &self,
+        sender: ClientConnectionSender,
+        query_id: QueryId,
+        query: String,
+    ) -> Result<(), NoSuchModule> {
+        self.tx
+            .send(ModuleSubscriptionCommand::AddQuery {
+                sender,
+                query_id,
+                query,
+            })
+            .map_err(|_| NoSuchModule)
+    }
+
+    pub fn remove_query(&self, sender: ClientConnectionSender, query_id: QueryId) -> Result<(), NoSuchModule> {
+        self.tx
+            .send(ModuleSubscriptionCommand::RemoveQuery { sender, query_id })
+            .map_err(|_| NoSuchModule)
+    }
+
+    /// Broadcasts a transaction committed outside of the module, e.g. by `SQL`, to the subscribers.
+    pub fn broadcast_commit_event(&self, event: ModuleEvent) -> Result<(), NoSuchModule> {
+        self.tx
+            .send(ModuleSubscriptionCommand::BroadcastCommitEvent { event })
+            .map_err(|_| NoSuchModule)
+    }
 }
 
 impl SubscriptionEventSender {
-    pub async fn broadcast_event(&self, client: Option<&ClientConnectionSender>, mut event: ModuleEvent) {
+    /// Broadcasts the `event` of a reducer run to the subscribers,
+    /// and always replies to the `client` which called the reducer, whatever the outcome.
+    pub async fn broadcast_event(&self, client: Option<ClientCall>, mut event: ModuleEvent) {
         match event.status {
             EventStatus::Committed(_) => {
-                self.commit_event_tx.send(event).expect("subscription actor panicked");
+                self.commit_event_tx
+                    .send((event, client))
+                    .expect("subscription actor panicked");
             }
-            EventStatus::Failed(_) => {
+            EventStatus::Failed(_) | EventStatus::WriteConflict | EventStatus::OutOfEnergy => {
                 if let Some(client) = client {
                     let message = TransactionUpdateMessage {
                         event: &mut event,
                         database_update: Default::default(),
+                        request_id: client.request_id,
                     };
-                    let _ = client.send_message(message).await;
+                    let _ = client.sender.send_message(message).await;
                 } else {
                     log::trace!("Reducer failed but there is no client to send the failure to!")
                 }
             }
-            EventStatus::OutOfEnergy => {} // ?
         }
     }
 
-    pub fn broadcast_event_blocking(&self, client: Option<&ClientConnectionSender>, event: ModuleEvent) {
+    pub fn broadcast_event_blocking(&self, client: Option<ClientCall>, event: ModuleEvent) {
         tokio::runtime::Handle::current().block_on(self.broadcast_event(client, event))
     }
 }
 
+/// The queries of a client, by their position in the subscriptions, with what the client sees of their rows.
+type ClientQueries = Vec<(usize, Visibility)>;
+
+/// The queries a client subscribed to, as a whole set by `Subscribe`,
+/// and one by one by `SubscribeQuery`, with the rows they match.
+struct ClientSubscriptions {
+    set: Vec<Query>,
+    by_id: HashMap<QueryId, Query>,
+    rows: RowCounts,
+}
+
+impl Default for ClientSubscriptions {
+    fn default() -> Self {
+        Self {
+            set: Vec::new(),
+            by_id: HashMap::new(),
+            rows: RowCounts::for_no_queries(),
+        }
+    }
+}
+
+impl ClientSubscriptions {
+    fn queries(&self) -> impl Iterator<Item = &Query> {
+        self.set.iter().chain(self.by_id.values())
+    }
+}
+
 struct ModuleSubscriptionActor {
     relational_db: Arc<RelationalDB>,
     subscriptions: Vec<Subscription>,
+    /// The position of each query in `subscriptions`.
+    by_query: HashMap<Query, usize>,
+    clients: HashMap<ClientActorId, ClientSubscriptions>,
     owner_identity: Identity,
 }
 
@@ -120,6 +202,8 @@ impl ModuleSubscriptionActor {
         Self {
             relational_db,
             subscriptions: Vec::new(),
+            by_query: HashMap::new(),
+            clients: HashMap::new(),
             owner_identity,
         }
     }
@@ -132,7 +216,18 @@ impl ModuleSubscriptionActor {
             Command::Subscription(ModuleSubscriptionCommand::RemoveSubscriber { client_id }) => {
                 self.remove_subscriber(client_id)
             }
-            Command::BroadcastCommitEvent { event } => self.broadcast_commit_event(event).await?,
+            Command::Subscription(ModuleSubscriptionCommand::AddQuery {
+                sender,
+                query_id,
+                query,
+            }) => self.add_query(sender, query_id, query).await?,
+            Command::Subscription(ModuleSubscriptionCommand::RemoveQuery { sender, query_id }) => {
+                self.remove_query(sender, query_id).await?
+            }
+            Command::BroadcastCommitEvent { event, client } => self.broadcast_commit_event(event, client).await?,
+            Command::Subscription(ModuleSubscriptionCommand::BroadcastCommitEvent { event }) => {
+                self.broadcast_commit_event(event, None).await?
+            }
         }
         Ok(())
     }
@@ -141,7 +236,7 @@ impl ModuleSubscriptionActor {
         &mut self,
         sender: ClientConnectionSender,
         subscription: Subscribe,
-        tx: &mut MutTxId,
+        tx: &TxId,
     ) -> Result<(), DBError> {
         self.remove_subscriber(sender.id);
         let auth = AuthCtx::new(self.owner_identity, sender.id.identity);
@@ -149,26 +244,24 @@ impl ModuleSubscriptionActor {
         let queries: QuerySet = subscription
             .query_strings
             .into_iter()
-            .map(|query| compile_query(&self.relational_db, tx, &query))
+            .map(|query| compile_query(&self.relational_db, &tx.into(), &query))
             .collect::<Result<_, _>>()?;
 
-        let sub = match self.subscriptions.iter_mut().find(|s| s.queries == queries) {
-            Some(sub) => {
-                sub.subscribers.push(sender);
-                sub
-            }
-            None => {
-                self.subscriptions.push(Subscription {
-                    queries,
-                    subscribers: vec![sender],
-                });
-                self.subscriptions.last_mut().unwrap()
-            }
-        };
-
-        let database_update = sub.queries.eval(&self.relational_db, tx, auth)?;
+        // Checks the client can see the rows of its queries before subscribing it to them.
+        let mut rows = RowCounts::new();
+        let database_update = queries.eval(&self.relational_db, tx.into(), auth, &mut rows)?;
 
-        let sender = sub.subscribers.last().unwrap();
+        for query in &queries.0 {
+            self.subscribe_to(&sender, query);
+        }
+        self.clients.insert(
+            sender.id,
+            ClientSubscriptions {
+                set: queries.0,
+                by_id: HashMap::new(),
+                rows,
+            },
+        );
 
         // NOTE: It is important to send the state in this thread because if you spawn a new
         // thread it's possible for messages to get sent to the client out of order. If you do
@@ -185,39 +278,284 @@ impl ModuleSubscriptionActor {
         subscription: Subscribe,
     ) -> Result<(), DBError> {
         //Split logic to properly handle `Error` + `Tx`
-        let mut tx = self.relational_db.begin_tx();
-        let result = self._add_subscription(sender, subscription, &mut tx).await;
-        self.relational_db.finish_tx(tx, result)
+        let tx = self.relational_db.begin_read_tx();
+        let result = self._add_subscription(sender, subscription, &tx).await;
+        self.relational_db.release_tx(tx);
+        result
+    }
+
+    /// Subscribes the client of `sender` to the `query`, unless it already is.
+    ///
+    /// The clients share the evaluation of each of their queries, not only of their whole sets.
+    fn subscribe_to(&mut self, sender: &ClientConnectionSender, query: &Query) {
+        match self.by_query.get(query) {
+            Some(&pos) => self.subscriptions[pos].add_subscriber(sender.clone()),
+            None => {
+                self.by_query.insert(query.clone(), self.subscriptions.len());
+                self.subscriptions.push(Subscription {
+                    query: query.clone(),
+                    subscribers: vec![sender.clone()],
+                });
+            }
+        }
+    }
+
+    /// Unsubscribes the client of `client_id` from the `query`,
+    /// and drops the subscription to it once it has no subscriber left.
+    fn unsubscribe_from(&mut self, client_id: ClientActorId, query: &Query) {
+        let Some(&pos) = self.by_query.get(query) else {
+            return;
+        };
+        let subscription = &mut self.subscriptions[pos];
+        subscription.remove_subscriber(client_id);
+        if subscription.subscribers.is_empty() {
+            self.by_query.remove(query);
+            self.subscriptions.swap_remove(pos);
+            if let Some(moved) = self.subscriptions.get(pos) {
+                *self.by_query.get_mut(&moved.query).unwrap() = pos;
+            }
+        }
+    }
+
+    async fn _add_query(
+        &mut self,
+        sender: ClientConnectionSender,
+        query_id: QueryId,
+        query: String,
+        tx: &TxId,
+    ) -> Result<(), DBError> {
+        if self
+            .clients
+            .get(&sender.id)
+            .map_or(false, |client| client.by_id.contains_key(&query_id))
+        {
+            return Err(SubscriptionError::QueryIdInUse(query_id).into());
+        }
+        let auth = AuthCtx::new(self.owner_identity, sender.id.identity);
+
+        let query = compile_query(&self.relational_db, &tx.into(), &query)?;
+
+        // Checks the client can see the rows of the query before subscribing it to it.
+        // The rows already matched by its other queries are sent again, the client ignores them.
+        let database_update = query.eval(&self.relational_db, tx.into(), auth)?;
+
+        self.subscribe_to(&sender, &query);
+        let client = self.clients.entry(sender.id).or_default();
+        if !client.queries().any(|x| x == &query) {
+            let others = client.set.iter().chain(client.by_id.values());
+            client
+                .rows
+                .add_query(&self.relational_db, tx.into(), auth, &query, others)?;
+            merge_updates(&mut client.rows, [&database_update]);
+        }
+        client.by_id.insert(query_id, query);
+
+        // NOTE: Sent in this thread for the same reason as in `_add_subscription`.
+        let _ = sender
+            .send_message(QueryUpdateMessage {
+                query_id,
+                database_update,
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn add_query(
+        &mut self,
+        sender: ClientConnectionSender,
+        query_id: QueryId,
+        query: String,
+    ) -> Result<(), DBError> {
+        //Split logic to properly handle `Error` + `Tx`
+        let tx = self.relational_db.begin_read_tx();
+        let result = self._add_query(sender, query_id, query, &tx).await;
+        self.relational_db.release_tx(tx);
+        result
+    }
+
+    async fn _remove_query(
+        &mut self,
+        sender: ClientConnectionSender,
+        query_id: QueryId,
+        tx: &TxId,
+    ) -> Result<(), DBError> {
+        let Some(client) = self.clients.get_mut(&sender.id) else {
+            return Err(SubscriptionError::QueryIdNotFound(query_id).into());
+        };
+        let Some(query) = client.by_id.get(&query_id) else {
+            return Err(SubscriptionError::QueryIdNotFound(query_id).into());
+        };
+        let auth = AuthCtx::new(self.owner_identity, sender.id.identity);
+
+        let still_subscribed =
+            client.set.contains(query) || client.by_id.iter().any(|(id, other)| *id != query_id && other == query);
+        let mut database_update = DatabaseUpdate { tables: vec![] };
+        if !still_subscribed {
+            // The client loses the rows of the query which none of its remaining queries match.
+            let mut deleted = query.eval(&self.relational_db, tx.into(), auth)?;
+            for op in deleted.tables.iter_mut().flat_map(|table| &mut table.ops) {
+                op.op_type = 0; // Delete
+            }
+            database_update = merge_updates(&mut client.rows, [&deleted]);
+        }
+
+        let query = client.by_id.remove(&query_id).unwrap();
+        if !still_subscribed {
+            client
+                .rows
+                .remove_query(client.set.iter().chain(client.by_id.values()))?;
+        }
+        if client.queries().next().is_none() {
+            self.clients.remove(&sender.id);
+        }
+        if !still_subscribed {
+            self.unsubscribe_from(sender.id, &query);
+        }
+
+        let _ = sender
+            .send_message(QueryUpdateMessage {
+                query_id,
+                database_update,
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn remove_query(&mut self, sender: ClientConnectionSender, query_id: QueryId) -> Result<(), DBError> {
+        //Split logic to properly handle `Error` + `Tx`
+        let tx = self.relational_db.begin_read_tx();
+        let result = self._remove_query(sender, query_id, &tx).await;
+        self.relational_db.release_tx(tx);
+        result
     }
 
     fn remove_subscriber(&mut self, client_id: ClientActorId) {
-        self.subscriptions.retain_mut(|sub| {
-            sub.remove_subscriber(client_id);
-            !sub.subscribers.is_empty()
-        })
+        if let Some(client) = self.clients.remove(&client_id) {
+            for query in client.queries() {
+                self.unsubscribe_from(client_id, query);
+            }
+        }
     }
 
-    async fn _broadcast_commit_event(&mut self, mut event: ModuleEvent, tx: &mut MutTxId) -> Result<(), DBError> {
+    async fn _broadcast_commit_event(
+        &mut self,
+        mut event: ModuleEvent,
+        client: Option<ClientCall>,
+        tx: &TxId,
+    ) -> Result<(), DBError> {
         let futures = FuturesUnordered::new();
-        let auth = AuthCtx::new(self.owner_identity, event.caller_identity);
+        let database_update = event.status.database_update().unwrap();
 
-        for subscription in &mut self.subscriptions {
-            let database_update = event.status.database_update().unwrap();
-            let incr = subscription
-                .queries
-                .eval_incr(&self.relational_db, tx, database_update, auth)?;
+        // Each distinct query is evaluated once for all the clients subscribed to it that see its rows alike,
+        // under the identity of one of them.
+        let mut incrs = Vec::with_capacity(self.subscriptions.len());
+        for subscription in &self.subscriptions {
+            let mut by_visibility = HashMap::new();
+            for subscriber in &subscription.subscribers {
+                let auth = AuthCtx::new(self.owner_identity, subscriber.id.identity);
+                let visibility = subscription.query.visibility(auth);
+                if let Entry::Vacant(entry) = by_visibility.entry(visibility) {
+                    let incr = subscription
+                        .query
+                        .eval_incr(&self.relational_db, tx.into(), database_update, auth);
+                    if let Err(err) = &incr {
+                        log::warn!("Failed to evaluate a subscription for {}: {err}", subscriber.id);
+                    }
+                    entry.insert(incr);
+                }
+            }
+            incrs.push(by_visibility);
+        }
 
-            if incr.tables.is_empty() {
-                continue;
+        // The clients subscribed to the same queries, and seeing them alike, are sent the same message.
+        let mut clients: HashMap<ClientActorId, (&ClientConnectionSender, ClientQueries)> = HashMap::new();
+        for (pos, subscription) in self.subscriptions.iter().enumerate() {
+            for subscriber in &subscription.subscribers {
+                let auth = AuthCtx::new(self.owner_identity, subscriber.id.identity);
+                clients
+                    .entry(subscriber.id)
+                    .or_insert((subscriber, Vec::new()))
+                    .1
+                    .push((pos, subscription.query.visibility(auth)));
+            }
+        }
+
+        // The clients of a query which failed to evaluate would miss its changes, and their rows would drift
+        // from those of the database, so they are sent the error and unsubscribed instead.
+        let mut failed = Vec::new();
+        clients.retain(|_, (subscriber, queries)| {
+            let Some(err) = queries
+                .iter()
+                .find_map(|(pos, visibility)| incrs[*pos][visibility].as_ref().err())
+            else {
+                return true;
+            };
+            let error = MessageExecutionError {
+                reducer: None,
+                caller_identity: subscriber.id.identity,
+                request_id: 0,
+                err: anyhow::anyhow!("failed to evaluate a subscription, which was dropped: {err}"),
+            };
+            let message = error.serialize(subscriber.protocol);
+            futures.push(subscriber.send(message).map(drop));
+            failed.push(subscriber.id);
+            false
+        });
+        let merge = |queries: &ClientQueries, rows: &mut RowCounts| {
+            merge_updates(
+                rows,
+                queries
+                    .iter()
+                    .filter_map(|(pos, visibility)| incrs[*pos][visibility].as_ref().ok()),
+            )
+        };
+
+        // The client which called the reducer is told of its outcome, even without changes to its rows,
+        // in a message of its own echoing the id of its call.
+        if let Some(client) = &client {
+            let database_update = match (
+                clients.remove(&client.sender.id),
+                self.clients.get_mut(&client.sender.id),
+            ) {
+                (Some((_, queries)), Some(subscriptions)) => merge(&queries, &mut subscriptions.rows),
+                _ => DatabaseUpdate { tables: vec![] },
+            };
+            let message = TransactionUpdateMessage {
+                event: &mut event,
+                database_update,
+                request_id: client.request_id,
+            };
+            let _ = client.sender.send_message(message).await;
+        }
+
+        let mut groups: HashMap<ClientQueries, Vec<&ClientConnectionSender>> = HashMap::new();
+        for (subscriber, queries) in clients.into_values() {
+            groups.entry(queries).or_default().push(subscriber);
+        }
+
+        for (queries, subscribers) in groups {
+            // The clients of a group match the same rows, so their rows change alike.
+            let mut incr = None;
+            for subscriber in &subscribers {
+                if let Some(subscriptions) = self.clients.get_mut(&subscriber.id) {
+                    let update = merge(&queries, &mut subscriptions.rows);
+                    incr.get_or_insert(update);
+                }
             }
+            let Some(incr) = incr.filter(|incr| incr.tables.iter().any(|x| !x.ops.is_empty())) else {
+                continue;
+            };
 
             let message = TransactionUpdateMessage {
                 event: &mut event,
                 database_update: incr,
+                request_id: 0,
             };
             let mut message = CachedMessage::new(message);
 
-            for subscriber in &subscription.subscribers {
+            for subscriber in subscribers {
                 // rustc realllly doesn't like subscriber.send_message(message) here for weird
                 // lifetime reasons, even though it would be sound
                 let message = message.serialize(subscriber.protocol);
@@ -226,14 +564,482 @@ impl ModuleSubscriptionActor {
         }
 
         futures.collect::<()>().await;
+        for client_id in failed {
+            self.remove_subscriber(client_id);
+        }
 
         Ok(())
     }
 
-    async fn broadcast_commit_event(&mut self, event: ModuleEvent) -> Result<(), DBError> {
+    async fn broadcast_commit_event(
+        &mut self,
+        mut event: ModuleEvent,
+        client: Option<ClientCall>,
+    ) -> Result<(), DBError> {
+        // Clients are only told about commits which are durable,
+        // so that they never observe a state which could be lost in a crash.
+        if let Some(commit) = event.commit.clone() {
+            if !commit.durable().await {
+                log::warn!("Broadcasting a commit which never became durable");
+            }
+        }
+
+        // Evaluated against the state right after the commit, as later commits may already be visible by now.
+        let tx = event
+            .read_tx
+            .take()
+            .unwrap_or_else(|| Arc::new(self.relational_db.begin_read_tx()));
         //Split logic to properly handle `Error` + `Tx`
-        let mut tx = self.relational_db.begin_tx();
-        let result = self._broadcast_commit_event(event, &mut tx).await;
-        self.relational_db.finish_tx(tx, result)
+        self._broadcast_commit_event(event, client, &tx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ClientName, DataMessage, Protocol};
+    use crate::db::relational_db::tests_utils::make_test_db;
+    use crate::host::module_host::{DatabaseUpdate, ModuleFunctionCall};
+    use crate::host::{ArgsTuple, EnergyDiff, Timestamp};
+    use crate::sql::execute::run;
+    use crate::subscription::query::Query;
+    use spacetimedb_lib::error::ResultTest;
+    use std::time::Duration;
+
+    fn client(identity: Identity, name: u64) -> (ClientConnectionSender, mpsc::Receiver<DataMessage>) {
+        let id = ClientActorId {
+            identity,
+            name: ClientName(name),
+        };
+        ClientConnectionSender::dummy_with_channel(id, Protocol::Text)
+    }
+
+    fn subscribe(queries: &[&str]) -> Subscribe {
+        Subscribe {
+            query_strings: queries.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    /// Commits `sql` as if a reducer called by `caller` ran it.
+    fn commit(db: &RelationalDB, owner: Identity, caller: Identity, sql: &str) -> ResultTest<ModuleEvent> {
+        let mut tx = db.begin_tx();
+        run(db, (&mut tx).into(), sql, AuthCtx::for_current(owner))?;
+        let (tx_data, _, read_tx) = db.commit_tx_with_read_tx(tx)?.expect("the transaction writes rows");
+        Ok(ModuleEvent {
+            timestamp: Timestamp::now(),
+            caller_identity: caller,
+            function_call: ModuleFunctionCall {
+                reducer: "update".to_string(),
+                args: ArgsTuple::default(),
+            },
+            status: EventStatus::Committed(DatabaseUpdate::from_writes(db, &tx_data)),
+            energy_quanta_used: EnergyDiff::ZERO,
+            host_execution_duration: Duration::ZERO,
+            commit: None,
+            read_tx: Some(Arc::new(read_tx)),
+        })
+    }
+
+    /// Returns the messages received by a client since the last call.
+    fn received(rx: &mut mpsc::Receiver<DataMessage>) -> Vec<String> {
+        let mut messages = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                DataMessage::Text(text) => messages.push(text),
+                DataMessage::Binary(_) => panic!("Expected a text message"),
+            }
+        }
+        messages
+    }
+
+    // Check that the changes to a private table are only sent to the owner,
+    // whoever makes them, while the queries on public tables are evaluated once for everyone.
+    #[tokio::test]
+    async fn test_subscription_private_table() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let db = Arc::new(db);
+        let owner = Identity::from_byte_array([1; 32]);
+        let other = Identity::from_byte_array([2; 32]);
+        let mut tx = db.begin_tx();
+        run(
+            &db,
+            (&mut tx).into(),
+            "CREATE TABLE _secret (id BIGINT UNSIGNED); CREATE TABLE score (id BIGINT UNSIGNED)",
+            AuthCtx::for_current(owner),
+        )?;
+        db.commit_tx(tx)?;
+
+        let mut actor = ModuleSubscriptionActor::new(db.clone(), owner);
+        let (owner_client, mut owner_rx) = client(owner, 1);
+        let (other_client, mut other_rx) = client(other, 2);
+        let (other_client_2, mut other_rx_2) = client(Identity::from_byte_array([3; 32]), 3);
+
+        let both = subscribe(&["SELECT * FROM _secret", "SELECT * FROM score"]);
+        actor.add_subscription(owner_client, both.clone()).await?;
+        // The other clients can't subscribe to the private table, so they aren't subscribed at all.
+        assert!(actor.add_subscription(other_client.clone(), both).await.is_err());
+        assert_eq!(actor.subscriptions.len(), 2);
+        actor
+            .add_subscription(other_client, subscribe(&["SELECT * FROM score"]))
+            .await?;
+        actor
+            .add_subscription(other_client_2, subscribe(&["SELECT * FROM score"]))
+            .await?;
+        assert_eq!(received(&mut owner_rx).len(), 1);
+        assert_eq!(received(&mut other_rx).len(), 1);
+        assert_eq!(received(&mut other_rx_2).len(), 1);
+
+        // The query on the public table is shared by the owner and the other clients.
+        let visibilities = |query: &Query| {
+            [owner, other]
+                .map(|caller| query.visibility(AuthCtx::new(owner, caller)))
+                .to_vec()
+        };
+        assert_eq!(actor.subscriptions.len(), 2);
+        assert_eq!(
+            visibilities(&actor.subscriptions[0].query),
+            [Visibility::Owner, Visibility::Public]
+        );
+        assert_eq!(
+            visibilities(&actor.subscriptions[1].query),
+            [Visibility::Public, Visibility::Public]
+        );
+
+        for (id, caller) in [owner, other].into_iter().enumerate() {
+            let sql = format!("INSERT INTO _secret (id) VALUES ({id}); INSERT INTO score (id) VALUES ({id})");
+            let event = commit(&db, owner, caller, &sql)?;
+            actor.broadcast_commit_event(event, None).await?;
+
+            let messages = received(&mut owner_rx);
+            assert_eq!(messages.len(), 1);
+            assert!(messages[0].contains("\"_secret\"") && messages[0].contains("\"score\""));
+            for rx in [&mut other_rx, &mut other_rx_2] {
+                let messages = received(rx);
+                assert_eq!(messages.len(), 1);
+                assert!(!messages[0].contains("\"_secret\"") && messages[0].contains("\"score\""));
+            }
+
+            // A change to the private table alone is sent to nobody else.
+            let event = commit(&db, owner, caller, "DELETE FROM _secret")?;
+            actor.broadcast_commit_event(event, None).await?;
+            assert_eq!(received(&mut owner_rx).len(), 1);
+            assert!(received(&mut other_rx).is_empty());
+            assert!(received(&mut other_rx_2).is_empty());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `(op, id)` of the rows of the single `QueryUpdate` received by a client since the last call.
+    fn query_update(rx: &mut mpsc::Receiver<DataMessage>, query_id: QueryId) -> Vec<(String, u64)> {
+        let messages = received(rx);
+        assert_eq!(messages.len(), 1);
+        let message: serde_json::Value = serde_json::from_str(&messages[0]).unwrap();
+        let update = &message["QueryUpdate"];
+        assert_eq!(update["query_id"], query_id);
+        let mut rows = Vec::new();
+        for table in update["subscription_update"]["table_updates"].as_array().unwrap() {
+            for op in table["table_row_operations"].as_array().unwrap() {
+                rows.push((op["op"].as_str().unwrap().to_string(), op["row"][0].as_u64().unwrap()));
+            }
+        }
+        rows.sort();
+        rows
+    }
+
+    // Check that subscribing to and unsubscribing from single queries only sends their rows,
+    // and deletes the rows no other query of the client matches.
+    #[tokio::test]
+    async fn test_subscribe_query() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let db = Arc::new(db);
+        let owner = Identity::from_byte_array([1; 32]);
+        let mut tx = db.begin_tx();
+        run(
+            &db,
+            (&mut tx).into(),
+            "CREATE TABLE score (id BIGINT UNSIGNED)",
+            AuthCtx::for_current(owner),
+        )?;
+        db.commit_tx(tx)?;
+        commit(&db, owner, owner, "INSERT INTO score (id) VALUES (1), (2), (3)")?;
+
+        let mut actor = ModuleSubscriptionActor::new(db.clone(), owner);
+        let (sender, mut rx) = client(owner, 1);
+        let insert = |id| ("insert".to_string(), id);
+        let delete = |id| ("delete".to_string(), id);
+
+        actor
+            .add_query(sender.clone(), 1, "SELECT * FROM score WHERE id < 3".into())
+            .await?;
+        assert_eq!(query_update(&mut rx, 1), [insert(1), insert(2)]);
+        actor
+            .add_query(sender.clone(), 2, "SELECT * FROM score WHERE id > 1".into())
+            .await?;
+        assert_eq!(query_update(&mut rx, 2), [insert(2), insert(3)]);
+        assert!(actor
+            .add_query(sender.clone(), 2, "SELECT * FROM score".into())
+            .await
+            .is_err());
+        assert!(received(&mut rx).is_empty());
+
+        // The client keeps the rows its other query matches.
+        actor.remove_query(sender.clone(), 2).await?;
+        assert_eq!(query_update(&mut rx, 2), [delete(3)]);
+        assert!(actor.remove_query(sender.clone(), 2).await.is_err());
+        assert_eq!(actor.subscriptions.len(), 1);
+
+        // The same query under another id is still subscribed to.
+        actor
+            .add_query(sender.clone(), 3, "SELECT * FROM score WHERE id < 3".into())
+            .await?;
+        assert_eq!(query_update(&mut rx, 3), [insert(1), insert(2)]);
+        actor.remove_query(sender.clone(), 1).await?;
+        assert_eq!(query_update(&mut rx, 1), []);
+        assert_eq!(actor.subscriptions.len(), 1);
+
+        let event = commit(&db, owner, owner, "INSERT INTO score (id) VALUES (0), (4)")?;
+        actor.broadcast_commit_event(event, None).await?;
+        let messages = received(&mut rx);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("TransactionUpdate"));
+
+        actor.remove_query(sender, 3).await?;
+        assert_eq!(query_update(&mut rx, 3), [delete(0), delete(1), delete(2)]);
+        assert!(actor.subscriptions.is_empty());
+        assert!(actor.clients.is_empty());
+
+        Ok(())
+    }
+
+    // Check that the clients subscribing to a query find it by its index, also after other queries are dropped.
+    #[tokio::test]
+    async fn test_subscription_index() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let db = Arc::new(db);
+        let owner = Identity::from_byte_array([1; 32]);
+        let mut tx = db.begin_tx();
+        run(
+            &db,
+            (&mut tx).into(),
+            "CREATE TABLE score (id BIGINT UNSIGNED)",
+            AuthCtx::for_current(owner),
+        )?;
+        db.commit_tx(tx)?;
+
+        let mut actor = ModuleSubscriptionActor::new(db.clone(), owner);
+        let queries = ["SELECT * FROM score WHERE id = 1", "SELECT * FROM score WHERE id = 2"];
+        let (first, _first_rx) = client(owner, 1);
+        let (second, _second_rx) = client(owner, 2);
+        let (third, _third_rx) = client(owner, 3);
+        actor.add_subscription(first.clone(), subscribe(&queries[..1])).await?;
+        actor.add_subscription(second, subscribe(&queries[1..])).await?;
+
+        // The second query takes the place of the first one.
+        actor.remove_subscriber(first.id);
+        assert_eq!(actor.subscriptions.len(), 1);
+        actor.add_subscription(third, subscribe(&queries[1..])).await?;
+        assert_eq!(actor.subscriptions.len(), 1);
+        assert_eq!(actor.subscriptions[0].subscribers.len(), 2);
+        for (pos, subscription) in actor.subscriptions.iter().enumerate() {
+            assert_eq!(actor.by_query[&subscription.query], pos);
+        }
+        assert_eq!(actor.by_query.len(), actor.subscriptions.len());
+
+        Ok(())
+    }
+
+    // Check that the clients of a query which fails to evaluate are told so and unsubscribed,
+    // while the other clients are sent their changes.
+    #[tokio::test]
+    async fn test_failed_subscription() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let db = Arc::new(db);
+        let owner = Identity::from_byte_array([1; 32]);
+        let mut tx = db.begin_tx();
+        run(
+            &db,
+            (&mut tx).into(),
+            "CREATE TABLE score (id BIGINT UNSIGNED)",
+            AuthCtx::for_current(owner),
+        )?;
+        db.commit_tx(tx)?;
+
+        let mut actor = ModuleSubscriptionActor::new(db.clone(), owner);
+        let (failing, mut failing_rx) = client(owner, 1);
+        let (other, mut other_rx) = client(owner, 2);
+        actor
+            .add_subscription(failing.clone(), subscribe(&["SELECT * FROM score WHERE 10 / id > 1"]))
+            .await?;
+        actor
+            .add_subscription(other.clone(), subscribe(&["SELECT * FROM score"]))
+            .await?;
+        assert_eq!(received(&mut failing_rx).len(), 1);
+        assert_eq!(received(&mut other_rx).len(), 1);
+
+        let event = commit(&db, owner, owner, "INSERT INTO score (id) VALUES (0)")?;
+        actor.broadcast_commit_event(event, None).await?;
+        let messages = received(&mut failing_rx);
+        assert_eq!(messages.len(), 1);
+        assert!(
+            messages[0].contains("failed to evaluate a subscription"),
+            "{}",
+            messages[0]
+        );
+        let messages = received(&mut other_rx);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("\"insert\""));
+
+        assert!(!actor.clients.contains_key(&failing.id));
+        assert!(actor.clients.contains_key(&other.id));
+        assert_eq!(actor.subscriptions.len(), 1);
+
+        Ok(())
+    }
+
+    // Check that a client isn't sent the deletion of a row which another of its queries still matches.
+    #[tokio::test]
+    async fn test_overlapping_queries() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let db = Arc::new(db);
+        let owner = Identity::from_byte_array([1; 32]);
+        let mut tx = db.begin_tx();
+        run(
+            &db,
+            (&mut tx).into(),
+            "CREATE TABLE Item (id BIGINT UNSIGNED, kind BIGINT UNSIGNED); CREATE TABLE Inventory (item_id BIGINT UNSIGNED)",
+            AuthCtx::for_current(owner),
+        )?;
+        db.commit_tx(tx)?;
+        let sql = "INSERT INTO Item (id, kind) VALUES (1, 1); INSERT INTO Inventory (item_id) VALUES (1)";
+        commit(&db, owner, owner, sql)?;
+
+        let mut actor = ModuleSubscriptionActor::new(db.clone(), owner);
+        let (sender, mut rx) = client(owner, 1);
+        let queries = subscribe(&[
+            "SELECT Item.* FROM Item JOIN Inventory ON Item.id = Inventory.item_id",
+            "SELECT * FROM Item WHERE kind = 1",
+        ]);
+        actor.add_subscription(sender, queries).await?;
+        assert_eq!(received(&mut rx).len(), 1);
+
+        let event = commit(&db, owner, owner, "DELETE FROM Inventory")?;
+        actor.broadcast_commit_event(event, None).await?;
+        assert!(received(&mut rx).is_empty());
+
+        let event = commit(&db, owner, owner, "DELETE FROM Item")?;
+        actor.broadcast_commit_event(event, None).await?;
+        let messages = received(&mut rx);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("\"delete\""));
+
+        Ok(())
+    }
+
+    // Check that the changes to a join are those of each commit, even when broadcast after the next commit.
+    #[tokio::test]
+    async fn test_join_commits_in_quick_succession() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let db = Arc::new(db);
+        let owner = Identity::from_byte_array([1; 32]);
+        let mut tx = db.begin_tx();
+        run(
+            &db,
+            (&mut tx).into(),
+            "CREATE TABLE Item (id BIGINT UNSIGNED, kind BIGINT UNSIGNED); CREATE TABLE Inventory (item_id BIGINT UNSIGNED)",
+            AuthCtx::for_current(owner),
+        )?;
+        db.commit_tx(tx)?;
+        commit(&db, owner, owner, "INSERT INTO Inventory (item_id) VALUES (1)")?;
+
+        let mut actor = ModuleSubscriptionActor::new(db.clone(), owner);
+        let (sender, mut rx) = client(owner, 1);
+        let queries = subscribe(&["SELECT Item.* FROM Item JOIN Inventory ON Item.id = Inventory.item_id"]);
+        actor.add_subscription(sender, queries).await?;
+        assert_eq!(received(&mut rx).len(), 1);
+
+        // Both sides of the join change before the first commit is broadcast.
+        let insert = commit(&db, owner, owner, "INSERT INTO Item (id, kind) VALUES (1, 1)")?;
+        let delete = commit(&db, owner, owner, "DELETE FROM Inventory")?;
+
+        actor.broadcast_commit_event(insert, None).await?;
+        let messages = received(&mut rx);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("\"insert\"") && !messages[0].contains("\"delete\""));
+
+        actor.broadcast_commit_event(delete, None).await?;
+        let messages = received(&mut rx);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("\"delete\"") && !messages[0].contains("\"insert\""));
+
+        Ok(())
+    }
+
+    // Check that the client calling a reducer is always replied to with the id of its call,
+    // while the other subscribers are only sent the changes to their rows, without it.
+    #[tokio::test]
+    async fn test_reducer_call_reply() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let db = Arc::new(db);
+        let owner = Identity::from_byte_array([1; 32]);
+        let caller = Identity::from_byte_array([2; 32]);
+        let mut tx = db.begin_tx();
+        run(
+            &db,
+            (&mut tx).into(),
+            "CREATE TABLE score (id BIGINT UNSIGNED); CREATE TABLE other (id BIGINT UNSIGNED)",
+            AuthCtx::for_current(owner),
+        )?;
+        db.commit_tx(tx)?;
+
+        let mut actor = ModuleSubscriptionActor::new(db.clone(), owner);
+        let (caller_client, mut caller_rx) = client(caller, 1);
+        let (subscriber, mut subscriber_rx) = client(owner, 2);
+        actor
+            .add_subscription(subscriber, subscribe(&["SELECT * FROM score"]))
+            .await?;
+        assert_eq!(received(&mut subscriber_rx).len(), 1);
+
+        let request_id = |message: &str| {
+            let message: serde_json::Value = serde_json::from_str(message).unwrap();
+            message["TransactionUpdate"]["event"]["function_call"]["request_id"].clone()
+        };
+        let call = ClientCall {
+            sender: caller_client.clone(),
+            request_id: 7,
+        };
+
+        // The caller isn't subscribed to anything, but is still told of the outcome of its call.
+        let event = commit(&db, owner, caller, "INSERT INTO score (id) VALUES (1)")?;
+        actor.broadcast_commit_event(event, Some(call.clone())).await?;
+        let messages = received(&mut caller_rx);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(request_id(&messages[0]), 7);
+        let messages = received(&mut subscriber_rx);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(request_id(&messages[0]), 0);
+
+        // Nor does it need to change any subscribed row.
+        let event = commit(&db, owner, caller, "INSERT INTO other (id) VALUES (1)")?;
+        actor.broadcast_commit_event(event, Some(call.clone())).await?;
+        assert_eq!(received(&mut caller_rx).len(), 1);
+        assert!(received(&mut subscriber_rx).is_empty());
+
+        // A subscribed caller gets a single message with both the id and its changes.
+        actor
+            .add_subscription(caller_client, subscribe(&["SELECT * FROM score"]))
+            .await?;
+        assert_eq!(received(&mut caller_rx).len(), 1);
+        let event = commit(&db, owner, caller, "INSERT INTO score (id) VALUES (2)")?;
+        actor.broadcast_commit_event(event, Some(call)).await?;
+        let messages = received(&mut caller_rx);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(request_id(&messages[0]), 7);
+        assert!(messages[0].contains("\"score\""));
+        let messages = received(&mut subscriber_rx);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(request_id(&messages[0]), 0);
+
+        Ok(())
     }
 }
@@ -1,28 +1,35 @@
-use crate::db::datastore::locking_tx_datastore::MutTxId;
+use std::collections::{HashMap, HashSet};
+
 use crate::db::relational_db::RelationalDB;
 use crate::error::{DBError, SubscriptionError};
-use crate::host::module_host::DatabaseTableUpdate;
+use crate::host::module_host::{DatabaseTableUpdate, DatabaseUpdate, TableOp};
 use crate::sql::compiler::compile_sql;
 use crate::sql::execute::execute_single_sql;
+use crate::vm::TxMode;
+use spacetimedb_lib::auth::StAccess;
 use spacetimedb_lib::identity::AuthCtx;
-use spacetimedb_lib::relation::{Column, FieldName, MemTable};
-use spacetimedb_sats::AlgebraicType;
-use spacetimedb_vm::expr::{Crud, CrudExpr, DbType, QueryExpr, SourceExpr};
+use spacetimedb_lib::relation::{Column, DbTable, FieldExpr, FieldName, MemTable};
+use spacetimedb_lib::PrimaryKey;
+use spacetimedb_sats::{AlgebraicType, ProductValue};
+use spacetimedb_vm::expr::{self, Crud, CrudExpr, DbType, QueryExpr, SourceExpr};
 
 pub enum QueryDef {
     Table(String),
     Sql(String),
 }
 
-#[derive(Clone, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub struct Query {
     pub queries: Vec<QueryExpr>,
 }
 
 impl Query {
+    /// Returns the queries on the single table of `table`, with its source replaced by the rows that changed.
+    ///
+    /// The queries joining tables are evaluated apart, by [Self::joins].
     pub fn queries_of_table_id<'a>(&'a self, table: &'a DatabaseTableUpdate) -> impl Iterator<Item = QueryExpr> + '_ {
         self.queries.iter().filter_map(move |x| {
-            if x.source.get_db_table().map(|x| x.table_id) == Some(table.table_id) {
+            if x.source.get_db_table().map(|x| x.table_id) == Some(table.table_id) && !x.query.iter().any(is_join) {
                 let t = to_mem_table(x.clone(), table);
                 Some(t)
             } else {
@@ -30,6 +37,335 @@ impl Query {
             }
         })
     }
+
+    /// Returns the queries joining two tables.
+    /// The tables the query returns rows of.
+    pub fn table_ids(&self) -> Result<HashSet<u32>, SubscriptionError> {
+        let mut table_ids = HashSet::new();
+        for q in &self.queries {
+            let table = match IncrementalJoin::new(q)? {
+                Some(join) => Some(join.table()),
+                None => q.source.get_db_table(),
+            };
+            table_ids.extend(table.map(|t| t.table_id));
+        }
+        Ok(table_ids)
+    }
+
+    pub fn joins(&self) -> Result<Vec<IncrementalJoin<'_>>, SubscriptionError> {
+        let mut joins = Vec::new();
+        for q in &self.queries {
+            if let Some(join) = IncrementalJoin::new(q)? {
+                joins.push(join);
+            }
+        }
+        Ok(joins)
+    }
+
+    /// Returns what the client of `auth` sees of the rows of the query.
+    ///
+    /// The rows of the public tables are seen alike by every client.
+    pub fn visibility(&self, auth: AuthCtx) -> Visibility {
+        let is_public = |x: &QueryExpr| x.source.table_access() == StAccess::Public;
+        let all_public = self
+            .queries
+            .iter()
+            .all(|q| is_public(q) && q.query.iter().filter_map(join_rhs).all(is_public));
+        if all_public || auth.owner != auth.caller {
+            Visibility::Public
+        } else {
+            Visibility::Owner
+        }
+    }
+}
+
+/// What a client sees of the tables of a database,
+/// so that the clients seeing them alike share the evaluation of their queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Visibility {
+    /// The owner of the database sees all its tables.
+    Owner,
+    /// The other clients only see its [StAccess::Public] tables.
+    Public,
+}
+
+fn is_join(q: &expr::Query) -> bool {
+    matches!(q, expr::Query::JoinInner(_) | expr::Query::JoinCross(_))
+}
+
+fn join_rhs(q: &expr::Query) -> Option<&QueryExpr> {
+    match q {
+        expr::Query::JoinInner(join) => Some(&join.rhs),
+        expr::Query::JoinCross(rhs) => Some(rhs),
+        _ => None,
+    }
+}
+
+fn join_rhs_mut(q: &mut expr::Query) -> Option<&mut QueryExpr> {
+    match q {
+        expr::Query::JoinInner(join) => Some(&mut join.rhs),
+        expr::Query::JoinCross(rhs) => Some(rhs),
+        _ => None,
+    }
+}
+
+/// The rows a subscription joining two tables yields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JoinOutput {
+    /// The rows of the left table that match a row of the right one, as in `SELECT lhs.* FROM lhs JOIN rhs`.
+    Lhs,
+    /// The rows of the right table that match a row of the left one, as in `SELECT rhs.* FROM lhs JOIN rhs`.
+    Rhs,
+    /// The pairs of rows that match, as in `SELECT * FROM lhs JOIN rhs`.
+    Joined,
+}
+
+/// The rows that a transaction inserted into and deleted from a table.
+struct Delta {
+    inserts: Vec<ProductValue>,
+    deletes: Vec<ProductValue>,
+}
+
+impl Delta {
+    fn of(table: &DbTable, update: &DatabaseUpdate) -> Self {
+        let mut inserts = Vec::new();
+        let mut deletes = Vec::new();
+        for op in update
+            .tables
+            .iter()
+            .filter(|x| x.table_id == table.table_id)
+            .flat_map(|x| &x.ops)
+        {
+            match op.op_type {
+                1 => inserts.push(op.row.clone()),
+                _ => deletes.push(op.row.clone()),
+            }
+        }
+        // A row both deleted and inserted is where it was.
+        let deleted: HashSet<_> = deletes.iter().map(RelationalDB::pk_for_row).collect();
+        let inserted: HashSet<_> = inserts.iter().map(RelationalDB::pk_for_row).collect();
+        inserts.retain(|row| !deleted.contains(&RelationalDB::pk_for_row(row)));
+        deletes.retain(|row| !inserted.contains(&RelationalDB::pk_for_row(row)));
+        Self { inserts, deletes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inserts.is_empty() && self.deletes.is_empty()
+    }
+}
+
+/// A subscription joining two tables, maintained incrementally as the rows of either side change.
+///
+/// The rows of a join are bilinear on the rows of its sides, so the rows it gains and loses
+/// by a transaction changing `lhs` by `ΔL` and `rhs` by `ΔR` are counted by the delta rule
+/// `ΔL ⋈ rhs + lhs ⋈ ΔR - ΔL ⋈ ΔR`, from the tables as they are after it.
+/// When the join yields the rows of one side, a row can match many rows of the other,
+/// so it is only inserted when it starts matching any, and deleted when it stops matching them all.
+pub struct IncrementalJoin<'a> {
+    expr: &'a QueryExpr,
+    /// The position of the join in the operations of `expr`.
+    join: usize,
+    lhs: &'a DbTable,
+    rhs: &'a DbTable,
+    output: JoinOutput,
+}
+
+impl<'a> IncrementalJoin<'a> {
+    /// Returns the join of `expr`, or `None` when it queries a single table.
+    ///
+    /// Fails when the join can't be maintained incrementally, as when it joins more than two tables.
+    pub fn new(expr: &'a QueryExpr) -> Result<Option<Self>, SubscriptionError> {
+        let mut joins = expr.query.iter().enumerate().filter(|(_, q)| is_join(q));
+        let Some((join, op)) = joins.next() else {
+            return Ok(None);
+        };
+        if joins.next().is_some() {
+            return Err(SubscriptionError::Unsupported("joins of more than two tables".into()));
+        }
+        let rhs = join_rhs(op).expect("a join has a right side");
+        let (Some(lhs), Some(rhs_table)) = (expr.source.get_db_table(), rhs.source.get_db_table()) else {
+            return Err(SubscriptionError::Unsupported("joins of values".into()));
+        };
+        if rhs.query.iter().any(is_join) {
+            return Err(SubscriptionError::Unsupported("joins of more than two tables".into()));
+        }
+
+        let output = match expr.query.last() {
+            Some(expr::Query::Project(fields)) if projects(fields, lhs) => JoinOutput::Lhs,
+            Some(expr::Query::Project(fields)) if projects(fields, rhs_table) => JoinOutput::Rhs,
+            Some(op @ expr::Query::Project(_)) => {
+                return Err(SubscriptionError::Unsupported(format!(
+                    "`{op}` of a join, instead of all the columns of one of its tables"
+                )))
+            }
+            _ => JoinOutput::Joined,
+        };
+
+        Ok(Some(Self {
+            expr,
+            join,
+            lhs,
+            rhs: rhs_table,
+            output,
+        }))
+    }
+
+    /// The table the rows yielded by the join are sent as.
+    pub fn table(&self) -> &'a DbTable {
+        match self.output {
+            JoinOutput::Rhs => self.rhs,
+            JoinOutput::Lhs | JoinOutput::Joined => self.lhs,
+        }
+    }
+
+    /// Returns the rows the join gains and loses by the transaction of `update`, evaluated in `tx` after it.
+    pub fn eval(
+        &self,
+        db: &RelationalDB,
+        mut tx: TxMode,
+        update: &DatabaseUpdate,
+        auth: AuthCtx,
+    ) -> Result<Option<DatabaseTableUpdate>, DBError> {
+        let lhs = Delta::of(self.lhs, update);
+        let rhs = Delta::of(self.rhs, update);
+        if lhs.is_empty() && rhs.is_empty() {
+            return Ok(None);
+        }
+
+        // The terms of the delta rule, with `None` for the side read from the table.
+        let mut terms = vec![
+            (Some(&lhs.inserts), None, 1),
+            (Some(&lhs.deletes), None, -1),
+            (None, Some(&rhs.inserts), 1),
+            (None, Some(&rhs.deletes), -1),
+        ];
+        if !lhs.is_empty() && !rhs.is_empty() {
+            terms.extend([
+                (Some(&lhs.inserts), Some(&rhs.inserts), -1),
+                (Some(&lhs.inserts), Some(&rhs.deletes), 1),
+                (Some(&lhs.deletes), Some(&rhs.inserts), 1),
+                (Some(&lhs.deletes), Some(&rhs.deletes), -1),
+            ]);
+        }
+
+        // How many times more each row is yielded after the transaction than before it, in the order they are found.
+        let mut changed: Vec<(ProductValue, i64)> = Vec::new();
+        let mut positions = HashMap::new();
+        for (lhs_rows, rhs_rows, sign) in terms {
+            if lhs_rows.map_or(false, Vec::is_empty) || rhs_rows.map_or(false, Vec::is_empty) {
+                continue;
+            }
+            for row in self.run(db, tx.reborrow(), lhs_rows, rhs_rows, auth)? {
+                let pos = *positions.entry(RelationalDB::pk_for_row(&row)).or_insert_with(|| {
+                    changed.push((row, 0));
+                    changed.len() - 1
+                });
+                changed[pos].1 += sign;
+            }
+        }
+        changed.retain(|(_, count)| *count != 0);
+        if changed.is_empty() {
+            return Ok(None);
+        }
+
+        // The number of times each changed row is yielded after the transaction,
+        // when it is a row of a side that may match many rows of the other.
+        let after = match self.output {
+            JoinOutput::Lhs => Some(self.count_after(db, tx, &lhs, &changed, auth)?),
+            JoinOutput::Rhs => Some(self.count_after(db, tx, &rhs, &changed, auth)?),
+            JoinOutput::Joined => None,
+        };
+
+        let table = self.table();
+        let mut ops = Vec::new();
+        for (row, count) in changed {
+            let row_pk = RelationalDB::pk_for_row(&row);
+            let (before, after) = match &after {
+                Some(after) => {
+                    let after = after.get(&row_pk).copied().unwrap_or(0);
+                    (after - count, after)
+                }
+                // A pair of rows is yielded at most once.
+                None => (i64::from(count < 0), i64::from(count > 0)),
+            };
+            let op_type = match (before > 0, after > 0) {
+                (false, true) => 1,
+                (true, false) => 0,
+                _ => continue,
+            };
+            ops.push(TableOp {
+                op_type,
+                row_pk: row_pk.to_bytes(),
+                row,
+            });
+        }
+
+        Ok((!ops.is_empty()).then(|| DatabaseTableUpdate {
+            table_id: table.table_id,
+            table_name: table.head.table_name.clone(),
+            ops,
+        }))
+    }
+
+    /// Counts how many times the `changed` rows of the side changed by `delta` are yielded after the transaction.
+    fn count_after(
+        &self,
+        db: &RelationalDB,
+        tx: TxMode,
+        delta: &Delta,
+        changed: &[(ProductValue, i64)],
+        auth: AuthCtx,
+    ) -> Result<HashMap<PrimaryKey, i64>, DBError> {
+        let deleted: HashSet<_> = delta.deletes.iter().map(RelationalDB::pk_for_row).collect();
+        let rows: Vec<_> = changed
+            .iter()
+            .map(|(row, _)| row)
+            .filter(|row| !deleted.contains(&RelationalDB::pk_for_row(row)))
+            .cloned()
+            .collect();
+
+        let mut counts = HashMap::new();
+        if rows.is_empty() {
+            return Ok(counts);
+        }
+        let (lhs, rhs) = match self.output {
+            JoinOutput::Rhs => (None, Some(&rows)),
+            JoinOutput::Lhs | JoinOutput::Joined => (Some(&rows), None),
+        };
+        for row in self.run(db, tx, lhs, rhs, auth)? {
+            *counts.entry(RelationalDB::pk_for_row(&row)).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+
+    /// Runs the join with the sides given `rows` instead of the rows of their table.
+    fn run(
+        &self,
+        db: &RelationalDB,
+        tx: TxMode,
+        lhs: Option<&Vec<ProductValue>>,
+        rhs: Option<&Vec<ProductValue>>,
+        auth: AuthCtx,
+    ) -> Result<Vec<ProductValue>, DBError> {
+        let mut q = self.expr.clone();
+        if let Some(rows) = lhs {
+            q.source = SourceExpr::MemTable(MemTable::new(&self.lhs.head, self.lhs.table_access, rows));
+        }
+        if let Some(rows) = rhs {
+            let join = join_rhs_mut(&mut q.query[self.join]).expect("a join has a right side");
+            join.source = SourceExpr::MemTable(MemTable::new(&self.rhs.head, self.rhs.table_access, rows));
+        }
+        Ok(run_query(db, tx, &q, auth)?.into_iter().flat_map(|x| x.data).collect())
+    }
+}
+
+/// Whether `fields` are all the columns of `table`, in order.
+fn projects(fields: &[FieldExpr], table: &DbTable) -> bool {
+    fields.len() == table.head.fields.len()
+        && fields
+            .iter()
+            .zip(&table.head.fields)
+            .all(|(x, col)| matches!(x, FieldExpr::Name(x) if *x == col.field))
 }
 
 pub const OP_TYPE_FIELD_NAME: &str = "__op_type";
@@ -70,14 +406,14 @@ pub fn to_mem_table(of: QueryExpr, data: &DatabaseTableUpdate) -> QueryExpr {
 /// Runs a query that evaluates if the changes made should be reported to the [ModuleSubscriptionManager]
 pub(crate) fn run_query(
     db: &RelationalDB,
-    tx: &mut MutTxId,
+    tx: TxMode,
     query: &QueryExpr,
     auth: AuthCtx,
 ) -> Result<Vec<MemTable>, DBError> {
     execute_single_sql(db, tx, CrudExpr::Query(query.clone()), auth)
 }
 
-pub fn compile_query(relational_db: &RelationalDB, tx: &MutTxId, input: &str) -> Result<Query, DBError> {
+pub fn compile_query(relational_db: &RelationalDB, tx: &TxMode, input: &str) -> Result<Query, DBError> {
     let input = input.trim();
     if input.is_empty() {
         return Err(SubscriptionError::Empty.into());
@@ -86,15 +422,44 @@ pub fn compile_query(relational_db: &RelationalDB, tx: &MutTxId, input: &str) ->
     let mut queries = Vec::new();
     for q in compile_sql(relational_db, tx, input)? {
         match q {
-            CrudExpr::Query(x) => queries.push(x),
+            CrudExpr::Query(x) => {
+                // A subscription is evaluated incrementally over the rows that changed,
+                // so it can't keep the rows sorted, count them for a limit or aggregate them,
+                // nor tell when a row of an outer join stops matching any row of the other side,
+                // or when a row stops being a duplicate, or stops matching the rows of another query.
+                // The rows sent must also be rows of the table, not columns computed from them.
+                if let Some(op) = x.query.iter().find(|q| {
+                    matches!(
+                        q,
+                        expr::Query::Sort(_)
+                            | expr::Query::Limit(_)
+                            | expr::Query::Aggregate(_)
+                            | expr::Query::JoinOuter(_)
+                            | expr::Query::Distinct
+                            | expr::Query::SetOp(_)
+                            | expr::Query::SemiJoin(_)
+                            | expr::Query::Compute(_)
+                    )
+                }) {
+                    return Err(SubscriptionError::Unsupported(format!("`{op}`")).into());
+                }
+                // Checks the join can be maintained, see [IncrementalJoin].
+                IncrementalJoin::new(&x)?;
+                queries.push(x)
+            }
             CrudExpr::Insert { .. } => {
                 return Err(SubscriptionError::SideEffect(Crud::Insert).into());
             }
             CrudExpr::Update { .. } => return Err(SubscriptionError::SideEffect(Crud::Update).into()),
             CrudExpr::Delete { .. } => return Err(SubscriptionError::SideEffect(Crud::Delete).into()),
+            CrudExpr::Explain { .. } => return Err(SubscriptionError::Unsupported("`EXPLAIN`".into()).into()),
             CrudExpr::CreateTable { .. } => {
                 return Err(SubscriptionError::SideEffect(Crud::Create(DbType::Table)).into())
             }
+            CrudExpr::CreateIndex { .. } => {
+                return Err(SubscriptionError::SideEffect(Crud::Create(DbType::Index)).into())
+            }
+            CrudExpr::AlterTable { .. } => return Err(SubscriptionError::SideEffect(Crud::Alter(DbType::Table)).into()),
             CrudExpr::Drop { kind, .. } => return Err(SubscriptionError::SideEffect(Crud::Drop(kind)).into()),
         }
     }
@@ -112,10 +477,11 @@ mod tests {
     use crate::db::relational_db::tests_utils::make_test_db;
     use crate::host::module_host::{DatabaseTableUpdate, DatabaseUpdate, TableOp};
     use crate::sql::execute::run;
-    use crate::subscription::subscription::QuerySet;
+    use crate::subscription::subscription::{merge_updates, QuerySet, RowCounts};
     use crate::vm::tests::create_table_from_program;
     use crate::vm::DbProgram;
     use itertools::Itertools;
+    use proptest::prelude::*;
     use spacetimedb_lib::auth::{StAccess, StTableType};
     use spacetimedb_lib::data_key::ToDataKey;
     use spacetimedb_lib::error::ResultTest;
@@ -124,6 +490,7 @@ mod tests {
     use spacetimedb_sats::{product, BuiltinType, ProductType};
     use spacetimedb_vm::dsl::{db_table, mem_table, scalar};
     use spacetimedb_vm::operator::OpCmp;
+    use std::collections::BTreeSet;
 
     #[test]
     fn test_subscribe() -> ResultTest<()> {
@@ -131,7 +498,7 @@ mod tests {
 
         let mut tx = db.begin_tx();
 
-        let p = &mut DbProgram::new(&db, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&db, (&mut tx).into(), AuthCtx::for_testing());
 
         let head = ProductType::from_iter([("inventory_id", BuiltinType::U64), ("name", BuiltinType::String)]);
 
@@ -162,7 +529,7 @@ mod tests {
         let q = QueryExpr::new(db_table((&schema).into(), "inventory", table_id)).with_project(fields);
 
         let q = to_mem_table(q, &data);
-        let result = run_query(&db, &mut tx, &q, AuthCtx::for_testing())?;
+        let result = run_query(&db, (&mut tx).into(), &q, AuthCtx::for_testing())?;
 
         assert_eq!(
             Some(table.as_without_table_name()),
@@ -180,7 +547,7 @@ mod tests {
             .with_project(fields);
 
         let q = to_mem_table(q, &data);
-        let result = run_query(&db, &mut tx, &q, AuthCtx::for_testing())?;
+        let result = run_query(&db, (&mut tx).into(), &q, AuthCtx::for_testing())?;
 
         let table = mem_table(head, vec![product!(1u64, "health")]);
         assert_eq!(
@@ -197,7 +564,7 @@ mod tests {
         let (db, _tmp_dir) = make_test_db()?;
 
         let mut tx = db.begin_tx();
-        let p = &mut DbProgram::new(&db, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&db, (&mut tx).into(), AuthCtx::for_testing());
 
         let head = ProductType::from_iter([("inventory_id", BuiltinType::U64), ("name", BuiltinType::String)]);
 
@@ -232,7 +599,7 @@ mod tests {
 
         let q = to_mem_table(q, &data);
 
-        let result = run_query(&db, &mut tx, &q, AuthCtx::for_testing())?;
+        let result = run_query(&db, (&mut tx).into(), &q, AuthCtx::for_testing())?;
 
         assert_eq!(
             Some(table.as_without_table_name()),
@@ -278,7 +645,9 @@ mod tests {
             tables: vec![data.clone()],
         };
 
-        let result = s.eval_incr(&db, &mut tx, &update, AuthCtx::for_testing())?;
+        let mut rows = RowCounts::new();
+        s.eval(&db, (&mut tx).into(), AuthCtx::for_testing(), &mut rows)?;
+        let result = s.eval_incr(&db, (&mut tx).into(), &update, AuthCtx::for_testing(), &mut rows)?;
         assert_eq!(result.tables.len(), 3, "Must return 3 tables");
         assert_eq!(
             result.tables.iter().map(|x| x.ops.len()).sum::<usize>(),
@@ -293,7 +662,7 @@ mod tests {
         //Try access the private table
         match run_query(
             &db,
-            &mut tx,
+            (&mut tx).into(),
             &q,
             AuthCtx::new(Identity::__dummy(), Identity::from_byte_array([1u8; 32])),
         ) {
@@ -321,7 +690,7 @@ mod tests {
         let (db, _tmp_dir) = make_test_db()?;
 
         let mut tx = db.begin_tx();
-        let p = &mut DbProgram::new(&db, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&db, (&mut tx).into(), AuthCtx::for_testing());
 
         let head = ProductType::from_iter([("inventory_id", BuiltinType::U64), ("name", BuiltinType::String)]);
         let row = product!(1u64, "health");
@@ -347,7 +716,7 @@ mod tests {
             },
         ]);
 
-        let result = s.eval(&db, &mut tx, AuthCtx::for_testing())?;
+        let result = s.eval(&db, (&mut tx).into(), AuthCtx::for_testing(), &mut RowCounts::new())?;
         assert_eq!(result.tables.len(), 3, "Must return 3 tables");
         assert_eq!(
             result.tables.iter().map(|x| x.ops.len()).sum::<usize>(),
@@ -364,7 +733,7 @@ mod tests {
         let (db, _tmp_dir) = make_test_db()?;
 
         let mut tx = db.begin_tx();
-        let p = &mut DbProgram::new(&db, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&db, (&mut tx).into(), AuthCtx::for_testing());
 
         let head = ProductType::from_iter([("inventory_id", BuiltinType::U64), ("name", BuiltinType::String)]);
         let row = product!(1u64, "health");
@@ -410,7 +779,9 @@ mod tests {
 
         let update = DatabaseUpdate { tables: vec![data] };
 
-        let result = s.eval_incr(&db, &mut tx, &update, AuthCtx::for_testing())?;
+        let mut rows = RowCounts::new();
+        s.eval(&db, (&mut tx).into(), AuthCtx::for_testing(), &mut rows)?;
+        let result = s.eval_incr(&db, (&mut tx).into(), &update, AuthCtx::for_testing(), &mut rows)?;
         assert_eq!(result.tables.len(), 3, "Must return 3 tables");
         assert_eq!(
             result.tables.iter().map(|x| x.ops.len()).sum::<usize>(),
@@ -435,7 +806,7 @@ mod tests {
     fn test_subscribe_commutative() -> ResultTest<()> {
         let (db, _tmp_dir) = make_test_db()?;
         let mut tx = db.begin_tx();
-        let p = &mut DbProgram::new(&db, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&db, (&mut tx).into(), AuthCtx::for_testing());
 
         let head_1 = ProductType::from_iter([("inventory_id", BuiltinType::U64), ("name", BuiltinType::String)]);
         let row_1 = product!(1u64, "health");
@@ -461,11 +832,11 @@ mod tests {
             },
         ]);
 
-        let result_1 = s.eval(&db, &mut tx, AuthCtx::for_testing())?;
+        let result_1 = s.eval(&db, (&mut tx).into(), AuthCtx::for_testing(), &mut RowCounts::new())?;
 
         let s = QuerySet(vec![Query { queries: vec![q_2] }, Query { queries: vec![q_1] }]);
 
-        let result_2 = s.eval(&db, &mut tx, AuthCtx::for_testing())?;
+        let result_2 = s.eval(&db, (&mut tx).into(), AuthCtx::for_testing(), &mut RowCounts::new())?;
         let to_row = |of: DatabaseUpdate| {
             of.tables
                 .iter()
@@ -486,7 +857,7 @@ mod tests {
 
         let sql_create = "CREATE TABLE MobileEntityState (entity_id BIGINT UNSIGNED, location_x INTEGER, location_z INTEGER, destination_x INTEGER, destination_z INTEGER, is_running BOOLEAN, timestamp  BIGINT UNSIGNED, dimension INTEGER UNSIGNED);\
         CREATE TABLE EnemyState (entity_id BIGINT UNSIGNED, herd_id INTEGER, status INTEGER, type INTEGER, direction INTEGER);";
-        run(&db, &mut tx, sql_create, AuthCtx::for_testing())?;
+        run(&db, (&mut tx).into(), sql_create, AuthCtx::for_testing())?;
 
         let sql_create = "\
         insert into MobileEntityState (entity_id, location_x, location_z, destination_x, destination_z, is_running, timestamp, dimension) values (1, 96001, 96001, 96001, 1867045146, false, 17167179743690094247, 3926297397);\
@@ -494,18 +865,374 @@ mod tests {
         
         insert into EnemyState (entity_id, herd_id, status, type, direction) values (1, 1181485940, 1633678837, 1158301365, 132191327);
         insert into EnemyState (entity_id, herd_id, status, type, direction) values (2, 2017368418, 194072456, 34423057, 1296770410);";
-        run(&db, &mut tx, sql_create, AuthCtx::for_testing())?;
+        run(&db, (&mut tx).into(), sql_create, AuthCtx::for_testing())?;
 
         let sql_query = "SELECT * FROM MobileEntityState JOIN EnemyState ON MobileEntityState.entity_id = EnemyState.entity_id WHERE location_x > 96000 AND MobileEntityState.location_x < 192000 AND MobileEntityState.location_z > 96000 AND MobileEntityState.location_z < 192000";
-        let q = compile_query(&db, &tx, sql_query)?;
+        let q = compile_query(&db, &(&mut tx).into(), sql_query)?;
 
         for q in q.queries {
             assert_eq!(
-                run_query(&db, &mut tx, &q, AuthCtx::for_testing())?.len(),
+                run_query(&db, (&mut tx).into(), &q, AuthCtx::for_testing())?.len(),
                 1,
                 "Not return results"
             );
         }
         Ok(())
     }
+
+    #[test]
+    fn test_subscribe_order_by_limit() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        let mut tx = db.begin_tx();
+
+        let head = ProductType::from_iter([("inventory_id", BuiltinType::U64), ("name", BuiltinType::String)]);
+        let p = &mut DbProgram::new(&db, (&mut tx).into(), AuthCtx::for_testing());
+        create_table_from_program(p, "inventory", head, &[product!(1u64, "health")])?;
+        let head = ProductType::from_iter([("player_id", BuiltinType::U64), ("inventory_id", BuiltinType::U64)]);
+        create_table_from_program(p, "player", head, &[product!(1u64, 1u64)])?;
+
+        for sql in [
+            "SELECT * FROM inventory ORDER BY inventory_id",
+            "SELECT * FROM inventory LIMIT 1",
+            "SELECT COUNT(*) FROM inventory",
+            "SELECT inventory.* FROM inventory LEFT JOIN player ON inventory.inventory_id = player.inventory_id",
+            "SELECT inventory.name, player.player_id FROM inventory JOIN player ON inventory.inventory_id = player.inventory_id",
+        ] {
+            let result = compile_query(&db, &(&mut tx).into(), sql);
+            assert!(
+                matches!(result, Err(DBError::Subscription(SubscriptionError::Unsupported(_)))),
+                "{sql}"
+            );
+        }
+        db.rollback_tx(tx);
+
+        Ok(())
+    }
+
+    const CREATE_ITEM_INVENTORY: &str = "\
+    CREATE TABLE Item (id BIGINT UNSIGNED, kind BIGINT UNSIGNED, serial BIGINT UNSIGNED);\
+    CREATE TABLE Inventory (item_id BIGINT UNSIGNED, amount BIGINT UNSIGNED, serial BIGINT UNSIGNED);";
+
+    fn compile_set(db: &RelationalDB, sql: &str) -> ResultTest<QuerySet> {
+        let tx = db.begin_read_tx();
+        let query = compile_query(db, &(&tx).into(), sql);
+        db.release_tx(tx);
+        Ok(QuerySet(vec![query?]))
+    }
+
+    /// Runs `sql` in its own transaction, and returns the changes it committed.
+    fn commit_sql(db: &RelationalDB, sql: &str) -> ResultTest<DatabaseUpdate> {
+        let mut tx = db.begin_tx();
+        run(db, (&mut tx).into(), sql, AuthCtx::for_testing())?;
+        Ok(match db.commit_tx(tx)? {
+            Some((tx_data, _)) => DatabaseUpdate::from_writes(db, &tx_data),
+            None => DatabaseUpdate { tables: vec![] },
+        })
+    }
+
+    fn eval_incr(
+        db: &RelationalDB,
+        s: &QuerySet,
+        update: &DatabaseUpdate,
+        rows: &mut RowCounts,
+    ) -> ResultTest<Vec<(u8, ProductValue)>> {
+        let tx = db.begin_read_tx();
+        let result = s.eval_incr(db, (&tx).into(), update, AuthCtx::for_testing(), rows);
+        db.release_tx(tx);
+        Ok(result?
+            .tables
+            .into_iter()
+            .flat_map(|x| x.ops)
+            .map(|x| (x.op_type, x.row))
+            .collect())
+    }
+
+    // Check that a join is updated when only the rows of the table it doesn't yield change,
+    // and that a row is only deleted once it matches no row of the other table.
+    #[test]
+    fn test_subscribe_join_incr() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        commit_sql(&db, CREATE_ITEM_INVENTORY)?;
+        commit_sql(&db, "INSERT INTO Item (id, kind, serial) VALUES (1, 10, 1), (2, 20, 2)")?;
+
+        let s = compile_set(
+            &db,
+            "SELECT Item.* FROM Item JOIN Inventory ON Item.id = Inventory.item_id",
+        )?;
+        let mut rows = RowCounts::new();
+        assert!(s
+            .eval(&db, (&db.begin_read_tx()).into(), AuthCtx::for_testing(), &mut rows)?
+            .tables
+            .iter()
+            .all(|x| x.ops.is_empty()));
+
+        let item = product!(1u64, 10u64, 1u64);
+        let update = commit_sql(
+            &db,
+            "INSERT INTO Inventory (item_id, amount, serial) VALUES (1, 5, 1), (1, 6, 2), (3, 7, 3)",
+        )?;
+        assert_eq!(eval_incr(&db, &s, &update, &mut rows)?, vec![(1, item.clone())]);
+
+        let update = commit_sql(&db, "DELETE FROM Inventory WHERE amount = 5")?;
+        assert_eq!(eval_incr(&db, &s, &update, &mut rows)?, vec![]);
+
+        // The item matches another row as it stops matching the last one.
+        let update = commit_sql(
+            &db,
+            "DELETE FROM Inventory WHERE amount = 6; INSERT INTO Inventory (item_id, amount, serial) VALUES (1, 8, 4)",
+        )?;
+        assert_eq!(eval_incr(&db, &s, &update, &mut rows)?, vec![]);
+
+        let update = commit_sql(&db, "DELETE FROM Inventory WHERE item_id = 1")?;
+        assert_eq!(eval_incr(&db, &s, &update, &mut rows)?, vec![(0, item)]);
+
+        // Both sides change at once.
+        let update = commit_sql(
+            &db,
+            "INSERT INTO Item (id, kind, serial) VALUES (3, 30, 3); DELETE FROM Item WHERE id = 2; \
+            INSERT INTO Inventory (item_id, amount, serial) VALUES (2, 9, 5)",
+        )?;
+        assert_eq!(
+            eval_incr(&db, &s, &update, &mut rows)?,
+            vec![(1, product!(3u64, 30u64, 3u64))]
+        );
+
+        Ok(())
+    }
+
+    // Check that the clients subscribed to a query are sent the rows of its single evaluation,
+    // merged with those of their other queries as if their whole set was evaluated.
+    #[test]
+    fn test_subscribe_shared_queries() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        commit_sql(&db, CREATE_ITEM_INVENTORY)?;
+
+        let compile = |sql| -> ResultTest<Query> { Ok(compile_set(&db, sql)?.0.remove(0)) };
+        let common = compile("SELECT * FROM Item WHERE kind = 1")?;
+        let client_1 = QuerySet(vec![common.clone(), compile("SELECT * FROM Item WHERE id = 1")?]);
+        let client_2 = QuerySet(vec![common.clone(), compile("SELECT * FROM Inventory")?]);
+
+        let update = commit_sql(
+            &db,
+            "INSERT INTO Item (id, kind, serial) VALUES (1, 1, 1), (1, 2, 2), (2, 1, 3); \
+            INSERT INTO Inventory (item_id, amount, serial) VALUES (1, 5, 1)",
+        )?;
+
+        let tx = db.begin_read_tx();
+        let auth = AuthCtx::for_testing();
+        let eval_incr = |query: &Query| query.eval_incr(&db, (&tx).into(), &update, auth);
+        let common = eval_incr(&common)?;
+        for client in [client_1, client_2] {
+            let merged = merge_updates(&mut RowCounts::new(), [&common, &eval_incr(&client.0[1])?]);
+            let expected = client.eval_incr(&db, (&tx).into(), &update, auth, &mut RowCounts::new())?;
+            let rows = |x: DatabaseUpdate| {
+                x.tables
+                    .into_iter()
+                    .flat_map(|x| x.ops.into_iter().map(move |op| (x.table_id, op.op_type, op.row)))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(rows(merged), rows(expected));
+        }
+        // A row found by several queries is sent once.
+        let merged = merge_updates(&mut RowCounts::new(), [&common, &common]);
+        assert_eq!(merged.tables.iter().map(|x| x.ops.len()).sum::<usize>(), 2);
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    // Check that only the rows of the tables which several queries of a client return rows of are counted,
+    // and that the rows of a table are counted once a query added later shares it.
+    #[test]
+    fn test_subscribe_counts_shared_tables() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        commit_sql(&db, CREATE_ITEM_INVENTORY)?;
+        commit_sql(
+            &db,
+            "INSERT INTO Item (id, kind, serial) VALUES (1, 1, 1), (2, 1, 2), (3, 2, 3); \
+            INSERT INTO Inventory (item_id, amount, serial) VALUES (1, 5, 1)",
+        )?;
+
+        let compile = |sql| -> ResultTest<Query> { Ok(compile_set(&db, sql)?.0.remove(0)) };
+        let kind = compile("SELECT * FROM Item WHERE kind = 1")?;
+        let id = compile("SELECT * FROM Item WHERE id = 1")?;
+        let s = QuerySet(vec![kind.clone(), compile("SELECT * FROM Inventory")?]);
+        let auth = AuthCtx::for_testing();
+        let tx = db.begin_read_tx();
+        let mut rows = RowCounts::new();
+        s.eval(&db, (&tx).into(), auth, &mut rows)?;
+        assert!(rows.is_empty());
+
+        // The rows of `Item` matched by `kind` are counted once `id` also returns rows of `Item`.
+        rows.add_query(&db, (&tx).into(), auth, &id, &s.0)?;
+        assert_eq!(rows.len(), 2);
+        let inserted = merge_updates(&mut rows, [&id.eval(&db, (&tx).into(), auth)?]);
+        assert_eq!(inserted.tables.iter().map(|x| x.ops.len()).sum::<usize>(), 0);
+        assert_eq!(rows.len(), 2);
+
+        let s = QuerySet(vec![kind, id]);
+        let update = commit_sql(&db, "DELETE FROM Item WHERE id = 1")?;
+        assert_eq!(
+            eval_incr(&db, &s, &update, &mut rows)?,
+            vec![(0, product!(1u64, 1u64, 1u64))]
+        );
+        assert_eq!(rows.len(), 1);
+
+        rows.remove_query(&s.0[..1])?;
+        assert!(rows.is_empty());
+        db.release_tx(tx);
+
+        Ok(())
+    }
+
+    // Check that a row is kept while another query of the client still matches it,
+    // and only deleted once none of them does.
+    #[test]
+    fn test_subscribe_overlapping_queries() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        commit_sql(&db, CREATE_ITEM_INVENTORY)?;
+        commit_sql(
+            &db,
+            "INSERT INTO Item (id, kind, serial) VALUES (1, 1, 1); \
+            INSERT INTO Inventory (item_id, amount, serial) VALUES (1, 5, 1)",
+        )?;
+
+        let compile = |sql| -> ResultTest<Query> { Ok(compile_set(&db, sql)?.0.remove(0)) };
+        let s = QuerySet(vec![
+            compile("SELECT Item.* FROM Item JOIN Inventory ON Item.id = Inventory.item_id")?,
+            compile("SELECT * FROM Item WHERE kind = 1")?,
+        ]);
+        let mut rows = RowCounts::new();
+        let tx = db.begin_read_tx();
+        let result = s.eval(&db, (&tx).into(), AuthCtx::for_testing(), &mut rows);
+        db.release_tx(tx);
+        assert_eq!(result?.tables.iter().map(|x| x.ops.len()).sum::<usize>(), 1);
+
+        let item = product!(1u64, 1u64, 1u64);
+        let update = commit_sql(&db, "DELETE FROM Inventory WHERE item_id = 1")?;
+        assert_eq!(eval_incr(&db, &s, &update, &mut rows)?, vec![]);
+
+        let update = commit_sql(&db, "INSERT INTO Inventory (item_id, amount, serial) VALUES (1, 6, 2)")?;
+        assert_eq!(eval_incr(&db, &s, &update, &mut rows)?, vec![]);
+
+        let update = commit_sql(&db, "DELETE FROM Item WHERE id = 1")?;
+        assert_eq!(eval_incr(&db, &s, &update, &mut rows)?, vec![(0, item)]);
+        assert!(rows.is_empty());
+
+        Ok(())
+    }
+
+    /// A change to the rows of `Item` or `Inventory`,
+    /// on join keys of a few values so that rows match many others.
+    #[derive(Debug, Clone)]
+    enum Change {
+        Insert { item: bool, key: u64, value: u64 },
+        Delete { item: bool, pos: usize },
+    }
+
+    fn change() -> impl Strategy<Value = Change> {
+        prop_oneof![
+            (any::<bool>(), 0..3u64, 0..3u64).prop_map(|(item, key, value)| Change::Insert { item, key, value }),
+            (any::<bool>(), any::<usize>()).prop_map(|(item, pos)| Change::Delete { item, pos }),
+        ]
+    }
+
+    fn subscribed_rows(update: DatabaseUpdate) -> BTreeSet<(u32, ProductValue)> {
+        update
+            .tables
+            .into_iter()
+            .flat_map(|x| x.ops.into_iter().map(move |op| (x.table_id, op.row)))
+            .collect()
+    }
+
+    /// Checks that the rows sent by [QuerySet::eval_incr] after each transaction of `txs`,
+    /// applied to the rows sent by [QuerySet::eval] before it, are the rows of [QuerySet::eval] after it.
+    fn check_join_incr(txs: Vec<Vec<Change>>) -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+        commit_sql(&db, CREATE_ITEM_INVENTORY)?;
+        let auth = AuthCtx::for_testing();
+
+        let tx = db.begin_read_tx();
+        let item_id = db.table_id_from_name_tx(&tx, "Item")?.unwrap();
+        let inventory_id = db.table_id_from_name_tx(&tx, "Inventory")?.unwrap();
+        db.release_tx(tx);
+
+        let mut sets = Vec::new();
+        let mut subscribed = Vec::new();
+        for sql in [
+            &["SELECT Item.* FROM Item JOIN Inventory ON Item.id = Inventory.item_id"][..],
+            &["SELECT Inventory.* FROM Item JOIN Inventory ON Item.id = Inventory.item_id WHERE Item.kind > 0"],
+            &["SELECT * FROM Item JOIN Inventory ON Item.id = Inventory.item_id WHERE Inventory.amount < 2"],
+            // Queries matching the same rows.
+            &[
+                "SELECT Item.* FROM Item JOIN Inventory ON Item.id = Inventory.item_id",
+                "SELECT * FROM Item WHERE kind = 1",
+            ],
+        ] {
+            let s = sql
+                .iter()
+                .map(|sql| Ok(compile_set(&db, sql)?.0.remove(0)))
+                .collect::<ResultTest<QuerySet>>()?;
+            let mut rows = RowCounts::new();
+            let tx = db.begin_read_tx();
+            subscribed.push((subscribed_rows(s.eval(&db, (&tx).into(), auth, &mut rows)?), rows));
+            db.release_tx(tx);
+            sets.push(s);
+        }
+
+        let mut serial = 0u64;
+        for changes in txs {
+            let mut tx = db.begin_tx();
+            for change in changes {
+                match change {
+                    Change::Insert { item, key, value } => {
+                        serial += 1;
+                        let table_id = if item { item_id } else { inventory_id };
+                        db.insert(&mut tx, table_id, product!(key, value, serial))?;
+                    }
+                    Change::Delete { item, pos } => {
+                        let table_id = if item { item_id } else { inventory_id };
+                        let rows: Vec<_> = db.iter(&tx, table_id)?.map(|x| x.view().clone()).collect();
+                        if !rows.is_empty() {
+                            db.delete_by_rel(&mut tx, table_id, vec![rows[pos % rows.len()].clone()])?;
+                        }
+                    }
+                }
+            }
+            let Some((tx_data, _)) = db.commit_tx(tx)? else {
+                continue;
+            };
+            let update = DatabaseUpdate::from_writes(&db, &tx_data);
+
+            let tx = db.begin_read_tx();
+            for (s, (rows, counts)) in sets.iter().zip(&mut subscribed) {
+                for table in s.eval_incr(&db, (&tx).into(), &update, auth, counts)?.tables {
+                    for op in table.ops {
+                        let row = (table.table_id, op.row);
+                        if op.op_type == 1 {
+                            assert!(rows.insert(row.clone()), "Inserts the subscribed row {row:?}");
+                        } else {
+                            assert!(rows.remove(&row), "Deletes the row {row:?}, that is not subscribed");
+                        }
+                    }
+                }
+                assert_eq!(
+                    *rows,
+                    subscribed_rows(s.eval(&db, (&tx).into(), auth, &mut RowCounts::new())?)
+                );
+            }
+            db.release_tx(tx);
+        }
+
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn prop_subscribe_join_incr_eq_eval(txs in prop::collection::vec(prop::collection::vec(change(), 1..8), 1..6)) {
+            check_join_incr(txs).unwrap();
+        }
+    }
 }
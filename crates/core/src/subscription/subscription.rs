@@ -1,19 +1,156 @@
 use spacetimedb_lib::identity::AuthCtx;
 use spacetimedb_sats::{AlgebraicValue, BuiltinValue};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use super::query::Query;
-use crate::db::datastore::locking_tx_datastore::MutTxId;
+use super::query::{IncrementalJoin, Query};
 use crate::error::DBError;
 use crate::subscription::query::{run_query, OP_TYPE_FIELD_NAME};
+use crate::vm::TxMode;
 use crate::{
     client::{ClientActorId, ClientConnectionSender},
     db::relational_db::RelationalDB,
     host::module_host::{DatabaseTableUpdate, DatabaseUpdate, TableOp},
 };
 
+/// The rows a client was sent, by table and primary key, with the number of its queries which match each of them.
+///
+/// Only a row which several of the queries may match needs counting, so once the queries are known,
+/// only the rows of the tables which more than one of them return rows of are counted.
+/// A row of any other table is matched by a single query, and is inserted and deleted as that query says.
+pub struct RowCounts {
+    /// The tables whose rows are counted, or `None` for every table.
+    tables: Option<HashSet<u32>>,
+    counts: HashMap<(u32, Vec<u8>), usize>,
+}
+
+impl Default for RowCounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RowCounts {
+    /// Counts the rows of every table, whichever queries match them.
+    pub fn new() -> Self {
+        Self {
+            tables: None,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Only counts the rows of the tables which more than one of the distinct `queries` return rows of.
+    pub fn for_queries<'a>(queries: impl IntoIterator<Item = &'a Query>) -> Result<Self, DBError> {
+        Ok(Self {
+            tables: Some(shared_tables(queries)?),
+            counts: HashMap::new(),
+        })
+    }
+
+    /// Like [RowCounts::for_queries] without any query yet, which are then added by [RowCounts::add_query].
+    pub fn for_no_queries() -> Self {
+        Self {
+            tables: Some(HashSet::new()),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// The number of rows counted.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    fn is_counted(&self, table_id: u32) -> bool {
+        self.tables.as_ref().map_or(true, |tables| tables.contains(&table_id))
+    }
+
+    /// Starts counting the rows of the tables which the new `query` returns rows of along with one of the `others`,
+    /// the queries the client already subscribed to, before the rows of `query` are merged.
+    ///
+    /// The rows of such a table were only matched by that other query so far, so they are counted from its evaluation.
+    pub fn add_query<'a>(
+        &mut self,
+        relational_db: &RelationalDB,
+        mut tx: TxMode,
+        auth: AuthCtx,
+        query: &Query,
+        others: impl IntoIterator<Item = &'a Query>,
+    ) -> Result<(), DBError> {
+        let Self {
+            tables: Some(tables),
+            counts,
+        } = self
+        else {
+            return Ok(());
+        };
+        let query_tables = query.table_ids()?;
+        let mut seen = Vec::new();
+        for other in others {
+            if other == query || seen.contains(&other) {
+                continue;
+            }
+            seen.push(other);
+
+            let shared = other
+                .table_ids()?
+                .intersection(&query_tables)
+                .filter(|table_id| !tables.contains(table_id))
+                .copied()
+                .collect::<HashSet<_>>();
+            if shared.is_empty() {
+                continue;
+            }
+            for table in other.eval(relational_db, tx.reborrow(), auth)?.tables {
+                if shared.contains(&table.table_id) {
+                    for op in table.ops {
+                        *counts.entry((table.table_id, op.row_pk)).or_default() += 1;
+                    }
+                }
+            }
+            tables.extend(shared);
+        }
+        Ok(())
+    }
+
+    /// Stops counting the rows of the tables which no more than one of the remaining `queries` return rows of,
+    /// once the rows of a removed query have been merged.
+    pub fn remove_query<'a>(&mut self, queries: impl IntoIterator<Item = &'a Query>) -> Result<(), DBError> {
+        if let Some(tables) = &mut self.tables {
+            *tables = shared_tables(queries)?;
+            self.counts.retain(|(table_id, _), _| tables.contains(table_id));
+        }
+        Ok(())
+    }
+}
+
+/// The tables which more than one of the distinct `queries` return rows of.
+fn shared_tables<'a>(queries: impl IntoIterator<Item = &'a Query>) -> Result<HashSet<u32>, DBError> {
+    let mut seen = Vec::new();
+    let mut returned = HashSet::new();
+    let mut shared = HashSet::new();
+    for query in queries {
+        if seen.contains(&query) {
+            continue;
+        }
+        seen.push(query);
+        for table_id in query.table_ids()? {
+            if !returned.insert(table_id) {
+                shared.insert(table_id);
+            }
+        }
+    }
+    Ok(shared)
+}
+
+/// Identifies a query among those a client subscribed to one by one, chosen by the client.
+pub type QueryId = u32;
+
+/// A query, with the clients subscribed to it.
 pub struct Subscription {
-    pub queries: QuerySet,
+    pub query: Query,
     pub subscribers: Vec<ClientConnectionSender>,
 }
 
@@ -49,69 +186,37 @@ impl Subscription {
 }
 
 impl QuerySet {
+    /// The queries of the set, each once, as a query subscribed to twice doesn't match its rows twice.
+    fn distinct(&self) -> impl Iterator<Item = &Query> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(|(i, query)| !self.0[..*i].contains(query))
+            .map(|(_, query)| query)
+    }
+
     /// Incremental evaluation of `rows` that matched the [Query] (aka subscriptions)
     ///
     /// This is equivalent to run a `trigger` on `INSERT/UPDATE/DELETE`, run the [Query] and see if the `row` is matched.
     ///
-    /// NOTE: The returned `rows` in [DatabaseUpdate] are **deduplicated** so if 2 queries match the same `row`, only one copy is returned.
+    /// The queries joining two tables are maintained as the rows of either side change, see [IncrementalJoin].
+    ///
+    /// NOTE: The returned `rows` in [DatabaseUpdate] are **deduplicated** so if 2 queries match the same `row`, only one copy is returned,
+    /// and a `row` is only deleted once none of the queries match it, as counted in `rows` by [QuerySet::eval].
     #[tracing::instrument(skip_all)]
     pub fn eval_incr(
         &self,
         relational_db: &RelationalDB,
-        tx: &mut MutTxId,
+        mut tx: TxMode,
         database_update: &DatabaseUpdate,
         auth: AuthCtx,
+        rows: &mut RowCounts,
     ) -> Result<DatabaseUpdate, DBError> {
-        let mut output = DatabaseUpdate { tables: vec![] };
-        let mut seen = HashSet::new();
-
-        for query in &self.0 {
-            for table in database_update.tables.iter().cloned() {
-                for q in query.queries_of_table_id(&table) {
-                    if let Some(result) = run_query(relational_db, tx, &q, auth)?
-                        .into_iter()
-                        .find(|x| !x.data.is_empty())
-                    {
-                        let pos_op_type = result.head.find_pos_by_name(OP_TYPE_FIELD_NAME).unwrap_or_else(|| {
-                            panic!(
-                                "failed to locate `{OP_TYPE_FIELD_NAME}` on `{}`. fields: {:?}",
-                                result.head.table_name,
-                                result.head.fields.iter().map(|x| &x.field).collect::<Vec<_>>()
-                            )
-                        });
-
-                        let mut table_row_operations = table.clone();
-                        table_row_operations.ops.clear();
-                        for mut row in result.data {
-                            //Hack: remove the hidden field OP_TYPE_FIELD_NAME. see `to_mem_table`
-                            // Needs to be done before calculating the PK.
-                            let op_type = if let AlgebraicValue::Builtin(BuiltinValue::U8(op)) =
-                                row.elements.remove(pos_op_type)
-                            {
-                                op
-                            } else {
-                                panic!("Fail to extract `{OP_TYPE_FIELD_NAME}` on `{}`", result.head.table_name)
-                            };
-
-                            let row_pk = RelationalDB::pk_for_row(&row);
-
-                            //Skip rows that are already resolved in a previous subscription...
-                            if seen.contains(&(table.table_id, row_pk)) {
-                                continue;
-                            }
-
-                            seen.insert((table.table_id, row_pk));
-
-                            let row_pk = row_pk.to_bytes();
-                            table_row_operations.ops.push(TableOp { op_type, row_pk, row });
-                        }
-                        output.tables.push(table_row_operations);
-                    }
-                }
-            }
+        let mut updates = Vec::with_capacity(self.0.len());
+        for query in self.distinct() {
+            updates.push(query.eval_incr(relational_db, tx.reborrow(), database_update, auth)?);
         }
-
-        Ok(output)
+        Ok(merge_updates(rows, &updates))
     }
 
     /// Direct execution of [Query] (aka subscriptions)
@@ -119,52 +224,195 @@ impl QuerySet {
     /// This is equivalent to run a direct query like `SELECT * FROM table` and get back all the `rows` that match it.
     ///
     /// NOTE: The returned `rows` in [DatabaseUpdate] are **deduplicated** so if 2 queries match the same `row`, only one copy is returned.
+    /// `rows` is reset to count the number of queries matching each of them, for [QuerySet::eval_incr],
+    /// only for the tables which more than one of the queries return rows of, see [RowCounts].
     ///
     /// This is a *major* difference with normal query execution, where is expected to return the full result set for each query.
     #[tracing::instrument(skip_all)]
     pub fn eval(
         &self,
         relational_db: &RelationalDB,
-        tx: &mut MutTxId,
+        mut tx: TxMode,
         auth: AuthCtx,
+        rows: &mut RowCounts,
     ) -> Result<DatabaseUpdate, DBError> {
+        *rows = RowCounts::for_queries(self.distinct())?;
+        let mut updates = Vec::with_capacity(self.0.len());
+        for query in self.distinct() {
+            updates.push(query.eval(relational_db, tx.reborrow(), auth)?);
+        }
+        Ok(merge_updates(rows, &updates))
+    }
+}
+
+impl Query {
+    /// Direct execution of the [Query], see [QuerySet::eval].
+    pub fn eval(&self, relational_db: &RelationalDB, mut tx: TxMode, auth: AuthCtx) -> Result<DatabaseUpdate, DBError> {
         let mut database_update: DatabaseUpdate = DatabaseUpdate { tables: vec![] };
         let mut seen = HashSet::new();
 
-        for query in &self.0 {
-            for q in &query.queries {
-                if let Some(t) = q.source.get_db_table() {
-                    for table in run_query(relational_db, tx, q, auth)? {
-                        {
-                            let mut table_row_operations = Vec::new();
-
-                            for row in table.data {
-                                let row_pk = RelationalDB::pk_for_row(&row);
-
-                                //Skip rows that are already resolved in a previous subscription...
-                                if seen.contains(&(t.table_id, row_pk)) {
-                                    continue;
-                                }
-                                seen.insert((t.table_id, row_pk));
-
-                                let row_pk = row_pk.to_bytes();
-                                table_row_operations.push(TableOp {
-                                    op_type: 1, // Insert
-                                    row_pk,
-                                    row,
-                                });
-                            }
-
-                            database_update.tables.push(DatabaseTableUpdate {
-                                table_id: t.table_id,
-                                table_name: t.head.table_name.clone(),
-                                ops: table_row_operations,
-                            });
+        for q in &self.queries {
+            let t = match IncrementalJoin::new(q)? {
+                Some(join) => Some(join.table()),
+                None => q.source.get_db_table(),
+            };
+            if let Some(t) = t {
+                for table in run_query(relational_db, tx.reborrow(), q, auth)? {
+                    let mut table_row_operations = Vec::new();
+
+                    for row in table.data {
+                        let row_pk = RelationalDB::pk_for_row(&row);
+
+                        //Skip rows that are already resolved in a previous subscription...
+                        if seen.contains(&(t.table_id, row_pk)) {
+                            continue;
                         }
+                        seen.insert((t.table_id, row_pk));
+
+                        let row_pk = row_pk.to_bytes();
+                        table_row_operations.push(TableOp {
+                            op_type: 1, // Insert
+                            row_pk,
+                            row,
+                        });
                     }
+
+                    database_update.tables.push(DatabaseTableUpdate {
+                        table_id: t.table_id,
+                        table_name: t.head.table_name.clone(),
+                        ops: table_row_operations,
+                    });
                 }
             }
         }
         Ok(database_update)
     }
+
+    /// Incremental evaluation of `rows` that matched the [Query], see [QuerySet::eval_incr].
+    ///
+    /// A query is evaluated apart from the other queries of a [QuerySet], so when many clients subscribe to it,
+    /// it is evaluated once for them all, and its rows are merged with those of the other queries of each client
+    /// by [merge_updates].
+    pub fn eval_incr(
+        &self,
+        relational_db: &RelationalDB,
+        mut tx: TxMode,
+        database_update: &DatabaseUpdate,
+        auth: AuthCtx,
+    ) -> Result<DatabaseUpdate, DBError> {
+        let mut output = DatabaseUpdate { tables: vec![] };
+        let mut seen = HashSet::new();
+
+        for table in database_update.tables.iter().cloned() {
+            for q in self.queries_of_table_id(&table) {
+                if let Some(result) = run_query(relational_db, tx.reborrow(), &q, auth)?
+                    .into_iter()
+                    .find(|x| !x.data.is_empty())
+                {
+                    let pos_op_type = result.head.find_pos_by_name(OP_TYPE_FIELD_NAME).unwrap_or_else(|| {
+                        panic!(
+                            "failed to locate `{OP_TYPE_FIELD_NAME}` on `{}`. fields: {:?}",
+                            result.head.table_name,
+                            result.head.fields.iter().map(|x| &x.field).collect::<Vec<_>>()
+                        )
+                    });
+
+                    let mut table_row_operations = table.clone();
+                    table_row_operations.ops.clear();
+                    for mut row in result.data {
+                        //Hack: remove the hidden field OP_TYPE_FIELD_NAME. see `to_mem_table`
+                        // Needs to be done before calculating the PK.
+                        let op_type =
+                            if let AlgebraicValue::Builtin(BuiltinValue::U8(op)) = row.elements.remove(pos_op_type) {
+                                op
+                            } else {
+                                panic!("Fail to extract `{OP_TYPE_FIELD_NAME}` on `{}`", result.head.table_name)
+                            };
+
+                        let row_pk = RelationalDB::pk_for_row(&row);
+
+                        if seen.contains(&(table.table_id, row_pk)) {
+                            continue;
+                        }
+
+                        seen.insert((table.table_id, row_pk));
+
+                        let row_pk = row_pk.to_bytes();
+                        table_row_operations.ops.push(TableOp { op_type, row_pk, row });
+                    }
+                    output.tables.push(table_row_operations);
+                }
+            }
+        }
+
+        for join in self.joins()? {
+            if let Some(mut table) = join.eval(relational_db, tx.reborrow(), database_update, auth)? {
+                table
+                    .ops
+                    .retain(|op| seen.insert((table.table_id, RelationalDB::pk_for_row(&op.row))));
+                output.tables.push(table);
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// Merges the `updates` of the queries of a client, in order, into the changes to the rows it sees.
+///
+/// A row is inserted when the first of the queries starts matching it, and deleted when the last one stops,
+/// as counted in `rows`, so a row stays while another query still matches it.
+/// The changed rows are **deduplicated**, each at the place of its first operation.
+/// The rows of the tables `rows` doesn't count are left as they are.
+pub fn merge_updates<'a>(
+    rows: &mut RowCounts,
+    updates: impl IntoIterator<Item = &'a DatabaseUpdate>,
+) -> DatabaseUpdate {
+    let tables: Vec<_> = updates.into_iter().flat_map(|x| &x.tables).collect();
+
+    // The counts of the changed rows before the updates.
+    let mut before = HashMap::new();
+    for table in &tables {
+        if !rows.is_counted(table.table_id) {
+            continue;
+        }
+        for op in &table.ops {
+            let key = (table.table_id, op.row_pk.clone());
+            let count = rows.counts.entry(key.clone()).or_default();
+            before.entry(key).or_insert(*count);
+            if op.op_type == 1 {
+                *count += 1;
+            } else {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+
+    let mut output = DatabaseUpdate { tables: vec![] };
+    for table in tables {
+        let mut table_row_operations = table.clone();
+        if !rows.is_counted(table.table_id) {
+            output.tables.push(table_row_operations);
+            continue;
+        }
+        table_row_operations.ops.retain_mut(|op| {
+            let key = (table.table_id, op.row_pk.clone());
+            let Some(before) = before.remove(&key) else {
+                return false;
+            };
+            let after = rows.counts[&key];
+            if after == 0 {
+                rows.counts.remove(&key);
+            }
+            op.op_type = match (before, after) {
+                (0, 0) => return false,
+                (0, _) => 1, // Insert
+                (_, 0) => 0, // Delete
+                _ => return false,
+            };
+            true
+        });
+        output.tables.push(table_row_operations);
+    }
+    output
 }
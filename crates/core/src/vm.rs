@@ -1,114 +1,558 @@
 //! The [DbProgram] that execute arbitrary queries & code against the database.
-use crate::db::cursor::{CatalogCursor, TableCursor};
-use crate::db::datastore::locking_tx_datastore::MutTxId;
+use crate::db::cursor::{CatalogCursor, IndexCursor, TableCursor};
+use crate::db::datastore::locking_tx_datastore::{IterByColRange, MutTxId, TxId};
 use crate::db::datastore::traits::{ColumnDef, IndexDef, IndexId, SequenceId, TableDef};
 use crate::db::relational_db::RelationalDB;
-use crate::error::DBError;
+use crate::error::{DBError, IndexError, TableError};
+use crate::sql::explain::QueryPlan;
+use itertools::Itertools;
 use spacetimedb_lib::auth::{StAccess, StTableType};
 use spacetimedb_lib::identity::AuthCtx;
-use spacetimedb_lib::relation::{FieldExpr, Relation};
+use spacetimedb_lib::relation::{DbTable, FieldExpr, FieldName, RelValueRef, Relation};
 use spacetimedb_lib::relation::{Header, MemTable, RelIter, RelValue, RowCount, Table};
 use spacetimedb_lib::table::ProductTypeMeta;
-use spacetimedb_sats::ProductValue;
+use spacetimedb_lib::IndexType;
+use spacetimedb_sats::{AlgebraicValue, ProductValue};
 use spacetimedb_vm::dsl::mem_table;
 use spacetimedb_vm::env::EnvDb;
 use spacetimedb_vm::errors::ErrorVm;
 use spacetimedb_vm::eval::IterRows;
 use spacetimedb_vm::expr::*;
 use spacetimedb_vm::program::{ProgramRef, ProgramVm};
-use spacetimedb_vm::rel_ops::RelOps;
+use spacetimedb_vm::rel_ops::{JoinKey, RelOps};
 use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+
+/// The transaction a [DbProgram] runs in.
+///
+/// Queries can be evaluated in either kind of transaction,
+/// but statements that modify the database need a mutable one.
+pub enum TxMode<'a> {
+    MutTx(&'a mut MutTxId),
+    Tx(&'a TxId),
+}
+
+impl TxMode<'_> {
+    /// Borrows the transaction again for a shorter lifetime,
+    /// so that it can be handed to several programs in turn.
+    pub fn reborrow(&mut self) -> TxMode<'_> {
+        match self {
+            TxMode::MutTx(tx) => TxMode::MutTx(tx),
+            TxMode::Tx(tx) => TxMode::Tx(tx),
+        }
+    }
+
+    /// The version of the schema as seen by the transaction.
+    pub fn schema_version(&self) -> u64 {
+        match self {
+            TxMode::MutTx(tx) => tx.schema_version(),
+            TxMode::Tx(tx) => tx.schema_version(),
+        }
+    }
+
+    fn mut_tx(&mut self) -> Result<&mut MutTxId, ErrorVm> {
+        match self {
+            TxMode::MutTx(tx) => Ok(tx),
+            TxMode::Tx(_) => Err(ErrorVm::Unsupported(
+                "Modifying the database in a read-only transaction".into(),
+            )),
+        }
+    }
+}
+
+impl<'a> From<&'a mut MutTxId> for TxMode<'a> {
+    fn from(tx: &'a mut MutTxId) -> Self {
+        TxMode::MutTx(tx)
+    }
+}
+
+impl<'a> From<&'a TxId> for TxMode<'a> {
+    fn from(tx: &'a TxId) -> Self {
+        TxMode::Tx(tx)
+    }
+}
 
 //TODO: This is partially duplicated from the `vm` crate to avoid borrow checker issues
 //and pull all that crate in core. Will be revisited after trait refactor
-pub fn build_query<'a>(
+pub fn build_query<'a>(stdb: &'a RelationalDB, tx: &'a TxMode, query: QueryCode) -> Result<Box<IterRows<'a>>, ErrorVm> {
+    build_query_with_plan(stdb, tx, query, None)
+}
+
+/// Like [build_query], and records the operations of the query in `plan`, when it is being explained.
+pub fn build_query_with_plan<'a>(
     stdb: &'a RelationalDB,
-    tx: &'a mut MutTxId,
+    tx: &'a TxMode,
     query: QueryCode,
+    mut plan: Option<&mut QueryPlan>,
 ) -> Result<Box<IterRows<'a>>, ErrorVm> {
     let q = match &query.table {
         Table::MemTable(x) => SourceExpr::MemTable(x.clone()),
         Table::DbTable(x) => SourceExpr::DbTable(x.clone()),
     };
 
-    //TODO HACK: Turn all inner tables in joins to in-memory to avoid borrow checker
     let mut query = query;
 
-    for q in &mut query.query {
-        if let Query::JoinInner(q) = q {
-            let table_access = q.rhs.table_access();
-            let rhs = get_table(stdb, tx, q.rhs.clone())?;
-            q.rhs = SourceExpr::MemTable(MemTable::new(&q.rhs.head(), table_access, &rhs.collect_vec()?));
+    let mut result = match index_source(stdb, tx, &mut query, &mut plan)? {
+        Some(result) => result,
+        None => {
+            let (operation, detail) = match &q {
+                SourceExpr::MemTable(x) => (
+                    "Memory Scan",
+                    describe(&plan, || format!("{} ({} rows)", x.head.table_name, x.data.len())),
+                ),
+                SourceExpr::DbTable(x) => ("Table Scan", describe(&plan, || x.head.table_name.clone())),
+            };
+            let result = get_table(stdb, tx, q)?;
+            record(&mut plan, operation, detail, None, result)
         }
-    }
-
-    let mut result = get_table(stdb, tx, q)?;
+    };
 
     for q in query.query {
         result = match q {
+            Query::IndexScan(scan) => {
+                let detail = describe(&plan, || scan.to_string());
+                let iter = result.select(move |row| Ok(scan.contains(row)));
+                record(&mut plan, "Filter", detail, None, Box::new(iter))
+            }
             Query::Select(cmp) => {
+                let detail = describe(&plan, || cmp.to_string());
                 let iter = result.select(move |row| cmp.compare(row));
-                Box::new(iter)
+                record(&mut plan, "Filter", detail, None, Box::new(iter))
             }
             Query::Project(cols) => {
                 if cols.is_empty() {
                     result
                 } else {
                     let iter = result.project(&cols.clone(), move |row| Ok(row.project(&cols)?))?;
-                    Box::new(iter)
+                    let detail = describe(&plan, || iter.head().fields.iter().map(|x| &x.field).join(", "));
+                    record(&mut plan, "Project", detail, None, Box::new(iter))
                 }
             }
+            Query::Compute(cols) => {
+                let detail = describe(&plan, || {
+                    cols.iter().map(|x| format!("{} AS {}", x.expr, x.field)).join(", ")
+                });
+                record(&mut plan, "Compute", detail, None, Box::new(result.compute(cols)))
+            }
             Query::JoinInner(q) => {
-                //Pick the smaller set to be at the left
-                let col_lhs = FieldExpr::Name(q.col_lhs);
-                let col_rhs = FieldExpr::Name(q.col_rhs);
-                let key_lhs = col_lhs.clone();
-                let key_rhs = col_rhs.clone();
-
-                let rhs = match q.rhs {
-                    SourceExpr::MemTable(x) => {
-                        Box::new(RelIter::new(x.head.clone(), x.row_count(), x)) as Box<IterRows<'_>>
+                let lhs = result;
+                match index_join(stdb, tx, &lhs.row_count(), &q)? {
+                    Some(join) => {
+                        let detail = describe(&plan, || join.describe(stdb, tx, &q));
+                        let iter = join.join(stdb, tx, lhs, q.col_lhs, q.col_rhs);
+                        record(&mut plan, "Index Join", detail, None, Box::new(iter))
                     }
-                    SourceExpr::DbTable(_) => {
-                        unreachable!()
+                    None => {
+                        let detail = describe(&plan, || format!("{} = {}", q.col_lhs, q.col_rhs));
+                        let mut join_plan = plan.as_deref().map(QueryPlan::sub_plan);
+                        let rhs = build_query_with_plan(stdb, tx, q.rhs.into(), join_plan.as_mut())?;
+
+                        let (key_lhs, key_rhs) = JoinKey::pair(lhs.head(), &q.col_lhs, rhs.head(), &q.col_rhs);
+                        let (cmp_lhs, cmp_rhs) = (key_lhs.clone(), key_rhs.clone());
+                        let iter = lhs.join_inner(
+                            rhs,
+                            move |row| Ok(key_lhs.get(row).into()),
+                            move |row| Ok(key_rhs.get(row).into()),
+                            move |lhs, rhs| Ok(cmp_lhs.get(lhs) == cmp_rhs.get(rhs)),
+                        )?;
+                        record(&mut plan, "Hash Join", detail, join_plan, Box::new(iter))
                     }
+                }
+            }
+            Query::JoinOuter(JoinOuterExpr { kind, join: q }) => {
+                let detail = describe(&plan, || format!("{} = {}", q.col_lhs, q.col_rhs));
+                let mut join_plan = plan.as_deref().map(QueryPlan::sub_plan);
+                let rhs = build_query_with_plan(stdb, tx, q.rhs.into(), join_plan.as_mut())?;
+
+                let (key_lhs, key_rhs) = JoinKey::pair(result.head(), &q.col_lhs, rhs.head(), &q.col_rhs);
+                let (cmp_lhs, cmp_rhs) = (key_lhs.clone(), key_rhs.clone());
+                let iter = result.join_outer(
+                    rhs,
+                    move |row| Ok(key_lhs.get(row).into()),
+                    move |row| Ok(key_rhs.get(row).into()),
+                    move |lhs, rhs| Ok(cmp_lhs.get(lhs) == cmp_rhs.get(rhs)),
+                    kind.keeps_lhs(),
+                    kind.keeps_rhs(),
+                )?;
+                let operation = match kind {
+                    OuterJoin::Left => "Hash Left Join",
+                    OuterJoin::Right => "Hash Right Join",
+                    OuterJoin::Full => "Hash Full Join",
                 };
-                let lhs = result;
-
-                let iter = lhs.join_inner(
+                record(&mut plan, operation, detail, join_plan, Box::new(iter))
+            }
+            Query::JoinCross(rhs) => {
+                let mut join_plan = plan.as_deref().map(QueryPlan::sub_plan);
+                let rhs = build_query_with_plan(stdb, tx, rhs.into(), join_plan.as_mut())?;
+                let iter = result.join_inner(
                     rhs,
-                    move |row| {
-                        let f = row.get(&key_lhs);
-                        Ok(f.into())
-                    },
-                    move |row| {
-                        let f = row.get(&key_rhs);
-                        Ok(f.into())
-                    },
-                    move |lhs, rhs| {
-                        let lhs = lhs.get(&col_lhs);
-                        let rhs = rhs.get(&col_rhs);
-                        Ok(lhs == rhs)
-                    },
+                    |_| Ok(ProductValue::new(&[])),
+                    |_| Ok(ProductValue::new(&[])),
+                    |_, _| Ok(true),
                 )?;
-                Box::new(iter)
+                record(&mut plan, "Cross Join", String::new(), join_plan, Box::new(iter))
+            }
+            Query::Sort(keys) => {
+                let detail = describe(&plan, || Query::Sort(keys.clone()).to_string());
+                let iter = result.sort_by(move |lhs, rhs| compare_rows(&keys, lhs, rhs));
+                record(&mut plan, "Sort", detail, None, Box::new(iter))
+            }
+            Query::Limit(limit) => {
+                let detail = describe(&plan, || Query::Limit(limit).to_string());
+                let LimitExpr { offset, limit } = limit;
+                record(&mut plan, "Limit", detail, None, Box::new(result.limit(offset, limit)))
+            }
+            Query::Aggregate(query) => {
+                let iter = result.aggregate(&query)?;
+                let detail = describe(&plan, || Query::Aggregate(query).to_string());
+                record(&mut plan, "Aggregate", detail, None, Box::new(iter))
+            }
+            Query::Distinct => record(&mut plan, "Distinct", String::new(), None, Box::new(result.distinct())),
+            Query::SetOp(SetOpExpr { op, all, rhs }) => {
+                let mut rhs_plan = plan.as_deref().map(QueryPlan::sub_plan);
+                let rhs = build_query_with_plan(stdb, tx, rhs.into(), rhs_plan.as_mut())?;
+                let iter = result.set_op(rhs, op, all)?;
+                let operation = match (op, all) {
+                    (SetOp::Union, false) => "Union",
+                    (SetOp::Union, true) => "Union All",
+                    (SetOp::Intersect, false) => "Intersect",
+                    (SetOp::Intersect, true) => "Intersect All",
+                    (SetOp::Except, false) => "Except",
+                    (SetOp::Except, true) => "Except All",
+                };
+                record(&mut plan, operation, String::new(), rhs_plan, Box::new(iter))
+            }
+            Query::SemiJoin(SemiJoinExpr { field, rhs, negated }) => {
+                let detail = describe(&plan, || match &field {
+                    Some(field) => format!("{field} {}IN subquery", if negated { "NOT " } else { "" }),
+                    None => format!("{}EXISTS subquery", if negated { "NOT " } else { "" }),
+                });
+                let mut rhs_plan = plan.as_deref().map(QueryPlan::sub_plan);
+                let rhs = build_query_with_plan(stdb, tx, rhs.into(), rhs_plan.as_mut())?;
+                let iter = result.semi_join(rhs, field.as_ref(), negated)?;
+                let operation = if negated { "Anti Join" } else { "Semi Join" };
+                record(&mut plan, operation, detail, rhs_plan, Box::new(iter))
             }
         };
     }
     Ok(result)
 }
 
-fn get_table<'a>(
+/// Returns the `detail` of an operation when the query is being explained,
+/// so it is only described then.
+fn describe(plan: &Option<&mut QueryPlan>, detail: impl FnOnce() -> String) -> String {
+    match plan {
+        Some(_) => detail(),
+        None => String::new(),
+    }
+}
+
+/// Records the `operation` that yields the rows of `iter` in `plan`, when the query is being explained.
+fn record<'a>(
+    plan: &mut Option<&mut QueryPlan>,
+    operation: &'static str,
+    detail: String,
+    children: Option<QueryPlan>,
+    iter: Box<IterRows<'a>>,
+) -> Box<IterRows<'a>> {
+    match plan {
+        Some(plan) => plan.step(operation, detail, children, iter),
+        None => iter,
+    }
+}
+
+/// Returns the name of the index of `index_type` on the leading column `col_id` of the table `table_id`.
+fn index_name(
+    stdb: &RelationalDB,
+    tx: &TxMode,
+    table_id: u32,
+    col_id: u32,
+    index_type: IndexType,
+) -> Result<String, DBError> {
+    let schema = match tx {
+        TxMode::MutTx(tx) => stdb.schema_for_table(tx, table_id)?,
+        TxMode::Tx(tx) => stdb.schema_for_table_tx(tx, table_id)?,
+    };
+    Ok(schema
+        .indexes
+        .iter()
+        .find(|x| x.index_type == index_type && x.cols.first() == Some(&col_id))
+        .map(|x| x.index_name.clone())
+        .unwrap_or_default())
+}
+
+/// Returns the rows of the source table of `query` from an index, when it can serve the first operations of `query`,
+/// and removes those operations from `query`.
+///
+/// A leading [Query::IndexScan] is served by seeking an index on its column.
+/// A [Query::Sort] in ascending order by a single column of the source table is served by the order of a btree index
+/// on the column, when only selections and joins, which keep the order of the source rows, come before the sort,
+/// and there's either no index scan, or it is on the same column.
+/// The sort is not served when `tx` has modified the table,
+/// as the index yields the rows inserted by `tx` apart from the committed ones.
+fn index_source<'a>(
     stdb: &'a RelationalDB,
-    tx: &'a mut MutTxId,
-    query: SourceExpr,
-) -> Result<Box<dyn RelOps + 'a>, ErrorVm> {
+    tx: &'a TxMode,
+    query: &mut QueryCode,
+    plan: &mut Option<&mut QueryPlan>,
+) -> Result<Option<Box<IterRows<'a>>>, ErrorVm> {
+    let Table::DbTable(table) = &query.table else {
+        return Ok(None);
+    };
+    let scan = match query.query.first() {
+        Some(Query::IndexScan(scan)) => Some(scan.clone()),
+        _ => None,
+    };
+    let start = usize::from(scan.is_some());
+    let sort = query.query[start..]
+        .iter()
+        .position(|q| !matches!(q, Query::Select(_) | Query::JoinInner(_)))
+        .and_then(|pos| match &query.query[start + pos] {
+            Query::Sort(keys) => match keys.as_slice() {
+                [SortKey {
+                    field: FieldExpr::Name(field),
+                    asc: true,
+                }] => Some((start + pos, field.clone())),
+                _ => None,
+            },
+            _ => None,
+        });
+
+    let (field, range) = match (&scan, &sort) {
+        (Some(scan), _) => (&scan.field, scan.range()),
+        (None, Some((_, field))) => (field, (Bound::Unbounded, Bound::Unbounded)),
+        (None, None) => return Ok(None),
+    };
+    if field.table() != table.head.table_name {
+        return Ok(None);
+    }
+    let Some(col_id) = table.head.column_pos(field) else {
+        return Ok(None);
+    };
+
+    // A hash index can only serve a scan for a single value, so it's only used when there's no btree index.
+    let schema = match tx {
+        TxMode::MutTx(tx) => stdb.schema_for_table(tx, table.table_id)?,
+        TxMode::Tx(tx) => stdb.schema_for_table_tx(tx, table.table_id)?,
+    };
+    let col = col_id as u32;
+    let mut indexes = schema.indexes.iter();
+    if !indexes
+        .clone()
+        .any(|x| x.index_type == IndexType::BTree && x.cols.first() == Some(&col))
+        && indexes.any(|x| x.index_type == IndexType::Hash && x.cols == [col])
+    {
+        let Some(value) = scan.as_ref().and_then(|scan| scan.as_eq()) else {
+            return Ok(None);
+        };
+        let iter = match tx {
+            TxMode::MutTx(tx) => stdb.iter_by_col_eq(tx, table.table_id, col, value)?,
+            TxMode::Tx(tx) => stdb.iter_by_col_eq_tx(tx, table.table_id, col, value)?,
+        };
+        let rows: Vec<_> = iter.map(|row| row.view().clone()).collect();
+        let head = table.head.clone();
+        let rows = MemTable::new(&head, table.table_access, &rows);
+        query.query.remove(0);
+        let iter = Box::new(RelIter::new(head, rows.row_count(), rows));
+        let detail = match plan {
+            Some(_) => {
+                let name = index_name(stdb, tx, table.table_id, col, IndexType::Hash)?;
+                format!(
+                    "{} using hash index `{name}`: {}",
+                    table.head.table_name,
+                    scan_detail(&scan)
+                )
+            }
+            None => String::new(),
+        };
+        return Ok(Some(record(plan, "Index Scan", detail, None, iter)));
+    }
+
+    let iter = match tx {
+        TxMode::MutTx(tx) => stdb.iter_by_col_range(tx, table.table_id, col, range)?,
+        TxMode::Tx(tx) => stdb.iter_by_col_range_tx(tx, table.table_id, col, range)?,
+    };
+
+    let ordered = matches!(iter, IterByColRange::CommittedIndex(_));
+    let indexed = !matches!(iter, IterByColRange::Scan(_));
+    let sorted = match sort {
+        Some((pos, sort_field)) if ordered && &sort_field == field => {
+            query.query.remove(pos);
+            true
+        }
+        // Without an index scan, the index only serves to sort the rows.
+        _ if scan.is_none() => return Ok(None),
+        _ => false,
+    };
+    if scan.is_some() {
+        query.query.remove(0);
+    }
+    let iter = Box::new(IndexCursor::new(table.clone(), iter)?);
+
+    // Without an index on the column, the rows are scanned and filtered by the bounds.
+    let (operation, detail) = match plan {
+        Some(_) if indexed => {
+            let name = index_name(stdb, tx, table.table_id, col, IndexType::BTree)?;
+            let mut detail = format!("{} using btree index `{name}`", table.head.table_name);
+            if scan.is_some() {
+                detail = format!("{detail}: {}", scan_detail(&scan));
+            }
+            if sorted {
+                detail.push_str(", in index order");
+            }
+            ("Index Scan", detail)
+        }
+        Some(_) => (
+            "Table Scan",
+            format!("{}: {}", table.head.table_name, scan_detail(&scan)),
+        ),
+        None => ("Index Scan", String::new()),
+    };
+    Ok(Some(record(plan, operation, detail, None, iter)))
+}
+
+/// A join that seeks an index on the join column of its right table for each row of the left,
+/// and filters the rows found by the selections on the right.
+struct IndexJoin {
+    table: DbTable,
+    col_id: u32,
+    index_type: IndexType,
+    filters: Vec<Query>,
+}
+
+/// Returns the [IndexJoin] for `join`, when it's expected to read fewer rows than a hash join.
+///
+/// That's when the right of `join` is a table with an index on the join column, only filtered by selections,
+/// and the left, estimated by `lhs`, isn't known to have more rows than the table,
+/// as each row of the left is a seek, and the hash join reads every row of the table instead.
+fn index_join(stdb: &RelationalDB, tx: &TxMode, lhs: &RowCount, join: &JoinExpr) -> Result<Option<IndexJoin>, ErrorVm> {
+    let SourceExpr::DbTable(table) = &join.rhs.source else {
+        return Ok(None);
+    };
+    if !join
+        .rhs
+        .query
+        .iter()
+        .all(|q| matches!(q, Query::Select(_) | Query::IndexScan(_)))
+    {
+        return Ok(None);
+    }
+    if join.col_rhs.table() != table.head.table_name {
+        return Ok(None);
+    }
+    let Some(col_id) = table.head.column_pos(&join.col_rhs) else {
+        return Ok(None);
+    };
+    let col_id = col_id as u32;
+
+    let schema = match tx {
+        TxMode::MutTx(tx) => stdb.schema_for_table(tx, table.table_id)?,
+        TxMode::Tx(tx) => stdb.schema_for_table_tx(tx, table.table_id)?,
+    };
+    let mut indexes = schema.indexes.iter();
+    let index_type = if indexes
+        .clone()
+        .any(|x| x.index_type == IndexType::BTree && x.cols.first() == Some(&col_id))
+    {
+        IndexType::BTree
+    } else if indexes.any(|x| x.index_type == IndexType::Hash && x.cols == [col_id]) {
+        IndexType::Hash
+    } else {
+        return Ok(None);
+    };
+
+    let rows = match tx {
+        TxMode::MutTx(tx) => stdb.iter(tx, table.table_id)?.size_hint().1,
+        TxMode::Tx(tx) => stdb.iter_tx(tx, table.table_id)?.size_hint().1,
+    };
+    if matches!(rows, Some(rows) if lhs.min > rows) {
+        return Ok(None);
+    }
+
+    Ok(Some(IndexJoin {
+        table: table.clone(),
+        col_id,
+        index_type,
+        filters: join.rhs.query.clone(),
+    }))
+}
+
+impl IndexJoin {
+    /// Joins the rows of `lhs` by their `col_lhs` with the rows of the table.
+    fn join<'a>(
+        self,
+        stdb: &'a RelationalDB,
+        tx: &'a TxMode,
+        lhs: Box<IterRows<'a>>,
+        col_lhs: FieldName,
+        col_rhs: FieldName,
+    ) -> impl RelOps + 'a {
+        let Self {
+            table, col_id, filters, ..
+        } = self;
+        let (key_lhs, _) = JoinKey::pair(lhs.head(), &col_lhs, &table.head, &col_rhs);
+        let head = table.head.clone();
+        lhs.join_index(&table.head, move |row| {
+            let value = key_lhs.get(row);
+            let rows = match tx {
+                TxMode::MutTx(tx) => stdb
+                    .iter_by_col_eq(tx, table.table_id, col_id, value)?
+                    .map(|row| row.view().clone())
+                    .collect::<Vec<_>>(),
+                TxMode::Tx(tx) => stdb
+                    .iter_by_col_eq_tx(tx, table.table_id, col_id, value)?
+                    .map(|row| row.view().clone())
+                    .collect(),
+            };
+            let mut matches = Vec::with_capacity(rows.len());
+            'rows: for row in rows {
+                let row = RelValue::new(&head, &row);
+                for filter in &filters {
+                    let keep = match filter {
+                        Query::Select(cmp) => cmp.compare(row.as_val_ref())?,
+                        Query::IndexScan(scan) => scan.contains(row.as_val_ref()),
+                        _ => true,
+                    };
+                    if !keep {
+                        continue 'rows;
+                    }
+                }
+                matches.push(row);
+            }
+            Ok(matches)
+        })
+    }
+
+    fn describe(&self, stdb: &RelationalDB, tx: &TxMode, join: &JoinExpr) -> String {
+        let name = index_name(stdb, tx, self.table.table_id, self.col_id, self.index_type).unwrap_or_default();
+        let kind = match self.index_type {
+            IndexType::BTree => "btree",
+            IndexType::Hash => "hash",
+        };
+        let mut detail = format!("{} = {} using {kind} index `{name}`", join.col_lhs, join.col_rhs);
+        if !self.filters.is_empty() {
+            detail.push_str(": ");
+            detail.push_str(&self.filters.iter().join(" AND "));
+        }
+        detail
+    }
+}
+
+/// Returns the description of the bounds of `scan`.
+fn scan_detail(scan: &Option<IndexScan>) -> String {
+    scan.as_ref().map(|x| x.to_string()).unwrap_or_default()
+}
+
+fn get_table<'a>(stdb: &'a RelationalDB, tx: &'a TxMode, query: SourceExpr) -> Result<Box<dyn RelOps + 'a>, ErrorVm> {
     let head = query.head();
     let row_count = query.row_count();
     Ok(match query {
         SourceExpr::MemTable(x) => Box::new(RelIter::new(head, row_count, x)) as Box<IterRows<'_>>,
         SourceExpr::DbTable(x) => {
-            let iter = stdb.iter(tx, x.table_id)?;
+            let iter = match tx {
+                TxMode::MutTx(tx) => stdb.iter(tx, x.table_id)?,
+                TxMode::Tx(tx) => stdb.iter_tx(tx, x.table_id)?,
+            };
             Box::new(TableCursor::new(x, iter)?) as Box<IterRows<'_>>
         }
     })
@@ -120,12 +564,12 @@ pub struct DbProgram<'db, 'tx> {
     pub(crate) env: EnvDb,
     pub(crate) stats: HashMap<String, u64>,
     pub(crate) db: &'db RelationalDB,
-    pub(crate) tx: &'tx mut MutTxId,
+    pub(crate) tx: TxMode<'tx>,
     pub(crate) auth: AuthCtx,
 }
 
 impl<'db, 'tx> DbProgram<'db, 'tx> {
-    pub fn new(db: &'db RelationalDB, tx: &'tx mut MutTxId, auth: AuthCtx) -> Self {
+    pub fn new(db: &'db RelationalDB, tx: TxMode<'tx>, auth: AuthCtx) -> Self {
         let mut env = EnvDb::new();
         Self::load_ops(&mut env);
         Self {
@@ -140,51 +584,163 @@ impl<'db, 'tx> DbProgram<'db, 'tx> {
     fn _eval_query(&mut self, query: QueryCode) -> Result<Code, ErrorVm> {
         let table_access = query.table.table_access();
 
-        let result = build_query(self.db, self.tx, query)?;
+        let result = build_query(self.db, &self.tx, query)?;
         let head = result.head().clone();
         let rows: Vec<_> = result.collect_vec()?;
 
         Ok(Code::Table(MemTable::new(&head, table_access, &rows)))
     }
 
-    fn _execute_insert(&mut self, table: &Table, rows: Vec<ProductValue>) -> Result<Code, ErrorVm> {
+    fn _explain(&mut self, query: QueryCode, analyze: bool) -> Result<Code, ErrorVm> {
+        let mut plan = QueryPlan::new(analyze);
+        let mut result = build_query_with_plan(self.db, &self.tx, query, Some(&mut plan))?;
+        if analyze {
+            while result.next()?.is_some() {}
+        }
+        drop(result);
+
+        Ok(Code::Table(plan.into_mem_table()))
+    }
+
+    /// Inserts the `rows` into `table`, returning them as inserted, with the values of their sequences,
+    /// or as updated by `on_conflict` when they violate its unique constraint.
+    fn _execute_insert(
+        &mut self,
+        table: &Table,
+        rows: Vec<ProductValue>,
+        on_conflict: Option<&OnConflict>,
+    ) -> Result<Vec<ProductValue>, ErrorVm> {
         match table {
             // TODO: How do we deal with mutating values?
             Table::MemTable(_) => Err(ErrorVm::Other(anyhow::anyhow!("How deal with mutating values?"))),
             Table::DbTable(x) => {
+                let mut inserted = Vec::with_capacity(rows.len());
                 for row in rows {
-                    self.db.insert(self.tx, x.table_id, row)?;
+                    match self.db.insert(self.tx.mut_tx()?, x.table_id, row.clone()) {
+                        Ok(row) => inserted.push(row),
+                        Err(err @ DBError::Index(IndexError::UniqueConstraintViolation { .. })) => {
+                            let Some(on_conflict) = on_conflict else {
+                                return Err(err.into());
+                            };
+                            inserted.extend(self.resolve_conflict(x.table_id, on_conflict, row, err)?);
+                        }
+                        Err(err) => return Err(err.into()),
+                    }
                 }
-                Ok(Code::Pass)
+                Ok(inserted)
             }
         }
     }
 
+    /// Resolves as `on_conflict` says the insertion of `row` into the table `table_id`,
+    /// that failed with the unique constraint violation `err`,
+    /// returning the row as updated, if it is.
+    fn resolve_conflict(
+        &mut self,
+        table_id: u32,
+        on_conflict: &OnConflict,
+        row: ProductValue,
+        err: DBError,
+    ) -> Result<Option<ProductValue>, ErrorVm> {
+        // Without columns, `ON CONFLICT DO NOTHING` skips the rows that violate any unique constraint.
+        let Some(&col_id) = on_conflict.cols.first() else {
+            return Ok(None);
+        };
+        let tx = self.tx.mut_tx()?;
+        let existing = self
+            .db
+            .iter_by_col_eq(tx, table_id, col_id, &row.elements[col_id as usize])?
+            .map(|x| x.view().clone())
+            .find(|x| {
+                on_conflict
+                    .cols
+                    .iter()
+                    .all(|col| x.elements[*col as usize] == row.elements[*col as usize])
+            });
+        // The row violates another unique constraint than the one of `ON CONFLICT`.
+        let Some(existing) = existing else {
+            return Err(err.into());
+        };
+
+        match &on_conflict.action {
+            ConflictAction::DoNothing => Ok(None),
+            ConflictAction::DoUpdate { set, selection } => {
+                let head = OnConflict::header(&(&self.db.schema_for_table(tx, table_id)?).into());
+                let rows = ProductValue::new(&[existing.elements.clone(), row.elements].concat());
+                let rows = RelValueRef::new(&head, &rows);
+                if let Some(selection) = selection {
+                    if !selection.compare(rows)? {
+                        return Ok(None);
+                    }
+                }
+                let updated = set.iter().map(|x| x.expr.eval(rows)).collect::<Result<Vec<_>, _>>()?;
+
+                self.db.delete_by_rel(tx, table_id, vec![existing])?;
+                Ok(Some(self.db.insert(tx, table_id, ProductValue::new(&updated))?))
+            }
+        }
+    }
+
+    /// Yields the `returning` columns computed from the `rows` of `table` that were inserted, updated or deleted,
+    /// or `result` when there is no `RETURNING` clause.
+    fn returning(
+        &mut self,
+        table: &Table,
+        rows: Vec<ProductValue>,
+        returning: Option<Vec<ComputedColumn>>,
+        result: Code,
+    ) -> Result<Code, ErrorVm> {
+        let Some(columns) = returning else {
+            return Ok(result);
+        };
+        // The header of an `INSERT` only has the columns it lists, but the rows have all of them.
+        let head = match table {
+            Table::MemTable(x) => x.head.clone(),
+            Table::DbTable(x) => (&self.db.schema_for_table(self.tx.mut_tx()?, x.table_id)?).into(),
+        };
+        let rows = MemTable::new(&head, table.table_access(), &rows);
+        self._eval_query(QueryCode {
+            table: Table::MemTable(rows),
+            query: vec![Query::Compute(columns)],
+        })
+    }
+
     fn _execute_delete(&mut self, table: &Table, rows: Vec<ProductValue>) -> Result<Code, ErrorVm> {
         match table {
             // TODO: How do we deal with mutating values?
             Table::MemTable(_) => Err(ErrorVm::Other(anyhow::anyhow!("How deal with mutating values?"))),
             Table::DbTable(t) => {
-                let count = self.db.delete_by_rel(self.tx, t.table_id, rows)?;
+                let count = self.db.delete_by_rel(self.tx.mut_tx()?, t.table_id, rows)?;
                 Ok(Code::Value(count.unwrap_or_default().into()))
             }
         }
     }
 
-    fn delete_query(&mut self, query: QueryCode) -> Result<Code, ErrorVm> {
+    fn delete_query(&mut self, query: QueryCode, returning: Option<Vec<ComputedColumn>>) -> Result<Code, ErrorVm> {
         let table = query.table.clone();
         let result = self._eval_query(query)?;
 
         match result {
-            Code::Table(result) => self._execute_delete(&table, result.data),
+            Code::Table(result) => {
+                let count = self._execute_delete(&table, result.data.clone())?;
+                self.returning(&table, result.data, returning, count)
+            }
             _ => Ok(result),
         }
     }
 
-    fn insert_query(&mut self, table: &Table, query: QueryCode) -> Result<Code, ErrorVm> {
+    fn insert_query(
+        &mut self,
+        table: &Table,
+        query: QueryCode,
+        returning: Option<Vec<ComputedColumn>>,
+    ) -> Result<Code, ErrorVm> {
         let result = self._eval_query(query)?;
         match result {
-            Code::Table(result) => self._execute_insert(table, result.data),
+            Code::Table(result) => {
+                let inserted = self._execute_insert(table, result.data, None)?;
+                self.returning(table, inserted, returning, Code::Pass)
+            }
             _ => Ok(result),
         }
     }
@@ -203,7 +759,8 @@ impl<'db, 'tx> DbProgram<'db, 'tx> {
             if meta.is_unique() {
                 indexes.push(IndexDef {
                     table_id: 0, // Ignored
-                    col_id: i as u32,
+                    cols: vec![i as u32],
+                    index_type: IndexType::BTree,
                     name: format!("{}_{}_idx", table_name, i),
                     is_unique: true,
                 });
@@ -215,7 +772,7 @@ impl<'db, 'tx> DbProgram<'db, 'tx> {
             })
         }
         self.db.create_table(
-            self.tx,
+            self.tx.mut_tx()?,
             TableDef {
                 table_name: table_name.to_string(),
                 columns: cols,
@@ -227,21 +784,79 @@ impl<'db, 'tx> DbProgram<'db, 'tx> {
         Ok(Code::Pass)
     }
 
-    fn drop(&mut self, name: &str, kind: DbType) -> Result<Code, ErrorVm> {
-        match kind {
-            DbType::Table => {
-                if let Some(id) = self.db.table_id_from_name(self.tx, name)? {
-                    self.db.drop_table(self.tx, id)?;
-                }
-            }
-            DbType::Index => {
-                if let Some(id) = self.db.index_id_from_name(self.tx, name)? {
-                    self.db.drop_index(self.tx, IndexId(id))?;
+    fn create_index(
+        &mut self,
+        name: String,
+        table_id: u32,
+        cols: Vec<u32>,
+        index_type: IndexType,
+        is_unique: bool,
+    ) -> Result<Code, ErrorVm> {
+        self.db.create_index(
+            self.tx.mut_tx()?,
+            IndexDef {
+                table_id,
+                cols,
+                index_type,
+                name,
+                is_unique,
+            },
+        )?;
+        Ok(Code::Pass)
+    }
+
+    fn alter_table(&mut self, table_name: &str, table_id: u32, alter: AlterTable) -> Result<Code, ErrorVm> {
+        let tx = self.tx.mut_tx()?;
+        match alter {
+            AlterTable::Rename { new_name } => self.db.rename_table(tx, table_id, &new_name)?,
+            AlterTable::AddColumn { column, attr, default } => {
+                let col_id = self.db.schema_for_table(tx, table_id)?.columns.len();
+                let col_name = column.name.unwrap_or_else(|| col_id.to_string());
+                self.db.add_column(
+                    tx,
+                    table_id,
+                    ColumnDef {
+                        col_name,
+                        col_type: column.algebraic_type,
+                        is_autoinc: attr.is_autoinc(),
+                    },
+                    default,
+                )?;
+                // As in `CREATE TABLE`, a unique column gets an index to enforce it.
+                if attr.is_unique() {
+                    self.db.create_index(
+                        tx,
+                        IndexDef {
+                            table_id,
+                            cols: vec![col_id as u32],
+                            index_type: IndexType::BTree,
+                            name: format!("{}_{}_idx", table_name, col_id),
+                            is_unique: true,
+                        },
+                    )?;
                 }
             }
+        }
+        Ok(Code::Pass)
+    }
+
+    /// Drops the object of `kind` with `name`, failing if there is none, unless `if_exists` is set.
+    fn drop(&mut self, name: &str, kind: DbType, if_exists: bool) -> Result<Code, ErrorVm> {
+        let tx = self.tx.mut_tx()?;
+        match kind {
+            DbType::Table => match self.db.table_id_from_name(tx, name)? {
+                Some(id) => self.db.drop_table(tx, id)?,
+                None if !if_exists => return Err(DBError::from(TableError::NotFound(name.into())).into()),
+                None => {}
+            },
+            DbType::Index => match self.db.index_id_from_name(tx, name)? {
+                Some(id) => self.db.drop_index(tx, IndexId(id))?,
+                None if !if_exists => return Err(DBError::from(IndexError::NameNotFound(name.into())).into()),
+                None => {}
+            },
             DbType::Sequence => {
-                if let Some(id) = self.db.sequence_id_from_name(self.tx, name)? {
-                    self.db.drop_sequence(self.tx, SequenceId(id))?;
+                if let Some(id) = self.db.sequence_id_from_name(tx, name)? {
+                    self.db.drop_sequence(tx, SequenceId(id))?;
                 }
             }
         }
@@ -272,8 +887,20 @@ impl ProgramVm for DbProgram<'_, '_> {
 
         match query {
             CrudCode::Query(query) => self._eval_query(query),
-            CrudCode::Insert { table, rows } => self._execute_insert(&table, rows),
-            CrudCode::Update { mut insert, delete } => {
+            CrudCode::Insert {
+                table,
+                rows,
+                on_conflict,
+                returning,
+            } => {
+                let inserted = self._execute_insert(&table, rows, on_conflict.as_ref())?;
+                self.returning(&table, inserted, returning, Code::Pass)
+            }
+            CrudCode::Update {
+                mut insert,
+                delete,
+                returning,
+            } => {
                 let table = delete.table.clone();
                 let result = self._eval_query(delete)?;
 
@@ -286,13 +913,14 @@ impl ProgramVm for DbProgram<'_, '_> {
                 let to_insert = mem_table(table.head(), deleted.data);
                 insert.table = Table::MemTable(to_insert);
 
-                let result = self.insert_query(&table, insert)?;
+                let result = self.insert_query(&table, insert, returning)?;
                 Ok(result)
             }
-            CrudCode::Delete { query } => {
-                let result = self.delete_query(query)?;
+            CrudCode::Delete { query, returning } => {
+                let result = self.delete_query(query, returning)?;
                 Ok(result)
             }
+            CrudCode::Explain { query, analyze } => self._explain(query, analyze),
             CrudCode::CreateTable {
                 name,
                 columns,
@@ -302,12 +930,26 @@ impl ProgramVm for DbProgram<'_, '_> {
                 let result = self.create_table(&name, columns, table_type, table_access)?;
                 Ok(result)
             }
+            CrudCode::CreateIndex {
+                name,
+                table_name: _,
+                table_id,
+                cols,
+                index_type,
+                is_unique,
+            } => self.create_index(name, table_id, cols, index_type, is_unique),
+            CrudCode::AlterTable {
+                table_name,
+                table_id,
+                alter,
+            } => self.alter_table(&table_name, table_id, alter),
             CrudCode::Drop {
                 name,
                 kind,
                 table_access: _,
+                if_exists,
             } => {
-                let result = self.drop(&name, kind)?;
+                let result = self.drop(&name, kind, if_exists)?;
                 Ok(result)
             }
         }
@@ -327,6 +969,24 @@ impl RelOps for TableCursor<'_> {
         &self.table.head
     }
 
+    fn row_count(&self) -> RowCount {
+        let (min, max) = self.iter.size_hint();
+        RowCount { min, max }
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        if let Some(row) = self.iter.next() {
+            return Ok(Some(RelValue::new(self.head(), row.view())));
+        };
+        Ok(None)
+    }
+}
+
+impl<R: RangeBounds<AlgebraicValue>> RelOps for IndexCursor<'_, R> {
+    fn head(&self) -> &Header {
+        &self.table.head
+    }
+
     fn row_count(&self) -> RowCount {
         RowCount::unknown()
     }
@@ -376,7 +1036,6 @@ pub(crate) mod tests {
     use crate::db::relational_db::tests_utils::make_test_db;
     use crate::db::relational_db::{ST_COLUMNS_NAME, ST_INDEXES_NAME, ST_SEQUENCES_NAME, ST_TABLES_NAME};
     use spacetimedb_lib::error::ResultTest;
-    use spacetimedb_lib::relation::{DbTable, FieldName};
     use spacetimedb_sats::{product, AlgebraicType, BuiltinType, ProductType, ProductValue};
     use spacetimedb_vm::dsl::*;
     use spacetimedb_vm::eval::run_ast;
@@ -421,8 +1080,10 @@ pub(crate) mod tests {
         schema: ProductType,
         rows: &[ProductValue],
     ) -> ResultTest<u32> {
-        let db = &mut p.db;
-        create_table_with_rows(db, p.tx, table_name, schema, rows)
+        let TxMode::MutTx(tx) = &mut p.tx else {
+            panic!("tables can only be created in a mutable transaction")
+        };
+        create_table_with_rows(p.db, tx, table_name, schema, rows)
     }
 
     #[test]
@@ -436,7 +1097,7 @@ pub(crate) mod tests {
         let (stdb, _tmp_dir) = make_test_db()?;
 
         let mut tx = stdb.begin_tx();
-        let p = &mut DbProgram::new(&stdb, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&stdb, (&mut tx).into(), AuthCtx::for_testing());
 
         let head = ProductType::from_iter([("inventory_id", BuiltinType::U64), ("name", BuiltinType::String)]);
         let row = product!(1u64, "health");
@@ -484,7 +1145,7 @@ pub(crate) mod tests {
         let (stdb, _tmp_dir) = make_test_db()?;
 
         let mut tx = stdb.begin_tx();
-        let p = &mut DbProgram::new(&stdb, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&stdb, (&mut tx).into(), AuthCtx::for_testing());
 
         let q = query(&st_table_schema()).with_select_cmp(
             OpCmp::Eq,
@@ -515,7 +1176,7 @@ pub(crate) mod tests {
         let (stdb, _tmp_dir) = make_test_db()?;
 
         let mut tx = stdb.begin_tx();
-        let p = &mut DbProgram::new(&stdb, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&stdb, (&mut tx).into(), AuthCtx::for_testing());
 
         let q = query(&st_columns_schema())
             .with_select_cmp(
@@ -560,10 +1221,10 @@ pub(crate) mod tests {
         db.commit_tx(tx)?;
 
         let mut tx = db.begin_tx();
-        let index = IndexDef::new("idx_1".into(), table_id, 0, true);
+        let index = IndexDef::new("idx_1".into(), table_id, vec![0], IndexType::BTree, true);
         let index_id = db.create_index(&mut tx, index)?;
 
-        let p = &mut DbProgram::new(&db, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&db, (&mut tx).into(), AuthCtx::for_testing());
 
         let q = query(&st_indexes_schema()).with_select_cmp(
             OpCmp::Eq,
@@ -577,7 +1238,8 @@ pub(crate) mod tests {
                 index_id: index_id.0,
                 index_name: "idx_1",
                 table_id,
-                col_id: 0,
+                cols: vec![0],
+                index_type: IndexType::BTree,
                 is_unique: true,
             })
                 .into(),
@@ -590,12 +1252,62 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sort_by_index() -> ResultTest<()> {
+        let (db, _tmp_dir) = make_test_db()?;
+
+        let head = ProductType::from_iter([("inventory_id", BuiltinType::U64), ("name", BuiltinType::String)]);
+        let rows = [3u64, 1, 2].map(|i| product!(i, format!("health{i}")));
+
+        let mut tx = db.begin_tx();
+        let table_id = create_table_with_rows(&db, &mut tx, "inventory", head.clone(), &rows)?;
+        let index = IndexDef::new("idx_1".into(), table_id, vec![0], IndexType::BTree, false);
+        db.create_index(&mut tx, index)?;
+        db.commit_tx(tx)?;
+
+        let table = db_table(head, "inventory", table_id);
+        let sorted = |asc| QueryCode {
+            table: Table::DbTable(table.clone()),
+            query: vec![Query::Sort(vec![SortKey::new(
+                FieldName::named("inventory", "inventory_id").into(),
+                asc,
+            )])],
+        };
+        let ids = |tx: &TxMode, query| -> ResultTest<Vec<u64>> {
+            Ok(build_query(&db, tx, query)?
+                .collect_vec()?
+                .iter()
+                .map(|row| *row.elements[0].as_u64().unwrap())
+                .collect())
+        };
+
+        // The committed rows are sorted by the index.
+        let tx = db.begin_read_tx();
+        let mut query = sorted(true);
+        assert!(index_source(&db, &(&tx).into(), &mut query, &mut None)?.is_some());
+        assert!(query.query.is_empty());
+        assert_eq!(ids(&(&tx).into(), sorted(true))?, vec![1, 2, 3]);
+        // The index can't serve a descending sort.
+        assert!(index_source(&db, &(&tx).into(), &mut sorted(false), &mut None)?.is_none());
+        assert_eq!(ids(&(&tx).into(), sorted(false))?, vec![3, 2, 1]);
+        db.release_tx(tx);
+
+        // Once the transaction inserts rows, they are sorted along with the committed ones.
+        let mut tx = db.begin_tx();
+        db.insert(&mut tx, table_id, product!(0u64, "health0"))?;
+        assert!(index_source(&db, &(&mut tx).into(), &mut sorted(true), &mut None)?.is_none());
+        assert_eq!(ids(&(&mut tx).into(), sorted(true))?, vec![0, 1, 2, 3]);
+        db.rollback_tx(tx);
+
+        Ok(())
+    }
+
     #[test]
     fn test_query_catalog_sequences() -> ResultTest<()> {
         let (db, _tmp_dir) = make_test_db()?;
 
         let mut tx = db.begin_tx();
-        let p = &mut DbProgram::new(&db, &mut tx, AuthCtx::for_testing());
+        let p = &mut DbProgram::new(&db, (&mut tx).into(), AuthCtx::for_testing());
 
         let q = query(&st_sequences_schema()).with_select_cmp(
             OpCmp::Eq,
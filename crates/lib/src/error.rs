@@ -112,6 +112,8 @@ pub enum AuthError {
     IndexPrivate { named: String },
     #[error("Sequence `{named}` is private")]
     SequencePrivate { named: String },
+    #[error("Only the owner of the database can change the schema of `{named}`")]
+    OwnerRequired { named: String },
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -205,6 +205,27 @@ pub enum IndexType {
     Hash,
 }
 
+impl IndexType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BTree => "btree",
+            Self::Hash => "hash",
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for IndexType {
+    type Error = &'a str;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        Ok(match value {
+            "btree" => Self::BTree,
+            "hash" => Self::Hash,
+            x => return Err(x),
+        })
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, de::Deserialize, ser::Serialize)]
 pub enum ColumnIndexAttribute {
     #[default]
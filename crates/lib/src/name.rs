@@ -43,6 +43,30 @@ pub enum PublishOp {
     Updated,
 }
 
+/// A change to the schema of a database, made automatically when its module is updated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchemaChange {
+    /// The module defines a new table.
+    CreateTable { table: String },
+    /// The module appends a column to the columns of an existing table.
+    AddColumn { table: String, column: String },
+    /// The module defines a new index on an existing table.
+    CreateIndex { table: String, index: String },
+    /// The module no longer defines an index of an existing table.
+    DropIndex { table: String, index: String },
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaChange::CreateTable { table } => write!(f, "created table `{table}`"),
+            SchemaChange::AddColumn { table, column } => write!(f, "added column `{column}` to table `{table}`"),
+            SchemaChange::CreateIndex { table, index } => write!(f, "created index `{index}` on table `{table}`"),
+            SchemaChange::DropIndex { table, index } => write!(f, "dropped index `{index}` from table `{table}`"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PublishResult {
     Success {
@@ -59,6 +83,12 @@ pub enum PublishResult {
         /// or not.
         address: String,
         op: PublishOp,
+        /// The changes made to the schema of an existing database to match the
+        /// updated module, in the order they were applied.
+        ///
+        /// Always empty when a new database was created.
+        #[serde(default)]
+        schema_changes: Vec<SchemaChange>,
     },
 
     /// The top level domain for the database name is not registered. For example:
@@ -57,7 +57,7 @@ impl From<OpUnary> for &str {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub enum OpMath {
     Add,
     Minus,
@@ -2,7 +2,7 @@ use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 
-use spacetimedb::db::Storage;
+use spacetimedb::db::{Durability, Storage};
 use spacetimedb::host::scheduler::Scheduler;
 use spacetimedb::Identity;
 use tempdir::TempDir;
@@ -37,6 +37,7 @@ pub fn main() {
         address,
         db_path.to_path_buf(),
         logger_path,
+        Durability::default(),
     );
 
     let iv = InstanceEnv::new(dbic, Scheduler::dummy(&scheduler_path), None);
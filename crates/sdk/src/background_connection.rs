@@ -1,14 +1,16 @@
-use crate::callbacks::{CredentialStore, DbCallbacks, ReducerCallbacks, SubscriptionAppliedCallbacks};
+use crate::callbacks::{parse_status, CredentialStore, DbCallbacks, ReducerCallbacks, SubscriptionAppliedCallbacks};
 use crate::client_api_messages;
 use crate::client_cache::{ClientCache, ClientCacheView, RowCallbackReminders};
 use crate::identity::Credentials;
-use crate::reducer::{AnyReducerEvent, Reducer};
+use crate::reducer::{AnyReducerEvent, Reducer, ReducerOutcome};
 use crate::websocket::DbConnection;
 use anyhow::{anyhow, Context, Result};
 use futures::stream::StreamExt;
-use futures_channel::mpsc;
+use futures_channel::{mpsc, oneshot};
 use spacetimedb_sats::bsatn;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::{
     runtime::{self, Builder, Runtime},
     task::JoinHandle,
@@ -17,6 +19,33 @@ use tokio::{
 /// A thread-safe mutable place that can be shared by multiple referents.
 type SharedCell<T> = Arc<Mutex<T>>;
 
+/// The reducer calls made with `call_reducer` which await their outcome, by request id.
+#[derive(Default)]
+pub(crate) struct PendingCalls {
+    last_request_id: u32,
+    calls: HashMap<u32, oneshot::Sender<ReducerOutcome>>,
+}
+
+impl PendingCalls {
+    /// Register a new call, returning its request id and the receiver of its outcome.
+    ///
+    /// Request ids are never 0, which the database sends in the events of other clients' calls.
+    fn insert(&mut self) -> (u32, oneshot::Receiver<ReducerOutcome>) {
+        self.last_request_id = self.last_request_id.checked_add(1).unwrap_or(1);
+        let (send, recv) = oneshot::channel();
+        self.calls.insert(self.last_request_id, send);
+        (self.last_request_id, recv)
+    }
+
+    /// Send the outcome of the call `request_id`, if this client made it.
+    fn resolve(&mut self, request_id: u32, outcome: ReducerOutcome) {
+        if let Some(send) = self.calls.remove(&request_id) {
+            // The caller may have dropped the future.
+            let _ = send.send(outcome);
+        }
+    }
+}
+
 pub struct BackgroundDbConnection {
     /// `Some` if not within the context of an outer runtime. The `Runtime` must
     /// then live as long as `Self`.
@@ -57,6 +86,7 @@ pub struct BackgroundDbConnection {
     pub(crate) db_callbacks: SharedCell<DbCallbacks>,
     pub(crate) reducer_callbacks: SharedCell<ReducerCallbacks>,
     pub(crate) subscription_callbacks: SharedCell<SubscriptionAppliedCallbacks>,
+    pending_calls: SharedCell<PendingCalls>,
 }
 
 // When called from within an async context, return a handle to it (and no
@@ -107,6 +137,16 @@ fn process_subscription_update_for_transaction_update(
     }
 }
 
+/// Apply the rows gained or lost upon `subscribe_query` or `unsubscribe_query`
+/// like those of a `TransactionUpdate`, leaving the rows of the other queries in place.
+fn process_subscription_update_for_query_update(
+    msg: client_api_messages::SubscriptionUpdate,
+    client_cache: &mut ClientCache,
+    callback_reminders: &mut RowCallbackReminders,
+) {
+    process_subscription_update_for_transaction_update(msg, client_cache, callback_reminders);
+}
+
 fn process_event(
     msg: client_api_messages::Event,
     reducer_callbacks: &mut ReducerCallbacks,
@@ -193,6 +233,7 @@ async fn receiver_loop(
     reducer_callbacks: SharedCell<ReducerCallbacks>,
     credentials: SharedCell<CredentialStore>,
     subscription_callbacks: SharedCell<SubscriptionAppliedCallbacks>,
+    pending_calls: SharedCell<PendingCalls>,
 ) {
     while let Some(msg) = recv.next().await {
         match msg {
@@ -219,7 +260,36 @@ async fn receiver_loop(
             } => {
                 log::info!("Message TransactionUpdate");
 
+                let outcome = transaction_update.event.as_ref().and_then(call_outcome);
                 process_transaction_update(transaction_update, &client_cache, &db_callbacks, &reducer_callbacks);
+
+                // Resolve the call only once the client cache holds its changes.
+                if let Some((request_id, outcome)) = outcome {
+                    pending_calls
+                        .lock()
+                        .expect("PendingCalls Mutex is poisoned")
+                        .resolve(request_id, outcome);
+                }
+            }
+            client_api_messages::Message {
+                r#type:
+                    Some(client_api_messages::message::Type::QueryUpdate(client_api_messages::QueryUpdate {
+                        query_id,
+                        subscription_update,
+                    })),
+            } => {
+                log::info!("Message QueryUpdate for query {}", query_id);
+                if let Some(update) = subscription_update {
+                    let mut callback_reminders = RowCallbackReminders::new_for_subscription_update(&update);
+                    let new_state = update_client_cache(&client_cache, |client_cache| {
+                        process_subscription_update_for_query_update(update, client_cache, &mut callback_reminders);
+                    });
+
+                    let mut db_callbacks_lock = db_callbacks.lock().expect("DbCallbacks Mutex is poisoned");
+                    new_state.invoke_row_callbacks(&mut callback_reminders, &mut db_callbacks_lock, None);
+                } else {
+                    log::error!("Received QueryUpdate with no SubscriptionUpdate");
+                }
             }
             client_api_messages::Message {
                 r#type: Some(client_api_messages::message::Type::IdentityToken(ident)),
@@ -232,6 +302,31 @@ async fn receiver_loop(
             other => log::info!("Unknown message: {:?}", other),
         }
     }
+
+    // The connection closed; the calls still pending will never be replied to.
+    pending_calls
+        .lock()
+        .expect("PendingCalls Mutex is poisoned")
+        .calls
+        .clear();
+}
+
+/// Extract the outcome of the call described by `event`, if it was made by this client with `call_reducer`.
+fn call_outcome(event: &client_api_messages::Event) -> Option<(u32, ReducerOutcome)> {
+    let request_id = event.function_call.as_ref()?.request_id;
+    if request_id == 0 {
+        return None;
+    }
+    let Some(status) = parse_status(event.status, event.message.clone()) else {
+        log::warn!("Received Event with unknown status {:?}", event.status);
+        return None;
+    };
+    let outcome = ReducerOutcome::Response {
+        status,
+        energy_quanta_used: event.energy_quanta_used,
+        host_execution_duration: Duration::from_micros(event.host_execution_duration_micros),
+    };
+    Some((request_id, outcome))
 }
 
 impl BackgroundDbConnection {
@@ -257,6 +352,7 @@ impl BackgroundDbConnection {
             db_callbacks,
             reducer_callbacks,
             subscription_callbacks,
+            pending_calls: Arc::new(Mutex::new(PendingCalls::default())),
         })
     }
 
@@ -272,6 +368,7 @@ impl BackgroundDbConnection {
             self.reducer_callbacks.clone(),
             self.credentials.clone(),
             self.subscription_callbacks.clone(),
+            self.pending_calls.clone(),
         ))
     }
 
@@ -359,15 +456,54 @@ impl BackgroundDbConnection {
         .with_context(|| "Subscribing to new queries")
     }
 
+    pub(crate) fn subscribe_query(&self, query_id: u32, query: String) -> Result<()> {
+        self.send_message(client_api_messages::Message {
+            r#type: Some(client_api_messages::message::Type::SubscribeQuery(
+                client_api_messages::SubscribeQuery { query_id, query },
+            )),
+        })
+        .with_context(|| format!("Subscribing to query {query_id}"))
+    }
+
+    pub(crate) fn unsubscribe_query(&self, query_id: u32) -> Result<()> {
+        self.send_message(client_api_messages::Message {
+            r#type: Some(client_api_messages::message::Type::UnsubscribeQuery(
+                client_api_messages::UnsubscribeQuery { query_id },
+            )),
+        })
+        .with_context(|| format!("Unsubscribing from query {query_id}"))
+    }
+
     pub(crate) fn invoke_reducer<R: Reducer>(&self, reducer: R) -> Result<()> {
         self.send_message(client_api_messages::Message {
             r#type: Some(client_api_messages::message::Type::FunctionCall(
                 client_api_messages::FunctionCall {
                     reducer: R::REDUCER_NAME.to_string(),
                     arg_bytes: bsatn::to_vec(&reducer).expect("Serializing reducer failed"),
+                    request_id: 0,
                 },
             )),
         })
         .with_context(|| format!("Invoking reducer {}", R::REDUCER_NAME))
     }
+
+    pub(crate) fn call_reducer<R: Reducer>(&self, reducer: R) -> Result<oneshot::Receiver<ReducerOutcome>> {
+        let mut pending_calls = self.pending_calls.lock().expect("PendingCalls Mutex is poisoned");
+        let (request_id, response) = pending_calls.insert();
+        self.send_message(client_api_messages::Message {
+            r#type: Some(client_api_messages::message::Type::FunctionCall(
+                client_api_messages::FunctionCall {
+                    reducer: R::REDUCER_NAME.to_string(),
+                    arg_bytes: bsatn::to_vec(&reducer).expect("Serializing reducer failed"),
+                    request_id,
+                },
+            )),
+        })
+        .map_err(|e| {
+            pending_calls.calls.remove(&request_id);
+            e
+        })
+        .with_context(|| format!("Calling reducer {}", R::REDUCER_NAME))?;
+        Ok(response)
+    }
 }
@@ -658,7 +658,7 @@ pub struct ReducerCallbacks {
 // which we must then compare against the enum variants.
 // This helper function does that comparison.
 
-fn parse_status(status: i32, message: String) -> Option<Status> {
+pub(crate) fn parse_status(status: i32, message: String) -> Option<Status> {
     if status == client_api_messages::event::Status::Committed as i32 {
         debug_assert!(message.is_empty());
         Some(Status::Committed)
@@ -667,6 +667,9 @@ fn parse_status(status: i32, message: String) -> Option<Status> {
     } else if status == client_api_messages::event::Status::OutOfEnergy as i32 {
         debug_assert!(message.is_empty());
         Some(Status::OutOfEnergy)
+    } else if status == client_api_messages::event::Status::WriteConflict as i32 {
+        debug_assert!(message.is_empty());
+        Some(Status::WriteConflict)
     } else {
         None
     }
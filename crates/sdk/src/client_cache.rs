@@ -66,12 +66,17 @@ impl<T: TableType> TableCache<T> {
     /// `row_hash` will be the `row_pk` field of a `client_api_messages::TableRowOperation`,
     /// which is a hash of the row generated by STDB.
     /// We treat `row_hash` as an opaque `Vec<u8>` identifier.
+    ///
+    /// Inserting a row already present does nothing,
+    /// as the `QueryUpdate` for a new query also contains the rows matched by the other queries.
     fn insert(&mut self, callbacks: &mut Vec<RowCallback<T>>, row_hash: Vec<u8>, value: T) {
-        callbacks.push(RowCallback::Insert(value.clone()));
-
-        if self.entries.insert(row_hash, value).is_some() {
-            log::warn!("Inserting a row already presint in table {:?}", T::TABLE_NAME);
+        if self.entries.contains_key(&row_hash) {
+            log::trace!("Inserting a row already present in table {:?}", T::TABLE_NAME);
+            return;
         }
+
+        callbacks.push(RowCallback::Insert(value.clone()));
+        self.entries.insert(row_hash, value);
     }
 
     /// Delete `value` from the cache and invoke any on-delete callbacks.
@@ -50,8 +50,8 @@ pub use spacetimedb_sats as sats;
 ///
 /// The `queries` should be a slice of strings representing SQL queries.
 ///
-/// A new call to `subscribe` (or [`subscribe_owned`]) will remove all previous subscriptions
-/// and replace them with the new `queries`.
+/// A new call to `subscribe` (or [`subscribe_owned`]) will remove all previous subscriptions,
+/// including those made by [`subscribe_query`], and replace them with the new `queries`.
 /// If any rows matched the previous subscribed queries but do not match the new queries,
 /// those rows will be removed from the client cache,
 /// and `TableType::on_delete` callbacks will be invoked for them.
@@ -68,8 +68,8 @@ pub fn subscribe(queries: &[&str]) -> anyhow::Result<()> {
 ///
 /// The `queries` should be a `Vec` of `String`s representing SQL queries.
 ///
-/// A new call to `subscribe_owned` (or [`subscribe`]) will remove all previous subscriptions
-/// and replace them with the new `queries`.
+/// A new call to `subscribe_owned` (or [`subscribe`]) will remove all previous subscriptions,
+/// including those made by [`subscribe_query`], and replace them with the new `queries`.
 /// If any rows matched the previous subscribed queries but do not match the new queries,
 /// those rows will be removed from the client cache,
 /// and `TableType::on_delete` callbacks will be invoked for them.
@@ -81,6 +81,36 @@ pub fn subscribe_owned(queries: Vec<String>) -> anyhow::Result<()> {
     with_connection(|conn| conn.subscribe_owned(queries))
 }
 
+/// Subscribe to a single query, in addition to the queries already subscribed to,
+/// to be notified when rows which match it are altered.
+///
+/// The `query` should be a string representing a SQL query.
+/// The `query_id` is chosen by the caller to identify the query in [`unsubscribe_query`],
+/// and must not be in use by another query.
+///
+/// Unlike [`subscribe`], the rows matching the other subscribed queries are not sent again.
+/// The rows matching `query` will be added to the client cache,
+/// and `TableType::on_insert` callbacks will be invoked for those not already present.
+///
+/// `subscribe_query` will return an error if called before establishing a connection
+/// with the autogenerated `connect` function.
+/// In that case, the query is not registered.
+pub fn subscribe_query(query_id: u32, query: &str) -> anyhow::Result<()> {
+    with_connection(|conn| conn.subscribe_query(query_id, query.into()))
+}
+
+/// Unsubscribe from a query previously subscribed to with [`subscribe_query`].
+///
+/// If any rows matched the query but do not match the remaining subscribed queries,
+/// those rows will be removed from the client cache,
+/// and `TableType::on_delete` callbacks will be invoked for them.
+///
+/// `unsubscribe_query` will return an error if called before establishing a connection
+/// with the autogenerated `connect` function.
+pub fn unsubscribe_query(query_id: u32) -> anyhow::Result<()> {
+    with_connection(|conn| conn.unsubscribe_query(query_id))
+}
+
 #[derive(Copy, Clone)]
 pub struct SubscriptionCallbackId {
     id: CallbackId<()>,
@@ -2,14 +2,35 @@ use crate::callbacks::CallbackId;
 use crate::global_connection::{with_connection, with_reducer_callbacks};
 use crate::identity::Identity;
 use anyhow::Result;
+use futures::Future;
 use spacetimedb_sats::{de::DeserializeOwned, ser::Serialize};
 use std::any::Any;
+use std::time::Duration;
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Status {
     Committed,
     Failed(String),
     OutOfEnergy,
+    WriteConflict,
+}
+
+/// The outcome of a reducer call made by this client with [`call_reducer`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ReducerOutcome {
+    /// The database replied to the call.
+    ///
+    /// Calls to nonexistent reducers, or with invalid arguments,
+    /// are replied to with a `Status::Failed`, and no energy used.
+    Response {
+        status: Status,
+        energy_quanta_used: i64,
+        host_execution_duration: Duration,
+    },
+    /// The call could not be sent, or the connection closed before the database replied.
+    ///
+    /// In the latter case, the reducer may or may not have run.
+    NoResponse(String),
 }
 
 #[derive(Copy, Clone)]
@@ -63,4 +84,24 @@ pub trait Reducer: DeserializeOwned + Serialize + Any + Send + Sync + Clone {
     }
 }
 
+/// Request that the remote database run the `reducer`, and wait for the outcome of the call.
+///
+/// Unlike [`Reducer::invoke`], the database always replies to the call,
+/// whether or not the reducer changed any subscribed rows.
+/// By the time the returned future resolves, the client cache reflects those changes.
+///
+/// The returned future resolves to `ReducerOutcome::NoResponse` if called before establishing a connection
+/// with the autogenerated `connect` function.
+pub fn call_reducer<R: Reducer>(reducer: R) -> impl Future<Output = ReducerOutcome> {
+    let response = with_connection(|conn| conn.call_reducer(reducer));
+    async move {
+        match response {
+            Err(e) => ReducerOutcome::NoResponse(format!("{e:#}")),
+            Ok(response) => response.await.unwrap_or_else(|_| {
+                ReducerOutcome::NoResponse("Connection closed before receiving the outcome of the call".to_string())
+            }),
+        }
+    }
+}
+
 pub type AnyReducerEvent = dyn Any + Send + Sync;
@@ -81,8 +81,8 @@ impl SpaceDb {
 
     pub(crate) fn run_sql(&self, sql: &str) -> anyhow::Result<Vec<MemTable>> {
         self.conn.with_auto_commit(|tx| {
-            let ast = compile_sql(&self.conn, tx, sql)?;
-            let result = execute_sql(&self.conn, tx, ast, self.auth)?;
+            let ast = compile_sql(&self.conn, &tx.into(), sql)?;
+            let result = execute_sql(&self.conn, tx.into(), ast, self.auth)?;
             //remove comments to see which SQL worked. Can't collect it outside from lack of a hook in the external `sqllogictest` crate... :(
             //append_file(&std::path::PathBuf::from(".ok.sql"), sql)?;
             Ok(result)
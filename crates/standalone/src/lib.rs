@@ -18,6 +18,7 @@ use spacetimedb::client::ClientActorIndex;
 use spacetimedb::control_db::ControlDb;
 use spacetimedb::database_instance_context::DatabaseInstanceContext;
 use spacetimedb::database_instance_context_controller::DatabaseInstanceContextController;
+use spacetimedb::db::Durability;
 use spacetimedb::db::{db_metrics, Storage};
 use spacetimedb::hash::Hash;
 use spacetimedb::host::UpdateOutcome;
@@ -245,6 +246,7 @@ impl spacetimedb_client_api::ControlCtx for StandaloneEnv {
         num_replicas: u32,
         force: bool,
         trace_log: bool,
+        durability: Durability,
     ) -> Result<(), anyhow::Error> {
         let database = Database {
             id: 0,
@@ -254,6 +256,7 @@ impl spacetimedb_client_api::ControlCtx for StandaloneEnv {
             num_replicas,
             program_bytes_address: *program_bytes_address,
             trace_log,
+            durability,
         };
 
         if force {
@@ -284,15 +287,23 @@ impl spacetimedb_client_api::ControlCtx for StandaloneEnv {
 
         database.program_bytes_address = *program_bytes_address;
         database.num_replicas = num_replicas;
-        let database_id = database.id;
+
+        // The new module is only recorded once the instances have been updated to it,
+        // so that a rejected update leaves the database on its previous module.
+        let update_result = self
+            .update_database_instances(&database)
+            .await?
+            // TODO(kim): this should really only run on the leader instance
+            .pop()
+            .flatten();
+        if let Some(Err(_)) = update_result {
+            return Ok(update_result);
+        }
+
         let new_database = database.clone();
         self.control_db.update_database(database).await?;
-
         self.schedule_database(Some(new_database), Some(old_database)).await?;
-        self.update_database_instances(database_id)
-            .await
-            // TODO(kim): this should really only run on the leader instance
-            .map(|mut res| res.pop().flatten())
+        Ok(update_result)
     }
 
     async fn delete_database(&self, address: &Address) -> Result<(), anyhow::Error> {
@@ -350,11 +361,16 @@ impl spacetimedb_client_api::ControlNodeDelegate for StandaloneEnv {
 
 impl StandaloneEnv {
     async fn insert_database_instance(&self, database_instance: DatabaseInstance) -> Result<(), anyhow::Error> {
+        let database_id = database_instance.database_id;
+        let Some(database) = self.control_db.get_database_by_id(database_id).await? else {
+            return Err(anyhow::anyhow!("Unknown database: {}", database_id));
+        };
         let mut new_database_instance = database_instance.clone();
         let id = self.control_db.insert_database_instance(database_instance).await?;
         new_database_instance.id = id;
 
-        self.on_insert_database_instance(&new_database_instance).await?;
+        self.on_insert_database_instance(&new_database_instance, &database)
+            .await?;
 
         Ok(())
     }
@@ -365,12 +381,13 @@ impl StandaloneEnv {
     async fn update_database_instance(
         &self,
         database_instance: DatabaseInstance,
+        database: &Database,
     ) -> Result<Option<UpdateDatabaseResult>, anyhow::Error> {
         self.control_db
             .update_database_instance(database_instance.clone())
             .await?;
 
-        self.on_update_database_instance(&database_instance).await
+        self.on_update_database_instance(&database_instance, database).await
     }
 
     async fn delete_database_instance(&self, database_instance_id: u64) -> Result<(), anyhow::Error> {
@@ -429,12 +446,12 @@ impl StandaloneEnv {
     // method should return a single result
     async fn update_database_instances(
         &self,
-        database_id: u64,
+        database: &Database,
     ) -> Result<Vec<Option<UpdateDatabaseResult>>, anyhow::Error> {
-        let instances = self.control_db.get_database_instances_by_database(database_id).await?;
+        let instances = self.control_db.get_database_instances_by_database(database.id).await?;
         let mut results = Vec::with_capacity(instances.len());
         for instance in instances {
-            let res = self.update_database_instance(instance).await?;
+            let res = self.update_database_instance(instance, database).await?;
             results.push(res);
         }
 
@@ -452,17 +469,21 @@ impl StandaloneEnv {
         Ok(())
     }
 
-    async fn on_insert_database_instance(&self, instance: &DatabaseInstance) -> Result<(), anyhow::Error> {
+    async fn on_insert_database_instance(
+        &self,
+        instance: &DatabaseInstance,
+        database: &Database,
+    ) -> Result<(), anyhow::Error> {
         let state = self.worker_db.get_database_instance_state(instance.id).unwrap();
         if let Some(mut state) = state {
             if !state.initialized {
                 // Start and init the service
-                self.init_module_on_database_instance(instance.database_id, instance.id)
+                self.init_module_on_database_instance(database.clone(), instance.id)
                     .await?;
                 state.initialized = true;
                 self.worker_db.upsert_database_instance_state(state).unwrap();
             } else {
-                self.start_module_on_database_instance(instance.database_id, instance.id)
+                self.start_module_on_database_instance(database.clone(), instance.id)
                     .await?;
             }
             Ok(())
@@ -472,7 +493,7 @@ impl StandaloneEnv {
                 database_instance_id: instance.id,
                 initialized: false,
             };
-            self.init_module_on_database_instance(instance.database_id, instance.id)
+            self.init_module_on_database_instance(database.clone(), instance.id)
                 .await?;
             self.worker_db.upsert_database_instance_state(state.clone()).unwrap();
             state.initialized = true;
@@ -484,15 +505,19 @@ impl StandaloneEnv {
     async fn on_update_database_instance(
         &self,
         instance: &DatabaseInstance,
+        database: &Database,
     ) -> Result<Option<UpdateDatabaseResult>, anyhow::Error> {
         let state = self.worker_db.get_database_instance_state(instance.id)?;
         match state {
             Some(state) if state.initialized => self
-                .update_module_on_database_instance(instance.database_id, instance.id)
+                .update_module_on_database_instance(database.clone(), instance.id)
                 .await
                 .map(Some),
 
-            _ => self.on_insert_database_instance(instance).await.map(|()| None),
+            _ => self
+                .on_insert_database_instance(instance, database)
+                .await
+                .map(|()| None),
         }
     }
 
@@ -511,23 +536,6 @@ impl StandaloneEnv {
         }
     }
 
-    async fn load_module_host_context(
-        &self,
-        database_id: u64,
-        instance_id: u64,
-    ) -> Result<ModuleHostContext, anyhow::Error> {
-        let database = if let Some(database) = self.control_db.get_database_by_id(database_id).await? {
-            database
-        } else {
-            return Err(anyhow::anyhow!(
-                "Unknown database/instance: {}/{}",
-                database_id,
-                instance_id
-            ));
-        };
-        self.load_module_host_context_inner(database, instance_id).await
-    }
-
     async fn load_module_host_context_inner(
         &self,
         database: Database,
@@ -559,24 +567,32 @@ impl StandaloneEnv {
         Ok(mhc)
     }
 
-    async fn init_module_on_database_instance(&self, database_id: u64, instance_id: u64) -> Result<(), anyhow::Error> {
-        let module_host_context = self.load_module_host_context(database_id, instance_id).await?;
+    async fn init_module_on_database_instance(
+        &self,
+        database: Database,
+        instance_id: u64,
+    ) -> Result<(), anyhow::Error> {
+        let module_host_context = self.load_module_host_context_inner(database, instance_id).await?;
         let _address = self.host_controller.init_module_host(module_host_context).await?;
         Ok(())
     }
 
-    async fn start_module_on_database_instance(&self, database_id: u64, instance_id: u64) -> Result<(), anyhow::Error> {
-        let module_host_context = self.load_module_host_context(database_id, instance_id).await?;
+    async fn start_module_on_database_instance(
+        &self,
+        database: Database,
+        instance_id: u64,
+    ) -> Result<(), anyhow::Error> {
+        let module_host_context = self.load_module_host_context_inner(database, instance_id).await?;
         let _address = self.host_controller.add_module_host(module_host_context).await?;
         Ok(())
     }
 
     async fn update_module_on_database_instance(
         &self,
-        database_id: u64,
+        database: Database,
         instance_id: u64,
     ) -> Result<UpdateDatabaseResult, anyhow::Error> {
-        let module_host_context = self.load_module_host_context(database_id, instance_id).await?;
+        let module_host_context = self.load_module_host_context_inner(database, instance_id).await?;
         let UpdateOutcome {
             module_host: _,
             update_result,
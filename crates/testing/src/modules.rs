@@ -7,7 +7,7 @@ use std::sync::Arc;
 use spacetimedb::address::Address;
 use spacetimedb::client::{ClientActorId, ClientConnection, Protocol};
 use spacetimedb::database_logger::DatabaseLogger;
-use spacetimedb::db::Storage;
+use spacetimedb::db::{Durability, Storage};
 use spacetimedb::hash::hash_bytes;
 
 use spacetimedb::messages::control_db::HostType;
@@ -99,7 +99,7 @@ pub fn compile(path: &str) {
 #[derive(Clone)]
 pub struct ModuleHandle {
     // Needs to hold a reference to the standalone env.
-    _env: Arc<StandaloneEnv>,
+    pub env: Arc<StandaloneEnv>,
     pub client: ClientConnection,
     pub db_address: Address,
 }
@@ -140,9 +140,18 @@ pub async fn load_module(name: &str) -> ModuleHandle {
 
     let host_type = HostType::Wasmer;
 
-    env.insert_database(&address, &identity, &program_bytes_addr, host_type, 1, true, false)
-        .await
-        .unwrap();
+    env.insert_database(
+        &address,
+        &identity,
+        &program_bytes_addr,
+        host_type,
+        1,
+        true,
+        false,
+        Durability::default(),
+    )
+    .await
+    .unwrap();
 
     let database = env.get_database_by_address(&address).await.unwrap().unwrap();
     let instance = env.get_leader_database_instance_by_database(database.id).await.unwrap();
@@ -159,7 +168,7 @@ pub async fn load_module(name: &str) -> ModuleHandle {
     // the runtime on which a module was created and then we could add impl
     // for stuff like "get logs" or "get message log"
     ModuleHandle {
-        _env: env,
+        env,
         client: ClientConnection::dummy(client_id, Protocol::Text, instance.id, module),
         db_address: address,
     }
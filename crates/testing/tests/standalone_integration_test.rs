@@ -1,4 +1,6 @@
 use serde_json::Value;
+use spacetimedb::host::UpdateDatabaseError;
+use spacetimedb_client_api::{ControlCtx, ControlStateDelegate};
 use spacetimedb_testing::modules::{compile, with_module_async};
 
 #[test]
@@ -42,3 +44,37 @@ fn test_calling_a_reducer_with_private_table() {
         assert_eq!(json["message"], Value::String("Private, World!".to_string()));
     });
 }
+
+#[test]
+fn test_update_with_failing_migrate_keeps_old_module() {
+    compile("rust-wasm-test");
+    with_module_async("rust-wasm-test", |module| async move {
+        let json = r#"{"call": {"fn": "add_private", "args": ["fail migrate"]}}"#.to_string();
+        module.send(json).await.unwrap();
+
+        let database = module
+            .env
+            .get_database_by_address(&module.db_address)
+            .await
+            .unwrap()
+            .unwrap();
+        let res = module
+            .env
+            .update_database(
+                &module.db_address,
+                &database.program_bytes_address,
+                database.num_replicas,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(res, Some(Err(UpdateDatabaseError::MigrateFailed(_)))));
+
+        // The module the client is connected to still serves the database.
+        let json = r#"{"call": {"fn": "query_private", "args": []}}"#.to_string();
+        module.send(json).await.unwrap();
+
+        let lines = module.read_log(Some(10)).await;
+        assert!(lines.contains("module update rejected: migrate reducer failed"));
+        assert!(lines.contains("Private, fail migrate!"));
+    });
+}
@@ -12,6 +12,10 @@ pub enum ErrorType {
     Expect(Ty, Ty),
     #[error("Function {0} not found")]
     NotFoundFun(String),
+    #[error("Operator `{op}` can't be applied to values of type {lhs} and {rhs}")]
+    OpArgs { op: String, lhs: Ty, rhs: Ty },
+    #[error("Function `{fun}` can't be called with arguments of type ({args})")]
+    FunArgs { fun: String, args: String },
     #[error("Binary op {0:?} expect {1} arguments, but got {2}")]
     OpMiss(Op, usize, usize),
     #[error("Logic op {0:?} expect arguments that resolve to `bool`, but it got the value `{{1.to_satn()}}`")]
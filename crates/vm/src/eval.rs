@@ -8,15 +8,15 @@ use spacetimedb_sats::{product, ProductType, ProductValue};
 
 use crate::dsl::{bin_op, call_fn, if_, mem_table, scalar, var};
 use crate::errors::{ErrorKind, ErrorLang, ErrorType, ErrorVm};
+use crate::expr::{compare_rows, Function, JoinOuterExpr, LimitExpr, Query, SemiJoinExpr, SetOpExpr};
 use crate::expr::{
     Code, CrudCode, CrudExpr, CrudExprOpt, Expr, ExprOpt, FunctionOpt, QueryCode, QueryExpr, QueryExprOpt, SourceExpr,
     SourceExprOpt, TyExpr,
 };
-use crate::expr::{Function, Query};
 use crate::functions::{Args, Param};
 use crate::operator::*;
 use crate::program::ProgramVm;
-use crate::rel_ops::RelOps;
+use crate::rel_ops::{JoinKey, RelOps};
 use crate::typecheck::check_types;
 use crate::types::{ty_op, Ty};
 
@@ -83,7 +83,12 @@ fn build_typed<P: ProgramVm>(p: &mut P, node: Expr) -> ExprOpt {
 
                 ExprOpt::Query(Box::new(source))
             }
-            CrudExpr::Insert { source, rows: data } => {
+            CrudExpr::Insert {
+                source,
+                rows: data,
+                on_conflict,
+                returning,
+            } => {
                 let source = build_source(source);
                 let mut rows = Vec::with_capacity(data.len());
                 for x in data {
@@ -100,18 +105,36 @@ fn build_typed<P: ProgramVm>(p: &mut P, node: Expr) -> ExprOpt {
                     }
                     rows.push(ProductValue::new(&row))
                 }
-                ExprOpt::Crud(Box::new(CrudExprOpt::Insert { source, rows }))
+                ExprOpt::Crud(Box::new(CrudExprOpt::Insert {
+                    source,
+                    rows,
+                    on_conflict,
+                    returning,
+                }))
             }
-            CrudExpr::Update { insert, delete } => {
+            CrudExpr::Update {
+                insert,
+                delete,
+                returning,
+            } => {
                 let insert = build_query_opt(insert);
                 let delete = build_query_opt(delete);
 
-                ExprOpt::Crud(Box::new(CrudExprOpt::Update { insert, delete }))
+                ExprOpt::Crud(Box::new(CrudExprOpt::Update {
+                    insert,
+                    delete,
+                    returning,
+                }))
             }
-            CrudExpr::Delete { query } => {
+            CrudExpr::Delete { query, returning } => {
                 let query = build_query_opt(query);
 
-                ExprOpt::Crud(Box::new(CrudExprOpt::Delete { query }))
+                ExprOpt::Crud(Box::new(CrudExprOpt::Delete { query, returning }))
+            }
+            CrudExpr::Explain { query, analyze } => {
+                let query = build_query_opt(query);
+
+                ExprOpt::Crud(Box::new(CrudExprOpt::Explain { query, analyze }))
             }
             CrudExpr::CreateTable {
                 name,
@@ -124,14 +147,40 @@ fn build_typed<P: ProgramVm>(p: &mut P, node: Expr) -> ExprOpt {
                 table_type,
                 table_access,
             })),
+            CrudExpr::CreateIndex {
+                name,
+                table_name,
+                table_id,
+                cols,
+                index_type,
+                is_unique,
+            } => ExprOpt::Crud(Box::new(CrudExprOpt::CreateIndex {
+                name,
+                table_name,
+                table_id,
+                cols,
+                index_type,
+                is_unique,
+            })),
+            CrudExpr::AlterTable {
+                table_name,
+                table_id,
+                alter,
+            } => ExprOpt::Crud(Box::new(CrudExprOpt::AlterTable {
+                table_name,
+                table_id,
+                alter,
+            })),
             CrudExpr::Drop {
                 name,
                 kind,
                 table_access,
+                if_exists,
             } => ExprOpt::Crud(Box::new(CrudExprOpt::Drop {
                 name,
                 kind,
                 table_access,
+                if_exists,
             })),
         },
         x => {
@@ -249,34 +298,44 @@ fn compile<P: ProgramVm>(p: &mut P, node: ExprOpt) -> Result<Code, ErrorVm> {
             let q = *q;
 
             match q {
-                CrudExprOpt::Insert { source, rows } => {
-                    let q = match source {
-                        SourceExprOpt::Value(x) => {
-                            let data = mem_table(x.of.type_of(), vec![x.of]);
-                            CrudCode::Insert {
-                                table: Table::MemTable(data),
-                                rows,
-                            }
-                        }
-                        SourceExprOpt::MemTable(x) => CrudCode::Insert {
-                            table: Table::MemTable(x.of),
-                            rows,
-                        },
-                        SourceExprOpt::DbTable(x) => CrudCode::Insert {
-                            table: Table::DbTable(x.of),
-                            rows,
-                        },
+                CrudExprOpt::Insert {
+                    source,
+                    rows,
+                    on_conflict,
+                    returning,
+                } => {
+                    let table = match source {
+                        SourceExprOpt::Value(x) => Table::MemTable(mem_table(x.of.type_of(), vec![x.of])),
+                        SourceExprOpt::MemTable(x) => Table::MemTable(x.of),
+                        SourceExprOpt::DbTable(x) => Table::DbTable(x.of),
                     };
-                    Code::Crud(q)
+                    Code::Crud(CrudCode::Insert {
+                        table,
+                        rows,
+                        on_conflict,
+                        returning,
+                    })
                 }
-                CrudExprOpt::Update { insert, delete } => {
+                CrudExprOpt::Update {
+                    insert,
+                    delete,
+                    returning,
+                } => {
                     let insert = compile_query(insert);
                     let delete = compile_query(delete);
-                    Code::Crud(CrudCode::Update { insert, delete })
+                    Code::Crud(CrudCode::Update {
+                        insert,
+                        delete,
+                        returning,
+                    })
+                }
+                CrudExprOpt::Delete { query, returning } => {
+                    let query = compile_query(query);
+                    Code::Crud(CrudCode::Delete { query, returning })
                 }
-                CrudExprOpt::Delete { query } => {
+                CrudExprOpt::Explain { query, analyze } => {
                     let query = compile_query(query);
-                    Code::Crud(CrudCode::Delete { query })
+                    Code::Crud(CrudCode::Explain { query, analyze })
                 }
                 CrudExprOpt::CreateTable {
                     name,
@@ -289,14 +348,40 @@ fn compile<P: ProgramVm>(p: &mut P, node: ExprOpt) -> Result<Code, ErrorVm> {
                     table_type,
                     table_access,
                 }),
+                CrudExprOpt::CreateIndex {
+                    name,
+                    table_name,
+                    table_id,
+                    cols,
+                    index_type,
+                    is_unique,
+                } => Code::Crud(CrudCode::CreateIndex {
+                    name,
+                    table_name,
+                    table_id,
+                    cols,
+                    index_type,
+                    is_unique,
+                }),
+                CrudExprOpt::AlterTable {
+                    table_name,
+                    table_id,
+                    alter,
+                } => Code::Crud(CrudCode::AlterTable {
+                    table_name,
+                    table_id,
+                    alter,
+                }),
                 CrudExprOpt::Drop {
                     name,
                     kind,
                     table_access,
+                    if_exists,
                 } => Code::Crud(CrudCode::Drop {
                     name,
                     kind,
                     table_access,
+                    if_exists,
                 }),
             }
         }
@@ -403,6 +488,11 @@ pub type IterRows<'a> = dyn RelOps + 'a;
 pub fn build_query(mut result: Box<IterRows>, query: Vec<Query>) -> Result<Box<IterRows<'_>>, ErrorVm> {
     for q in query {
         result = match q {
+            // Without a database to seek, the scan filters the rows.
+            Query::IndexScan(scan) => {
+                let iter = result.select(move |row| Ok(scan.contains(row)));
+                Box::new(iter)
+            }
             Query::Select(cmp) => {
                 let iter = result.select(move |row| cmp.compare(row));
                 Box::new(iter)
@@ -415,52 +505,83 @@ pub fn build_query(mut result: Box<IterRows>, query: Vec<Query>) -> Result<Box<I
                     Box::new(iter)
                 }
             }
+            Query::Compute(cols) => Box::new(result.compute(cols)),
             Query::JoinInner(q) => {
-                //Pick the smaller set to be at the left
-                let col_lhs = FieldExpr::Name(q.col_lhs);
-                let col_rhs = FieldExpr::Name(q.col_rhs);
-                let key_lhs = col_lhs.clone();
-                let key_rhs = col_rhs.clone();
-                let row_rhs = q.rhs.row_count();
-
-                let head = q.rhs.head();
-                let rhs = match q.rhs {
-                    SourceExpr::MemTable(x) => Box::new(RelIter::new(head, row_rhs, x)) as Box<IterRows<'_>>,
-                    SourceExpr::DbTable(_) => {
-                        // let iter = stdb.scan(tx, x.table_id)?;
-                        //
-                        // Box::new(TableCursor::new(x, iter)?) as Box<IterRows<'_>>
-                        //
-                        // Box::new(RelIter::new(q.rhs.head(), row_rhs, x)) as Box<IterRows<'_>>;
-                        todo!("How pass the db iter?")
-                    }
-                };
-
-                let lhs = result;
-
-                let iter = lhs.join_inner(
+                let rhs = build_source_query(q.rhs)?;
+                let (key_lhs, key_rhs) = JoinKey::pair(result.head(), &q.col_lhs, rhs.head(), &q.col_rhs);
+                let (cmp_lhs, cmp_rhs) = (key_lhs.clone(), key_rhs.clone());
+                let iter = result.join_inner(
+                    rhs,
+                    move |row| Ok(key_lhs.get(row).into()),
+                    move |row| Ok(key_rhs.get(row).into()),
+                    move |lhs, rhs| Ok(cmp_lhs.get(lhs) == cmp_rhs.get(rhs)),
+                )?;
+                Box::new(iter)
+            }
+            Query::JoinOuter(JoinOuterExpr { kind, join: q }) => {
+                let rhs = build_source_query(q.rhs)?;
+                let (key_lhs, key_rhs) = JoinKey::pair(result.head(), &q.col_lhs, rhs.head(), &q.col_rhs);
+                let (cmp_lhs, cmp_rhs) = (key_lhs.clone(), key_rhs.clone());
+                let iter = result.join_outer(
                     rhs,
-                    move |row| {
-                        let f = row.get(&key_lhs);
-                        Ok(f.into())
-                    },
-                    move |row| {
-                        let f = row.get(&key_rhs);
-                        Ok(f.into())
-                    },
-                    move |lhs, rhs| {
-                        let lhs = lhs.get(&col_lhs);
-                        let rhs = rhs.get(&col_rhs);
-                        Ok(lhs == rhs)
-                    },
+                    move |row| Ok(key_lhs.get(row).into()),
+                    move |row| Ok(key_rhs.get(row).into()),
+                    move |lhs, rhs| Ok(cmp_lhs.get(lhs) == cmp_rhs.get(rhs)),
+                    kind.keeps_lhs(),
+                    kind.keeps_rhs(),
                 )?;
                 Box::new(iter)
             }
+            Query::JoinCross(rhs) => {
+                let rhs = build_source_query(rhs)?;
+                let iter = result.join_inner(
+                    rhs,
+                    |_| Ok(ProductValue::new(&[])),
+                    |_| Ok(ProductValue::new(&[])),
+                    |_, _| Ok(true),
+                )?;
+                Box::new(iter)
+            }
+            Query::Sort(keys) => {
+                let iter = result.sort_by(move |lhs, rhs| compare_rows(&keys, lhs, rhs));
+                Box::new(iter)
+            }
+            Query::Limit(LimitExpr { offset, limit }) => Box::new(result.limit(offset, limit)),
+            Query::Aggregate(query) => Box::new(result.aggregate(&query)?),
+            Query::Distinct => Box::new(result.distinct()),
+            Query::SetOp(SetOpExpr { op, all, rhs }) => {
+                let rhs = build_source_query(rhs)?;
+                Box::new(result.set_op(rhs, op, all)?)
+            }
+            Query::SemiJoin(SemiJoinExpr { field, rhs, negated }) => {
+                let rhs = build_source_query(rhs)?;
+                Box::new(result.semi_join(rhs, field.as_ref(), negated)?)
+            }
         };
     }
     Ok(result)
 }
 
+/// Builds the query on the right of a join, set operation or subquery.
+fn build_source_query<'a>(query: QueryExpr) -> Result<Box<IterRows<'a>>, ErrorVm> {
+    let row_count = query.source.row_count();
+    let head = query.source.head();
+    match query.source {
+        SourceExpr::MemTable(x) => {
+            let rhs = Box::new(RelIter::new(head, row_count, x)) as Box<IterRows<'_>>;
+            build_query(rhs, query.query)
+        }
+        SourceExpr::DbTable(_) => {
+            // let iter = stdb.scan(tx, x.table_id)?;
+            //
+            // Box::new(TableCursor::new(x, iter)?) as Box<IterRows<'_>>
+            //
+            // Box::new(RelIter::new(q.rhs.head(), row_rhs, x)) as Box<IterRows<'_>>;
+            todo!("How pass the db iter?")
+        }
+    }
+}
+
 /// Optimize & compile the [Expr] for late execution
 #[tracing::instrument(skip_all)]
 pub fn build_ast<P: ProgramVm>(p: &mut P, ast: Expr) -> Result<Code, ErrorVm> {
@@ -539,11 +660,13 @@ pub fn create_game_data() -> GameData {
 mod tests {
     use super::*;
     use crate::dsl::{prefix_op, query, value};
+    use crate::expr::{AggregateExpr, AggregateFn, SetOp, SortKey};
     use crate::program::Program;
     use spacetimedb_lib::auth::StAccess;
     use spacetimedb_lib::error::RelationError;
+    use spacetimedb_lib::error::ResultTest;
     use spacetimedb_lib::identity::AuthCtx;
-    use spacetimedb_lib::relation::{FieldName, MemTable};
+    use spacetimedb_lib::relation::{FieldName, MemTable, RelValue};
 
     fn fib(n: u64) -> u64 {
         if n < 2 {
@@ -776,6 +899,219 @@ mod tests {
         assert_eq!(result.as_without_table_name(), input.as_without_table_name(), "Project");
     }
 
+    #[test]
+    fn test_join_inner_duplicated_keys() {
+        let p = &mut Program::new(AuthCtx::for_testing());
+        let head = ProductType::from_iter([("id", BuiltinType::U64), ("name", BuiltinType::String)]);
+        let rows = vec![product!(scalar(1u64), scalar("a")), product!(scalar(1u64), scalar("b"))];
+        let table = mem_table(head, rows);
+        let field = table.get_field_named("id").unwrap().clone();
+
+        // Every row of the left matches both rows of the right.
+        let q = query(table.clone()).with_join_inner(table, field.clone(), field);
+        let result = run_query(p, q.into());
+        let mut names: Vec<_> = result
+            .data
+            .iter()
+            .map(|row| (row.elements[1].clone(), row.elements[3].clone()))
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                (scalar("a"), scalar("a")),
+                (scalar("a"), scalar("b")),
+                (scalar("b"), scalar("a")),
+                (scalar("b"), scalar("b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_join_index() -> ResultTest<()> {
+        let head = ProductType::from_iter([("id", BuiltinType::U64)]);
+        let lhs = mem_table(head.clone(), vec![product!(scalar(1u64)), product!(scalar(2u64))]);
+        let rhs = mem_table(head, vec![product!(scalar(2u64)), product!(scalar(3u64))]);
+        let id = FieldExpr::Name(lhs.get_field_named("id").unwrap().clone());
+
+        let lhs_head = lhs.head.clone();
+        let lhs = RelIter::new(lhs_head, lhs.row_count(), lhs);
+        let iter = lhs.join_index(&rhs.head, |row| {
+            let value = row.get(&id);
+            Ok(rhs
+                .data
+                .iter()
+                .filter(|x| &x.elements[0] == value)
+                .map(|x| RelValue::new(&rhs.head, x))
+                .collect())
+        });
+        let result = iter.collect_vec()?;
+        assert_eq!(result, vec![product!(scalar(2u64), scalar(2u64))]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_limit() {
+        let p = &mut Program::new(AuthCtx::for_testing());
+
+        let head = ProductType::from_iter([("id", BuiltinType::U64), ("name", BuiltinType::String)]);
+        let rows = vec![
+            product!(scalar(2u64), scalar("b")),
+            product!(scalar(1u64), scalar("b")),
+            product!(scalar(3u64), scalar("a")),
+        ];
+        let input = mem_table(head, rows);
+        let id = input.get_field(0).unwrap().clone();
+        let name = input.get_field(1).unwrap().clone();
+
+        let q =
+            query(input.clone()).with_sort(&[SortKey::new(name.into(), true), SortKey::new(id.clone().into(), false)]);
+        let result = run_query(p, q.into());
+        let ids = result
+            .data
+            .iter()
+            .map(|row| row.elements[0].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![scalar(3u64), scalar(2u64), scalar(1u64)], "Sort");
+
+        let q = query(input)
+            .with_sort(&[SortKey::new(id.into(), true)])
+            .with_limit(1, Some(1));
+        let result = run_query(p, q.into());
+        let ids = result
+            .data
+            .iter()
+            .map(|row| row.elements[0].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![scalar(2u64)], "Limit");
+    }
+
+    #[test]
+    fn test_set_op_all() {
+        let p = &mut Program::new(AuthCtx::for_testing());
+        let head = ProductType::from_iter([("id", BuiltinType::U64)]);
+        let ids = |xs: &[u64]| xs.iter().map(|x| product!(scalar(*x))).collect::<Vec<_>>();
+        let lhs = mem_table(head.clone(), ids(&[1, 1, 1, 2]));
+        let rhs = mem_table(head, ids(&[1, 3]));
+
+        let run = |p: &mut Program, op, all| {
+            let q = query(lhs.clone()).with_set_op(op, all, rhs.clone());
+            let mut rows = run_query(p, q.into()).data;
+            rows.sort();
+            rows
+        };
+        assert_eq!(run(p, SetOp::Union, false), ids(&[1, 2, 3]));
+        assert_eq!(run(p, SetOp::Union, true), ids(&[1, 1, 1, 1, 2, 3]));
+        assert_eq!(run(p, SetOp::Intersect, false), ids(&[1]));
+        assert_eq!(run(p, SetOp::Intersect, true), ids(&[1]));
+        assert_eq!(run(p, SetOp::Except, false), ids(&[2]));
+        assert_eq!(run(p, SetOp::Except, true), ids(&[1, 1, 2]));
+    }
+
+    #[test]
+    fn test_semi_join_null() {
+        let p = &mut Program::new(AuthCtx::for_testing());
+        let head = ProductType::from_iter([("id", AlgebraicType::option(AlgebraicType::U64))]);
+        let some = |x: u64| product!(AlgebraicValue::OptionSome(x.into()));
+        let none = product!(AlgebraicValue::OptionNone());
+        let lhs = mem_table(head.clone(), vec![some(1), none.clone(), some(2)]);
+        let field = lhs.get_field_named("id").unwrap().clone();
+
+        let run = |p: &mut Program, rhs: &MemTable, negated| {
+            let q = query(lhs.clone()).with_semi_join(Some(field.clone()), rhs.clone(), negated);
+            run_query(p, q.into()).data
+        };
+        let rhs = mem_table(head, vec![some(1), none]);
+        assert_eq!(run(p, &rhs, false), vec![some(1)]);
+        // No row is known not to be in a set with a `NULL`, as in SQL.
+        assert_eq!(run(p, &rhs, true), vec![]);
+
+        let rhs = mem_table(
+            ProductType::from_iter([("id", BuiltinType::U64)]),
+            vec![product!(scalar(2u64))],
+        );
+        assert_eq!(run(p, &rhs, false), vec![some(2)]);
+        assert_eq!(run(p, &rhs, true), vec![some(1)]);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let p = &mut Program::new(AuthCtx::for_testing());
+
+        let head = ProductType::from_iter([("name", BuiltinType::String), ("score", BuiltinType::I32)]);
+        let rows = vec![
+            product!(scalar("b"), scalar(3i32)),
+            product!(scalar("a"), scalar(-1i32)),
+            product!(scalar("b"), scalar(5i32)),
+        ];
+        let input = mem_table(head, rows);
+        let name = input.get_field(0).unwrap().clone();
+        let score = input.get_field(1).unwrap().clone();
+        let table = input.head.table_name.clone();
+        let aggregate = |fun, arg: Option<&FieldName>, field| AggregateExpr {
+            fun,
+            arg: arg.cloned(),
+            field: FieldName::named(&table, field),
+        };
+
+        let q = query(input.clone()).with_aggregate(
+            &[name.clone()],
+            &[
+                aggregate(AggregateFn::Count, None, "count(*)"),
+                aggregate(AggregateFn::Sum, Some(&score), "sum(score)"),
+                aggregate(AggregateFn::Max, Some(&score), "max(score)"),
+            ],
+        );
+        let result = run_query(p, q.into());
+        assert_eq!(
+            result.head.ty(),
+            ProductType::from_iter([
+                ("name", AlgebraicType::String),
+                ("count(*)", AlgebraicType::U64),
+                ("sum(score)", AlgebraicType::I64),
+                ("max(score)", AlgebraicType::option(AlgebraicType::I32)),
+            ]),
+            "Aggregate header"
+        );
+        assert_eq!(
+            result.data,
+            vec![
+                product!(
+                    scalar("a"),
+                    scalar(1u64),
+                    scalar(-1i64),
+                    AlgebraicValue::OptionSome(scalar(-1i32))
+                ),
+                product!(
+                    scalar("b"),
+                    scalar(2u64),
+                    scalar(8i64),
+                    AlgebraicValue::OptionSome(scalar(5i32))
+                ),
+            ],
+            "Group by"
+        );
+
+        let q = query(input.clone())
+            .with_select_cmp(OpCmp::Gt, score.clone(), scalar(10i32))
+            .with_aggregate(
+                &[],
+                &[
+                    aggregate(AggregateFn::Count, None, "count(*)"),
+                    aggregate(AggregateFn::Avg, Some(&score), "avg(score)"),
+                ],
+            );
+        let result = run_query(p, q.into());
+        assert_eq!(
+            result.data,
+            vec![product!(scalar(0u64), AlgebraicValue::OptionNone())],
+            "Aggregate without rows"
+        );
+
+        let q = query(input).with_aggregate(&[], &[aggregate(AggregateFn::Sum, Some(&name), "sum(name)")]);
+        assert!(matches!(run_ast(p, q.into()), Code::Halt(_)), "Sum of strings");
+    }
+
     #[test]
     fn test_query_logic() {
         let p = &mut Program::new(AuthCtx::for_testing());
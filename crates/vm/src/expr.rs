@@ -1,21 +1,25 @@
 use spacetimedb_lib::auth::{StAccess, StTableType};
 use spacetimedb_lib::error::AuthError;
 use spacetimedb_lib::table::ProductTypeMeta;
-use spacetimedb_lib::Identity;
+use spacetimedb_lib::{ColumnIndexAttribute, Identity, IndexType};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt;
+use std::ops::{Bound, RangeBounds};
 
 use spacetimedb_lib::relation::{
-    DbTable, FieldExpr, FieldName, Header, MemTable, RelValueRef, Relation, RowCount, Table,
+    Column, DbTable, FieldExpr, FieldName, Header, MemTable, RelValueRef, Relation, RowCount, Table,
 };
 use spacetimedb_sats::algebraic_type::AlgebraicType;
 use spacetimedb_sats::algebraic_value::AlgebraicValue;
+use spacetimedb_sats::builtin_type::BuiltinType;
 use spacetimedb_sats::satn::Satn;
-use spacetimedb_sats::{ProductValue, Typespace, WithTypespace};
+use spacetimedb_sats::{ProductTypeElement, ProductValue, Typespace, WithTypespace};
 
 use crate::errors::{ErrorKind, ErrorLang, ErrorType, ErrorVm};
-use crate::functions::{FunDef, Param};
-use crate::operator::{Op, OpCmp, OpLogic, OpQuery};
+use crate::functions::{FunDef, Param, ScalarFn};
+use crate::operator::{Op, OpCmp, OpLogic, OpMath, OpQuery};
+use crate::ops::math::checked_math;
 use crate::types::Ty;
 
 /// A `index` into the list of [Fun]
@@ -76,6 +80,19 @@ pub enum ColumnOp {
         lhs: Box<ColumnOp>,
         rhs: Box<ColumnOp>,
     },
+    /// An arithmetic operation on numbers of the same type, as in `score * 2`.
+    Math {
+        op: OpMath,
+        lhs: Box<ColumnOp>,
+        rhs: Box<ColumnOp>,
+    },
+    /// A call of a built-in scalar function, as in `lower(name)`.
+    Call {
+        fun: ScalarFn,
+        args: Vec<ColumnOp>,
+    },
+    /// Negates a condition, as in `NOT name LIKE 'a%'`.
+    Not(Box<ColumnOp>),
 }
 
 impl ColumnOp {
@@ -87,65 +104,156 @@ impl ColumnOp {
         }
     }
 
+    pub fn math(op: OpMath, lhs: ColumnOp, rhs: ColumnOp) -> Self {
+        Self::Math {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }
+    }
+
+    /// Returns the expressions this one is computed from.
+    pub fn args(&self) -> Vec<&ColumnOp> {
+        match self {
+            ColumnOp::Field(_) => Vec::new(),
+            ColumnOp::Cmp { lhs, rhs, .. } | ColumnOp::Math { lhs, rhs, .. } => vec![lhs, rhs],
+            ColumnOp::Call { args, .. } => args.iter().collect(),
+            ColumnOp::Not(x) => vec![x],
+        }
+    }
+
     fn reduce(&self, row: RelValueRef, value: &ColumnOp) -> Result<AlgebraicValue, ErrorLang> {
         match value {
             ColumnOp::Field(field) => Ok(row.get(field).clone()),
-            ColumnOp::Cmp { op, lhs, rhs } => Ok(self.compare_bin_op(row, *op, lhs, rhs)?.into()),
+            // An unknown comparison, as with a `none` operand, is `false`.
+            ColumnOp::Cmp { op, lhs, rhs } => Ok(self.compare_bin_op(row, *op, lhs, rhs)?.unwrap_or(false).into()),
+            ColumnOp::Math { op, lhs, rhs } => {
+                let lhs = self.reduce(row, lhs)?;
+                let rhs = self.reduce(row, rhs)?;
+                // A `none` operand yields `none`, and `some` operands yield `some`.
+                match (as_option(&lhs), as_option(&rhs)) {
+                    (Some(None), _) | (_, Some(None)) => Ok(AlgebraicValue::OptionNone()),
+                    (None, None) => checked_math(*op, &lhs, &rhs),
+                    (x, y) => {
+                        let x = x.flatten().unwrap_or(&lhs);
+                        let y = y.flatten().unwrap_or(&rhs);
+                        checked_math(*op, x, y).map(AlgebraicValue::OptionSome)
+                    }
+                }
+            }
+            ColumnOp::Call { fun, args } => {
+                let args = args
+                    .iter()
+                    .map(|x| self.reduce(row, x))
+                    .collect::<Result<Vec<_>, _>>()?;
+                fun.call(&args)
+            }
+            // The negation of an unknown comparison is unknown too, so also `false`.
+            ColumnOp::Not(x) if matches!(**x, ColumnOp::Cmp { .. }) => {
+                Ok(self.reduce_truth(row, x)?.map_or(false, |x| !x).into())
+            }
+            ColumnOp::Not(x) => {
+                let x = self.reduce(row, x)?;
+                match as_option(&x) {
+                    Some(None) => Ok(x),
+                    Some(Some(x)) => Ok(AlgebraicValue::OptionSome((!to_bool(x)?).into())),
+                    None => Ok((!to_bool(&x)?).into()),
+                }
+            }
         }
     }
 
+    /// Reduces `value` to a `bool`, where unknown, as in `NULL LIKE 'a%'`, is `false`.
     fn reduce_bool(&self, row: RelValueRef, value: &ColumnOp) -> Result<bool, ErrorLang> {
-        match value {
-            ColumnOp::Field(field) => {
-                let field = row.get(field);
+        Ok(self.reduce_truth(row, value)?.unwrap_or(false))
+    }
 
-                match field.as_bool() {
-                    Some(b) => Ok(*b),
-                    None => Err(ErrorType::FieldBool(field.clone()).into()),
+    /// Reduces the condition `value` to a `bool`, or to `None` when it is unknown,
+    /// as when it is `none` or compares a `none`.
+    fn reduce_truth(&self, row: RelValueRef, value: &ColumnOp) -> Result<Option<bool>, ErrorLang> {
+        match value {
+            ColumnOp::Cmp { op, lhs, rhs } => Ok(self.compare_bin_op(row, *op, lhs, rhs)?),
+            ColumnOp::Not(x) => Ok(self.reduce_truth(row, x)?.map(|x| !x)),
+            value => {
+                let value = self.reduce(row, value)?;
+                match as_option(&value) {
+                    Some(None) => Ok(None),
+                    Some(Some(x)) => to_bool(x).map(Some),
+                    None => to_bool(&value).map(Some),
                 }
             }
-            ColumnOp::Cmp { op, lhs, rhs } => Ok(self.compare_bin_op(row, *op, lhs, rhs)?),
         }
     }
 
-    fn compare_bin_op(&self, row: RelValueRef, op: OpQuery, lhs: &ColumnOp, rhs: &ColumnOp) -> Result<bool, ErrorVm> {
+    /// Computes the condition `lhs op rhs`, or `None` when it is unknown.
+    ///
+    /// Like in SQL, a comparison with a `none` operand, as in `x > NULL`, is unknown,
+    /// and so is a logical operation on an unknown operand whose result depends on it.
+    fn compare_bin_op(
+        &self,
+        row: RelValueRef,
+        op: OpQuery,
+        lhs: &ColumnOp,
+        rhs: &ColumnOp,
+    ) -> Result<Option<bool>, ErrorVm> {
         match op {
             OpQuery::Cmp(op) => {
                 let lhs = self.reduce(row, lhs)?;
                 let rhs = self.reduce(row, rhs)?;
+                // A `none` operand makes the comparison unknown, and `some(x)` compares as `x`.
+                let (lhs, rhs) = match (as_option(&lhs), as_option(&rhs)) {
+                    (Some(None), _) | (_, Some(None)) => return Ok(None),
+                    (x, y) => (x.flatten().unwrap_or(&lhs), y.flatten().unwrap_or(&rhs)),
+                };
 
-                Ok(match op {
+                Ok(Some(match op {
                     OpCmp::Eq => lhs == rhs,
                     OpCmp::NotEq => lhs != rhs,
                     OpCmp::Lt => lhs < rhs,
                     OpCmp::LtEq => lhs <= rhs,
                     OpCmp::Gt => lhs > rhs,
                     OpCmp::GtEq => lhs >= rhs,
-                })
+                }))
             }
             OpQuery::Logic(op) => {
-                let lhs = self.reduce_bool(row, lhs)?;
-                let rhs = self.reduce_bool(row, rhs)?;
-
-                Ok(match op {
-                    OpLogic::And => lhs && rhs,
-                    OpLogic::Or => lhs || rhs,
+                let lhs = self.reduce_truth(row, lhs)?;
+                let rhs = self.reduce_truth(row, rhs)?;
+
+                Ok(match (op, lhs, rhs) {
+                    (OpLogic::And, Some(false), _) | (OpLogic::And, _, Some(false)) => Some(false),
+                    (OpLogic::And, Some(true), Some(true)) => Some(true),
+                    (OpLogic::Or, Some(true), _) | (OpLogic::Or, _, Some(true)) => Some(true),
+                    (OpLogic::Or, Some(false), Some(false)) => Some(false),
+                    _ => None,
                 })
             }
         }
     }
 
+    /// Computes the value of the expression for the `row`.
+    pub fn eval(&self, row: RelValueRef) -> Result<AlgebraicValue, ErrorVm> {
+        Ok(self.reduce(row, self)?)
+    }
+
     pub fn compare(&self, row: RelValueRef) -> Result<bool, ErrorVm> {
         match self {
             ColumnOp::Field(field) => {
                 let lhs = row.get(field);
                 Ok(*lhs.as_bool().unwrap())
             }
-            ColumnOp::Cmp { op, lhs, rhs } => self.compare_bin_op(row, *op, lhs, rhs),
+            ColumnOp::Cmp { op, lhs, rhs } => Ok(self.compare_bin_op(row, *op, lhs, rhs)?.unwrap_or(false)),
+            x => Ok(self.reduce_bool(row, x)?),
         }
     }
 }
 
+fn to_bool(value: &AlgebraicValue) -> Result<bool, ErrorLang> {
+    match value.as_bool() {
+        Some(b) => Ok(*b),
+        None => Err(ErrorType::FieldBool(value.clone()).into()),
+    }
+}
+
 impl fmt::Display for ColumnOp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -155,11 +263,39 @@ impl fmt::Display for ColumnOp {
             ColumnOp::Cmp { op, lhs, rhs } => {
                 write!(f, "{} {} {}", lhs, op, rhs)
             }
+            ColumnOp::Math { op, lhs, rhs } => {
+                write!(f, "({} {} {})", lhs, op, rhs)
+            }
+            ColumnOp::Call {
+                fun: ScalarFn::Like,
+                args,
+            } if args.len() == 2 => {
+                write!(f, "{} like {}", args[0], args[1])
+            }
+            ColumnOp::Call {
+                fun: ScalarFn::IsNull,
+                args,
+            } if args.len() == 1 => {
+                write!(f, "{} is null", args[0])
+            }
+            ColumnOp::Call { fun, args } => {
+                write!(f, "{fun}(")?;
+                for (pos, x) in args.iter().enumerate() {
+                    write!(f, "{x}")?;
+                    if pos + 1 < args.len() {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+            ColumnOp::Not(x) => {
+                write!(f, "not {}", x)
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub enum SourceExpr {
     MemTable(MemTable),
     DbTable(DbTable),
@@ -223,19 +359,110 @@ impl From<DbTable> for SourceExpr {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub struct JoinExpr {
-    pub rhs: SourceExpr,
+    pub rhs: QueryExpr,
     pub col_lhs: FieldName,
     pub col_rhs: FieldName,
 }
 
 impl JoinExpr {
-    pub fn new(rhs: SourceExpr, col_lhs: FieldName, col_rhs: FieldName) -> Self {
+    pub fn new(rhs: QueryExpr, col_lhs: FieldName, col_rhs: FieldName) -> Self {
         Self { rhs, col_lhs, col_rhs }
     }
 }
 
+/// Which rows of an outer join are kept when they match no row of the other side.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub enum OuterJoin {
+    /// As in `LEFT JOIN`, keeps the rows of the left.
+    Left,
+    /// As in `RIGHT JOIN`, keeps the rows of the right.
+    Right,
+    /// As in `FULL JOIN`, keeps the rows of both sides.
+    Full,
+}
+
+impl OuterJoin {
+    /// Whether the rows of the left that match no row of the right are kept.
+    pub fn keeps_lhs(self) -> bool {
+        matches!(self, OuterJoin::Left | OuterJoin::Full)
+    }
+
+    /// Whether the rows of the right that match no row of the left are kept.
+    pub fn keeps_rhs(self) -> bool {
+        matches!(self, OuterJoin::Right | OuterJoin::Full)
+    }
+}
+
+impl fmt::Display for OuterJoin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OuterJoin::Left => write!(f, "left"),
+            OuterJoin::Right => write!(f, "right"),
+            OuterJoin::Full => write!(f, "full"),
+        }
+    }
+}
+
+/// A [JoinExpr] that also yields the rows that match no row of the other side, as kept by `kind`,
+/// with the columns of the other side set to `none`.
+///
+/// As there are no nulls, the columns of a side that may be missing are of option types in the result.
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct JoinOuterExpr {
+    pub kind: OuterJoin,
+    pub join: JoinExpr,
+}
+
+/// How the rows of two queries are combined, as in `query1 UNION query2`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub enum SetOp {
+    /// As in `UNION`, yields the rows of both queries.
+    Union,
+    /// As in `INTERSECT`, yields the rows of the left that are also rows of the right.
+    Intersect,
+    /// As in `EXCEPT`, yields the rows of the left that are not rows of the right.
+    Except,
+}
+
+impl fmt::Display for SetOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetOp::Union => write!(f, "union"),
+            SetOp::Intersect => write!(f, "intersect"),
+            SetOp::Except => write!(f, "except"),
+        }
+    }
+}
+
+/// Combines the rows with the rows of the query `rhs` by `op`.
+///
+/// Unless `all` is set, as in `UNION ALL`, the duplicated rows of the result are removed.
+/// With `all`, a row is kept by `INTERSECT` as many times as it's on both sides,
+/// and by `EXCEPT` as many times as it's more on the left than on the right.
+///
+/// Both queries must yield rows of the same types, and the rows keep the [Header] of the left.
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct SetOpExpr {
+    pub op: SetOp,
+    pub all: bool,
+    pub rhs: QueryExpr,
+}
+
+/// Keeps the rows whose column `field` is among the values of the single column of the query `rhs`,
+/// as in `field IN (SELECT ...)`, or when `field` is `None`, all the rows if `rhs` yields any, as in `EXISTS (SELECT ...)`.
+///
+/// When `negated`, keeps the rows that the other form drops, as in `NOT IN` and `NOT EXISTS`.
+/// As in SQL, a `none` is never known to be in, or not in, the values, so `NOT IN` drops the rows with a `none`
+/// in `field`, and every row if `rhs` yields a `none`, unless `rhs` yields no rows.
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct SemiJoinExpr {
+    pub field: Option<FieldName>,
+    pub rhs: QueryExpr,
+    pub negated: bool,
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
 pub enum DbType {
     Table,
@@ -250,22 +477,48 @@ pub enum Crud {
     Update,
     Delete,
     Create(DbType),
+    Alter(DbType),
     Drop(DbType),
 }
 
+/// A change to the definition of an existing table, as in `ALTER TABLE`.
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub enum AlterTable {
+    /// Renames the table to `new_name`.
+    Rename { new_name: String },
+    /// Appends `column` to the table, set to `default` in the rows it already has.
+    AddColumn {
+        column: ProductTypeElement,
+        attr: ColumnIndexAttribute,
+        default: AlgebraicValue,
+    },
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
 pub enum CrudExpr {
     Query(QueryExpr),
+    /// Inserts the `rows`, yielding the `returning` columns computed from the rows inserted, if any.
     Insert {
         source: SourceExpr,
         rows: Vec<Vec<FieldExpr>>,
+        on_conflict: Option<OnConflict>,
+        returning: Option<Vec<ComputedColumn>>,
     },
+    /// Replaces the rows of `delete` by the rows of `insert`, yielding the `returning` columns computed from the new rows, if any.
     Update {
         insert: QueryExpr,
         delete: QueryExpr,
+        returning: Option<Vec<ComputedColumn>>,
     },
+    /// Deletes the rows of `query`, yielding the `returning` columns computed from them, if any.
     Delete {
         query: QueryExpr,
+        returning: Option<Vec<ComputedColumn>>,
+    },
+    /// Describes how `query` is executed, and when `analyze` is set, executes it to measure each operation.
+    Explain {
+        query: QueryExpr,
+        analyze: bool,
     },
     CreateTable {
         name: String,
@@ -273,10 +526,24 @@ pub enum CrudExpr {
         table_type: StTableType,
         table_access: StAccess,
     },
+    CreateIndex {
+        name: String,
+        table_name: String,
+        table_id: u32,
+        cols: Vec<u32>,
+        index_type: IndexType,
+        is_unique: bool,
+    },
+    AlterTable {
+        table_name: String,
+        table_id: u32,
+        alter: AlterTable,
+    },
     Drop {
         name: String,
         kind: DbType,
         table_access: StAccess,
+        if_exists: bool,
     },
 }
 
@@ -298,19 +565,341 @@ pub enum CrudExpr {
 //     }
 // }
 
+/// A key to sort the rows by, as in `ORDER BY field [ASC | DESC]`.
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct SortKey {
+    pub field: FieldExpr,
+    pub asc: bool,
+}
+
+impl SortKey {
+    pub fn new(field: FieldExpr, asc: bool) -> Self {
+        Self { field, asc }
+    }
+
+    /// Compares the rows `lhs` and `rhs` by the value of this key.
+    pub fn compare(&self, lhs: RelValueRef, rhs: RelValueRef) -> Ordering {
+        let ord = lhs.get(&self.field).cmp(rhs.get(&self.field));
+        if self.asc {
+            ord
+        } else {
+            ord.reverse()
+        }
+    }
+}
+
+/// Compares the rows `lhs` and `rhs` by `keys`, from the most to the least significant key.
+pub fn compare_rows(keys: &[SortKey], lhs: RelValueRef, rhs: RelValueRef) -> Ordering {
+    keys.iter()
+        .map(|key| key.compare(lhs, rhs))
+        .find(|ord| ord.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Skips the first `offset` rows, then yields at most `limit` rows, as in `LIMIT limit OFFSET offset`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct LimitExpr {
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+/// An aggregate function, as in `COUNT(*)` or `SUM(field)`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub enum AggregateFn {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl AggregateFn {
+    /// Returns the type of the result of this function over a column of type `arg`,
+    /// or `None` if the function can't aggregate values of that type.
+    ///
+    /// `arg` is `None` for `COUNT(*)`, which counts rows rather than values.
+    /// The values of a column of an option type are aggregated skipping the `none`s.
+    /// `MIN`, `MAX` and `AVG` are undefined over no values, so their result is an option,
+    /// whereas `SUM` over no values is zero.
+    pub fn result_type(self, arg: Option<&AlgebraicType>) -> Option<AlgebraicType> {
+        let arg = arg.map(|ty| option_type(ty).unwrap_or(ty));
+        match (self, arg) {
+            (Self::Count, _) => Some(AlgebraicType::U64),
+            (_, None) => None,
+            (Self::Sum, Some(ty)) => sum_type(ty),
+            (Self::Min | Self::Max, Some(ty)) => Some(AlgebraicType::option(ty.clone())),
+            (Self::Avg, Some(ty)) => sum_type(ty).map(|_| AlgebraicType::option(AlgebraicType::F64)),
+        }
+    }
+}
+
+impl fmt::Display for AggregateFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Count => "count",
+            Self::Sum => "sum",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Avg => "avg",
+        })
+    }
+}
+
+/// Returns the type of the `some` variant when `ty` is an option type.
+pub fn option_type(ty: &AlgebraicType) -> Option<&AlgebraicType> {
+    match ty {
+        AlgebraicType::Sum(sum) => match &*sum.variants {
+            [some, none] if some.has_name("some") && none.has_name("none") => Some(&some.algebraic_type),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the value of the `some` variant when `value` is an option, `Some(None)` when it's `none`,
+/// and `None` when it isn't an option.
+///
+/// Any sum value of tag `0`, or of tag `1` and no payload, is taken for an option,
+/// as the values it is used on are either options or builtin values.
+pub(crate) fn as_option(value: &AlgebraicValue) -> Option<Option<&AlgebraicValue>> {
+    match value {
+        AlgebraicValue::Sum(x) if x.tag == 0 => Some(Some(&x.value)),
+        AlgebraicValue::Sum(x) if x.tag == 1 && *x.value == AlgebraicValue::UNIT => Some(None),
+        _ => None,
+    }
+}
+
+/// Whether `ty` is an integer or float type.
+pub(crate) fn is_number(ty: &AlgebraicType) -> bool {
+    sum_type(ty).is_some()
+}
+
+/// Returns the type of the `SUM` of numbers of type `ty`, or `None` if `ty` isn't a number type.
+fn sum_type(ty: &AlgebraicType) -> Option<AlgebraicType> {
+    let AlgebraicType::Builtin(ty) = ty else {
+        return None;
+    };
+    Some(match ty {
+        BuiltinType::I8 | BuiltinType::I16 | BuiltinType::I32 | BuiltinType::I64 => AlgebraicType::I64,
+        BuiltinType::U8 | BuiltinType::U16 | BuiltinType::U32 | BuiltinType::U64 => AlgebraicType::U64,
+        BuiltinType::I128 => AlgebraicType::I128,
+        BuiltinType::U128 => AlgebraicType::U128,
+        BuiltinType::F32 | BuiltinType::F64 => AlgebraicType::F64,
+        _ => return None,
+    })
+}
+
+/// A call of the aggregate function `fun` over the column `arg`, or over the rows when `arg` is `None`,
+/// which results in the column `field` of the aggregated rows.
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct AggregateExpr {
+    pub fun: AggregateFn,
+    pub arg: Option<FieldName>,
+    pub field: FieldName,
+}
+
+impl fmt::Display for AggregateExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.arg {
+            Some(arg) => write!(f, "{}({arg})", self.fun),
+            None => write!(f, "{}(*)", self.fun),
+        }
+    }
+}
+
+/// Groups the rows by the values of the columns `group_by` and computes the `aggregates` of each group,
+/// as in `SELECT ... GROUP BY ...`.
+///
+/// Yields a row per group, made of the `group_by` columns followed by the `aggregates`,
+/// in the order of the values of the `group_by` columns.
+/// Without `group_by`, all the rows make up a single group, even when there are none.
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct AggregateQuery {
+    pub group_by: Vec<FieldName>,
+    pub aggregates: Vec<AggregateExpr>,
+}
+
+/// Selects the rows whose column `field` falls within the bounds,
+/// which the database serves by seeking an index on the column instead of scanning the whole table.
+///
+/// It is only served by an index as the first operation on a [SourceExpr::DbTable],
+/// elsewhere it filters the rows like a [Query::Select].
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct IndexScan {
+    pub field: FieldName,
+    pub lower_bound: Bound<AlgebraicValue>,
+    pub upper_bound: Bound<AlgebraicValue>,
+}
+
+impl IndexScan {
+    /// Returns the value of `field` when the bounds only admit that value, as in `field = value`.
+    pub fn as_eq(&self) -> Option<&AlgebraicValue> {
+        match (&self.lower_bound, &self.upper_bound) {
+            (Bound::Included(lower), Bound::Included(upper)) if lower == upper => Some(lower),
+            _ => None,
+        }
+    }
+
+    /// Returns the bounds as a range of values.
+    pub fn range(&self) -> (Bound<AlgebraicValue>, Bound<AlgebraicValue>) {
+        (self.lower_bound.clone(), self.upper_bound.clone())
+    }
+
+    /// Whether the value of `field` in `row` falls within the bounds.
+    pub fn contains(&self, row: RelValueRef) -> bool {
+        let field = FieldExpr::Name(self.field.clone());
+        (self.lower_bound.as_ref(), self.upper_bound.as_ref()).contains(row.get(&field))
+    }
+
+    /// Returns the bounds in a form that can be ordered, as [Bound] is not [Ord].
+    fn bounds(&self) -> [(u8, Option<&AlgebraicValue>); 2] {
+        fn key(bound: &Bound<AlgebraicValue>) -> (u8, Option<&AlgebraicValue>) {
+            match bound {
+                Bound::Included(x) => (0, Some(x)),
+                Bound::Excluded(x) => (1, Some(x)),
+                Bound::Unbounded => (2, None),
+            }
+        }
+        [key(&self.lower_bound), key(&self.upper_bound)]
+    }
+}
+
+impl PartialOrd for IndexScan {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexScan {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.field
+            .cmp(&other.field)
+            .then_with(|| self.bounds().cmp(&other.bounds()))
+    }
+}
+
+impl fmt::Display for IndexScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |x: &AlgebraicValue| FieldExpr::Value(x.clone());
+        if let Some(x) = self.as_eq() {
+            return write!(f, "{} = {}", self.field, value(x));
+        }
+        match &self.lower_bound {
+            Bound::Included(x) => write!(f, "{} >= {}", self.field, value(x))?,
+            Bound::Excluded(x) => write!(f, "{} > {}", self.field, value(x))?,
+            Bound::Unbounded => {}
+        }
+        if !matches!(self.lower_bound, Bound::Unbounded) && !matches!(self.upper_bound, Bound::Unbounded) {
+            write!(f, " AND ")?;
+        }
+        match &self.upper_bound {
+            Bound::Included(x) => write!(f, "{} <= {}", self.field, value(x)),
+            Bound::Excluded(x) => write!(f, "{} < {}", self.field, value(x)),
+            Bound::Unbounded => Ok(()),
+        }
+    }
+}
+
+/// A column computed from each row by `expr`, named `field`, whose values are of type `ty`.
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct ComputedColumn {
+    pub expr: ColumnOp,
+    pub field: FieldName,
+    pub ty: AlgebraicType,
+}
+
+/// What an `INSERT` does with a row whose values in the unique columns `cols` are already in the table,
+/// as in `INSERT ... ON CONFLICT (cols) DO NOTHING` or `DO UPDATE SET ...`.
+///
+/// When `cols` is empty, it applies to the rows that violate any unique constraint.
 #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub struct OnConflict {
+    pub cols: Vec<u32>,
+    pub action: ConflictAction,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub enum ConflictAction {
+    /// Skips the row to insert.
+    DoNothing,
+    /// Replaces the existing row by the row of `set`, computed from the existing row and the row to insert,
+    /// when they pass the `selection`, or skips the row to insert otherwise.
+    ///
+    /// Both rows are laid out by [OnConflict::header].
+    DoUpdate {
+        set: Vec<ComputedColumn>,
+        selection: Option<ColumnOp>,
+    },
+}
+
+impl OnConflict {
+    /// The name of the table the row to insert is referred to by, as in `SET score = excluded.score`.
+    pub const EXCLUDED: &'static str = "excluded";
+
+    /// Returns the header of the rows `DO UPDATE` computes from,
+    /// the columns of the existing row of the table of `head`, followed by the columns of the row to insert.
+    pub fn header(head: &Header) -> Header {
+        let excluded: Vec<_> = head
+            .fields
+            .iter()
+            .map(|x| {
+                let field = match &x.field {
+                    FieldName::Name { field, .. } => FieldName::named(Self::EXCLUDED, field),
+                    FieldName::Pos { field, .. } => FieldName::positional(Self::EXCLUDED, *field),
+                };
+                Column::new(field, x.algebraic_type.clone())
+            })
+            .collect();
+        let mut fields = head.fields.clone();
+        fields.extend(excluded);
+        Header::new(&head.table_name, &fields)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub enum Query {
+    IndexScan(IndexScan),
     Select(ColumnOp),
     Project(Vec<FieldExpr>),
+    /// Projects each row onto the columns computed from it, as in `SELECT score * 2 AS double, name`.
+    Compute(Vec<ComputedColumn>),
     JoinInner(JoinExpr),
+    JoinOuter(JoinOuterExpr),
+    /// Joins every row with every row of the query, as in `FROM a, b`.
+    JoinCross(QueryExpr),
+    Sort(Vec<SortKey>),
+    Limit(LimitExpr),
+    Aggregate(AggregateQuery),
+    /// Removes the duplicated rows, as in `SELECT DISTINCT`.
+    Distinct,
+    SetOp(SetOpExpr),
+    SemiJoin(SemiJoinExpr),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct QueryExpr {
     pub source: SourceExpr,
     pub query: Vec<Query>,
 }
 
+impl From<SourceExpr> for QueryExpr {
+    fn from(source: SourceExpr) -> Self {
+        Self::new(source)
+    }
+}
+
+impl From<MemTable> for QueryExpr {
+    fn from(x: MemTable) -> Self {
+        Self::new(x)
+    }
+}
+
+impl From<DbTable> for QueryExpr {
+    fn from(x: DbTable) -> Self {
+        Self::new(x)
+    }
+}
+
 impl QueryExpr {
     pub fn new<T: Into<SourceExpr>>(source: T) -> Self {
         Self {
@@ -346,30 +935,117 @@ impl QueryExpr {
         x
     }
 
+    pub fn with_compute(self, cols: &[ComputedColumn]) -> Self {
+        let mut x = self;
+        x.query.push(Query::Compute(cols.into()));
+        x
+    }
+
     pub fn with_join_inner<Source>(self, with: Source, lhs: FieldName, rhs: FieldName) -> Self
     where
-        Source: Into<SourceExpr>,
+        Source: Into<QueryExpr>,
     {
         let mut x = self;
         x.query.push(Query::JoinInner(JoinExpr::new(with.into(), lhs, rhs)));
         x
     }
+
+    pub fn with_join_outer<Source>(self, kind: OuterJoin, with: Source, lhs: FieldName, rhs: FieldName) -> Self
+    where
+        Source: Into<QueryExpr>,
+    {
+        let mut x = self;
+        let join = JoinExpr::new(with.into(), lhs, rhs);
+        x.query.push(Query::JoinOuter(JoinOuterExpr { kind, join }));
+        x
+    }
+
+    pub fn with_join_cross<Source>(self, with: Source) -> Self
+    where
+        Source: Into<QueryExpr>,
+    {
+        let mut x = self;
+        x.query.push(Query::JoinCross(with.into()));
+        x
+    }
+
+    pub fn with_sort(self, keys: &[SortKey]) -> Self {
+        let mut x = self;
+        if !keys.is_empty() {
+            x.query.push(Query::Sort(keys.into()));
+        }
+        x
+    }
+
+    pub fn with_aggregate(self, group_by: &[FieldName], aggregates: &[AggregateExpr]) -> Self {
+        let mut x = self;
+        x.query.push(Query::Aggregate(AggregateQuery {
+            group_by: group_by.into(),
+            aggregates: aggregates.into(),
+        }));
+        x
+    }
+
+    pub fn with_distinct(self) -> Self {
+        let mut x = self;
+        x.query.push(Query::Distinct);
+        x
+    }
+
+    pub fn with_set_op<Source>(self, op: SetOp, all: bool, with: Source) -> Self
+    where
+        Source: Into<QueryExpr>,
+    {
+        let mut x = self;
+        x.query.push(Query::SetOp(SetOpExpr {
+            op,
+            all,
+            rhs: with.into(),
+        }));
+        x
+    }
+
+    pub fn with_semi_join<Source>(self, field: Option<FieldName>, with: Source, negated: bool) -> Self
+    where
+        Source: Into<QueryExpr>,
+    {
+        let mut x = self;
+        x.query.push(Query::SemiJoin(SemiJoinExpr {
+            field,
+            rhs: with.into(),
+            negated,
+        }));
+        x
+    }
+
+    pub fn with_limit(self, offset: usize, limit: Option<usize>) -> Self {
+        let mut x = self;
+        if offset > 0 || limit.is_some() {
+            x.query.push(Query::Limit(LimitExpr { offset, limit }));
+        }
+        x
+    }
 }
 
 impl AuthAccess for Query {
     fn check_auth(&self, owner: Identity, caller: Identity) -> Result<(), AuthError> {
         if owner == caller {
             Ok(())
-        } else if let Query::JoinInner(j) = self {
-            if j.rhs.table_access() == StAccess::Public {
+        } else {
+            let rhs = match self {
+                Query::JoinInner(j) | Query::JoinOuter(JoinOuterExpr { join: j, .. }) => &j.rhs,
+                Query::JoinCross(rhs)
+                | Query::SetOp(SetOpExpr { rhs, .. })
+                | Query::SemiJoin(SemiJoinExpr { rhs, .. }) => rhs,
+                _ => return Ok(()),
+            };
+            if rhs.source.table_access() == StAccess::Public {
                 Ok(())
             } else {
                 Err(AuthError::TablePrivate {
-                    named: j.rhs.table_name().to_string(),
+                    named: rhs.source.table_name().to_string(),
                 })
             }
-        } else {
-            Ok(())
         }
     }
 }
@@ -425,13 +1101,21 @@ pub enum CrudExprOpt {
     Insert {
         source: SourceExprOpt,
         rows: Vec<ProductValue>,
+        on_conflict: Option<OnConflict>,
+        returning: Option<Vec<ComputedColumn>>,
     },
     Update {
         insert: QueryExprOpt,
         delete: QueryExprOpt,
+        returning: Option<Vec<ComputedColumn>>,
     },
     Delete {
         query: QueryExprOpt,
+        returning: Option<Vec<ComputedColumn>>,
+    },
+    Explain {
+        query: QueryExprOpt,
+        analyze: bool,
     },
     CreateTable {
         name: String,
@@ -439,10 +1123,24 @@ pub enum CrudExprOpt {
         table_type: StTableType,
         table_access: StAccess,
     },
+    CreateIndex {
+        name: String,
+        table_name: String,
+        table_id: u32,
+        cols: Vec<u32>,
+        index_type: IndexType,
+        is_unique: bool,
+    },
+    AlterTable {
+        table_name: String,
+        table_id: u32,
+        alter: AlterTable,
+    },
     Drop {
         name: String,
         kind: DbType,
         table_access: StAccess,
+        if_exists: bool,
     },
 }
 
@@ -512,6 +1210,9 @@ impl fmt::Display for SourceExprOpt {
 impl fmt::Display for Query {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Query::IndexScan(q) => {
+                write!(f, "index scan {q}")
+            }
             Query::Select(q) => {
                 write!(f, "select {q}")
             }
@@ -528,8 +1229,104 @@ impl fmt::Display for Query {
                 }
                 Ok(())
             }
+            Query::Compute(q) => {
+                write!(f, "compute ")?;
+                for (pos, x) in q.iter().enumerate() {
+                    write!(f, "{} AS {}", x.expr, x.field)?;
+                    if pos + 1 < q.len() {
+                        write!(f, ", ")?;
+                    }
+                }
+                Ok(())
+            }
             Query::JoinInner(q) => {
-                write!(f, "&inner {} ON {} = {}", q.rhs, q.col_lhs, q.col_rhs)
+                write!(f, "&inner {}", q.rhs.source)?;
+                for (pos, x) in q.rhs.query.iter().enumerate() {
+                    write!(f, "{}{x}", if pos == 0 { " (" } else { ", " })?;
+                }
+                if !q.rhs.query.is_empty() {
+                    write!(f, ")")?;
+                }
+                write!(f, " ON {} = {}", q.col_lhs, q.col_rhs)
+            }
+            Query::JoinOuter(JoinOuterExpr { kind, join: q }) => {
+                write!(f, "&{kind} outer {}", q.rhs.source)?;
+                for (pos, x) in q.rhs.query.iter().enumerate() {
+                    write!(f, "{}{x}", if pos == 0 { " (" } else { ", " })?;
+                }
+                if !q.rhs.query.is_empty() {
+                    write!(f, ")")?;
+                }
+                write!(f, " ON {} = {}", q.col_lhs, q.col_rhs)
+            }
+            Query::JoinCross(q) => {
+                write!(f, "&cross {}", q.source)?;
+                for (pos, x) in q.query.iter().enumerate() {
+                    write!(f, "{}{x}", if pos == 0 { " (" } else { ", " })?;
+                }
+                if !q.query.is_empty() {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            Query::Sort(keys) => {
+                write!(f, "sort ")?;
+                for (pos, key) in keys.iter().enumerate() {
+                    write!(f, "{} {}", key.field, if key.asc { "ASC" } else { "DESC" })?;
+                    if pos + 1 < keys.len() {
+                        write!(f, ", ")?;
+                    }
+                }
+                Ok(())
+            }
+            Query::Aggregate(AggregateQuery { group_by, aggregates }) => {
+                write!(f, "aggregate")?;
+                for (pos, x) in aggregates.iter().enumerate() {
+                    write!(f, " {x} AS {}", x.field)?;
+                    if pos + 1 < aggregates.len() {
+                        write!(f, ",")?;
+                    }
+                }
+                if !group_by.is_empty() {
+                    write!(f, " group by ")?;
+                    for (pos, x) in group_by.iter().enumerate() {
+                        write!(f, "{x}")?;
+                        if pos + 1 < group_by.len() {
+                            write!(f, ", ")?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Query::Limit(LimitExpr { offset, limit }) => {
+                if let Some(limit) = limit {
+                    write!(f, "limit {limit} ")?;
+                }
+                write!(f, "offset {offset}")
+            }
+            Query::Distinct => write!(f, "distinct"),
+            Query::SetOp(SetOpExpr { op, all, rhs }) => {
+                write!(f, "{op}{} {}", if *all { " all" } else { "" }, rhs.source)?;
+                for (pos, x) in rhs.query.iter().enumerate() {
+                    write!(f, "{}{x}", if pos == 0 { " (" } else { ", " })?;
+                }
+                if !rhs.query.is_empty() {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            Query::SemiJoin(SemiJoinExpr { field, rhs, negated }) => {
+                match field {
+                    Some(field) => write!(f, "{field}{} in {}", if *negated { " not" } else { "" }, rhs.source)?,
+                    None => write!(f, "{}exists {}", if *negated { "not " } else { "" }, rhs.source)?,
+                }
+                for (pos, x) in rhs.query.iter().enumerate() {
+                    write!(f, "{}{x}", if pos == 0 { " (" } else { ", " })?;
+                }
+                if !rhs.query.is_empty() {
+                    write!(f, ")")?;
+                }
+                Ok(())
             }
         }
     }
@@ -601,7 +1398,7 @@ impl fmt::Display for ExprOpt {
             ExprOpt::Crud(x) => {
                 let x = &**x;
                 match x {
-                    CrudExprOpt::Insert { source, rows } => {
+                    CrudExprOpt::Insert { source, rows, .. } => {
                         write!(f, "{}", source)?;
                         for row in rows {
                             write!(f, "{row:?}")?;
@@ -609,7 +1406,10 @@ impl fmt::Display for ExprOpt {
                     }
                     CrudExprOpt::Update { .. } => {}
                     CrudExprOpt::Delete { .. } => {}
+                    CrudExprOpt::Explain { .. } => {}
                     CrudExprOpt::CreateTable { .. } => {}
+                    CrudExprOpt::CreateIndex { .. } => {}
+                    CrudExprOpt::AlterTable { .. } => {}
                     CrudExprOpt::Drop { .. } => {}
                 };
                 Ok(())
@@ -664,6 +1464,16 @@ impl AuthAccess for QueryCode {
     }
 }
 
+impl From<QueryExpr> for QueryCode {
+    fn from(x: QueryExpr) -> Self {
+        let table = match x.source {
+            SourceExpr::MemTable(x) => Table::MemTable(x),
+            SourceExpr::DbTable(x) => Table::DbTable(x),
+        };
+        QueryCode { table, query: x.query }
+    }
+}
+
 impl Relation for QueryCode {
     fn head(&self) -> Header {
         self.table.head()
@@ -680,13 +1490,21 @@ pub enum CrudCode {
     Insert {
         table: Table,
         rows: Vec<ProductValue>,
+        on_conflict: Option<OnConflict>,
+        returning: Option<Vec<ComputedColumn>>,
     },
     Update {
         insert: QueryCode,
         delete: QueryCode,
+        returning: Option<Vec<ComputedColumn>>,
     },
     Delete {
         query: QueryCode,
+        returning: Option<Vec<ComputedColumn>>,
+    },
+    Explain {
+        query: QueryCode,
+        analyze: bool,
     },
     CreateTable {
         name: String,
@@ -694,10 +1512,24 @@ pub enum CrudCode {
         table_type: StTableType,
         table_access: StAccess,
     },
+    CreateIndex {
+        name: String,
+        table_name: String,
+        table_id: u32,
+        cols: Vec<u32>,
+        index_type: IndexType,
+        is_unique: bool,
+    },
+    AlterTable {
+        table_name: String,
+        table_id: u32,
+        alter: AlterTable,
+    },
     Drop {
         name: String,
         kind: DbType,
         table_access: StAccess,
+        if_exists: bool,
     },
 }
 
@@ -709,11 +1541,12 @@ impl AuthAccess for CrudCode {
         match self {
             CrudCode::Query(q) => q.check_auth(owner, caller),
             CrudCode::Insert { table, .. } => table.check_auth(owner, caller),
-            CrudCode::Update { insert, delete } => {
+            CrudCode::Update { insert, delete, .. } => {
                 insert.check_auth(owner, caller)?;
                 delete.check_auth(owner, caller)
             }
             CrudCode::Delete { query, .. } => query.check_auth(owner, caller),
+            CrudCode::Explain { query, .. } => query.check_auth(owner, caller),
             //TODO: Must allow to create private tables for `caller`
             CrudCode::CreateTable { name, table_access, .. } => {
                 if table_access == &StAccess::Public {
@@ -724,10 +1557,23 @@ impl AuthAccess for CrudCode {
                     })
                 }
             }
+            CrudCode::CreateIndex { table_name, .. } | CrudCode::AlterTable { table_name, .. } => {
+                Err(AuthError::OwnerRequired {
+                    named: table_name.to_string(),
+                })
+            }
+            CrudCode::Drop {
+                name,
+                kind: DbType::Index,
+                ..
+            } => Err(AuthError::OwnerRequired {
+                named: name.to_string(),
+            }),
             CrudCode::Drop {
                 name,
                 kind,
                 table_access,
+                ..
             } => {
                 if table_access == &StAccess::Public {
                     Ok(())
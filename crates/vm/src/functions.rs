@@ -1,6 +1,8 @@
-use crate::errors::{ErrorKind, ErrorLang};
-use crate::expr::{Code, FunctionId};
+use crate::errors::{ErrorKind, ErrorLang, ErrorType};
+use crate::expr::{as_option, is_number, option_type, Code, FunctionId};
+use crate::ops::{math, string};
 use crate::program::{ProgramRef, ProgramVm};
+use crate::types::Ty;
 use spacetimedb_sats::algebraic_type::AlgebraicType;
 use spacetimedb_sats::algebraic_value::AlgebraicValue;
 use std::fmt;
@@ -82,6 +84,15 @@ impl<'a> Args<'a> {
         }
     }
 
+    /// Returns the values of the arguments.
+    pub fn to_vec(&self) -> Vec<AlgebraicValue> {
+        match self {
+            Args::Unary(x) => vec![(*x).clone()],
+            Args::Binary(a, b) => vec![(*a).clone(), (*b).clone()],
+            Args::Splat(x) => x.to_vec(),
+        }
+    }
+
     pub fn param_extract<F, T>(&self, pos: usize, f: F) -> Result<&'a T, ErrorLang>
     where
         F: Fn(&'a AlgebraicValue) -> Option<&'a T>,
@@ -130,3 +141,196 @@ pub struct Lambda {
     pub(crate) head: FunDef,
     pub(crate) body: Code,
 }
+
+/// A built-in scalar function, that computes a value from the values of its arguments,
+/// as in `lower(name)` or `name LIKE 'a%'`.
+///
+/// Except `coalesce` and `is_null`, they yield `none` when any argument is `none`,
+/// so their result is an option when any argument is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub enum ScalarFn {
+    Lower,
+    Upper,
+    Length,
+    Abs,
+    /// Returns its first argument that is not `none`.
+    Coalesce,
+    /// Whether its first argument matches the pattern of its second argument.
+    Like,
+    /// Whether its argument is `none`, as in `name IS NULL`.
+    IsNull,
+}
+
+impl From<ScalarFn> for &str {
+    fn from(x: ScalarFn) -> Self {
+        match x {
+            ScalarFn::Lower => "std::str::lower",
+            ScalarFn::Upper => "std::str::upper",
+            ScalarFn::Length => "std::str::length",
+            ScalarFn::Abs => "std::math::abs",
+            ScalarFn::Coalesce => "std::ops::coalesce",
+            ScalarFn::Like => "std::str::like",
+            ScalarFn::IsNull => "std::ops::is_null",
+        }
+    }
+}
+
+impl fmt::Display for ScalarFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScalarFn::Lower => "lower",
+            ScalarFn::Upper => "upper",
+            ScalarFn::Length => "length",
+            ScalarFn::Abs => "abs",
+            ScalarFn::Coalesce => "coalesce",
+            ScalarFn::Like => "like",
+            ScalarFn::IsNull => "is_null",
+        })
+    }
+}
+
+impl ScalarFn {
+    pub const ALL: [Self; 7] = [
+        Self::Lower,
+        Self::Upper,
+        Self::Length,
+        Self::Abs,
+        Self::Coalesce,
+        Self::Like,
+        Self::IsNull,
+    ];
+
+    /// Returns the function called `name`, ignoring the case, as in `LOWER(name)`.
+    ///
+    /// `LIKE` and `IS NULL` are operators, so they aren't called by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .filter(|x| ![Self::Like, Self::IsNull].contains(x))
+            .find(|x| x.to_string().eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the type of the result of calling this function with arguments of the types `args`,
+    /// or an error if it can't be called with them.
+    ///
+    /// An argument of [Ty::Unknown] type is a `none` of any option type, as in `lower(NULL)`,
+    /// so the result is `none` too, and its type is also unknown.
+    pub fn result_type(self, args: &[Ty]) -> Result<Ty, ErrorType> {
+        let invalid = || ErrorType::FunArgs {
+            fun: self.to_string(),
+            args: args.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+        };
+
+        if self == Self::IsNull {
+            return match args {
+                [_] => Ok(AlgebraicType::Bool.into()),
+                _ => Err(invalid()),
+            };
+        }
+
+        if self == Self::Coalesce {
+            let mut result: Option<&AlgebraicType> = None;
+            let mut is_option = true;
+            for ty in args {
+                let Ty::Val(ty) = ty else {
+                    continue;
+                };
+                let inner = option_type(ty).unwrap_or(ty);
+                if result.map_or(false, |x| x != inner) {
+                    return Err(invalid());
+                }
+                result = Some(inner);
+                is_option &= option_type(ty).is_some();
+            }
+            return match result {
+                _ if args.is_empty() => Err(invalid()),
+                Some(ty) if is_option => Ok(AlgebraicType::option(ty.clone()).into()),
+                Some(ty) => Ok(ty.clone().into()),
+                None => Ok(Ty::Unknown),
+            };
+        }
+
+        let mut is_option = false;
+        let mut types = Vec::with_capacity(args.len());
+        for ty in args {
+            match ty {
+                Ty::Val(ty) => {
+                    is_option |= option_type(ty).is_some();
+                    types.push(option_type(ty).unwrap_or(ty));
+                }
+                _ => return Ok(Ty::Unknown),
+            }
+        }
+        let result = match (self, types.as_slice()) {
+            (Self::Lower | Self::Upper, [&AlgebraicType::String]) => AlgebraicType::String,
+            (Self::Length, [&AlgebraicType::String]) => AlgebraicType::U64,
+            (Self::Abs, [ty]) if is_number(ty) => (*ty).clone(),
+            (Self::Like, [&AlgebraicType::String, &AlgebraicType::String]) => AlgebraicType::Bool,
+            _ => return Err(invalid()),
+        };
+        Ok(if is_option {
+            AlgebraicType::option(result)
+        } else {
+            result
+        }
+        .into())
+    }
+
+    /// Calls the function with the values `args`.
+    pub fn call(self, args: &[AlgebraicValue]) -> Result<AlgebraicValue, ErrorLang> {
+        let invalid = || {
+            ErrorLang::new(
+                ErrorKind::Params,
+                Some(&format!("Invalid arguments for `{self}`: {args:?}")),
+            )
+        };
+
+        if self == Self::IsNull {
+            return match args {
+                [x] => Ok(AlgebraicValue::Bool(matches!(as_option(x), Some(None)))),
+                _ => Err(invalid()),
+            };
+        }
+
+        if self == Self::Coalesce {
+            // The result is only an option when all the arguments are.
+            let is_option = args.iter().all(|x| as_option(x).is_some());
+            let found = args.iter().find_map(|x| match as_option(x) {
+                Some(x) => x,
+                None => Some(x),
+            });
+            return match found {
+                Some(x) if is_option => Ok(AlgebraicValue::OptionSome(x.clone())),
+                Some(x) => Ok(x.clone()),
+                None => Ok(AlgebraicValue::OptionNone()),
+            };
+        }
+
+        let mut is_option = false;
+        let mut values = Vec::with_capacity(args.len());
+        for x in args {
+            match as_option(x) {
+                Some(None) => return Ok(AlgebraicValue::OptionNone()),
+                Some(Some(x)) => {
+                    is_option = true;
+                    values.push(x);
+                }
+                None => values.push(x),
+            }
+        }
+        let string = |x: &AlgebraicValue| x.as_string().cloned().ok_or_else(invalid);
+        let result = match (self, values.as_slice()) {
+            (Self::Lower, [x]) => AlgebraicValue::String(string(x)?.to_lowercase()),
+            (Self::Upper, [x]) => AlgebraicValue::String(string(x)?.to_uppercase()),
+            (Self::Length, [x]) => AlgebraicValue::U64(string(x)?.chars().count() as u64),
+            (Self::Abs, [x]) => math::checked_abs(x)?,
+            (Self::Like, [x, pattern]) => AlgebraicValue::Bool(string::like(&string(x)?, &string(pattern)?)),
+            _ => return Err(invalid()),
+        };
+        Ok(if is_option {
+            AlgebraicValue::OptionSome(result)
+        } else {
+            result
+        })
+    }
+}
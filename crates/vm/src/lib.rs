@@ -35,5 +35,5 @@ pub mod iterators;
 pub mod ops;
 pub mod program;
 pub mod rel_ops;
-mod typecheck;
+pub mod typecheck;
 pub mod types;
@@ -1,7 +1,9 @@
 use std::ops::*;
 
+use crate::errors::{ErrorKind, ErrorLang};
 use crate::expr::Code;
 use crate::functions::Args;
+use crate::operator::OpMath;
 use crate::ops::shared::bin_op;
 use crate::program::ProgramRef;
 use spacetimedb_sats::algebraic_value::AlgebraicValue;
@@ -41,6 +43,93 @@ math_op!(math_minus, Sub::sub);
 math_op!(math_mul, Mul::mul);
 math_op!(math_div, Div::div);
 
+/// Applies `op` to the integers `$a` and `$b`, failing on overflow and on a division by zero.
+macro_rules! checked_int_op {
+    ($op:expr, $a:expr, $b:expr) => {
+        match $op {
+            OpMath::Add => $a.checked_add($b),
+            OpMath::Minus => $a.checked_sub($b),
+            OpMath::Mul => $a.checked_mul($b),
+            OpMath::Div if $b == 0 => {
+                return Err(ErrorLang::new(ErrorKind::OutOfBounds, Some("Division by zero")));
+            }
+            OpMath::Div => $a.checked_div($b),
+        }
+        .map(AlgebraicValue::from)
+    };
+}
+
+/// Applies `op` to the floats `$a` and `$b`.
+macro_rules! float_op {
+    ($op:expr, $a:expr, $b:expr) => {
+        AlgebraicValue::from(match $op {
+            OpMath::Add => $a + $b,
+            OpMath::Minus => $a - $b,
+            OpMath::Mul => $a * $b,
+            OpMath::Div => $a / $b,
+        })
+    };
+}
+
+fn invalid_params(op: impl std::fmt::Display) -> ErrorLang {
+    ErrorLang::new(
+        ErrorKind::Params,
+        Some(&format!("`{op}` expects numbers of the same type")),
+    )
+}
+
+/// Like the math ops loaded by the `vm`, but fails instead of panicking
+/// when the result of integers overflows, or they are divided by zero.
+///
+/// Both values must be numbers of the same type.
+pub(crate) fn checked_math(
+    op: OpMath,
+    lhs: &AlgebraicValue,
+    rhs: &AlgebraicValue,
+) -> Result<AlgebraicValue, ErrorLang> {
+    let result = match (lhs.as_builtin(), rhs.as_builtin()) {
+        (Some(lhs), Some(rhs)) => match (lhs, rhs) {
+            (BuiltinValue::U8(a), BuiltinValue::U8(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::I8(a), BuiltinValue::I8(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::U16(a), BuiltinValue::U16(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::I16(a), BuiltinValue::I16(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::U32(a), BuiltinValue::U32(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::I32(a), BuiltinValue::I32(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::U64(a), BuiltinValue::U64(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::I64(a), BuiltinValue::I64(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::U128(a), BuiltinValue::U128(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::I128(a), BuiltinValue::I128(b)) => checked_int_op!(op, a, *b),
+            (BuiltinValue::F32(a), BuiltinValue::F32(b)) => Some(float_op!(op, a.into_inner(), b.into_inner())),
+            (BuiltinValue::F64(a), BuiltinValue::F64(b)) => Some(float_op!(op, a.into_inner(), b.into_inner())),
+            _ => return Err(invalid_params(op)),
+        },
+        _ => return Err(invalid_params(op)),
+    };
+    result.ok_or_else(|| ErrorLang::new(ErrorKind::OutOfBounds, Some(&format!("Overflow in `{op}`"))))
+}
+
+/// Returns the absolute value of the number `x`, failing when it overflows, as in `abs(i8::MIN)`.
+pub(crate) fn checked_abs(x: &AlgebraicValue) -> Result<AlgebraicValue, ErrorLang> {
+    let result = match x.as_builtin() {
+        Some(BuiltinValue::I8(x)) => x.checked_abs().map(AlgebraicValue::from),
+        Some(BuiltinValue::I16(x)) => x.checked_abs().map(AlgebraicValue::from),
+        Some(BuiltinValue::I32(x)) => x.checked_abs().map(AlgebraicValue::from),
+        Some(BuiltinValue::I64(x)) => x.checked_abs().map(AlgebraicValue::from),
+        Some(BuiltinValue::I128(x)) => x.checked_abs().map(AlgebraicValue::from),
+        Some(BuiltinValue::F32(x)) => Some(x.into_inner().abs().into()),
+        Some(BuiltinValue::F64(x)) => Some(x.into_inner().abs().into()),
+        Some(
+            BuiltinValue::U8(_)
+            | BuiltinValue::U16(_)
+            | BuiltinValue::U32(_)
+            | BuiltinValue::U64(_)
+            | BuiltinValue::U128(_),
+        ) => Some(x.clone()),
+        _ => return Err(invalid_params("abs")),
+    };
+    result.ok_or_else(|| ErrorLang::new(ErrorKind::OutOfBounds, Some("Overflow in `abs`")))
+}
+
 fn _math_op<F>(args: Args<'_>, f: F) -> Code
 where
     F: Fn(&AlgebraicValue, &AlgebraicValue) -> AlgebraicValue,
@@ -3,3 +3,4 @@ pub(crate) mod logic;
 pub(crate) mod math;
 pub mod parse;
 pub(crate) mod shared;
+pub(crate) mod string;
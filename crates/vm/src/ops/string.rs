@@ -0,0 +1,52 @@
+/// Whether `value` matches the `LIKE` `pattern`,
+/// where `%` matches any sequence of characters, `_` matches any single character,
+/// and `\` escapes the next character so it matches itself.
+pub(crate) fn like(value: &str, pattern: &str) -> bool {
+    enum Token {
+        Any,
+        One,
+        Char(char),
+    }
+
+    let mut tokens = Vec::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '%' => Token::Any,
+            '_' => Token::One,
+            '\\' => Token::Char(chars.next().unwrap_or('\\')),
+            c => Token::Char(c),
+        });
+    }
+    let value: Vec<char> = value.chars().collect();
+
+    // Matches greedily, backtracking to the last `%` on a mismatch,
+    // which takes one more character each time.
+    let (mut v, mut p) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match tokens.get(p) {
+            Some(Token::Any) => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(Token::One) => {
+                v += 1;
+                p += 1;
+            }
+            Some(Token::Char(c)) if *c == value[v] => {
+                v += 1;
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((any, taken)) => {
+                    backtrack = Some((any, taken + 1));
+                    p = any + 1;
+                    v = taken + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[p..].iter().all(|x| matches!(x, Token::Any))
+}
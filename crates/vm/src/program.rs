@@ -9,7 +9,7 @@ use crate::env::EnvDb;
 use crate::errors::ErrorVm;
 use crate::eval::{build_query, IterRows};
 use crate::expr::{Code, CrudCode, FunctionId};
-use crate::functions::FunDef;
+use crate::functions::{Args, FunDef, ScalarFn};
 use crate::operator::*;
 use crate::ops::logic;
 use crate::ops::math;
@@ -63,6 +63,18 @@ pub trait ProgramVm {
         ops.insert(OpMath::Mul.into(), env.functions.add(OpMath::Mul, Box::new(math::mul)));
         ops.insert(OpMath::Div.into(), env.functions.add(OpMath::Div, Box::new(math::div)));
 
+        for fun in ScalarFn::ALL {
+            env.functions.add(
+                fun,
+                Box::new(
+                    move |_p: ProgramRef<'_>, args: Args<'_>| match fun.call(&args.to_vec()) {
+                        Ok(x) => Code::Value(x),
+                        Err(err) => Code::Halt(err),
+                    },
+                ),
+            );
+        }
+
         env.functions.ops = ops
     }
 
@@ -196,9 +208,18 @@ impl ProgramVm for Program {
             CrudCode::Delete { .. } => {
                 todo!()
             }
+            CrudCode::Explain { .. } => {
+                todo!()
+            }
             CrudCode::CreateTable { .. } => {
                 todo!()
             }
+            CrudCode::CreateIndex { .. } => {
+                todo!()
+            }
+            CrudCode::AlterTable { .. } => {
+                todo!()
+            }
             CrudCode::Drop { .. } => {
                 todo!()
             }
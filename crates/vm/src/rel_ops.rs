@@ -1,7 +1,13 @@
-use crate::errors::ErrorVm;
-use spacetimedb_lib::relation::{FieldExpr, Header, RelValue, RelValueRef, RowCount};
+use crate::errors::{ErrorKind, ErrorLang, ErrorVm};
+use crate::expr::{option_type, AggregateFn, AggregateQuery, ComputedColumn, SetOp};
+use spacetimedb_lib::error::RelationError;
+use spacetimedb_lib::relation::{Column, FieldExpr, FieldName, Header, RelValue, RelValueRef, RowCount};
+use spacetimedb_sats::algebraic_type::AlgebraicType;
+use spacetimedb_sats::algebraic_value::AlgebraicValue;
+use spacetimedb_sats::builtin_value::BuiltinValue;
 use spacetimedb_sats::product_value::ProductValue;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{btree_map, BTreeMap, HashMap, HashSet};
 
 pub(crate) trait ResultExt<T> {
     fn unpack_fold(self) -> Result<T, ErrorVm>;
@@ -44,7 +50,10 @@ pub trait RelOps {
         P: FnMut(RelValueRef) -> Result<bool, ErrorVm>,
         Self: Sized,
     {
-        let count = self.row_count();
+        let count = RowCount {
+            min: 0,
+            max: self.row_count().max,
+        };
         let head = self.head().clone();
         Select::new(self, count, head, predicate)
     }
@@ -69,13 +78,33 @@ pub trait RelOps {
         Ok(Project::new(self, count, head, extractor))
     }
 
+    /// Creates an `Iterator` that projects each row onto the `columns` computed from it.
+    ///
+    /// Note:
+    ///
+    /// It is the equivalent of a `SELECT` clause on SQL with expressions like `score * 2` or `lower(name)`.
+    #[inline]
+    fn compute(self, columns: Vec<ComputedColumn>) -> Compute<Self>
+    where
+        Self: Sized,
+    {
+        let count = self.row_count();
+        let fields: Vec<_> = columns
+            .iter()
+            .map(|x| Column::new(x.field.clone(), x.ty.clone()))
+            .collect();
+        let head = Header::new(&self.head().table_name, &fields);
+        Compute::new(self, count, head, columns)
+    }
+
     /// Intersection between the left and the right, both (non-sorted) `iterators`.
     ///
-    /// The hash join strategy requires the right iterator can be collected to a `HashMap`.
-    /// The left iterator can be arbitrarily long.
+    /// The hash join strategy requires the right iterator can be collected to a `HashMap`,
+    /// which happens when the first row is requested.
+    /// The left iterator can be arbitrarily long, and is streamed.
     ///
     /// It is therefore asymmetric (you can't flip the iterators to get a right_outer join).
-    ///    
+    ///
     /// Note:
     ///
     /// It is the equivalent of a `INNER JOIN` clause on SQL.
@@ -98,6 +127,298 @@ pub trait RelOps {
         Ok(JoinInner::new(head, self, with, key_lhs, key_rhs, predicate))
     }
 
+    /// Like [RelOps::join_inner], and also yields the rows of the left that match no row of the right
+    /// when `keep_lhs`, and after the others, the rows of the right that match no row of the left when `keep_rhs`,
+    /// with the columns of the missing side set to `none`.
+    ///
+    /// In the [Header], the columns of a side that may be missing are of the option type of their column,
+    /// and their values are wrapped in `some` when present, unless the column is already of an option type.
+    ///
+    /// Note:
+    ///
+    /// It is the equivalent of a `LEFT`, `RIGHT` or `FULL OUTER JOIN` clause on SQL.
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    fn join_outer<P, KeyLhs, KeyRhs, Rhs>(
+        self,
+        with: Rhs,
+        key_lhs: KeyLhs,
+        key_rhs: KeyRhs,
+        predicate: P,
+        keep_lhs: bool,
+        keep_rhs: bool,
+    ) -> Result<JoinOuter<Self, Rhs, KeyLhs, KeyRhs, P>, ErrorVm>
+    where
+        Self: Sized,
+        P: FnMut(RelValueRef, RelValueRef) -> Result<bool, ErrorVm>,
+        KeyLhs: FnMut(RelValueRef) -> Result<ProductValue, ErrorVm>,
+        KeyRhs: FnMut(RelValueRef) -> Result<ProductValue, ErrorVm>,
+        Rhs: RelOps,
+    {
+        let lhs_width = self.head().fields.len();
+        let mut head = self.head().extend(with.head());
+        let mut wrap = Vec::with_capacity(head.fields.len());
+        for (pos, col) in head.fields.iter_mut().enumerate() {
+            let nullable = if pos < lhs_width { keep_rhs } else { keep_lhs };
+            let is_option = option_type(&col.algebraic_type).is_some();
+            if nullable && !is_option {
+                col.algebraic_type = AlgebraicType::option(col.algebraic_type.clone());
+            }
+            wrap.push(nullable && !is_option);
+        }
+        Ok(JoinOuter {
+            head,
+            count: RowCount::unknown(),
+            lhs: self,
+            rhs: with,
+            key_lhs,
+            key_rhs,
+            predicate,
+            keep_lhs,
+            keep_rhs,
+            lhs_width,
+            wrap,
+            rows: Vec::new(),
+            map: HashMap::new(),
+            filled: false,
+            left: None,
+            unmatched: None,
+        })
+    }
+
+    /// Intersection between the left `iterator` and the rows of the right, with the [Header] `rhs`,
+    /// that `lookup` returns for each row of the left.
+    ///
+    /// It is the nested loop strategy, where `lookup` is expected to seek an index on the join column of the right,
+    /// so only the matching rows of the right are read, instead of all of them.
+    ///
+    /// Note:
+    ///
+    /// It is the equivalent of a `INNER JOIN` clause on SQL.
+    #[inline]
+    fn join_index<F>(self, rhs: &Header, lookup: F) -> IndexJoin<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(RelValueRef) -> Result<Vec<RelValue>, ErrorVm>,
+    {
+        let head = self.head().extend(rhs);
+        IndexJoin::new(head, self, lookup)
+    }
+
+    /// Creates an `Iterator` that yields the rows sorted with the comparator function `compare`.
+    ///
+    /// The sort is stable, so rows that compare equal keep their relative order.
+    /// All the rows are materialized before the first one is yielded.
+    ///
+    /// Note:
+    ///
+    /// It is the equivalent of a `ORDER BY` clause on SQL.
+    #[inline]
+    fn sort_by<C>(self, compare: C) -> Sort<Self, C>
+    where
+        C: FnMut(RelValueRef, RelValueRef) -> Ordering,
+        Self: Sized,
+    {
+        let count = self.row_count();
+        let head = self.head().clone();
+        Sort::new(self, count, head, compare)
+    }
+
+    /// Creates an `Iterator` that skips the first `offset` rows,
+    /// then yields at most `limit` rows, or all the remaining ones when `limit` is `None`.
+    ///
+    /// Note:
+    ///
+    /// It is the equivalent of a `LIMIT ... OFFSET ...` clause on SQL.
+    #[inline]
+    fn limit(self, offset: usize, limit: Option<usize>) -> Limit<Self>
+    where
+        Self: Sized,
+    {
+        let count = self.row_count();
+        let min = count.min.saturating_sub(offset);
+        let max = count.max.map(|max| max.saturating_sub(offset));
+        let count = match limit {
+            Some(limit) => RowCount {
+                min: min.min(limit),
+                max: Some(max.map_or(limit, |max| max.min(limit))),
+            },
+            None => RowCount { min, max },
+        };
+        let head = self.head().clone();
+        Limit::new(self, count, head, offset, limit)
+    }
+
+    /// Creates an `Iterator` that groups the rows by the columns `group_by` of `query`
+    /// and yields a row per group with the `aggregates` of `query` computed over the rows of the group.
+    ///
+    /// The [Header] is pre-checked that all the fields exist and can be aggregated,
+    /// and return a error otherwise.
+    /// All the rows are consumed before the first group is yielded.
+    ///
+    /// Note:
+    ///
+    /// It is the equivalent of a `GROUP BY` clause on SQL.
+    #[inline]
+    fn aggregate(self, query: &AggregateQuery) -> Result<Aggregate<Self>, ErrorVm>
+    where
+        Self: Sized,
+    {
+        let input = self.head();
+        let position = |field| {
+            input
+                .column_pos(field)
+                .ok_or_else(|| RelationError::FieldNotFound(input.clone(), field.clone()))
+        };
+
+        let mut fields = Vec::with_capacity(query.group_by.len() + query.aggregates.len());
+        let mut group_by = Vec::with_capacity(query.group_by.len());
+        for field in &query.group_by {
+            let pos = position(field)?;
+            fields.push(input.fields[pos].clone());
+            group_by.push(pos);
+        }
+
+        let mut args = Vec::with_capacity(query.aggregates.len());
+        let mut accumulators = Vec::with_capacity(query.aggregates.len());
+        for x in &query.aggregates {
+            let arg = x.arg.as_ref().map(position).transpose()?;
+            let arg_ty = arg.map(|pos| &input.fields[pos].algebraic_type);
+            let ty = x.fun.result_type(arg_ty).ok_or_else(|| {
+                let ty = arg_ty.map(|ty| format!("{ty:?}")).unwrap_or_default();
+                ErrorVm::Unsupported(format!("Can't compute `{x}` over values of type {ty}"))
+            })?;
+            accumulators.push(Accumulator::new(x.fun, &ty));
+            fields.push(Column::new(x.field.clone(), ty));
+            args.push(arg.map(|pos| Arg {
+                pos,
+                nullable: option_type(&input.fields[pos].algebraic_type).is_some(),
+            }));
+        }
+
+        let count = if group_by.is_empty() {
+            RowCount::exact(1)
+        } else {
+            let count = self.row_count();
+            RowCount {
+                min: count.min.min(1),
+                max: count.max,
+            }
+        };
+        let head = Header::new(&input.table_name, &fields);
+        Ok(Aggregate::new(self, count, head, group_by, args, accumulators))
+    }
+
+    /// Creates an `Iterator` that yields each distinct row once, the first time it's found.
+    ///
+    /// Note:
+    ///
+    /// It is the equivalent of a `SELECT DISTINCT` clause on SQL.
+    #[inline]
+    fn distinct(self) -> Distinct<Self>
+    where
+        Self: Sized,
+    {
+        let count = self.row_count();
+        let count = RowCount {
+            min: count.min.min(1),
+            max: count.max,
+        };
+        let head = self.head().clone();
+        Distinct::new(self, count, head)
+    }
+
+    /// Combines the rows of the left and the right `iterators` by `op`, removing the duplicated rows unless `all`,
+    /// as described by [SetOpExpr](crate::expr::SetOpExpr).
+    ///
+    /// The [Header] is pre-checked that both sides have columns of the same types, and return a error otherwise.
+    /// The rows keep the [Header] of the left.
+    /// For `INTERSECT` and `EXCEPT`, the right is collected to a `HashMap` when the first row is requested,
+    /// and the left is streamed.
+    ///
+    /// Note:
+    ///
+    /// It is the equivalent of a `UNION`, `INTERSECT` or `EXCEPT` clause on SQL.
+    #[inline]
+    fn set_op<Rhs>(self, with: Rhs, op: SetOp, all: bool) -> Result<SetOperation<Self, Rhs>, ErrorVm>
+    where
+        Self: Sized,
+        Rhs: RelOps,
+    {
+        let (lhs, rhs) = (self.head(), with.head());
+        if lhs.fields.len() != rhs.fields.len()
+            || lhs
+                .fields
+                .iter()
+                .zip(&rhs.fields)
+                .any(|(lhs, rhs)| lhs.algebraic_type != rhs.algebraic_type)
+        {
+            return Err(ErrorVm::Unsupported(format!(
+                "`{op}` of `{}` and `{}`, whose columns are of different types",
+                lhs.table_name, rhs.table_name
+            )));
+        }
+        let (count_lhs, count_rhs) = (self.row_count(), with.row_count());
+        let count = match op {
+            SetOp::Union => RowCount {
+                min: if all {
+                    count_lhs.min + count_rhs.min
+                } else {
+                    count_lhs.min.max(count_rhs.min).min(1)
+                },
+                max: count_lhs.max.zip(count_rhs.max).map(|(lhs, rhs)| lhs + rhs),
+            },
+            SetOp::Intersect | SetOp::Except => RowCount {
+                min: 0,
+                max: count_lhs.max,
+            },
+        };
+        let head = self.head().clone();
+        Ok(SetOperation::new(head, count, self, with, op, all))
+    }
+
+    /// Keeps the rows of the left `iterator` by the rows of the right,
+    /// as described by [SemiJoinExpr](crate::expr::SemiJoinExpr).
+    ///
+    /// The [Header] is pre-checked that `field` exists, and that the right has a single column to match it with,
+    /// and return a error otherwise.
+    /// The values of the right are collected to a `HashSet` when the first row is requested,
+    /// and the left is streamed.
+    ///
+    /// Note:
+    ///
+    /// It is the equivalent of a `IN (SELECT ...)` or `EXISTS (SELECT ...)` clause on SQL.
+    #[inline]
+    fn semi_join<Rhs>(self, with: Rhs, field: Option<&FieldName>, negated: bool) -> Result<SemiJoin<Self, Rhs>, ErrorVm>
+    where
+        Self: Sized,
+        Rhs: RelOps,
+    {
+        let head = self.head().clone();
+        let field = match field {
+            Some(field) => {
+                let pos = head
+                    .column_pos(field)
+                    .ok_or_else(|| RelationError::FieldNotFound(head.clone(), field.clone()))?;
+                if with.head().fields.len() != 1 {
+                    return Err(ErrorVm::Unsupported(format!(
+                        "`IN` with a subquery that yields {} columns, instead of one",
+                        with.head().fields.len()
+                    )));
+                }
+                let lhs = option_type(&head.fields[pos].algebraic_type).is_some();
+                let rhs = option_type(&with.head().fields[0].algebraic_type).is_some();
+                Some((pos, lhs, rhs))
+            }
+            None => None,
+        };
+        let count = RowCount {
+            min: 0,
+            max: self.row_count().max,
+        };
+        Ok(SemiJoin::new(head, count, self, with, field, negated))
+    }
+
     /// Utility to collect the results into a [Vec]
     #[inline]
     fn collect_vec(mut self) -> Result<Vec<ProductValue>, ErrorVm>
@@ -216,43 +537,27 @@ where
 }
 
 #[derive(Clone, Debug)]
-pub struct JoinInner<Lhs, Rhs, KeyLhs, KeyRhs, P> {
+pub struct Compute<I> {
     pub(crate) head: Header,
     pub(crate) count: RowCount,
-    pub(crate) lhs: Lhs,
-    pub(crate) rhs: Rhs,
-    pub(crate) key_lhs: KeyLhs,
-    pub(crate) key_rhs: KeyRhs,
-    pub(crate) predicate: P,
-    map: HashMap<ProductValue, Vec<RelValue>>,
-    filled: bool,
-    left: Option<RelValue>,
+    pub(crate) iter: I,
+    columns: Vec<ComputedColumn>,
 }
 
-impl<Lhs, Rhs, KeyLhs, KeyRhs, P> JoinInner<Lhs, Rhs, KeyLhs, KeyRhs, P> {
-    pub fn new(head: Header, lhs: Lhs, rhs: Rhs, key_lhs: KeyLhs, key_rhs: KeyRhs, predicate: P) -> Self {
-        Self {
+impl<I> Compute<I> {
+    pub fn new(iter: I, count: RowCount, head: Header, columns: Vec<ComputedColumn>) -> Compute<I> {
+        Compute {
             head,
-            count: RowCount::unknown(),
-            map: HashMap::new(),
-            lhs,
-            rhs,
-            key_lhs,
-            key_rhs,
-            predicate,
-            filled: false,
-            left: None,
+            count,
+            iter,
+            columns,
         }
     }
 }
 
-impl<Lhs, Rhs, KeyLhs, KeyRhs, P> RelOps for JoinInner<Lhs, Rhs, KeyLhs, KeyRhs, P>
+impl<I> RelOps for Compute<I>
 where
-    Lhs: RelOps,
-    Rhs: RelOps,
-    KeyLhs: FnMut(RelValueRef) -> Result<ProductValue, ErrorVm>,
-    KeyRhs: FnMut(RelValueRef) -> Result<ProductValue, ErrorVm>,
-    P: FnMut(RelValueRef, RelValueRef) -> Result<bool, ErrorVm>,
+    I: RelOps,
 {
     fn head(&self) -> &Header {
         &self.head
@@ -263,39 +568,891 @@ where
     }
 
     fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
-        if !self.filled {
-            self.map = HashMap::with_capacity(self.rhs.row_count().min);
-            while let Some(v) = self.rhs.next()? {
-                let k = (self.key_rhs)(v.as_val_ref())?;
-                let values = self.map.entry(k).or_insert_with(|| Vec::with_capacity(1));
-                values.push(v);
+        if let Some(v) = self.iter.next()? {
+            let row = v.as_val_ref();
+            let elements = self
+                .columns
+                .iter()
+                .map(|x| x.expr.eval(row))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Some(RelValue::new(&self.head, &ProductValue::new(&elements))));
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Sort<I, C> {
+    pub(crate) head: Header,
+    pub(crate) count: RowCount,
+    pub(crate) iter: I,
+    pub(crate) compare: C,
+    sorted: Option<std::vec::IntoIter<RelValue>>,
+}
+
+impl<I, C> Sort<I, C> {
+    pub fn new(iter: I, count: RowCount, head: Header, compare: C) -> Sort<I, C> {
+        Sort {
+            iter,
+            count,
+            compare,
+            head,
+            sorted: None,
+        }
+    }
+}
+
+impl<I, C> RelOps for Sort<I, C>
+where
+    I: RelOps,
+    C: FnMut(RelValueRef, RelValueRef) -> Ordering,
+{
+    fn head(&self) -> &Header {
+        &self.head
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.count
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        if self.sorted.is_none() {
+            let count = self.iter.row_count();
+            let mut rows = Vec::with_capacity(count.max.unwrap_or(count.min));
+            while let Some(row) = self.iter.next()? {
+                rows.push(row);
             }
-            self.filled = true;
+            let compare = &mut self.compare;
+            rows.sort_by(|lhs, rhs| compare(lhs.as_val_ref(), rhs.as_val_ref()));
+            self.sorted = Some(rows.into_iter());
         }
-        loop {
-            let lhs = if let Some(left) = &self.left {
-                left.clone()
-            } else {
-                match self.lhs.next()? {
-                    None => return Ok(None),
-                    Some(x) => {
-                        self.left = Some(x.clone());
-                        x
-                    }
-                }
-            };
+        Ok(self.sorted.as_mut().and_then(|rows| rows.next()))
+    }
+}
 
-            let k = (self.key_lhs)(lhs.as_val_ref())?;
-            if let Some(rvv) = self.map.get_mut(&k) {
-                if let Some(rhs) = rvv.pop() {
-                    if (self.predicate)(lhs.as_val_ref(), rhs.as_val_ref())? {
-                        self.count.add_exact(1);
-                        return Ok(Some(lhs.clone().extend(&self.head, rhs)));
-                    }
-                }
+#[derive(Clone, Debug)]
+pub struct Limit<I> {
+    pub(crate) head: Header,
+    pub(crate) count: RowCount,
+    pub(crate) iter: I,
+    pub(crate) offset: usize,
+    pub(crate) limit: Option<usize>,
+}
+
+impl<I> Limit<I> {
+    pub fn new(iter: I, count: RowCount, head: Header, offset: usize, limit: Option<usize>) -> Limit<I> {
+        Limit {
+            iter,
+            count,
+            head,
+            offset,
+            limit,
+        }
+    }
+}
+
+impl<I> RelOps for Limit<I>
+where
+    I: RelOps,
+{
+    fn head(&self) -> &Header {
+        &self.head
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.count
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        while self.offset > 0 {
+            if self.iter.next()?.is_none() {
+                return Ok(None);
+            }
+            self.offset -= 1;
+        }
+        match &mut self.limit {
+            Some(0) => Ok(None),
+            Some(limit) => {
+                *limit -= 1;
+                self.iter.next()
             }
-            self.left = None;
-            continue;
+            None => self.iter.next(),
+        }
+    }
+}
+
+/// The argument of an aggregate function, at the position `pos` of the input rows.
+///
+/// The `none`s of a `nullable` argument are skipped, and the `some`s are unwrapped.
+#[derive(Clone, Copy, Debug)]
+struct Arg {
+    pos: usize,
+    nullable: bool,
+}
+
+impl Arg {
+    fn get<'a>(&self, row: &'a ProductValue) -> Option<&'a AlgebraicValue> {
+        let value = &row.elements[self.pos];
+        match value {
+            AlgebraicValue::Sum(sum) if self.nullable => (sum.tag == 0).then_some(&*sum.value),
+            value => Some(value),
+        }
+    }
+}
+
+/// A number widened to compute its `SUM` and `AVG` with others of the same type.
+#[derive(Clone, Copy, Debug)]
+enum Number {
+    Signed(i128),
+    Unsigned(u128),
+    Float(f64),
+}
+
+impl Number {
+    fn new(value: &AlgebraicValue) -> Option<Self> {
+        Some(match value {
+            AlgebraicValue::Builtin(value) => match value {
+                BuiltinValue::I8(x) => Self::Signed((*x).into()),
+                BuiltinValue::I16(x) => Self::Signed((*x).into()),
+                BuiltinValue::I32(x) => Self::Signed((*x).into()),
+                BuiltinValue::I64(x) => Self::Signed((*x).into()),
+                BuiltinValue::I128(x) => Self::Signed(*x),
+                BuiltinValue::U8(x) => Self::Unsigned((*x).into()),
+                BuiltinValue::U16(x) => Self::Unsigned((*x).into()),
+                BuiltinValue::U32(x) => Self::Unsigned((*x).into()),
+                BuiltinValue::U64(x) => Self::Unsigned((*x).into()),
+                BuiltinValue::U128(x) => Self::Unsigned(*x),
+                BuiltinValue::F32(x) => Self::Float(f32::from(*x).into()),
+                BuiltinValue::F64(x) => Self::Float((*x).into()),
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Self::Signed(x) => x as f64,
+            Self::Unsigned(x) => x as f64,
+            Self::Float(x) => x,
         }
     }
+
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        Some(match (self, rhs) {
+            (Self::Signed(lhs), Self::Signed(rhs)) => Self::Signed(lhs.checked_add(rhs)?),
+            (Self::Unsigned(lhs), Self::Unsigned(rhs)) => Self::Unsigned(lhs.checked_add(rhs)?),
+            (lhs, rhs) => Self::Float(lhs.as_f64() + rhs.as_f64()),
+        })
+    }
+
+    /// Converts the number into a value of type `ty`, if it fits.
+    fn into_value(self, ty: &AlgebraicType) -> Option<AlgebraicValue> {
+        Some(match (self, ty) {
+            (Self::Signed(x), &AlgebraicType::I64) => AlgebraicValue::I64(x.try_into().ok()?),
+            (Self::Signed(x), &AlgebraicType::I128) => AlgebraicValue::I128(x),
+            (Self::Unsigned(x), &AlgebraicType::U64) => AlgebraicValue::U64(x.try_into().ok()?),
+            (Self::Unsigned(x), &AlgebraicType::U128) => AlgebraicValue::U128(x),
+            (x, _) => AlgebraicValue::from(x.as_f64()),
+        })
+    }
+}
+
+/// The running state of an aggregate function over the rows of a group.
+#[derive(Clone, Debug)]
+enum Accumulator {
+    Count(u64),
+    Sum { sum: Option<Number>, ty: AlgebraicType },
+    Min(Option<AlgebraicValue>),
+    Max(Option<AlgebraicValue>),
+    Avg { sum: f64, count: u64 },
+}
+
+impl Accumulator {
+    fn new(fun: AggregateFn, ty: &AlgebraicType) -> Self {
+        match fun {
+            AggregateFn::Count => Self::Count(0),
+            AggregateFn::Sum => Self::Sum {
+                sum: None,
+                ty: ty.clone(),
+            },
+            AggregateFn::Min => Self::Min(None),
+            AggregateFn::Max => Self::Max(None),
+            AggregateFn::Avg => Self::Avg { sum: 0.0, count: 0 },
+        }
+    }
+
+    /// Accumulates the `value` of the argument, or a row for `COUNT(*)`.
+    fn update(&mut self, value: &AlgebraicValue) -> Result<(), ErrorVm> {
+        match self {
+            Self::Count(count) => *count += 1,
+            Self::Sum { sum, .. } => {
+                let x = Number::new(value).ok_or_else(|| not_a_number(value))?;
+                *sum = Some(match sum {
+                    None => x,
+                    Some(sum) => sum.checked_add(x).ok_or_else(overflow)?,
+                });
+            }
+            Self::Min(min) => {
+                if !matches!(min, Some(min) if value >= min) {
+                    *min = Some(value.clone());
+                }
+            }
+            Self::Max(max) => {
+                if !matches!(max, Some(max) if value <= max) {
+                    *max = Some(value.clone());
+                }
+            }
+            Self::Avg { sum, count } => {
+                *sum += Number::new(value).ok_or_else(|| not_a_number(value))?.as_f64();
+                *count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<AlgebraicValue, ErrorVm> {
+        let option = |x: Option<AlgebraicValue>| x.map_or_else(AlgebraicValue::OptionNone, AlgebraicValue::OptionSome);
+        Ok(match self {
+            Self::Count(count) => AlgebraicValue::U64(count),
+            Self::Sum { sum, ty } => sum
+                .unwrap_or(Number::Unsigned(0))
+                .into_value(&ty)
+                .ok_or_else(overflow)?,
+            Self::Min(x) | Self::Max(x) => option(x),
+            Self::Avg { count: 0, .. } => option(None),
+            Self::Avg { sum, count } => option(Some(AlgebraicValue::from(sum / count as f64))),
+        })
+    }
+}
+
+fn not_a_number(value: &AlgebraicValue) -> ErrorVm {
+    ErrorLang::new(
+        ErrorKind::TypeMismatch,
+        Some(&format!("Expected a number, found {value:?}")),
+    )
+    .into()
+}
+
+fn overflow() -> ErrorVm {
+    ErrorLang::new(ErrorKind::OutOfBounds, Some("Aggregate overflowed its result type")).into()
+}
+
+#[derive(Debug)]
+pub struct Aggregate<I> {
+    pub(crate) head: Header,
+    pub(crate) count: RowCount,
+    pub(crate) iter: I,
+    group_by: Vec<usize>,
+    args: Vec<Option<Arg>>,
+    accumulators: Vec<Accumulator>,
+    groups: Option<btree_map::IntoIter<Vec<AlgebraicValue>, Vec<Accumulator>>>,
+}
+
+impl<I> Aggregate<I> {
+    fn new(
+        iter: I,
+        count: RowCount,
+        head: Header,
+        group_by: Vec<usize>,
+        args: Vec<Option<Arg>>,
+        accumulators: Vec<Accumulator>,
+    ) -> Aggregate<I> {
+        Aggregate {
+            iter,
+            count,
+            head,
+            group_by,
+            args,
+            accumulators,
+            groups: None,
+        }
+    }
+}
+
+impl<I> RelOps for Aggregate<I>
+where
+    I: RelOps,
+{
+    fn head(&self) -> &Header {
+        &self.head
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.count
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        if self.groups.is_none() {
+            let mut groups = BTreeMap::new();
+            if self.group_by.is_empty() {
+                groups.insert(Vec::new(), self.accumulators.clone());
+            }
+            while let Some(row) = self.iter.next()? {
+                let key: Vec<_> = self
+                    .group_by
+                    .iter()
+                    .map(|pos| row.data.elements[*pos].clone())
+                    .collect();
+                let group = groups.entry(key).or_insert_with(|| self.accumulators.clone());
+                for (acc, arg) in group.iter_mut().zip(&self.args) {
+                    match arg {
+                        None => acc.update(&AlgebraicValue::UNIT)?,
+                        Some(arg) => {
+                            if let Some(value) = arg.get(&row.data) {
+                                acc.update(value)?;
+                            }
+                        }
+                    }
+                }
+            }
+            self.groups = Some(groups.into_iter());
+        }
+
+        let Some((mut elements, group)) = self.groups.as_mut().and_then(|groups| groups.next()) else {
+            return Ok(None);
+        };
+        for acc in group {
+            elements.push(acc.finish()?);
+        }
+        Ok(Some(RelValue::new(&self.head, &ProductValue::new(&elements))))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct JoinInner<Lhs, Rhs, KeyLhs, KeyRhs, P> {
+    pub(crate) head: Header,
+    pub(crate) count: RowCount,
+    pub(crate) lhs: Lhs,
+    pub(crate) rhs: Rhs,
+    pub(crate) key_lhs: KeyLhs,
+    pub(crate) key_rhs: KeyRhs,
+    pub(crate) predicate: P,
+    map: HashMap<ProductValue, Vec<RelValue>>,
+    filled: bool,
+    /// The current row of the left, its key, and the position of the next row of the right to match it with.
+    left: Option<(RelValue, ProductValue, usize)>,
+}
+
+impl<Lhs, Rhs, KeyLhs, KeyRhs, P> JoinInner<Lhs, Rhs, KeyLhs, KeyRhs, P> {
+    pub fn new(head: Header, lhs: Lhs, rhs: Rhs, key_lhs: KeyLhs, key_rhs: KeyRhs, predicate: P) -> Self {
+        Self {
+            head,
+            count: RowCount::unknown(),
+            map: HashMap::new(),
+            lhs,
+            rhs,
+            key_lhs,
+            key_rhs,
+            predicate,
+            filled: false,
+            left: None,
+        }
+    }
+}
+
+impl<Lhs, Rhs, KeyLhs, KeyRhs, P> RelOps for JoinInner<Lhs, Rhs, KeyLhs, KeyRhs, P>
+where
+    Lhs: RelOps,
+    Rhs: RelOps,
+    KeyLhs: FnMut(RelValueRef) -> Result<ProductValue, ErrorVm>,
+    KeyRhs: FnMut(RelValueRef) -> Result<ProductValue, ErrorVm>,
+    P: FnMut(RelValueRef, RelValueRef) -> Result<bool, ErrorVm>,
+{
+    fn head(&self) -> &Header {
+        &self.head
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.count
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        if !self.filled {
+            self.map = HashMap::with_capacity(self.rhs.row_count().min);
+            while let Some(v) = self.rhs.next()? {
+                let k = (self.key_rhs)(v.as_val_ref())?;
+                let values = self.map.entry(k).or_insert_with(|| Vec::with_capacity(1));
+                values.push(v);
+            }
+            self.filled = true;
+        }
+        loop {
+            if let Some((lhs, key, pos)) = &mut self.left {
+                if let Some(rhs) = self.map.get(key).and_then(|rows| rows.get(*pos)) {
+                    *pos += 1;
+                    if (self.predicate)(lhs.as_val_ref(), rhs.as_val_ref())? {
+                        self.count.add_exact(1);
+                        return Ok(Some(lhs.clone().extend(&self.head, rhs.clone())));
+                    }
+                    continue;
+                }
+            }
+            match self.lhs.next()? {
+                None => return Ok(None),
+                Some(lhs) => {
+                    let key = (self.key_lhs)(lhs.as_val_ref())?;
+                    self.left = Some((lhs, key, 0));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the value a row is joined by, from its column `field`.
+///
+/// When a join column is an option and the column it's joined with isn't,
+/// as happens to the columns of the side of an outer join that may be missing,
+/// the value of its `some` is read instead, so both columns are compared by the values they hold.
+#[derive(Clone, Debug)]
+pub struct JoinKey {
+    field: FieldExpr,
+    unwrap: bool,
+}
+
+impl JoinKey {
+    /// Returns the keys of the join of the rows with [Header] `lhs` by `col_lhs`,
+    /// with the rows with [Header] `rhs` by `col_rhs`.
+    pub fn pair(lhs: &Header, col_lhs: &FieldName, rhs: &Header, col_rhs: &FieldName) -> (Self, Self) {
+        let is_option = |head: &Header, col: &FieldName| {
+            head.column_pos(col)
+                .map_or(false, |pos| option_type(&head.fields[pos].algebraic_type).is_some())
+        };
+        let (lhs_option, rhs_option) = (is_option(lhs, col_lhs), is_option(rhs, col_rhs));
+        let key = |field: &FieldName, unwrap| JoinKey {
+            field: FieldExpr::Name(field.clone()),
+            unwrap,
+        };
+        (
+            key(col_lhs, lhs_option && !rhs_option),
+            key(col_rhs, rhs_option && !lhs_option),
+        )
+    }
+
+    pub fn get<'a>(&'a self, row: RelValueRef<'a>) -> &'a AlgebraicValue {
+        let value = row.get(&self.field);
+        match value {
+            AlgebraicValue::Sum(sum) if self.unwrap && sum.tag == 0 => &sum.value,
+            value => value,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JoinOuter<Lhs, Rhs, KeyLhs, KeyRhs, P> {
+    pub(crate) head: Header,
+    pub(crate) count: RowCount,
+    pub(crate) lhs: Lhs,
+    pub(crate) rhs: Rhs,
+    pub(crate) key_lhs: KeyLhs,
+    pub(crate) key_rhs: KeyRhs,
+    pub(crate) predicate: P,
+    keep_lhs: bool,
+    keep_rhs: bool,
+    /// The number of columns of the left.
+    lhs_width: usize,
+    /// Whether the value of each column of the result is wrapped in `some`.
+    wrap: Vec<bool>,
+    /// The rows of the right, and whether each one matched a row of the left.
+    rows: Vec<(RelValue, bool)>,
+    /// The positions in `rows` of the rows of the right with each key.
+    map: HashMap<ProductValue, Vec<usize>>,
+    filled: bool,
+    /// The current row of the left, its key, the position of the next row of the right to match it with,
+    /// and whether it matched any.
+    left: Option<(RelValue, ProductValue, usize, bool)>,
+    unmatched: Option<std::vec::IntoIter<RelValue>>,
+}
+
+impl<Lhs, Rhs, KeyLhs, KeyRhs, P> JoinOuter<Lhs, Rhs, KeyLhs, KeyRhs, P> {
+    /// Returns the row joining `lhs` with `rhs`, where a missing side is padded with `none`s.
+    fn row(&self, lhs: Option<&RelValue>, rhs: Option<&RelValue>) -> RelValue {
+        let (lhs_wrap, rhs_wrap) = self.wrap.split_at(self.lhs_width);
+        let mut elements = Vec::with_capacity(self.wrap.len());
+        pad(&mut elements, lhs, lhs_wrap);
+        pad(&mut elements, rhs, rhs_wrap);
+        RelValue::new(&self.head, &ProductValue::new(&elements))
+    }
+}
+
+/// Appends the values of `row` to `elements`, wrapped in `some` where `wrap` says so,
+/// or a `none` for each column when the side is missing.
+fn pad(elements: &mut Vec<AlgebraicValue>, row: Option<&RelValue>, wrap: &[bool]) {
+    match row {
+        Some(row) => elements.extend(row.data.elements.iter().zip(wrap).map(|(value, wrap)| match wrap {
+            true => AlgebraicValue::OptionSome(value.clone()),
+            false => value.clone(),
+        })),
+        None => elements.extend(std::iter::repeat_with(AlgebraicValue::OptionNone).take(wrap.len())),
+    }
+}
+
+impl<Lhs, Rhs, KeyLhs, KeyRhs, P> RelOps for JoinOuter<Lhs, Rhs, KeyLhs, KeyRhs, P>
+where
+    Lhs: RelOps,
+    Rhs: RelOps,
+    KeyLhs: FnMut(RelValueRef) -> Result<ProductValue, ErrorVm>,
+    KeyRhs: FnMut(RelValueRef) -> Result<ProductValue, ErrorVm>,
+    P: FnMut(RelValueRef, RelValueRef) -> Result<bool, ErrorVm>,
+{
+    fn head(&self) -> &Header {
+        &self.head
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.count
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        if !self.filled {
+            while let Some(v) = self.rhs.next()? {
+                let k = (self.key_rhs)(v.as_val_ref())?;
+                self.map.entry(k).or_default().push(self.rows.len());
+                self.rows.push((v, false));
+            }
+            self.filled = true;
+        }
+        loop {
+            if let Some(unmatched) = &mut self.unmatched {
+                let Some(rhs) = unmatched.next() else {
+                    return Ok(None);
+                };
+                self.count.add_exact(1);
+                return Ok(Some(self.row(None, Some(&rhs))));
+            }
+            if let Some((lhs, key, pos, matched)) = &mut self.left {
+                if let Some(&idx) = self.map.get(key).and_then(|rows| rows.get(*pos)) {
+                    *pos += 1;
+                    let (rhs, rhs_matched) = &mut self.rows[idx];
+                    if (self.predicate)(lhs.as_val_ref(), rhs.as_val_ref())? {
+                        *matched = true;
+                        *rhs_matched = true;
+                        let (lhs, rhs) = (lhs.clone(), rhs.clone());
+                        self.count.add_exact(1);
+                        return Ok(Some(self.row(Some(&lhs), Some(&rhs))));
+                    }
+                    continue;
+                }
+                let (lhs, _, _, matched) = self.left.take().unwrap();
+                if !matched && self.keep_lhs {
+                    self.count.add_exact(1);
+                    return Ok(Some(self.row(Some(&lhs), None)));
+                }
+            }
+            match self.lhs.next()? {
+                Some(lhs) => {
+                    let key = (self.key_lhs)(lhs.as_val_ref())?;
+                    self.left = Some((lhs, key, 0, false));
+                }
+                None if self.keep_rhs => {
+                    let rows = std::mem::take(&mut self.rows);
+                    let unmatched: Vec<_> = rows
+                        .into_iter()
+                        .filter_map(|(row, matched)| (!matched).then_some(row))
+                        .collect();
+                    self.unmatched = Some(unmatched.into_iter());
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IndexJoin<Lhs, F> {
+    pub(crate) head: Header,
+    pub(crate) count: RowCount,
+    pub(crate) lhs: Lhs,
+    pub(crate) lookup: F,
+    left: Option<RelValue>,
+    matches: std::vec::IntoIter<RelValue>,
+}
+
+impl<Lhs, F> IndexJoin<Lhs, F> {
+    pub fn new(head: Header, lhs: Lhs, lookup: F) -> Self {
+        Self {
+            head,
+            count: RowCount::unknown(),
+            lhs,
+            lookup,
+            left: None,
+            matches: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<Lhs, F> RelOps for IndexJoin<Lhs, F>
+where
+    Lhs: RelOps,
+    F: FnMut(RelValueRef) -> Result<Vec<RelValue>, ErrorVm>,
+{
+    fn head(&self) -> &Header {
+        &self.head
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.count
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        loop {
+            if let Some(lhs) = &self.left {
+                if let Some(rhs) = self.matches.next() {
+                    self.count.add_exact(1);
+                    return Ok(Some(lhs.clone().extend(&self.head, rhs)));
+                }
+            }
+            match self.lhs.next()? {
+                None => return Ok(None),
+                Some(lhs) => {
+                    self.matches = (self.lookup)(lhs.as_val_ref())?.into_iter();
+                    self.left = Some(lhs);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Distinct<I> {
+    pub(crate) head: Header,
+    pub(crate) count: RowCount,
+    pub(crate) iter: I,
+    seen: HashSet<ProductValue>,
+}
+
+impl<I> Distinct<I> {
+    pub fn new(iter: I, count: RowCount, head: Header) -> Distinct<I> {
+        Distinct {
+            head,
+            count,
+            iter,
+            seen: HashSet::new(),
+        }
+    }
+}
+
+impl<I> RelOps for Distinct<I>
+where
+    I: RelOps,
+{
+    fn head(&self) -> &Header {
+        &self.head
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.count
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        while let Some(row) = self.iter.next()? {
+            if self.seen.insert(row.data.clone()) {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Debug)]
+pub struct SetOperation<Lhs, Rhs> {
+    pub(crate) head: Header,
+    pub(crate) count: RowCount,
+    pub(crate) lhs: Lhs,
+    pub(crate) rhs: Rhs,
+    op: SetOp,
+    all: bool,
+    /// How many times each row of the right is yet to be matched with a row of the left.
+    map: HashMap<ProductValue, usize>,
+    filled: bool,
+    /// The rows already yielded, to skip their duplicates.
+    seen: HashSet<ProductValue>,
+    /// Whether the left is exhausted, and the rows of the right are yielded.
+    lhs_done: bool,
+}
+
+impl<Lhs, Rhs> SetOperation<Lhs, Rhs> {
+    pub fn new(head: Header, count: RowCount, lhs: Lhs, rhs: Rhs, op: SetOp, all: bool) -> Self {
+        Self {
+            head,
+            count,
+            lhs,
+            rhs,
+            op,
+            all,
+            map: HashMap::new(),
+            filled: false,
+            seen: HashSet::new(),
+            lhs_done: false,
+        }
+    }
+
+    /// Whether `row` is yielded, as it wasn't yielded before, unless duplicates are kept.
+    fn first_seen(&mut self, row: &ProductValue) -> bool {
+        self.all || self.seen.insert(row.clone())
+    }
+}
+
+impl<Lhs, Rhs> RelOps for SetOperation<Lhs, Rhs>
+where
+    Lhs: RelOps,
+    Rhs: RelOps,
+{
+    fn head(&self) -> &Header {
+        &self.head
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.count
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        if self.op == SetOp::Union {
+            while !self.lhs_done {
+                match self.lhs.next()? {
+                    Some(row) if self.first_seen(&row.data) => return Ok(Some(row)),
+                    Some(_) => {}
+                    None => self.lhs_done = true,
+                }
+            }
+            while let Some(row) = self.rhs.next()? {
+                if self.first_seen(&row.data) {
+                    return Ok(Some(RelValue::new(&self.head, &row.data)));
+                }
+            }
+            return Ok(None);
+        }
+
+        if !self.filled {
+            while let Some(row) = self.rhs.next()? {
+                *self.map.entry(row.data).or_default() += 1;
+            }
+            self.filled = true;
+        }
+        while let Some(row) = self.lhs.next()? {
+            let matches = self.map.get_mut(&row.data);
+            let keep = match (self.op, matches) {
+                (SetOp::Intersect, Some(count)) if *count > 0 => {
+                    // Without `all`, the row is yielded once, so no other row matches it.
+                    *count = if self.all { *count - 1 } else { 0 };
+                    true
+                }
+                (SetOp::Intersect, _) => false,
+                (SetOp::Except, Some(count)) if self.all => {
+                    // The row is kept once it outnumbers its matches.
+                    let matched = *count > 0;
+                    *count = count.saturating_sub(1);
+                    !matched
+                }
+                (SetOp::Except, Some(_)) => false,
+                (SetOp::Except, None) => self.first_seen(&row.data),
+                (SetOp::Union, _) => unreachable!("Union is yielded above"),
+            };
+            if keep {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Debug)]
+pub struct SemiJoin<Lhs, Rhs> {
+    pub(crate) head: Header,
+    pub(crate) count: RowCount,
+    pub(crate) lhs: Lhs,
+    pub(crate) rhs: Rhs,
+    /// The position of the column matched with the right,
+    /// and whether it and the column of the right are of option types.
+    field: Option<(usize, bool, bool)>,
+    negated: bool,
+    /// The values of the right, unwrapped from their `some`.
+    values: HashSet<AlgebraicValue>,
+    /// Whether the right has any rows, and whether any of them has a `none`.
+    rhs_any: bool,
+    rhs_none: bool,
+    filled: bool,
+}
+
+impl<Lhs, Rhs> SemiJoin<Lhs, Rhs> {
+    pub fn new(
+        head: Header,
+        count: RowCount,
+        lhs: Lhs,
+        rhs: Rhs,
+        field: Option<(usize, bool, bool)>,
+        negated: bool,
+    ) -> Self {
+        Self {
+            head,
+            count,
+            lhs,
+            rhs,
+            field,
+            negated,
+            values: HashSet::new(),
+            rhs_any: false,
+            rhs_none: false,
+            filled: false,
+        }
+    }
+}
+
+/// Returns the value of `value`, unwrapped from its `some` when it's of an option type,
+/// or `None` when it's a `none`.
+fn some_value(value: &AlgebraicValue, is_option: bool) -> Option<&AlgebraicValue> {
+    match value {
+        AlgebraicValue::Sum(sum) if is_option => (sum.tag == 0).then_some(&*sum.value),
+        value => Some(value),
+    }
+}
+
+impl<Lhs, Rhs> RelOps for SemiJoin<Lhs, Rhs>
+where
+    Lhs: RelOps,
+    Rhs: RelOps,
+{
+    fn head(&self) -> &Header {
+        &self.head
+    }
+
+    fn row_count(&self) -> RowCount {
+        self.count
+    }
+
+    fn next(&mut self) -> Result<Option<RelValue>, ErrorVm> {
+        if !self.filled {
+            while let Some(row) = self.rhs.next()? {
+                self.rhs_any = true;
+                let Some((_, _, is_option)) = self.field else {
+                    // `EXISTS` only needs to know if there's any row.
+                    break;
+                };
+                match some_value(&row.data.elements[0], is_option) {
+                    Some(value) => {
+                        self.values.insert(value.clone());
+                    }
+                    None => self.rhs_none = true,
+                }
+            }
+            self.filled = true;
+        }
+        while let Some(row) = self.lhs.next()? {
+            let keep = match self.field {
+                None => self.rhs_any != self.negated,
+                Some((pos, is_option, _)) => match some_value(&row.data.elements[pos], is_option) {
+                    Some(value) if self.values.contains(value) => !self.negated,
+                    // Nothing is in no values, but otherwise a `none` is never known not to be in the values,
+                    // nor is any value when they hold a `none`.
+                    _ if self.negated && !self.rhs_any => true,
+                    None => false,
+                    Some(_) => self.negated && !self.rhs_none,
+                },
+            };
+            if keep {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
 }
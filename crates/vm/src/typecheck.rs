@@ -1,7 +1,9 @@
 use crate::env::EnvTy;
 use crate::errors::ErrorType;
-use crate::expr::{CrudExprOpt, ExprOpt, SourceExprOpt};
+use crate::expr::{as_option, is_number, option_type, ColumnOp, CrudExprOpt, ExprOpt, SourceExprOpt};
+use crate::operator::OpQuery;
 use crate::types::Ty;
+use spacetimedb_lib::relation::{FieldExpr, FieldName};
 use spacetimedb_sats::algebraic_type::AlgebraicType;
 
 fn get_type<'a>(_env: &'a mut EnvTy, node: &'a ExprOpt) -> &'a Ty {
@@ -137,8 +139,10 @@ pub(crate) fn check_types(env: &mut EnvTy, ast: &ExprOpt) -> Result<Ty, ErrorTyp
             match q {
                 CrudExprOpt::Insert { source, .. } => Ok(ty_source(source)),
                 CrudExprOpt::Update { insert, .. } => Ok(ty_source(&insert.source)),
-                CrudExprOpt::Delete { query } => Ok(ty_source(&query.source)),
+                CrudExprOpt::Delete { query, .. } => Ok(ty_source(&query.source)),
+                CrudExprOpt::Explain { .. } => Ok(Ty::Unknown),
                 CrudExprOpt::CreateTable { columns, .. } => Ok(AlgebraicType::Product(columns.columns.clone()).into()),
+                CrudExprOpt::CreateIndex { .. } | CrudExprOpt::AlterTable { .. } => Ok(Ty::Unknown),
                 CrudExprOpt::Drop { .. } => {
                     //todo: Extract the type from the catalog...
                     Ok(Ty::Unknown)
@@ -151,6 +155,98 @@ pub(crate) fn check_types(env: &mut EnvTy, ast: &ExprOpt) -> Result<Ty, ErrorTyp
     }
 }
 
+/// Returns the type of the `some` variant when `ty` is an option type, or `ty` otherwise.
+fn strip_option(ty: &AlgebraicType) -> &AlgebraicType {
+    option_type(ty).unwrap_or(ty)
+}
+
+/// Returns the type of the values `op` computes from a row, whose fields are of the types `field_type` returns,
+/// or an error if its operands are of types it can't operate on.
+///
+/// The type of `NULL`, as in `field = NULL`, is [Ty::Unknown], as it stands for the `none` of any option type.
+/// Options are compared and operated on like the values of their `some` variant,
+/// and the result of operating on an option is an option.
+/// Comparing with a `none` is neither true nor false, so such rows don't pass a filter.
+pub fn check_column_op<E>(op: &ColumnOp, field_type: &impl Fn(&FieldName) -> Result<AlgebraicType, E>) -> Result<Ty, E>
+where
+    E: From<ErrorType>,
+{
+    let op_args = |op: String, lhs: &Ty, rhs: &Ty| ErrorType::OpArgs {
+        op,
+        lhs: lhs.clone(),
+        rhs: rhs.clone(),
+    };
+    let check_bool = |x: &ColumnOp| -> Result<Ty, E> {
+        let ty = check_column_op(x, field_type)?;
+        match &ty {
+            Ty::Val(AlgebraicType::Bool) | Ty::Unknown => Ok(ty),
+            Ty::Val(x) if strip_option(x) == &AlgebraicType::Bool => Ok(ty),
+            _ => Err(ErrorType::Expect(AlgebraicType::Bool.into(), ty).into()),
+        }
+    };
+
+    match op {
+        ColumnOp::Field(FieldExpr::Name(x)) => Ok(field_type(x)?.into()),
+        ColumnOp::Field(FieldExpr::Value(x)) => Ok(match as_option(x) {
+            Some(None) => Ty::Unknown,
+            Some(Some(x)) => AlgebraicType::option(x.type_of()).into(),
+            None => x.type_of().into(),
+        }),
+        ColumnOp::Cmp {
+            op: OpQuery::Cmp(cmp),
+            lhs,
+            rhs,
+        } => {
+            let lhs = check_column_op(lhs, field_type)?;
+            let rhs = check_column_op(rhs, field_type)?;
+            match (&lhs, &rhs) {
+                (Ty::Val(x), Ty::Val(y)) if strip_option(x) != strip_option(y) => {
+                    Err(op_args(cmp.to_string(), &lhs, &rhs).into())
+                }
+                _ => Ok(AlgebraicType::Bool.into()),
+            }
+        }
+        ColumnOp::Cmp {
+            op: OpQuery::Logic(_),
+            lhs,
+            rhs,
+        } => {
+            check_bool(lhs)?;
+            check_bool(rhs)?;
+            Ok(AlgebraicType::Bool.into())
+        }
+        ColumnOp::Not(x) => check_bool(x),
+        ColumnOp::Math { op, lhs, rhs } => {
+            let lhs = check_column_op(lhs, field_type)?;
+            let rhs = check_column_op(rhs, field_type)?;
+            let (ty, is_option) = match (&lhs, &rhs) {
+                (Ty::Unknown, Ty::Unknown) => return Ok(Ty::Unknown),
+                (Ty::Val(x), Ty::Unknown) | (Ty::Unknown, Ty::Val(x)) => (strip_option(x), true),
+                (Ty::Val(x), Ty::Val(y)) if strip_option(x) == strip_option(y) => {
+                    (strip_option(x), option_type(x).is_some() || option_type(y).is_some())
+                }
+                _ => return Err(op_args(op.to_string(), &lhs, &rhs).into()),
+            };
+            if !is_number(ty) {
+                return Err(op_args(op.to_string(), &lhs, &rhs).into());
+            }
+            Ok(if is_option {
+                AlgebraicType::option(ty.clone())
+            } else {
+                ty.clone()
+            }
+            .into())
+        }
+        ColumnOp::Call { fun, args } => {
+            let args = args
+                .iter()
+                .map(|x| check_column_op(x, field_type))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(fun.result_type(&args)?)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use spacetimedb_lib::identity::AuthCtx;
@@ -6,6 +6,8 @@ use spacetimedb_lib::bsatn;
 
 #[spacetimedb(table)]
 #[spacetimedb(index(btree, name = "foo", x))]
+#[spacetimedb(index(btree, x, y))]
+#[spacetimedb(index(hash, name = "z_hash_idx", z))]
 pub struct TestA {
     pub x: u32,
     pub y: u32,
@@ -46,8 +48,15 @@ pub struct _Private {
 
 pub type TestAlias = TestA;
 
-// #[spacetimedb(migrate)]
-// pub fn migrate() {}
+#[spacetimedb(migrate)]
+pub fn migrate() -> anyhow::Result<()> {
+    log::info!("Migrate called!");
+    // Lets tests have an update of this module rejected.
+    if _Private::iter().any(|private| private.name == "fail migrate") {
+        anyhow::bail!("Migrate failed!");
+    }
+    Ok(())
+}
 
 #[spacetimedb(init)]
 pub fn init() {